@@ -0,0 +1,84 @@
+// Runs an Apple I in the terminal: the Woz Monitor at $FF00, a PIA for
+// the keyboard and display, and RAM everywhere else.
+//
+//   cargo run --no-default-features --example apple1 -- <wozmon.bin>
+//
+// The monitor is Apple's and isn't included, it's the 256 byte ROM image.
+// Type monitor commands as usual, "FF00.FF0F" to dump memory or "300: A9
+// 01" to store bytes, letters are sent as capitals. Ctrl-C quits.
+
+use std::io::{Read, Write};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crust_6502_emulator::apple1::MONITOR_BASE;
+use crust_6502_emulator::device::BusDevice;
+use crust_6502_emulator::{cpu6502, CYCLES_PER_FRAME};
+
+// The Apple I ran at about 1MHz, a frame's worth of cycles at a time is
+// close enough for typing at
+const FRAME: Duration = Duration::from_micros(16_667);
+
+struct Rom(Vec<u8>);
+
+impl BusDevice for Rom {
+    fn read(&mut self, offset: u16) -> u8 {
+        self.peek(offset)
+    }
+
+    fn peek(&self, offset: u16) -> u8 {
+        self.0[offset as usize]
+    }
+
+    fn write(&mut self, _offset: u16, _data: u8) {}
+}
+
+fn main() {
+    let rom_path = std::env::args().nth(1).expect("usage: apple1 <wozmon.bin>");
+    let rom = std::fs::read(&rom_path).unwrap_or_else(|e| panic!("can't read {}: {}", rom_path, e));
+    if rom.len() != 0x100 {
+        panic!("{} is {} bytes, the Woz Monitor is 256", rom_path, rom.len());
+    }
+
+    let mut cpu = cpu6502::new();
+    let port = cpu.attach_apple1();
+    cpu.bus.borrow_mut().map("rom", MONITOR_BASE, 0xFFFF, Box::new(Rom(rom)));
+    cpu.reset();
+
+    // Reads block, so they happen on their own thread and the machine
+    // picks up whatever has arrived once a frame
+    let (keys_tx, keys) = mpsc::channel();
+    thread::spawn(move || {
+        for byte in std::io::stdin().bytes() {
+            match byte {
+                Ok(byte) if keys_tx.send(byte).is_ok() => {}
+                _ => break,
+            }
+        }
+    });
+
+    let mut stdout = std::io::stdout();
+    let mut next_frame = Instant::now();
+
+    loop {
+        let typed: Vec<u8> = keys.try_iter().collect();
+        port.type_text(&typed);
+
+        for _ in 0..CYCLES_PER_FRAME {
+            cpu.clock();
+        }
+        port.poll();
+
+        let output = port.take_output();
+        if !output.is_empty() {
+            stdout.write_all(&output).unwrap();
+            stdout.flush().unwrap();
+        }
+
+        next_frame += FRAME;
+        if let Some(wait) = next_frame.checked_duration_since(Instant::now()) {
+            thread::sleep(wait);
+        }
+    }
+}
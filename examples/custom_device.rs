@@ -0,0 +1,108 @@
+// A bus device of your own: a periodic timer with an interrupt, mapped at
+// $D000 next to RAM, and a guest that counts its ticks.
+//
+//   cargo run --no-default-features --example custom_device
+//
+//   $D000  CONTROL  bit 0 starts the timer
+//   $D001  STATUS   bit 7 set once it's fired, reading acknowledges it
+//
+// Devices don't see the clock, so time comes from an alarm on the CPU's
+// scheduler and the interrupt line goes up and down through its event
+// queue, the same as the built-in chips.
+
+use std::sync::{Arc, Mutex, PoisonError};
+
+use crust_6502_emulator::assembler::assemble;
+use crust_6502_emulator::device::BusDevice;
+use crust_6502_emulator::interrupts::IrqSource;
+use crust_6502_emulator::run::StopReason;
+use crust_6502_emulator::scheduler::EventQueue;
+use crust_6502_emulator::cpu6502;
+
+const TIMER_BASE: u16 = 0xD000;
+const PERIOD: u64 = 1000;
+
+const GUEST: &str = "
+    CONTROL = $D000
+    STATUS = $D001
+    TICKS = $0200
+    .org $8000
+    reset:  LDA #1
+            STA CONTROL
+            CLI
+    idle:   JMP idle
+    irq:    BIT STATUS
+            INC TICKS
+            RTI
+    .org $FFFC
+    .word reset, irq
+";
+
+#[derive(Default)]
+struct TimerState {
+    running: bool,
+    fired: bool,
+}
+
+struct Timer {
+    state: Arc<Mutex<TimerState>>,
+    irq: (EventQueue, IrqSource),
+}
+
+impl BusDevice for Timer {
+    fn read(&mut self, offset: u16) -> u8 {
+        let value = self.peek(offset);
+        if offset == 1 {
+            self.state.lock().unwrap_or_else(PoisonError::into_inner).fired = false;
+            let (events, source) = &self.irq;
+            let source = *source;
+            events.schedule_in(0, move |cpu| cpu.set_irq(source, false));
+        }
+        value
+    }
+
+    fn peek(&self, offset: u16) -> u8 {
+        let state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        match offset {
+            0 => state.running as u8,
+            1 => (state.fired as u8) << 7,
+            _ => 0xFF,
+        }
+    }
+
+    fn write(&mut self, offset: u16, data: u8) {
+        if offset == 0 {
+            self.state.lock().unwrap_or_else(PoisonError::into_inner).running = data & 1 != 0;
+        }
+    }
+
+    fn reset(&mut self) {
+        *self.state.lock().unwrap_or_else(PoisonError::into_inner) = TimerState::default();
+    }
+}
+
+fn main() {
+    let guest = assemble(GUEST).unwrap_or_else(|e| panic!("the guest doesn't assemble: {}", e));
+    let mut cpu = cpu6502::new();
+    cpu.load_program(&guest.bytes, guest.origin);
+
+    // The device gets its own IRQ source, which shows up in the debugger's
+    // "irq" statistics by name
+    let state = Arc::new(Mutex::new(TimerState::default()));
+    let source = cpu.interrupts.register_source("timer");
+    let timer = Timer { state: state.clone(), irq: (cpu.events(), source) };
+    cpu.bus.borrow_mut().map("timer", TIMER_BASE, TIMER_BASE + 1, Box::new(timer));
+
+    cpu.add_alarm_every(PERIOD, move |cpu| {
+        let mut state = state.lock().unwrap_or_else(PoisonError::into_inner);
+        if state.running {
+            state.fired = true;
+            cpu.set_irq(source, true);
+        }
+    });
+
+    cpu.reset();
+    let reason = cpu.run_until(|cpu| cpu.peek(0x0200) == 5, 10 * PERIOD);
+    assert_eq!(reason, StopReason::ConditionMet);
+    println!("5 ticks by cycle {}, {} IRQs taken", cpu.clock_count(), cpu.interrupts.stats.taken);
+}
@@ -0,0 +1,89 @@
+// Drives the debugger from a script instead of the window: the same console
+// commands, each edit going through the Debugger so it can be undone, with
+// the host deciding when the program runs.
+//
+//   cargo run --no-default-features --example debugger_script -- [script]
+//
+// A script is console commands, one a line, with # for comments. "go" runs
+// until a breakpoint or the cycle cap. Without a script it runs a built-in
+// one against a small routine that sums 1 to 5 into $0200, whose labels
+// can be used in addresses.
+
+use crust_6502_emulator::annotations::Annotations;
+use crust_6502_emulator::assembler::assemble;
+use crust_6502_emulator::console::Console;
+use crust_6502_emulator::debugger::Debugger;
+use crust_6502_emulator::symbols::SymbolTable;
+use crust_6502_emulator::cpu6502;
+
+// How long "go" may run before it's given up on
+const MAX_CYCLES: u64 = 1_000_000;
+
+const GUEST: &str = "
+    SUM = $0200
+    .org $8000
+    start:  LDA #0
+            LDX #5
+    add:    STX SUM
+            CLC
+            ADC SUM
+            DEX
+            BNE add
+    done:   STA SUM
+    halt:   JMP halt
+";
+
+const SCRIPT: &str = "
+    # stop once the sum is in A
+    bp done
+    go
+    ? a
+    vectors
+    # look at it stored, then poke it and take the poke back
+    bc
+    s
+    m $0200 1
+    poke $0200, ff
+    m $0200 1
+    undo
+    m $0200 1
+";
+
+fn main() {
+    let guest = assemble(GUEST).unwrap_or_else(|e| panic!("the guest doesn't assemble: {}", e));
+    let script = match std::env::args().nth(1) {
+        Some(path) => std::fs::read_to_string(&path).unwrap_or_else(|e| panic!("can't read {}: {}", path, e)),
+        None => SCRIPT.to_string(),
+    };
+
+    let mut cpu = cpu6502::new();
+    cpu.load_program(&guest.bytes, guest.origin);
+    cpu.set_reset_vector(guest.origin);
+    cpu.reset();
+
+    let mut symbols = SymbolTable::new();
+    for (name, addr) in &guest.labels {
+        symbols.insert(name, *addr);
+    }
+    let mut debugger = Debugger::new();
+    let mut console = Console::new();
+    let mut notes = Annotations::new();
+
+    for line in script.lines().map(str::trim).filter(|line| !line.is_empty() && !line.starts_with('#')) {
+        console.input = line.to_string();
+        console.submit(&mut cpu, &mut debugger, &mut symbols, &mut notes);
+
+        // The console only says to run, running is up to the host. A
+        // breakpoint right where it starts doesn't count.
+        if debugger.running {
+            let start = cpu.clock_count();
+            let reason = cpu.run_until(|cpu| cpu.clock_count() > start && debugger.is_breakpoint(cpu.pc), MAX_CYCLES);
+            debugger.running = false;
+            console.print(std::format!("stopped at ${:04x}, {:?}", cpu.pc, reason));
+        }
+    }
+
+    for line in &console.output {
+        println!("{}", line);
+    }
+}
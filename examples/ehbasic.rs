@@ -0,0 +1,111 @@
+// Boots Lee Davison's Enhanced BASIC in the terminal on a small machine:
+// 48K of RAM at $0000-$BFFF, a 6551 ACIA and the ROM image at the top of
+// memory, so its vectors land on $FFFA-$FFFF.
+//
+//   cargo run --no-default-features --example ehbasic -- <rom> [--acia <addr>]
+//
+// The ROM has to be built for the ACIA's address, $A000 unless --acia says
+// otherwise, with the data register at +0 and status at +1. EhBASIC wants
+// a carriage return at the end of each line, which is what Enter sends.
+// Ctrl-C quits.
+
+use std::io::{Read, Write};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crust_6502_emulator::device::BusDevice;
+use crust_6502_emulator::{cpu6502, CYCLES_PER_FRAME};
+
+const RAM_END: u16 = 0xBFFF;
+const ROM_MAX: usize = 0x10000 - (RAM_END as usize + 1);
+
+// Real time, about a 1.79MHz part
+const FRAME: Duration = Duration::from_micros(16_639);
+
+struct Rom(Vec<u8>);
+
+impl BusDevice for Rom {
+    fn read(&mut self, offset: u16) -> u8 {
+        self.peek(offset)
+    }
+
+    fn peek(&self, offset: u16) -> u8 {
+        self.0[offset as usize]
+    }
+
+    fn write(&mut self, _offset: u16, _data: u8) {}
+}
+
+fn main() {
+    let mut rom_path = None;
+    let mut acia_addr = 0xA000;
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--acia" => {
+                let addr = args.next().expect("--acia needs an address");
+                acia_addr = u16::from_str_radix(addr.trim_start_matches('$'), 16).expect("--acia address must be hex");
+            }
+            _ => rom_path = Some(arg),
+        }
+    }
+
+    let rom_path = rom_path.expect("usage: ehbasic <rom> [--acia <addr>]");
+    let rom = std::fs::read(&rom_path).unwrap_or_else(|e| panic!("can't read {}: {}", rom_path, e));
+    if rom.is_empty() || rom.len() > ROM_MAX {
+        panic!("{} is {} bytes, the ROM has to fit in {}", rom_path, rom.len(), ROM_MAX);
+    }
+
+    let rom_start = (0x10000 - rom.len()) as u16;
+
+    let mut cpu = cpu6502::new();
+    {
+        let mut bus = cpu.bus.borrow_mut();
+        bus.set_unmapped(RAM_END + 1, 0xFFFF);
+        bus.map("rom", rom_start, 0xFFFF, Box::new(Rom(rom)));
+    }
+    let port = cpu.attach_acia(acia_addr);
+    cpu.reset();
+
+    // Reads block, so they happen on their own thread and the machine
+    // picks up whatever has arrived once a frame
+    let (keys_tx, keys) = mpsc::channel();
+    thread::spawn(move || {
+        for byte in std::io::stdin().bytes() {
+            let byte = match byte {
+                Ok(b'\n') => b'\r',
+                Ok(byte) => byte,
+                Err(_) => break,
+            };
+            if keys_tx.send(byte).is_err() {
+                break;
+            }
+        }
+    });
+
+    let mut stdout = std::io::stdout();
+    let mut next_frame = Instant::now();
+
+    loop {
+        let typed: Vec<u8> = keys.try_iter().collect();
+        port.send(&typed);
+
+        for _ in 0..CYCLES_PER_FRAME {
+            cpu.clock();
+        }
+
+        // EhBASIC ends lines with CR LF, the terminal only needs the LF
+        let output: Vec<u8> = port.take_output().into_iter().filter(|&c| c != b'\r').collect();
+        if !output.is_empty() {
+            stdout.write_all(&output).unwrap();
+            stdout.flush().unwrap();
+        }
+
+        next_frame += FRAME;
+        if let Some(wait) = next_frame.checked_duration_since(Instant::now()) {
+            thread::sleep(wait);
+        }
+    }
+}
@@ -0,0 +1,53 @@
+// The core inside a host program of your own, the way a game or a test rig
+// would carry it: the host owns the loop and hands over input a frame at a
+// time, then looks at the picture and memory it gets back.
+//
+//   cargo run --no-default-features --example embedding
+//
+// The guest is assembled at startup. It counts the keys it gets at $0200 and
+// copies each key it's given to $0201.
+
+use crust_6502_emulator::assembler::assemble;
+use crust_6502_emulator::frame::{InputState, KEYBOARD_ADDR};
+use crust_6502_emulator::machine::Machine;
+use crust_6502_emulator::{cpu6502, CYCLES_PER_FRAME};
+
+// KEY is put in front, where the frame writes the key
+const GUEST: &str = "
+    COUNT = $0200
+    LAST = $0201
+    .org $8000
+    start:  LDA KEY
+            BEQ start
+            STA LAST
+            LDA #0
+            STA KEY
+            INC COUNT
+            JMP start
+";
+
+fn main() {
+    let source = std::format!("KEY = ${:04x}\n{}", KEYBOARD_ADDR, GUEST);
+    let guest = assemble(&source).unwrap_or_else(|e| panic!("the guest doesn't assemble: {}", e));
+
+    let mut cpu = cpu6502::new();
+    cpu.load_program(&guest.bytes, guest.origin);
+    cpu.set_reset_vector(guest.origin);
+    cpu.reset();
+    let mut machine = Machine::from_cpu(cpu);
+
+    // The host's own loop, one key per frame and then a quiet one
+    for key in "HOST".bytes() {
+        for input in [InputState { key: Some(key), ..InputState::default() }, InputState::default()] {
+            let output = machine.run_frame(&input);
+            assert_eq!(output.framebuffer.pixels.len(), output.framebuffer.width * output.framebuffer.height);
+        }
+
+        let cpu = machine.cpu(0);
+        println!("frame {:>3}: key {:?} seen, {} keys so far", machine.master_clock() / CYCLES_PER_FRAME as u64, cpu.peek(0x0201) as char, cpu.peek(0x0200));
+    }
+
+    let cpu = machine.cpu(0);
+    assert_eq!(cpu.peek_bytes(0x0200, 2), [4, b'T']);
+    println!("PC ${:04x}, {} cycles", cpu.pc, machine.master_clock());
+}
@@ -0,0 +1,150 @@
+// Runs a program without opening a window and prints the final CPU state.
+//
+//   cargo run --no-default-features --example headless -- [hex bytes] [instructions] [--char-out <addr>] [--exit <addr>] [--watchdog]
+//
+// With no arguments it runs the built-in multiplication demo (10 * 3).
+// Writes or calls to the --char-out address print a character, the --exit
+// address ends the run with A as the exit code. With --watchdog the run
+// ends early once the program sits in a JMP or branch to itself.
+//
+// For rendering regression checks, --frames <n> runs whole frames instead
+// of instructions and hashes the --screen memory after each one:
+//
+//   ... --frames 60 --screen 0200,400 --golden screen.golden [--bless]
+//
+// --bless writes the hashes to the golden file, without it they're
+// compared and any difference fails the run.
+//
+// The same frames' sound can be saved or checked instead, mixed from every
+// device that makes any:
+//
+//   ... --frames 60 --wav out.wav | --wav-golden expected.wav
+
+use crust_6502_emulator::framehash::{self, memory_frame};
+use crust_6502_emulator::frame::AUDIO_SAMPLE_RATE;
+use crust_6502_emulator::machine::Machine;
+use crust_6502_emulator::traps::Traps;
+use crust_6502_emulator::watchdog::Watchdog;
+use crust_6502_emulator::wav::{self, compare_audio};
+use crust_6502_emulator::{cpu6502, decode_hex, print_cpu};
+
+const DEMO: &str = "A2 0A 8E 00 00 A2 03 8E 01 00 AC 00 00 A9 00 18 6D 01 00 88 D0 FA 8D 02 00 EA EA EA";
+
+fn main() {
+    let mut traps = Traps::default();
+    let mut positional = Vec::new();
+    let mut frames = None;
+    let mut screen = (0x0200, 0x400);
+    let mut golden = None;
+    let mut bless = false;
+    let mut watchdog = None;
+    let mut wav_out = None;
+    let mut wav_golden = None;
+
+    let mut all = std::env::args().skip(1);
+    while let Some(arg) = all.next() {
+        let addr = |s: Option<String>| s.and_then(|s| u16::from_str_radix(s.trim_start_matches('$'), 16).ok()).expect("trap addresses are hex");
+        match arg.as_str() {
+            "--char-out" => traps.char_out = Some(addr(all.next())),
+            "--exit" => traps.exit = Some(addr(all.next())),
+            "--frames" => frames = Some(all.next().and_then(|n| n.parse().ok()).expect("--frames takes a count")),
+            "--screen" => {
+                let range = all.next().expect("--screen takes <start>,<length>");
+                let (start, len) = range.split_once(',').expect("--screen takes <start>,<length>");
+                screen = (addr(Some(start.to_string())), usize::from_str_radix(len, 16).expect("--screen length is hex"));
+            }
+            "--golden" => golden = all.next(),
+            "--bless" => bless = true,
+            "--watchdog" => watchdog = Some(Watchdog::default()),
+            "--wav" => wav_out = all.next(),
+            "--wav-golden" => wav_golden = all.next(),
+            _ => positional.push(arg),
+        }
+    }
+
+    let mut args = positional.into_iter();
+    let program = args.next().unwrap_or_else(|| DEMO.to_string());
+    let instructions: u32 = args.next().map(|n| n.parse().expect("instruction count must be a number")).unwrap_or(1000);
+
+    let code = decode_hex(program.replace(' ', "").as_str()).expect("program must be hex bytes");
+
+    let mut cpu = cpu6502::new();
+
+    cpu.load_program(&code, 0x8000);
+    cpu.set_reset_vector(0x8000);
+    cpu.set_traps(traps);
+    cpu.set_watchdog(watchdog);
+    cpu.reset();
+
+    if let (Some(frames), Some(path)) = (frames, wav_out) {
+        let mut machine = Machine::from_cpu(cpu);
+        let samples = machine.record_wav(&path, frames).unwrap_or_else(|e| panic!("{}: {}", path, e));
+        println!("{} samples at {}Hz written to {}", samples, AUDIO_SAMPLE_RATE, path);
+        return;
+    }
+
+    if let (Some(frames), Some(golden)) = (frames, wav_golden) {
+        let (_, expected) = wav::load_wav(&golden).unwrap_or_else(|e| panic!("{}", e));
+        let mut machine = Machine::from_cpu(cpu);
+        let samples: Vec<f32> = (0..frames).flat_map(|_| machine.run_frame(&Default::default()).audio_samples).collect();
+        let problems = compare_audio(&expected, &samples, 1.0 / i16::MAX as f32);
+        for problem in &problems {
+            println!("{}", problem);
+        }
+        if !problems.is_empty() {
+            std::process::exit(1);
+        }
+        println!("{} samples match {}", samples.len(), golden);
+        return;
+    }
+
+    if let Some(frames) = frames {
+        let hashes = cpu.frame_hashes(frames, &mut memory_frame(screen.0, screen.1));
+        let golden = golden.expect("--frames needs a --golden file");
+
+        if bless {
+            framehash::save_golden(&golden, &hashes).unwrap_or_else(|e| panic!("{}", e));
+            println!("{} frames written to {}", hashes.len(), golden);
+            return;
+        }
+
+        let expected = framehash::load_golden(&golden).unwrap_or_else(|e| panic!("{}", e));
+        let problems = framehash::compare(&expected, &hashes);
+        for problem in &problems {
+            println!("{}", problem);
+        }
+        if !problems.is_empty() {
+            std::process::exit(1);
+        }
+        println!("{} frames match {}", hashes.len(), golden);
+        return;
+    }
+
+    for _ in 0..instructions {
+        if cpu.exit_code().is_some() {
+            break;
+        }
+        if cpu.watchdog().is_some() && cpu.at_self_loop() {
+            println!("Halted at ${:04x}", cpu.pc);
+            break;
+        }
+
+        loop {
+            cpu.clock();
+
+            if cpu.complete() {
+                break;
+            }
+        }
+    }
+
+    print_cpu(&mut cpu);
+
+    let bus = cpu.bus.borrow();
+    let zero_page: Vec<String> = (0..16).map(|addr| std::format!("{:02x}", bus.read(addr, true))).collect();
+    println!("Zero page: {}", zero_page.join(" "));
+
+    if let Some(code) = cpu.exit_code() {
+        std::process::exit(code as i32);
+    }
+}
@@ -0,0 +1,84 @@
+// Runs a binary with no window and checks memory afterwards, the shape of
+// a test for a guest program. The run ends when the program loops to
+// itself, hits the exit trap or a BRK, or runs out of cycles.
+//
+//   cargo run --no-default-features --example headless_assert -- [<file> <load addr>] [--expect <addr>=<bytes>]...
+//
+// Addresses and bytes are hex, several bytes are checked from addr on:
+// --expect 0200=0a0b0c. Any mismatch fails the run. With no file it runs a
+// built-in routine that fills $0200-$020F with 0 to 15.
+
+use crust_6502_emulator::assembler::assemble;
+use crust_6502_emulator::run::StopReason;
+use crust_6502_emulator::watchdog::Watchdog;
+use crust_6502_emulator::{cpu6502, decode_hex};
+
+const MAX_CYCLES: u64 = 100_000_000;
+
+const BUILT_IN: &str = "
+    .org $8000
+            LDX #0
+    fill:   TXA
+            STA $0200,X
+            INX
+            CPX #16
+            BNE fill
+    done:   JMP done
+";
+
+fn hex16(s: &str) -> u16 {
+    u16::from_str_radix(s.trim_start_matches('$'), 16).unwrap_or_else(|_| panic!("'{}' isn't a hex address", s))
+}
+
+fn main() {
+    let mut positional = Vec::new();
+    let mut expected = Vec::new();
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--expect" => {
+                let check = args.next().expect("--expect takes <addr>=<bytes>");
+                let (addr, bytes) = check.split_once('=').expect("--expect takes <addr>=<bytes>");
+                expected.push((hex16(addr), decode_hex(bytes).unwrap_or_else(|e| panic!("{}", e))));
+            }
+            _ => positional.push(arg),
+        }
+    }
+
+    let (program, load_addr) = match positional.as_slice() {
+        [file, addr] => (std::fs::read(file).unwrap_or_else(|e| panic!("can't read {}: {}", file, e)), hex16(addr)),
+        [] => {
+            let built_in = assemble(BUILT_IN).unwrap_or_else(|e| panic!("{}", e));
+            expected.push((0x0200, (0..16).collect()));
+            (built_in.bytes, built_in.origin)
+        }
+        _ => panic!("give a file and the address to load it at, or neither"),
+    };
+
+    let mut cpu = cpu6502::new();
+    cpu.load_program(&program, load_addr);
+    cpu.set_reset_vector(load_addr);
+    cpu.set_watchdog(Some(Watchdog::default()));
+    cpu.reset();
+
+    let reason = cpu.run_until(|_| false, MAX_CYCLES);
+    match reason {
+        StopReason::Halted(pc) => println!("halted at ${:04x} after {} cycles", pc, cpu.clock_count()),
+        StopReason::Trap(code) => println!("exited with {:?} after {} cycles", code, cpu.clock_count()),
+        other => println!("stopped with {:?} at ${:04x}", other, cpu.pc),
+    }
+
+    let mut failed = false;
+    for (addr, bytes) in &expected {
+        let found = cpu.peek_bytes(*addr, bytes.len());
+        if &found != bytes {
+            println!("${:04x}: expected {:02x?}, found {:02x?}", addr, bytes, found);
+            failed = true;
+        }
+    }
+    if failed || reason == StopReason::CycleLimit {
+        std::process::exit(1);
+    }
+    println!("{} checks passed", expected.len());
+}
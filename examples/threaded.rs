@@ -0,0 +1,137 @@
+// Runs the CPU on a worker thread and the window on the main one. The
+// worker never waits for the window: it sends a snapshot after each frame
+// of emulation only if the window has taken the last one, and picks up
+// commands without blocking.
+//
+//   cargo run --example threaded
+//
+// SPACE pauses, R resets, ESC quits.
+
+use std::sync::mpsc::{self, Receiver, SyncSender, TryRecvError, TrySendError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use minifb::{Key, KeyRepeat, Window, WindowOptions};
+
+use crust_6502_emulator::gui::StatusText;
+use crust_6502_emulator::snapshot::MachineState;
+use crust_6502_emulator::{cpu6502, decode_hex, CYCLES_PER_FRAME};
+
+const WIDTH: usize = 480;
+const HEIGHT: usize = 200;
+
+// 10 * 3, looping forever so there is always something to watch
+const DEMO: &str = "A2 0A 8E 00 00 A2 03 8E 01 00 AC 00 00 A9 00 18 6D 01 00 88 D0 FA 8D 02 00 EE 03 00 4C 00 80";
+
+enum Command {
+    Pause(bool),
+    Reset,
+    Quit,
+}
+
+// What the window gets to see of the machine
+struct Frame {
+    state: MachineState,
+    // Frames the worker ran since the last one the window took
+    frames: u64,
+}
+
+fn emulate(commands: Receiver<Command>, frames: SyncSender<Frame>) {
+    let mut cpu = cpu6502::new();
+    cpu.load_program(&decode_hex(DEMO.replace(' ', "").as_str()).unwrap(), 0x8000);
+    cpu.set_reset_vector(0x8000);
+    cpu.reset();
+
+    let mut paused = false;
+    let mut unseen = 0;
+
+    loop {
+        loop {
+            match commands.try_recv() {
+                Ok(Command::Pause(pause)) => paused = pause,
+                Ok(Command::Reset) => cpu.reset(),
+                Ok(Command::Quit) | Err(TryRecvError::Disconnected) => return,
+                Err(TryRecvError::Empty) => break,
+            }
+        }
+
+        if paused {
+            thread::sleep(Duration::from_millis(5));
+        } else {
+            for _ in 0..CYCLES_PER_FRAME {
+                cpu.clock();
+            }
+            unseen += 1;
+        }
+
+        // The window is busy with the last frame, carry on without it
+        match frames.try_send(Frame { state: MachineState::capture(&cpu), frames: unseen }) {
+            Ok(()) => unseen = 0,
+            Err(TrySendError::Full(_)) => {}
+            Err(TrySendError::Disconnected(_)) => return,
+        }
+    }
+}
+
+fn main() {
+    let (command_tx, command_rx) = mpsc::channel();
+    let (frame_tx, frame_rx) = mpsc::sync_channel(1);
+    let worker = thread::spawn(move || emulate(command_rx, frame_tx));
+
+    let mut window = Window::new("Threaded - ESC to exit", WIDTH, HEIGHT, WindowOptions::default()).unwrap_or_else(|e| panic!("{:?}", e));
+    window.limit_update_rate(Some(Duration::from_micros(16600)));
+
+    let text = StatusText::new(WIDTH, HEIGHT, 1);
+    let mut buffer = vec![0u32; WIDTH * HEIGHT];
+    let mut paused = false;
+    let mut latest: Option<Frame> = None;
+    let (mut counted, mut since) = (0u64, Instant::now());
+    let mut fps = 0.0;
+
+    while window.is_open() && !window.is_key_down(Key::Escape) {
+        if window.is_key_pressed(Key::Space, KeyRepeat::No) {
+            paused = !paused;
+            let _ = command_tx.send(Command::Pause(paused));
+        }
+        if window.is_key_pressed(Key::R, KeyRepeat::No) {
+            let _ = command_tx.send(Command::Reset);
+        }
+
+        if let Ok(frame) = frame_rx.try_recv() {
+            counted += frame.frames;
+            latest = Some(frame);
+        }
+
+        // Emulated frames per second of wall clock, how far ahead of the
+        // window the worker is running
+        if since.elapsed() >= Duration::from_secs(1) {
+            fps = counted as f64 / since.elapsed().as_secs_f64();
+            (counted, since) = (0, Instant::now());
+        }
+
+        buffer.iter_mut().for_each(|pixel| *pixel = 0);
+
+        if let Some(frame) = &latest {
+            let cpu = &frame.state.cpu;
+            text.draw(&mut buffer, (10, 10), std::format!("PC ${:04x}  A ${:02x}  X ${:02x}  Y ${:02x}  SP ${:02x}  P ${:02x}", cpu.pc, cpu.a, cpu.x, cpu.y, cpu.stkp, cpu.status).as_str(), 1);
+            text.draw(&mut buffer, (10, 25), std::format!("cycle {}", cpu.clock_count).as_str(), 1);
+
+            if let Some(ram) = frame.state.device("ram") {
+                for row in 0..4 {
+                    let bytes: Vec<String> = ram[row * 16..row * 16 + 16].iter().map(|b| std::format!("{:02x}", b)).collect();
+                    text.draw(&mut buffer, (10, 50 + row * 10), std::format!("${:04x}: {}", row * 16, bytes.join(" ")).as_str(), 1);
+                }
+            }
+        }
+
+        let status = if paused { "PAUSED".to_string() } else { std::format!("{:.0} frames/s", fps) };
+        text.draw(&mut buffer, (10, 110), status.as_str(), 0x00FF00FF);
+        text.draw(&mut buffer, (10, 180), "SPACE = Pause    R = Reset    ESC = Quit", 1);
+
+        window.update_with_buffer(&buffer, WIDTH, HEIGHT).unwrap();
+    }
+
+    let _ = command_tx.send(Command::Quit);
+    drop(frame_rx);
+    worker.join().unwrap();
+}
@@ -0,0 +1,166 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::ops::RangeInclusive;
+use std::path::Path;
+
+use crate::cpu6502;
+
+// Every read and write the CPU makes to the addresses of interest, with the
+// cycle it happened on and the instruction that made it, for working out
+// how a program drives its I/O registers. Accesses come from the CPU's own
+// bus cycles, so opcode and operand fetches are in it too, debugger peeks
+// and DMA aren't.
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AccessKind {
+    Read,
+    Write,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Access {
+    pub cycle: u64,
+    // The instruction making it, or the one interrupted for a push
+    pub pc: u16,
+    pub addr: u16,
+    pub value: u8,
+    pub kind: AccessKind,
+}
+
+// Which addresses get recorded
+pub enum AccessFilter {
+    Range(RangeInclusive<u16>),
+    Predicate(Box<dyn Fn(u16) -> bool + Send>),
+}
+
+impl AccessFilter {
+    pub fn matches(&self, addr: u16) -> bool {
+        match self {
+            AccessFilter::Range(range) => range.contains(&addr),
+            AccessFilter::Predicate(predicate) => predicate(addr),
+        }
+    }
+}
+
+pub struct AccessLog {
+    filter: AccessFilter,
+    accesses: Vec<Access>,
+}
+
+impl AccessLog {
+    pub fn new(filter: AccessFilter) -> Self {
+        AccessLog { filter, accesses: Vec::new() }
+    }
+
+    pub fn range(start: u16, end: u16) -> Self {
+        AccessLog::new(AccessFilter::Range(start..=end))
+    }
+
+    pub fn accesses(&self) -> &[Access] {
+        &self.accesses
+    }
+
+    pub fn clear(&mut self) {
+        self.accesses.clear();
+    }
+
+    pub fn record(&mut self, access: Access) {
+        if self.filter.matches(access.addr) {
+            self.accesses.push(access);
+        }
+    }
+
+    // One row per access, addresses and values in hex without a prefix so
+    // spreadsheets keep them as text
+    pub fn write_csv<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        writeln!(out, "cycle,pc,addr,value,access")?;
+        for access in &self.accesses {
+            let kind = match access.kind {
+                AccessKind::Read => "read",
+                AccessKind::Write => "write",
+            };
+            writeln!(out, "{},{:04x},{:04x},{:02x},{}", access.cycle, access.pc, access.addr, access.value, kind)?;
+        }
+        Ok(())
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let mut out = BufWriter::new(File::create(path)?);
+        self.write_csv(&mut out)?;
+        out.flush()
+    }
+}
+
+impl cpu6502 {
+    // A new log replaces whatever was logged so far, None stops logging
+    pub fn set_access_log(&mut self, log: Option<AccessLog>) {
+        self.access_log = log;
+    }
+
+    pub fn access_log(&self) -> Option<&AccessLog> {
+        self.access_log.as_ref()
+    }
+
+    pub fn take_access_log(&mut self) -> Option<AccessLog> {
+        self.access_log.take()
+    }
+
+    pub(crate) fn log_access(&mut self, addr: u16, value: u8, kind: AccessKind) {
+        if let Some(log) = &mut self.access_log {
+            log.record(Access { cycle: self.clock_count, pc: self.instruction_pc, addr, value, kind });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn filters_and_csv() {
+        let mut cpu = cpu6502::new();
+        // LDA $0210 / INC $0211 / STA $0300 / loop: JMP loop
+        cpu.load_program(&[0xAD, 0x10, 0x02, 0xEE, 0x11, 0x02, 0x8D, 0x00, 0x03, 0x4C, 0x09, 0x80], 0x8000);
+        cpu.set_reset_vector(0x8000);
+        cpu.reset();
+        cpu.bus.borrow_mut().write(0x0210, 0x42);
+        cpu.set_access_log(Some(AccessLog::range(0x0200, 0x02FF)));
+        cpu.run_instructions(4, 100);
+
+        let seen: Vec<(u16, u16, u8, AccessKind)> = cpu.access_log().unwrap().accesses().iter().map(|a| (a.pc, a.addr, a.value, a.kind)).collect();
+        assert_eq!(
+            seen,
+            [
+                (0x8000, 0x0210, 0x42, AccessKind::Read),
+                (0x8003, 0x0211, 0x00, AccessKind::Read),
+                (0x8003, 0x0211, 0x01, AccessKind::Write),
+            ]
+        );
+
+        let mut csv = Vec::new();
+        cpu.access_log().unwrap().write_csv(&mut csv).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        assert!(csv.starts_with("cycle,pc,addr,value,access\n"));
+        assert!(csv.ends_with(",8003,0211,01,write\n"), "{}", csv);
+
+        // An NMI's pushes go down to the instruction it interrupted, the
+        // handler's own accesses to the handler
+        cpu.load_program(&[0xAD, 0x10, 0x02], 0x9000);
+        cpu.set_nmi_vector(0x9000);
+        cpu.set_access_log(Some(AccessLog::range(0x0100, 0x02FF)));
+        cpu.trigger_nmi();
+        cpu.run_instructions(2, 100);
+        let seen: Vec<(u16, u16, AccessKind)> = cpu.access_log().unwrap().accesses().iter().map(|a| (a.pc, a.addr, a.kind)).collect();
+        assert_eq!(seen.len(), 4, "{:?}", seen);
+        assert!(seen[..3].iter().all(|&(pc, _, kind)| pc == 0x8009 && kind == AccessKind::Write), "{:?}", seen);
+        assert_eq!(seen[3], (0x9000, 0x0210, AccessKind::Read));
+
+        // Only the odd ones, and the code fetches come through too
+        let odd_ones = AccessFilter::Predicate(Box::new(|addr| addr == 0x8001 || addr == 0x0300));
+        cpu.reset();
+        cpu.set_access_log(Some(AccessLog::new(odd_ones)));
+        cpu.run_instructions(3, 100);
+        let addrs: Vec<u16> = cpu.take_access_log().unwrap().accesses().iter().map(|a| a.addr).collect();
+        assert_eq!(addrs, [0x8001, 0x0300]);
+    }
+}
@@ -0,0 +1,251 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+use crate::cpu6502;
+use crate::device::BusDevice;
+use crate::interrupts::IrqSource;
+use crate::scheduler::EventQueue;
+
+// A 6551 ACIA as a console: bytes the host sends arrive at the receiver,
+// bytes the guest transmits pile up for the host to take. There's no baud
+// rate, a byte is received as soon as it's sent and the transmitter is
+// always empty.
+//
+//   +0  data     read takes the received byte, write transmits
+//   +1  status   write is a programmed reset
+//   +2  command
+//   +3  control
+
+const STATUS_IRQ: u8 = 0x80;
+const STATUS_TDRE: u8 = 0x10;
+const STATUS_RDRF: u8 = 0x08;
+
+const COMMAND_DTR: u8 = 0x01;
+// Set to stop the receiver interrupting
+const COMMAND_RX_IRQ_OFF: u8 = 0x02;
+
+#[derive(Default)]
+struct Lines {
+    received: VecDeque<u8>,
+    transmitted: Vec<u8>,
+    command: u8,
+    control: u8,
+    // Where the IRQ output goes once attached, and what it was last set to
+    wiring: Option<(EventQueue, IrqSource)>,
+    irq_raised: bool,
+}
+
+impl Lines {
+    // Called after anything that can change the IRQ output. The line is
+    // moved at the end of the CPU's current clock, so a byte read in the
+    // handler has it down again before the next instruction.
+    fn update_irq(&mut self) {
+        let asserted = self.irq();
+        if asserted == self.irq_raised {
+            return;
+        }
+        if let Some((events, source)) = &self.wiring {
+            let source = *source;
+            events.schedule_in(0, move |cpu| cpu.set_irq(source, asserted));
+            self.irq_raised = asserted;
+        }
+    }
+
+    fn irq(&self) -> bool {
+        !self.received.is_empty() && self.command & (COMMAND_DTR | COMMAND_RX_IRQ_OFF) == COMMAND_DTR
+    }
+
+    fn status(&self) -> u8 {
+        let mut status = STATUS_TDRE;
+        if !self.received.is_empty() {
+            status |= STATUS_RDRF;
+        }
+        if self.irq() {
+            status |= STATUS_IRQ;
+        }
+        status
+    }
+}
+
+// The host's end of the serial line, which can be on another thread
+#[derive(Clone, Default)]
+pub struct AciaPort(Arc<Mutex<Lines>>);
+
+impl AciaPort {
+    fn lines(&self) -> MutexGuard<'_, Lines> {
+        self.0.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    pub fn send(&self, bytes: &[u8]) {
+        let mut lines = self.lines();
+        lines.received.extend(bytes);
+        lines.update_irq();
+    }
+
+    // Everything transmitted since the last call
+    pub fn take_output(&self) -> Vec<u8> {
+        std::mem::take(&mut self.lines().transmitted)
+    }
+
+    // Bytes sent that the guest hasn't read yet
+    pub fn pending_input(&self) -> usize {
+        self.lines().received.len()
+    }
+
+    pub fn irq(&self) -> bool {
+        self.lines().irq()
+    }
+}
+
+pub struct Acia {
+    port: AciaPort,
+}
+
+impl Acia {
+    pub fn new() -> Self {
+        Acia { port: AciaPort::default() }
+    }
+
+    pub fn port(&self) -> AciaPort {
+        self.port.clone()
+    }
+}
+
+impl BusDevice for Acia {
+    fn read(&mut self, offset: u16) -> u8 {
+        let mut lines = self.port.lines();
+        match offset & 3 {
+            0 => {
+                let data = lines.received.pop_front().unwrap_or(0);
+                lines.update_irq();
+                data
+            }
+            _ => {
+                drop(lines);
+                self.peek(offset)
+            }
+        }
+    }
+
+    fn peek(&self, offset: u16) -> u8 {
+        let lines = self.port.lines();
+        match offset & 3 {
+            0 => lines.received.front().copied().unwrap_or(0),
+            1 => lines.status(),
+            2 => lines.command,
+            _ => lines.control,
+        }
+    }
+
+    fn write(&mut self, offset: u16, data: u8) {
+        let mut lines = self.port.lines();
+        match offset & 3 {
+            0 => lines.transmitted.push(data),
+            // Programmed reset leaves the top of the command register alone
+            1 => lines.command &= 0xE0,
+            2 => lines.command = data,
+            _ => lines.control = data,
+        }
+        lines.update_irq();
+    }
+
+    fn reset(&mut self) {
+        let mut lines = self.port.lines();
+        lines.command = 0;
+        lines.control = 0;
+        lines.update_irq();
+    }
+}
+
+impl cpu6502 {
+    // Maps an ACIA at $base-$base+3 with its IRQ wired to the CPU and
+    // returns the host's end of it
+    pub fn attach_acia(&mut self, base: u16) -> AciaPort {
+        let acia = Acia::new();
+        let port = acia.port();
+        self.bus.borrow_mut().map("acia", base, base.wrapping_add(3), Box::new(acia));
+
+        let source = self.interrupts.register_source("acia");
+        let mut lines = port.lines();
+        lines.wiring = Some((self.events(), source));
+        lines.update_irq();
+        drop(lines);
+
+        port
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn registers() {
+        let mut acia = Acia::new();
+        let port = acia.port();
+
+        assert_eq!(acia.read(1), STATUS_TDRE);
+        port.send(b"ok");
+        assert_eq!(acia.peek(1), STATUS_TDRE | STATUS_RDRF);
+        assert_eq!((acia.read(0), acia.read(0), acia.read(1)), (b'o', b'k', STATUS_TDRE));
+
+        acia.write(0, b'!');
+        assert_eq!(port.take_output(), b"!");
+        assert!(port.take_output().is_empty());
+
+        acia.write(2, 0xCB);
+        acia.write(1, 0x00);
+        assert_eq!(acia.read(2), 0xC0);
+    }
+
+    #[test]
+    fn polled_echo() {
+        let mut cpu = cpu6502::new();
+        // loop: LDA $A001 / AND #$08 / BEQ loop / LDA $A000 / STA $A000 / JMP loop
+        cpu.load_program(&[0xAD, 0x01, 0xA0, 0x29, 0x08, 0xF0, 0xF9, 0xAD, 0x00, 0xA0, 0x8D, 0x00, 0xA0, 0x4C, 0x00, 0x80], 0x8000);
+        cpu.set_reset_vector(0x8000);
+        let port = cpu.attach_acia(0xA000);
+        cpu.reset();
+
+        port.send(b"hi");
+        for _ in 0..1000 {
+            cpu.clock();
+        }
+        assert_eq!(port.take_output(), b"hi");
+        assert_eq!(port.pending_input(), 0);
+    }
+
+    #[test]
+    fn received_bytes_interrupt() {
+        let mut cpu = cpu6502::new();
+        // LDA #$01 / STA $A002 / CLI / loop: JMP loop
+        cpu.load_program(&[0xA9, COMMAND_DTR, 0x8D, 0x02, 0xA0, 0x58, 0x4C, 0x06, 0x80], 0x8000);
+        // irq: LDA $A001 / BPL done / LDA $A000 / STA $10 / done: RTI
+        cpu.load_program(&[0xAD, 0x01, 0xA0, 0x10, 0x05, 0xAD, 0x00, 0xA0, 0x85, 0x10, 0x40], 0x9000);
+        cpu.set_reset_vector(0x8000);
+        cpu.set_irq_vector(0x9000);
+        let port = cpu.attach_acia(0xA000);
+        cpu.reset();
+
+        for _ in 0..20 {
+            cpu.clock();
+        }
+        assert!(cpu.interrupts.asserted_sources().is_empty());
+
+        // Raised on the next clock, not on some later poll
+        port.send(b"x");
+        assert!(port.irq());
+        cpu.clock();
+        assert!(!cpu.interrupts.asserted_sources().is_empty());
+
+        for _ in 0..200 {
+            cpu.clock();
+        }
+
+        assert_eq!(cpu.bus.borrow().read(0x10, true), b'x');
+        assert!(!port.irq());
+        assert!(cpu.interrupts.asserted_sources().is_empty());
+        // Down as soon as the byte is read, so the handler runs only once
+        assert_eq!(cpu.interrupts.stats.taken, 1);
+    }
+}
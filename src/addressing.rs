@@ -0,0 +1,157 @@
+// Effective addresses against a model written from the NMOS 6502 datasheet
+// rather than from the emulator, for random operands, index registers and
+// pointers. Each mode is run on its own with PC on the operand, the way
+// clock() calls it once the opcode is fetched, and has to land on the
+// model's address, move PC past the operand and report a page crossing
+// only where the datasheet has one.
+
+use proptest::prelude::*;
+
+use crate::cpu6502;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Mode {
+    Imm,
+    Zp,
+    Zpx,
+    Zpy,
+    Abs,
+    Abx,
+    Aby,
+    Ind,
+    Izx,
+    Izy,
+}
+
+const MODES: [Mode; 10] = [Mode::Imm, Mode::Zp, Mode::Zpx, Mode::Zpy, Mode::Abs, Mode::Abx, Mode::Aby, Mode::Ind, Mode::Izx, Mode::Izy];
+
+impl Mode {
+    fn operand_len(&self) -> u16 {
+        match self {
+            Mode::Imm | Mode::Zp | Mode::Zpx | Mode::Zpy | Mode::Izx | Mode::Izy => 1,
+            Mode::Abs | Mode::Abx | Mode::Aby | Mode::Ind => 2,
+        }
+    }
+
+    fn run(&self, cpu: &mut cpu6502) -> u8 {
+        match self {
+            Mode::Imm => cpu6502::IMM(cpu),
+            Mode::Zp => cpu6502::ZP0(cpu),
+            Mode::Zpx => cpu6502::ZPX(cpu),
+            Mode::Zpy => cpu6502::ZPY(cpu),
+            Mode::Abs => cpu6502::ABS(cpu),
+            Mode::Abx => cpu6502::ABX(cpu),
+            Mode::Aby => cpu6502::ABY(cpu),
+            Mode::Ind => cpu6502::IND(cpu),
+            Mode::Izx => cpu6502::IZX(cpu),
+            Mode::Izy => cpu6502::IZY(cpu),
+        }
+    }
+}
+
+// The address the datasheet gives and whether indexing carried into the
+// high byte. `pc` is where the operand starts.
+fn model(mode: Mode, pc: u16, x: u8, y: u8, memory: impl Fn(u16) -> u8) -> (u16, bool) {
+    let zp = memory(pc);
+    let absolute = u16::from_le_bytes([memory(pc), memory(pc.wrapping_add(1))]);
+    // A pointer in zero page, whose high byte comes from $00 after $FF
+    let zp_pointer = |at: u8| u16::from_le_bytes([memory(at as u16), memory(at.wrapping_add(1) as u16)]);
+    let indexed = |base: u16, index: u8| {
+        let address = base.wrapping_add(index as u16);
+        (address, address >> 8 != base >> 8)
+    };
+
+    match mode {
+        Mode::Imm => (pc, false),
+        Mode::Zp => (zp as u16, false),
+        Mode::Zpx => (zp.wrapping_add(x) as u16, false),
+        Mode::Zpy => (zp.wrapping_add(y) as u16, false),
+        Mode::Abs => (absolute, false),
+        Mode::Abx => indexed(absolute, x),
+        Mode::Aby => indexed(absolute, y),
+        // The pointer's high byte is fetched without carrying into its
+        // page, JMP ($10FF) reads $10FF and $1000
+        Mode::Ind => {
+            let high = (absolute & 0xFF00) | (absolute.wrapping_add(1) & 0x00FF);
+            (u16::from_le_bytes([memory(absolute), memory(high)]), false)
+        }
+        Mode::Izx => (zp_pointer(zp.wrapping_add(x)), false),
+        Mode::Izy => indexed(zp_pointer(zp), y),
+    }
+}
+
+// Operand and pointer bytes put where the mode will look, the rest of
+// memory as a fresh CPU has it
+fn check(mode: Mode, pc: u16, operand: [u8; 2], pointer: [u8; 2], x: u8, y: u8) -> Result<(), TestCaseError> {
+    let mut cpu = cpu6502::new();
+    {
+        let mut bus = cpu.bus.borrow_mut();
+        bus.write(pc, operand[0]);
+        bus.write(pc.wrapping_add(1), operand[1]);
+        let absolute = u16::from_le_bytes(operand);
+        let pointer_at = match mode {
+            Mode::Izx => operand[0].wrapping_add(x) as u16,
+            Mode::Izy => operand[0] as u16,
+            _ => absolute,
+        };
+        let next = match mode {
+            Mode::Ind => (pointer_at & 0xFF00) | (pointer_at.wrapping_add(1) & 0x00FF),
+            _ => (pointer_at as u8).wrapping_add(1) as u16,
+        };
+        if matches!(mode, Mode::Ind | Mode::Izx | Mode::Izy) {
+            bus.write(pointer_at, pointer[0]);
+            bus.write(next, pointer[1]);
+        }
+    }
+    cpu.pc = pc;
+    cpu.x = x;
+    cpu.y = y;
+
+    let bus = cpu.bus.clone();
+    let (expected, crossed) = model(mode, pc, x, y, |addr| bus.borrow().read(addr, true));
+    let extra = mode.run(&mut cpu);
+
+    prop_assert_eq!(cpu.addr_abs, expected, "{:?} at ${:04x} with {:02x?}, X={:02x} Y={:02x}", mode, pc, operand, x, y);
+    prop_assert_eq!(extra == 1, crossed, "{:?} page crossing", mode);
+    prop_assert_eq!(cpu.pc, pc.wrapping_add(mode.operand_len()));
+    Ok(())
+}
+
+proptest! {
+    #[test]
+    fn effective_addresses_match_the_datasheet(
+        mode in proptest::sample::select(MODES.to_vec()),
+        pc in any::<u16>(),
+        operand in any::<[u8; 2]>(),
+        pointer in any::<[u8; 2]>(),
+        x in any::<u8>(),
+        y in any::<u8>(),
+    ) {
+        check(mode, pc, operand, pointer, x, y)?;
+    }
+
+    // The corners the random cases rarely find: indexes carrying out of a
+    // page or past $FFFF, zero page pointers at $FF, and JMP ($xxFF)
+    #[test]
+    fn page_edges_match_the_datasheet(
+        mode in proptest::sample::select(MODES.to_vec()),
+        pc in any::<u16>(),
+        high in any::<u8>(),
+        pointer in any::<[u8; 2]>(),
+        index in 0x80u8..=0xFF,
+    ) {
+        check(mode, pc, [0xFF, high], pointer, index, index)?;
+        check(mode, pc, [0xFF, 0xFF], pointer, index, index)?;
+    }
+}
+
+#[test]
+fn relative_targets_are_signed_from_the_next_instruction() {
+    let mut cpu = cpu6502::new();
+    for (pc, offset, target) in [(0x8000u16, 0x10u8, 0x8011u16), (0x8000, 0xF0, 0x7FF1), (0x80FE, 0x7F, 0x817E), (0xFFFE, 0x05, 0x0004)] {
+        cpu.bus.borrow_mut().write(pc, offset);
+        cpu.pc = pc;
+        cpu6502::REL(&mut cpu);
+        assert_eq!(cpu.pc.wrapping_add(cpu.addr_rel), target, "offset {:02x} at ${:04x}", offset, pc);
+    }
+}
@@ -0,0 +1,679 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::symbols::SymbolTable;
+use crate::{cpu6502, Variant};
+
+// In order of precedence, when two vectors share a handler it's named
+// after the first
+const VECTORS: [(&str, u16); 3] = [("reset", 0xFFFC), ("nmi", 0xFFFA), ("irq", 0xFFFE)];
+
+// How an instruction gets to the address it refers to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum RefKind {
+    Call,
+    Jump,
+    Branch,
+    // JMP ($xxxx), through the pointer as memory holds it now
+    Indirect,
+}
+
+impl RefKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            RefKind::Call => "call",
+            RefKind::Jump => "jump",
+            RefKind::Branch => "branch",
+            RefKind::Indirect => "indirect",
+        }
+    }
+}
+
+// One instruction that goes to an address
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct XRef {
+    pub from: u16,
+    pub kind: RefKind,
+}
+
+// Everything that goes to each address, by the address gone to
+pub type XRefs = BTreeMap<u16, BTreeSet<XRef>>;
+
+// "xref $8003 call, $8010 branch"
+pub fn describe_xrefs(refs: &BTreeSet<XRef>) -> String {
+    let refs: Vec<String> = refs.iter().map(|xref| std::format!("${:04x} {}", xref.from, xref.kind.name())).collect();
+    std::format!("xref {}", refs.join(", "))
+}
+
+// A subroutine found by following code from an entry point
+#[derive(Debug, Clone)]
+pub struct Function {
+    pub entry: u16,
+    // Lowest and highest address of any instruction reached from the entry
+    pub start: u16,
+    pub end: u16,
+    pub instructions: usize,
+    // Where each of those instructions starts
+    pub code: BTreeSet<u16>,
+    // Subroutines this one calls with JSR or jumps into with JMP
+    pub calls: BTreeSet<u16>,
+    // Set for the handlers the CPU vectors point at
+    pub vector: Option<&'static str>,
+    // Has a JMP ($xxxx), which can go anywhere. Its pointer is followed as
+    // memory holds it now, as a call, but may well hold something else
+    // by the time the jump runs.
+    pub indirect: bool,
+    // Every call, jump and branch made from the function's code, as the
+    // address gone to and where from
+    pub refs: BTreeSet<(u16, XRef)>,
+}
+
+impl Function {
+    // The label given to the function when it has no symbol of its own
+    pub fn name(&self) -> String {
+        match self.vector {
+            Some(vector) => vector.to_string(),
+            None => std::format!("sub_{:04x}", self.entry),
+        }
+    }
+}
+
+pub struct CallGraph {
+    pub functions: BTreeMap<u16, Function>,
+    pub xrefs: XRefs,
+}
+
+// Walks the code reachable from the vectors plus any entry points seen
+// while the program ran (the profiler's JSR targets, say), treating every
+// JSR target as the start of a new function.
+pub fn analyze(cpu: &cpu6502, observed: &[u16]) -> CallGraph {
+    let mut vectors: BTreeMap<u16, &'static str> = BTreeMap::new();
+    for (name, vector) in VECTORS.iter() {
+        // A vector still pointing at $0000 was never set up
+        let target = read16(cpu, *vector);
+        if target != 0x0000 {
+            vectors.entry(target).or_insert(*name);
+        }
+    }
+
+    let mut entries: BTreeSet<u16> = vectors.keys().copied().collect();
+    entries.extend(observed.iter().copied());
+
+    // Keep walking until no new JSR targets turn up, then walk everything
+    // once more so a JMP to any entry point counts as a tail call
+    let mut functions: BTreeMap<u16, Function> = BTreeMap::new();
+    loop {
+        let pending: Vec<u16> = entries.iter().copied().filter(|entry| !functions.contains_key(entry)).collect();
+        if pending.is_empty() {
+            break;
+        }
+
+        for entry in pending {
+            let function = walk(cpu, entry, &entries);
+            functions.insert(entry, function);
+        }
+
+        for function in functions.values() {
+            entries.extend(function.calls.iter().copied());
+        }
+    }
+
+    let functions: BTreeMap<u16, Function> = entries.iter().map(|entry| {
+        let mut function = walk(cpu, *entry, &entries);
+        function.vector = vectors.get(entry).copied();
+        (*entry, function)
+    }).collect();
+
+    // Code shared between functions is walked by each of them, the sets
+    // keep one of each reference
+    let mut xrefs = XRefs::new();
+    for (target, xref) in functions.values().flat_map(|function| function.refs.iter()) {
+        xrefs.entry(*target).or_default().insert(*xref);
+    }
+
+    CallGraph { functions, xrefs }
+}
+
+fn read16(cpu: &cpu6502, addr: u16) -> u16 {
+    let bus = cpu.bus.borrow();
+    (bus.read(addr, true) as u16) | ((bus.read(addr.wrapping_add(1), true) as u16) << 8)
+}
+
+// Where JMP ($xxxx) would go, the NMOS part doesn't carry into the high
+// byte of the pointer
+fn indirect_target(cpu: &cpu6502, pointer: u16) -> u16 {
+    let high = match cpu.variant {
+        Variant::Nmos6502 => (pointer & 0xFF00) | (pointer.wrapping_add(1) & 0x00FF),
+        Variant::Cmos65C02 => pointer.wrapping_add(1),
+    };
+    let bus = cpu.bus.borrow();
+    u16::from_le_bytes([bus.read(pointer, true), bus.read(high, true)])
+}
+
+// Follow one function's code from its entry, stopping at returns and at
+// calls or jumps into other known entry points
+fn walk(cpu: &cpu6502, entry: u16, entries: &BTreeSet<u16>) -> Function {
+    let read = |addr: u16| cpu.bus.borrow().read(addr, true);
+
+    let mut function = Function {
+        entry,
+        start: entry,
+        end: entry,
+        instructions: 0,
+        code: BTreeSet::new(),
+        calls: BTreeSet::new(),
+        vector: None,
+        indirect: false,
+        refs: BTreeSet::new(),
+    };
+
+    let mut visited = BTreeSet::new();
+    let mut work = vec![entry];
+
+    while let Some(addr) = work.pop() {
+        if !visited.insert(addr) {
+            continue;
+        }
+
+        let opcode = read(addr);
+        if !cpu.is_instruction(opcode) {
+            continue;
+        }
+
+        let len = cpu.instruction_len(opcode);
+        let next = addr.wrapping_add(len);
+
+        function.instructions += 1;
+        function.code.insert(addr);
+        function.start = function.start.min(addr);
+        function.end = function.end.max(addr.wrapping_add(len - 1));
+
+        match opcode {
+            // JSR, the callee is a function of its own
+            0x20 => {
+                let target = read16(cpu, addr.wrapping_add(1));
+                function.calls.insert(target);
+                function.refs.insert((target, XRef { from: addr, kind: RefKind::Call }));
+                work.push(next);
+            }
+            // JMP, a tail call when it lands on another entry point
+            0x4C => {
+                let target = read16(cpu, addr.wrapping_add(1));
+                function.refs.insert((target, XRef { from: addr, kind: RefKind::Jump }));
+                if target != entry && entries.contains(&target) {
+                    function.calls.insert(target);
+                } else {
+                    work.push(target);
+                }
+            }
+            // A pointer still holding $0000 hasn't been set up yet
+            0x6C => {
+                function.indirect = true;
+                let target = indirect_target(cpu, read16(cpu, addr.wrapping_add(1)));
+                if target != 0x0000 {
+                    function.calls.insert(target);
+                    function.refs.insert((target, XRef { from: addr, kind: RefKind::Indirect }));
+                }
+            }
+            // RTS, RTI and BRK end the path
+            0x60 | 0x40 | 0x00 => {}
+            // Branches may go either way
+            0x10 | 0x30 | 0x50 | 0x70 | 0x90 | 0xB0 | 0xD0 | 0xF0 => {
+                let offset = read(addr.wrapping_add(1)) as i8;
+                let target = next.wrapping_add(offset as u16);
+                function.refs.insert((target, XRef { from: addr, kind: RefKind::Branch }));
+                work.push(target);
+                work.push(next);
+            }
+            _ => work.push(next),
+        }
+    }
+
+    function
+}
+
+impl CallGraph {
+    // Every address an instruction was found at, anything else the
+    // functions span is data as far as we can tell
+    pub fn code(&self) -> BTreeSet<u16> {
+        self.functions.values().flat_map(|function| function.code.iter().copied()).collect()
+    }
+
+    // Label from the symbol table if there is one, otherwise our own
+    fn label(&self, addr: u16, symbols: &SymbolTable) -> String {
+        match (symbols.name_of(addr), self.functions.get(&addr)) {
+            (Some(name), _) => name.to_string(),
+            (None, Some(function)) => function.name(),
+            (None, None) => std::format!("sub_{:04x}", addr),
+        }
+    }
+
+    // Name every function that doesn't already have a symbol, so the
+    // disassembly and the expression evaluator can use the labels.
+    // Returns how many were added.
+    pub fn add_labels(&self, symbols: &mut SymbolTable) -> usize {
+        let mut added = 0;
+
+        for function in self.functions.values() {
+            if symbols.name_of(function.entry).is_none() {
+                symbols.insert(function.name().as_str(), function.entry);
+                added += 1;
+            }
+        }
+
+        added
+    }
+
+    pub fn to_dot(&self, symbols: &SymbolTable) -> String {
+        let mut s = String::from("digraph calls {\n    node [shape=box, fontname=monospace];\n");
+
+        for function in self.functions.values() {
+            let shape = if function.vector.is_some() { ", style=bold" } else { "" };
+            s.push_str(std::format!("    n{:04x} [label=\"{}\\n${:04x}-${:04x}\"{}];\n", function.entry, json_escape(&self.label(function.entry, symbols)), function.start, function.end, shape).as_str());
+        }
+
+        for function in self.functions.values() {
+            for callee in &function.calls {
+                s.push_str(std::format!("    n{:04x} -> n{:04x};\n", function.entry, callee).as_str());
+            }
+        }
+
+        s.push_str("}\n");
+        s
+    }
+
+    pub fn to_json(&self, symbols: &SymbolTable) -> String {
+        let mut s = String::from("{\n  \"functions\": [");
+
+        for (i, function) in self.functions.values().enumerate() {
+            let calls: Vec<String> = function.calls.iter().map(|addr| addr.to_string()).collect();
+
+            s.push_str(if i == 0 { "\n" } else { ",\n" });
+            s.push_str(std::format!(
+                "    {{\"name\": \"{}\", \"entry\": {}, \"start\": {}, \"end\": {}, \"instructions\": {}, \"vector\": {}, \"indirect\": {}, \"calls\": [{}]}}",
+                json_escape(&self.label(function.entry, symbols)),
+                function.entry,
+                function.start,
+                function.end,
+                function.instructions,
+                match function.vector {
+                    Some(vector) => std::format!("\"{}\"", vector),
+                    None => "null".to_string(),
+                },
+                function.indirect,
+                calls.join(", "),
+            ).as_str());
+        }
+
+        s.push_str("\n  ],\n  \"xrefs\": [");
+
+        let refs = self.xrefs.iter().flat_map(|(target, refs)| refs.iter().map(move |xref| (*target, xref)));
+        for (i, (target, xref)) in refs.enumerate() {
+            s.push_str(if i == 0 { "\n" } else { ",\n" });
+            s.push_str(std::format!("    {{\"to\": {}, \"from\": {}, \"kind\": \"{}\"}}", target, xref.from, xref.kind.name()).as_str());
+        }
+
+        s.push_str("\n  ]\n}\n");
+        s
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EdgeKind {
+    // Straight on to the next instruction, including a branch not taken
+    FallThrough,
+    Taken,
+    Jump,
+}
+
+// A run of instructions that is only ever entered at the top and left at
+// the bottom
+#[derive(Debug, Clone)]
+pub struct BasicBlock {
+    pub start: u16,
+    pub instructions: Vec<u16>,
+    pub successors: Vec<(u16, EdgeKind)>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ControlFlowGraph {
+    pub entry: u16,
+    pub blocks: BTreeMap<u16, BasicBlock>,
+}
+
+// Basic blocks of the subroutine at `entry`. Calls and tail jumps into
+// other subroutines end a path the same way they do in the call graph.
+pub fn control_flow(cpu: &cpu6502, entry: u16) -> ControlFlowGraph {
+    let graph = analyze(cpu, &[entry]);
+    let entries: BTreeSet<u16> = graph.functions.keys().copied().collect();
+    let read = |addr: u16| cpu.bus.borrow().read(addr, true);
+
+    let mut successors: BTreeMap<u16, Vec<(u16, EdgeKind)>> = BTreeMap::new();
+    let mut lengths: BTreeMap<u16, u16> = BTreeMap::new();
+    let mut leaders = BTreeSet::from([entry]);
+    let mut work = vec![entry];
+
+    while let Some(addr) = work.pop() {
+        if lengths.contains_key(&addr) {
+            continue;
+        }
+
+        let opcode = read(addr);
+        if !cpu.is_instruction(opcode) {
+            continue;
+        }
+
+        let len = cpu.instruction_len(opcode);
+        let next = addr.wrapping_add(len);
+        lengths.insert(addr, len);
+
+        let edges = match opcode {
+            0x4C => {
+                let target = read16(cpu, addr.wrapping_add(1));
+                if target != entry && entries.contains(&target) {
+                    vec![]
+                } else {
+                    leaders.insert(target);
+                    vec![(target, EdgeKind::Jump)]
+                }
+            }
+            0x6C | 0x60 | 0x40 | 0x00 => vec![],
+            0x10 | 0x30 | 0x50 | 0x70 | 0x90 | 0xB0 | 0xD0 | 0xF0 => {
+                let offset = read(addr.wrapping_add(1)) as i8;
+                let target = next.wrapping_add(offset as u16);
+                leaders.insert(target);
+                leaders.insert(next);
+                vec![(target, EdgeKind::Taken), (next, EdgeKind::FallThrough)]
+            }
+            _ => vec![(next, EdgeKind::FallThrough)],
+        };
+
+        work.extend(edges.iter().map(|(target, _)| *target));
+        successors.insert(addr, edges);
+    }
+
+    // An instruction carries on its predecessor's block only when it is
+    // reached by falling straight through from exactly one instruction.
+    // Anything else, a jump into the middle of an instruction included,
+    // starts a block of its own.
+    let falls_into = |addr: &u16| -> Option<u16> {
+        let next = addr.wrapping_add(lengths[addr]);
+        (successors[addr].as_slice() == [(next, EdgeKind::FallThrough)]).then_some(next)
+    };
+
+    let mut fall_ins: BTreeMap<u16, usize> = BTreeMap::new();
+    for addr in lengths.keys() {
+        if let Some(next) = falls_into(addr) {
+            *fall_ins.entry(next).or_insert(0) += 1;
+        }
+    }
+
+    let starts_block = |addr: &u16| leaders.contains(addr) || fall_ins.get(addr) != Some(&1);
+
+    let mut blocks: BTreeMap<u16, BasicBlock> = BTreeMap::new();
+
+    for start in lengths.keys().filter(|addr| starts_block(addr)) {
+        let mut block = BasicBlock { start: *start, instructions: Vec::new(), successors: Vec::new() };
+        let mut addr = *start;
+
+        loop {
+            block.instructions.push(addr);
+
+            match falls_into(&addr) {
+                Some(next) if lengths.contains_key(&next) && !starts_block(&next) => addr = next,
+                _ => break,
+            }
+        }
+
+        block.successors = successors[&addr].iter().copied().filter(|(target, _)| lengths.contains_key(target)).collect();
+        blocks.insert(block.start, block);
+    }
+
+    ControlFlowGraph { entry, blocks }
+}
+
+impl ControlFlowGraph {
+    // The block holding the instruction at addr
+    pub fn block_at(&self, addr: u16) -> Option<&BasicBlock> {
+        self.blocks.range(..=addr).next_back().map(|(_, block)| block).filter(|block| block.instructions.contains(&addr))
+    }
+
+    // `map_lines` is the disassembly from cpu6502::disassemble
+    pub fn to_dot(&self, map_lines: &BTreeMap<u16, String>, symbols: &SymbolTable) -> String {
+        let name = match symbols.name_of(self.entry) {
+            Some(name) => name.to_string(),
+            None => std::format!("sub_{:04x}", self.entry),
+        };
+
+        let mut s = std::format!("digraph \"{}\" {{\n    node [shape=box, fontname=monospace];\n", json_escape(&name));
+
+        for block in self.blocks.values() {
+            let mut label = String::new();
+            if let Some(name) = symbols.name_of(block.start) {
+                label.push_str(std::format!("{}:\\l", json_escape(name)).as_str());
+            }
+            for addr in &block.instructions {
+                let line = map_lines.get(addr).map(|line| line.as_str()).unwrap_or("???");
+                label.push_str(json_escape(line).as_str());
+                label.push_str("\\l");
+            }
+
+            s.push_str(std::format!("    b{:04x} [label=\"{}\"];\n", block.start, label).as_str());
+        }
+
+        for block in self.blocks.values() {
+            for (target, kind) in &block.successors {
+                let style = match kind {
+                    EdgeKind::FallThrough => "",
+                    EdgeKind::Taken => " [color=green]",
+                    EdgeKind::Jump => " [color=blue]",
+                };
+                s.push_str(std::format!("    b{:04x} -> b{:04x}{};\n", block.start, target, style).as_str());
+            }
+        }
+
+        s.push_str("}\n");
+        s
+    }
+}
+
+pub(crate) fn json_escape(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn load(cpu: &cpu6502, addr: u16, bytes: &[u8]) {
+        for (i, byte) in bytes.iter().enumerate() {
+            cpu.bus.borrow_mut().write(addr + i as u16, *byte);
+        }
+    }
+
+    fn set_vector(cpu: &cpu6502, vector: u16, target: u16) {
+        load(cpu, vector, &[target as u8, (target >> 8) as u8]);
+    }
+
+    #[test]
+    fn finds_functions_from_the_vectors() {
+        let cpu = cpu6502::new();
+        // reset: JSR $8010 / JMP $8000, $8010: JSR $8020 / RTS, $8020: RTS
+        load(&cpu, 0x8000, &[0x20, 0x10, 0x80, 0x4C, 0x00, 0x80]);
+        load(&cpu, 0x8010, &[0x20, 0x20, 0x80, 0x60]);
+        load(&cpu, 0x8020, &[0x60]);
+        // irq: RTI
+        load(&cpu, 0x9000, &[0x40]);
+        set_vector(&cpu, 0xFFFC, 0x8000);
+        set_vector(&cpu, 0xFFFE, 0x9000);
+
+        let graph = analyze(&cpu, &[]);
+
+        assert_eq!(graph.functions.keys().copied().collect::<Vec<_>>(), vec![0x8000, 0x8010, 0x8020, 0x9000]);
+        assert_eq!(graph.functions[&0x8000].vector, Some("reset"));
+        assert_eq!(graph.functions[&0x9000].name(), "irq");
+        assert_eq!(graph.functions[&0x8000].calls.iter().copied().collect::<Vec<_>>(), vec![0x8010]);
+        assert_eq!(graph.functions[&0x8010].calls.iter().copied().collect::<Vec<_>>(), vec![0x8020]);
+        assert_eq!((graph.functions[&0x8010].start, graph.functions[&0x8010].end), (0x8010, 0x8013));
+    }
+
+    #[test]
+    fn reset_wins_a_shared_vector() {
+        let cpu = cpu6502::new();
+        load(&cpu, 0x8000, &[0x4C, 0x00, 0x80]);
+        for vector in [0xFFFA, 0xFFFC, 0xFFFE] {
+            set_vector(&cpu, vector, 0x8000);
+        }
+
+        let graph = analyze(&cpu, &[]);
+        assert_eq!(graph.functions[&0x8000].vector, Some("reset"));
+    }
+
+    #[test]
+    fn observed_entries_and_tail_calls() {
+        let cpu = cpu6502::new();
+        // $8000 is only reachable through JMP ($0300), the profiler saw it
+        // called. It tail calls $8010 which was also seen.
+        load(&cpu, 0x8000, &[0xE8, 0x4C, 0x10, 0x80]);
+        load(&cpu, 0x8010, &[0x6C, 0x00, 0x03]);
+
+        let graph = analyze(&cpu, &[0x8000, 0x8010]);
+
+        assert_eq!(graph.functions[&0x8000].calls.iter().copied().collect::<Vec<_>>(), vec![0x8010]);
+        assert_eq!(graph.functions[&0x8000].instructions, 2);
+        assert!(graph.functions[&0x8010].indirect);
+    }
+
+    #[test]
+    fn labels_go_into_the_symbol_table() {
+        let cpu = cpu6502::new();
+        load(&cpu, 0x8000, &[0x20, 0x10, 0x80, 0x60]);
+        load(&cpu, 0x8010, &[0x60]);
+        set_vector(&cpu, 0xFFFC, 0x8000);
+
+        let mut symbols = SymbolTable::new();
+        symbols.insert("main", 0x8000);
+
+        let graph = analyze(&cpu, &[]);
+        assert_eq!(graph.add_labels(&mut symbols), 1);
+        assert_eq!(symbols.name_of(0x8000), Some("main"));
+        assert_eq!(symbols.lookup("sub_8010"), Some(0x8010));
+    }
+
+    #[test]
+    fn dot_and_json_escape_labels() {
+        let cpu = cpu6502::new();
+        load(&cpu, 0x8000, &[0x60]);
+        set_vector(&cpu, 0xFFFC, 0x8000);
+
+        let mut symbols = SymbolTable::new();
+        symbols.insert("say\"hi\"", 0x8000);
+
+        let graph = analyze(&cpu, &[]);
+        assert!(graph.to_dot(&symbols).contains("label=\"say\\\"hi\\\"\\n$8000-$8000\""));
+        assert!(graph.to_json(&symbols).contains("\"name\": \"say\\\"hi\\\"\""));
+    }
+
+    #[test]
+    fn disassembly_uses_labels() {
+        let mut cpu = cpu6502::new();
+        // JSR $8010 / BNE -5 / LDA $00fb
+        load(&cpu, 0x8000, &[0x20, 0x10, 0x80, 0xD0, 0xFB, 0xA5, 0xFB]);
+        load(&cpu, 0x8010, &[0x60]);
+        set_vector(&cpu, 0xFFFC, 0x8000);
+
+        let mut symbols = SymbolTable::new();
+        symbols.insert("ptr", 0x00FB);
+        analyze(&cpu, &[]).add_labels(&mut symbols);
+
+        let lines = cpu.disassemble_with_symbols(0x8000, 0x8005, &symbols);
+        assert_eq!(lines[&0x8000], "$8000: JSR sub_8010 {ABS}");
+        assert_eq!(lines[&0x8003], "$8003: BNE reset {REL}");
+        assert_eq!(lines[&0x8005], "$8005: LDA ptr {ZP0}");
+
+        let plain = cpu.disassemble(0x8000, 0x8005);
+        assert_eq!(plain[&0x8003], "$8003: BNE $8000 {REL}");
+    }
+
+    #[test]
+    fn indirect_jumps_and_cross_references() {
+        let cpu = cpu6502::new();
+        // reset: JSR $8010 / loop: BNE loop / JMP ($80FF), whose pointer
+        // takes its high byte from the JSR at $8000 rather than from $8100,
+        // so goes to $2020
+        load(&cpu, 0x8000, &[0x20, 0x10, 0x80, 0xD0, 0xFE, 0x6C, 0xFF, 0x80]);
+        load(&cpu, 0x80FF, &[0x20]);
+        load(&cpu, 0x8100, &[0x90]);
+        // $8010: JMP $8000, $2020: RTS
+        load(&cpu, 0x8010, &[0x4C, 0x00, 0x80]);
+        load(&cpu, 0x2020, &[0x60]);
+        set_vector(&cpu, 0xFFFC, 0x8000);
+
+        let graph = analyze(&cpu, &[]);
+        assert!(graph.functions.contains_key(&0x2020));
+        assert!(graph.functions[&0x8000].indirect);
+
+        let refs = |target: u16| graph.xrefs[&target].iter().map(|xref| (xref.from, xref.kind)).collect::<Vec<_>>();
+        assert_eq!(refs(0x8000), [(0x8010, RefKind::Jump)]);
+        assert_eq!(refs(0x8003), [(0x8003, RefKind::Branch)]);
+        assert_eq!(refs(0x8010), [(0x8000, RefKind::Call)]);
+        assert_eq!(refs(0x2020), [(0x8005, RefKind::Indirect)]);
+        assert_eq!(describe_xrefs(&graph.xrefs[&0x2020]), "xref $8005 indirect");
+        assert!(graph.to_json(&SymbolTable::new()).contains("{\"to\": 8224, \"from\": 32773, \"kind\": \"indirect\"}"));
+    }
+
+    #[test]
+    fn loop_splits_into_blocks() {
+        let cpu = cpu6502::new();
+        // LDX #3 / DEX / BNE -3 / RTS
+        load(&cpu, 0x8000, &[0xA2, 0x03, 0xCA, 0xD0, 0xFD, 0x60]);
+
+        let cfg = control_flow(&cpu, 0x8000);
+
+        assert_eq!(cfg.blocks.keys().copied().collect::<Vec<_>>(), vec![0x8000, 0x8002, 0x8005]);
+        assert_eq!(cfg.blocks[&0x8000].successors, vec![(0x8002, EdgeKind::FallThrough)]);
+        assert_eq!(cfg.blocks[&0x8002].instructions, vec![0x8002, 0x8003]);
+        assert_eq!(cfg.blocks[&0x8002].successors, vec![(0x8002, EdgeKind::Taken), (0x8005, EdgeKind::FallThrough)]);
+        assert!(cfg.blocks[&0x8005].successors.is_empty());
+    }
+
+    #[test]
+    fn jump_into_an_operand_gets_its_own_block() {
+        let cpu = cpu6502::new();
+        // LDA #$EA / BNE -3 lands on the operand byte, which decodes as NOP
+        load(&cpu, 0x8000, &[0xA9, 0xEA, 0xD0, 0xFD, 0x60]);
+
+        let cfg = control_flow(&cpu, 0x8000);
+
+        assert_eq!(cfg.blocks[&0x8000].instructions, vec![0x8000]);
+        assert_eq!(cfg.blocks[&0x8001].instructions, vec![0x8001]);
+        assert_eq!(cfg.blocks[&0x8002].instructions, vec![0x8002]);
+        assert_eq!(cfg.block_at(0x8001).map(|block| block.start), Some(0x8001));
+    }
+
+    #[test]
+    fn calls_and_tail_jumps_end_the_path() {
+        let cpu = cpu6502::new();
+        // JSR $8010 / JMP $8010, where $8010 is a subroutine of its own
+        load(&cpu, 0x8000, &[0x20, 0x10, 0x80, 0x4C, 0x10, 0x80]);
+        load(&cpu, 0x8010, &[0x60]);
+
+        let cfg = control_flow(&cpu, 0x8000);
+
+        assert_eq!(cfg.blocks.len(), 1);
+        assert_eq!(cfg.blocks[&0x8000].instructions, vec![0x8000, 0x8003]);
+        assert!(cfg.blocks[&0x8000].successors.is_empty());
+    }
+
+    #[test]
+    fn dot_lists_blocks_and_edges() {
+        let mut cpu = cpu6502::new();
+        load(&cpu, 0x8000, &[0xA2, 0x03, 0xCA, 0xD0, 0xFD, 0x60]);
+
+        let cfg = control_flow(&cpu, 0x8000);
+        let dot = cfg.to_dot(&cpu.disassemble(0x8000, 0x8005), &SymbolTable::new());
+
+        assert!(dot.starts_with("digraph \"sub_8000\""));
+        assert!(dot.contains("b8002 -> b8002 [color=green];"));
+        assert!(dot.contains("b8002 -> b8005;"));
+    }
+}
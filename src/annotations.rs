@@ -0,0 +1,167 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::analysis::{describe_xrefs, XRefs};
+use crate::symbols::SymbolTable;
+
+// A comment attached to a single address or to an inclusive range of them
+#[derive(Debug, Clone, PartialEq)]
+pub struct Annotation {
+    pub start: u16,
+    pub end: u16,
+    pub text: String,
+}
+
+impl Annotation {
+    pub fn contains(&self, addr: u16) -> bool {
+        self.start <= addr && addr <= self.end
+    }
+}
+
+// Free-text notes about a ROM, kept next to it so they come back the next
+// time the same image is loaded
+pub struct Annotations {
+    notes: BTreeMap<u16, Annotation>,
+}
+
+impl Annotations {
+    pub fn new() -> Self {
+        Annotations { notes: BTreeMap::new() }
+    }
+
+    // Where the notes for a ROM live, "game.nes" keeps them in "game.nes.notes"
+    pub fn path_for_rom<P: AsRef<Path>>(rom: P) -> PathBuf {
+        let mut path = rom.as_ref().as_os_str().to_owned();
+        path.push(".notes");
+        PathBuf::from(path)
+    }
+
+    // Replaces any note that already starts at the same address
+    pub fn add(&mut self, start: u16, end: u16, text: &str) {
+        let (start, end) = (start.min(end), start.max(end));
+        self.notes.insert(start, Annotation { start, end, text: text.to_string() });
+    }
+
+    pub fn remove(&mut self, start: u16) -> bool {
+        self.notes.remove(&start).is_some()
+    }
+
+    // The note starting at this address, the one shown beside an instruction
+    pub fn at(&self, addr: u16) -> Option<&Annotation> {
+        self.notes.get(&addr)
+    }
+
+    // Every note whose range covers this address
+    pub fn covering(&self, addr: u16) -> impl Iterator<Item = &Annotation> {
+        self.notes.range(..=addr).map(|(_, note)| note).filter(move |note| note.contains(addr))
+    }
+
+    pub fn is_annotated(&self, addr: u16) -> bool {
+        self.covering(addr).next().is_some()
+    }
+
+    pub fn len(&self) -> usize {
+        self.notes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.notes.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Annotation> {
+        self.notes.values()
+    }
+
+    pub fn load_file<P: AsRef<Path>>(&mut self, path: P) -> io::Result<usize> {
+        let text = fs::read_to_string(path)?;
+        Ok(self.load_str(&text))
+    }
+
+    // One note per line, "$c000 text" or "$c000-$c0ff text". Lines that
+    // don't parse are skipped, returns how many notes were added.
+    pub fn load_str(&mut self, text: &str) -> usize {
+        let mut count = 0;
+
+        for line in text.lines() {
+            let line = line.trim();
+            let (range, text) = match line.split_once(char::is_whitespace) {
+                Some((range, text)) => (range, text.trim()),
+                None => continue,
+            };
+
+            if let Some((start, end)) = parse_range(range) {
+                self.add(start, end, text);
+                count += 1;
+            }
+        }
+
+        count
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let mut s = String::new();
+
+        for note in self.notes.values() {
+            let line = if note.start == note.end {
+                std::format!("${:04x} {}\n", note.start, note.text)
+            } else {
+                std::format!("${:04x}-${:04x} {}\n", note.start, note.end, note.text)
+            };
+            s.push_str(line.as_str());
+        }
+
+        fs::write(path, s)
+    }
+}
+
+fn parse_addr(s: &str) -> Option<u16> {
+    let s = s.trim();
+    let s = s.strip_prefix('$').or_else(|| s.strip_prefix("0x")).unwrap_or(s);
+
+    u16::from_str_radix(s, 16).ok()
+}
+
+// "$c000" or "$c000-$c0ff"
+pub fn parse_range(s: &str) -> Option<(u16, u16)> {
+    match s.split_once('-') {
+        Some((start, end)) => Some((parse_addr(start)?, parse_addr(end)?)),
+        None => parse_addr(s).map(|addr| (addr, addr)),
+    }
+}
+
+// The disassembly as a listing: labels on their own line, range notes as a
+// comment block ahead of the first instruction they cover, what goes to an
+// instruction as a comment above it and single address notes as trailing
+// comments.
+pub fn annotated_listing(map_lines: &BTreeMap<u16, String>, notes: &Annotations, symbols: &SymbolTable, xrefs: &XRefs) -> String {
+    let mut s = String::new();
+
+    for (addr, line) in map_lines {
+        // Ranges can start on an operand byte, so pick up every note that
+        // begins between the previous instruction and this one
+        let previous = map_lines.range(..*addr).next_back().map(|(a, _)| *a as u32 + 1).unwrap_or(0);
+        for note in notes.notes.range(previous as u16..=*addr).map(|(_, note)| note) {
+            if note.start != note.end {
+                s.push_str(std::format!("; ${:04x}-${:04x} {}\n", note.start, note.end, note.text).as_str());
+            }
+        }
+
+        if let Some(refs) = xrefs.get(addr) {
+            s.push_str(std::format!("; {}\n", describe_xrefs(refs)).as_str());
+        }
+
+        if let Some(name) = symbols.name_of(*addr) {
+            s.push_str(std::format!("{}:\n", name).as_str());
+        }
+
+        s.push_str(line);
+        if let Some(note) = notes.at(*addr).filter(|note| note.start == note.end) {
+            s.push_str(std::format!("    ; {}", note.text).as_str());
+        }
+        s.push('\n');
+    }
+
+    s
+}
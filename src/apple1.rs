@@ -0,0 +1,141 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+use crate::cpu6502;
+use crate::pia::{Pia, PiaPort};
+
+// An Apple I: RAM, a PIA for the keyboard and the display, and the Woz
+// Monitor at $FF00, which is Apple's and so has to be loaded from a file.
+//
+//   $D010  KBD    the last key, bit 7 always set
+//   $D011  KBDCR  bit 7 set while a key is waiting, CA1 is the key strobe
+//   $D012  DSP    a write sends bits 0-6 to the display, bit 7 is busy
+//   $D013  DSPCR
+//
+// The display takes characters as fast as they come, so PB7 is held low
+// and never shows busy. The PIA's IRQ isn't connected, as on the board.
+
+pub const PIA_BASE: u16 = 0xD010;
+pub const MONITOR_BASE: u16 = 0xFF00;
+
+// The host's end of the keyboard and display
+#[derive(Clone)]
+pub struct Apple1Port {
+    pia: PiaPort,
+    // Typed but not handed to the PIA yet
+    keys: Arc<Mutex<VecDeque<u8>>>,
+}
+
+impl Apple1Port {
+    fn keys(&self) -> MutexGuard<'_, VecDeque<u8>> {
+        self.keys.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    // Queues keys the way the Apple I keyboard sends them: upper case, with
+    // a carriage return for a newline
+    pub fn type_text(&self, text: &[u8]) {
+        let mut keys = self.keys();
+        for &c in text {
+            keys.push_back(if c == b'\n' { b'\r' } else { c.to_ascii_uppercase() });
+        }
+        drop(keys);
+        self.poll();
+    }
+
+    // Hands the next key to the PIA once the program has read the last.
+    // Called as the machine runs, a frame at a time is plenty.
+    pub fn poll(&self) {
+        if self.pia.ca1_pending() {
+            return;
+        }
+        if let Some(key) = self.keys().pop_front() {
+            self.pia.set_port_a(key | 0x80);
+            self.pia.set_ca1(false);
+            self.pia.set_ca1(true);
+        }
+    }
+
+    pub fn pending_keys(&self) -> usize {
+        self.keys().len()
+    }
+
+    // What's been printed since the last call, with a newline for each
+    // carriage return. The display has no lower case or control codes
+    // besides CR, so those are dropped.
+    pub fn take_output(&self) -> Vec<u8> {
+        self.pia
+            .take_strobes_b()
+            .into_iter()
+            .map(|c| c & 0x7F)
+            .filter_map(|c| match c {
+                b'\r' => Some(b'\n'),
+                0x20..=0x5F => Some(c),
+                _ => None,
+            })
+            .collect()
+    }
+
+    pub fn pia(&self) -> PiaPort {
+        self.pia.clone()
+    }
+}
+
+impl cpu6502 {
+    // Makes this an Apple I: a PIA at $D010-$D013 with the display ready,
+    // and the reset vector at the monitor. Load the monitor at
+    // MONITOR_BASE before resetting.
+    pub fn attach_apple1(&mut self) -> Apple1Port {
+        let pia = Pia::new();
+        let port = Apple1Port { pia: pia.port(), keys: Arc::default() };
+        port.pia.set_port_b(0x00);
+        self.bus.borrow_mut().map("pia", PIA_BASE, PIA_BASE + 3, Box::new(pia));
+        self.set_reset_vector(MONITOR_BASE);
+        port
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::assemble;
+
+    #[test]
+    fn echoes_keys_like_the_monitor() {
+        // The Woz Monitor's keyboard and display loops, without the rest
+        let source = "
+            KBD = $D010
+            KBDCR = $D011
+            DSP = $D012
+            DSPCR = $D013
+            .org $FF00
+            reset:  LDY #$7F
+                    STY DSP         ; DDRB, PB7 an input
+                    LDA #$A7
+                    STA KBDCR
+                    STA DSPCR
+            next:   LDA KBDCR
+                    BPL next
+                    LDA KBD
+            echo:   BIT DSP
+                    BMI echo
+                    STA DSP
+                    JMP next
+        ";
+        let mut cpu = cpu6502::new();
+        cpu.load_program(&assemble(source).unwrap().bytes, MONITOR_BASE);
+        let port = cpu.attach_apple1();
+        cpu.reset();
+
+        port.type_text(b"hello\n");
+        for _ in 0..20 {
+            for _ in 0..200 {
+                cpu.clock();
+            }
+            port.poll();
+        }
+
+        assert_eq!(cpu.pc & 0xFF00, MONITOR_BASE);
+        assert_eq!(port.pending_keys(), 0);
+        assert_eq!(port.take_output(), b"HELLO\n");
+    }
+}
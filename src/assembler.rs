@@ -0,0 +1,1039 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use crate::{cpu6502, is_documented};
+
+// A small two pass assembler for the documented NMOS instructions, enough
+// to build the demo programs at startup and to write test programs as
+// source rather than hex.
+//
+//   ; comments run to the end of the line
+//   COUNT = 10            constants, "COUNT .equ 10" works too
+//   .org $8000            where the following code goes, $8000 if not given
+//   loop:  LDA table,X    labels end with a colon
+//          .byte 1, $02, 'c', "text"
+//          .asciiz "text"  .ascii leaves off the 0
+//          .word loop
+//   .if COUNT > 8         conditional assembly, with .else and .endif,
+//   .endif                on anything already defined
+//   .include "lcd.s"      relative to the file including it
+//   .macro store value, addr
+//          LDA #value     parameters are replaced wherever they're a
+//          STA addr       whole word, and \@ by a number that's different
+//   .endmacro             each time so labels inside can be reused
+//          store 1, $0200
+//
+// Numbers are decimal unless they start with $ (hex) or % (binary) and *
+// is the current address. Expressions have + - * / % & | ^ << >>, the
+// comparisons, ~ and parentheses, with the usual precedence, and < and >
+// at the front take the low and high byte of everything after them.
+// Operands that fit in a byte use zero page forms where there are any,
+// unless they refer to a label defined later, which is assumed to be a
+// full address.
+
+const DEFAULT_ORIGIN: u16 = 0x8000;
+
+const BRANCHES: [&str; 8] = ["BCC", "BCS", "BEQ", "BMI", "BNE", "BPL", "BVC", "BVS"];
+
+pub struct Assembly {
+    pub origin: u16,
+    pub bytes: Vec<u8>,
+    // Every label with its address, in the order they were defined
+    pub labels: Vec<(String, u16)>,
+}
+
+impl Assembly {
+    pub fn label(&self, name: &str) -> Option<u16> {
+        self.labels.iter().find(|(label, _)| label == name).map(|(_, addr)| *addr)
+    }
+}
+
+// Includes are found relative to the current directory
+pub fn assemble(source: &str) -> Result<Assembly, String> {
+    assemble_lines(expand(source, None)?)
+}
+
+pub fn assemble_file<P: AsRef<Path>>(path: P) -> Result<Assembly, String> {
+    let path = path.as_ref();
+    let source = fs::read_to_string(path).map_err(|e| std::format!("can't read {}: {}", path.display(), e))?;
+    assemble_lines(expand(&source, Some(path))?)
+}
+
+fn assemble_lines(lines: Vec<SourceLine>) -> Result<Assembly, String> {
+    let cpu = cpu6502::new();
+    let mut pass = Pass::new(&cpu);
+
+    for last in [false, true] {
+        pass.start(last);
+
+        // Whether each enclosing .if is taking its lines
+        let mut conditions: Vec<bool> = Vec::new();
+
+        for line in &lines {
+            pass.line(&line.text, &mut conditions).map_err(|e| std::format!("{}: {}", line.at, e))?;
+        }
+
+        if !conditions.is_empty() {
+            return Err(".if without .endif".to_string());
+        }
+    }
+
+    Ok(Assembly { origin: pass.origin.unwrap_or(DEFAULT_ORIGIN), bytes: pass.bytes, labels: pass.labels })
+}
+
+// How far includes and macros can go inside each other, past this it's
+// taken to be one including or calling itself
+const MAX_NESTING: usize = 16;
+
+// A line once includes and macros are expanded, with where it came from
+// for errors: "line 3", "lcd.s line 3" or "line 9 in print"
+struct SourceLine {
+    at: String,
+    text: String,
+}
+
+struct Macro {
+    params: Vec<String>,
+    body: Vec<String>,
+}
+
+// Includes and macros are dealt with before either pass, so the passes
+// see the same lines. The lines go through a first pass as they come out,
+// which decides each .if the way the real first pass will, so includes,
+// macro definitions and macro calls in a branch not taken are left alone.
+struct Expander<'a> {
+    macros: HashMap<String, Macro>,
+    // Counts expansions, for \@
+    expansions: usize,
+    lines: Vec<SourceLine>,
+    pass: Pass<'a>,
+    conditions: Vec<bool>,
+}
+
+fn expand(source: &str, path: Option<&Path>) -> Result<Vec<SourceLine>, String> {
+    let cpu = cpu6502::new();
+    let mut expander = Expander { macros: HashMap::new(), expansions: 0, lines: Vec::new(), pass: Pass::new(&cpu), conditions: Vec::new() };
+    expander.pass.start(false);
+    let dir = path.and_then(Path::parent).unwrap_or(Path::new(""));
+    expander.file(source, None, dir, 0)?;
+    Ok(expander.lines)
+}
+
+impl Expander<'_> {
+    fn push(&mut self, at: String, text: &str) -> Result<(), String> {
+        self.pass.line(text, &mut self.conditions).map_err(|e| std::format!("{}: {}", at, e))?;
+        self.lines.push(SourceLine { at, text: text.to_string() });
+        Ok(())
+    }
+
+    fn file(&mut self, source: &str, name: Option<&str>, dir: &Path, depth: usize) -> Result<(), String> {
+        let lines = source
+            .lines()
+            .enumerate()
+            .map(|(number, text)| match name {
+                Some(name) => (std::format!("{} line {}", name, number + 1), text.to_string()),
+                None => (std::format!("line {}", number + 1), text.to_string()),
+            })
+            .collect();
+        self.lines(lines, dir, depth)
+    }
+
+    fn lines(&mut self, lines: Vec<(String, String)>, dir: &Path, depth: usize) -> Result<(), String> {
+        // The macro being defined, and where it started
+        let mut defining: Option<(String, Macro, String)> = None;
+
+        for (at, text) in lines {
+            let text = strip_comment(&text).trim();
+            let (first, rest) = split_word(text);
+
+            if defining.is_some() {
+                if matches!(first.to_ascii_lowercase().as_str(), ".endmacro" | ".endm") {
+                    if let Some((name, definition, _)) = defining.take() {
+                        self.macros.insert(name, definition);
+                    }
+                } else if let Some((_, definition, _)) = &mut defining {
+                    definition.body.push(text.to_string());
+                }
+                continue;
+            }
+
+            // Anything can have a label in front, which stays where it is
+            let (label, line) = match first.strip_suffix(':') {
+                Some(_) => (Some(first), rest),
+                None => (None, text),
+            };
+            let (word, operand) = split_word(line);
+            let word = word.to_ascii_lowercase();
+
+            // The passes skip the whole branch, .macro to .endmacro included
+            if !self.conditions.iter().all(|c| *c) {
+                self.push(at, text)?;
+                continue;
+            }
+
+            let error = |e: String| std::format!("{}: {}", at, e);
+            let nested = if depth == MAX_NESTING { Err(error("includes or macros nested too deep".to_string())) } else { Ok(()) };
+
+            if word == ".macro" || word == ".include" || self.macros.contains_key(&word) {
+                if let Some(label) = label {
+                    self.push(at.clone(), label)?;
+                }
+            }
+
+            match word.as_str() {
+                ".macro" => {
+                    let (name, params) = split_word(operand);
+                    if name.is_empty() {
+                        return Err(error(".macro needs a name".to_string()));
+                    }
+                    let params = split_items(params).into_iter().filter(|param| !param.is_empty()).map(str::to_string).collect();
+                    defining = Some((name.to_ascii_lowercase(), Macro { params, body: Vec::new() }, at.clone()));
+                }
+                ".endmacro" | ".endm" => return Err(error(std::format!("{} without .macro", word))),
+                ".include" => {
+                    nested?;
+                    let name = operand.strip_prefix('"').and_then(|name| name.strip_suffix('"')).ok_or_else(|| error(".include takes a file name in quotes".to_string()))?;
+                    let path = dir.join(name);
+                    let source = fs::read_to_string(&path).map_err(|e| error(std::format!("can't read {}: {}", path.display(), e)))?;
+                    self.file(&source, Some(name), path.parent().unwrap_or(dir), depth + 1)?;
+                }
+                name if self.macros.contains_key(name) => {
+                    nested?;
+                    let body = self.call(name, operand).map_err(error)?;
+                    let lines = body.into_iter().map(|text| (std::format!("{} in {}", at, name), text)).collect();
+                    self.lines(lines, dir, depth + 1)?;
+                }
+                _ => self.push(at, text)?,
+            }
+        }
+
+        match defining {
+            Some((name, _, at)) => Err(std::format!("{}: .macro {} without .endmacro", at, name)),
+            None => Ok(()),
+        }
+    }
+
+    // The macro's lines with the arguments put in
+    fn call(&mut self, name: &str, operand: &str) -> Result<Vec<String>, String> {
+        self.expansions += 1;
+        let unique = self.expansions.to_string();
+        let definition = &self.macros[name];
+
+        let args = if operand.is_empty() { Vec::new() } else { split_items(operand) };
+        if args.len() != definition.params.len() {
+            return Err(std::format!("{} takes {} arguments, not {}", name, definition.params.len(), args.len()));
+        }
+
+        let substitute = |line: &String| {
+            let line = line.replace("\\@", &unique);
+            let mut out = String::new();
+            let mut rest = line.as_str();
+            while let Some(start) = rest.find(is_word_char) {
+                let len = rest[start..].find(|c: char| !is_word_char(c)).unwrap_or(rest.len() - start);
+                let word = &rest[start..start + len];
+                out.push_str(&rest[..start]);
+                out.push_str(definition.params.iter().position(|param| param == word).map_or(word, |i| args[i]));
+                rest = &rest[start + len..];
+            }
+            out.push_str(rest);
+            out
+        };
+        Ok(definition.body.iter().map(substitute).collect())
+    }
+}
+
+enum Operand<'a> {
+    Implied,
+    Accumulator,
+    Immediate(&'a str),
+    // Absolute or zero page, plain or indexed by X or Y
+    Direct(&'a str, Option<char>),
+    Indirect(&'a str),
+    IndirectX(&'a str),
+    IndirectY(&'a str),
+}
+
+struct Pass<'a> {
+    cpu: &'a cpu6502,
+    symbols: HashMap<String, i64>,
+    labels: Vec<(String, u16)>,
+    // Whether each instruction with a plain address used zero page, decided
+    // on the first pass so the second lays the code out the same way
+    zero_page: Vec<bool>,
+    last: bool,
+    pc: u16,
+    origin: Option<u16>,
+    bytes: Vec<u8>,
+    instructions: usize,
+}
+
+impl<'a> Pass<'a> {
+    fn new(cpu: &'a cpu6502) -> Self {
+        Pass { cpu, symbols: HashMap::new(), labels: Vec::new(), zero_page: Vec::new(), last: false, pc: 0, origin: None, bytes: Vec::new(), instructions: 0 }
+    }
+
+    // Back to the top of the source, keeping the symbols and zero page
+    // choices the passes before found
+    fn start(&mut self, last: bool) {
+        self.last = last;
+        self.pc = DEFAULT_ORIGIN;
+        self.origin = None;
+        self.bytes.clear();
+        self.labels.clear();
+        self.instructions = 0;
+    }
+
+    fn line(&mut self, line: &str, conditions: &mut Vec<bool>) -> Result<(), String> {
+        let line = strip_comment(line).trim();
+        let (directive, rest) = split_word(line);
+        let taking = conditions.iter().all(|c| *c);
+
+        match directive.to_ascii_lowercase().as_str() {
+            ".if" => {
+                let value = if taking { self.eval(rest)?.ok_or("a .if has to be decided on the first pass")? } else { 0 };
+                conditions.push(value != 0);
+                return Ok(());
+            }
+            ".else" => {
+                let last = conditions.last_mut().ok_or(".else without .if")?;
+                *last = !*last;
+                return Ok(());
+            }
+            ".endif" => {
+                conditions.pop().ok_or(".endif without .if")?;
+                return Ok(());
+            }
+            _ if !taking => return Ok(()),
+            _ => {}
+        }
+
+        let mut line = line;
+        if let Some(name) = directive.strip_suffix(':') {
+            self.define(name, self.pc as i64)?;
+            self.labels.push((name.to_string(), self.pc));
+            line = rest;
+        }
+
+        if line.is_empty() {
+            return Ok(());
+        }
+
+        // NAME = value, NAME .equ value or NAME equ value
+        let (word, operand) = split_word(line);
+        let assignment = match split_word(operand) {
+            (equ, value) if matches!(equ.to_ascii_lowercase().as_str(), ".equ" | "equ") => Some((word, value)),
+            _ => line.split_once('=').map(|(name, value)| (name.trim(), value)).filter(|(name, value)| is_name(name) && !value.starts_with('=')),
+        };
+
+        if let Some((name, value)) = assignment {
+            if let Some(value) = self.eval(value)? {
+                self.define(name, value)?;
+            } else if self.last {
+                return Err(std::format!("{} is defined in terms of itself", name));
+            }
+            return Ok(());
+        }
+
+        match word.to_ascii_lowercase().as_str() {
+            ".org" => {
+                let value = self.eval(operand)?.ok_or(".org has to be known on the first pass")?;
+                self.pc = to_u16(value)?;
+                Ok(())
+            }
+            ".byte" => {
+                for item in split_items(operand) {
+                    if item.starts_with('"') {
+                        self.emit(&parse_string(item)?)?;
+                    } else {
+                        let value = self.eval_final(item)?;
+                        self.emit(&[to_u8(value)?])?;
+                    }
+                }
+                Ok(())
+            }
+            ".ascii" => self.emit(&parse_string(operand)?),
+            ".asciiz" => {
+                self.emit(&parse_string(operand)?)?;
+                self.emit(&[0])
+            }
+            ".word" => {
+                for item in split_items(operand) {
+                    let value = to_u16(self.eval_final(item)?)?;
+                    self.emit(&value.to_le_bytes())?;
+                }
+                Ok(())
+            }
+            _ if word.starts_with('.') => Err(std::format!("unknown directive {}", word)),
+            _ => self.instruction(&word.to_ascii_uppercase(), parse_operand(operand)?),
+        }
+    }
+
+    fn define(&mut self, name: &str, value: i64) -> Result<(), String> {
+        if !is_name(name) {
+            return Err(std::format!("'{}' isn't a valid name", name));
+        }
+
+        match self.symbols.insert(name.to_string(), value) {
+            Some(previous) if previous != value && !self.last => Err(std::format!("{} is already defined", name)),
+            _ => Ok(()),
+        }
+    }
+
+    fn instruction(&mut self, mnemonic: &str, operand: Operand) -> Result<(), String> {
+        if BRANCHES.contains(&mnemonic) {
+            let Operand::Direct(target, None) = operand else {
+                return Err(std::format!("{} takes a label or an address", mnemonic));
+            };
+            let offset = match self.eval(target)? {
+                Some(target) => target - (self.pc as i64 + 2),
+                None if self.last => return Err(std::format!("{} isn't defined", target)),
+                None => 0,
+            };
+            if self.last && !(-128..=127).contains(&offset) {
+                return Err(std::format!("branch to {} is {} bytes away, out of range", target, offset));
+            }
+            return self.encode(mnemonic, "REL", &[offset as u8]);
+        }
+
+        match operand {
+            Operand::Implied => self.encode(mnemonic, "IMP", &[]),
+            Operand::Accumulator => self.encode(mnemonic, "IMP", &[]),
+            Operand::Immediate(expr) => {
+                let value = self.eval_final(expr)?;
+                self.encode(mnemonic, "IMM", &[to_u8(value)?])
+            }
+            Operand::IndirectX(expr) => {
+                let value = self.eval_final(expr)?;
+                self.encode(mnemonic, "IZX", &[to_u8(value)?])
+            }
+            Operand::IndirectY(expr) => {
+                let value = self.eval_final(expr)?;
+                self.encode(mnemonic, "IZY", &[to_u8(value)?])
+            }
+            Operand::Indirect(expr) => {
+                let value = to_u16(self.eval_final(expr)?)?;
+                self.encode(mnemonic, "IND", &value.to_le_bytes())
+            }
+            Operand::Direct(expr, index) => {
+                let (zp, abs) = match index {
+                    None => ("ZP0", "ABS"),
+                    Some('X') => ("ZPX", "ABX"),
+                    _ => ("ZPY", "ABY"),
+                };
+
+                let value = self.eval(expr)?;
+                let instruction = self.instructions;
+                self.instructions += 1;
+
+                if !self.last {
+                    let fits = value.is_some_and(|value| (0..=0xFF).contains(&value));
+                    self.zero_page.push(fits && self.opcode(mnemonic, zp).is_some());
+                }
+
+                let value = match value {
+                    Some(value) => value,
+                    None if self.last => return Err(std::format!("{} isn't defined", expr)),
+                    None => 0,
+                };
+
+                if self.zero_page[instruction] {
+                    self.encode(mnemonic, zp, &[to_u8(value)?])
+                } else {
+                    self.encode(mnemonic, abs, &to_u16(value)?.to_le_bytes())
+                }
+            }
+        }
+    }
+
+    fn opcode(&self, mnemonic: &str, mode: &str) -> Option<u8> {
+        (0..=0xFF).find(|&opcode| is_documented(opcode) && self.cpu.lookup[opcode as usize].name == mnemonic && self.cpu.addr_mode_name(opcode) == mode)
+    }
+
+    fn encode(&mut self, mnemonic: &str, mode: &str, operand: &[u8]) -> Result<(), String> {
+        if !self.cpu.lookup.iter().any(|instruction| instruction.name == mnemonic) || mnemonic == "???" {
+            return Err(std::format!("unknown instruction {}", mnemonic));
+        }
+
+        let opcode = self.opcode(mnemonic, mode).ok_or_else(|| std::format!("{} has no {} form", mnemonic, mode))?;
+        self.emit(&[opcode])?;
+        self.emit(operand)
+    }
+
+    fn emit(&mut self, bytes: &[u8]) -> Result<(), String> {
+        let origin = *self.origin.get_or_insert(self.pc);
+        let offset = self.pc.checked_sub(origin).ok_or("code below the first .org")? as usize;
+
+        if offset < self.bytes.len() {
+            return Err(std::format!("code at ${:04x} overlaps what's already there", self.pc));
+        }
+
+        self.bytes.resize(offset, 0);
+        self.bytes.extend_from_slice(bytes);
+        self.pc = self.pc.wrapping_add(bytes.len() as u16);
+        Ok(())
+    }
+
+    // The value of `expr`, or None while it refers to something not yet
+    // defined
+    fn eval(&self, expr: &str) -> Result<Option<i64>, String> {
+        let expr = expr.trim();
+        if let Some(rest) = expr.strip_prefix('<') {
+            return Ok(self.eval(rest)?.map(|value| value & 0xFF));
+        }
+        if let Some(rest) = expr.strip_prefix('>') {
+            return Ok(self.eval(rest)?.map(|value| (value >> 8) & 0xFF));
+        }
+
+        let tokens = tokenize(expr)?;
+        if tokens.is_empty() {
+            return Err("missing a value".to_string());
+        }
+
+        let mut at = 0;
+        let value = self.binary(&tokens, &mut at, 1)?;
+        match tokens.get(at) {
+            Some(_) => Err(std::format!("can't make sense of '{}'", expr)),
+            None => Ok(value),
+        }
+    }
+
+    // Operators binding at least as tightly as `min`, by precedence climbing
+    fn binary(&self, tokens: &[Token], at: &mut usize, min: u8) -> Result<Option<i64>, String> {
+        let mut left = self.unary(tokens, at)?;
+        while let Some(&Token::Op(op)) = tokens.get(*at) {
+            let precedence = precedence(op);
+            if precedence == 0 || precedence < min {
+                break;
+            }
+            *at += 1;
+            let right = self.binary(tokens, at, precedence + 1)?;
+            left = match (left, right) {
+                (Some(left), Some(right)) => Some(apply(op, left, right)?),
+                _ => None,
+            };
+        }
+        Ok(left)
+    }
+
+    fn unary(&self, tokens: &[Token], at: &mut usize) -> Result<Option<i64>, String> {
+        let token = *tokens.get(*at).ok_or("expression ends too soon")?;
+        *at += 1;
+        match token {
+            Token::Value(term) => self.term(term),
+            Token::Open => {
+                let value = self.binary(tokens, at, 1)?;
+                if tokens.get(*at) != Some(&Token::Close) {
+                    return Err("missing )".to_string());
+                }
+                *at += 1;
+                Ok(value)
+            }
+            Token::Close => Err("unexpected )".to_string()),
+            Token::Op(op) => {
+                let value = self.unary(tokens, at)?;
+                Ok(match op {
+                    "-" => value.map(i64::wrapping_neg),
+                    "+" => value,
+                    "~" => value.map(|value| !value),
+                    "<" => value.map(|value| value & 0xFF),
+                    ">" => value.map(|value| (value >> 8) & 0xFF),
+                    _ => return Err(std::format!("{} needs something in front of it", op)),
+                })
+            }
+        }
+    }
+
+    fn eval_final(&self, expr: &str) -> Result<i64, String> {
+        match self.eval(expr)? {
+            Some(value) => Ok(value),
+            None if self.last => Err(std::format!("{} isn't defined", expr)),
+            None => Ok(0),
+        }
+    }
+
+    fn term(&self, term: &str) -> Result<Option<i64>, String> {
+        let parsed = if let Some(hex) = term.strip_prefix('$') {
+            i64::from_str_radix(hex, 16).ok()
+        } else if let Some(binary) = term.strip_prefix('%') {
+            i64::from_str_radix(binary, 2).ok()
+        } else if term == "*" {
+            Some(self.pc as i64)
+        } else if term.len() == 3 && term.starts_with('\'') && term.ends_with('\'') {
+            Some(term.as_bytes()[1] as i64)
+        } else if term.starts_with(|c: char| c.is_ascii_digit()) {
+            term.parse().ok()
+        } else if is_name(term) {
+            return Ok(self.symbols.get(term).copied());
+        } else {
+            None
+        };
+
+        parsed.map(Some).ok_or_else(|| std::format!("can't make sense of '{}'", term))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Token<'a> {
+    Value(&'a str),
+    Op(&'static str),
+    Open,
+    Close,
+}
+
+// Longest first, so << isn't taken for <
+const OPERATORS: [&str; 17] = ["<<", ">>", "==", "!=", "<=", ">=", "+", "-", "*", "/", "%", "&", "|", "^", "~", "<", ">"];
+
+fn tokenize(expr: &str) -> Result<Vec<Token<'_>>, String> {
+    let mut tokens = Vec::new();
+    let mut rest = expr.trim_start();
+
+    while let Some(c) = rest.chars().next() {
+        // Where a value is expected * is the address and % starts a binary
+        // number, otherwise they're multiply and modulo
+        let value_next = !matches!(tokens.last(), Some(Token::Value(_)) | Some(Token::Close));
+        let operator = OPERATORS.iter().find(|op| rest.starts_with(**op)).filter(|_| !(value_next && (c == '*' || c == '%')));
+
+        let len = if c == '(' {
+            tokens.push(Token::Open);
+            1
+        } else if c == ')' {
+            tokens.push(Token::Close);
+            1
+        } else if let Some(op) = operator {
+            tokens.push(Token::Op(op));
+            op.len()
+        } else if c == '\'' {
+            let len = rest[1..].find('\'').map(|end| end + 2).ok_or_else(|| std::format!("unterminated character in '{}'", expr))?;
+            tokens.push(Token::Value(&rest[..len]));
+            len
+        } else {
+            let len = rest[c.len_utf8()..].find(|c: char| !is_word_char(c)).map_or(rest.len(), |end| end + c.len_utf8());
+            tokens.push(Token::Value(&rest[..len]));
+            len
+        };
+        rest = rest[len..].trim_start();
+    }
+
+    Ok(tokens)
+}
+
+// 0 for the operators that only go in front of a value
+fn precedence(op: &str) -> u8 {
+    match op {
+        "==" | "!=" | "<" | ">" | "<=" | ">=" => 1,
+        "|" => 2,
+        "^" => 3,
+        "&" => 4,
+        "<<" | ">>" => 5,
+        "+" | "-" => 6,
+        "*" | "/" | "%" => 7,
+        _ => 0,
+    }
+}
+
+fn apply(op: &str, left: i64, right: i64) -> Result<i64, String> {
+    Ok(match op {
+        "==" => (left == right) as i64,
+        "!=" => (left != right) as i64,
+        "<" => (left < right) as i64,
+        ">" => (left > right) as i64,
+        "<=" => (left <= right) as i64,
+        ">=" => (left >= right) as i64,
+        "|" => left | right,
+        "^" => left ^ right,
+        "&" => left & right,
+        "<<" | ">>" if !(0..64).contains(&right) => return Err(std::format!("can't shift by {}", right)),
+        "<<" => left << right,
+        ">>" => left >> right,
+        "+" => left.wrapping_add(right),
+        "-" => left.wrapping_sub(right),
+        "*" => left.wrapping_mul(right),
+        "/" | "%" if right == 0 => return Err("division by zero".to_string()),
+        "/" => left.wrapping_div(right),
+        _ => left.wrapping_rem(right),
+    })
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
+
+fn is_name(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(is_word_char) && !name.starts_with(|c: char| c.is_ascii_digit())
+}
+
+// Everything up to a ';' that isn't in a string or a character
+fn strip_comment(line: &str) -> &str {
+    let mut quote = None;
+    let mut escaped = false;
+    for (i, c) in line.char_indices() {
+        match quote {
+            _ if escaped => escaped = false,
+            Some('"') if c == '\\' => escaped = true,
+            Some(q) if c == q => quote = None,
+            Some(_) => {}
+            // 'c' is only a character if it closes two along
+            None if c == '\'' && line[i + 1..].chars().nth(1) == Some('\'') => quote = Some('\''),
+            None if c == '"' => quote = Some('"'),
+            None if c == ';' => return &line[..i],
+            None => {}
+        }
+    }
+    line
+}
+
+// Splits on commas that aren't in strings, characters or parentheses
+fn split_items(list: &str) -> Vec<&str> {
+    let mut items = Vec::new();
+    let mut depth = 0;
+    let mut quote = None;
+    let mut escaped = false;
+    let mut start = 0;
+    for (i, c) in list.char_indices() {
+        match quote {
+            _ if escaped => escaped = false,
+            Some('"') if c == '\\' => escaped = true,
+            Some(q) if c == q => quote = None,
+            Some(_) => {}
+            None if c == '"' || c == '\'' => quote = Some(c),
+            None if c == '(' => depth += 1,
+            None if c == ')' => depth -= 1,
+            None if c == ',' && depth == 0 => {
+                items.push(list[start..i].trim());
+                start = i + 1;
+            }
+            None => {}
+        }
+    }
+    items.push(list[start..].trim());
+    items
+}
+
+// The bytes of "text", with \n \r \t \0 \\ and \" escapes
+fn parse_string(string: &str) -> Result<Vec<u8>, String> {
+    let inner = string
+        .trim()
+        .strip_prefix('"')
+        .and_then(|inner| inner.strip_suffix('"'))
+        .ok_or_else(|| std::format!("'{}' isn't a string in quotes", string.trim()))?;
+
+    let mut bytes = Vec::new();
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        let c = match c {
+            '\\' => match chars.next() {
+                Some('n') => '\n',
+                Some('r') => '\r',
+                Some('t') => '\t',
+                Some('0') => '\0',
+                Some(c @ ('\\' | '"')) => c,
+                other => return Err(std::format!("unknown escape \\{}", other.map(String::from).unwrap_or_default())),
+            },
+            c => c,
+        };
+        if !c.is_ascii() {
+            return Err(std::format!("'{}' isn't ASCII", c));
+        }
+        bytes.push(c as u8);
+    }
+    Ok(bytes)
+}
+
+// The first word and the rest, trimmed
+fn split_word(line: &str) -> (&str, &str) {
+    match line.split_once(char::is_whitespace) {
+        Some((word, rest)) => (word, rest.trim()),
+        None => (line, ""),
+    }
+}
+
+fn parse_operand(operand: &str) -> Result<Operand<'_>, String> {
+    let operand = operand.trim();
+    let upper = operand.to_ascii_uppercase();
+
+    if operand.is_empty() {
+        return Ok(Operand::Implied);
+    }
+    if upper == "A" {
+        return Ok(Operand::Accumulator);
+    }
+    if let Some(value) = operand.strip_prefix('#') {
+        return Ok(Operand::Immediate(value.trim()));
+    }
+
+    if operand.starts_with('(') {
+        if let Some(inner) = upper.strip_suffix(",X)").map(|inner| &operand[1..inner.len()]) {
+            return Ok(Operand::IndirectX(inner.trim()));
+        }
+        if let Some(inner) = upper.strip_suffix("),Y").map(|inner| &operand[1..inner.len()]) {
+            return Ok(Operand::IndirectY(inner.trim()));
+        }
+        if let Some(inner) = operand.strip_suffix(')') {
+            return Ok(Operand::Indirect(inner[1..].trim()));
+        }
+        return Err(std::format!("can't make sense of '{}'", operand));
+    }
+
+    match upper.rsplit_once(',') {
+        Some((address, index)) if index.trim() == "X" || index.trim() == "Y" => {
+            Ok(Operand::Direct(operand[..address.len()].trim(), index.trim().chars().next()))
+        }
+        Some(_) => Err(std::format!("can't make sense of '{}'", operand)),
+        None => Ok(Operand::Direct(operand, None)),
+    }
+}
+
+fn to_u8(value: i64) -> Result<u8, String> {
+    // Negative bytes are fine, -1 is $ff
+    u8::try_from(value).or_else(|_| i8::try_from(value).map(|value| value as u8)).map_err(|_| std::format!("{} doesn't fit in a byte", value))
+}
+
+fn to_u16(value: i64) -> Result<u16, String> {
+    u16::try_from(value).map_err(|_| std::format!("{} doesn't fit in 16 bits", value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encodes_every_addressing_mode() {
+        let source = "
+            .org $0600
+            start:  LDA #$01        ; immediate
+                    LDA $10         ; zero page
+                    LDA $10,X
+                    LDX $10,Y
+                    LDA $1234
+                    LDA $1234,X
+                    STA $0200,Y
+                    STA $10,Y       ; no zero page,Y form, so absolute
+                    LDA ($20,X)
+                    LDA ($20),Y
+                    JMP ($FFFC)
+                    ASL A
+                    NOP
+                    BNE start
+        ";
+
+        let assembly = assemble(source).unwrap();
+        assert_eq!(assembly.origin, 0x0600);
+        assert_eq!(assembly.bytes, vec![
+            0xA9, 0x01, 0xA5, 0x10, 0xB5, 0x10, 0xB6, 0x10, 0xAD, 0x34, 0x12, 0xBD, 0x34, 0x12, 0x99, 0x00, 0x02,
+            0x99, 0x10, 0x00, 0xA1, 0x20, 0xB1, 0x20, 0x6C, 0xFC, 0xFF, 0x0A, 0xEA, 0xD0, 0xE1,
+        ]);
+        assert_eq!(assembly.label("start"), Some(0x0600));
+    }
+
+    #[test]
+    fn labels_constants_and_data() {
+        let source = "
+            COUNT = 3
+            OUT = $0200
+                    LDX #COUNT-1
+            loop:   LDA table,X
+                    STA OUT,X
+                    DEX
+                    BPL loop
+                    JMP done
+            table:  .byte 'a', %101, -1
+                    .word table, >OUT, <table
+            done:   RTS
+        ";
+
+        let assembly = assemble(source).unwrap();
+        let table = assembly.label("table").unwrap();
+        assert_eq!(table, 0x800E);
+        // A forward reference is taken to be absolute
+        assert_eq!(&assembly.bytes[2..5], &[0xBD, 0x0E, 0x80]);
+        assert_eq!(&assembly.bytes[0x0E..0x17], &[b'a', 5, 0xFF, 0x0E, 0x80, 0x02, 0x00, 0x0E, 0x00]);
+        assert_eq!(assembly.label("done"), Some(0x8017));
+    }
+
+    #[test]
+    fn conditional_assembly() {
+        let source = "
+            FAST = 1
+            .if FAST
+                LDA #1
+                .if FAST > 1
+                    LDA #2
+                .endif
+            .else
+                LDA #3
+            .endif
+        ";
+
+        assert_eq!(assemble(source).unwrap().bytes, vec![0xA9, 0x01]);
+        assert_eq!(assemble(&source.replace("FAST = 1", "FAST = 0")).unwrap().bytes, vec![0xA9, 0x03]);
+
+        // Includes and macros in a branch not taken are left alone too
+        assert_eq!(assemble(".if 0\n.include \"missing.s\"\n.endif\nNOP").unwrap().bytes, vec![0xEA]);
+        let source = "
+            .if 0
+            .macro pause
+                NOP
+            .endmacro
+            .endif
+            pause
+        ";
+        assert!(assemble(source).is_err());
+        assert_eq!(assemble(&source.replace(".if 0", ".if 1")).unwrap().bytes, vec![0xEA]);
+    }
+
+    #[test]
+    fn expressions() {
+        let source = "
+            E = %10000000
+            RS = %00100000
+            BASE .equ $0300
+            SIZE equ 4
+            .byte 2+3*4, (2+3)*4, 1<<4|1, $ff & ~$0f, 7 % 3, %101*2, 100/7, RS|E
+            .word *+2*2, BASE+SIZE*$10, <(BASE+$1ff), -(1+2) & $ffff
+            .byte RS==$20, ',', ';', 'a'+1 ; a real comment
+        ";
+
+        assert_eq!(
+            assemble(source).unwrap().bytes,
+            vec![14, 20, 17, 0xF0, 1, 10, 14, 0xA0, 0x0C, 0x80, 0x40, 0x03, 0xFF, 0x00, 0xFD, 0xFF, 1, b',', b';', b'b']
+        );
+        assert_eq!(assemble(".byte 1/0").err().unwrap(), "line 1: division by zero");
+        assert_eq!(assemble(".byte (1+2").err().unwrap(), "line 1: missing )");
+    }
+
+    #[test]
+    fn strings() {
+        let source = r#"
+            .ascii "Hi; there"
+            .asciiz "a,\"b\"\n"
+            .byte "ok", 0
+        "#;
+
+        assert_eq!(assemble(source).unwrap().bytes, b"Hi; therea,\"b\"\n\0ok\0".to_vec());
+    }
+
+    #[test]
+    fn macros() {
+        let source = r"
+            .macro store value, addr
+                    LDA #value
+                    STA addr
+            .endmacro
+            .macro wait count
+                    LDX #count
+            loop\@: DEX
+                    BNE loop\@
+            .endm
+            .macro twice
+                    wait 1
+                    wait 2
+            .endmacro
+            start:  store $41, $0200
+                    twice
+        ";
+
+        let assembly = assemble(source).unwrap();
+        assert_eq!(
+            assembly.bytes,
+            vec![0xA9, 0x41, 0x8D, 0x00, 0x02, 0xA2, 0x01, 0xCA, 0xD0, 0xFD, 0xA2, 0x02, 0xCA, 0xD0, 0xFD]
+        );
+        assert_eq!(assembly.label("start"), Some(0x8000));
+        assert_eq!(assembly.label("loop3"), Some(0x8007));
+        assert_eq!(assembly.label("loop4"), Some(0x800C));
+
+        assert_eq!(assemble(".macro m a\nNOP\n.endm\nm").err().unwrap(), "line 4: m takes 1 arguments, not 0");
+        assert_eq!(assemble("NOP\n.macro m\nNOP").err().unwrap(), "line 2: .macro m without .endmacro");
+        assert!(assemble(".macro m\nm\n.endm\nm").err().unwrap().contains("nested too deep"));
+        assert_eq!(assemble(".macro m\nLDA #$100\n.endm\nNOP\nm").err().unwrap(), "line 5 in m: 256 doesn't fit in a byte");
+    }
+
+    #[test]
+    fn includes() {
+        let dir = std::env::temp_dir().join(std::format!("crust-asm-{}", std::process::id()));
+        fs::create_dir_all(dir.join("lib")).unwrap();
+        fs::write(dir.join("main.s"), ".include \"lib/lcd.s\"\n    LDA #LCD_ON\n    print\n").unwrap();
+        fs::write(dir.join("lib/lcd.s"), ".include \"defs.s\"\n.macro print\n    STA PORTB\n.endmacro\n").unwrap();
+        fs::write(dir.join("lib/defs.s"), "PORTB = $6000\nLCD_ON = %00001110\n").unwrap();
+
+        let assembly = assemble_file(dir.join("main.s"));
+        fs::write(dir.join("lib/defs.s"), "PORTB = $6000\nLCD_ON = $100\n").unwrap();
+        let error = assemble_file(dir.join("main.s"));
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(assembly.unwrap().bytes, vec![0xA9, 0x0E, 0x8D, 0x00, 0x60]);
+        assert_eq!(error.err().unwrap(), "line 2: 256 doesn't fit in a byte");
+        assert!(assemble(".include \"no-such-file.s\"").err().unwrap().starts_with("line 1: can't read no-such-file.s"));
+    }
+
+    #[test]
+    fn ben_eater_hello_world() {
+        let source = "
+            PORTB = $6000
+            PORTA = $6001
+            DDRB = $6002
+            DDRA = $6003
+
+            E  = %10000000
+            RW = %01000000
+            RS = %00100000
+
+              .org $8000
+
+            reset:
+              ldx #$ff
+              txs
+
+              lda #%11111111 ; Set all pins on port B to output
+              sta DDRB
+              lda #%11100000 ; Set top 3 pins on port A to output
+              sta DDRA
+
+              ldx #0
+            print:
+              lda message,x
+              beq loop
+              jsr print_char
+              inx
+              jmp print
+
+            loop:
+              jmp loop
+
+            message: .asciiz \"Hello, world!\"
+
+            print_char:
+              sta PORTB
+              lda #RS         ; Set RS; Clear RW/E bits
+              sta PORTA
+              lda #(RS | E)   ; Set E bit to send instruction
+              sta PORTA
+              lda #RS
+              sta PORTA
+              rts
+
+              .org $fffc
+              .word reset
+              .word $0000
+        ";
+
+        let assembly = assemble(source).unwrap();
+        assert_eq!(assembly.bytes.len(), 0x8000);
+        let message = assembly.label("message").unwrap() as usize - 0x8000;
+        assert_eq!(&assembly.bytes[message..message + 14], b"Hello, world!\0");
+        assert_eq!(&assembly.bytes[0x7FFC..], &[0x00, 0x80, 0x00, 0x00]);
+        assert!(assembly.bytes.windows(2).any(|pair| pair == [0xA9, 0xA0]));
+    }
+
+    #[test]
+    fn errors_name_the_line() {
+        assert_eq!(assemble("NOP\nLDA #$100").err().unwrap(), "line 2: 256 doesn't fit in a byte");
+        assert_eq!(assemble("JMP nowhere").err().unwrap(), "line 1: nowhere isn't defined");
+        assert_eq!(assemble("FOO #1").err().unwrap(), "line 1: unknown instruction FOO");
+        assert_eq!(assemble("STX $1234,X").err().unwrap(), "line 1: STX has no ABX form");
+        assert!(assemble(".if 1\nNOP").is_err());
+
+        let far = std::format!("start: NOP\n.org $8100\nBNE start");
+        assert!(assemble(&far).err().unwrap().contains("out of range"));
+    }
+}
@@ -0,0 +1,306 @@
+use std::any::Any;
+
+use crate::cpu6502;
+use crate::device::BusDevice;
+use crate::error::EmuError;
+use crate::riot::{Riot, RiotPort};
+use crate::tia::{Tia, TiaPort};
+
+// The Atari 2600's memory map. The 6507 only has 13 address lines, so the
+// whole map repeats every 8K, and inside that the chips are picked out by
+// single lines:
+//
+//   A12=1                cartridge, a 4K window
+//   A12=0 A7=0           TIA
+//   A12=0 A7=1 A9=0      RIOT RAM, $80-$FF in zero page and again at
+//                        $180-$1FF where the stack is
+//   A12=0 A7=1 A9=1      RIOT ports and timer, $280-$297
+//
+// The RIOT's IRQ pin isn't connected, the 6507 has no IRQ input.
+
+const CART_WINDOW: usize = 0x1000;
+
+// How a cartridge bigger than the 4K window swaps banks in: touching one
+// of a run of hotspot addresses at the top of the window, read or write,
+// selects the bank that goes with it
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Banking {
+    // 2K, seen twice over, or 4K
+    None,
+    // 8K, hotspots $1FF8-$1FF9
+    F8,
+    // 16K, $1FF6-$1FF9
+    F6,
+    // 32K, $1FF4-$1FFB
+    F4,
+}
+
+impl Banking {
+    pub fn for_size(size: usize) -> Option<Banking> {
+        match size {
+            0x0800 | 0x1000 => Some(Banking::None),
+            0x2000 => Some(Banking::F8),
+            0x4000 => Some(Banking::F6),
+            0x8000 => Some(Banking::F4),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Banking::None => "no bank switching",
+            Banking::F8 => "F8 bank switching",
+            Banking::F6 => "F6 bank switching",
+            Banking::F4 => "F4 bank switching",
+        }
+    }
+
+    // The first hotspot as an offset into the window
+    fn first_hotspot(&self) -> usize {
+        match self {
+            Banking::None => CART_WINDOW,
+            Banking::F8 => 0xFF8,
+            Banking::F6 => 0xFF6,
+            Banking::F4 => 0xFF4,
+        }
+    }
+}
+
+pub struct Cartridge2600 {
+    rom: Vec<u8>,
+    banking: Banking,
+    bank: usize,
+}
+
+impl Cartridge2600 {
+    pub fn new(rom: &[u8]) -> Result<Cartridge2600, EmuError> {
+        let banking = Banking::for_size(rom.len()).ok_or_else(|| {
+            EmuError::BadImage(std::format!("a {} byte 2600 cartridge isn't 2K, 4K, 8K, 16K or 32K", rom.len()))
+        })?;
+
+        let mut cart = Cartridge2600 { rom: rom.to_vec(), banking, bank: 0 };
+        cart.reset();
+        Ok(cart)
+    }
+
+    pub fn banking(&self) -> Banking {
+        self.banking
+    }
+
+    pub fn bank(&self) -> usize {
+        self.bank
+    }
+
+    fn banks(&self) -> usize {
+        (self.rom.len() / CART_WINDOW).max(1)
+    }
+
+    // Carts start in their last bank, which is where the reset vector
+    // that matters lives
+    pub fn reset(&mut self) {
+        self.bank = self.banks() - 1;
+    }
+
+    fn peek(&self, offset: usize) -> u8 {
+        let offset = offset % CART_WINDOW;
+        self.rom[(self.bank * CART_WINDOW + offset) % self.rom.len()]
+    }
+
+    fn touch(&mut self, offset: usize) {
+        let hotspot = (offset % CART_WINDOW).wrapping_sub(self.banking.first_hotspot());
+        if hotspot < self.banks() {
+            self.bank = hotspot;
+        }
+    }
+}
+
+// The host's ends of the chips: the picture and fire buttons on the TIA,
+// the joysticks on RIOT port A and the console switches on port B
+#[derive(Clone)]
+pub struct Atari2600Ports {
+    pub tia: TiaPort,
+    pub riot: RiotPort,
+}
+
+pub struct Atari2600 {
+    tia: Tia,
+    riot: Riot,
+    cart: Cartridge2600,
+}
+
+impl Atari2600 {
+    pub fn new(tia: Tia, riot: Riot, cart: Cartridge2600) -> Self {
+        Atari2600 { tia, riot, cart }
+    }
+
+    pub fn ports(&self) -> Atari2600Ports {
+        Atari2600Ports { tia: self.tia.port(), riot: self.riot.port() }
+    }
+
+    // What the RIOT sees of an address, its RS line is A9
+    fn riot_offset(addr: u16) -> u16 {
+        addr & 0x27F
+    }
+}
+
+impl BusDevice for Atari2600 {
+    fn read(&mut self, offset: u16) -> u8 {
+        let addr = offset & 0x1FFF;
+        if addr & 0x1000 != 0 {
+            self.cart.touch(addr as usize);
+            self.cart.peek(addr as usize)
+        } else if addr & 0x80 != 0 {
+            self.riot.read(Atari2600::riot_offset(addr))
+        } else {
+            self.tia.read(addr)
+        }
+    }
+
+    fn peek(&self, offset: u16) -> u8 {
+        let addr = offset & 0x1FFF;
+        if addr & 0x1000 != 0 {
+            self.cart.peek(addr as usize)
+        } else if addr & 0x80 != 0 {
+            self.riot.peek(Atari2600::riot_offset(addr))
+        } else {
+            self.tia.peek(addr)
+        }
+    }
+
+    fn write(&mut self, offset: u16, data: u8) {
+        let addr = offset & 0x1FFF;
+        if addr & 0x1000 != 0 {
+            self.cart.touch(addr as usize);
+        } else if addr & 0x80 != 0 {
+            self.riot.write(Atari2600::riot_offset(addr), data);
+        } else {
+            self.tia.write(addr, data);
+        }
+    }
+
+    fn as_any(&self) -> Option<&dyn Any> {
+        Some(self)
+    }
+
+    fn reset(&mut self) {
+        self.tia.reset();
+        self.riot.reset();
+        self.cart.reset();
+    }
+}
+
+impl cpu6502 {
+    // Makes the whole address space a 2600 with `rom` plugged in, in place
+    // of whatever 2600 was there before
+    pub fn attach_atari2600(&mut self, rom: &[u8]) -> Result<Atari2600Ports, EmuError> {
+        let cart = Cartridge2600::new(rom)?;
+        let mut riot = Riot::new(self.events());
+        riot.connect_log(self.device_log().logger("riot"));
+        let console = Atari2600::new(Tia::new(self.events()), riot, cart);
+        let ports = console.ports();
+
+        let mut bus = self.bus.borrow_mut();
+        bus.unmap("atari2600");
+        bus.map("atari2600", 0x0000, 0xFFFF, Box::new(console));
+
+        Ok(ports)
+    }
+
+    // The ports of the 2600 attached last, if one still is
+    pub fn atari2600(&self) -> Option<Atari2600Ports> {
+        let bus = self.bus.borrow();
+        let mapping = bus.mappings().find(|mapping| mapping.name == "atari2600")?;
+        let device = mapping.device();
+        device.as_any()?.downcast_ref::<Atari2600>().map(Atari2600::ports)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A 4K bank per `banks` filled with its number, with a reset vector
+    // pointing at $F000
+    fn rom(banks: usize) -> Vec<u8> {
+        let mut rom: Vec<u8> = (0..banks).flat_map(|bank| vec![bank as u8; CART_WINDOW]).collect();
+        let len = rom.len();
+        rom[len - 4] = 0x00;
+        rom[len - 3] = 0xF0;
+        rom
+    }
+
+    #[test]
+    fn memory_map() {
+        let mut cpu = cpu6502::new();
+        let port = cpu.attach_atari2600(&rom(1)).unwrap().riot;
+        let mut bus = cpu.bus.borrow_mut();
+
+        // Zero page RAM is also the stack, in every 8K mirror
+        bus.write(0x0080, 0x42);
+        assert_eq!(bus.read(0x0180, false), 0x42);
+        assert_eq!(bus.read(0x2080, false), 0x42);
+        bus.write(0x01FF, 0x17);
+        assert_eq!(port.ram()[0x7F], 0x17);
+
+        // Ports at $280, the cartridge in every odd 4K
+        port.set_port_a(0xEF);
+        assert_eq!(bus.read(0x0280, false), 0xEF);
+        assert_eq!(bus.read(0xFFFD, false), 0xF0);
+        assert_eq!(bus.read(0x1FFD, false), 0xF0);
+
+        // The TIA below $80, collisions and inputs repeating every 16
+        bus.write(0x002C, 0x00);
+        assert_eq!(bus.read(0x003C, false), 0x80);
+        drop(bus);
+        cpu.atari2600().unwrap().tia.set_fire(0, true);
+        assert_eq!(cpu.bus.borrow().read(0x000C, false), 0x00);
+    }
+
+    #[test]
+    fn small_cartridges_fill_the_window() {
+        let cart = Cartridge2600::new(&[0xEA; 0x800]).unwrap();
+        assert_eq!((cart.banking(), cart.peek(0x000), cart.peek(0x800)), (Banking::None, 0xEA, 0xEA));
+        assert!(matches!(Cartridge2600::new(&[0; 3000]), Err(EmuError::BadImage(_))));
+    }
+
+    #[test]
+    fn bank_switching() {
+        for (banks, banking, first) in [(2, Banking::F8, 0x1FF8), (4, Banking::F6, 0x1FF6), (8, Banking::F4, 0x1FF4)] {
+            let mut cpu = cpu6502::new();
+            cpu.attach_atari2600(&rom(banks)).unwrap();
+            let mut bus = cpu.bus.borrow_mut();
+
+            // Last bank first, then whichever hotspot was touched last
+            assert_eq!(bus.read(0x1000, false), banks as u8 - 1, "{:?}", banking);
+            for bank in 0..banks as u16 {
+                bus.read(first + bank, false);
+                assert_eq!(bus.read(0xF000, false), bank as u8, "{:?}", banking);
+            }
+
+            bus.write(first, 0);
+            assert_eq!(bus.read(0x1000, true), 0, "{:?}", banking);
+
+            // The debugger looking doesn't switch
+            bus.read(first + 1, true);
+            assert_eq!(bus.read(0x1000, true), 0, "{:?}", banking);
+        }
+    }
+
+    #[test]
+    fn runs_from_the_reset_vector() {
+        let mut cpu = cpu6502::new();
+        // LDA #$05 / STA $0295 (TIM8T) / loop: LDA $0284 (INTIM) / STA $80 / JMP loop
+        let mut image = rom(1);
+        image[..12].copy_from_slice(&[0xA9, 0x05, 0x8D, 0x95, 0x02, 0xAD, 0x84, 0x02, 0x85, 0x80, 0x4C, 0x05]);
+        image[12] = 0xF0;
+        cpu.attach_atari2600(&image).unwrap();
+        cpu.reset();
+        assert_eq!(cpu.pc, 0xF000);
+
+        for _ in 0..30 {
+            cpu.clock();
+        }
+        let count = cpu.bus.borrow().read(0x80, true);
+        assert!(count > 0 && count < 5, "timer read {}", count);
+    }
+}
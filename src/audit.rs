@@ -0,0 +1,233 @@
+use crate::cpu6502;
+use crate::replay::Recording;
+use crate::snapshot::MachineState;
+
+// Replays depend on the emulator doing exactly the same thing every time it
+// is given the same start state and inputs. An audit plays a recording
+// twice and compares state hashes at regular checkpoints, so anything that
+// leaks in from the host (device state that snapshots miss, HashMap order,
+// the wall clock) shows up as the first checkpoint where the runs part.
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Audit {
+    // How long to run, in cycles from the start of the recording
+    pub cycles: u64,
+    // Cycles between checkpoints. Smaller pins a divergence down closer
+    // but hashes more often.
+    pub interval: u64,
+}
+
+// The first checkpoint the two runs disagreed at
+#[derive(Debug, Clone, PartialEq)]
+pub struct Divergence {
+    // Cycles from the start, the last checkpoint that still matched and the
+    // one that didn't
+    pub after: u64,
+    pub at: u64,
+    // From the first run's state to the second's
+    pub diff: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct AuditReport {
+    // Checkpoints compared, including the one that failed
+    pub checkpoints: usize,
+    pub divergence: Option<Divergence>,
+}
+
+impl AuditReport {
+    pub fn is_deterministic(&self) -> bool {
+        self.divergence.is_none()
+    }
+
+    pub fn describe(&self) -> Vec<String> {
+        match &self.divergence {
+            None => vec![std::format!("{} checkpoints matched", self.checkpoints)],
+            Some(divergence) => {
+                let mut lines = vec![std::format!("runs differ between cycle {} and {}:", divergence.after, divergence.at)];
+                lines.extend(divergence.diff.iter().map(|change| std::format!("  {}", change)));
+                lines
+            }
+        }
+    }
+}
+
+impl Audit {
+    pub fn new(cycles: u64) -> Self {
+        Audit { cycles, interval: 1000 }
+    }
+
+    // Cycle counts from the start of the recording to check at
+    fn checkpoints(&self) -> impl Iterator<Item = u64> {
+        let (cycles, interval) = (self.cycles, self.interval.max(1));
+        (1..=cycles.div_ceil(interval)).map(move |i| (i * interval).min(cycles))
+    }
+
+    // Plays the recording on two separately built machines side by side.
+    // This is the stronger check, it catches anything that differs between
+    // two instances of the same machine.
+    pub fn compare(&self, first: &mut cpu6502, second: &mut cpu6502, recording: &Recording) -> Result<AuditReport, String> {
+        let start = recording.initial.cpu.clock_count;
+        first.start_replay(recording.clone())?;
+        second.start_replay(recording.clone())?;
+
+        let mut report = AuditReport { checkpoints: 0, divergence: None };
+        let mut after = 0;
+
+        for checkpoint in self.checkpoints() {
+            run_to(first, start + checkpoint);
+            run_to(second, start + checkpoint);
+            report.checkpoints += 1;
+
+            let (a, b) = (MachineState::capture(first), MachineState::capture(second));
+            if a.hash() != b.hash() {
+                report.divergence = Some(Divergence { after, at: checkpoint, diff: a.describe_diff(&b) });
+                break;
+            }
+            after = checkpoint;
+        }
+
+        first.player = None;
+        second.player = None;
+        Ok(report)
+    }
+
+    // Plays the recording twice on the same machine, for when there's only
+    // the one. The machine is put back at the recording's start afterwards.
+    pub fn repeat(&self, cpu: &mut cpu6502, recording: &Recording) -> Result<AuditReport, String> {
+        let start = recording.initial.cpu.clock_count;
+
+        cpu.start_replay(recording.clone())?;
+        let hashes: Vec<u64> = self.checkpoints().map(|checkpoint| {
+            run_to(cpu, start + checkpoint);
+            MachineState::capture(cpu).hash()
+        }).collect();
+
+        cpu.start_replay(recording.clone())?;
+        let mut report = AuditReport { checkpoints: 0, divergence: None };
+        let mut after = 0;
+
+        for (checkpoint, hash) in self.checkpoints().zip(hashes) {
+            run_to(cpu, start + checkpoint);
+            report.checkpoints += 1;
+
+            let second = MachineState::capture(cpu);
+            if second.hash() != hash {
+                // Only the hash of the first run was kept, go back for the rest
+                cpu.start_replay(recording.clone())?;
+                run_to(cpu, start + checkpoint);
+                let first = MachineState::capture(cpu);
+
+                report.divergence = Some(Divergence { after, at: checkpoint, diff: first.describe_diff(&second) });
+                break;
+            }
+            after = checkpoint;
+        }
+
+        cpu.player = None;
+        recording.restore(cpu)?;
+        Ok(report)
+    }
+}
+
+fn run_to(cpu: &mut cpu6502, cycle: u64) {
+    while cpu.clock_count < cycle {
+        cpu.clock();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicU8, Ordering};
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::device::BusDevice;
+    use crate::replay::Stimulus;
+
+    // A register whose value lives outside the machine, so snapshots can't
+    // see or restore it
+    struct Leaky(Arc<AtomicU8>);
+
+    impl BusDevice for Leaky {
+        fn read(&mut self, _offset: u16) -> u8 {
+            self.0.fetch_add(1, Ordering::Relaxed).wrapping_add(1)
+        }
+
+        fn peek(&self, _offset: u16) -> u8 {
+            self.0.load(Ordering::Relaxed)
+        }
+
+        fn write(&mut self, _offset: u16, _data: u8) {}
+    }
+
+    // LDA $00FF / STA $10 / INX / JMP $8000, with the last key typed at $FF
+    fn machine() -> cpu6502 {
+        let mut cpu = cpu6502::new();
+        cpu.load_program(&[0xA5, 0xFF, 0x85, 0x10, 0xE8, 0x4C, 0x00, 0x80], 0x8000);
+        cpu.set_reset_vector(0x8000);
+        cpu.reset();
+        cpu
+    }
+
+    fn recording(cpu: &mut cpu6502) -> Recording {
+        cpu.start_recording();
+        for key in [b'a', b'b'] {
+            for _ in 0..150 {
+                cpu.clock();
+            }
+            cpu.stimulate(Stimulus::Write { addr: 0x00FF, data: key });
+        }
+        cpu.stop_recording().unwrap()
+    }
+
+    #[test]
+    fn replays_are_deterministic() {
+        let mut cpu = machine();
+        let recording = recording(&mut cpu);
+        let audit = Audit { cycles: 1000, interval: 64 };
+
+        let report = audit.repeat(&mut cpu, &recording).unwrap();
+        assert!(report.is_deterministic());
+        assert_eq!(report.checkpoints, 16);
+        assert_eq!(MachineState::capture(&cpu), recording.initial);
+
+        let report = audit.compare(&mut machine(), &mut machine(), &recording).unwrap();
+        assert_eq!(report.describe(), vec!["16 checkpoints matched"]);
+    }
+
+    #[test]
+    fn host_state_is_caught() {
+        let mut cpu = machine();
+        let recording = recording(&mut cpu);
+
+        // The counter carries on from the first run into the second
+        let counter = Arc::new(AtomicU8::new(0));
+        cpu.bus.borrow_mut().map("leaky", 0x00FF, 0x00FF, Box::new(Leaky(counter.clone())));
+
+        let report = Audit { cycles: 500, interval: 100 }.repeat(&mut cpu, &recording).unwrap();
+        let divergence = report.divergence.unwrap();
+        assert_eq!((divergence.after, divergence.at), (0, 100));
+        assert!(divergence.diff.iter().any(|line| line.starts_with("ram $0010")));
+    }
+
+    #[test]
+    fn machines_built_differently_are_caught() {
+        let mut cpu = machine();
+        let recording = recording(&mut cpu);
+
+        let mut second = machine();
+        second.bus.borrow_mut().map("stuck", 0x00FF, 0x00FF, Box::new(Leaky(Arc::new(AtomicU8::new(0x41)))));
+
+        let report = Audit { cycles: 500, interval: 100 }.compare(&mut machine(), &mut second, &recording).unwrap();
+        assert!(!report.is_deterministic());
+        assert_eq!(report.describe()[0], "runs differ between cycle 0 and 100:");
+    }
+
+    #[test]
+    fn checkpoints_cover_the_whole_run() {
+        let audit = Audit { cycles: 250, interval: 100 };
+        assert_eq!(audit.checkpoints().collect::<Vec<u64>>(), vec![100, 200, 250]);
+        assert_eq!(Audit { cycles: 5, interval: 0 }.checkpoints().count(), 5);
+    }
+}
@@ -0,0 +1,236 @@
+use std::any::Any;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+use crate::cpu6502;
+use crate::device::BusDevice;
+
+// Memory bigger than the address space, seen a bank at a time through a
+// window, with a control register choosing which bank. It's how cartridge
+// boards, banked EEPROM designs and machines with more RAM than 64K all
+// do it. The window and the register are separate devices sharing the
+// banks, so the register can go anywhere; boards that pick a bank by
+// writing into ROM have the window take the writes itself.
+//
+// Banks is the memory on its own for a Mapper to keep, it's plain data.
+
+pub struct Banks {
+    data: Vec<u8>,
+    bank_size: usize,
+    selected: usize,
+    writable: bool,
+}
+
+impl Banks {
+    // ROM cut into banks, the last one padded with $ff if it's short
+    pub fn rom(image: &[u8], bank_size: usize) -> Self {
+        let bank_size = bank_size.max(1);
+        let mut data = image.to_vec();
+        data.resize(image.len().div_ceil(bank_size).max(1) * bank_size, 0xFF);
+        Banks { data, bank_size, selected: 0, writable: false }
+    }
+
+    pub fn ram(count: usize, bank_size: usize) -> Self {
+        let bank_size = bank_size.max(1);
+        Banks { data: vec![0; count.max(1) * bank_size], bank_size, selected: 0, writable: true }
+    }
+
+    pub fn count(&self) -> usize {
+        self.data.len() / self.bank_size
+    }
+
+    pub fn bank_size(&self) -> usize {
+        self.bank_size
+    }
+
+    pub fn selected(&self) -> usize {
+        self.selected
+    }
+
+    // Numbers past the last bank wrap, as the unused high bits of a bank
+    // register usually aren't wired to anything
+    pub fn select(&mut self, bank: usize) {
+        self.selected = bank % self.count();
+    }
+
+    // Where an offset into the window is in the whole of the memory,
+    // repeating if the window is bigger than a bank
+    pub fn offset(&self, offset: u16) -> usize {
+        self.selected * self.bank_size + offset as usize % self.bank_size
+    }
+
+    pub fn read(&self, offset: u16) -> u8 {
+        self.data[self.offset(offset)]
+    }
+
+    pub fn write(&mut self, offset: u16, data: u8) {
+        if self.writable {
+            let at = self.offset(offset);
+            self.data[at] = data;
+        }
+    }
+
+    // The bank as it is now, for a memory view
+    pub fn bank(&self, bank: usize) -> &[u8] {
+        let start = (bank % self.count()) * self.bank_size;
+        &self.data[start..start + self.bank_size]
+    }
+}
+
+// The host's side, for looking at or switching banks from outside
+#[derive(Clone)]
+pub struct BankPort(Arc<Mutex<Banks>>);
+
+impl BankPort {
+    pub fn banks(&self) -> MutexGuard<'_, Banks> {
+        self.0.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    pub fn selected(&self) -> usize {
+        self.banks().selected()
+    }
+
+    pub fn select(&self, bank: usize) {
+        self.banks().select(bank);
+    }
+}
+
+// The window. Reset brings bank 0 back in.
+pub struct BankedRegion {
+    port: BankPort,
+    writes_select: bool,
+}
+
+impl BankedRegion {
+    pub fn new(banks: Banks) -> Self {
+        BankedRegion { port: BankPort(Arc::new(Mutex::new(banks))), writes_select: false }
+    }
+
+    // Writes anywhere in the window choose the bank instead of going to
+    // memory, like a UxROM board's
+    pub fn writes_select(mut self) -> Self {
+        self.writes_select = true;
+        self
+    }
+
+    pub fn port(&self) -> BankPort {
+        self.port.clone()
+    }
+
+    // The control register, to map wherever the board decodes it
+    pub fn register(&self) -> BankRegister {
+        BankRegister { port: self.port.clone() }
+    }
+}
+
+impl BusDevice for BankedRegion {
+    fn read(&mut self, offset: u16) -> u8 {
+        self.peek(offset)
+    }
+
+    fn peek(&self, offset: u16) -> u8 {
+        self.port.banks().read(offset)
+    }
+
+    fn write(&mut self, offset: u16, data: u8) {
+        let mut banks = self.port.banks();
+        if self.writes_select {
+            banks.select(data as usize);
+        } else {
+            banks.write(offset, data);
+        }
+    }
+
+    fn reset(&mut self) {
+        self.port.select(0);
+    }
+
+    fn as_any(&self) -> Option<&dyn Any> {
+        Some(self)
+    }
+}
+
+// Writing selects a bank, reading gives the one selected
+pub struct BankRegister {
+    port: BankPort,
+}
+
+impl BusDevice for BankRegister {
+    fn read(&mut self, offset: u16) -> u8 {
+        self.peek(offset)
+    }
+
+    fn peek(&self, _offset: u16) -> u8 {
+        self.port.selected() as u8
+    }
+
+    fn write(&mut self, _offset: u16, data: u8) {
+        self.port.select(data as usize);
+    }
+}
+
+impl cpu6502 {
+    // Maps `region` over $start-$end and, if there is one, its control
+    // register at `register`
+    pub fn map_banked(&mut self, name: &str, start: u16, end: u16, region: BankedRegion, register: Option<u16>) -> BankPort {
+        let port = region.port();
+        let mut bus = self.bus.borrow_mut();
+        if let Some(addr) = register {
+            bus.map(&std::format!("{} bank", name), addr, addr, Box::new(region.register()));
+        }
+        bus.map(name, start, end, Box::new(region));
+        port
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Four 16K banks, each filled with its own number
+    fn numbered() -> Banks {
+        let image: Vec<u8> = (0..4u8).flat_map(|bank| [bank; 0x4000]).collect();
+        Banks::rom(&image, 0x4000)
+    }
+
+    #[test]
+    fn register_picks_the_bank() {
+        let mut cpu = cpu6502::new();
+        // LDA #$02 / STA $7000 / LDX $8000 / loop: JMP loop
+        cpu.load_program(&[0xA9, 0x02, 0x8D, 0x00, 0x70, 0xAE, 0x00, 0x80, 0x4C, 0x08, 0xC0], 0xC000);
+        cpu.set_reset_vector(0xC000);
+        let port = cpu.map_banked("rom", 0x8000, 0xBFFF, BankedRegion::new(numbered()), Some(0x7000));
+        cpu.reset();
+        cpu.run_instructions(3, 100);
+
+        assert_eq!((cpu.x, port.selected()), (2, 2));
+        assert_eq!(cpu.bus.borrow().read(0x7000, true), 2);
+        // ROM ignores writes, and a number past the end wraps
+        cpu.bus.borrow_mut().write(0x8000, 0x55);
+        assert_eq!(cpu.bus.borrow().read(0xBFFF, true), 2);
+        port.select(7);
+        assert_eq!(cpu.bus.borrow().read(0x8000, true), 3);
+
+        cpu.reset();
+        assert_eq!(port.selected(), 0);
+    }
+
+    #[test]
+    fn ram_banks_and_selecting_by_writes() {
+        let mut ram = BankedRegion::new(Banks::ram(2, 0x100));
+        ram.write(0x10, 0xAA);
+        ram.port().select(1);
+        assert_eq!(ram.read(0x10), 0x00);
+        ram.write(0x110, 0xBB);
+        ram.port().select(0);
+        assert_eq!(ram.read(0x10), 0xAA);
+        assert_eq!(ram.port().banks().bank(1)[0x10], 0xBB);
+
+        let mut uxrom = BankedRegion::new(numbered()).writes_select();
+        uxrom.write(0x1234, 0x01);
+        assert_eq!((uxrom.read(0x0000), uxrom.read(0x3FFF)), (1, 1));
+
+        // A short image is padded out to a whole bank
+        let banks = Banks::rom(&[0xEA; 0x100], 0x4000);
+        assert_eq!((banks.count(), banks.read(0x100)), (1, 0xFF));
+    }
+}
@@ -0,0 +1,364 @@
+use crate::assembler;
+use crate::cpu6502;
+use crate::run::StopReason;
+use crate::watchdog::Watchdog;
+
+// Small programs that work the CPU harder than the demos do, run by
+// selftest. Each ends by spinning at `done` and says what it should have
+// left in memory, and how many cycles it takes to get there from reset,
+// so they check timing as well as results: an instruction a cycle out
+// anywhere in the loops moves the total.
+//
+// The functional program is in the style of Klaus Dormann's test suite,
+// each check branches to itself when it fails and the number of the test
+// it's in is kept at TEST, so a failure says where it stopped.
+
+// Far more than any of them needs
+const MAX_CYCLES: u64 = 1_000_000;
+
+pub struct Benchmark {
+    pub name: &'static str,
+    pub source: &'static str,
+    // Address and the value expected there once `done` is reached
+    pub expected: &'static [(u16, u8)],
+    // From reset to `done`, as worked out on this core
+    pub cycles: u64,
+}
+
+pub struct BenchmarkResult {
+    // None when it never got to `done`
+    pub cycles: Option<u64>,
+    // Empty when everything came out as expected
+    pub failures: Vec<String>,
+}
+
+pub const BENCHMARKS: &[Benchmark] = &[
+    Benchmark {
+        name: "multiply",
+        source: "
+            FIRST = $10
+            SECOND = $11
+            RESULTS = $0300
+
+            ; Products of each pair, low byte then high, at RESULTS
+                    LDY #0
+            next:   LDA pairs,Y
+                    STA FIRST
+                    LDA pairs+1,Y
+                    STA SECOND
+                    JSR multiply
+                    STA RESULTS+1,Y
+                    LDA SECOND
+                    STA RESULTS,Y
+                    INY
+                    INY
+                    CPY #8
+                    BNE next
+            done:   JMP done
+
+            ; FIRST * SECOND by shift and add, high byte in A and the low
+            ; one shifted into SECOND
+            multiply:
+                    LDA #0
+                    LDX #8
+                    LSR SECOND
+            bit:    BCC skip
+                    CLC
+                    ADC FIRST
+            skip:   ROR A
+                    ROR SECOND
+                    DEX
+                    BNE bit
+                    RTS
+
+            pairs:  .byte 13, 11, 255, 255, 0, 77, 128, 2
+        ",
+        expected: &[
+            (0x0300, 0x8F), (0x0301, 0x00),
+            (0x0302, 0x01), (0x0303, 0xFE),
+            (0x0304, 0x00), (0x0305, 0x00),
+            (0x0306, 0x00), (0x0307, 0x01),
+        ],
+        // The 8 the reset takes, 143 in the loop over the pairs and 626 in
+        // the calls, 15 a bit or 19 with an add
+        cycles: 777,
+    },
+    Benchmark {
+        name: "sieve",
+        source: "
+            COUNT = $10
+            SIEVE = $0400           ; non-zero for every number crossed out
+            STEP = $11
+
+                    LDA #0
+                    TAX
+            clear:  STA SIEVE,X
+                    INX
+                    BNE clear
+                    STA COUNT
+                    LDX #2
+            number: LDA SIEVE,X
+                    BNE next
+                    INC COUNT
+            ; Cross out every multiple that fits in a byte
+                    STX STEP
+                    TXA
+            cross:  CLC
+                    ADC STEP
+                    BCS next
+                    TAY
+                    LDA #1
+                    STA SIEVE,Y
+                    TYA
+                    JMP cross
+            next:   INX
+                    BNE number
+            done:   JMP done
+        ",
+        // 54 primes below 256, 97 and 251 among them and 91 not
+        expected: &[(0x0010, 54), (0x0461, 0), (0x04FB, 0), (0x045B, 1)],
+        cycles: 15571,
+    },
+    Benchmark {
+        name: "crc16",
+        source: "
+            CRC = $10               ; low byte, high at $11
+
+            ; CRC-16/CCITT, polynomial $1021 from $ffff, a bit at a time
+                    LDA #$FF
+                    STA CRC
+                    STA CRC+1
+                    LDY #0
+            byte:   LDA text,Y
+                    BEQ done
+                    EOR CRC+1
+                    STA CRC+1
+                    LDX #8
+            bit:    ASL CRC
+                    ROL CRC+1
+                    BCC next
+                    LDA CRC+1
+                    EOR #$10
+                    STA CRC+1
+                    LDA CRC
+                    EOR #$21
+                    STA CRC
+            next:   DEX
+                    BNE bit
+                    INY
+                    JMP byte
+            done:   JMP done
+
+            text:   .asciiz \"123456789\"
+        ",
+        // The standard check value, $29b1
+        expected: &[(0x0010, 0xB1), (0x0011, 0x29)],
+        cycles: 1948,
+    },
+    Benchmark {
+        name: "functional",
+        source: "
+            TEST = $0200
+
+                    LDX #$FF
+                    TXS
+                    LDA #1
+                    STA TEST
+            ; 1: loads set N and Z
+                    LDA #$00
+                    BNE *
+                    BMI *
+                    LDA #$80
+                    BEQ *
+                    BPL *
+            ; 2: ADC carries and overflows
+                    INC TEST
+                    CLC
+                    LDA #$7F
+                    ADC #$01
+                    BVC *
+                    BCS *
+                    CMP #$80
+                    BNE *
+                    SEC
+                    LDA #$FF
+                    ADC #$00
+                    BCC *
+                    BNE *
+                    BVS *
+            ; 3: SBC borrows and overflows
+                    INC TEST
+                    SEC
+                    LDA #$00
+                    SBC #$01
+                    BCS *
+                    CMP #$FF
+                    BNE *
+                    SEC
+                    LDA #$80
+                    SBC #$01
+                    BVC *
+                    CMP #$7F
+                    BNE *
+            ; 4: compares
+                    INC TEST
+                    LDX #$40
+                    CPX #$41
+                    BCS *
+                    BEQ *
+                    BPL *
+                    LDY #$40
+                    CPY #$40
+                    BNE *
+                    BCC *
+            ; 5: shifts and rotates through the carry
+                    INC TEST
+                    LDA #$81
+                    ASL A
+                    BCC *
+                    CMP #$02
+                    BNE *
+                    ROR A
+                    BCS *
+                    CMP #$81
+                    BNE *
+                    LSR A
+                    BCC *
+                    ROL A
+                    BCS *
+                    CMP #$81
+                    BNE *
+            ; 6: BIT, and the flags through the stack
+                    INC TEST
+                    LDA #$C0
+                    STA $20
+                    LDA #$01
+                    BIT $20
+                    BNE *
+                    BVC *
+                    BPL *
+                    PHP
+                    PLA
+                    AND #$C3
+                    CMP #$C3
+                    BNE *
+                    LDA #$01
+                    PHA
+                    PLP
+                    BCC *
+                    BEQ *
+                    BVS *
+                    BMI *
+            ; 7: indexed and indirect addressing
+                    INC TEST
+                    LDA #$34
+                    STA $0321
+                    LDA #$21
+                    STA $30
+                    LDA #$03
+                    STA $31
+                    LDY #0
+                    LDA #0
+                    LDA ($30),Y
+                    CMP #$34
+                    BNE *
+                    LDX #$10
+                    LDA #0
+                    LDA ($20,X)
+                    CMP #$34
+                    BNE *
+                    LDY #$21
+                    LDA #0
+                    LDA $0300,Y
+                    CMP #$34
+                    BNE *
+            ; 8: calls leave the stack where it was
+                    INC TEST
+                    LDA #0
+                    JSR sub
+                    CMP #$55
+                    BNE *
+                    TSX
+                    CPX #$FF
+                    BNE *
+            done:   JMP done
+
+            sub:    LDA #$55
+                    RTS
+        ",
+        expected: &[(0x0200, 8)],
+        cycles: 297,
+    },
+];
+
+pub fn find(name: &str) -> Option<&'static Benchmark> {
+    BENCHMARKS.iter().find(|benchmark| benchmark.name == name)
+}
+
+impl Benchmark {
+    // Runs the benchmark on a machine of its own, from reset to `done`
+    pub fn run(&self) -> BenchmarkResult {
+        let failed = |failure: String| BenchmarkResult { cycles: None, failures: vec![failure] };
+        let assembly = match assembler::assemble(self.source) {
+            Ok(assembly) => assembly,
+            Err(e) => return failed(std::format!("{}: {}", self.name, e)),
+        };
+        let done = match assembly.label("done") {
+            Some(done) => done,
+            None => return failed(std::format!("{} has no done label", self.name)),
+        };
+
+        let mut cpu = cpu6502::new();
+        cpu.load_program(&assembly.bytes, assembly.origin);
+        cpu.set_reset_vector(assembly.origin);
+        cpu.set_watchdog(Some(Watchdog::default()));
+        cpu.reset();
+        let start = cpu.clock_count;
+
+        match cpu.run_until(|cpu| cpu.pc == done, MAX_CYCLES) {
+            StopReason::ConditionMet => {}
+            StopReason::Halted(at) => {
+                let test = cpu.bus.borrow().read(0x0200, true);
+                return failed(std::format!("{} stuck at ${:04x}, test {} if it keeps one at $0200", self.name, at, test));
+            }
+            stop => return failed(std::format!("{} never reached ${:04x}, {:?} at ${:04x}", self.name, done, stop, cpu.pc)),
+        }
+
+        let cycles = cpu.clock_count - start;
+        let bus = cpu.bus.borrow();
+        let mut failures: Vec<String> = self
+            .expected
+            .iter()
+            .filter(|(addr, expected)| bus.read(*addr, true) != *expected)
+            .map(|(addr, expected)| std::format!("{}: ${:04x} is ${:02x}, expected ${:02x}", self.name, addr, bus.read(*addr, true), expected))
+            .collect();
+        if cycles != self.cycles {
+            failures.push(std::format!("{}: took {} cycles, expected {}", self.name, cycles, self.cycles));
+        }
+
+        BenchmarkResult { cycles: Some(cycles), failures }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_benchmark_checks_out() {
+        for benchmark in BENCHMARKS {
+            let result = benchmark.run();
+            assert_eq!(result.failures, Vec::<String>::new(), "{}", benchmark.name);
+        }
+    }
+
+    #[test]
+    fn a_failed_check_says_which_test() {
+        // Test 2 expecting the ADC to carry when it doesn't
+        let functional = find("functional").unwrap();
+        let broken = Benchmark { source: functional.source.replacen("BCS *", "BCC *", 1).leak(), ..*functional };
+        let result = broken.run();
+        assert_eq!(result.cycles, None);
+        assert!(result.failures[0].contains("test 2"), "{:?}", result.failures);
+    }
+}
@@ -0,0 +1,370 @@
+// The debugger with an egui front end: registers, disassembly, memory,
+// breakpoints and watches each in their own window, arranged however
+// suits. The minifb window in main.rs stays the lightweight default, this
+// one is for when there's more to look at. Breakpoints, watches and which
+// panels are open are kept in the program's project file, see project.rs.
+//
+//   cargo run --features egui-ui --bin crust-6502-egui -- [program] [--org <addr>] [--symbols <file>]
+
+use std::path::{Path, PathBuf};
+
+use eframe::egui;
+
+use crust_6502_emulator::codeview::CodeView;
+use crust_6502_emulator::debugger::{Debugger, Register};
+use crust_6502_emulator::demos;
+use crust_6502_emulator::expr;
+use crust_6502_emulator::project::Project;
+use crust_6502_emulator::symbols::SymbolTable;
+use crust_6502_emulator::{cpu6502, CYCLES_PER_FRAME, FLAGS6502};
+
+// Disassembly shown either side of PC
+const LINES_BEFORE_PC: usize = 8;
+const LINES_AFTER_PC: usize = 24;
+
+// Memory window rows, 16 bytes each
+const MEMORY_ROWS: u16 = 16;
+
+struct Panels {
+    registers: bool,
+    disassembly: bool,
+    memory: bool,
+    breakpoints: bool,
+    watches: bool,
+}
+
+struct App {
+    cpu: cpu6502,
+    debugger: Debugger,
+    symbols: SymbolTable,
+    code: CodeView,
+    panels: Panels,
+    memory_addr: String,
+    new_breakpoint: String,
+    new_watch: String,
+    status: String,
+    // Where the project is kept, and what was last written there
+    project_path: Option<PathBuf>,
+    project: Project,
+}
+
+impl App {
+    fn new(cpu: cpu6502, symbols: SymbolTable, status: String, project_path: Option<PathBuf>, project: Project) -> Self {
+        // Panels the project doesn't mention start open
+        let shows = |panel: &str| project.layout.get(panel).copied().unwrap_or(true);
+        let mut debugger = Debugger::new();
+        debugger.breakpoints = project.breakpoints.clone();
+        debugger.watches = project.watches.clone();
+
+        App {
+            cpu,
+            debugger,
+            symbols,
+            code: CodeView::new(),
+            panels: Panels {
+                registers: shows("registers"),
+                disassembly: shows("disassembly"),
+                memory: shows("memory"),
+                breakpoints: shows("breakpoints"),
+                watches: shows("watches"),
+            },
+            memory_addr: "0000".to_string(),
+            new_breakpoint: String::new(),
+            new_watch: String::new(),
+            status,
+            project_path,
+            project,
+        }
+    }
+
+    // Writes the project out whenever something in it has changed
+    fn save_project(&mut self) {
+        let Some(path) = &self.project_path else {
+            return;
+        };
+
+        let mut project = self.project.clone();
+        project.breakpoints = self.debugger.breakpoints.clone();
+        project.watches = self.debugger.watches.clone();
+        let panels = &self.panels;
+        for (panel, shown) in [
+            ("registers", panels.registers),
+            ("disassembly", panels.disassembly),
+            ("memory", panels.memory),
+            ("breakpoints", panels.breakpoints),
+            ("watches", panels.watches),
+        ] {
+            project.layout.insert(panel.to_string(), shown);
+        }
+
+        if project != self.project {
+            match project.save(path) {
+                Ok(()) => self.project = project,
+                Err(e) => self.status = std::format!("can't save project: {}", e),
+            }
+        }
+    }
+
+    fn step(&mut self) {
+        loop {
+            self.cpu.clock();
+            if self.cpu.complete() {
+                break;
+            }
+        }
+    }
+
+    fn run_frame(&mut self) {
+        self.debugger.apply_cheats(&mut self.cpu);
+
+        for _ in 0..CYCLES_PER_FRAME {
+            self.cpu.clock();
+            if self.cpu.complete() && self.debugger.is_breakpoint(self.cpu.pc) {
+                self.debugger.running = false;
+                self.status = std::format!("break at ${:04x}", self.cpu.pc);
+                break;
+            }
+        }
+    }
+
+    fn eval(&self, text: &str) -> Result<i64, String> {
+        expr::eval(text, &self.cpu, &self.symbols)
+    }
+
+    fn controls(&mut self, ui: &mut egui::Ui) {
+        egui::menu::bar(ui, |ui| {
+            ui.menu_button("View", |ui| {
+                ui.checkbox(&mut self.panels.registers, "Registers");
+                ui.checkbox(&mut self.panels.disassembly, "Disassembly");
+                ui.checkbox(&mut self.panels.memory, "Memory");
+                ui.checkbox(&mut self.panels.breakpoints, "Breakpoints");
+                ui.checkbox(&mut self.panels.watches, "Watches");
+            });
+
+            ui.separator();
+
+            let run_label = if self.debugger.running { "Pause" } else { "Run" };
+            if ui.button(run_label).clicked() {
+                self.debugger.running = !self.debugger.running;
+                self.status.clear();
+            }
+            if ui.add_enabled(!self.debugger.running, egui::Button::new("Step")).clicked() {
+                self.step();
+            }
+            if ui.button("Reset").clicked() {
+                self.cpu.reset();
+            }
+            if ui.button("Undo").clicked() {
+                self.debugger.undo(&mut self.cpu);
+            }
+            if ui.button("Redo").clicked() {
+                self.debugger.redo(&mut self.cpu);
+            }
+
+            ui.separator();
+            ui.label(self.status.as_str());
+        });
+    }
+
+    fn registers(&mut self, ui: &mut egui::Ui) {
+        egui::Grid::new("registers").num_columns(2).show(ui, |ui| {
+            for reg in [Register::A, Register::X, Register::Y, Register::SP, Register::PC, Register::STATUS] {
+                let digits = if reg == Register::PC { 4 } else { 2 };
+                let mut value = reg.get(&self.cpu);
+
+                ui.monospace(reg.name().to_ascii_uppercase());
+                let drag = egui::DragValue::new(&mut value).hexadecimal(digits, false, true).clamp_range(0..=if digits == 4 { 0xFFFF } else { 0xFF });
+                if ui.add(drag).changed() {
+                    self.debugger.set_register(&mut self.cpu, reg, value);
+                }
+                ui.end_row();
+            }
+        });
+
+        ui.separator();
+
+        let flags = [
+            ("N", FLAGS6502::N),
+            ("V", FLAGS6502::V),
+            ("U", FLAGS6502::U),
+            ("B", FLAGS6502::B),
+            ("D", FLAGS6502::D),
+            ("I", FLAGS6502::I),
+            ("Z", FLAGS6502::Z),
+            ("C", FLAGS6502::C),
+        ];
+        ui.horizontal(|ui| {
+            for (name, flag) in flags {
+                let set = self.cpu.flag(flag);
+                let color = if set { egui::Color32::LIGHT_GREEN } else { egui::Color32::DARK_GRAY };
+                ui.colored_label(color, egui::RichText::new(name).monospace());
+            }
+        });
+
+        ui.monospace(std::format!("cycle {}", self.cpu.clock_count()));
+    }
+
+    fn disassembly(&mut self, ui: &mut egui::Ui) {
+        let pc = self.cpu.pc;
+        let lines = self.code.around(&self.cpu, pc, LINES_BEFORE_PC, LINES_AFTER_PC, &self.symbols);
+
+        ui.label("Click a line to toggle a breakpoint");
+        for (addr, line) in lines {
+            let marker = if self.debugger.is_breakpoint(addr) { "●" } else { " " };
+            let text = egui::RichText::new(std::format!("{} {}", marker, line)).monospace();
+            let text = if addr == pc { text.color(egui::Color32::YELLOW) } else { text };
+
+            if ui.selectable_label(addr == pc, text).clicked() && !self.debugger.breakpoints.remove(&addr) {
+                self.debugger.breakpoints.insert(addr);
+            }
+        }
+    }
+
+    fn memory(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            ui.label("Address");
+            ui.text_edit_singleline(&mut self.memory_addr);
+        });
+
+        let start = match self.eval(&self.memory_addr) {
+            Ok(addr) => (addr as u16) & 0xFFF0,
+            Err(e) => {
+                ui.colored_label(egui::Color32::RED, e);
+                return;
+            }
+        };
+
+        let bus = self.cpu.bus.borrow();
+        for row in 0..MEMORY_ROWS {
+            let addr = start.wrapping_add(row * 16);
+            let bytes: Vec<u8> = (0..16).map(|i| bus.read(addr.wrapping_add(i), true)).collect();
+            let hex: Vec<String> = bytes.iter().map(|b| std::format!("{:02x}", b)).collect();
+            let text: String = bytes.iter().map(|&b| if b.is_ascii_graphic() || b == b' ' { b as char } else { '.' }).collect();
+            ui.monospace(std::format!("{:04x}  {}  {}", addr, hex.join(" "), text));
+        }
+    }
+
+    fn breakpoints(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            ui.text_edit_singleline(&mut self.new_breakpoint);
+            if ui.button("Add").clicked() {
+                match self.eval(&self.new_breakpoint) {
+                    Ok(addr) => {
+                        self.debugger.breakpoints.insert(addr as u16);
+                        self.new_breakpoint.clear();
+                    }
+                    Err(e) => self.status = e,
+                }
+            }
+        });
+
+        let mut removed = None;
+        for &addr in &self.debugger.breakpoints {
+            ui.horizontal(|ui| {
+                let name = self.symbols.name_of(addr).map(|name| std::format!(" {}", name)).unwrap_or_default();
+                ui.monospace(std::format!("${:04x}{}", addr, name));
+                if ui.small_button("x").clicked() {
+                    removed = Some(addr);
+                }
+            });
+        }
+        if let Some(addr) = removed {
+            self.debugger.breakpoints.remove(&addr);
+        }
+    }
+
+    fn watches(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            ui.text_edit_singleline(&mut self.new_watch);
+            if ui.button("Watch").clicked() && !self.new_watch.trim().is_empty() {
+                self.debugger.watches.push(std::mem::take(&mut self.new_watch));
+            }
+        });
+
+        let mut removed = None;
+        for (i, watch) in self.debugger.watches.iter().enumerate() {
+            ui.horizontal(|ui| {
+                let value = match self.eval(watch) {
+                    Ok(value) => std::format!("${:x} ({})", value, value),
+                    Err(e) => e,
+                };
+                ui.monospace(std::format!("{} = {}", watch, value));
+                if ui.small_button("x").clicked() {
+                    removed = Some(i);
+                }
+            });
+        }
+        if let Some(i) = removed {
+            self.debugger.watches.remove(i);
+        }
+    }
+}
+
+impl eframe::App for App {
+    fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+        if self.debugger.running {
+            self.run_frame();
+            ctx.request_repaint();
+        }
+
+        egui::TopBottomPanel::top("controls").show(ctx, |ui| self.controls(ui));
+
+        let mut panels = std::mem::replace(&mut self.panels, Panels { registers: false, disassembly: false, memory: false, breakpoints: false, watches: false });
+        egui::Window::new("Registers").open(&mut panels.registers).show(ctx, |ui| self.registers(ui));
+        egui::Window::new("Disassembly").open(&mut panels.disassembly).show(ctx, |ui| self.disassembly(ui));
+        egui::Window::new("Memory").open(&mut panels.memory).show(ctx, |ui| self.memory(ui));
+        egui::Window::new("Breakpoints").open(&mut panels.breakpoints).show(ctx, |ui| self.breakpoints(ui));
+        egui::Window::new("Watches").open(&mut panels.watches).show(ctx, |ui| self.watches(ui));
+        self.panels = panels;
+
+        self.save_project();
+    }
+}
+
+fn main() -> eframe::Result<()> {
+    let mut program = None;
+    let mut org = None;
+    let mut symbols = SymbolTable::new();
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--org" => org = args.next().map(|s| u16::from_str_radix(s.trim_start_matches('$'), 16).expect("--org takes a hex address")),
+            "--symbols" => symbols.load_file(&args.next().expect("--symbols takes a file")).expect("failed to load symbols"),
+            _ => program = Some(arg),
+        }
+    }
+
+    let project_path = program.as_ref().map(Project::path_for_rom);
+    let project = match project_path.as_ref().filter(|path| path.exists()) {
+        Some(path) => Project::load(path).expect("failed to load project"),
+        None => Project::new(),
+    };
+    let project_dir = project_path.as_ref().and_then(|path| path.parent()).map(Path::to_path_buf).unwrap_or_default();
+    for path in &project.symbols {
+        symbols.load_file(project_dir.join(path)).expect("failed to load symbols");
+    }
+
+    let mut cpu = cpu6502::new();
+    let status = match program {
+        Some(path) => {
+            let report = cpu.load_any(&path, org).expect("failed to load program");
+            if !report.sets_reset_vector() {
+                cpu.set_reset_vector(report.entry);
+            }
+            cpu.reset();
+            report.describe().join(", ")
+        }
+        None => {
+            let demo = &demos::DEMOS[0];
+            demo.load(&mut cpu).expect("built-in demo assembles");
+            std::format!("demo {}: {}", demo.name, demo.description)
+        }
+    };
+
+    let options = eframe::NativeOptions {
+        viewport: egui::ViewportBuilder::default().with_inner_size([1100.0, 720.0]),
+        ..Default::default()
+    };
+    eframe::run_native("crust 6502", options, Box::new(|_cc| Box::new(App::new(cpu, symbols, status, project_path, project))))
+}
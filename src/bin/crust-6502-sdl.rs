@@ -0,0 +1,165 @@
+// A plain player window on SDL2, for running programs rather than
+// debugging them: the picture scaled up by a whole number to fill the
+// window, with pixels as wide as the machine drew them, paced by vsync and
+// played with a keyboard or a gamepad. A 2600 cartridge shows the TIA's
+// picture, anything else the whole address space a pixel per byte.
+//
+//   cargo run --features sdl2 --bin crust-6502-sdl -- [program] [--org <addr>] [--scale <n>] [--no-vsync]
+//
+// Arrows or the d-pad/left stick are the joystick, Enter, Space or the A
+// button its fire button. R resets, F11 toggles fullscreen, Esc quits.
+
+use std::time::{Duration, Instant};
+
+use sdl2::controller::{Axis, Button, GameController};
+use sdl2::event::Event;
+use sdl2::keyboard::{Keycode, Scancode};
+use sdl2::pixels::{Color, PixelFormatEnum};
+use sdl2::rect::Rect;
+use sdl2::video::FullscreenType;
+
+use crust_6502_emulator::cpu6502;
+use crust_6502_emulator::demos;
+use crust_6502_emulator::frame::InputState;
+use crust_6502_emulator::machine::Machine;
+
+// Frames run without vsync
+const FRAME_INTERVAL: Duration = Duration::from_micros(16_667);
+
+// Stick deflection taken as a direction
+const STICK_DEAD_ZONE: i16 = 12_000;
+
+// The biggest whole number scale that fits, centred
+fn placement(window: (u32, u32), picture: (u32, u32), aspect: u32) -> Rect {
+    let (width, height) = (picture.0 * aspect, picture.1);
+    let scale = (window.0 / width).min(window.1 / height).max(1);
+    let (w, h) = (width * scale, height * scale);
+    Rect::new((window.0 as i32 - w as i32) / 2, (window.1 as i32 - h as i32) / 2, w, h)
+}
+
+// The joystick from the arrows, Enter and Space, and any pads
+fn read_input(keys: &sdl2::keyboard::KeyboardState, pads: &[GameController]) -> InputState {
+    let pad = |button: Button| pads.iter().any(|pad| pad.button(button));
+    let stick = |axis: Axis, sign: i16| pads.iter().any(|pad| pad.axis(axis).saturating_mul(sign) > STICK_DEAD_ZONE);
+
+    InputState {
+        up: keys.is_scancode_pressed(Scancode::Up) || pad(Button::DPadUp) || stick(Axis::LeftY, -1),
+        down: keys.is_scancode_pressed(Scancode::Down) || pad(Button::DPadDown) || stick(Axis::LeftY, 1),
+        left: keys.is_scancode_pressed(Scancode::Left) || pad(Button::DPadLeft) || stick(Axis::LeftX, -1),
+        right: keys.is_scancode_pressed(Scancode::Right) || pad(Button::DPadRight) || stick(Axis::LeftX, 1),
+        fire: keys.is_scancode_pressed(Scancode::Return) || keys.is_scancode_pressed(Scancode::Space) || pad(Button::A),
+        key: None,
+    }
+}
+
+fn main() -> Result<(), String> {
+    let mut program = None;
+    let mut org = None;
+    let mut scale = 3;
+    let mut vsync = true;
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--org" => {
+                let addr = args.next().and_then(|s| u16::from_str_radix(s.trim_start_matches('$'), 16).ok());
+                org = Some(addr.ok_or("--org takes a hex address")?);
+            }
+            "--scale" => scale = args.next().and_then(|s| s.parse().ok()).ok_or("--scale takes a number")?,
+            "--no-vsync" => vsync = false,
+            _ => program = Some(arg),
+        }
+    }
+
+    let mut cpu = cpu6502::new();
+    match program {
+        Some(path) => {
+            let report = cpu.load_any(&path, org).map_err(|e| e.to_string())?;
+            if !report.sets_reset_vector() {
+                cpu.set_reset_vector(report.entry);
+            }
+        }
+        None => {
+            demos::DEMOS[0].load(&mut cpu)?;
+        }
+    }
+    cpu.reset();
+    let mut machine = Machine::from_cpu(cpu);
+    let picture = machine.framebuffer();
+    let (mut width, mut height, aspect) = (picture.width as u32, picture.height as u32, picture.pixel_aspect as u32);
+
+    let sdl = sdl2::init()?;
+    let video = sdl.video()?;
+    let game_controllers = sdl.game_controller()?;
+
+    let window = video
+        .window("crust 6502", width * aspect * scale, height * scale)
+        .position_centered()
+        .resizable()
+        .build()
+        .map_err(|e| e.to_string())?;
+    let mut canvas = window.into_canvas().accelerated();
+    if vsync {
+        canvas = canvas.present_vsync();
+    }
+    let mut canvas = canvas.build().map_err(|e| e.to_string())?;
+    let texture_creator = canvas.texture_creator();
+    let mut texture = texture_creator.create_texture_streaming(PixelFormatEnum::ARGB8888, width, height).map_err(|e| e.to_string())?;
+
+    let mut events = sdl.event_pump()?;
+    let mut pads: Vec<GameController> = Vec::new();
+    let mut next_frame = Instant::now();
+
+    'running: loop {
+        for event in events.poll_iter() {
+            match event {
+                Event::Quit { .. } | Event::KeyDown { keycode: Some(Keycode::Escape), .. } => break 'running,
+                Event::KeyDown { keycode: Some(Keycode::R), repeat: false, .. } => machine.reset(),
+                Event::KeyDown { keycode: Some(Keycode::F11), repeat: false, .. } => {
+                    let window = canvas.window_mut();
+                    let fullscreen = match window.fullscreen_state() {
+                        FullscreenType::Off => FullscreenType::Desktop,
+                        _ => FullscreenType::Off,
+                    };
+                    window.set_fullscreen(fullscreen)?;
+                }
+                Event::ControllerDeviceAdded { which, .. } => {
+                    if let Ok(pad) = game_controllers.open(which) {
+                        pads.push(pad);
+                    }
+                }
+                Event::ControllerDeviceRemoved { which, .. } => pads.retain(|pad| pad.instance_id() != which),
+                _ => (),
+            }
+        }
+
+        let frame = machine.run_frame(&read_input(&events.keyboard_state(), &pads));
+        let picture = &frame.framebuffer;
+
+        // A different machine or video mode can change the picture's size
+        if (picture.width as u32, picture.height as u32) != (width, height) {
+            (width, height) = (picture.width as u32, picture.height as u32);
+            texture = texture_creator.create_texture_streaming(PixelFormatEnum::ARGB8888, width, height).map_err(|e| e.to_string())?;
+        }
+
+        let pixels: Vec<u8> = picture.pixels.iter().flat_map(|pixel| pixel.to_ne_bytes()).collect();
+        texture.update(None, &pixels, width as usize * 4).map_err(|e| e.to_string())?;
+
+        let place = placement(canvas.output_size()?, (width, height), picture.pixel_aspect as u32);
+        canvas.set_draw_color(Color::RGB(0, 0, 0));
+        canvas.clear();
+        canvas.copy(&texture, None, Some(place))?;
+        canvas.present();
+
+        // Vsync holds present() to the display's rate, otherwise wait here
+        if !vsync {
+            next_frame += FRAME_INTERVAL;
+            match next_frame.checked_duration_since(Instant::now()) {
+                Some(wait) => std::thread::sleep(wait),
+                None => next_frame = Instant::now(),
+            }
+        }
+    }
+
+    Ok(())
+}
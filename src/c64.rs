@@ -0,0 +1,161 @@
+use std::fs;
+use std::path::Path;
+
+use crate::cia::{Cia, CiaLine, CiaPort};
+use crate::cpu6502;
+use crate::device::Rom;
+use crate::error::EmuError;
+use crate::keymatrix::{KeyLayout, KeyMatrix, KeyboardPort};
+use crate::vicii::VicPort;
+
+// The C64's I/O chips, for running its KERNAL and BASIC, which are
+// Commodore's and so have to be loaded from files.
+//
+//   $A000-$BFFF  BASIC
+//   $D000-$D3FF  the VIC-II
+//   $D800-$DBFF  colour RAM
+//   $DC00-$DCFF  CIA 1, the keyboard on its ports (port A picks the rows,
+//                port B reads the columns) and the IRQ line
+//   $DD00-$DDFF  CIA 2, wired to NMI, with the VIC's bank on PA0-PA1
+//   $E000-$FFFF  the KERNAL
+//
+// Each CIA decodes A0-A3 only, so its 16 registers repeat through the page.
+// The joysticks pull port A (port 2) and port B (port 1) of CIA 1 low
+// through set_port_a and set_port_b. There's no 6510 port at $00-$01 to
+// bank the ROMs out, they stay where they are and writes under them are
+// lost.
+
+pub const BASIC_BASE: u16 = 0xA000;
+pub const VIC_BASE: u16 = 0xD000;
+pub const COLOUR_RAM_BASE: u16 = 0xD800;
+pub const CIA1_BASE: u16 = 0xDC00;
+pub const CIA2_BASE: u16 = 0xDD00;
+pub const KERNAL_BASE: u16 = 0xE000;
+
+// A PAL C64's clock
+pub const CLOCK_HZ: u64 = 985_248;
+
+// The host's end of the machine's chips
+#[derive(Clone)]
+pub struct C64Ports {
+    pub cia1: CiaPort,
+    pub cia2: CiaPort,
+    pub keyboard: KeyboardPort,
+    pub vic: VicPort,
+}
+
+// The three ROM images, 8K, 8K and 4K
+pub struct C64Roms {
+    pub basic: Vec<u8>,
+    pub kernal: Vec<u8>,
+    pub characters: Vec<u8>,
+}
+
+impl C64Roms {
+    // "basic", "kernal" and "chargen" in `dir`, as VICE names them
+    pub fn load<P: AsRef<Path>>(dir: P) -> Result<C64Roms, EmuError> {
+        let read = |name: &str| {
+            let path = dir.as_ref().join(name);
+            fs::read(&path).map_err(|e| EmuError::io(path, e))
+        };
+        Ok(C64Roms { basic: read("basic")?, kernal: read("kernal")?, characters: read("chargen")? })
+    }
+
+    fn check(&self) -> Result<(), EmuError> {
+        for (name, image, size) in [("BASIC", &self.basic, 0x2000), ("KERNAL", &self.kernal, 0x2000), ("character", &self.characters, 0x1000)] {
+            if image.len() != size {
+                return Err(EmuError::BadImage(std::format!("the {} ROM is {} bytes, not {}", name, image.len(), size)));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl cpu6502 {
+    // Maps the C64's VIC-II and CIAs, with the keyboard matrix on the
+    // first CIA using the built-in C64 layout for the host's keys
+    pub fn attach_c64(&mut self) -> C64Ports {
+        let keyboard = KeyMatrix::new(KeyLayout::c64()).port();
+
+        let mut cia1 = Cia::new(self.events(), CLOCK_HZ);
+        cia1.connect(CiaLine::Irq(self.interrupts.register_source("cia1")));
+        cia1.connect_keyboard(keyboard.clone());
+        cia1.connect_log(self.device_log().logger("cia1"));
+        let mut cia2 = Cia::new(self.events(), CLOCK_HZ);
+        cia2.connect(CiaLine::Nmi);
+        cia2.connect_log(self.device_log().logger("cia2"));
+
+        let vic = self.attach_vic(VIC_BASE, COLOUR_RAM_BASE);
+        vic.connect_bank_select(cia2.port());
+
+        let ports = C64Ports { cia1: cia1.port(), cia2: cia2.port(), keyboard, vic };
+        let mut bus = self.bus.borrow_mut();
+        bus.map_mirrored("cia1", CIA1_BASE, CIA1_BASE + 0xFF, 16, Box::new(cia1));
+        bus.map_mirrored("cia2", CIA2_BASE, CIA2_BASE + 0xFF, 16, Box::new(cia2));
+        ports
+    }
+
+    // Puts BASIC and the KERNAL over RAM and gives the VIC-II the
+    // character ROM. Reset afterwards to boot.
+    pub fn load_c64_roms(&mut self, roms: &C64Roms, ports: &C64Ports) -> Result<(), EmuError> {
+        roms.check()?;
+        let mut bus = self.bus.borrow_mut();
+        bus.map("basic", BASIC_BASE, BASIC_BASE + 0x1FFF, Box::new(Rom::new(&roms.basic)));
+        bus.map("kernal", KERNAL_BASE, 0xFFFF, Box::new(Rom::new(&roms.kernal)));
+        ports.vic.set_character_rom(&roms.characters);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::assemble;
+
+    #[test]
+    fn kernal_style_keyboard_scan() {
+        // Timer A interrupting every 1/60s, as the KERNAL sets it up, and
+        // an IRQ handler that scans row 1 into $10
+        let source = "
+            .org $8000
+            reset:  LDA #$95
+                    STA $DC04
+                    LDA #$42
+                    STA $DC05
+                    LDA #$81
+                    STA $DC0D
+                    LDA #$11
+                    STA $DC0E
+                    LDA #$FF
+                    STA $DC02
+                    CLI
+            loop:   JMP loop
+            .org $9000
+            irq:    LDA #$FD
+                    STA $DC00
+                    LDA $DC01
+                    STA $10
+                    INC $11
+                    LDA $DC0D
+                    RTI
+        ";
+        let mut cpu = cpu6502::new();
+        let program = assemble(source).unwrap();
+        cpu.load_program(&program.bytes, program.origin);
+        cpu.set_reset_vector(0x8000);
+        cpu.set_irq_vector(0x9000);
+        let ports = cpu.attach_c64();
+        cpu.reset();
+
+        ports.keyboard.set_pressed(&["A"]);
+        for _ in 0..CLOCK_HZ / 60 * 3 {
+            cpu.clock();
+        }
+
+        let bus = cpu.bus.borrow();
+        assert_eq!(bus.read(0x10, true), !0x04);
+        assert!((2..=3).contains(&bus.read(0x11, true)));
+        // The page repeats the registers
+        assert_eq!(bus.read(0xDC12, true), 0xFF);
+    }
+}
@@ -0,0 +1,198 @@
+use crate::cpu6502;
+use crate::symbols::SymbolTable;
+
+// The calls the program is in the middle of, followed as JSRs and
+// interrupts happen so the debugger can say how it got where it is. It's
+// kept all the time, unlike the shadow stack, and never complains: it only
+// has to be a good guess.
+//
+// Programs do all sorts with their stacks, so rather than wait for the RTS
+// that matches a call, a call is forgotten as soon as SP moves up past its
+// return address. That covers the RTS and RTI themselves, PLA PLA to throw
+// a return away and TXS to start again, while an address pushed for an RTS
+// to jump to leaves the calls below it alone. A routine that changes its
+// own return address, to step over inline data, keeps its frame but shows
+// up as altered.
+
+// Past this the oldest calls are dropped, recursion that deep has run off
+// the stack anyway
+const MAX_DEPTH: usize = 256;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CallKind {
+    Jsr,
+    Irq,
+    Nmi,
+    Brk,
+}
+
+impl CallKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            CallKind::Jsr => "JSR",
+            CallKind::Irq => "IRQ",
+            CallKind::Nmi => "NMI",
+            CallKind::Brk => "BRK",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Call {
+    pub kind: CallKind,
+    // The JSR or BRK, or the instruction an IRQ or NMI came in ahead of
+    pub site: u16,
+    // The subroutine or handler called
+    pub entry: u16,
+    pub return_to: u16,
+    // Stack pointer once the return address (and status) were pushed
+    pub stkp: u8,
+}
+
+impl Call {
+    // Where the return address is on the stack, and what should be there
+    fn pushed(&self) -> (u16, u16) {
+        match self.kind {
+            CallKind::Jsr => (0x0100 + self.stkp as u16 + 1, self.return_to.wrapping_sub(1)),
+            _ => (0x0100 + self.stkp as u16 + 2, self.return_to),
+        }
+    }
+
+    // Whether the return address on the stack is still the one pushed
+    pub fn is_intact(&self, cpu: &cpu6502) -> bool {
+        let (addr, expected) = self.pushed();
+        let bus = cpu.bus.borrow();
+        let word = bus.read(addr, true) as u16 | (bus.read(addr.wrapping_add(1), true) as u16) << 8;
+        word == expected
+    }
+
+    // "JSR sub_8010 from $8000, ret $8003"
+    pub fn describe(&self, symbols: &SymbolTable) -> String {
+        let label = |addr: u16| match symbols.name_of(addr) {
+            Some(name) => name.to_string(),
+            None => std::format!("${:04x}", addr),
+        };
+        std::format!("{} {} from {}, ret {}", self.kind.name(), label(self.entry), label(self.site), label(self.return_to))
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct CallStack {
+    calls: Vec<Call>,
+}
+
+impl CallStack {
+    pub fn new() -> Self {
+        CallStack::default()
+    }
+
+    pub fn clear(&mut self) {
+        self.calls.clear();
+    }
+
+    // Outermost first
+    pub fn calls(&self) -> &[Call] {
+        &self.calls
+    }
+
+    pub fn push(&mut self, call: Call) {
+        if self.calls.len() == MAX_DEPTH {
+            self.calls.remove(0);
+        }
+        self.calls.push(call);
+    }
+
+    // After every instruction, with the instruction's address, where it
+    // left PC and SP
+    pub fn on_instruction(&mut self, opcode: u8, pc: u16, new_pc: u16, new_stkp: u8) {
+        while self.calls.last().is_some_and(|call| call.stkp < new_stkp) {
+            self.calls.pop();
+        }
+
+        if opcode == 0x20 {
+            self.push(Call { kind: CallKind::Jsr, site: pc, entry: new_pc, return_to: pc.wrapping_add(3), stkp: new_stkp });
+        }
+    }
+}
+
+impl cpu6502 {
+    // Innermost first, the way a debugger shows them
+    pub fn backtrace(&self) -> Vec<Call> {
+        self.call_stack.calls().iter().rev().copied().collect()
+    }
+
+    // Called once an interrupt or BRK has pushed PC and status and PC is
+    // at the handler
+    pub(crate) fn enter_interrupt(&mut self, kind: CallKind) {
+        let bus = self.bus.borrow();
+        let at = 0x0100 + self.stkp as u16 + 2;
+        let return_to = bus.read(at, true) as u16 | (bus.read(at.wrapping_add(1), true) as u16) << 8;
+        drop(bus);
+
+        // BRK's return address skips its padding byte
+        let site = if kind == CallKind::Brk { return_to.wrapping_sub(2) } else { return_to };
+        self.call_stack.push(Call { kind, site, entry: self.pc, return_to, stkp: self.stkp });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // run_instructions stops in front of a BRK, this goes through it
+    fn step(cpu: &mut cpu6502, count: usize) {
+        for _ in 0..count {
+            cpu.clock();
+            while !cpu.complete() {
+                cpu.clock();
+            }
+        }
+    }
+
+    #[test]
+    fn follows_calls_interrupts_and_stack_tricks() {
+        let mut cpu = cpu6502::new();
+        // main: JSR outer / loop: JMP loop
+        // outer: JSR inner / PLA / PLA / JMP loop
+        // inner: LDA #$90 / PHA / LDA #$0f / PHA / BRK / NOP / RTS
+        // $9010: RTS, which the pushed $900f sends to from inner
+        cpu.load_program(&[0x20, 0x06, 0x80, 0x4C, 0x03, 0x80], 0x8000);
+        cpu.load_program(&[0x20, 0x0E, 0x80, 0x68, 0x68, 0x4C, 0x03, 0x80], 0x8006);
+        cpu.load_program(&[0xA9, 0x90, 0x48, 0xA9, 0x0F, 0x48, 0x00, 0xEA, 0xEA, 0x60], 0x800E);
+        cpu.load_program(&[0x40], 0x9000);
+        cpu.load_program(&[0x60], 0x9010);
+        cpu.bus.borrow_mut().write(0xFFFE, 0x00);
+        cpu.bus.borrow_mut().write(0xFFFF, 0x90);
+        cpu.set_reset_vector(0x8000);
+        cpu.reset();
+        // The reset's own cycles
+        step(&mut cpu, 1);
+
+        // Into inner, past its pushes and into the BRK handler
+        step(&mut cpu, 7);
+        let mut symbols = SymbolTable::new();
+        symbols.insert("inner", 0x800E);
+        let trace: Vec<String> = cpu.backtrace().iter().map(|call| call.describe(&symbols)).collect();
+        assert_eq!(trace, ["BRK $9000 from $8014, ret $8016", "JSR inner from $8006, ret $8009", "JSR $8006 from $8000, ret $8003"]);
+        assert!(cpu.backtrace().iter().all(|call| call.is_intact(&cpu)));
+
+        // RTI, NOP, then the RTS that jumps to $9010 doesn't touch the calls
+        step(&mut cpu, 2);
+        assert_eq!(cpu.backtrace().len(), 2);
+        step(&mut cpu, 1);
+        assert_eq!((cpu.pc, cpu.backtrace().len()), (0x9010, 2));
+
+        // $9010's RTS returns from inner, then outer throws its own return
+        // address away with PLA PLA
+        step(&mut cpu, 1);
+        assert_eq!((cpu.pc, cpu.backtrace().len()), (0x8009, 1));
+        step(&mut cpu, 2);
+        assert!(cpu.backtrace().is_empty());
+
+        // A return address written over shows
+        cpu.reset();
+        step(&mut cpu, 2);
+        cpu.bus.borrow_mut().write(0x01FC, 0x20);
+        assert!(!cpu.backtrace()[0].is_intact(&cpu));
+    }
+}
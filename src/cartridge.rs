@@ -0,0 +1,694 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::cpu6502;
+use crate::error::EmuError;
+use crate::snapshot::{Snapshot, StateReader, StateWriter};
+
+// What a mapper decided to do with an address the CPU or PPU put on the bus
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MapResult {
+    // The cartridge does not respond to this address
+    Unmapped,
+    // Offset into PRG memory (CPU side) or CHR memory (PPU side)
+    Offset(usize),
+    // Offset into the cartridge's PRG RAM, battery backed or not. Boards
+    // without any leave the address to whatever else is on the bus.
+    Ram(usize),
+    // The mapper serviced the access itself (bank registers, coprocessors...)
+    Handled(u8),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Mirror {
+    Horizontal,
+    Vertical,
+    OneScreenLo,
+    OneScreenHi,
+}
+
+// A mapper translates addresses on the CPU and PPU buses into the
+// cartridge's own memory. Implement this to support additional boards
+// and register the implementation with a MapperRegistry. The cartridge
+// lives on the bus, which can be shared between threads.
+pub trait Mapper: Send + Sync {
+    fn cpu_map_read(&self, addr: u16) -> MapResult;
+    fn cpu_map_write(&mut self, addr: u16, data: u8) -> MapResult;
+    fn ppu_map_read(&self, addr: u16) -> MapResult;
+    fn ppu_map_write(&mut self, addr: u16, data: u8) -> MapResult;
+
+    fn reset(&mut self) {}
+
+    // Mappers that control nametable mirroring can override the header
+    fn mirror(&self) -> Option<Mirror> {
+        None
+    }
+
+    // Bank registers and anything else a snapshot needs to bring the board
+    // back, the same contract as snapshot::Snapshot. `version` is the
+    // STATE_VERSION the data was written with. Boards without state can
+    // keep the defaults.
+    fn save_state(&self) -> Vec<u8> {
+        Vec::new()
+    }
+
+    fn load_state(&mut self, _data: &[u8], _version: u32) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+// The TV system a cartridge was made for
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TvRegion {
+    Ntsc,
+    Pal,
+    // Runs on either
+    Multi,
+    Dendy,
+}
+
+impl TvRegion {
+    pub fn name(&self) -> &'static str {
+        match self {
+            TvRegion::Ntsc => "NTSC",
+            TvRegion::Pal => "PAL",
+            TvRegion::Multi => "NTSC/PAL",
+            TvRegion::Dendy => "Dendy",
+        }
+    }
+}
+
+// Everything the iNES header says about a cartridge, handed to mapper
+// factories so boards can look at more than the bank counts. NES 2.0
+// headers fill in the sizes exactly, for plain iNES they're the usual
+// guesses.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CartridgeHeader {
+    pub mapper_id: u16,
+    // Bank counts in the units iNES uses, 16K and 8K. Sizes NES 2.0 gives
+    // that aren't whole banks round down, and counts stop at 255.
+    pub prg_banks: u8,
+    pub chr_banks: u8,
+    pub mirror: Mirror,
+    // Battery backed PRG RAM at $6000-$7FFF
+    pub battery: bool,
+    pub trainer: bool,
+    pub nes2: bool,
+    pub submapper: u8,
+    // In bytes
+    pub prg_rom_size: usize,
+    pub chr_rom_size: usize,
+    pub prg_ram_size: usize,
+    // The part of PRG RAM kept by the battery
+    pub prg_nvram_size: usize,
+    pub chr_ram_size: usize,
+    pub region: TvRegion,
+    pub raw: [u8; 16],
+}
+
+impl CartridgeHeader {
+    pub fn parse(bytes: &[u8]) -> Result<CartridgeHeader, EmuError> {
+        if bytes.len() < 16 || &bytes[0..4] != b"NES\x1A" {
+            return Err(EmuError::BadHeader("not an iNES image".to_string()));
+        }
+
+        let mapper1 = bytes[6];
+        let mapper2 = bytes[7];
+
+        let mut raw = [0u8; 16];
+        raw.copy_from_slice(&bytes[0..16]);
+
+        let battery = mapper1 & 0x02 != 0;
+        let nes2 = mapper2 & 0x0C == 0x08;
+        let mut mapper_id = (((mapper2 >> 4) << 4) | (mapper1 >> 4)) as u16;
+
+        let (submapper, prg_rom_size, chr_rom_size, prg_ram_size, prg_nvram_size, chr_ram_size, region) = if nes2 {
+            mapper_id |= ((bytes[8] & 0x0F) as u16) << 8;
+            let region = match bytes[12] & 0x03 {
+                0 => TvRegion::Ntsc,
+                1 => TvRegion::Pal,
+                2 => TvRegion::Multi,
+                _ => TvRegion::Dendy,
+            };
+
+            (
+                bytes[8] >> 4,
+                rom_size(bytes[4], bytes[9] & 0x0F, 16384),
+                rom_size(bytes[5], bytes[9] >> 4, 8192),
+                shift_size(bytes[10] & 0x0F),
+                shift_size(bytes[10] >> 4),
+                shift_size(bytes[11] & 0x0F),
+                region,
+            )
+        } else {
+            // Byte 8 is PRG RAM in 8K units, 0 meaning 8K, but only a battery
+            // says the board really has its own rather than leaving $6000
+            // to the rest of the machine
+            let prg_ram = if battery { bytes[8].max(1) as usize * 8192 } else { 0 };
+            let chr_ram = if bytes[5] == 0 { 8192 } else { 0 };
+            let region = if bytes[9] & 0x01 != 0 { TvRegion::Pal } else { TvRegion::Ntsc };
+
+            (0, bytes[4] as usize * 16384, bytes[5] as usize * 8192, prg_ram, prg_ram, chr_ram, region)
+        };
+
+        Ok(CartridgeHeader {
+            mapper_id,
+            prg_banks: (prg_rom_size / 16384).min(255) as u8,
+            chr_banks: (chr_rom_size / 8192).min(255) as u8,
+            mirror: if mapper1 & 0x01 != 0 { Mirror::Vertical } else { Mirror::Horizontal },
+            battery,
+            trainer: mapper1 & 0x04 != 0,
+            nes2,
+            submapper,
+            prg_rom_size,
+            chr_rom_size,
+            prg_ram_size,
+            prg_nvram_size,
+            chr_ram_size,
+            region,
+            raw,
+        })
+    }
+
+    // Where PRG ROM starts, past the header and any trainer
+    pub fn data_offset(&self) -> usize {
+        if self.trainer { 16 + 512 } else { 16 }
+    }
+}
+
+// A NES 2.0 ROM size: a 12 bit count of `unit`s, or when the top nibble
+// is $F an exponent and multiplier packed into the low byte
+fn rom_size(lsb: u8, msb: u8, unit: usize) -> usize {
+    if msb == 0x0F {
+        (1usize << (lsb >> 2)) * ((lsb & 0x03) as usize * 2 + 1)
+    } else {
+        (((msb as usize) << 8) | lsb as usize) * unit
+    }
+}
+
+// A NES 2.0 RAM size, 64 << n bytes with 0 meaning none
+fn shift_size(n: u8) -> usize {
+    if n == 0 { 0 } else { 64 << n }
+}
+
+pub type MapperFactory = Box<dyn Fn(&CartridgeHeader) -> Box<dyn Mapper>>;
+
+// Maps iNES mapper numbers to constructors. The loader consults it when a
+// cartridge is opened, so new mappers can be plugged in from outside.
+pub struct MapperRegistry {
+    factories: HashMap<u16, MapperFactory>,
+}
+
+impl MapperRegistry {
+    pub fn new() -> Self {
+        let mut registry = MapperRegistry {
+            factories: HashMap::new(),
+        };
+
+        registry.register(0, |header| Box::new(Mapper000::new(header.prg_banks, header.chr_banks)));
+
+        registry
+    }
+
+    pub fn register<F>(&mut self, mapper_id: u16, factory: F)
+        where F: Fn(&CartridgeHeader) -> Box<dyn Mapper> + 'static
+    {
+        self.factories.insert(mapper_id, Box::new(factory));
+    }
+
+    pub fn is_registered(&self, mapper_id: u16) -> bool {
+        self.factories.contains_key(&mapper_id)
+    }
+
+    pub fn create(&self, header: &CartridgeHeader) -> Option<Box<dyn Mapper>> {
+        self.factories.get(&header.mapper_id).map(|factory| factory(header))
+    }
+}
+
+// NROM, no bank switching. 16K carts are mirrored into $C000-$FFFF.
+pub struct Mapper000 {
+    prg_banks: u8,
+    chr_banks: u8,
+}
+
+impl Mapper000 {
+    pub fn new(prg_banks: u8, chr_banks: u8) -> Self {
+        Mapper000 { prg_banks, chr_banks }
+    }
+}
+
+impl Mapper for Mapper000 {
+    fn cpu_map_read(&self, addr: u16) -> MapResult {
+        if addr >= 0x8000 {
+            let mask = if self.prg_banks > 1 { 0x7FFF } else { 0x3FFF };
+            MapResult::Offset((addr & mask) as usize)
+        } else if addr >= 0x6000 {
+            MapResult::Ram((addr & 0x1FFF) as usize)
+        } else {
+            MapResult::Unmapped
+        }
+    }
+
+    fn cpu_map_write(&mut self, addr: u16, _data: u8) -> MapResult {
+        // Writing to ROM does nothing, but the cartridge still claims the address
+        if addr >= 0x8000 {
+            let mask = if self.prg_banks > 1 { 0x7FFF } else { 0x3FFF };
+            MapResult::Offset((addr & mask) as usize)
+        } else if addr >= 0x6000 {
+            MapResult::Ram((addr & 0x1FFF) as usize)
+        } else {
+            MapResult::Unmapped
+        }
+    }
+
+    fn ppu_map_read(&self, addr: u16) -> MapResult {
+        if addr <= 0x1FFF {
+            MapResult::Offset(addr as usize)
+        } else {
+            MapResult::Unmapped
+        }
+    }
+
+    fn ppu_map_write(&mut self, addr: u16, _data: u8) -> MapResult {
+        // Only writable when the board carries CHR RAM instead of ROM
+        if addr <= 0x1FFF && self.chr_banks == 0 {
+            MapResult::Offset(addr as usize)
+        } else {
+            MapResult::Unmapped
+        }
+    }
+}
+
+pub struct Cartridge {
+    header: CartridgeHeader,
+    prg_memory: Vec<u8>,
+    chr_memory: Vec<u8>,
+    chr_ram: bool,
+    prg_ram: Vec<u8>,
+    // Where battery RAM is kept between runs, and whether it's changed
+    // since it was last written there
+    save_path: Option<PathBuf>,
+    save_dirty: bool,
+    mapper_id: u16,
+    prg_banks: u8,
+    chr_banks: u8,
+    mirror: Mirror,
+    mapper: Box<dyn Mapper>,
+}
+
+impl Cartridge {
+    pub fn from_file<P: AsRef<Path>>(path: P, registry: &MapperRegistry) -> Result<Cartridge, EmuError> {
+        let path = path.as_ref();
+        let bytes = fs::read(path).map_err(|e| EmuError::io(path, e))?;
+
+        Cartridge::from_bytes(&bytes, registry)
+    }
+
+    pub fn from_bytes(bytes: &[u8], registry: &MapperRegistry) -> Result<Cartridge, EmuError> {
+        Cartridge::from_header(CartridgeHeader::parse(bytes)?, bytes, registry)
+    }
+
+    // The image with a header other than its own, one corrected from a ROM
+    // database say
+    pub fn from_header(header: CartridgeHeader, bytes: &[u8], registry: &MapperRegistry) -> Result<Cartridge, EmuError> {
+        let prg_banks = header.prg_banks;
+        let chr_banks = header.chr_banks;
+        let mapper_id = header.mapper_id;
+        let mirror = header.mirror;
+
+        let mut offset = header.data_offset();
+
+        let prg_size = header.prg_rom_size;
+        let chr_size = header.chr_rom_size;
+
+        if bytes.len() < offset + prg_size + chr_size {
+            return Err(EmuError::BadImage("iNES image is truncated".to_string()));
+        }
+
+        let prg_memory = bytes[offset..offset + prg_size].to_vec();
+        offset += prg_size;
+
+        // No CHR ROM means the board has CHR RAM instead, 8K unless a NES
+        // 2.0 header says otherwise
+        let chr_ram = chr_size == 0;
+        let chr_memory = if chr_ram {
+            vec![0; header.chr_ram_size.max(8192)]
+        } else {
+            bytes[offset..offset + chr_size].to_vec()
+        };
+
+        let mapper = registry.create(&header).ok_or(EmuError::UnsupportedMapper(mapper_id))?;
+
+        let prg_ram = vec![0; header.prg_ram_size.max(header.prg_nvram_size)];
+
+        Ok(Cartridge {
+            header,
+            prg_memory,
+            chr_memory,
+            chr_ram,
+            prg_ram,
+            save_path: None,
+            save_dirty: false,
+            mapper_id,
+            prg_banks,
+            chr_banks,
+            mirror,
+            mapper,
+        })
+    }
+
+    pub fn cpu_read(&self, addr: u16) -> Option<u8> {
+        match self.mapper.cpu_map_read(addr) {
+            MapResult::Offset(offset) => self.prg_memory.get(offset).copied(),
+            MapResult::Ram(offset) if !self.prg_ram.is_empty() => Some(self.prg_ram[offset % self.prg_ram.len()]),
+            MapResult::Ram(_) => None,
+            MapResult::Handled(data) => Some(data),
+            MapResult::Unmapped => None,
+        }
+    }
+
+    pub fn cpu_write(&mut self, addr: u16, data: u8) -> bool {
+        match self.mapper.cpu_map_write(addr, data) {
+            MapResult::Offset(_) => true,
+            MapResult::Ram(offset) if !self.prg_ram.is_empty() => {
+                let len = self.prg_ram.len();
+                self.prg_ram[offset % len] = data;
+                self.save_dirty |= self.header.battery;
+                true
+            }
+            MapResult::Ram(_) => false,
+            MapResult::Handled(_) => true,
+            MapResult::Unmapped => false,
+        }
+    }
+
+    pub fn ppu_read(&self, addr: u16) -> Option<u8> {
+        match self.mapper.ppu_map_read(addr) {
+            MapResult::Offset(offset) => self.chr_memory.get(offset).copied(),
+            MapResult::Handled(data) => Some(data),
+            MapResult::Ram(_) | MapResult::Unmapped => None,
+        }
+    }
+
+    pub fn ppu_write(&mut self, addr: u16, data: u8) -> bool {
+        match self.mapper.ppu_map_write(addr, data) {
+            MapResult::Offset(offset) => {
+                if self.chr_ram {
+                    if let Some(byte) = self.chr_memory.get_mut(offset) {
+                        *byte = data;
+                    }
+                }
+                true
+            }
+            MapResult::Handled(_) => true,
+            MapResult::Ram(_) | MapResult::Unmapped => false,
+        }
+    }
+
+    // The .sav beside a ROM, where its battery RAM is kept
+    pub fn save_path_for<P: AsRef<Path>>(rom: P) -> PathBuf {
+        rom.as_ref().with_extension("sav")
+    }
+
+    // Keeps battery RAM in `path` from now on, starting from what's there
+    // if it exists. Does nothing for carts without a battery.
+    pub fn attach_save<P: AsRef<Path>>(&mut self, path: P) -> io::Result<()> {
+        if !self.has_battery() {
+            return Ok(());
+        }
+
+        let path = path.as_ref();
+        match fs::read(path) {
+            Ok(data) => {
+                // A save from a differently sized board still gets what fits
+                let len = data.len().min(self.prg_ram.len());
+                self.prg_ram[..len].copy_from_slice(&data[..len]);
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+
+        self.save_path = Some(path.to_path_buf());
+        self.save_dirty = false;
+        Ok(())
+    }
+
+    // Writes battery RAM out if it changed since the last time. Returns
+    // whether anything was written.
+    pub fn flush_save(&mut self) -> io::Result<bool> {
+        match &self.save_path {
+            Some(path) if self.save_dirty => {
+                fs::write(path, &self.prg_ram)?;
+                self.save_dirty = false;
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    pub fn has_battery(&self) -> bool {
+        self.header.battery && !self.prg_ram.is_empty()
+    }
+
+    pub fn save_path(&self) -> Option<&Path> {
+        self.save_path.as_deref()
+    }
+
+    pub fn prg_ram(&self) -> &[u8] {
+        &self.prg_ram
+    }
+
+    pub fn reset(&mut self) {
+        self.mapper.reset();
+    }
+
+    pub fn mirror(&self) -> Mirror {
+        self.mapper.mirror().unwrap_or(self.mirror)
+    }
+
+    pub fn header(&self) -> &CartridgeHeader {
+        &self.header
+    }
+
+    pub fn mapper_id(&self) -> u16 {
+        self.mapper_id
+    }
+
+    pub fn prg_banks(&self) -> u8 {
+        self.prg_banks
+    }
+
+    pub fn chr_banks(&self) -> u8 {
+        self.chr_banks
+    }
+}
+
+// The ROM itself isn't saved, only CHR RAM and the mapper's registers, so
+// a snapshot has to be restored onto the same image it was taken from
+impl cpu6502 {
+    // Writes the inserted cartridge's battery RAM to its .sav if it has
+    // changed, see Cartridge::flush_save
+    pub fn flush_cartridge_save(&mut self) -> io::Result<bool> {
+        match &mut self.bus.borrow_mut().cart {
+            Some(cart) => cart.flush_save(),
+            None => Ok(false),
+        }
+    }
+}
+
+impl Snapshot for Cartridge {
+    fn save_state(&self) -> Vec<u8> {
+        let mut w = StateWriter::new();
+
+        w.u16(self.mapper_id);
+        w.u8(self.prg_banks);
+        w.u8(self.chr_banks);
+        w.bytes(if self.chr_ram { &self.chr_memory } else { &[] });
+        w.bytes(&self.mapper.save_state());
+        w.bytes(&self.prg_ram);
+
+        w.finish()
+    }
+
+    fn load_state(&mut self, data: &[u8], version: u32) -> Result<(), String> {
+        let mut r = StateReader::new(data);
+
+        if (r.u16()?, r.u8()?, r.u8()?) != (self.mapper_id, self.prg_banks, self.chr_banks) {
+            return Err("snapshot was taken with a different cartridge".to_string());
+        }
+
+        let chr = r.bytes()?;
+        if self.chr_ram && chr.len() != self.chr_memory.len() {
+            return Err("CHR RAM has the wrong size".to_string());
+        }
+
+        let mapper = r.bytes()?;
+
+        // PRG RAM joined in version 2, older images leave it as it is
+        let prg_ram = if version >= 2 { Some(r.bytes()?) } else { None };
+        if prg_ram.is_some_and(|ram| ram.len() != self.prg_ram.len()) {
+            return Err("PRG RAM has the wrong size".to_string());
+        }
+
+        // The mapper goes first, if it turns the state down nothing has changed
+        self.mapper.load_state(mapper, version)?;
+
+        if self.chr_ram {
+            self.chr_memory.copy_from_slice(chr);
+        }
+        if let Some(prg_ram) = prg_ram {
+            self.save_dirty |= self.header.battery && prg_ram != self.prg_ram.as_slice();
+            self.prg_ram.copy_from_slice(prg_ram);
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A header plus PRG banks filled with their bank number
+    fn image(prg_banks: u8, chr_banks: u8, flags6: u8) -> Vec<u8> {
+        let mut bytes = vec![b'N', b'E', b'S', 0x1A, prg_banks, chr_banks, flags6, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        if flags6 & 0x04 != 0 {
+            bytes.extend(std::iter::repeat(0xEE).take(512));
+        }
+        for bank in 0..prg_banks {
+            bytes.extend(std::iter::repeat(bank).take(16384));
+        }
+        bytes.extend(std::iter::repeat(0xCC).take(chr_banks as usize * 8192));
+        bytes
+    }
+
+    #[test]
+    fn rejects_bad_magic() {
+        let mut bytes = image(1, 1, 0);
+        bytes[3] = 0;
+        let err = Cartridge::from_bytes(&bytes, &MapperRegistry::new()).err().unwrap();
+        assert!(matches!(err, EmuError::BadHeader(_)));
+    }
+
+    #[test]
+    fn rejects_truncated_image() {
+        let mut bytes = image(2, 1, 0);
+        bytes.truncate(bytes.len() - 1);
+        let err = Cartridge::from_bytes(&bytes, &MapperRegistry::new()).err().unwrap();
+        assert!(matches!(err, EmuError::BadImage(_)));
+    }
+
+    #[test]
+    fn rejects_unregistered_mapper() {
+        let bytes = image(1, 1, 0x10);
+        let err = Cartridge::from_bytes(&bytes, &MapperRegistry::new()).err().unwrap();
+        assert!(matches!(err, EmuError::UnsupportedMapper(0x01)));
+    }
+
+    #[test]
+    fn skips_trainer() {
+        let cart = Cartridge::from_bytes(&image(1, 1, 0x04), &MapperRegistry::new()).unwrap();
+        assert!(cart.header().trainer);
+        assert_eq!(cart.cpu_read(0x8000), Some(0));
+    }
+
+    #[test]
+    fn mirrors_16k_prg() {
+        let cart = Cartridge::from_bytes(&image(1, 1, 0x01), &MapperRegistry::new()).unwrap();
+        assert_eq!(cart.cpu_read(0xC000), Some(0));
+        assert_eq!(cart.mirror(), Mirror::Vertical);
+
+        let cart = Cartridge::from_bytes(&image(2, 1, 0), &MapperRegistry::new()).unwrap();
+        assert_eq!(cart.cpu_read(0x8000), Some(0));
+        assert_eq!(cart.cpu_read(0xC000), Some(1));
+        assert_eq!(cart.cpu_read(0x6000), None);
+    }
+
+    #[test]
+    fn chr_ram_when_no_chr_rom() {
+        let mut cart = Cartridge::from_bytes(&image(1, 0, 0), &MapperRegistry::new()).unwrap();
+        assert!(cart.ppu_write(0x0010, 0x5A));
+        assert_eq!(cart.ppu_read(0x0010), Some(0x5A));
+
+        let mut cart = Cartridge::from_bytes(&image(1, 1, 0), &MapperRegistry::new()).unwrap();
+        assert!(!cart.ppu_write(0x0010, 0x5A));
+        assert_eq!(cart.ppu_read(0x0010), Some(0xCC));
+    }
+
+    #[test]
+    fn factories_see_the_header() {
+        struct Battery(bool);
+
+        impl Mapper for Battery {
+            fn cpu_map_read(&self, _addr: u16) -> MapResult {
+                MapResult::Handled(self.0 as u8)
+            }
+            fn cpu_map_write(&mut self, _addr: u16, _data: u8) -> MapResult {
+                MapResult::Unmapped
+            }
+            fn ppu_map_read(&self, _addr: u16) -> MapResult {
+                MapResult::Unmapped
+            }
+            fn ppu_map_write(&mut self, _addr: u16, _data: u8) -> MapResult {
+                MapResult::Unmapped
+            }
+        }
+
+        let mut registry = MapperRegistry::new();
+        registry.register(2, |header| Box::new(Battery(header.battery)));
+        assert!(registry.is_registered(2));
+
+        let cart = Cartridge::from_bytes(&image(1, 1, 0x22), &registry).unwrap();
+        assert_eq!(cart.mapper_id(), 2);
+        assert_eq!(cart.cpu_read(0x1234), Some(1));
+    }
+
+    #[test]
+    fn nes2_header_fields() {
+        let mut bytes = image(2, 1, 0x02);
+        // NES 2.0, mapper $100 submapper 3, 8K of NVRAM and 2K of RAM, PAL
+        bytes[7] = 0x08;
+        bytes[8] = 0x31;
+        bytes[10] = 0x77;
+        bytes[12] = 0x01;
+
+        let header = CartridgeHeader::parse(&bytes).unwrap();
+        assert!(header.nes2);
+        assert_eq!((header.mapper_id, header.submapper, header.region), (0x100, 3, TvRegion::Pal));
+        assert_eq!((header.prg_rom_size, header.chr_rom_size), (32768, 8192));
+        assert_eq!((header.prg_ram_size, header.prg_nvram_size), (8192, 8192));
+
+        // Exponent-multiplier sizes, 2^3 * 3 bytes
+        bytes[4] = (3 << 2) | 1;
+        bytes[9] = 0x0F;
+        assert_eq!(CartridgeHeader::parse(&bytes).unwrap().prg_rom_size, 24);
+
+        let header = CartridgeHeader::parse(&image(1, 0, 0x02)).unwrap();
+        assert!(!header.nes2);
+        assert_eq!((header.prg_nvram_size, header.chr_ram_size, header.region), (8192, 8192, TvRegion::Ntsc));
+    }
+
+    #[test]
+    fn battery_ram_survives_in_a_save_file() {
+        let path = std::env::temp_dir().join(std::format!("crust-cartridge-{}.sav", std::process::id()));
+        let _ = fs::remove_file(&path);
+
+        let mut cart = Cartridge::from_bytes(&image(1, 1, 0x02), &MapperRegistry::new()).unwrap();
+        cart.attach_save(&path).unwrap();
+        assert!(!cart.flush_save().unwrap());
+
+        assert!(cart.cpu_write(0x6000, 0x42));
+        assert!(cart.cpu_write(0x7FFF, 0x99));
+        assert!(cart.flush_save().unwrap());
+        assert!(!cart.flush_save().unwrap());
+
+        let mut cart = Cartridge::from_bytes(&image(1, 1, 0x02), &MapperRegistry::new()).unwrap();
+        assert_eq!(cart.cpu_read(0x6000), Some(0));
+        cart.attach_save(&path).unwrap();
+        assert_eq!((cart.cpu_read(0x6000), cart.cpu_read(0x7FFF)), (Some(0x42), Some(0x99)));
+
+        fs::remove_file(&path).unwrap();
+    }
+}
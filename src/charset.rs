@@ -0,0 +1,153 @@
+// How the RAM viewer turns bytes into the characters beside them. Strings
+// are stored differently from machine to machine, so the decoding's a
+// choice: plain ASCII, PETSCII as the Commodores keep text, Apple II
+// screen codes as text pages hold it, or a table loaded from a file.
+//
+// Only printable ASCII can be drawn, so anything else comes out as a dot:
+// PETSCII's graphics, control codes, and whatever a table leaves out.
+
+// Drawn for bytes with nothing printable to show
+const UNPRINTABLE: char = '.';
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub enum Charset {
+    #[default]
+    Ascii,
+    // The set the C64 starts up in, capitals and graphics
+    Petscii,
+    // Inverse, flashing and normal all come out the same
+    AppleScreen,
+    Custom { name: String, table: Vec<char> },
+}
+
+impl Charset {
+    // "ascii", "petscii" or "apple", anything else is taken to be a table
+    // file, see parse_table
+    pub fn parse(text: &str) -> Result<Charset, String> {
+        match text {
+            "ascii" => Ok(Charset::Ascii),
+            "petscii" => Ok(Charset::Petscii),
+            "apple" => Ok(Charset::AppleScreen),
+            path => {
+                let table = std::fs::read_to_string(path).map_err(|e| std::format!("can't read {}: {}", path, e))?;
+                Charset::parse_table(path, &table)
+            }
+        }
+    }
+
+    // A line a byte, or a range of them, and the character for it, with
+    // a range going up from that character:
+    //
+    //   ; lower case where ASCII has capitals
+    //   41-5a a
+    //   20 ' '
+    //
+    // Bytes not given show as dots.
+    pub fn parse_table(name: &str, text: &str) -> Result<Charset, String> {
+        let mut table = vec![UNPRINTABLE; 256];
+        for (number, line) in text.lines().enumerate() {
+            let line = line.split(';').next().unwrap_or_default().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let bad = || std::format!("{} line {}: '{}' isn't <byte>[-<byte>] <character>", name, number + 1, line);
+
+            let (bytes, glyph) = line.split_once(char::is_whitespace).ok_or_else(bad)?;
+            let glyph = glyph.trim();
+            let glyph = glyph.strip_prefix('\'').and_then(|quoted| quoted.strip_suffix('\'')).unwrap_or(glyph);
+            let mut chars = glyph.chars();
+            let first = match (chars.next(), chars.next()) {
+                (Some(c), None) if c == ' ' || c.is_ascii_graphic() => c,
+                _ => return Err(bad()),
+            };
+
+            let (from, to) = bytes.split_once('-').unwrap_or((bytes, bytes));
+            let from = u8::from_str_radix(from, 16).map_err(|_| bad())?;
+            let to = u8::from_str_radix(to, 16).map_err(|_| bad())?;
+            for (i, byte) in (from..=to).enumerate() {
+                let c = char::from_u32(first as u32 + i as u32).filter(|&c| c == ' ' || c.is_ascii_graphic()).ok_or_else(bad)?;
+                table[byte as usize] = c;
+            }
+        }
+        Ok(Charset::Custom { name: name.to_string(), table })
+    }
+
+    pub fn name(&self) -> &str {
+        match self {
+            Charset::Ascii => "ascii",
+            Charset::Petscii => "petscii",
+            Charset::AppleScreen => "apple",
+            Charset::Custom { name, .. } => name,
+        }
+    }
+
+    // The built-in one after this, round to ASCII again after the last
+    // and from a table
+    pub fn next(&self) -> Charset {
+        match self {
+            Charset::Ascii => Charset::Petscii,
+            Charset::Petscii => Charset::AppleScreen,
+            Charset::AppleScreen | Charset::Custom { .. } => Charset::Ascii,
+        }
+    }
+
+    pub fn decode(&self, byte: u8) -> char {
+        let c = match self {
+            Charset::Ascii => byte as char,
+            Charset::Petscii => match byte {
+                0x20..=0x5B | 0x5D => byte as char,
+                // The arrows up and left
+                0x5E => '^',
+                0x5F => '_',
+                0xA0 => ' ',
+                _ => UNPRINTABLE,
+            },
+            Charset::AppleScreen => match byte {
+                // Lower case on the IIe, the rest repeat @ to ? in each
+                // quarter
+                0xE0..=0xFF => (byte & 0x7F) as char,
+                _ if byte & 0x3F < 0x20 => (b'@' + (byte & 0x3F)) as char,
+                _ => (byte & 0x3F) as char,
+            },
+            Charset::Custom { table, .. } => table[byte as usize],
+        };
+
+        if c == ' ' || c.is_ascii_graphic() {
+            c
+        } else {
+            UNPRINTABLE
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode(charset: &Charset, bytes: &[u8]) -> String {
+        bytes.iter().map(|&byte| charset.decode(byte)).collect()
+    }
+
+    #[test]
+    fn built_in_decodings() {
+        assert_eq!(decode(&Charset::Ascii, b"Hi!\x00\x7f\xc1"), "Hi!...");
+        // READY. with a pound sign, an arrow and a graphic
+        assert_eq!(decode(&Charset::Petscii, &[0x52, 0x45, 0x41, 0x44, 0x59, 0x2E, 0x5C, 0x5F, 0x61, 0xA0]), "READY.._. ");
+        // HELLO normal, inverse and flashing, then a lower case a on a IIe
+        assert_eq!(decode(&Charset::AppleScreen, &[0xC8, 0xC5, 0x0C, 0x4C, 0x8F, 0xA0, 0xB1, 0xE1, 0xFF]), "HELLO 1a.");
+        assert_eq!(Charset::AppleScreen.next().next(), Charset::Petscii);
+    }
+
+    #[test]
+    fn tables() {
+        let charset = Charset::parse_table("lower.txt", "; flipped case\n41-5a a\n20 ' '\n").unwrap();
+        assert_eq!(decode(&charset, b"HI THERE\x00"), "hi there.");
+        assert_eq!(charset.name(), "lower.txt");
+
+        assert!(Charset::parse_table("bad.txt", "41").is_err());
+        assert!(Charset::parse_table("bad.txt", "zz a").is_err());
+        assert!(Charset::parse_table("bad.txt", "41 ab").is_err());
+        // Running past ~
+        assert!(Charset::parse_table("bad.txt", "00-ff a").is_err());
+    }
+}
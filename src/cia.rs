@@ -0,0 +1,758 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+use crate::cpu6502;
+use crate::device::BusDevice;
+use crate::devicelog::{DeviceEventKind, DeviceLogger};
+use crate::interrupts::IrqSource;
+use crate::keymatrix::KeyboardPort;
+use crate::scheduler::{AlarmId, EventQueue};
+
+// A 6526 CIA: two 8 bit ports, two 16 bit interval timers, a time of day
+// clock and a serial shift register.
+//
+//   +0  port A data        +1  port B data
+//   +2  port A direction   +3  port B direction
+//   +4  timer A low        +5  timer A high
+//   +6  timer B low        +7  timer B high
+//   +8  TOD tenths         +9  TOD seconds
+//   +A  TOD minutes        +B  TOD hours, bit 7 PM
+//   +C  serial data
+//   +D  interrupts: reads the flags and clears them, bit 7 set if any
+//       enabled one is; writes set (bit 7 set) or clear the enable bits
+//   +E  CRA                +F  CRB
+//
+// Timer writes go to the latch, and to the counter too while the timer is
+// stopped. A running timer counts down to zero, reloads from the latch and
+// counts on, or stops there in one-shot mode. Control registers:
+//
+//   bit 0    start
+//   bit 1    timer output on PB6 (A) or PB7 (B)
+//   bit 2    the output toggles on each underflow, rather than pulsing
+//   bit 3    one-shot
+//   bit 4    load the latch into the counter now, reads back 0
+//   CRA 5    count CNT edges rather than cycles
+//   CRA 6    serial register shifts out rather than in
+//   CRA 7    TOD fed 50Hz rather than 60Hz
+//   CRB 6-5  count cycles, CNT edges, timer A underflows, or timer A
+//            underflows while CNT is high
+//   CRB 7    TOD writes set the alarm
+//
+// Nothing drives CNT, which a pull-up holds high, so the CNT modes never
+// count. The TOD clock keeps the mains' time, so its 50/60Hz bit changes
+// nothing. The serial register is a stub: a byte written in output mode
+// goes out at once for the host to take, and one the host shifts in lands
+// in the register, each with the serial flag set.
+//
+// Like the RIOT the chip isn't clocked, the timers and the clock are
+// worked out from the cycles since they were last looked at.
+
+const ICR_TA: u8 = 0x01;
+const ICR_TB: u8 = 0x02;
+const ICR_ALARM: u8 = 0x04;
+const ICR_SP: u8 = 0x08;
+const ICR_FLAG: u8 = 0x10;
+
+const CR_START: u8 = 0x01;
+const CR_PBON: u8 = 0x02;
+const CR_TOGGLE: u8 = 0x04;
+const CR_ONESHOT: u8 = 0x08;
+const CR_LOAD: u8 = 0x10;
+const CRA_CNT: u8 = 0x20;
+const CRA_SP_OUT: u8 = 0x40;
+const CRB_ALARM: u8 = 0x80;
+
+// Tenths of a second in the TOD clock's 12 hour day, twice over
+const DAY: u64 = 24 * 60 * 60 * 10;
+
+// Bytes sent out of the serial register before the oldest are dropped
+const MAX_SERIAL: usize = 1024;
+
+// Where a CIA's interrupt output goes. The C64 wires its first CIA to IRQ
+// and its second to NMI.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CiaLine {
+    Irq(IrqSource),
+    Nmi,
+}
+
+#[derive(Clone, Copy)]
+struct Timer {
+    counter: u16,
+    latch: u16,
+    control: u8,
+    // PB6 or PB7 while the output is on in toggle mode
+    toggle: bool,
+}
+
+impl Timer {
+    fn new() -> Timer {
+        Timer { counter: 0xFFFF, latch: 0xFFFF, control: 0, toggle: false }
+    }
+
+    fn running(&self) -> bool {
+        self.control & CR_START != 0
+    }
+
+    // Counts `ticks` down, returning how many times it passed zero
+    fn count(&mut self, ticks: u64) -> u64 {
+        if !self.running() || ticks == 0 {
+            return 0;
+        }
+        let counter = self.counter as u64;
+        if ticks <= counter {
+            self.counter -= ticks as u16;
+            return 0;
+        }
+
+        // The tick after zero reloads it, and so on every latch + 1
+        let after = ticks - counter - 1;
+        let underflows = if self.control & CR_ONESHOT != 0 {
+            self.counter = self.latch;
+            self.control &= !CR_START;
+            1
+        } else {
+            let period = self.latch as u64 + 1;
+            self.counter = (self.latch as u64 - after % period) as u16;
+            1 + after / period
+        };
+        if underflows % 2 == 1 {
+            self.toggle = !self.toggle;
+        }
+        underflows
+    }
+
+    // Ticks until it next passes zero
+    fn until_underflow(&self) -> Option<u64> {
+        self.running().then(|| self.counter as u64 + 1)
+    }
+
+    fn write_control(&mut self, data: u8) {
+        // Starting the timer sets its toggle output
+        if data & CR_START != 0 && !self.running() {
+            self.toggle = true;
+        }
+        if data & CR_LOAD != 0 {
+            self.counter = self.latch;
+        }
+        self.control = data & !CR_LOAD;
+    }
+
+    fn write_high(&mut self, data: u8) {
+        self.latch = (self.latch & 0x00FF) | ((data as u16) << 8);
+        if !self.running() {
+            self.counter = self.latch;
+        }
+    }
+
+    // What the timer puts on its port B pin while its output is on. The
+    // pulse is a cycle long, so it's never seen.
+    fn output(&self) -> bool {
+        self.control & CR_TOGGLE != 0 && self.toggle
+    }
+}
+
+struct Tod {
+    // Tenths of a second since midnight, as of cycle `since`
+    tenths: u64,
+    since: u64,
+    running: bool,
+    alarm: u64,
+    // Reading the hours holds what the registers show until the tenths
+    // are read
+    latched: Option<[u8; 4]>,
+}
+
+impl Tod {
+    fn elapsed(&self, now: u64, cycles_per_tenth: u64) -> u64 {
+        if self.running { now.saturating_sub(self.since) / cycles_per_tenth } else { 0 }
+    }
+
+    fn time(&self, now: u64, cycles_per_tenth: u64) -> u64 {
+        (self.tenths + self.elapsed(now, cycles_per_tenth)) % DAY
+    }
+
+    // Ticks after `since` that the clock reads the alarm time, from the
+    // first one after `elapsed`
+    fn next_alarm(&self, elapsed: u64) -> u64 {
+        let next = (self.tenths + elapsed + 1) % DAY;
+        elapsed + 1 + (self.alarm + DAY - next) % DAY
+    }
+
+    // Stops the clock where it is, so a register can be changed
+    fn hold(&mut self, now: u64, cycles_per_tenth: u64) {
+        self.tenths = self.time(now, cycles_per_tenth);
+        self.since = now;
+    }
+}
+
+// Tenths of a second since midnight to the tenths, seconds, minutes and
+// hours registers, in BCD with the hours 1-12 and bit 7 for PM
+fn tod_registers(time: u64) -> [u8; 4] {
+    let bcd = |n: u64| (((n / 10) << 4) | (n % 10)) as u8;
+    let hours = time / 36000;
+    let hours_12 = if hours % 12 == 0 { 12 } else { hours % 12 };
+    let pm = if hours >= 12 { 0x80 } else { 0 };
+    [bcd(time % 10), bcd(time / 10 % 60), bcd(time / 600 % 60), bcd(hours_12) | pm]
+}
+
+fn tod_time(registers: [u8; 4]) -> u64 {
+    let binary = |n: u8| (n >> 4) as u64 * 10 + (n & 0x0F) as u64;
+    let hours = binary(registers[3] & 0x1F) % 12 + if registers[3] & 0x80 != 0 { 12 } else { 0 };
+    ((hours * 60 + binary(registers[2] & 0x7F)) * 60 + binary(registers[1] & 0x7F)) * 10 + binary(registers[0] & 0x0F)
+}
+
+struct Chip {
+    // Output registers, direction registers (1 bits drive the pin) and
+    // what the host has on the pins
+    output: [u8; 2],
+    direction: [u8; 2],
+    input: [u8; 2],
+    // A keyboard matrix scanned by port A with its columns read on port B
+    keyboard: Option<KeyboardPort>,
+    timers: [Timer; 2],
+    tod: Tod,
+    cycles_per_tenth: u64,
+    serial: u8,
+    sent: VecDeque<u8>,
+    flags: u8,
+    mask: u8,
+    events: EventQueue,
+    // Cycle the timers and the alarm were brought up to
+    updated: u64,
+    // Where the interrupt output goes once connected, what it was last set
+    // to and the event waiting to raise it
+    line: Option<CiaLine>,
+    raised: bool,
+    pending_event: Option<AlarmId>,
+    log: Option<DeviceLogger>,
+}
+
+impl Chip {
+    fn pins(&self, port: usize) -> u8 {
+        let mut input = self.input[port];
+        if port == 1 {
+            if let Some(keyboard) = &self.keyboard {
+                input &= keyboard.scan(self.pins(0));
+            }
+        }
+        let mut pins = (self.output[port] & self.direction[port]) | (input & !self.direction[port]);
+
+        // The timers take over PB6 and PB7 while their outputs are on
+        if port == 1 {
+            for (timer, bit) in self.timers.iter().zip([0x40, 0x80]) {
+                if timer.control & CR_PBON != 0 {
+                    pins = if timer.output() { pins | bit } else { pins & !bit };
+                }
+            }
+        }
+        pins
+    }
+
+    // Brings the timers and the TOD alarm up to the cycle the CPU is on
+    fn update(&mut self) {
+        let now = self.events.now();
+        let cycles = now.saturating_sub(self.updated);
+
+        let tod = &self.tod;
+        if tod.running && tod.next_alarm(tod.elapsed(self.updated, self.cycles_per_tenth)) <= tod.elapsed(now, self.cycles_per_tenth) {
+            self.flags |= ICR_ALARM;
+            self.log(DeviceEventKind::Timer, format_args!("TOD alarm"));
+        }
+        self.updated = now;
+
+        let a_ticks = if self.timers[0].control & CRA_CNT == 0 { cycles } else { 0 };
+        let a_underflows = self.timers[0].count(a_ticks);
+        let b_ticks = match (self.timers[1].control >> 5) & 3 {
+            0 => cycles,
+            1 => 0,
+            _ => a_underflows,
+        };
+        let b_underflows = self.timers[1].count(b_ticks);
+
+        if a_underflows > 0 {
+            self.flags |= ICR_TA;
+            self.log(DeviceEventKind::Timer, format_args!("timer A underflowed {} time(s)", a_underflows));
+        }
+        if b_underflows > 0 {
+            self.flags |= ICR_TB;
+            self.log(DeviceEventKind::Timer, format_args!("timer B underflowed {} time(s)", b_underflows));
+        }
+    }
+
+    fn log(&self, kind: DeviceEventKind, text: std::fmt::Arguments) {
+        if let Some(log) = &self.log {
+            log.log(kind, text);
+        }
+    }
+
+    // Cycles until an interrupt that's enabled and not yet flagged would
+    // be, if anything is going to raise one
+    fn until_interrupt(&self) -> Option<u64> {
+        let [a, b] = &self.timers;
+        let a_cycles = a.until_underflow().filter(|_| a.control & CRA_CNT == 0);
+        let mut due = Vec::new();
+
+        if self.mask & !self.flags & ICR_TA != 0 {
+            due.extend(a_cycles);
+        }
+        if self.mask & !self.flags & ICR_TB != 0 && b.running() {
+            match (b.control >> 5) & 3 {
+                0 => due.extend(b.until_underflow()),
+                1 => {}
+                // Counting timer A, it passes zero on A's counter + 1th
+                // underflow from now
+                _ => due.extend(a_cycles.filter(|_| b.counter == 0 || a.control & CR_ONESHOT == 0).map(|first| first + b.counter as u64 * (a.latch as u64 + 1))),
+            }
+        }
+        let tod = &self.tod;
+        if self.mask & !self.flags & ICR_ALARM != 0 && tod.running {
+            let now = self.events.now();
+            let at = tod.since + tod.next_alarm(tod.elapsed(now, self.cycles_per_tenth)) * self.cycles_per_tenth;
+            due.push(at.saturating_sub(now));
+        }
+
+        due.into_iter().min()
+    }
+
+    // Brings the interrupt output up to date and, if something enabled is
+    // going to flag, arranges to look again then
+    fn update_irq(&mut self, port: &CiaPort) {
+        let line = match self.line {
+            Some(line) => line,
+            None => return,
+        };
+
+        let asserted = self.flags & self.mask != 0;
+        if asserted != self.raised {
+            self.raised = asserted;
+            match line {
+                CiaLine::Irq(source) => {
+                    self.events.schedule_in(0, move |cpu| cpu.set_irq(source, asserted));
+                }
+                // NMI is taken on the edge, letting go of it does nothing
+                CiaLine::Nmi if asserted => {
+                    self.log(DeviceEventKind::Nmi, format_args!("NMI, ICR ${:02x}", self.flags));
+                    self.events.schedule_in(0, |cpu| cpu.trigger_nmi());
+                }
+                CiaLine::Nmi => {}
+            }
+        }
+
+        if let Some(id) = self.pending_event.take() {
+            self.events.cancel(id);
+        }
+        if asserted {
+            return;
+        }
+        if let Some(cycles) = self.until_interrupt() {
+            // Requests are taken up at the end of the clock they're made
+            // in, the cycle after now
+            let port = port.clone();
+            self.pending_event = Some(self.events.schedule_in(cycles.saturating_sub(1), move |_| {
+                let mut chip = port.chip();
+                chip.pending_event = None;
+                chip.update();
+                chip.update_irq(&port);
+            }));
+        }
+    }
+}
+
+// The host's side of the ports, the FLAG pin and the serial register,
+// which can be on another thread
+#[derive(Clone)]
+pub struct CiaPort(Arc<Mutex<Chip>>);
+
+impl CiaPort {
+    fn chip(&self) -> MutexGuard<'_, Chip> {
+        self.0.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    // What's on the pins: the chip's outputs where the direction register
+    // says it drives them, the host's inputs and any keyboard elsewhere
+    pub fn port_a(&self) -> u8 {
+        self.chip().pins(0)
+    }
+
+    pub fn port_b(&self) -> u8 {
+        let mut chip = self.chip();
+        chip.update();
+        chip.pins(1)
+    }
+
+    // The host pulls pins low, joysticks on a C64
+    pub fn set_port_a(&self, data: u8) {
+        self.chip().input[0] = data;
+    }
+
+    pub fn set_port_b(&self, data: u8) {
+        self.chip().input[1] = data;
+    }
+
+    // A falling edge on FLAG, which the C64 has on the cassette read line
+    // and the serial bus's SRQ
+    pub fn pulse_flag(&self) {
+        let mut chip = self.chip();
+        chip.flags |= ICR_FLAG;
+        chip.update_irq(self);
+    }
+
+    // A byte arriving on SP, if the serial register is shifting in
+    pub fn shift_in(&self, data: u8) {
+        let mut chip = self.chip();
+        if chip.timers[0].control & CRA_SP_OUT == 0 {
+            chip.serial = data;
+            chip.flags |= ICR_SP;
+            chip.update_irq(self);
+        }
+    }
+
+    // What's been sent out of the serial register since the last call
+    pub fn take_serial(&self) -> Vec<u8> {
+        self.chip().sent.drain(..).collect()
+    }
+
+    // Whether the interrupt output is asserted
+    pub fn irq(&self) -> bool {
+        let mut chip = self.chip();
+        chip.update();
+        chip.flags & chip.mask != 0
+    }
+}
+
+pub struct Cia {
+    port: CiaPort,
+}
+
+impl Cia {
+    // `clock_hz` sets how many cycles there are to a tenth of a second for
+    // the TOD clock
+    pub fn new(events: EventQueue, clock_hz: u64) -> Self {
+        let now = events.now();
+        let chip = Chip {
+            output: [0; 2],
+            direction: [0; 2],
+            // Nothing pulling the pins down
+            input: [0xFF; 2],
+            keyboard: None,
+            timers: [Timer::new(); 2],
+            tod: Tod { tenths: 0, since: now, running: true, alarm: 0, latched: None },
+            cycles_per_tenth: (clock_hz / 10).max(1),
+            serial: 0,
+            sent: VecDeque::new(),
+            flags: 0,
+            mask: 0,
+            events,
+            updated: now,
+            line: None,
+            raised: false,
+            pending_event: None,
+            log: None,
+        };
+        Cia { port: CiaPort(Arc::new(Mutex::new(chip))) }
+    }
+
+    pub fn port(&self) -> CiaPort {
+        self.port.clone()
+    }
+
+    // Wires the interrupt output to `line`
+    pub fn connect(&mut self, line: CiaLine) {
+        let mut chip = self.port.chip();
+        chip.line = Some(line);
+        chip.update();
+        chip.update_irq(&self.port);
+    }
+
+    // Scans `keyboard` with port A and reads its columns on port B, the
+    // way the C64's first CIA does
+    pub fn connect_keyboard(&mut self, keyboard: KeyboardPort) {
+        self.port.chip().keyboard = Some(keyboard);
+    }
+
+    // Logs the timers underflowing, the TOD alarm and NMIs, as the chip
+    // notices them
+    pub fn connect_log(&mut self, log: DeviceLogger) {
+        self.port.chip().log = Some(log);
+    }
+}
+
+impl BusDevice for Cia {
+    fn read(&mut self, offset: u16) -> u8 {
+        let mut chip = self.port.chip();
+        chip.update();
+        match offset & 0x0F {
+            0x08 => {
+                let data = match chip.tod.latched.take() {
+                    Some(latched) => latched[0],
+                    None => tod_registers(chip.tod.time(chip.events.now(), chip.cycles_per_tenth))[0],
+                };
+                return data;
+            }
+            0x0B => {
+                let registers = chip.tod.latched.unwrap_or_else(|| tod_registers(chip.tod.time(chip.events.now(), chip.cycles_per_tenth)));
+                chip.tod.latched = Some(registers);
+                return registers[3];
+            }
+            0x0D => {
+                let data = chip.flags | if chip.flags & chip.mask != 0 { 0x80 } else { 0 };
+                chip.flags = 0;
+                chip.update_irq(&self.port);
+                return data;
+            }
+            _ => {}
+        }
+        drop(chip);
+        self.peek(offset)
+    }
+
+    fn peek(&self, offset: u16) -> u8 {
+        let mut chip = self.port.chip();
+        chip.update();
+        let [a, b] = chip.timers;
+        match offset & 0x0F {
+            0x00 => chip.pins(0),
+            0x01 => chip.pins(1),
+            0x02 => chip.direction[0],
+            0x03 => chip.direction[1],
+            0x04 => a.counter as u8,
+            0x05 => (a.counter >> 8) as u8,
+            0x06 => b.counter as u8,
+            0x07 => (b.counter >> 8) as u8,
+            register @ 0x08..=0x0B => {
+                let registers = chip.tod.latched.unwrap_or_else(|| tod_registers(chip.tod.time(chip.events.now(), chip.cycles_per_tenth)));
+                registers[register as usize - 0x08]
+            }
+            0x0C => chip.serial,
+            0x0D => chip.flags | if chip.flags & chip.mask != 0 { 0x80 } else { 0 },
+            0x0E => a.control,
+            _ => b.control,
+        }
+    }
+
+    fn write(&mut self, offset: u16, data: u8) {
+        let mut chip = self.port.chip();
+        chip.update();
+        match offset & 0x0F {
+            0x00 => chip.output[0] = data,
+            0x01 => chip.output[1] = data,
+            0x02 => chip.direction[0] = data,
+            0x03 => chip.direction[1] = data,
+            0x04 => chip.timers[0].latch = (chip.timers[0].latch & 0xFF00) | data as u16,
+            0x05 => chip.timers[0].write_high(data),
+            0x06 => chip.timers[1].latch = (chip.timers[1].latch & 0xFF00) | data as u16,
+            0x07 => chip.timers[1].write_high(data),
+            register @ 0x08..=0x0B => {
+                let (now, cycles_per_tenth) = (chip.events.now(), chip.cycles_per_tenth);
+                let index = register as usize - 0x08;
+                if chip.timers[1].control & CRB_ALARM != 0 {
+                    let mut registers = tod_registers(chip.tod.alarm);
+                    registers[index] = data;
+                    chip.tod.alarm = tod_time(registers) % DAY;
+                } else {
+                    // Setting the hours stops the clock and setting the
+                    // tenths starts it again
+                    chip.tod.hold(now, cycles_per_tenth);
+                    let mut registers = tod_registers(chip.tod.tenths);
+                    registers[index] = data;
+                    chip.tod.tenths = tod_time(registers) % DAY;
+                    match index {
+                        3 => chip.tod.running = false,
+                        0 => chip.tod.running = true,
+                        _ => {}
+                    }
+                }
+            }
+            0x0C => {
+                chip.serial = data;
+                if chip.timers[0].control & CRA_SP_OUT != 0 {
+                    if chip.sent.len() == MAX_SERIAL {
+                        chip.sent.pop_front();
+                    }
+                    chip.sent.push_back(data);
+                    chip.flags |= ICR_SP;
+                }
+            }
+            0x0D => {
+                if data & 0x80 != 0 {
+                    chip.mask |= data & 0x1F;
+                } else {
+                    chip.mask &= !(data & 0x1F);
+                }
+            }
+            0x0E => chip.timers[0].write_control(data),
+            _ => chip.timers[1].write_control(data),
+        }
+        chip.update_irq(&self.port);
+    }
+
+    fn reset(&mut self) {
+        let mut chip = self.port.chip();
+        chip.update();
+        chip.output = [0; 2];
+        chip.direction = [0; 2];
+        chip.timers = [Timer::new(); 2];
+        chip.serial = 0;
+        chip.flags = 0;
+        chip.mask = 0;
+        chip.tod.latched = None;
+        chip.update_irq(&self.port);
+    }
+}
+
+impl cpu6502 {
+    // Maps a CIA at $base-$base+F with its interrupt output on the IRQ
+    // line, and returns the host's end of it. `clock_hz` is the CPU's
+    // clock, for the TOD clock.
+    pub fn attach_cia(&mut self, base: u16, clock_hz: u64) -> CiaPort {
+        let mut cia = Cia::new(self.events(), clock_hz);
+        cia.connect(CiaLine::Irq(self.interrupts.register_source("cia")));
+        cia.connect_log(self.device_log().logger("cia"));
+        let port = cia.port();
+        self.bus.borrow_mut().map("cia", base, base.wrapping_add(0x0F), Box::new(cia));
+        port
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keymatrix::{KeyLayout, KeyMatrix};
+
+    fn run(cpu: &mut cpu6502, cycles: u64) {
+        for _ in 0..cycles {
+            cpu.clock();
+        }
+    }
+
+    #[test]
+    fn timers_count_and_reload() {
+        let mut cpu = cpu6502::new();
+        let mut cia = Cia::new(cpu.events(), 1_000_000);
+
+        // Timer A continuous from 9, so passing zero every 10 cycles
+        cia.write(0x04, 9);
+        cia.write(0x05, 0);
+        assert_eq!(cia.peek(0x04), 9);
+        cia.write(0x0E, CR_START);
+        run(&mut cpu, 4);
+        assert_eq!(cia.peek(0x04), 5);
+        run(&mut cpu, 6);
+        assert_eq!(cia.peek(0x04), 9);
+        assert_eq!(cia.read(0x0D), ICR_TA);
+        assert_eq!(cia.read(0x0D), 0);
+
+        // Timer B counting timer A's underflows, one-shot from 2
+        cia.write(0x06, 2);
+        cia.write(0x07, 0);
+        cia.write(0x0F, 0x40 | CR_ONESHOT | CR_START);
+        run(&mut cpu, 20);
+        assert_eq!(cia.peek(0x06), 0);
+        assert_eq!(cia.peek(0x0D) & ICR_TB, 0);
+        run(&mut cpu, 10);
+        assert_eq!(cia.peek(0x0D) & ICR_TB, ICR_TB);
+        assert_eq!((cia.peek(0x06), cia.peek(0x0F) & CR_START), (2, 0));
+
+        // Force loading, and PB7 toggling as B runs off the cycles
+        cia.write(0x06, 0x34);
+        cia.write(0x07, 0x12);
+        cia.write(0x0F, CR_LOAD);
+        assert_eq!((cia.peek(0x06), cia.peek(0x07), cia.peek(0x0F)), (0x34, 0x12, 0));
+        cia.write(0x06, 1);
+        cia.write(0x0F, CR_LOAD | CR_START | CR_PBON | CR_TOGGLE);
+        let port = cia.port();
+        assert_eq!(port.port_b() & 0x80, 0x80);
+        run(&mut cpu, 0x1202);
+        assert_eq!(port.port_b() & 0x80, 0);
+    }
+
+    #[test]
+    fn time_of_day_and_alarm() {
+        let mut cpu = cpu6502::new();
+        // 10 cycles to a tenth of a second
+        let mut cia = Cia::new(cpu.events(), 100);
+
+        // 11:59:59.8 PM, stopped while the hours are set
+        cia.write(0x0B, 0x91);
+        cia.write(0x0A, 0x59);
+        cia.write(0x09, 0x59);
+        run(&mut cpu, 50);
+        cia.write(0x08, 0x08);
+        assert_eq!(cia.read(0x0B), 0x91);
+        run(&mut cpu, 20);
+
+        // The hours latched what the clock showed until the tenths are read
+        assert_eq!((cia.read(0x0A), cia.read(0x09), cia.read(0x08)), (0x59, 0x59, 0x08));
+        assert_eq!([0x08, 0x09, 0x0A, 0x0B].map(|register| cia.read(register)), [0x00, 0x00, 0x00, 0x12]);
+
+        // An alarm a second on
+        cia.write(0x0F, CRB_ALARM);
+        cia.write(0x0B, 0x12);
+        cia.write(0x0A, 0x00);
+        cia.write(0x09, 0x01);
+        cia.write(0x08, 0x00);
+        // Clearing the one that went off at midnight, the alarm's default
+        assert_eq!(cia.read(0x0D), ICR_ALARM);
+        run(&mut cpu, 95);
+        assert_eq!(cia.peek(0x0D) & ICR_ALARM, 0);
+        run(&mut cpu, 10);
+        assert_eq!(cia.read(0x0D), ICR_ALARM);
+        assert_eq!(tod_time(tod_registers(DAY - 1)), DAY - 1);
+    }
+
+    #[test]
+    fn scans_the_keyboard() {
+        let mut cia = Cia::new(EventQueue::default(), 1_000_000);
+        let keyboard = KeyMatrix::new(KeyLayout::c64()).port();
+        cia.connect_keyboard(keyboard.clone());
+
+        // A is row 1 column 2
+        keyboard.set_pressed(&["A"]);
+        cia.write(0x02, 0xFF);
+        cia.write(0x00, !0x02);
+        assert_eq!(cia.read(0x01), !0x04);
+        cia.write(0x00, !0x01);
+        assert_eq!(cia.read(0x01), 0xFF);
+
+        // A joystick pulls port B down too
+        cia.port().set_port_b(!0x10);
+        assert_eq!(cia.read(0x01), !0x10);
+    }
+
+    #[test]
+    fn interrupts_on_irq_and_nmi() {
+        let mut cpu = cpu6502::new();
+        // LDA #$20 / STA $DC04 / LDA #$00 / STA $DC05 / LDA #$81 / STA $DC0D
+        // LDA #$01 / STA $DC0E / CLI / loop: JMP loop
+        cpu.load_program(
+            &[0xA9, 0x20, 0x8D, 0x04, 0xDC, 0xA9, 0x00, 0x8D, 0x05, 0xDC, 0xA9, 0x81, 0x8D, 0x0D, 0xDC, 0xA9, 0x01, 0x8D, 0x0E, 0xDC, 0x58, 0x4C, 0x15, 0x80],
+            0x8000,
+        );
+        // irq: INC $10 / LDA $DC0D / RTI, the read acknowledges it
+        cpu.load_program(&[0xE6, 0x10, 0xAD, 0x0D, 0xDC, 0x40], 0x9000);
+        cpu.set_reset_vector(0x8000);
+        cpu.set_irq_vector(0x9000);
+        cpu.attach_cia(0xDC00, 1_000_000);
+
+        // The same on NMI, from a port B write's FLAG pulse
+        let mut nmi = Cia::new(cpu.events(), 1_000_000);
+        nmi.connect(CiaLine::Nmi);
+        let nmi_port = nmi.port();
+        cpu.bus.borrow_mut().map("cia2", 0xDD00, 0xDD0F, Box::new(nmi));
+        // nmi: INC $11 / LDA $DD0D / RTI
+        cpu.load_program(&[0xE6, 0x11, 0xAD, 0x0D, 0xDD, 0x40], 0x9100);
+        cpu.set_nmi_vector(0x9100);
+        cpu.reset();
+
+        run(&mut cpu, 200);
+        assert!(cpu.bus.borrow().read(0x10, true) >= 4);
+        assert_eq!(cpu.bus.borrow().read(0x11, true), 0);
+
+        cpu.bus.borrow_mut().write(0xDD0D, 0x80 | ICR_FLAG);
+        nmi_port.pulse_flag();
+        run(&mut cpu, 50);
+        assert_eq!(cpu.bus.borrow().read(0x11, true), 1);
+        assert!(!nmi_port.irq());
+    }
+}
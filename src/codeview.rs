@@ -0,0 +1,175 @@
+use std::collections::HashMap;
+
+use crate::{cpu6502, is_documented};
+use crate::symbols::SymbolTable;
+
+// Disassembly decoded as it's looked at rather than for all of memory up
+// front. Only the lines around where the view is are decoded, and each is
+// kept with the bytes it came from: once those bytes are written the line
+// is decoded again, so code that modifies itself shows what will run.
+//
+// The text has symbol names in it, so clear() the view when they change.
+
+struct Line {
+    bytes: Vec<u8>,
+    text: String,
+    next: u16,
+}
+
+impl Line {
+    fn suspect(&self) -> bool {
+        self.bytes[0] == 0x00 || !is_documented(self.bytes[0])
+    }
+}
+
+#[derive(Default)]
+pub struct CodeView {
+    lines: HashMap<u16, Line>,
+    // Where the view is when it isn't following the PC
+    focus: Option<u16>,
+}
+
+impl CodeView {
+    pub fn new() -> Self {
+        CodeView::default()
+    }
+
+    pub fn clear(&mut self) {
+        self.lines.clear();
+    }
+
+    pub fn focus(&self) -> Option<u16> {
+        self.focus
+    }
+
+    // None goes back to following the PC
+    pub fn set_focus(&mut self, addr: Option<u16>) {
+        self.focus = addr;
+    }
+
+    // The instruction at `addr` and the address of the one after it
+    pub fn line(&mut self, cpu: &cpu6502, addr: u16, symbols: &SymbolTable) -> (&str, u16) {
+        let stale = match self.lines.get(&addr) {
+            Some(line) => {
+                let bus = cpu.bus.borrow();
+                line.bytes.iter().enumerate().any(|(i, &byte)| bus.read(addr.wrapping_add(i as u16), true) != byte)
+            }
+            None => true,
+        };
+
+        if stale {
+            let (text, next) = cpu.disassemble_line(addr, symbols);
+            let len = next.wrapping_sub(addr);
+            let bus = cpu.bus.borrow();
+            let bytes = (0..len).map(|i| bus.read(addr.wrapping_add(i), true)).collect();
+            self.lines.insert(addr, Line { bytes, text, next });
+        }
+
+        let line = &self.lines[&addr];
+        (line.text.as_str(), line.next)
+    }
+
+    // Up to `before` lines leading up to `addr`, the line at `addr`, then
+    // `after` more. Instructions don't say where the one before them
+    // starts, so the lines before are found by decoding forward from each
+    // point a little further back. Of the starts that land on `addr`, the
+    // one with the fewest BRKs and undocumented opcodes wins, those being
+    // what data and a misaligned start tend to decode as.
+    pub fn around(&mut self, cpu: &cpu6502, addr: u16, before: usize, after: usize, symbols: &SymbolTable) -> Vec<(u16, String)> {
+        let mut window = self.leading_up_to(cpu, addr, before, symbols);
+
+        let mut at = addr;
+        for _ in 0..=after {
+            let (text, next) = self.line(cpu, at, symbols);
+            window.push((at, text.to_string()));
+
+            // Ran off the top of memory
+            if next <= at {
+                break;
+            }
+            at = next;
+        }
+
+        window
+    }
+
+    fn leading_up_to(&mut self, cpu: &cpu6502, addr: u16, count: usize, symbols: &SymbolTable) -> Vec<(u16, String)> {
+        // Instructions are at most three bytes
+        let furthest = (count * 3).min(addr as usize) as u16;
+        let mut best: Option<(usize, Vec<u16>)> = None;
+
+        for back in (1..=furthest).rev() {
+            let mut at = addr - back;
+            let mut starts = Vec::new();
+            while at < addr {
+                starts.push(at);
+                let (_, next) = self.line(cpu, at, symbols);
+                if next <= at {
+                    break;
+                }
+                at = next;
+            }
+            if at != addr {
+                continue;
+            }
+
+            let starts = starts.split_off(starts.len().saturating_sub(count));
+            let suspect = starts.iter().filter(|start| self.lines[start].suspect()).count();
+            let better = match &best {
+                Some((fewest, lines)) => suspect < *fewest || (suspect == *fewest && starts.len() > lines.len()),
+                None => true,
+            };
+            if better {
+                best = Some((suspect, starts));
+            }
+        }
+
+        best.map(|(_, starts)| starts).unwrap_or_default()
+            .into_iter()
+            .map(|start| (start, self.lines[&start].text.clone()))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn window_around_an_address() {
+        let mut cpu = cpu6502::new();
+        // LDA #$01 / STA $0200 / INX / BNE $8000 / NOP
+        cpu.load_program(&[0xA9, 0x01, 0x8D, 0x00, 0x02, 0xE8, 0xD0, 0xF8, 0xEA], 0x8000);
+        let mut view = CodeView::new();
+        let symbols = SymbolTable::new();
+
+        let window = view.around(&cpu, 0x8005, 2, 2, &symbols);
+        let addrs: Vec<u16> = window.iter().map(|(addr, _)| *addr).collect();
+        assert_eq!(addrs, vec![0x8000, 0x8002, 0x8005, 0x8006, 0x8008]);
+        assert!(window[2].1.contains("INX"));
+
+        // Nothing before the bottom of memory
+        let addrs: Vec<u16> = view.around(&cpu, 0x0000, 4, 0, &symbols).iter().map(|(addr, _)| *addr).collect();
+        assert_eq!(addrs, vec![0x0000]);
+    }
+
+    #[test]
+    fn writes_invalidate_lines() {
+        let mut cpu = cpu6502::new();
+        cpu.load_program(&[0xA9, 0x01, 0xEA], 0x8000);
+        let mut view = CodeView::new();
+        let symbols = SymbolTable::new();
+
+        assert!(view.line(&cpu, 0x8000, &symbols).0.contains("#$01"));
+
+        // The operand changes
+        cpu.bus.borrow_mut().write(0x8001, 0x42);
+        assert!(view.line(&cpu, 0x8000, &symbols).0.contains("#$42"));
+
+        // So does the length, LDA #imm becomes LDA abs
+        cpu.bus.borrow_mut().write(0x8000, 0xAD);
+        let (text, next) = view.line(&cpu, 0x8000, &symbols);
+        assert!(text.contains("{ABS}"));
+        assert_eq!(next, 0x8003);
+    }
+}
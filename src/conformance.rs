@@ -0,0 +1,373 @@
+// Flag behaviour, one instruction at a time. Each case puts the CPU in a
+// known state, runs the single instruction at $0200 and checks every
+// register plus any bytes the instruction should have written.
+
+use crate::{cpu6502, FLAGS6502};
+
+const CODE: u16 = 0x0200;
+
+const C: u8 = FLAGS6502::C as u8;
+const Z: u8 = FLAGS6502::Z as u8;
+const I: u8 = FLAGS6502::I as u8;
+const D: u8 = FLAGS6502::D as u8;
+const B: u8 = FLAGS6502::B as u8;
+const U: u8 = FLAGS6502::U as u8;
+const V: u8 = FLAGS6502::V as u8;
+const N: u8 = FLAGS6502::N as u8;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Regs {
+    a: u8,
+    x: u8,
+    y: u8,
+    p: u8,
+    s: u8,
+    // None is just past the instruction
+    pc: Option<u16>,
+}
+
+const fn regs(a: u8, x: u8, y: u8, p: u8) -> Regs {
+    Regs { a, x, y, p, s: 0xFD, pc: None }
+}
+
+impl Regs {
+    const fn s(self, s: u8) -> Regs {
+        Regs { s, ..self }
+    }
+
+    const fn pc(self, pc: u16) -> Regs {
+        Regs { pc: Some(pc), ..self }
+    }
+}
+
+struct Case {
+    name: &'static str,
+    code: &'static [u8],
+    memory: &'static [(u16, u8)],
+    before: Regs,
+    after: Regs,
+    written: &'static [(u16, u8)],
+}
+
+const CASES: &[Case] = &[
+    // Arithmetic
+    Case { name: "ADC carries out", code: &[0x69, 0x01], memory: &[], before: regs(0xFF, 0, 0, 0), after: regs(0x00, 0, 0, Z | C | U), written: &[] },
+    Case { name: "ADC adds the carry in", code: &[0x69, 0x10], memory: &[], before: regs(0x10, 0, 0, C), after: regs(0x21, 0, 0, U), written: &[] },
+    Case { name: "ADC positive overflow", code: &[0x69, 0x50], memory: &[], before: regs(0x50, 0, 0, 0), after: regs(0xA0, 0, 0, N | V | U), written: &[] },
+    Case { name: "ADC negative overflow", code: &[0x69, 0x90], memory: &[], before: regs(0xD0, 0, 0, 0), after: regs(0x60, 0, 0, V | C | U), written: &[] },
+    Case { name: "ADC clears V", code: &[0x69, 0x10], memory: &[], before: regs(0x50, 0, 0, V), after: regs(0x60, 0, 0, U), written: &[] },
+    Case { name: "SBC borrows out", code: &[0xE9, 0xF0], memory: &[], before: regs(0x50, 0, 0, C), after: regs(0x60, 0, 0, U), written: &[] },
+    Case { name: "SBC overflow", code: &[0xE9, 0xB0], memory: &[], before: regs(0x50, 0, 0, C), after: regs(0xA0, 0, 0, N | V | U), written: &[] },
+    Case { name: "SBC to zero", code: &[0xE9, 0x05], memory: &[], before: regs(0x05, 0, 0, C), after: regs(0x00, 0, 0, Z | C | U), written: &[] },
+    Case { name: "SBC borrows in", code: &[0xE9, 0x03], memory: &[], before: regs(0x05, 0, 0, 0), after: regs(0x01, 0, 0, C | U), written: &[] },
+
+    // Logic
+    Case { name: "AND to zero", code: &[0x29, 0x0F], memory: &[], before: regs(0xF0, 0, 0, N), after: regs(0x00, 0, 0, Z | U), written: &[] },
+    Case { name: "ORA negative", code: &[0x09, 0x80], memory: &[], before: regs(0x00, 0, 0, Z), after: regs(0x80, 0, 0, N | U), written: &[] },
+    Case { name: "EOR to zero", code: &[0x49, 0xFF], memory: &[], before: regs(0xFF, 0, 0, C | V), after: regs(0x00, 0, 0, C | V | Z | U), written: &[] },
+
+    // BIT takes N and V from memory and Z from A & memory
+    Case { name: "BIT zp sets N V Z", code: &[0x24, 0x10], memory: &[(0x0010, 0xC0)], before: regs(0x3F, 0, 0, 0), after: regs(0x3F, 0, 0, N | V | Z | U), written: &[] },
+    Case { name: "BIT abs clears N V Z", code: &[0x2C, 0x34, 0x12], memory: &[(0x1234, 0x01)], before: regs(0x01, 0, 0, N | V | Z), after: regs(0x01, 0, 0, U), written: &[] },
+    Case { name: "BIT V only", code: &[0x24, 0x10], memory: &[(0x0010, 0x41)], before: regs(0x01, 0, 0, C), after: regs(0x01, 0, 0, C | V | U), written: &[] },
+
+    // Compares set C when the register is at least the operand
+    Case { name: "CMP equal", code: &[0xC9, 0x40], memory: &[], before: regs(0x40, 0, 0, N), after: regs(0x40, 0, 0, Z | C | U), written: &[] },
+    Case { name: "CMP greater", code: &[0xC9, 0x20], memory: &[], before: regs(0x40, 0, 0, Z), after: regs(0x40, 0, 0, C | U), written: &[] },
+    Case { name: "CMP negative difference", code: &[0xC9, 0x10], memory: &[], before: regs(0xF0, 0, 0, 0), after: regs(0xF0, 0, 0, N | C | U), written: &[] },
+    Case { name: "CMP zp", code: &[0xC5, 0x10], memory: &[(0x0010, 0x7F)], before: regs(0x7F, 0, 0, 0), after: regs(0x7F, 0, 0, Z | C | U), written: &[] },
+    Case { name: "CPX equal", code: &[0xE0, 0x05], memory: &[], before: regs(0, 0x05, 0, 0), after: regs(0, 0x05, 0, Z | C | U), written: &[] },
+    Case { name: "CPX abs", code: &[0xEC, 0x34, 0x12], memory: &[(0x1234, 0x01)], before: regs(0, 0x03, 0, 0), after: regs(0, 0x03, 0, C | U), written: &[] },
+    Case { name: "CPY negative difference", code: &[0xC0, 0x10], memory: &[], before: regs(0, 0, 0x90, 0), after: regs(0, 0, 0x90, N | C | U), written: &[] },
+
+    // Subtractions that go below zero wrap, they mustn't panic
+    Case { name: "CMP $00 with $01", code: &[0xC9, 0x01], memory: &[], before: regs(0x00, 0, 0, C | Z), after: regs(0x00, 0, 0, N | U), written: &[] },
+    Case { name: "CMP $00 with $ff", code: &[0xC9, 0xFF], memory: &[], before: regs(0x00, 0, 0, N), after: regs(0x00, 0, 0, U), written: &[] },
+    Case { name: "CMP $7f with $80", code: &[0xC9, 0x80], memory: &[], before: regs(0x7F, 0, 0, C), after: regs(0x7F, 0, 0, N | U), written: &[] },
+    Case { name: "CMP $80 with $7f", code: &[0xC9, 0x7F], memory: &[], before: regs(0x80, 0, 0, N), after: regs(0x80, 0, 0, C | U), written: &[] },
+    Case { name: "CPX $00 with $01", code: &[0xE0, 0x01], memory: &[], before: regs(0, 0x00, 0, C), after: regs(0, 0x00, 0, N | U), written: &[] },
+    Case { name: "CPX zp $01 with $ff", code: &[0xE4, 0x10], memory: &[(0x0010, 0xFF)], before: regs(0, 0x01, 0, C | N), after: regs(0, 0x01, 0, U), written: &[] },
+    Case { name: "CPY $7f with $80", code: &[0xC0, 0x80], memory: &[], before: regs(0, 0, 0x7F, C), after: regs(0, 0, 0x7F, N | U), written: &[] },
+    Case { name: "DEC zp $00", code: &[0xC6, 0x10], memory: &[(0x0010, 0x00)], before: regs(0, 0, 0, Z), after: regs(0, 0, 0, N | U), written: &[(0x0010, 0xFF)] },
+    Case { name: "DEC zp $80", code: &[0xC6, 0x10], memory: &[(0x0010, 0x80)], before: regs(0, 0, 0, N), after: regs(0, 0, 0, U), written: &[(0x0010, 0x7F)] },
+    Case { name: "DEX $00", code: &[0xCA], memory: &[], before: regs(0, 0x00, 0, Z), after: regs(0, 0xFF, 0, N | U), written: &[] },
+    Case { name: "DEY $00", code: &[0x88], memory: &[], before: regs(0, 0, 0x00, Z), after: regs(0, 0, 0xFF, N | U), written: &[] },
+    Case { name: "SBC no borrow $00 - $01", code: &[0xE9, 0x01], memory: &[], before: regs(0x00, 0, 0, C), after: regs(0xFF, 0, 0, N | U), written: &[] },
+    Case { name: "SBC no borrow $80 - $01", code: &[0xE9, 0x01], memory: &[], before: regs(0x80, 0, 0, C), after: regs(0x7F, 0, 0, V | C | U), written: &[] },
+    Case { name: "SBC no borrow $7f - $ff", code: &[0xE9, 0xFF], memory: &[], before: regs(0x7F, 0, 0, C), after: regs(0x80, 0, 0, N | V | U), written: &[] },
+    Case { name: "SBC no borrow $ff - $ff", code: &[0xE9, 0xFF], memory: &[], before: regs(0xFF, 0, 0, C), after: regs(0x00, 0, 0, Z | C | U), written: &[] },
+
+    // Loads
+    Case { name: "LDA imm zero", code: &[0xA9, 0x00], memory: &[], before: regs(0x12, 0, 0, N), after: regs(0x00, 0, 0, Z | U), written: &[] },
+    Case { name: "LDA zp,X", code: &[0xB5, 0x10], memory: &[(0x0011, 0x80)], before: regs(0, 1, 0, Z), after: regs(0x80, 1, 0, N | U), written: &[] },
+    Case { name: "LDA (zp),Y", code: &[0xB1, 0x20], memory: &[(0x0020, 0x00), (0x0021, 0x30), (0x3004, 0x7F)], before: regs(0, 0, 4, N | Z), after: regs(0x7F, 0, 4, U), written: &[] },
+    Case { name: "LDA abs leaves C V", code: &[0xAD, 0x34, 0x12], memory: &[(0x1234, 0x01)], before: regs(0, 0, 0, C | V), after: regs(0x01, 0, 0, C | V | U), written: &[] },
+    Case { name: "LDX abs", code: &[0xAE, 0x34, 0x12], memory: &[(0x1234, 0x80)], before: regs(0, 0, 0, 0), after: regs(0, 0x80, 0, N | U), written: &[] },
+    Case { name: "LDY imm zero", code: &[0xA0, 0x00], memory: &[], before: regs(0, 0, 0x44, 0), after: regs(0, 0, 0x00, Z | U), written: &[] },
+
+    // Shifts and rotates
+    Case { name: "ASL A carries out", code: &[0x0A], memory: &[], before: regs(0x81, 0, 0, 0), after: regs(0x02, 0, 0, C | U), written: &[] },
+    Case { name: "ASL zp", code: &[0x06, 0x10], memory: &[(0x0010, 0x40)], before: regs(0, 0, 0, C), after: regs(0, 0, 0, N | U), written: &[(0x0010, 0x80)] },
+    Case { name: "LSR A to zero", code: &[0x4A], memory: &[], before: regs(0x01, 0, 0, N), after: regs(0x00, 0, 0, Z | C | U), written: &[] },
+    Case { name: "ROL A carry through", code: &[0x2A], memory: &[], before: regs(0x80, 0, 0, C), after: regs(0x01, 0, 0, C | U), written: &[] },
+    Case { name: "ROL A into N", code: &[0x2A], memory: &[], before: regs(0x40, 0, 0, 0), after: regs(0x80, 0, 0, N | U), written: &[] },
+    Case { name: "ROL zp", code: &[0x26, 0x10], memory: &[(0x0010, 0x80)], before: regs(0, 0, 0, 0), after: regs(0, 0, 0, Z | C | U), written: &[(0x0010, 0x00)] },
+    Case { name: "ROR A carry through", code: &[0x6A], memory: &[], before: regs(0x01, 0, 0, C), after: regs(0x80, 0, 0, N | C | U), written: &[] },
+    Case { name: "ROR zp", code: &[0x66, 0x10], memory: &[(0x0010, 0x02)], before: regs(0, 0, 0, 0), after: regs(0, 0, 0, U), written: &[(0x0010, 0x01)] },
+
+    // Increments and decrements leave C and V alone
+    Case { name: "INC zp negative", code: &[0xE6, 0x10], memory: &[(0x0010, 0x7F)], before: regs(0, 0, 0, C | V), after: regs(0, 0, 0, C | V | N | U), written: &[(0x0010, 0x80)] },
+    Case { name: "DEC zp to zero", code: &[0xC6, 0x10], memory: &[(0x0010, 0x01)], before: regs(0, 0, 0, 0), after: regs(0, 0, 0, Z | U), written: &[(0x0010, 0x00)] },
+    Case { name: "INX negative", code: &[0xE8], memory: &[], before: regs(0, 0x7F, 0, C), after: regs(0, 0x80, 0, C | N | U), written: &[] },
+    Case { name: "DEY to zero", code: &[0x88], memory: &[], before: regs(0, 0, 0x01, 0), after: regs(0, 0, 0x00, Z | U), written: &[] },
+
+    // Transfers, TXS is the one that doesn't touch the flags
+    Case { name: "TAX zero", code: &[0xAA], memory: &[], before: regs(0x00, 0x05, 0, 0), after: regs(0x00, 0x00, 0, Z | U), written: &[] },
+    Case { name: "TYA negative", code: &[0x98], memory: &[], before: regs(0, 0, 0xFF, 0), after: regs(0xFF, 0, 0xFF, N | U), written: &[] },
+    Case { name: "TSX negative", code: &[0xBA], memory: &[], before: regs(0, 0, 0, 0).s(0x80), after: regs(0, 0x80, 0, N | U).s(0x80), written: &[] },
+    Case { name: "TXS leaves flags", code: &[0x9A], memory: &[], before: regs(0, 0x00, 0, 0), after: regs(0, 0x00, 0, U).s(0x00), written: &[] },
+
+    // Flag instructions
+    Case { name: "SEC", code: &[0x38], memory: &[], before: regs(0, 0, 0, 0), after: regs(0, 0, 0, C | U), written: &[] },
+    Case { name: "CLC", code: &[0x18], memory: &[], before: regs(0, 0, 0, C | N), after: regs(0, 0, 0, N | U), written: &[] },
+    Case { name: "SEI", code: &[0x78], memory: &[], before: regs(0, 0, 0, 0), after: regs(0, 0, 0, I | U), written: &[] },
+    Case { name: "CLI", code: &[0x58], memory: &[], before: regs(0, 0, 0, I | Z), after: regs(0, 0, 0, Z | U), written: &[] },
+    Case { name: "SED", code: &[0xF8], memory: &[], before: regs(0, 0, 0, 0), after: regs(0, 0, 0, D | U), written: &[] },
+    Case { name: "CLD", code: &[0xD8], memory: &[], before: regs(0, 0, 0, D), after: regs(0, 0, 0, U), written: &[] },
+    Case { name: "CLV", code: &[0xB8], memory: &[], before: regs(0, 0, 0, V | C), after: regs(0, 0, 0, C | U), written: &[] },
+
+    // B and U only exist on the stack: PHP and BRK push them set, PLP and
+    // RTI drop B, and U always reads back as 1
+    Case { name: "PHP pushes B and U", code: &[0x08], memory: &[], before: regs(0, 0, 0, C | N), after: regs(0, 0, 0, C | N | U).s(0xFC), written: &[(0x01FD, C | N | B | U)] },
+    Case { name: "PLP drops B", code: &[0x28], memory: &[(0x01FD, 0xFF)], before: regs(0, 0, 0, 0).s(0xFC), after: regs(0, 0, 0, 0xFF & !B), written: &[] },
+    Case { name: "PLP sets U", code: &[0x28], memory: &[(0x01FD, 0x00)], before: regs(0, 0, 0, C | Z).s(0xFC), after: regs(0, 0, 0, U), written: &[] },
+    Case { name: "PLA negative", code: &[0x68], memory: &[(0x01FD, 0x80)], before: regs(0, 0, 0, Z).s(0xFC), after: regs(0x80, 0, 0, N | U), written: &[] },
+    Case { name: "RTI drops B", code: &[0x40], memory: &[(0x01FB, 0xFF), (0x01FC, 0x34), (0x01FD, 0x12)], before: regs(0, 0, 0, I).s(0xFA), after: regs(0, 0, 0, 0xFF & !B).pc(0x1234), written: &[] },
+    Case { name: "RTI sets U", code: &[0x40], memory: &[(0x01FB, 0x00), (0x01FC, 0x34), (0x01FD, 0x12)], before: regs(0, 0, 0, I).s(0xFA), after: regs(0, 0, 0, U).pc(0x1234), written: &[] },
+    Case {
+        name: "BRK pushes B and the address past its padding byte",
+        code: &[0x00, 0xEA],
+        memory: &[(0xFFFE, 0x00), (0xFFFF, 0x90)],
+        before: regs(0, 0, 0, C),
+        after: regs(0, 0, 0, C | I | U).s(0xFA).pc(0x9000),
+        written: &[(0x01FD, 0x02), (0x01FC, 0x02), (0x01FB, C | B | U)],
+    },
+];
+
+// Runs the one instruction and returns the registers it left behind
+fn execute(case: &Case) -> cpu6502 {
+    let mut cpu = cpu6502::new();
+    for &(addr, value) in case.memory {
+        cpu.bus.borrow_mut().write(addr, value);
+    }
+    cpu.load_program(case.code, CODE);
+
+    cpu.a = case.before.a;
+    cpu.x = case.before.x;
+    cpu.y = case.before.y;
+    cpu.status = case.before.p;
+    cpu.stkp = case.before.s;
+    cpu.pc = CODE;
+
+    cpu.clock();
+    while !cpu.complete() {
+        cpu.clock();
+    }
+    cpu
+}
+
+#[test]
+fn flags_table() {
+    let mut failures = Vec::new();
+
+    for case in CASES {
+        let cpu = execute(case);
+        let got = Regs { a: cpu.a, x: cpu.x, y: cpu.y, p: cpu.status, s: cpu.stkp, pc: Some(cpu.pc) };
+        let expected = Regs { pc: Some(case.after.pc.unwrap_or(CODE + case.code.len() as u16)), ..case.after };
+
+        if got != expected {
+            failures.push(std::format!("{}: expected {:02x?}, got {:02x?}", case.name, expected, got));
+        }
+
+        for &(addr, value) in case.written {
+            let byte = cpu.bus.borrow().read(addr, true);
+            if byte != value {
+                failures.push(std::format!("{}: expected ${:02x} at ${:04x}, got ${:02x}", case.name, value, addr, byte));
+            }
+        }
+    }
+
+    assert!(failures.is_empty(), "\n{}", failures.join("\n"));
+}
+
+// NOP / NOP / NOP at $0200 with the handlers at $9000 (IRQ) and $A000 (NMI)
+fn interrupted(status: u8) -> cpu6502 {
+    let mut cpu = cpu6502::new();
+    cpu.load_program(&[0xEA, 0xEA, 0xEA], CODE);
+    cpu.load_program(&[0xEA], 0x9000);
+    cpu.load_program(&[0xEA], 0xA000);
+    cpu.set_irq_vector(0x9000);
+    cpu.set_nmi_vector(0xA000);
+    cpu.status = status;
+    cpu.stkp = 0xFD;
+    cpu.pc = CODE;
+    cpu
+}
+
+fn run_until_pc(cpu: &mut cpu6502, pc: u16) {
+    for _ in 0..100 {
+        cpu.clock();
+        if cpu.pc == pc {
+            return;
+        }
+    }
+    panic!("never reached ${:04x}", pc);
+}
+
+#[test]
+fn irq_pushes_status_with_b_clear() {
+    let mut cpu = interrupted(C | U);
+    cpu.assert_irq(true);
+    run_until_pc(&mut cpu, 0x9000);
+
+    // The I in the pushed copy is the one the handler's RTI restores
+    let pushed = cpu.bus.borrow().read(0x01FB, true);
+    assert_eq!(pushed, C | U);
+    assert_eq!(cpu.status & I, I);
+}
+
+#[test]
+fn nmi_pushes_status_with_b_clear() {
+    let mut cpu = interrupted(C | I | U);
+    cpu.trigger_nmi();
+    run_until_pc(&mut cpu, 0xA000);
+
+    assert_eq!(cpu.bus.borrow().read(0x01FB, true), C | I | U);
+
+    let mut cpu = interrupted(Z | U);
+    cpu.trigger_nmi();
+    run_until_pc(&mut cpu, 0xA000);
+
+    assert_eq!(cpu.bus.borrow().read(0x01FB, true), Z | U);
+    assert_eq!(cpu.status & I, I);
+}
+
+#[test]
+fn brk_and_irq_differ_only_in_b() {
+    let mut cpu = interrupted(N | U);
+    cpu.assert_irq(true);
+    run_until_pc(&mut cpu, 0x9000);
+    let irq = cpu.bus.borrow().read(0x01FB, true);
+
+    let mut cpu = interrupted(N | U);
+    cpu.load_program(&[0x00, 0xEA], CODE);
+    run_until_pc(&mut cpu, 0x9000);
+    let brk = cpu.bus.borrow().read(0x01FB, true);
+
+    assert_eq!(irq ^ brk, B);
+}
+
+// Stack images in the layout of the SingleStepTests (Tom Harte's
+// ProcessorTests) 6502 cases: every register and each byte of RAM the
+// instruction reads or writes, before and after. P is set the way a test
+// harness sets it, B and U included, and the register keeps neither.
+struct State {
+    pc: u16,
+    s: u8,
+    a: u8,
+    x: u8,
+    y: u8,
+    p: u8,
+    ram: &'static [(u16, u8)],
+}
+
+struct StepCase {
+    name: &'static str,
+    initial: State,
+    end: State,
+}
+
+const STACK_IMAGES: &[StepCase] = &[
+    StepCase {
+        name: "08 b0",
+        initial: State { pc: 0x0200, s: 0xFD, a: 0, x: 0, y: 0, p: N | B | U, ram: &[(0x0200, 0x08), (0x0201, 0xB0)] },
+        end: State { pc: 0x0201, s: 0xFC, a: 0, x: 0, y: 0, p: N | U, ram: &[(0x01FD, N | B | U)] },
+    },
+    StepCase {
+        name: "08 00",
+        initial: State { pc: 0x0200, s: 0x00, a: 0, x: 0, y: 0, p: D | I, ram: &[(0x0200, 0x08), (0x0201, 0x00)] },
+        end: State { pc: 0x0201, s: 0xFF, a: 0, x: 0, y: 0, p: D | I | U, ram: &[(0x0100, D | I | B | U)] },
+    },
+    StepCase {
+        name: "28 10",
+        initial: State { pc: 0x0200, s: 0xFC, a: 0, x: 0, y: 0, p: N | Z | U, ram: &[(0x0200, 0x28), (0x01FD, B)] },
+        end: State { pc: 0x0201, s: 0xFD, a: 0, x: 0, y: 0, p: U, ram: &[(0x01FD, B)] },
+    },
+    StepCase {
+        name: "28 cf",
+        initial: State { pc: 0x0200, s: 0xFF, a: 0, x: 0, y: 0, p: B | U, ram: &[(0x0200, 0x28), (0x0100, N | V | D | I | Z | C)] },
+        end: State { pc: 0x0201, s: 0x00, a: 0, x: 0, y: 0, p: N | V | U | D | I | Z | C, ram: &[] },
+    },
+    StepCase {
+        name: "00 ea",
+        initial: State { pc: 0x0200, s: 0xFD, a: 0, x: 0, y: 0, p: B | C, ram: &[(0x0200, 0x00), (0x0201, 0xEA), (0xFFFE, 0x00), (0xFFFF, 0x90)] },
+        end: State { pc: 0x9000, s: 0xFA, a: 0, x: 0, y: 0, p: I | U | C, ram: &[(0x01FD, 0x02), (0x01FC, 0x02), (0x01FB, B | U | C)] },
+    },
+    StepCase {
+        name: "40 30",
+        initial: State { pc: 0x0200, s: 0xFA, a: 0, x: 0, y: 0, p: I | U, ram: &[(0x0200, 0x40), (0x01FB, B | U), (0x01FC, 0x00), (0x01FD, 0x30)] },
+        end: State { pc: 0x3000, s: 0xFD, a: 0, x: 0, y: 0, p: U, ram: &[] },
+    },
+];
+
+#[test]
+fn stack_images() {
+    let mut failures = Vec::new();
+
+    for case in STACK_IMAGES {
+        let mut cpu = cpu6502::new();
+        for &(addr, value) in case.initial.ram {
+            cpu.bus.borrow_mut().write(addr, value);
+        }
+        let State { pc, s, a, x, y, p, .. } = case.initial;
+        (cpu.pc, cpu.stkp, cpu.a, cpu.x, cpu.y) = (pc, s, a, x, y);
+        cpu.set_status(p);
+
+        cpu.clock();
+        while !cpu.complete() {
+            cpu.clock();
+        }
+
+        let end = &case.end;
+        let got = (cpu.pc, cpu.stkp, cpu.a, cpu.x, cpu.y, cpu.status);
+        let expected = (end.pc, end.s, end.a, end.x, end.y, end.p);
+        if got != expected {
+            failures.push(std::format!("{}: expected {:02x?}, got {:02x?}", case.name, expected, got));
+        }
+        for &(addr, value) in end.ram {
+            let byte = cpu.bus.borrow().read(addr, true);
+            if byte != value {
+                failures.push(std::format!("{}: expected ${:02x} at ${:04x}, got ${:02x}", case.name, value, addr, byte));
+            }
+        }
+    }
+
+    assert!(failures.is_empty(), "\n{}", failures.join("\n"));
+}
+
+#[test]
+fn b_never_reaches_the_register() {
+    // A debugger writing P, then an IRQ: the handler sees B clear
+    let mut cpu = interrupted(0xFF);
+    cpu.set_status(0xFF & !I);
+    assert_eq!(cpu.status, 0xFF & !I & !B);
+    cpu.assert_irq(true);
+    run_until_pc(&mut cpu, 0x9000);
+    assert_eq!(cpu.bus.borrow().read(0x01FB, true), 0xFF & !I & !B);
+
+    // and setting the field directly is masked before the next instruction
+    let mut cpu = interrupted(N | B);
+    cpu.clock();
+    assert_eq!(cpu.status, N | U);
+}
+
@@ -0,0 +1,863 @@
+use crate::accesslog::AccessLog;
+use crate::analysis::{self, ControlFlowGraph, XRefs};
+use crate::annotations::{annotated_listing, Annotations};
+use crate::audit::Audit;
+use crate::charset::Charset;
+use crate::{cpu6502, OpenBus};
+use crate::debugger::{Debugger, Register};
+use crate::devicelog::DeviceLogFilter;
+use crate::expr;
+use crate::patch::Patch;
+use crate::pins::PinState;
+use crate::replay::Recording;
+use crate::run::StopReason;
+use crate::snapshot::MachineState;
+use crate::source::Syntax;
+use crate::symbols::SymbolTable;
+use crate::timing::{Line, TimingFuzz};
+use crate::trace::{self, TraceFormat};
+use crate::vectors::Vector;
+
+use std::collections::VecDeque;
+
+// How many lines of output we keep around for scrolling back
+const MAX_OUTPUT: usize = 256;
+
+// Longest memory dump "m" will print, the whole address space
+const MAX_DUMP: i64 = 0x10000;
+
+// Ranges "snap diff" lists before giving a count of the rest
+const MAX_SNAP_RANGES: usize = 32;
+
+// How long "until" runs waiting for its interrupt, several seconds of a
+// 1MHz machine
+const MAX_UNTIL_CYCLES: u64 = 10_000_000;
+
+// Where "trace dump" writes when not given a file
+const TRACE_DUMP_FILE: &str = "trace_dump.log";
+
+const HELP: &[&str] = &[
+    "m <addr> [len]      dump memory",
+    "poke <addr>,<val>   write a byte",
+    "<reg>=<expr>        set a, x, y, sp, pc or p",
+    "bp <addr>           set breakpoint",
+    "bc <addr>           clear breakpoint",
+    "bl                  list breakpoints",
+    "go / stop / s       run, halt or step",
+    "until nmi|irq|<vector>  run until the next NMI, IRQ or vector at $fffa/c/e is taken",
+    "? <expr>            evaluate expression",
+    "watch [<expr>]      add an expression to the watches, or show them all",
+    "unwatch <n>         remove a watch",
+    "undo / redo         revert or reapply an edit",
+    "cheat <addr>,<val>  hold a location at a value",
+    "cheat <n> on|off    switch a cheat, undoable",
+    "cheats              list cheats",
+    "patch <addr>:<val>|<code>  poke on every reset, or a Game Genie code",
+    "patch <n> on|off    switch a patch",
+    "patches             list patches",
+    "bt                  the calls that led here, innermost first",
+    "shadow on|off|strict  check returns against a shadow stack, strict stops on SP wrapping too",
+    "allow <addr>        let the RTS/RTI at addr return anywhere",
+    "unmapped <start>,<end> | clear  take RAM away from a range",
+    "unmapped faults on|off  stop on accesses nothing answers",
+    "openbus latch|<val>|random <seed>  what unmapped reads return",
+    "mirror <start>,<end> <size> | clear  repeat RAM through a range",
+    "decode <start>,<end> <mask> | clear  RAM seeing only the address lines in mask",
+    "irq                 interrupt statistics per source",
+    "vectors             the NMI, RESET and IRQ vectors and when each was last taken",
+    "vector nmi|reset|irq <addr>  point a vector somewhere else, undoable",
+    "trace <file>|tcp:<host:port> [json]  trace every instruction",
+    "trace off           stop tracing and flush",
+    "trace ring <n>|off  keep the last n instructions in memory",
+    "trace dump [last <n>] [file] [json]  write the kept instructions out",
+    "accesses <start>,<end>  log every read and write in a range",
+    "accesses off | save <file>  stop logging, or write the log as CSV",
+    "stats [n] | stats clear  top n mnemonics and the addressing modes",
+    "devlog on|off|clear  log device register writes, timers and interrupts",
+    "devlog show [<device>|<kind> ...]  filter the devices pane, kinds are register, timer, irq, nmi and note",
+    "devlog save <file>  write what the filter passes, JSON lines for .json or .jsonl",
+    "pins on|off         record the address bus, data bus and control pins every tick",
+    "pins [<n>]          the pins on the last n ticks, 16 if not given",
+    "note <addr>[-<end>] <text>  comment an address or range",
+    "unnote <addr>       remove the note starting at addr",
+    "notes               list notes",
+    "export <file>       write the annotated disassembly",
+    "source <file> <start> <end> [vasm]  write ca65 (or vasm) source that reassembles",
+    "analyze [dot|json <file>]  find subroutines and label them",
+    "xref <addr>         the calls, jumps and branches analyze found going to addr",
+    "charset [ascii|petscii|apple|<file>]  how the RAM viewer shows bytes as characters",
+    "state save|load <file>  write or restore a full machine snapshot",
+    "snap a|b            keep a snapshot to compare, b is now if not taken",
+    "snap diff           the memory that changed from a to b",
+    "cfg <addr> [file]   show a subroutine's basic blocks, or write DOT",
+    "cfg off             back to the code view",
+    "fuzz irq|nmi <addr> [n]  interrupt 0..n cycles from now, compare runs up to addr",
+    "audit <n> [every]   run n cycles twice from here, checking they match",
+];
+
+// The command line shown in the debug window. Every edit it makes goes
+// through the Debugger so it can be undone.
+pub struct Console {
+    pub open: bool,
+    pub input: String,
+    pub output: VecDeque<String>,
+    // Subroutine picked with "cfg", drawn in place of the code view
+    pub cfg: Option<ControlFlowGraph>,
+    // What the last "analyze" found going to each address, shown in the
+    // code view
+    pub xrefs: XRefs,
+    // How the RAM viewer decodes characters, K steps through the built-in
+    // ones
+    pub charset: Charset,
+    // Kept with "snap a" and "snap b" for "snap diff"
+    pub snapshots: [Option<MachineState>; 2],
+    // What the device log pane shows, and "devlog save" writes
+    pub device_filter: DeviceLogFilter,
+}
+
+impl Console {
+    pub fn new() -> Self {
+        Console {
+            open: false,
+            input: String::new(),
+            output: VecDeque::new(),
+            cfg: None,
+            xrefs: XRefs::new(),
+            charset: Charset::default(),
+            snapshots: [None, None],
+            device_filter: DeviceLogFilter::default(),
+        }
+    }
+
+    pub fn print(&mut self, line: String) {
+        self.output.push_back(line);
+        if self.output.len() > MAX_OUTPUT {
+            self.output.pop_front();
+        }
+    }
+
+    // Run whatever is in the input line
+    pub fn submit(&mut self, cpu: &mut cpu6502, debugger: &mut Debugger, symbols: &mut SymbolTable, notes: &mut Annotations) {
+        let line = std::mem::take(&mut self.input);
+        let line = line.trim();
+
+        if line.is_empty() {
+            return;
+        }
+
+        self.print(std::format!("> {}", line));
+
+        if let Err(e) = self.execute(line, cpu, debugger, symbols, notes) {
+            self.print(std::format!("error: {}", e));
+        }
+    }
+
+    pub fn execute(&mut self, line: &str, cpu: &mut cpu6502, debugger: &mut Debugger, symbols: &mut SymbolTable, notes: &mut Annotations) -> Result<(), String> {
+        let (command, rest) = match line.split_once(char::is_whitespace) {
+            Some((command, rest)) => (command, rest.trim()),
+            None => (line, ""),
+        };
+
+        // Note text is free-form and may contain '=' or ',', so handle it
+        // before anything else looks at the line
+        if command.eq_ignore_ascii_case("note") {
+            let (range, text) = rest.split_once(char::is_whitespace).ok_or("note takes an address and some text")?;
+            let (start, end) = match range.split_once('-') {
+                Some((start, end)) => (expr::eval(start, cpu, symbols)?, expr::eval(end, cpu, symbols)?),
+                None => {
+                    let addr = expr::eval(range, cpu, symbols)?;
+                    (addr, addr)
+                }
+            };
+
+            notes.add(start as u16, end as u16, text.trim());
+            return Ok(());
+        }
+
+        // Register assignment, "a=ff" or "pc = reset"
+        if let Some((lhs, rhs)) = line.split_once('=') {
+            let reg = Register::from_name(lhs).ok_or_else(|| std::format!("unknown register '{}'", lhs.trim()))?;
+            let value = expr::eval(rhs, cpu, symbols)?;
+            debugger.set_register(cpu, reg, value as u16);
+            return Ok(());
+        }
+
+        let args = split_args(rest);
+        let arg = |i: usize| -> Result<i64, String> {
+            match args.get(i) {
+                Some(a) => expr::eval(a, cpu, symbols),
+                None => Err(std::format!("'{}' is missing an argument", command)),
+            }
+        };
+
+        match command.to_ascii_lowercase().as_str() {
+            "m" => {
+                let addr = arg(0)? as u16;
+                let len = if args.len() > 1 { arg(1)? } else { 0x10 };
+                if !(0..=MAX_DUMP).contains(&len) {
+                    return Err(std::format!("length must be between 0 and ${:x}", MAX_DUMP));
+                }
+                let len = len as u32;
+
+                let mut offset = 0u32;
+                while offset < len {
+                    let row = addr.wrapping_add(offset as u16);
+                    let mut text = std::format!("${:04x}:", row);
+
+                    for column in 0..(len - offset).min(8) {
+                        text.push_str(std::format!(" {:02x}", cpu.bus.borrow().read(row.wrapping_add(column as u16), true)).as_str());
+                    }
+
+                    self.print(text);
+                    offset += 8;
+                }
+
+                let end = addr.saturating_add(len.saturating_sub(1).min(0xFFFF) as u16);
+                let lines: Vec<String> = notes.iter()
+                    .filter(|note| note.start <= end && note.end >= addr)
+                    .map(|note| std::format!("  ; ${:04x} {}", note.start, note.text))
+                    .collect();
+                for line in lines {
+                    self.print(line);
+                }
+            }
+            "poke" => {
+                let addr = arg(0)? as u16;
+                let value = arg(1)? as u8;
+                debugger.poke(cpu, addr, value);
+            }
+            "bp" => {
+                let addr = arg(0)? as u16;
+                debugger.breakpoints.insert(addr);
+                self.print(std::format!("breakpoint at ${:04x}", addr));
+            }
+            "bc" => {
+                if args.is_empty() {
+                    debugger.breakpoints.clear();
+                } else {
+                    debugger.breakpoints.remove(&(arg(0)? as u16));
+                }
+            }
+            "bl" => {
+                let lines: Vec<String> = debugger.breakpoints.iter().map(|addr| match symbols.name_of(*addr) {
+                    Some(name) => std::format!("${:04x} {}", addr, name),
+                    None => std::format!("${:04x}", addr),
+                }).collect();
+
+                if lines.is_empty() {
+                    self.print("no breakpoints".to_string());
+                }
+                for line in lines {
+                    self.print(line);
+                }
+            }
+            "go" | "g" => debugger.running = true,
+            "stop" => debugger.running = false,
+            "s" | "step" => {
+                debugger.running = false;
+                loop {
+                    cpu.clock();
+                    if cpu.complete() {
+                        break;
+                    }
+                }
+            }
+            "until" => {
+                debugger.running = false;
+                let (reason, vector) = match args.first().map(|a| a.to_ascii_lowercase()).as_deref() {
+                    Some("nmi") => (cpu.run_until_nmi(MAX_UNTIL_CYCLES), Vector::Nmi),
+                    Some("irq") => (cpu.run_until_irq(MAX_UNTIL_CYCLES), Vector::Irq),
+                    Some(_) => {
+                        let addr = arg(0)? as u16;
+                        let vector = Vector::ALL.into_iter().find(|vector| vector.addr() == addr).ok_or_else(|| std::format!("${:04x} isn't a vector, try $fffa, $fffc or $fffe", addr))?;
+                        (cpu.run_until_vector(addr, MAX_UNTIL_CYCLES), vector)
+                    }
+                    None => return Err("until takes nmi, irq or a vector's address".to_string()),
+                };
+                match (reason, cpu.vector_taken(vector)) {
+                    (StopReason::ConditionMet, Some(taken)) => self.print(taken.describe()),
+                    (StopReason::CycleLimit, _) => self.print(std::format!("no {} in {} cycles", vector.name(), MAX_UNTIL_CYCLES)),
+                    (reason, _) => self.print(std::format!("stopped at ${:04x}: {:?}", cpu.pc, reason)),
+                }
+            }
+            "?" | "print" => {
+                let value = expr::eval(rest, cpu, symbols)?;
+                self.print(std::format!("${:04x} #{}", value & 0xFFFF, value));
+            }
+            "watch" => {
+                if !rest.is_empty() {
+                    debugger.watches.push(rest.to_string());
+                }
+                let lines: Vec<String> = debugger.watches.iter().enumerate().map(|(i, watch)| match expr::eval(watch, cpu, symbols) {
+                    Ok(value) => std::format!("{:>3} {} = ${:04x} #{}", i, watch, value & 0xFFFF, value),
+                    Err(e) => std::format!("{:>3} {}: {}", i, watch, e),
+                }).collect();
+
+                if lines.is_empty() {
+                    self.print("no watches".to_string());
+                }
+                for line in lines {
+                    self.print(line);
+                }
+            }
+            "unwatch" => {
+                let index = arg(0)? as usize;
+                if index >= debugger.watches.len() {
+                    return Err(std::format!("no watch {}", index));
+                }
+                debugger.watches.remove(index);
+            }
+            "undo" => {
+                if !debugger.undo(cpu) {
+                    self.print("nothing to undo".to_string());
+                }
+            }
+            "redo" => {
+                if !debugger.redo(cpu) {
+                    self.print("nothing to redo".to_string());
+                }
+            }
+            "cheat" => match args.as_slice() {
+                [index, state @ ("on" | "off")] => {
+                    let index = expr::eval(index, cpu, symbols)? as usize;
+                    if !debugger.set_cheat(cpu, index, *state == "on") {
+                        return Err(std::format!("no cheat {}", index));
+                    }
+                }
+                [_, _] => {
+                    let index = debugger.add_cheat(arg(0)? as u16, arg(1)? as u8);
+                    debugger.set_cheat(cpu, index, true);
+                    self.print(std::format!("cheat {} on", index));
+                }
+                _ => return Err("cheat takes <addr>,<value> or <n> on|off".to_string()),
+            },
+            "cheats" => {
+                let lines: Vec<String> = debugger.cheats().iter().enumerate()
+                    .map(|(i, cheat)| std::format!("{:>3} ${:04x} = ${:02x} {}", i, cheat.addr, cheat.value, if cheat.enabled { "on" } else { "off" }))
+                    .collect();
+
+                if lines.is_empty() {
+                    self.print("no cheats".to_string());
+                }
+                for line in lines {
+                    self.print(line);
+                }
+            }
+            "patch" => match args.as_slice() {
+                [index, state @ ("on" | "off")] => {
+                    let index = expr::eval(index, cpu, symbols)? as usize;
+                    if !cpu.set_patch(index, *state == "on") {
+                        return Err(std::format!("no patch {}", index));
+                    }
+                }
+                [_] => {
+                    let patch = Patch::parse(rest)?;
+                    let index = cpu.add_patch(patch);
+                    self.print(std::format!("patch {}: {}", index, patch.describe()));
+                }
+                _ => return Err("patch takes <addr>:<value>, a Game Genie code or <n> on|off".to_string()),
+            },
+            "patches" => {
+                let lines: Vec<String> = cpu.patches().iter().enumerate()
+                    .map(|(i, entry)| std::format!("{:>3} {} {}", i, entry.patch.describe(), if entry.enabled { "on" } else { "off" }))
+                    .collect();
+
+                if lines.is_empty() {
+                    self.print("no patches".to_string());
+                }
+                for line in lines {
+                    self.print(line);
+                }
+            }
+            "bt" | "backtrace" => {
+                let lines: Vec<String> = cpu.backtrace().iter().enumerate()
+                    .map(|(i, call)| std::format!("{:>3}{} {}", i, if call.is_intact(cpu) { " " } else { "!" }, call.describe(symbols)))
+                    .collect();
+
+                if lines.is_empty() {
+                    self.print(std::format!("no calls, at ${:04x}", cpu.pc));
+                }
+                for line in lines {
+                    self.print(line);
+                }
+            }
+            "shadow" => match rest {
+                "on" => cpu.enable_shadow_stack(),
+                "off" => cpu.disable_shadow_stack(),
+                "strict" => cpu.enable_stack_checks(),
+                _ => return Err("shadow takes on, off or strict".to_string()),
+            },
+            "allow" => {
+                let addr = arg(0)? as u16;
+                match &mut cpu.shadow_stack {
+                    Some(shadow_stack) => shadow_stack.allow(addr),
+                    None => return Err("shadow stack is off".to_string()),
+                }
+            }
+            "unmapped" => match rest {
+                "clear" => cpu.bus.borrow_mut().clear_unmapped(),
+                "faults on" => cpu.bus.borrow_mut().report_unmapped(true),
+                "faults off" => cpu.bus.borrow_mut().report_unmapped(false),
+                _ => {
+                    let (start, end) = (arg(0)? as u16, arg(1)? as u16);
+                    cpu.bus.borrow_mut().set_unmapped(start, end);
+                }
+            },
+            "mirror" => match rest {
+                "clear" => cpu.bus.borrow_mut().clear_ram_mirrors(),
+                _ => {
+                    let (start, end, size) = (arg(0)? as u16, arg(1)? as u16, arg(2)? as u16);
+                    cpu.bus.borrow_mut().mirror_ram(start, end, size);
+                }
+            },
+            "decode" => match rest {
+                "clear" => cpu.bus.borrow_mut().clear_ram_decoding(),
+                _ => {
+                    let (start, end, mask) = (arg(0)? as u16, arg(1)? as u16, arg(2)? as u16);
+                    cpu.bus.borrow_mut().decode_ram(start, end, mask);
+                }
+            },
+            "openbus" => {
+                let open_bus = match rest {
+                    "latch" => OpenBus::Latch,
+                    _ if rest.starts_with("random") => OpenBus::Random(arg(1).unwrap_or(0) as u64),
+                    _ => OpenBus::Fixed(arg(0)? as u8),
+                };
+                cpu.bus.borrow_mut().set_open_bus(open_bus);
+            }
+            "trace" => match args.first() {
+                None => return Err("trace needs a file, tcp:<host:port> or off".to_string()),
+                Some(&"off") => {
+                    cpu.set_trace(None);
+                    self.print("trace off".to_string());
+                }
+                Some(&"ring") => match args.get(1) {
+                    None => return Err("trace ring needs a size or off".to_string()),
+                    Some(&"off") => {
+                        cpu.set_trace_ring(None);
+                        self.print("trace ring off".to_string());
+                    }
+                    Some(_) => {
+                        let capacity = arg(1)? as usize;
+                        cpu.set_trace_ring(Some(capacity));
+                        self.print(std::format!("keeping the last {} instructions", capacity));
+                    }
+                },
+                Some(&"dump") => {
+                    let mut rest = &args[1..];
+                    let mut count = usize::MAX;
+                    if rest.first() == Some(&"last") {
+                        count = arg(2)? as usize;
+                        rest = &rest[2..];
+                    }
+                    let target = rest.first().copied().unwrap_or(TRACE_DUMP_FILE);
+                    let format = TraceFormat::parse(rest.get(1).unwrap_or(&"nestest"))?;
+
+                    if cpu.trace_ring().is_none() {
+                        return Err("no trace ring, start one with trace ring <n>".to_string());
+                    }
+                    let mut sink = trace::open(target, format).map_err(|e| e.to_string())?;
+                    let written = cpu.dump_trace_ring(count, sink.as_mut()).map_err(|e| e.to_string())?;
+                    self.print(std::format!("{} instructions written to {}", written, target));
+                }
+                Some(target) => {
+                    let format = TraceFormat::parse(args.get(1).unwrap_or(&"nestest"))?;
+                    cpu.set_trace(Some(trace::open(target, format).map_err(|e| e.to_string())?));
+                    self.print(std::format!("tracing to {}", target));
+                }
+            },
+            "accesses" => match args.first() {
+                Some(&"off") => {
+                    cpu.set_access_log(None);
+                    self.print("access log off".to_string());
+                }
+                Some(&"save") => {
+                    let path = args.get(1).ok_or("accesses save needs a file")?;
+                    let log = cpu.access_log().ok_or("no access log, start one with accesses <start>,<end>")?;
+                    log.save(path).map_err(|e| e.to_string())?;
+                    self.print(std::format!("{} accesses written to {}", log.accesses().len(), path));
+                }
+                _ => {
+                    let (start, end) = (arg(0)? as u16, arg(1)? as u16);
+                    cpu.set_access_log(Some(AccessLog::range(start, end)));
+                    self.print(std::format!("logging accesses to ${:04x}-${:04x}", start, end));
+                }
+            },
+            "devlog" => match args.first() {
+                Some(&"on") | Some(&"off") => {
+                    let on = args[0] == "on";
+                    cpu.device_log().set_enabled(on);
+                    self.print(std::format!("device log {}", args[0]));
+                }
+                Some(&"clear") => {
+                    cpu.device_log().clear();
+                    self.print("device log cleared".to_string());
+                }
+                Some(&"show") => {
+                    self.device_filter = DeviceLogFilter::parse(&args[1..]);
+                    self.print(std::format!("showing {}", self.device_filter.describe()));
+                }
+                Some(&"save") => {
+                    let path = args.get(1).ok_or("devlog save needs a file")?;
+                    let written = cpu.device_log().save(path, &self.device_filter).map_err(|e| e.to_string())?;
+                    self.print(std::format!("{} device events written to {}", written, path));
+                }
+                _ => return Err("devlog takes on, off, clear, show or save".to_string()),
+            },
+            "pins" => match args.first() {
+                Some(&"on") | Some(&"off") => {
+                    cpu.set_pin_tracing(args[0] == "on");
+                    self.print(std::format!("pin tracing {}", args[0]));
+                }
+                _ if !cpu.pin_tracing() => return Err("pin tracing is off, pins on to start it".to_string()),
+                _ => {
+                    let count = if args.is_empty() { 16 } else { arg(0)? as usize };
+                    let history = cpu.pin_history();
+                    self.print(PinState::HEADER.to_string());
+                    for pins in &history[history.len().saturating_sub(count)..] {
+                        self.print(pins.describe());
+                    }
+                }
+            },
+            "stats" => match args.first() {
+                Some(&"clear") => {
+                    cpu.reset_stats();
+                    self.print("stats cleared".to_string());
+                }
+                _ => {
+                    let limit = if args.is_empty() { 10 } else { arg(0)? as usize };
+                    for line in cpu.stats().describe(limit) {
+                        self.print(line);
+                    }
+                }
+            },
+            "irq" => {
+                let stats = cpu.interrupts.stats.clone();
+                self.print(std::format!("{} IRQs taken, {} storms, max depth {}", stats.taken, stats.storms, stats.max_depth));
+
+                let lines: Vec<String> = cpu.interrupts.source_names().iter().zip(&stats.per_source)
+                    .map(|(name, count)| std::format!("  {:<16} {}", name, count))
+                    .collect();
+                for line in lines {
+                    self.print(line);
+                }
+            }
+            "vectors" => {
+                let lines: Vec<String> = Vector::ALL.iter().map(|&vector| cpu.describe_vector(vector, symbols)).collect();
+                for line in lines {
+                    self.print(line);
+                }
+            }
+            "vector" => {
+                let name = args.first().ok_or("vector takes nmi, reset or irq and an address")?;
+                let vector = Vector::from_name(name).ok_or_else(|| std::format!("unknown vector '{}'", name))?;
+                let addr = arg(1)? as u16;
+                debugger.set_vector(cpu, vector, addr);
+                self.print(cpu.describe_vector(vector, symbols));
+            }
+            "unnote" => {
+                if !notes.remove(arg(0)? as u16) {
+                    return Err("no note starts there".to_string());
+                }
+            }
+            "notes" => {
+                if notes.is_empty() {
+                    self.print("no notes".to_string());
+                }
+
+                let lines: Vec<String> = notes.iter().map(|note| if note.start == note.end {
+                    std::format!("${:04x}       {}", note.start, note.text)
+                } else {
+                    std::format!("${:04x}-${:04x} {}", note.start, note.end, note.text)
+                }).collect();
+                for line in lines {
+                    self.print(line);
+                }
+            }
+            "export" => {
+                if rest.is_empty() {
+                    return Err("export needs a file name".to_string());
+                }
+
+                let xrefs = analysis::analyze(cpu, &[]).xrefs;
+                let listing = annotated_listing(&cpu.disassemble_with_symbols(0x0000, 0xFFFF, symbols), notes, symbols, &xrefs);
+                std::fs::write(rest, listing).map_err(|e| e.to_string())?;
+                self.print(std::format!("wrote {}", rest));
+            }
+            "source" => {
+                let path = args.first().ok_or("source needs a file name")?;
+                let syntax = match args.get(3) {
+                    None | Some(&"ca65") => Syntax::Ca65,
+                    Some(&"vasm") => Syntax::Vasm,
+                    Some(other) => return Err(std::format!("unknown syntax '{}'", other)),
+                };
+
+                // Whatever the analysis can't reach from the vectors is written as data
+                let code = analysis::analyze(cpu, &[]).code();
+                let code = if code.is_empty() { None } else { Some(&code) };
+
+                let mut file = std::fs::File::create(path).map_err(|e| e.to_string())?;
+                cpu.disassemble_to_writer(&mut file, arg(1)? as u16, arg(2)? as u16, syntax, symbols, code).map_err(|e| e.to_string())?;
+                self.print(std::format!("wrote {}", path));
+            }
+            "analyze" => {
+                // Subroutines the profiler saw being called are entry points too
+                let observed: Vec<u16> = if cpu.is_profiling() {
+                    cpu.profile_report(crate::profiler::ProfileSort::Inclusive).iter().map(|entry| entry.addr).collect()
+                } else {
+                    Vec::new()
+                };
+
+                let graph = analysis::analyze(cpu, &observed);
+                let added = graph.add_labels(symbols);
+                self.print(std::format!("{} functions, {} new labels", graph.functions.len(), added));
+                self.xrefs = graph.xrefs.clone();
+
+                match args.as_slice() {
+                    [] => {}
+                    ["dot", path] => std::fs::write(path, graph.to_dot(symbols)).map_err(|e| e.to_string())?,
+                    ["json", path] => std::fs::write(path, graph.to_json(symbols)).map_err(|e| e.to_string())?,
+                    _ => return Err("analyze takes dot or json and a file name".to_string()),
+                }
+            }
+            "xref" => {
+                if self.xrefs.is_empty() {
+                    return Err("nothing analyzed yet, run analyze first".to_string());
+                }
+                let target = arg(0)? as u16;
+                let lines: Vec<String> = match self.xrefs.get(&target) {
+                    Some(refs) => refs.iter().map(|xref| {
+                        let (text, _) = cpu.disassemble_line(xref.from, symbols);
+                        std::format!("  {:<8} {}", xref.kind.name(), text)
+                    }).collect(),
+                    None => Vec::new(),
+                };
+                self.print(std::format!("{} references to ${:04x}", lines.len(), target));
+                for line in lines {
+                    self.print(line);
+                }
+            }
+            "charset" => {
+                if let Some(name) = args.first() {
+                    self.charset = Charset::parse(name)?;
+                }
+                self.print(std::format!("RAM viewer characters are {}", self.charset.name()));
+            }
+            "state" => match args.as_slice() {
+                ["save", path] => {
+                    MachineState::capture(cpu).save(path).map_err(|e| e.to_string())?;
+                    self.print(std::format!("wrote {}", path));
+                }
+                ["load", path] => {
+                    let state = MachineState::load(path).map_err(|e| e.to_string())?;
+                    state.restore(cpu)?;
+                    self.print(std::format!("restored {}", path));
+                }
+                _ => return Err("state takes save or load and a file name".to_string()),
+            },
+            "snap" => match args.as_slice() {
+                [which @ ("a" | "b")] => {
+                    self.snapshots[(*which == "b") as usize] = Some(MachineState::capture(cpu));
+                    self.print(std::format!("snapshot {} taken at cycle {}", which, cpu.clock_count));
+                }
+                ["diff"] => {
+                    let [Some(a), b] = &self.snapshots else {
+                        return Err("take snapshot a first".to_string());
+                    };
+                    let now = MachineState::capture(cpu);
+                    let ranges = a.diff(b.as_ref().unwrap_or(&now));
+
+                    let bytes: usize = ranges.iter().map(|range| range.before.len()).sum();
+                    let lines: Vec<String> = ranges.iter().take(MAX_SNAP_RANGES).map(|range| range.describe()).collect();
+                    self.print(std::format!("{} bytes changed in {} ranges", bytes, ranges.len()));
+                    for line in lines {
+                        self.print(line);
+                    }
+                    if ranges.len() > MAX_SNAP_RANGES {
+                        self.print(std::format!("and {} more", ranges.len() - MAX_SNAP_RANGES));
+                    }
+                }
+                _ => return Err("snap takes a, b or diff".to_string()),
+            },
+            "cfg" => {
+                if rest == "off" {
+                    self.cfg = None;
+                    return Ok(());
+                }
+
+                let graph = analysis::control_flow(cpu, arg(0)? as u16);
+                self.print(std::format!("{} blocks", graph.blocks.len()));
+
+                match args.get(1) {
+                    Some(path) => {
+                        let dot = graph.to_dot(&cpu.disassemble_with_symbols(0x0000, 0xFFFF, symbols), symbols);
+                        std::fs::write(path, dot).map_err(|e| e.to_string())?;
+                        self.print(std::format!("wrote {}", path));
+                    }
+                    None => self.cfg = Some(graph),
+                }
+            }
+            "fuzz" => {
+                let line = match args.first() {
+                    Some(&"irq") => Line::Irq,
+                    Some(&"nmi") => Line::Nmi,
+                    _ => return Err("fuzz takes irq or nmi, an address and a number of cycles".to_string()),
+                };
+
+                let mut fuzz = TimingFuzz::new(line, arg(1)? as u16);
+                if args.len() > 2 {
+                    fuzz.last = arg(2)? as u64;
+                }
+
+                // Checkpoints are taken between instructions
+                while !cpu.complete() {
+                    cpu.clock();
+                }
+
+                let report = fuzz.run(cpu, &MachineState::capture(cpu))?;
+                for line in report.describe() {
+                    self.print(line);
+                }
+            }
+            "audit" => {
+                let mut audit = Audit::new(arg(0)? as u64);
+                if args.len() > 1 {
+                    audit.interval = arg(1)? as u64;
+                }
+
+                let report = audit.repeat(cpu, &Recording::capture(cpu))?;
+                for line in report.describe() {
+                    self.print(line);
+                }
+            }
+            "help" | "h" => {
+                for line in HELP {
+                    self.print(line.to_string());
+                }
+            }
+            _ => return Err(std::format!("unknown command '{}', try help", command)),
+        }
+
+        Ok(())
+    }
+}
+
+// Arguments are separated by commas when there are any, so expressions can
+// contain spaces ("poke $0200 + x, $42"), otherwise by whitespace.
+fn split_args(rest: &str) -> Vec<&str> {
+    if rest.is_empty() {
+        Vec::new()
+    } else if rest.contains(',') {
+        rest.split(',').map(|a| a.trim()).collect()
+    } else {
+        rest.split_whitespace().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(console: &mut Console, cpu: &mut cpu6502, line: &str) -> Result<(), String> {
+        let mut debugger = Debugger::new();
+        let mut symbols = SymbolTable::new();
+        let mut notes = Annotations::new();
+        console.execute(line, cpu, &mut debugger, &mut symbols, &mut notes)
+    }
+
+    #[test]
+    fn split_args_by_comma_or_whitespace() {
+        assert!(split_args("").is_empty());
+        assert_eq!(split_args("0200 10"), vec!["0200", "10"]);
+        assert_eq!(split_args("$0200 + x, $42"), vec!["$0200 + x", "$42"]);
+        assert_eq!(split_args("a,b ,  c"), vec!["a", "b", "c"]);
+    }
+
+    #[test]
+    fn dump_rejects_bad_lengths() {
+        let mut console = Console::new();
+        let mut cpu = cpu6502::new();
+
+        assert!(run(&mut console, &mut cpu, "m 0, 0-1").is_err());
+        assert!(run(&mut console, &mut cpu, "m 0, 10001").is_err());
+        assert!(run(&mut console, &mut cpu, "m 0, 20").is_ok());
+        assert_eq!(console.output.len(), 4);
+    }
+
+    #[test]
+    fn patches_are_added_switched_and_listed() {
+        let mut console = Console::new();
+        let mut cpu = cpu6502::new();
+
+        assert!(run(&mut console, &mut cpu, "patch GOSSIP").is_ok());
+        assert!(run(&mut console, &mut cpu, "patch $10:ff").is_ok());
+        assert!(run(&mut console, &mut cpu, "patch 0 off").is_ok());
+        assert!(run(&mut console, &mut cpu, "patch 5 on").is_err());
+        assert!(run(&mut console, &mut cpu, "patch ZZZ").is_err());
+        assert!(run(&mut console, &mut cpu, "patches").is_ok());
+        let listed: Vec<&str> = console.output.iter().rev().take(2).map(|s| s.as_str()).collect();
+        assert_eq!(listed, ["  1 $0010 = $ff on reset on", "  0 $d1dd reads $14 off"]);
+    }
+
+    #[test]
+    fn snapshots_show_what_changed() {
+        let mut console = Console::new();
+        let mut cpu = cpu6502::new();
+
+        assert!(run(&mut console, &mut cpu, "snap diff").is_err());
+        assert!(run(&mut console, &mut cpu, "snap a").is_ok());
+        assert!(run(&mut console, &mut cpu, "poke $0040, 3").is_ok());
+        assert!(run(&mut console, &mut cpu, "poke $0041, 4").is_ok());
+        assert!(run(&mut console, &mut cpu, "snap diff").is_ok());
+        let shown: Vec<&str> = console.output.iter().rev().take(2).rev().map(|s| s.as_str()).collect();
+        assert_eq!(shown, ["2 bytes changed in 1 ranges", "ram $0040-$0041: 00 00 -> 03 04"]);
+
+        // With b taken, later changes don't count
+        assert!(run(&mut console, &mut cpu, "snap b").is_ok());
+        assert!(run(&mut console, &mut cpu, "poke $0050, 1").is_ok());
+        assert!(run(&mut console, &mut cpu, "snap diff").is_ok());
+        assert_eq!(console.output.back().map(|s| s.as_str()), Some("ram $0040-$0041: 00 00 -> 03 04"));
+    }
+
+    #[test]
+    fn vectors_list_and_edit() {
+        let mut console = Console::new();
+        let mut cpu = cpu6502::new();
+        cpu.set_reset_vector(0x8000);
+        cpu.reset();
+
+        assert!(run(&mut console, &mut cpu, "vector brk $9000").is_ok());
+        assert_eq!(cpu.vector(Vector::Irq), 0x9000);
+        assert!(run(&mut console, &mut cpu, "vector foo $9000").is_err());
+        assert!(run(&mut console, &mut cpu, "vectors").is_ok());
+        let shown: Vec<&str> = console.output.iter().rev().take(2).rev().map(|s| s.as_str()).collect();
+        assert_eq!(shown, ["RESET $8000              RESET at cycle 0 to $8000", "IRQ   $9000              never taken"]);
+    }
+
+    #[test]
+    fn watches_show_their_values() {
+        let mut console = Console::new();
+        let mut cpu = cpu6502::new();
+        let mut debugger = Debugger::new();
+        let mut symbols = SymbolTable::new();
+        let mut notes = Annotations::new();
+        cpu.x = 3;
+
+        for line in ["watch x + 1", "watch nowhere", "unwatch 1", "watch"] {
+            console.execute(line, &mut cpu, &mut debugger, &mut symbols, &mut notes).unwrap();
+        }
+        assert_eq!(debugger.watches, ["x + 1"]);
+        assert_eq!(console.output.back().unwrap(), "  0 x + 1 = $0004 #4");
+        assert!(console.execute("unwatch 1", &mut cpu, &mut debugger, &mut symbols, &mut notes).is_err());
+    }
+
+    #[test]
+    fn output_is_bounded() {
+        let mut console = Console::new();
+        for i in 0..MAX_OUTPUT + 10 {
+            console.print(i.to_string());
+        }
+        assert_eq!(console.output.len(), MAX_OUTPUT);
+        assert_eq!(console.output.front().map(|s| s.as_str()), Some("10"));
+    }
+}
@@ -0,0 +1,829 @@
+use crate::{Bus, SharedBus};
+
+// A 65C816 core sharing the bus with the 6502 one. It covers the
+// emulation/native mode switch, the M/X register width flags, 24-bit long
+// addressing and the new transfer, stack and block move instructions.
+// Decimal mode and some addressing modes (stack relative, indirect long,
+// (dp),Y...) are not implemented yet; unimplemented opcodes skip over their
+// operand as a 2 cycle NOP and are remembered in `last_unimplemented`.
+//
+// Bank $00 is the shared bus. The other banks are backed by RAM owned by
+// the core, `extended_banks` banks of it mirrored through the 24-bit space.
+
+#[derive(Debug, Clone, Copy)]
+#[repr(u8)]
+pub enum FLAGS65816 {
+    C = (1 << 0),
+    // Carry Bit
+    Z = (1 << 1),
+    // Zero
+    I = (1 << 2),
+    // Disable Interrupts
+    D = (1 << 3),
+    // Decimal Mode
+    X = (1 << 4),
+    // 8-bit index registers (native mode), Break in emulation mode
+    M = (1 << 5),
+    // 8-bit accumulator and memory (native mode)
+    V = (1 << 6),
+    // Overflow
+    N = (1 << 7), // Negative
+}
+
+pub struct cpu65816 {
+    // 16-bit accumulator, A is the low byte and B the high byte
+    pub c: u16,
+    pub x: u16,
+    pub y: u16,
+    pub sp: u16,
+    // Direct page register, the 65816's relocatable zero page
+    pub d: u16,
+    // Data bank and program bank registers
+    pub dbr: u8,
+    pub pbr: u8,
+    pub pc: u16,
+    pub p: u8,
+    // Emulation mode, set at reset to behave like a 6502
+    pub e: bool,
+    pub cycles: u8,
+    pub clock_count: u64,
+    pub last_unimplemented: Option<u8>,
+    bus: SharedBus,
+    extended: Vec<u8>,
+}
+
+pub const EXTENDED_BANKS: usize = 16;
+
+impl cpu65816 {
+    pub fn new() -> Self {
+        cpu65816::with_bus(SharedBus::new(Bus::new()))
+    }
+
+    pub fn with_bus(bus: SharedBus) -> Self {
+        cpu65816 {
+            c: 0,
+            x: 0,
+            y: 0,
+            sp: 0x01FF,
+            d: 0,
+            dbr: 0,
+            pbr: 0,
+            pc: 0,
+            p: FLAGS65816::M as u8 | FLAGS65816::X as u8 | FLAGS65816::I as u8,
+            e: true,
+            cycles: 0,
+            clock_count: 0,
+            last_unimplemented: None,
+            bus,
+            extended: vec![0; EXTENDED_BANKS * 0x10000],
+        }
+    }
+
+    pub fn connect_bus(&mut self, bus: SharedBus) {
+        self.bus = bus;
+    }
+
+    pub fn get_flag(&self, f: FLAGS65816) -> bool {
+        self.p & (f as u8) != 0
+    }
+
+    pub fn set_flag(&mut self, f: FLAGS65816, v: bool) {
+        if v {
+            self.p |= f as u8
+        } else {
+            self.p &= !(f as u8)
+        }
+    }
+
+    // True when the accumulator (and memory accesses through it) is 8 bits wide
+    pub fn m8(&self) -> bool {
+        self.e || self.get_flag(FLAGS65816::M)
+    }
+
+    // True when X and Y are 8 bits wide
+    pub fn x8(&self) -> bool {
+        self.e || self.get_flag(FLAGS65816::X)
+    }
+
+    pub fn read24(&self, addr: u32) -> u8 {
+        let bank = (addr >> 16) & 0xFF;
+        if bank == 0 {
+            self.bus.borrow().read(addr as u16, false)
+        } else {
+            self.extended[(addr as usize - 0x10000) % self.extended.len()]
+        }
+    }
+
+    pub fn write24(&mut self, addr: u32, data: u8) {
+        let bank = (addr >> 16) & 0xFF;
+        if bank == 0 {
+            self.bus.borrow_mut().write(addr as u16, data);
+        } else {
+            let len = self.extended.len();
+            self.extended[(addr as usize - 0x10000) % len] = data;
+        }
+    }
+
+    fn read_value(&self, addr: u32, wide: bool) -> u16 {
+        let lo = self.read24(addr & 0xFFFFFF) as u16;
+        if wide {
+            lo | (self.read24((addr + 1) & 0xFFFFFF) as u16) << 8
+        } else {
+            lo
+        }
+    }
+
+    fn write_value(&mut self, addr: u32, value: u16, wide: bool) {
+        self.write24(addr & 0xFFFFFF, (value & 0x00FF) as u8);
+        if wide {
+            self.write24((addr + 1) & 0xFFFFFF, (value >> 8) as u8);
+        }
+    }
+
+    // Instruction stream, the program counter wraps within its bank
+    fn fetch8(&mut self) -> u8 {
+        let data = self.read24(((self.pbr as u32) << 16) | self.pc as u32);
+        self.pc = self.pc.wrapping_add(1);
+        data
+    }
+
+    fn fetch16(&mut self) -> u16 {
+        let lo = self.fetch8() as u16;
+        let hi = self.fetch8() as u16;
+        (hi << 8) | lo
+    }
+
+    fn fetch24(&mut self) -> u32 {
+        let lo = self.fetch16() as u32;
+        let bank = self.fetch8() as u32;
+        (bank << 16) | lo
+    }
+
+    fn push8(&mut self, data: u8) {
+        self.write24(self.sp as u32, data);
+        self.sp = self.sp.wrapping_sub(1);
+        if self.e {
+            self.sp = 0x0100 | (self.sp & 0x00FF);
+        }
+    }
+
+    fn pull8(&mut self) -> u8 {
+        self.sp = self.sp.wrapping_add(1);
+        if self.e {
+            self.sp = 0x0100 | (self.sp & 0x00FF);
+        }
+        self.read24(self.sp as u32)
+    }
+
+    fn push16(&mut self, data: u16) {
+        self.push8((data >> 8) as u8);
+        self.push8((data & 0x00FF) as u8);
+    }
+
+    fn pull16(&mut self) -> u16 {
+        let lo = self.pull8() as u16;
+        let hi = self.pull8() as u16;
+        (hi << 8) | lo
+    }
+
+    fn set_nz(&mut self, value: u16, wide: bool) {
+        if wide {
+            self.set_flag(FLAGS65816::Z, value == 0);
+            self.set_flag(FLAGS65816::N, value & 0x8000 != 0);
+        } else {
+            self.set_flag(FLAGS65816::Z, value & 0x00FF == 0);
+            self.set_flag(FLAGS65816::N, value & 0x0080 != 0);
+        }
+    }
+
+    // Keep the width flags consistent with the mode we are in. With 8-bit
+    // index registers the high bytes of X and Y are forced to zero.
+    fn fix_widths(&mut self) {
+        if self.e {
+            self.p |= FLAGS65816::M as u8 | FLAGS65816::X as u8;
+            self.sp = 0x0100 | (self.sp & 0x00FF);
+        }
+
+        if self.x8() {
+            self.x &= 0x00FF;
+            self.y &= 0x00FF;
+        }
+    }
+
+    fn set_a(&mut self, value: u16) {
+        if self.m8() {
+            self.c = (self.c & 0xFF00) | (value & 0x00FF);
+        } else {
+            self.c = value;
+        }
+        self.set_nz(value, !self.m8());
+    }
+
+    fn index_value(&self, value: u16) -> u16 {
+        if self.x8() { value & 0x00FF } else { value }
+    }
+
+    // Effective addresses for the implemented addressing modes
+    fn addr_dp(&mut self) -> u32 {
+        self.d.wrapping_add(self.fetch8() as u16) as u32
+    }
+
+    fn addr_dp_x(&mut self) -> u32 {
+        self.d.wrapping_add(self.fetch8() as u16).wrapping_add(self.x) as u32
+    }
+
+    fn addr_abs(&mut self) -> u32 {
+        ((self.dbr as u32) << 16) | self.fetch16() as u32
+    }
+
+    fn addr_abs_x(&mut self) -> u32 {
+        (self.addr_abs() + self.x as u32) & 0xFFFFFF
+    }
+
+    fn addr_abs_y(&mut self) -> u32 {
+        (self.addr_abs() + self.y as u32) & 0xFFFFFF
+    }
+
+    fn addr_long(&mut self) -> u32 {
+        self.fetch24()
+    }
+
+    fn addr_long_x(&mut self) -> u32 {
+        (self.fetch24() + self.x as u32) & 0xFFFFFF
+    }
+
+    // Immediate operands are one or two bytes depending on the width flag
+    fn imm(&mut self, wide: bool) -> u16 {
+        if wide { self.fetch16() } else { self.fetch8() as u16 }
+    }
+
+    fn adc(&mut self, value: u16) {
+        let carry = self.get_flag(FLAGS65816::C) as u32;
+
+        if self.m8() {
+            let a = (self.c & 0x00FF) as u32;
+            let v = (value & 0x00FF) as u32;
+            let result = a + v + carry;
+            self.set_flag(FLAGS65816::C, result > 0xFF);
+            self.set_flag(FLAGS65816::V, (!(a ^ v) & (a ^ result) & 0x80) != 0);
+            self.set_a(result as u16 & 0x00FF);
+        } else {
+            let a = self.c as u32;
+            let v = value as u32;
+            let result = a + v + carry;
+            self.set_flag(FLAGS65816::C, result > 0xFFFF);
+            self.set_flag(FLAGS65816::V, (!(a ^ v) & (a ^ result) & 0x8000) != 0);
+            self.set_a(result as u16);
+        }
+    }
+
+    fn sbc(&mut self, value: u16) {
+        // Same as the 6502, subtraction is addition of the inverted operand
+        let mask = if self.m8() { 0x00FF } else { 0xFFFF };
+        self.adc(!value & mask);
+    }
+
+    fn compare(&mut self, register: u16, value: u16, wide: bool) {
+        let (register, value) = if wide { (register, value) } else { (register & 0x00FF, value & 0x00FF) };
+        self.set_flag(FLAGS65816::C, register >= value);
+        self.set_nz(register.wrapping_sub(value), wide);
+    }
+
+    fn branch(&mut self, condition: bool) -> u8 {
+        let offset = self.fetch8() as i8;
+        if condition {
+            self.pc = self.pc.wrapping_add(offset as u16);
+            3
+        } else {
+            2
+        }
+    }
+
+    fn interrupt(&mut self, native_vector: u16, emulation_vector: u16, brk: bool) {
+        if !self.e {
+            self.push8(self.pbr);
+        }
+        self.push16(self.pc);
+
+        // In emulation mode bit 4 is the B flag on the pushed copy only
+        let status = if self.e && brk { self.p | 0x10 } else if self.e { self.p & !0x10 } else { self.p };
+        self.push8(status);
+
+        self.set_flag(FLAGS65816::I, true);
+        self.set_flag(FLAGS65816::D, false);
+        self.pbr = 0;
+
+        let vector = if self.e { emulation_vector } else { native_vector };
+        self.pc = self.read_value(vector as u32, true);
+    }
+
+    pub fn reset(&mut self) {
+        self.e = true;
+        self.p = FLAGS65816::M as u8 | FLAGS65816::X as u8 | FLAGS65816::I as u8;
+        self.d = 0;
+        self.dbr = 0;
+        self.pbr = 0;
+        self.sp = 0x01FD;
+        self.fix_widths();
+        self.pc = self.read_value(0xFFFC, true);
+        self.last_unimplemented = None;
+        self.cycles = 8;
+    }
+
+    pub fn irq(&mut self) {
+        if !self.get_flag(FLAGS65816::I) {
+            self.interrupt(0xFFEE, 0xFFFE, false);
+            self.cycles = 7;
+        }
+    }
+
+    pub fn nmi(&mut self) {
+        self.interrupt(0xFFEA, 0xFFFA, false);
+        self.cycles = 8;
+    }
+
+    pub fn complete(&self) -> bool {
+        self.cycles == 0
+    }
+
+    pub fn clock(&mut self) {
+        if self.cycles == 0 {
+            self.cycles = self.step();
+        }
+
+        self.clock_count += 1;
+        self.cycles -= 1;
+    }
+
+    // Execute one whole instruction and return roughly how many cycles it
+    // took: the base count plus one per extra byte of 16-bit data.
+    pub fn step(&mut self) -> u8 {
+        let opcode = self.fetch8();
+        let m16 = !self.m8();
+        let x16 = !self.x8();
+        let mw = m16 as u8;
+        let xw = x16 as u8;
+
+        match opcode {
+            // Flags
+            0x18 => { self.set_flag(FLAGS65816::C, false); 2 }
+            0x38 => { self.set_flag(FLAGS65816::C, true); 2 }
+            0x58 => { self.set_flag(FLAGS65816::I, false); 2 }
+            0x78 => { self.set_flag(FLAGS65816::I, true); 2 }
+            0xD8 => { self.set_flag(FLAGS65816::D, false); 2 }
+            0xF8 => { self.set_flag(FLAGS65816::D, true); 2 }
+            0xB8 => { self.set_flag(FLAGS65816::V, false); 2 }
+            0xC2 => {
+                // REP
+                let mask = self.fetch8();
+                self.p &= !mask;
+                self.fix_widths();
+                3
+            }
+            0xE2 => {
+                // SEP
+                let mask = self.fetch8();
+                self.p |= mask;
+                self.fix_widths();
+                3
+            }
+            0xFB => {
+                // XCE, swap carry and emulation
+                let carry = self.get_flag(FLAGS65816::C);
+                self.set_flag(FLAGS65816::C, self.e);
+                self.e = carry;
+                self.fix_widths();
+                2
+            }
+
+            // Loads
+            0xA9 => { let v = self.imm(m16); self.set_a(v); 2 + mw }
+            0xA5 => { let a = self.addr_dp(); let v = self.read_value(a, m16); self.set_a(v); 3 + mw }
+            0xB5 => { let a = self.addr_dp_x(); let v = self.read_value(a, m16); self.set_a(v); 4 + mw }
+            0xAD => { let a = self.addr_abs(); let v = self.read_value(a, m16); self.set_a(v); 4 + mw }
+            0xBD => { let a = self.addr_abs_x(); let v = self.read_value(a, m16); self.set_a(v); 4 + mw }
+            0xB9 => { let a = self.addr_abs_y(); let v = self.read_value(a, m16); self.set_a(v); 4 + mw }
+            0xAF => { let a = self.addr_long(); let v = self.read_value(a, m16); self.set_a(v); 5 + mw }
+            0xBF => { let a = self.addr_long_x(); let v = self.read_value(a, m16); self.set_a(v); 5 + mw }
+            0xA2 => { let v = self.imm(x16); self.x = v; self.set_nz(v, x16); 2 + xw }
+            0xAE => { let a = self.addr_abs(); let v = self.read_value(a, x16); self.x = v; self.set_nz(v, x16); 4 + xw }
+            0xA6 => { let a = self.addr_dp(); let v = self.read_value(a, x16); self.x = v; self.set_nz(v, x16); 3 + xw }
+            0xA0 => { let v = self.imm(x16); self.y = v; self.set_nz(v, x16); 2 + xw }
+            0xAC => { let a = self.addr_abs(); let v = self.read_value(a, x16); self.y = v; self.set_nz(v, x16); 4 + xw }
+            0xA4 => { let a = self.addr_dp(); let v = self.read_value(a, x16); self.y = v; self.set_nz(v, x16); 3 + xw }
+
+            // Stores
+            0x85 => { let a = self.addr_dp(); self.write_value(a, self.c, m16); 3 + mw }
+            0x95 => { let a = self.addr_dp_x(); self.write_value(a, self.c, m16); 4 + mw }
+            0x8D => { let a = self.addr_abs(); self.write_value(a, self.c, m16); 4 + mw }
+            0x9D => { let a = self.addr_abs_x(); self.write_value(a, self.c, m16); 5 + mw }
+            0x99 => { let a = self.addr_abs_y(); self.write_value(a, self.c, m16); 5 + mw }
+            0x8F => { let a = self.addr_long(); self.write_value(a, self.c, m16); 5 + mw }
+            0x9F => { let a = self.addr_long_x(); self.write_value(a, self.c, m16); 5 + mw }
+            0x8E => { let a = self.addr_abs(); self.write_value(a, self.x, x16); 4 + xw }
+            0x86 => { let a = self.addr_dp(); self.write_value(a, self.x, x16); 3 + xw }
+            0x8C => { let a = self.addr_abs(); self.write_value(a, self.y, x16); 4 + xw }
+            0x84 => { let a = self.addr_dp(); self.write_value(a, self.y, x16); 3 + xw }
+            0x9C => { let a = self.addr_abs(); self.write_value(a, 0, m16); 4 + mw }
+            0x64 => { let a = self.addr_dp(); self.write_value(a, 0, m16); 3 + mw }
+
+            // Arithmetic and logic
+            0x69 => { let v = self.imm(m16); self.adc(v); 2 + mw }
+            0x6D => { let a = self.addr_abs(); let v = self.read_value(a, m16); self.adc(v); 4 + mw }
+            0x65 => { let a = self.addr_dp(); let v = self.read_value(a, m16); self.adc(v); 3 + mw }
+            0xE9 => { let v = self.imm(m16); self.sbc(v); 2 + mw }
+            0xED => { let a = self.addr_abs(); let v = self.read_value(a, m16); self.sbc(v); 4 + mw }
+            0xE5 => { let a = self.addr_dp(); let v = self.read_value(a, m16); self.sbc(v); 3 + mw }
+            0x29 => { let v = self.imm(m16); self.set_a(self.c & v); 2 + mw }
+            0x09 => { let v = self.imm(m16); self.set_a(self.c | v); 2 + mw }
+            0x49 => { let v = self.imm(m16); self.set_a(self.c ^ v); 2 + mw }
+            0xC9 => { let v = self.imm(m16); self.compare(self.c, v, m16); 2 + mw }
+            0xCD => { let a = self.addr_abs(); let v = self.read_value(a, m16); self.compare(self.c, v, m16); 4 + mw }
+            0xC5 => { let a = self.addr_dp(); let v = self.read_value(a, m16); self.compare(self.c, v, m16); 3 + mw }
+            0xE0 => { let v = self.imm(x16); self.compare(self.x, v, x16); 2 + xw }
+            0xC0 => { let v = self.imm(x16); self.compare(self.y, v, x16); 2 + xw }
+
+            // Increments and decrements
+            0xE8 => { self.x = self.index_value(self.x.wrapping_add(1)); self.set_nz(self.x, x16); 2 }
+            0xC8 => { self.y = self.index_value(self.y.wrapping_add(1)); self.set_nz(self.y, x16); 2 }
+            0xCA => { self.x = self.index_value(self.x.wrapping_sub(1)); self.set_nz(self.x, x16); 2 }
+            0x88 => { self.y = self.index_value(self.y.wrapping_sub(1)); self.set_nz(self.y, x16); 2 }
+            0x1A => { self.set_a(self.c.wrapping_add(1)); 2 }
+            0x3A => { self.set_a(self.c.wrapping_sub(1)); 2 }
+
+            // Transfers
+            0xAA => { self.x = self.index_value(self.c); self.set_nz(self.x, x16); 2 }
+            0xA8 => { self.y = self.index_value(self.c); self.set_nz(self.y, x16); 2 }
+            0x8A => { self.set_a(self.x); 2 }
+            0x98 => { self.set_a(self.y); 2 }
+            0x9B => { self.y = self.x; self.set_nz(self.y, x16); 2 }
+            0xBB => { self.x = self.y; self.set_nz(self.x, x16); 2 }
+            0x9A => { self.sp = if self.e { 0x0100 | (self.x & 0x00FF) } else { self.x }; 2 }
+            0xBA => { self.x = self.index_value(self.sp); self.set_nz(self.x, x16); 2 }
+            0x5B => { self.d = self.c; self.set_nz(self.d, true); 2 }
+            0x7B => { self.c = self.d; self.set_nz(self.c, true); 2 }
+            0x1B => { self.sp = if self.e { 0x0100 | (self.c & 0x00FF) } else { self.c }; 2 }
+            0x3B => { self.c = self.sp; self.set_nz(self.c, true); 2 }
+            0xEB => {
+                // XBA, swap the two halves of the accumulator
+                self.c = self.c.rotate_left(8);
+                self.set_nz(self.c & 0x00FF, false);
+                3
+            }
+
+            // Branches
+            0x80 => self.branch(true),
+            0x82 => {
+                let offset = self.fetch16();
+                self.pc = self.pc.wrapping_add(offset);
+                4
+            }
+            0xF0 => self.branch(self.get_flag(FLAGS65816::Z)),
+            0xD0 => self.branch(!self.get_flag(FLAGS65816::Z)),
+            0xB0 => self.branch(self.get_flag(FLAGS65816::C)),
+            0x90 => self.branch(!self.get_flag(FLAGS65816::C)),
+            0x30 => self.branch(self.get_flag(FLAGS65816::N)),
+            0x10 => self.branch(!self.get_flag(FLAGS65816::N)),
+            0x70 => self.branch(self.get_flag(FLAGS65816::V)),
+            0x50 => self.branch(!self.get_flag(FLAGS65816::V)),
+
+            // Jumps and subroutines
+            0x4C => { self.pc = self.fetch16(); 3 }
+            0x5C => {
+                let target = self.fetch24();
+                self.pbr = (target >> 16) as u8;
+                self.pc = target as u16;
+                4
+            }
+            0x20 => {
+                let target = self.fetch16();
+                self.push16(self.pc.wrapping_sub(1));
+                self.pc = target;
+                6
+            }
+            0x22 => {
+                let target = self.fetch24();
+                self.push8(self.pbr);
+                self.push16(self.pc.wrapping_sub(1));
+                self.pbr = (target >> 16) as u8;
+                self.pc = target as u16;
+                8
+            }
+            0x60 => { self.pc = self.pull16().wrapping_add(1); 6 }
+            0x6B => {
+                self.pc = self.pull16().wrapping_add(1);
+                self.pbr = self.pull8();
+                6
+            }
+            0x40 => {
+                self.p = self.pull8();
+                self.pc = self.pull16();
+                if !self.e {
+                    self.pbr = self.pull8();
+                }
+                self.fix_widths();
+                6 + !self.e as u8
+            }
+
+            // Stack
+            0x48 => { if m16 { self.push16(self.c) } else { self.push8(self.c as u8) } 3 + mw }
+            0x68 => { let v = if m16 { self.pull16() } else { self.pull8() as u16 }; self.set_a(v); 4 + mw }
+            0xDA => { if x16 { self.push16(self.x) } else { self.push8(self.x as u8) } 3 + xw }
+            0xFA => { self.x = if x16 { self.pull16() } else { self.pull8() as u16 }; self.set_nz(self.x, x16); 4 + xw }
+            0x5A => { if x16 { self.push16(self.y) } else { self.push8(self.y as u8) } 3 + xw }
+            0x7A => { self.y = if x16 { self.pull16() } else { self.pull8() as u16 }; self.set_nz(self.y, x16); 4 + xw }
+            0x08 => { self.push8(self.p | if self.e { 0x30 } else { 0 }); 3 }
+            0x28 => { self.p = self.pull8(); self.fix_widths(); 4 }
+            0x8B => { self.push8(self.dbr); 3 }
+            0xAB => { self.dbr = self.pull8(); self.set_nz(self.dbr as u16, false); 4 }
+            0x4B => { self.push8(self.pbr); 3 }
+            0x0B => { self.push16(self.d); 4 }
+            0x2B => { self.d = self.pull16(); self.set_nz(self.d, true); 5 }
+
+            // Block moves, one byte per execution, repeating until C wraps
+            0x54 | 0x44 => {
+                let dst_bank = self.fetch8();
+                let src_bank = self.fetch8();
+                self.dbr = dst_bank;
+
+                let data = self.read24(((src_bank as u32) << 16) | self.x as u32);
+                self.write24(((dst_bank as u32) << 16) | self.y as u32, data);
+
+                if opcode == 0x54 {
+                    self.x = self.index_value(self.x.wrapping_add(1));
+                    self.y = self.index_value(self.y.wrapping_add(1));
+                } else {
+                    self.x = self.index_value(self.x.wrapping_sub(1));
+                    self.y = self.index_value(self.y.wrapping_sub(1));
+                }
+
+                self.c = self.c.wrapping_sub(1);
+                if self.c != 0xFFFF {
+                    self.pc = self.pc.wrapping_sub(3);
+                }
+                7
+            }
+
+            // Interrupts
+            0x00 => {
+                self.fetch8();
+                self.interrupt(0xFFE6, 0xFFFE, true);
+                7 + !self.e as u8
+            }
+            0x02 => {
+                self.fetch8();
+                self.interrupt(0xFFE4, 0xFFF4, false);
+                7 + !self.e as u8
+            }
+
+            0xEA => 2,
+            0x42 => { self.fetch8(); 2 }
+
+            _ => {
+                // Step over the operand so we stay aligned with the program
+                for _ in 0..self.operand_length(opcode) {
+                    self.fetch8();
+                }
+                self.last_unimplemented = Some(opcode);
+                2
+            }
+        }
+    }
+
+    // Bytes following an opcode, from the regular layout of the opcode
+    // matrix. Immediate operands depend on the current M and X widths.
+    pub fn operand_length(&self, opcode: u8) -> u16 {
+        let m16 = !self.m8() as u16;
+        let x16 = !self.x8() as u16;
+        let odd_row = opcode & 0x10 != 0;
+
+        match opcode {
+            0x20 | 0x62 | 0x82 | 0xF4 | 0x44 | 0x54 => 2,
+            0x22 => 3,
+            0x40 | 0x60 | 0x6B => 0,
+            0x00 | 0x02 | 0x42 | 0xC2 | 0xE2 | 0x80 => 1,
+            0xA0 | 0xA2 | 0xC0 | 0xE0 => 1 + x16,
+            _ => match opcode & 0x0F {
+                0x00 => if odd_row { 1 } else { 0 },
+                0x02 => if odd_row { 1 } else { 0 },
+                0x01 | 0x03 | 0x04 | 0x05 | 0x06 | 0x07 => 1,
+                0x09 => if odd_row { 2 } else { 1 + m16 },
+                0x08 | 0x0A | 0x0B => 0,
+                0x0C | 0x0D | 0x0E => 2,
+                _ => 3,
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Loads a program at $8000, points the reset vector at it and resets
+    fn cpu_with(program: &[u8]) -> cpu65816 {
+        let mut cpu = cpu65816::new();
+        for (i, byte) in program.iter().enumerate() {
+            cpu.write24(0x8000 + i as u32, *byte);
+        }
+        cpu.write24(0xFFFC, 0x00);
+        cpu.write24(0xFFFD, 0x80);
+        cpu.reset();
+        cpu
+    }
+
+    fn run(cpu: &mut cpu65816, instructions: usize) {
+        for _ in 0..instructions {
+            cpu.step();
+        }
+    }
+
+    #[test]
+    fn xce_rep_sep_switch_widths() {
+        // CLC / XCE / REP #$30 / SEP #$20 / SEC / XCE
+        let mut cpu = cpu_with(&[0x18, 0xFB, 0xC2, 0x30, 0xE2, 0x20, 0x38, 0xFB]);
+        assert!(cpu.e && cpu.m8() && cpu.x8());
+
+        run(&mut cpu, 2);
+        assert!(!cpu.e);
+        assert!(cpu.get_flag(FLAGS65816::C), "XCE moves the old E into carry");
+        assert!(cpu.m8() && cpu.x8(), "native mode starts with 8-bit registers");
+
+        run(&mut cpu, 1);
+        assert!(!cpu.m8() && !cpu.x8());
+
+        run(&mut cpu, 1);
+        assert!(cpu.m8() && !cpu.x8());
+
+        cpu.x = 0x1234;
+        run(&mut cpu, 2);
+        assert!(cpu.e && cpu.m8() && cpu.x8());
+        assert_eq!(cpu.x, 0x0034, "8-bit index registers lose their high byte");
+        assert_eq!(cpu.sp & 0xFF00, 0x0100);
+    }
+
+    #[test]
+    fn sep_in_emulation_mode_keeps_8_bits() {
+        // REP #$30 has no effect on the widths in emulation mode
+        let mut cpu = cpu_with(&[0xC2, 0x30]);
+        run(&mut cpu, 1);
+        assert!(cpu.m8() && cpu.x8());
+    }
+
+    #[test]
+    fn lda_flags_follow_the_accumulator_width() {
+        // LDA #$80 in 8-bit, then native 16-bit LDA #$0080 and LDA #$8000
+        let mut cpu = cpu_with(&[0xA9, 0x80, 0x18, 0xFB, 0xC2, 0x20, 0xA9, 0x80, 0x00, 0xA9, 0x00, 0x80, 0xA9, 0x00, 0x00]);
+
+        run(&mut cpu, 1);
+        assert!(cpu.get_flag(FLAGS65816::N));
+        assert_eq!(cpu.c & 0x00FF, 0x80);
+
+        run(&mut cpu, 4);
+        assert_eq!(cpu.c, 0x0080);
+        assert!(!cpu.get_flag(FLAGS65816::N), "bit 7 isn't the sign bit of a 16-bit value");
+
+        run(&mut cpu, 1);
+        assert_eq!(cpu.c, 0x8000);
+        assert!(cpu.get_flag(FLAGS65816::N) && !cpu.get_flag(FLAGS65816::Z));
+
+        run(&mut cpu, 1);
+        assert!(cpu.get_flag(FLAGS65816::Z) && !cpu.get_flag(FLAGS65816::N));
+        assert_eq!(cpu.pc, 0x800F);
+    }
+
+    #[test]
+    fn lda_8_bit_keeps_b() {
+        let mut cpu = cpu_with(&[0xA9, 0x00]);
+        cpu.c = 0x1234;
+        run(&mut cpu, 1);
+        assert_eq!(cpu.c, 0x1200);
+        assert!(cpu.get_flag(FLAGS65816::Z));
+    }
+
+    #[test]
+    fn adc_carry_and_overflow_by_width() {
+        // CLC / LDA #$7F / ADC #$01 in 8-bit
+        let mut cpu = cpu_with(&[0x18, 0xA9, 0x7F, 0x69, 0x01]);
+        run(&mut cpu, 3);
+        assert_eq!(cpu.c & 0x00FF, 0x80);
+        assert!(cpu.get_flag(FLAGS65816::V) && cpu.get_flag(FLAGS65816::N) && !cpu.get_flag(FLAGS65816::C));
+
+        // Native 16-bit: CLC / XCE / REP #$21 / LDA #$00FF / ADC #$0001,
+        // then ADC #$FF00 carries out of bit 15. XCE leaves carry set so
+        // the REP clears it along with M.
+        let mut cpu = cpu_with(&[0x18, 0xFB, 0xC2, 0x21, 0xA9, 0xFF, 0x00, 0x69, 0x01, 0x00, 0x69, 0x00, 0xFF]);
+        run(&mut cpu, 5);
+        assert_eq!(cpu.c, 0x0100);
+        assert!(!cpu.get_flag(FLAGS65816::C) && !cpu.get_flag(FLAGS65816::V) && !cpu.get_flag(FLAGS65816::N));
+
+        run(&mut cpu, 1);
+        assert_eq!(cpu.c, 0x0000);
+        assert!(cpu.get_flag(FLAGS65816::C) && cpu.get_flag(FLAGS65816::Z));
+    }
+
+    #[test]
+    fn mvn_copies_upwards() {
+        // LDA #$03 (8-bit, B stays 0) / LDX #$00 / LDY #$00 / MVN $01,$00
+        let mut cpu = cpu_with(&[0xA9, 0x03, 0xA2, 0x00, 0xA0, 0x00, 0x54, 0x01, 0x00]);
+        for (i, byte) in [0x11, 0x22, 0x33, 0x44].iter().enumerate() {
+            cpu.write24(i as u32, *byte);
+        }
+        cpu.c = 0;
+
+        run(&mut cpu, 3);
+        while cpu.pc != 0x8009 {
+            cpu.step();
+        }
+
+        assert_eq!((0..4).map(|i| cpu.read24(0x10000 + i)).collect::<Vec<_>>(), vec![0x11, 0x22, 0x33, 0x44]);
+        assert_eq!(cpu.c, 0xFFFF);
+        assert_eq!((cpu.x, cpu.y), (4, 4));
+        assert_eq!(cpu.dbr, 0x01);
+    }
+
+    #[test]
+    fn mvp_copies_downwards() {
+        // LDA #$01 / LDX #$11 / LDY #$21 / MVP $00,$00, overlapping moves
+        // go from the top so the source isn't overwritten first
+        let mut cpu = cpu_with(&[0xA9, 0x01, 0xA2, 0x11, 0xA0, 0x21, 0x44, 0x00, 0x00]);
+        cpu.write24(0x10, 0xAA);
+        cpu.write24(0x11, 0xBB);
+
+        run(&mut cpu, 3);
+        while cpu.pc != 0x8009 {
+            cpu.step();
+        }
+
+        assert_eq!((cpu.read24(0x20), cpu.read24(0x21)), (0xAA, 0xBB));
+        assert_eq!((cpu.x, cpu.y), (0x0F, 0x1F));
+    }
+
+    #[test]
+    fn jsl_and_rtl_cross_banks() {
+        // JSL $028000, with RTL waiting in bank 2
+        let mut cpu = cpu_with(&[0x22, 0x00, 0x80, 0x02, 0xEA]);
+        cpu.write24(0x028000, 0x6B);
+        let sp = cpu.sp;
+
+        run(&mut cpu, 1);
+        assert_eq!((cpu.pbr, cpu.pc), (0x02, 0x8000));
+        assert_eq!(cpu.sp, sp - 3);
+        assert_eq!(cpu.read24(sp as u32), 0x00, "program bank pushed first");
+
+        run(&mut cpu, 1);
+        assert_eq!((cpu.pbr, cpu.pc), (0x00, 0x8004));
+        assert_eq!(cpu.sp, sp);
+    }
+
+    #[test]
+    fn emulation_stack_wraps_in_page_one() {
+        // PHA / PLA / PLA with the stack pointer at the edges of page one
+        let mut cpu = cpu_with(&[0x48, 0x68, 0x68]);
+        cpu.sp = 0x0100;
+        cpu.c = 0x42;
+
+        run(&mut cpu, 1);
+        assert_eq!(cpu.sp, 0x01FF);
+        assert_eq!(cpu.read24(0x0100), 0x42);
+
+        run(&mut cpu, 1);
+        assert_eq!(cpu.sp, 0x0100);
+
+        // Pulling from the top of the page wraps to $0100, not $0200
+        cpu.write24(0x0100, 0x99);
+        cpu.write24(0x0200, 0x11);
+        cpu.sp = 0x01FF;
+        run(&mut cpu, 1);
+        assert_eq!(cpu.sp, 0x0100);
+        assert_eq!(cpu.c & 0x00FF, 0x99);
+    }
+
+    #[test]
+    fn native_stack_leaves_page_one() {
+        // CLC / XCE / PHA
+        let mut cpu = cpu_with(&[0x18, 0xFB, 0x48]);
+        run(&mut cpu, 2);
+        cpu.sp = 0x0100;
+        run(&mut cpu, 1);
+        assert_eq!(cpu.sp, 0x00FF);
+    }
+
+    #[test]
+    fn unimplemented_opcodes_skip_their_operands() {
+        // ORA ($12),Y / ORA [$12] / ORA $123456,X / ORA #$1234 in 16-bit / NOP
+        let mut cpu = cpu_with(&[0x11, 0x12, 0x07, 0x12, 0x1F, 0x56, 0x34, 0x12, 0xEA]);
+        run(&mut cpu, 3);
+        assert_eq!(cpu.pc, 0x8008);
+        assert_eq!(cpu.last_unimplemented, Some(0x1F));
+
+        let mut wide = cpu_with(&[]);
+        wide.e = false;
+        wide.p = 0;
+        assert_eq!(wide.operand_length(0x09), 2);
+        assert_eq!(wide.operand_length(0xA2), 2);
+        assert_eq!(cpu.operand_length(0x09), 1);
+        assert_eq!(cpu.operand_length(0xFC), 2);
+        assert_eq!(cpu.operand_length(0xFA), 0);
+    }
+}
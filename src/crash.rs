@@ -0,0 +1,189 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::cpu6502;
+use crate::registers::Status;
+use crate::symbols::SymbolTable;
+
+// What's written out when the CPU jams or the debugger stops on an error:
+// the registers, the last instructions run, what's on the stack and the
+// code around PC. The history is kept all the time, a PC per instruction,
+// so there's something to show without a trace having been switched on.
+
+// How many instructions back a report goes
+pub const HISTORY_LEN: usize = 64;
+
+// Instructions shown after PC, the ones before come from the history
+const LINES_AFTER: usize = 4;
+const LINES_BEFORE: usize = 8;
+
+#[derive(Debug, Clone)]
+pub struct PcHistory {
+    pcs: [u16; HISTORY_LEN],
+    // Where the next one goes, and how many there are up to HISTORY_LEN
+    next: usize,
+    len: usize,
+}
+
+impl PcHistory {
+    pub fn new() -> Self {
+        PcHistory { pcs: [0; HISTORY_LEN], next: 0, len: 0 }
+    }
+
+    pub fn push(&mut self, pc: u16) {
+        self.pcs[self.next] = pc;
+        self.next = (self.next + 1) % HISTORY_LEN;
+        self.len = (self.len + 1).min(HISTORY_LEN);
+    }
+
+    // Oldest first
+    pub fn recent(&self) -> Vec<u16> {
+        let start = (self.next + HISTORY_LEN - self.len) % HISTORY_LEN;
+        (0..self.len).map(|i| self.pcs[(start + i) % HISTORY_LEN]).collect()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CrashReport {
+    pub reason: String,
+    pub cycle: u64,
+    pub a: u8,
+    pub x: u8,
+    pub y: u8,
+    pub stkp: u8,
+    pub status: u8,
+    pub pc: u16,
+    // Oldest first, ending with the instruction at PC when it's been run
+    pub history: Vec<u16>,
+    // From the top of the stack, SP + 1, up to $01ff
+    pub stack: Vec<u8>,
+    pub disassembly: Vec<String>,
+}
+
+impl CrashReport {
+    pub fn capture(cpu: &cpu6502, reason: &str, symbols: &SymbolTable) -> Self {
+        let bus = cpu.bus.borrow();
+        let stack = (0x0100 + cpu.stkp as u16 + 1..=0x01FF).map(|addr| bus.read(addr, true)).collect();
+        drop(bus);
+
+        let history = cpu.pc_history.recent();
+
+        // The history knows where instructions started, which can't be
+        // worked out going backwards from PC
+        let mut disassembly = Vec::new();
+        let before: Vec<u16> = history.iter().rev().filter(|&&addr| addr != cpu.pc).take(LINES_BEFORE).copied().collect();
+        for &addr in before.iter().rev() {
+            disassembly.push(std::format!("  {}", cpu.disassemble_line(addr, symbols).0));
+        }
+        let mut addr = cpu.pc;
+        for i in 0..=LINES_AFTER {
+            let (line, next) = cpu.disassemble_line(addr, symbols);
+            disassembly.push(std::format!("{} {}", if i == 0 { ">" } else { " " }, line));
+            addr = next;
+        }
+
+        CrashReport {
+            reason: reason.to_string(),
+            cycle: cpu.clock_count,
+            a: cpu.a,
+            x: cpu.x,
+            y: cpu.y,
+            stkp: cpu.stkp,
+            status: cpu.status,
+            pc: cpu.pc,
+            history,
+            stack,
+            disassembly,
+        }
+    }
+
+    // "game.nes" writes its report to "game.nes.crash.txt"
+    pub fn path_for_rom<P: AsRef<Path>>(rom: P) -> PathBuf {
+        let mut path = rom.as_ref().as_os_str().to_owned();
+        path.push(".crash.txt");
+        PathBuf::from(path)
+    }
+
+    pub fn lines(&self) -> Vec<String> {
+        let mut lines = vec![
+            std::format!("crash at ${:04x}, cycle {}: {}", self.pc, self.cycle, self.reason),
+            std::format!("A=${:02x} X=${:02x} Y=${:02x} SP=${:02x} P=${:02x} {}", self.a, self.x, self.y, self.stkp, self.status, Status(self.status)),
+            String::new(),
+            "code:".to_string(),
+        ];
+        lines.extend(self.disassembly.iter().cloned());
+
+        lines.push(String::new());
+        lines.push(std::format!("stack ({} bytes):", self.stack.len()));
+        for (row, bytes) in self.stack.chunks(16).enumerate() {
+            let hex: Vec<String> = bytes.iter().map(|byte| std::format!("{:02x}", byte)).collect();
+            lines.push(std::format!("  ${:04x}: {}", 0x0100 + self.stkp as usize + 1 + row * 16, hex.join(" ")));
+        }
+
+        lines.push(String::new());
+        lines.push(std::format!("last {} instructions, oldest first:", self.history.len()));
+        for pcs in self.history.chunks(8) {
+            let row: Vec<String> = pcs.iter().map(|pc| std::format!("${:04x}", pc)).collect();
+            lines.push(std::format!("  {}", row.join(" ")));
+        }
+        lines
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let mut text = self.lines().join("\n");
+        text.push('\n');
+        fs::write(path, text)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Halt;
+
+    #[test]
+    fn history_wraps() {
+        let mut history = PcHistory::new();
+        assert!(history.recent().is_empty());
+        for pc in 0..HISTORY_LEN as u16 + 3 {
+            history.push(pc);
+        }
+        let recent = history.recent();
+        assert_eq!((recent.len(), recent[0], recent[HISTORY_LEN - 1]), (HISTORY_LEN, 3, HISTORY_LEN as u16 + 2));
+    }
+
+    #[test]
+    fn jams_halt_and_report() {
+        let mut cpu = cpu6502::new();
+        // LDX #$07 / JSR sub / ... sub: PHA / JAM
+        cpu.load_program(&[0xA2, 0x07, 0x20, 0x06, 0x80, 0xEA, 0x48, 0x02, 0xEA], 0x8000);
+        cpu.set_reset_vector(0x8000);
+        cpu.reset();
+        for _ in 0..40 {
+            cpu.clock();
+        }
+        assert_eq!((cpu.halt_state(), cpu.pc), (Halt::Jammed, 0x8007));
+        let diagnostics = cpu.diagnostics.drain();
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].message(), "CPU jammed on $02, only reset gets it going");
+
+        let report = CrashReport::capture(&cpu, &diagnostics[0].message(), &SymbolTable::new());
+        assert_eq!(report.history, [0x8000, 0x8002, 0x8006, 0x8007]);
+        assert_eq!(report.stack, [0x00, 0x04, 0x80, 0x00, 0x00]);
+        assert!(report.disassembly[3].starts_with("> $8007: JAM"), "{:?}", report.disassembly);
+
+        let text = report.lines().join("\n");
+        assert!(text.starts_with("crash at $8007, cycle "), "{}", text);
+        assert!(text.contains("\n  $01fb: 00 04 80 00 00\n"), "{}", text);
+
+        // The 65C02 runs on past it, and reset gets an NMOS part going again
+        cpu.reset();
+        assert_eq!(cpu.halt_state(), Halt::Running);
+        cpu.set_variant(crate::Variant::Cmos65C02);
+        for _ in 0..40 {
+            cpu.clock();
+        }
+        assert_eq!(cpu.halt_state(), Halt::Running);
+    }
+}
@@ -0,0 +1,127 @@
+use crate::cpu6502;
+use crate::device::BusDevice;
+use crate::scheduler::EventQueue;
+
+// A free running 32 bit count of CPU cycles, for guest programs timing
+// themselves. It counts emulated cycles, so a benchmark gives the same
+// answer every run and on every host.
+//
+//   +0-+3  the count, low byte first. Reading +0 latches all four bytes
+//          and +1-+3 read from the latch, so the bytes of one read agree.
+//   +4     writing anything starts the count again from 0
+//
+// The count is the cycle the reading instruction started on, as with the
+// other devices that work out their state from the scheduler's clock.
+
+pub const REGISTERS_SIZE: u16 = 5;
+
+pub struct CycleCounter {
+    events: EventQueue,
+    // The cycle the count was last started from
+    since: u64,
+    latched: u32,
+}
+
+impl CycleCounter {
+    pub fn new(events: EventQueue) -> Self {
+        let since = events.now();
+        CycleCounter { events, since, latched: 0 }
+    }
+
+    pub fn count(&self) -> u32 {
+        self.events.now().wrapping_sub(self.since) as u32
+    }
+}
+
+impl BusDevice for CycleCounter {
+    fn read(&mut self, offset: u16) -> u8 {
+        if offset == 0 {
+            self.latched = self.count();
+        }
+        self.peek(offset)
+    }
+
+    // Byte 0 is live, the rest are what the last read of +0 latched
+    fn peek(&self, offset: u16) -> u8 {
+        match offset {
+            0 => self.count() as u8,
+            1..=3 => (self.latched >> (8 * offset)) as u8,
+            _ => 0,
+        }
+    }
+
+    fn write(&mut self, offset: u16, _data: u8) {
+        if offset == 4 {
+            self.since = self.events.now();
+            self.latched = 0;
+        }
+    }
+
+    fn reset(&mut self) {
+        self.since = self.events.now();
+        self.latched = 0;
+    }
+}
+
+impl cpu6502 {
+    // Maps a cycle counter at $base-$base+4
+    pub fn attach_cycle_counter(&mut self, base: u16) {
+        let counter = CycleCounter::new(self.events());
+        self.bus.borrow_mut().map("cyclecounter", base, base.wrapping_add(REGISTERS_SIZE - 1), Box::new(counter));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::assemble;
+
+    #[test]
+    fn guest_times_a_loop() {
+        // Restart the count, spin 10 times round a 5 cycle loop, then read
+        // the count into $10-$13 twice over
+        let source = "
+            .org $8000
+            reset:  STA $BD04
+                    LDX #10
+            loop:   DEX
+                    BNE loop
+                    LDA $BD00
+                    STA $10
+                    LDA $BD01
+                    STA $11
+                    LDA $BD02
+                    STA $12
+                    LDA $BD00
+                    STA $14
+            done:   JMP done
+        ";
+        let program = assemble(source).unwrap();
+        let mut cpu = cpu6502::new();
+        cpu.load_program(&program.bytes, program.origin);
+        cpu.set_reset_vector(0x8000);
+        cpu.attach_cycle_counter(0xBD00);
+        cpu.reset();
+        cpu.run_instructions(40, 1000);
+
+        // From the STA: its 4, LDX 2, 10 DEX, 9 taken BNEs and the last
+        // one, then the LDA reading it
+        let bus = cpu.bus.borrow();
+        let count = |addr: u16| u32::from_le_bytes([bus.read(addr, true), bus.read(addr + 1, true), bus.read(addr + 2, true), 0]);
+        assert_eq!(count(0x10), 4 + 2 + 10 * 2 + 9 * 3 + 2);
+        // LDA/STA pairs of 4 and 3 cycles later
+        assert_eq!(bus.read(0x14, true), (count(0x10) + 3 * 7) as u8);
+    }
+
+    #[test]
+    fn latches_and_wraps() {
+        let events = EventQueue::default();
+        let mut counter = CycleCounter { events, since: u64::MAX - 0xFF, latched: 0 };
+        // now is 0, 256 cycles past since
+        assert_eq!(counter.read(0), 0x00);
+        assert_eq!((counter.read(1), counter.read(2), counter.read(3)), (0x01, 0x00, 0x00));
+        counter.write(4, 0);
+        assert_eq!(counter.count(), 0);
+        assert_eq!(counter.peek(1), 0);
+    }
+}
@@ -0,0 +1,190 @@
+// Instruction timing against the published NMOS 6502 cycle table. The
+// base counts and which opcodes take the extra cycle on a page crossing
+// are checked against the lookup table, then every indexed read, store and
+// read-modify-write is run with and without a crossing.
+
+use crate::{cpu6502, is_documented, FLAGS6502};
+
+const CODE: u16 = 0x0200;
+
+// Opcode, base cycles and whether crossing a page adds one. Branches are
+// listed at 2, taking one and crossing a page are the branch's own doing.
+const PUBLISHED: &[(u8, u8, bool)] = &[
+    // ADC
+    (0x69, 2, false), (0x65, 3, false), (0x75, 4, false), (0x6D, 4, false), (0x7D, 4, true), (0x79, 4, true), (0x61, 6, false), (0x71, 5, true),
+    // AND
+    (0x29, 2, false), (0x25, 3, false), (0x35, 4, false), (0x2D, 4, false), (0x3D, 4, true), (0x39, 4, true), (0x21, 6, false), (0x31, 5, true),
+    // ASL
+    (0x0A, 2, false), (0x06, 5, false), (0x16, 6, false), (0x0E, 6, false), (0x1E, 7, false),
+    // Branches
+    (0x90, 2, false), (0xB0, 2, false), (0xF0, 2, false), (0x30, 2, false), (0xD0, 2, false), (0x10, 2, false), (0x50, 2, false), (0x70, 2, false),
+    // BIT, BRK
+    (0x24, 3, false), (0x2C, 4, false), (0x00, 7, false),
+    // Flag clears
+    (0x18, 2, false), (0xD8, 2, false), (0x58, 2, false), (0xB8, 2, false),
+    // CMP
+    (0xC9, 2, false), (0xC5, 3, false), (0xD5, 4, false), (0xCD, 4, false), (0xDD, 4, true), (0xD9, 4, true), (0xC1, 6, false), (0xD1, 5, true),
+    // CPX, CPY
+    (0xE0, 2, false), (0xE4, 3, false), (0xEC, 4, false), (0xC0, 2, false), (0xC4, 3, false), (0xCC, 4, false),
+    // DEC, DEX, DEY
+    (0xC6, 5, false), (0xD6, 6, false), (0xCE, 6, false), (0xDE, 7, false), (0xCA, 2, false), (0x88, 2, false),
+    // EOR
+    (0x49, 2, false), (0x45, 3, false), (0x55, 4, false), (0x4D, 4, false), (0x5D, 4, true), (0x59, 4, true), (0x41, 6, false), (0x51, 5, true),
+    // INC, INX, INY
+    (0xE6, 5, false), (0xF6, 6, false), (0xEE, 6, false), (0xFE, 7, false), (0xE8, 2, false), (0xC8, 2, false),
+    // JMP, JSR
+    (0x4C, 3, false), (0x6C, 5, false), (0x20, 6, false),
+    // LDA
+    (0xA9, 2, false), (0xA5, 3, false), (0xB5, 4, false), (0xAD, 4, false), (0xBD, 4, true), (0xB9, 4, true), (0xA1, 6, false), (0xB1, 5, true),
+    // LDX
+    (0xA2, 2, false), (0xA6, 3, false), (0xB6, 4, false), (0xAE, 4, false), (0xBE, 4, true),
+    // LDY
+    (0xA0, 2, false), (0xA4, 3, false), (0xB4, 4, false), (0xAC, 4, false), (0xBC, 4, true),
+    // LSR
+    (0x4A, 2, false), (0x46, 5, false), (0x56, 6, false), (0x4E, 6, false), (0x5E, 7, false),
+    // NOP
+    (0xEA, 2, false),
+    // ORA
+    (0x09, 2, false), (0x05, 3, false), (0x15, 4, false), (0x0D, 4, false), (0x1D, 4, true), (0x19, 4, true), (0x01, 6, false), (0x11, 5, true),
+    // Stack
+    (0x48, 3, false), (0x08, 3, false), (0x68, 4, false), (0x28, 4, false),
+    // ROL
+    (0x2A, 2, false), (0x26, 5, false), (0x36, 6, false), (0x2E, 6, false), (0x3E, 7, false),
+    // ROR
+    (0x6A, 2, false), (0x66, 5, false), (0x76, 6, false), (0x6E, 6, false), (0x7E, 7, false),
+    // RTI, RTS
+    (0x40, 6, false), (0x60, 6, false),
+    // SBC
+    (0xE9, 2, false), (0xE5, 3, false), (0xF5, 4, false), (0xED, 4, false), (0xFD, 4, true), (0xF9, 4, true), (0xE1, 6, false), (0xF1, 5, true),
+    // Flag sets
+    (0x38, 2, false), (0xF8, 2, false), (0x78, 2, false),
+    // STA
+    (0x85, 3, false), (0x95, 4, false), (0x8D, 4, false), (0x9D, 5, false), (0x99, 5, false), (0x81, 6, false), (0x91, 6, false),
+    // STX, STY
+    (0x86, 3, false), (0x96, 4, false), (0x8E, 4, false), (0x84, 3, false), (0x94, 4, false), (0x8C, 4, false),
+    // Transfers
+    (0xAA, 2, false), (0xA8, 2, false), (0xBA, 2, false), (0x8A, 2, false), (0x9A, 2, false), (0x98, 2, false),
+];
+
+// Runs the instruction at CODE with X and Y at 1 and the indexed base (or
+// the pointer at $80 for (zp),Y) at `base`. Returns the cycles it took.
+fn indexed_cycles(opcode: u8, base: u16) -> u32 {
+    let mut cpu = cpu6502::new();
+    let code = match cpu.addr_mode_name(opcode) {
+        "IZY" => {
+            cpu.load_program(&[base as u8, (base >> 8) as u8], 0x0080);
+            vec![opcode, 0x80]
+        }
+        _ => vec![opcode, base as u8, (base >> 8) as u8],
+    };
+    cpu.load_program(&code, CODE);
+    cpu.x = 1;
+    cpu.y = 1;
+    cpu.pc = CODE;
+
+    let mut cycles = 1;
+    cpu.clock();
+    while !cpu.complete() {
+        cpu.clock();
+        cycles += 1;
+    }
+    cycles
+}
+
+#[test]
+fn lookup_matches_published_table() {
+    let cpu = cpu6502::new();
+    let mut failures = Vec::new();
+
+    for &(opcode, cycles, penalty) in PUBLISHED {
+        let instruction = &cpu.lookup[opcode as usize];
+        if (instruction.cycles, instruction.page_penalty) != (cycles, penalty) {
+            failures.push(std::format!(
+                "{:02x} {}: {} cycles, penalty {}, published {} and {}",
+                opcode, instruction.name, instruction.cycles, instruction.page_penalty, cycles, penalty
+            ));
+        }
+    }
+
+    assert!(failures.is_empty(), "\n{}", failures.join("\n"));
+    assert_eq!(PUBLISHED.len(), (0..=0xFF).filter(|&opcode| is_documented(opcode)).count());
+}
+
+#[test]
+fn page_crossings_cost_what_the_table_says() {
+    let cpu = cpu6502::new();
+    let mut failures = Vec::new();
+
+    let indexed = PUBLISHED.iter().filter(|(opcode, _, _)| matches!(cpu.addr_mode_name(*opcode), "ABX" | "ABY" | "IZY"));
+    for &(opcode, cycles, penalty) in indexed {
+        let expected = [cycles as u32, cycles as u32 + penalty as u32];
+        let found = [indexed_cycles(opcode, 0x1000), indexed_cycles(opcode, 0x10FF)];
+        if found != expected {
+            failures.push(std::format!("{:02x} {}: {:?} cycles without and with a crossing, expected {:?}", opcode, cpu.instruction_name(opcode), found, expected));
+        }
+    }
+
+    assert!(failures.is_empty(), "\n{}", failures.join("\n"));
+}
+
+// The eight branches, the flag each tests and whether it branches with
+// the flag set
+const BRANCHES: [(u8, FLAGS6502, bool); 8] = [
+    (0x10, FLAGS6502::N, false),
+    (0x30, FLAGS6502::N, true),
+    (0x50, FLAGS6502::V, false),
+    (0x70, FLAGS6502::V, true),
+    (0x90, FLAGS6502::C, false),
+    (0xB0, FLAGS6502::C, true),
+    (0xD0, FLAGS6502::Z, false),
+    (0xF0, FLAGS6502::Z, true),
+];
+
+// Runs the branch at `at` with `offset`, the flag set or clear. Returns the
+// cycles it took and where it left PC.
+fn branch_cycles(opcode: u8, flag: FLAGS6502, set: bool, at: u16, offset: u8) -> (u32, u16) {
+    let mut cpu = cpu6502::new();
+    cpu.load_program(&[opcode, offset], at);
+    cpu.set_flag(flag, set);
+    cpu.pc = at;
+
+    let mut cycles = 1;
+    cpu.clock();
+    while !cpu.complete() {
+        cpu.clock();
+        cycles += 1;
+    }
+    (cycles, cpu.pc)
+}
+
+#[test]
+fn branches_cost_two_three_or_four() {
+    let mut failures = Vec::new();
+
+    for (opcode, flag, when) in BRANCHES {
+        // The page is the one the next instruction is on, so from $02F0
+        // +$0D stays on $02 and +$0E lands on $03
+        let cases = [
+            ("not taken", !when, 0x02F0, 0x40, 2, 0x02F2),
+            ("taken", when, 0x02F0, 0x0D, 3, 0x02FF),
+            ("taken across a page", when, 0x02F0, 0x0E, 4, 0x0300),
+            ("taken back across a page", when, 0x0300, 0xFC, 4, 0x02FE),
+            ("taken back", when, 0x0310, 0xFC, 3, 0x030E),
+            ("not taken across a page", !when, 0x02FE, 0x10, 2, 0x0300),
+        ];
+        for (case, set, at, offset, cycles, pc) in cases {
+            let found = branch_cycles(opcode, flag, set, at, offset);
+            if found != (cycles, pc) {
+                failures.push(std::format!("{:02x} {} at ${:04x} +{:02x}: {} cycles to ${:04x}, expected {} to ${:04x}", opcode, case, at, offset, found.0, found.1, cycles, pc));
+            }
+        }
+    }
+
+    assert!(failures.is_empty(), "\n{}", failures.join("\n"));
+}
+
+#[test]
+fn the_t_state_audit_passes() {
+    let divergences = crate::tstates::audit();
+    assert!(divergences.is_empty(), "\n{}", divergences.join("\n"));
+}
@@ -0,0 +1,413 @@
+use crate::error::EmuError;
+use crate::{cpu6502, FLAGS6502};
+
+// 1541 disk images, and enough of the C64 KERNAL's disk side to LOAD from
+// one without emulating the drive. A JSR to SETLFS, SETNAM or LOAD is
+// serviced here instead and returns as the ROM routine would, leaving the
+// same zero page variables behind, so this works with or without a KERNAL
+// image loaded.
+//
+// A D64 is the disk's sectors in order, tracks 1-35 (or 40) of 21 down to
+// 17 sectors of 256 bytes. The directory starts at track 18 sector 1 and
+// files are chains of sectors, each starting with the track and sector
+// of the next one.
+
+const SECTOR_SIZE: usize = 256;
+const DIRECTORY_TRACK: u8 = 18;
+// Disk name, in the BAM at track 18 sector 0
+const DISK_NAME: usize = 0x90;
+// Names are padded out to 16 characters with shifted spaces
+const NAME_PAD: u8 = 0xA0;
+
+// The KERNAL's jump table entries
+pub const SETLFS: u16 = 0xFFBA;
+pub const SETNAM: u16 = 0xFFBD;
+pub const LOAD: u16 = 0xFFD5;
+
+// Where SETLFS and SETNAM leave their arguments, and LOAD its end address
+// and status
+const ZP_STATUS: u16 = 0x90;
+const ZP_END: u16 = 0xAE;
+const ZP_NAME_LEN: u16 = 0xB7;
+const ZP_LOGICAL: u16 = 0xB8;
+const ZP_SECONDARY: u16 = 0xB9;
+const ZP_DEVICE: u16 = 0xBA;
+const ZP_NAME: u16 = 0xBB;
+
+// LOAD's error codes, in A with carry set
+const ERROR_FILE_NOT_FOUND: u8 = 4;
+const ERROR_DEVICE_NOT_PRESENT: u8 = 5;
+const ERROR_MISSING_FILE_NAME: u8 = 8;
+
+const STATUS_EOF: u8 = 0x40;
+
+fn sectors_in_track(track: u8) -> usize {
+    match track {
+        1..=17 => 21,
+        18..=24 => 19,
+        25..=30 => 18,
+        _ => 17,
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FileType {
+    Del,
+    Seq,
+    Prg,
+    Usr,
+    Rel,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DirEntry {
+    // PETSCII, without the padding
+    pub name: Vec<u8>,
+    pub file_type: FileType,
+    pub track: u8,
+    pub sector: u8,
+    pub blocks: u16,
+}
+
+impl DirEntry {
+    // The name with anything that isn't plain ASCII as '?'
+    pub fn display_name(&self) -> String {
+        petscii_to_string(&self.name)
+    }
+}
+
+fn petscii_to_string(petscii: &[u8]) -> String {
+    petscii.iter().map(|&c| if (0x20..0x7F).contains(&c) { c as char } else { '?' }).collect()
+}
+
+// A name matches a pattern where every character does, '?' matching any,
+// up to a '*' which matches whatever is left
+fn matches(pattern: &[u8], name: &[u8]) -> bool {
+    let mut name = name.iter();
+    for &p in pattern {
+        if p == b'*' {
+            return true;
+        }
+        match name.next() {
+            Some(&c) if p == b'?' || p == c => (),
+            _ => return false,
+        }
+    }
+    name.next().is_none()
+}
+
+pub struct D64 {
+    bytes: Vec<u8>,
+    tracks: u8,
+}
+
+impl D64 {
+    // 35 or 40 tracks, with or without the error bytes on the end
+    pub fn from_bytes(bytes: &[u8]) -> Result<D64, EmuError> {
+        let tracks = match bytes.len() {
+            174848 | 175531 => 35,
+            196608 | 197376 => 40,
+            len => return Err(EmuError::BadImage(std::format!("{} bytes isn't the size of a D64 image", len))),
+        };
+        Ok(D64 { bytes: bytes.to_vec(), tracks })
+    }
+
+    fn sector(&self, track: u8, sector: u8) -> Result<&[u8], EmuError> {
+        if track == 0 || track > self.tracks || sector as usize >= sectors_in_track(track) {
+            return Err(EmuError::BadImage(std::format!("no track {} sector {} on the disk", track, sector)));
+        }
+        let before: usize = (1..track).map(sectors_in_track).sum();
+        let start = (before + sector as usize) * SECTOR_SIZE;
+        Ok(&self.bytes[start..start + SECTOR_SIZE])
+    }
+
+    // The data in a chain of sectors, refusing one that loops
+    fn chain(&self, mut track: u8, mut sector: u8) -> Result<Vec<u8>, EmuError> {
+        let limit = self.bytes.len() / SECTOR_SIZE;
+        let mut data = Vec::new();
+        for _ in 0..limit {
+            let block = self.sector(track, sector)?;
+            if block[0] == 0 {
+                // The second byte is the last one used
+                let last = (block[1] as usize).max(1);
+                data.extend_from_slice(&block[2..=last]);
+                return Ok(data);
+            }
+            data.extend_from_slice(&block[2..]);
+            track = block[0];
+            sector = block[1];
+        }
+        Err(EmuError::BadImage("sector chain loops".to_string()))
+    }
+
+    pub fn disk_name(&self) -> String {
+        let bam = self.sector(DIRECTORY_TRACK, 0).unwrap_or(&[]);
+        let name = bam.get(DISK_NAME..DISK_NAME + 16).unwrap_or(&[]);
+        let end = name.iter().position(|&c| c == NAME_PAD).unwrap_or(name.len());
+        petscii_to_string(&name[..end])
+    }
+
+    // The files in the directory, scratched ones left out
+    pub fn entries(&self) -> Result<Vec<DirEntry>, EmuError> {
+        let mut entries = Vec::new();
+        let (mut track, mut sector) = (DIRECTORY_TRACK, 1);
+        let limit = sectors_in_track(DIRECTORY_TRACK);
+
+        for _ in 0..limit {
+            let block = self.sector(track, sector)?;
+            for entry in block.chunks(32) {
+                let file_type = match entry[2] & 0x07 {
+                    _ if entry[2] == 0 => continue,
+                    0 => FileType::Del,
+                    1 => FileType::Seq,
+                    2 => FileType::Prg,
+                    3 => FileType::Usr,
+                    _ => FileType::Rel,
+                };
+                let name = &entry[5..21];
+                let end = name.iter().position(|&c| c == NAME_PAD).unwrap_or(name.len());
+                entries.push(DirEntry {
+                    name: name[..end].to_vec(),
+                    file_type,
+                    track: entry[3],
+                    sector: entry[4],
+                    blocks: entry[30] as u16 | (entry[31] as u16) << 8,
+                });
+            }
+            if block[0] == 0 {
+                return Ok(entries);
+            }
+            track = block[0];
+            sector = block[1];
+        }
+        Err(EmuError::BadImage("directory chain loops".to_string()))
+    }
+
+    // The first PRG file matching a LOAD style pattern, "*" being any
+    pub fn find(&self, pattern: &[u8]) -> Result<Option<DirEntry>, EmuError> {
+        // A drive number in front, "0:NAME", makes no difference here
+        let pattern = match pattern {
+            [b'0' | b'1', b':', rest @ ..] => rest,
+            _ => pattern,
+        };
+        Ok(self.entries()?.into_iter().find(|entry| entry.file_type == FileType::Prg && matches(pattern, &entry.name)))
+    }
+
+    // A file's contents, the load address first for a PRG
+    pub fn read_file(&self, entry: &DirEntry) -> Result<Vec<u8>, EmuError> {
+        self.chain(entry.track, entry.sector)
+    }
+}
+
+// Where a program that starts with a BASIC line of just "SYS <address>"
+// actually starts, which is how most machine code on disk is launched
+pub fn sys_address(prg: &[u8]) -> Option<u16> {
+    // Load address, link to the next line, line number, then the SYS token
+    let line = prg.get(6..)?;
+    if prg.get(..2)? != [0x01, 0x08] || line.first() != Some(&0x9E) {
+        return None;
+    }
+    let digits: String = line[1..].iter().skip_while(|&&c| c == b' ').take_while(|c| c.is_ascii_digit()).map(|&c| c as char).collect();
+    digits.parse().ok()
+}
+
+// The disk in drive 8, for LOAD to read from
+pub struct KernalShim {
+    disk: D64,
+}
+
+impl KernalShim {
+    pub fn disk(&self) -> &D64 {
+        &self.disk
+    }
+}
+
+impl cpu6502 {
+    // Puts a disk in drive 8 and starts servicing the KERNAL's disk calls
+    pub fn insert_disk(&mut self, disk: D64) {
+        self.kernal = Some(KernalShim { disk });
+    }
+
+    pub fn eject_disk(&mut self) {
+        self.kernal = None;
+    }
+
+    pub fn kernal_shim(&self) -> Option<&KernalShim> {
+        self.kernal.as_ref()
+    }
+
+    // Called after a JSR, with the same contract as trap_call: true if the
+    // target was a KERNAL entry serviced here, which has then returned
+    pub(crate) fn kernal_call(&mut self) -> bool {
+        if self.kernal.is_none() {
+            return false;
+        }
+
+        match self.pc {
+            SETLFS => {
+                self.write(ZP_LOGICAL, self.a);
+                self.write(ZP_DEVICE, self.x);
+                self.write(ZP_SECONDARY, self.y);
+            }
+            SETNAM => {
+                self.write(ZP_NAME_LEN, self.a);
+                self.write(ZP_NAME, self.x);
+                self.write(ZP_NAME + 1, self.y);
+            }
+            LOAD => {
+                let result = self.kernal_load();
+                if let Err(error) = result {
+                    self.a = error;
+                }
+                self.set_flag(FLAGS6502::C, result.is_err());
+            }
+            _ => return false,
+        }
+
+        true
+    }
+
+    // LOAD with A=0, to X/Y for secondary address 0 and otherwise to the
+    // file's own address. Verifying (A=1) just succeeds.
+    fn kernal_load(&mut self) -> Result<(), u8> {
+        if self.read(ZP_DEVICE) != 8 {
+            return Err(ERROR_DEVICE_NOT_PRESENT);
+        }
+        let len = self.read(ZP_NAME_LEN);
+        if len == 0 {
+            return Err(ERROR_MISSING_FILE_NAME);
+        }
+        let at = self.read(ZP_NAME) as u16 | (self.read(ZP_NAME + 1) as u16) << 8;
+        let name: Vec<u8> = (0..len as u16).map(|i| self.read(at.wrapping_add(i))).collect();
+
+        let disk = match &self.kernal {
+            Some(shim) => &shim.disk,
+            None => return Err(ERROR_DEVICE_NOT_PRESENT),
+        };
+        let file = match disk.find(&name) {
+            Ok(Some(entry)) => disk.read_file(&entry).map_err(|_| ERROR_FILE_NOT_FOUND)?,
+            _ => return Err(ERROR_FILE_NOT_FOUND),
+        };
+        if file.len() < 2 {
+            return Err(ERROR_FILE_NOT_FOUND);
+        }
+
+        let mut addr = file[0] as u16 | (file[1] as u16) << 8;
+        if self.read(ZP_SECONDARY) == 0 {
+            addr = self.x as u16 | (self.y as u16) << 8;
+        }
+        if self.a == 0 {
+            for (i, &byte) in file[2..].iter().enumerate() {
+                self.write(addr.wrapping_add(i as u16), byte);
+            }
+        }
+
+        let end = addr.wrapping_add((file.len() - 2) as u16);
+        self.write(ZP_END, end as u8);
+        self.write(ZP_END + 1, (end >> 8) as u8);
+        self.write(ZP_STATUS, STATUS_EOF);
+        self.x = end as u8;
+        self.y = (end >> 8) as u8;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    fn offset(track: u8, sector: u8) -> usize {
+        ((1..track).map(sectors_in_track).sum::<usize>() + sector as usize) * SECTOR_SIZE
+    }
+
+    // A 35 track disk called TEST holding `files`, each in a chain of
+    // sectors on track 1 onwards
+    pub(crate) fn disk(files: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut bytes = vec![0; 174848];
+        let bam = offset(DIRECTORY_TRACK, 0);
+        bytes[bam + DISK_NAME..bam + DISK_NAME + 16].fill(NAME_PAD);
+        bytes[bam + DISK_NAME..bam + DISK_NAME + 4].copy_from_slice(b"TEST");
+
+        let directory = offset(DIRECTORY_TRACK, 1);
+        bytes[directory + 1] = 0xFF;
+        let mut sector = 0;
+        for (i, (name, data)) in files.iter().enumerate() {
+            let entry = directory + i * 32;
+            bytes[entry + 2] = 0x82;
+            bytes[entry + 3] = 1;
+            bytes[entry + 4] = sector;
+            bytes[entry + 5..entry + 21].fill(NAME_PAD);
+            bytes[entry + 5..entry + 5 + name.len()].copy_from_slice(name.as_bytes());
+
+            let blocks: Vec<&[u8]> = data.chunks(254).collect();
+            bytes[entry + 30] = blocks.len() as u8;
+            for (j, block) in blocks.iter().enumerate() {
+                let at = offset(1, sector);
+                if j + 1 == blocks.len() {
+                    bytes[at + 1] = block.len() as u8 + 1;
+                } else {
+                    bytes[at] = 1;
+                    bytes[at + 1] = sector + 1;
+                }
+                bytes[at + 2..at + 2 + block.len()].copy_from_slice(block);
+                sector += 1;
+            }
+        }
+        bytes
+    }
+
+    #[test]
+    fn directory_and_files() {
+        let big: Vec<u8> = (0..600).map(|i| i as u8).collect();
+        let d64 = D64::from_bytes(&disk(&[("FIRST", &[0x00, 0xC0, 1, 2, 3]), ("BIGGER FILE", &big)])).unwrap();
+
+        assert_eq!(d64.disk_name(), "TEST");
+        let entries = d64.entries().unwrap();
+        let names: Vec<String> = entries.iter().map(DirEntry::display_name).collect();
+        assert_eq!(names, ["FIRST", "BIGGER FILE"]);
+        assert_eq!(entries[1].blocks, 3);
+
+        assert_eq!(d64.read_file(&entries[1]).unwrap(), big);
+        assert_eq!(d64.find(b"*").unwrap().unwrap().display_name(), "FIRST");
+        assert_eq!(d64.find(b"0:BIG*").unwrap().unwrap().display_name(), "BIGGER FILE");
+        assert_eq!(d64.find(b"F?RST").unwrap().unwrap().display_name(), "FIRST");
+        assert!(d64.find(b"FIRS").unwrap().is_none());
+
+        assert!(D64::from_bytes(&[0; 1000]).is_err());
+    }
+
+    #[test]
+    fn sys_lines() {
+        // 10 SYS 2064
+        let prg = [0x01, 0x08, 0x0C, 0x08, 0x0A, 0x00, 0x9E, b' ', b'2', b'0', b'6', b'4', 0x00, 0x00, 0x00];
+        assert_eq!(sys_address(&prg), Some(2064));
+        assert_eq!(sys_address(&[0x00, 0xC0, 0xEA]), None);
+    }
+
+    #[test]
+    fn load_trap() {
+        let mut cpu = cpu6502::new();
+        let d64 = D64::from_bytes(&disk(&[("DATA", &[0x00, 0x20, 0xAA, 0xBB])])).unwrap();
+        cpu.insert_disk(d64);
+
+        // LDA #8 / TAX / LDY #1 / JSR SETLFS / LDA #4 / LDX #<name / LDY #>name
+        // JSR SETNAM / LDA #0 / JSR LOAD / loop: JMP loop / name: "DATA"
+        cpu.load_program(
+            &[
+                0xA9, 0x08, 0xAA, 0xA0, 0x01, 0x20, 0xBA, 0xFF, 0xA9, 0x04, 0xA2, 0x1A, 0xA0, 0x80, 0x20, 0xBD, 0xFF, 0xA9, 0x00, 0x20,
+                0xD5, 0xFF, 0x4C, 0x16, 0x80, 0xEA, b'D', b'A', b'T', b'A',
+            ],
+            0x8000,
+        );
+        cpu.set_reset_vector(0x8000);
+        cpu.reset();
+        for _ in 0..100 {
+            cpu.clock();
+        }
+
+        let bus = cpu.bus.borrow();
+        assert_eq!((bus.read(0x2000, true), bus.read(0x2001, true)), (0xAA, 0xBB));
+        drop(bus);
+        assert_eq!((cpu.x, cpu.y, cpu.get_flag(FLAGS6502::C)), (0x02, 0x20, 0));
+    }
+}
@@ -0,0 +1,314 @@
+use std::collections::{BTreeSet, VecDeque};
+
+use crate::cpu6502;
+use crate::replay::Stimulus;
+use crate::vectors::Vector;
+
+// How many edits we remember before the oldest ones fall off the stack
+const MAX_UNDO: usize = 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Register {
+    A,
+    X,
+    Y,
+    SP,
+    PC,
+    STATUS,
+}
+
+impl Register {
+    pub fn from_name(name: &str) -> Option<Register> {
+        match name.trim().to_ascii_lowercase().as_str() {
+            "a" => Some(Register::A),
+            "x" => Some(Register::X),
+            "y" => Some(Register::Y),
+            "sp" => Some(Register::SP),
+            "pc" => Some(Register::PC),
+            "p" => Some(Register::STATUS),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Register::A => "a",
+            Register::X => "x",
+            Register::Y => "y",
+            Register::SP => "sp",
+            Register::PC => "pc",
+            Register::STATUS => "p",
+        }
+    }
+
+    pub fn get(&self, cpu: &cpu6502) -> u16 {
+        match self {
+            Register::A => cpu.a as u16,
+            Register::X => cpu.x as u16,
+            Register::Y => cpu.y as u16,
+            Register::SP => cpu.stkp as u16,
+            Register::PC => cpu.pc,
+            Register::STATUS => cpu.status as u16,
+        }
+    }
+
+    pub fn set(&self, cpu: &mut cpu6502, value: u16) {
+        match self {
+            Register::A => cpu.a = value as u8,
+            Register::X => cpu.x = value as u8,
+            Register::Y => cpu.y = value as u8,
+            Register::SP => cpu.stkp = value as u8,
+            Register::PC => cpu.pc = value,
+            Register::STATUS => cpu.set_status(value as u8),
+        }
+    }
+}
+
+// Holds a memory location at a fixed value while enabled, e.g. a lives counter
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cheat {
+    pub addr: u16,
+    pub value: u8,
+    pub enabled: bool,
+}
+
+// A single modification made from the debugger. Every edit remembers the
+// value it replaced so it can be played backwards as well as forwards.
+// Machine state is only touched through cpu6502::stimulate, so edits made
+// while recording end up in the recording.
+#[derive(Debug, Clone)]
+pub enum Edit {
+    Poke { addr: u16, old: u8, new: u8 },
+    Register { reg: Register, old: u16, new: u16 },
+    // Index into Debugger::cheats
+    Cheat { index: usize, old: bool, new: bool },
+    // Both bytes at once, so one undo puts the old handler back
+    Vector { vector: Vector, old: u16, new: u16 },
+}
+
+impl Edit {
+    fn apply(&self, cpu: &mut cpu6502, cheats: &mut [Cheat], forward: bool) {
+        match *self {
+            Edit::Poke { addr, old, new } => {
+                cpu.stimulate(Stimulus::Write { addr, data: if forward { new } else { old } });
+            }
+            Edit::Register { reg, old, new } => {
+                cpu.stimulate(Stimulus::Register { reg, value: if forward { new } else { old } });
+            }
+            Edit::Cheat { index, old, new } => {
+                if let Some(cheat) = cheats.get_mut(index) {
+                    cheat.enabled = if forward { new } else { old };
+                }
+            }
+            Edit::Vector { vector, old, new } => {
+                let addr = if forward { new } else { old };
+                cpu.stimulate(Stimulus::Write { addr: vector.addr(), data: addr as u8 });
+                cpu.stimulate(Stimulus::Write { addr: vector.addr().wrapping_add(1), data: (addr >> 8) as u8 });
+            }
+        }
+    }
+}
+
+// The command layer all debugger-initiated mutations go through, so that
+// any of them can be reverted without reloading state.
+pub struct Debugger {
+    undo_stack: VecDeque<Edit>,
+    redo_stack: Vec<Edit>,
+    pub breakpoints: BTreeSet<u16>,
+    // Expressions shown with their values, worked out afresh each time
+    pub watches: Vec<String>,
+    // Cheats are only ever added, so indices into this stay valid for the
+    // edits that refer to them
+    cheats: Vec<Cheat>,
+    // Free running until a breakpoint is hit, instead of single stepping
+    pub running: bool,
+}
+
+impl Debugger {
+    pub fn new() -> Self {
+        Debugger {
+            undo_stack: VecDeque::new(),
+            redo_stack: Vec::new(),
+            breakpoints: BTreeSet::new(),
+            watches: Vec::new(),
+            cheats: Vec::new(),
+            running: false,
+        }
+    }
+
+    pub fn is_breakpoint(&self, addr: u16) -> bool {
+        self.breakpoints.contains(&addr)
+    }
+
+    pub fn poke(&mut self, cpu: &mut cpu6502, addr: u16, value: u8) {
+        let old = cpu.bus.borrow().read(addr, true);
+        self.execute(cpu, Edit::Poke { addr, old, new: value });
+    }
+
+    pub fn set_vector(&mut self, cpu: &mut cpu6502, vector: Vector, addr: u16) {
+        let old = cpu.vector(vector);
+        self.execute(cpu, Edit::Vector { vector, old, new: addr });
+    }
+
+    pub fn set_register(&mut self, cpu: &mut cpu6502, reg: Register, value: u16) {
+        let old = reg.get(cpu);
+        self.execute(cpu, Edit::Register { reg, old, new: value });
+    }
+
+    // Adds a cheat, switched off, and returns its index
+    pub fn add_cheat(&mut self, addr: u16, value: u8) -> usize {
+        self.cheats.push(Cheat { addr, value, enabled: false });
+        self.cheats.len() - 1
+    }
+
+    pub fn cheats(&self) -> &[Cheat] {
+        &self.cheats
+    }
+
+    pub fn set_cheat(&mut self, cpu: &mut cpu6502, index: usize, enabled: bool) -> bool {
+        match self.cheats.get(index) {
+            Some(cheat) => {
+                let old = cheat.enabled;
+                self.execute(cpu, Edit::Cheat { index, old, new: enabled });
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Called once per frame to hold every enabled cheat's location
+    pub fn apply_cheats(&self, cpu: &mut cpu6502) {
+        for cheat in self.cheats.iter().filter(|cheat| cheat.enabled) {
+            cpu.stimulate(Stimulus::Write { addr: cheat.addr, data: cheat.value });
+        }
+    }
+
+    pub fn execute(&mut self, cpu: &mut cpu6502, edit: Edit) {
+        edit.apply(cpu, &mut self.cheats, true);
+
+        self.undo_stack.push_back(edit);
+        if self.undo_stack.len() > MAX_UNDO {
+            self.undo_stack.pop_front();
+        }
+
+        // A fresh edit invalidates anything we could have redone
+        self.redo_stack.clear();
+    }
+
+    pub fn undo(&mut self, cpu: &mut cpu6502) -> bool {
+        if let Some(edit) = self.undo_stack.pop_back() {
+            edit.apply(cpu, &mut self.cheats, false);
+            self.redo_stack.push(edit);
+            true
+        } else {
+            false
+        }
+    }
+
+    pub fn redo(&mut self, cpu: &mut cpu6502) -> bool {
+        if let Some(edit) = self.redo_stack.pop() {
+            edit.apply(cpu, &mut self.cheats, true);
+            self.undo_stack.push_back(edit);
+            true
+        } else {
+            false
+        }
+    }
+
+    pub fn undo_depth(&self) -> usize {
+        self.undo_stack.len()
+    }
+
+    pub fn redo_depth(&self) -> usize {
+        self.redo_stack.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn poke_undo_redo() {
+        let mut cpu = cpu6502::new();
+        let mut debugger = Debugger::new();
+
+        cpu.bus.borrow_mut().write(0x0200, 0x11);
+        debugger.poke(&mut cpu, 0x0200, 0x42);
+        assert_eq!(cpu.bus.borrow().read(0x0200, true), 0x42);
+
+        assert!(debugger.undo(&mut cpu));
+        assert_eq!(cpu.bus.borrow().read(0x0200, true), 0x11);
+        assert_eq!(debugger.redo_depth(), 1);
+
+        assert!(debugger.redo(&mut cpu));
+        assert_eq!(cpu.bus.borrow().read(0x0200, true), 0x42);
+        assert!(!debugger.redo(&mut cpu));
+    }
+
+    #[test]
+    fn register_edits_undo_in_order() {
+        let mut cpu = cpu6502::new();
+        let mut debugger = Debugger::new();
+
+        cpu.a = 1;
+        debugger.set_register(&mut cpu, Register::A, 2);
+        debugger.set_register(&mut cpu, Register::A, 3);
+        debugger.set_register(&mut cpu, Register::PC, 0x8000);
+
+        assert!(debugger.undo(&mut cpu));
+        assert_eq!(cpu.pc, 0);
+        assert!(debugger.undo(&mut cpu));
+        assert_eq!(cpu.a, 2);
+        assert!(debugger.undo(&mut cpu));
+        assert_eq!(cpu.a, 1);
+        assert!(!debugger.undo(&mut cpu));
+    }
+
+    #[test]
+    fn new_edit_clears_redo() {
+        let mut cpu = cpu6502::new();
+        let mut debugger = Debugger::new();
+
+        debugger.poke(&mut cpu, 0x10, 1);
+        debugger.undo(&mut cpu);
+        debugger.poke(&mut cpu, 0x11, 2);
+
+        assert_eq!(debugger.redo_depth(), 0);
+        assert!(!debugger.redo(&mut cpu));
+    }
+
+    #[test]
+    fn oldest_edits_fall_off() {
+        let mut cpu = cpu6502::new();
+        let mut debugger = Debugger::new();
+
+        for i in 0..MAX_UNDO + 10 {
+            debugger.poke(&mut cpu, 0x0300, i as u8);
+        }
+
+        assert_eq!(debugger.undo_depth(), MAX_UNDO);
+    }
+
+    #[test]
+    fn cheat_toggles_are_undoable() {
+        let mut cpu = cpu6502::new();
+        let mut debugger = Debugger::new();
+
+        let index = debugger.add_cheat(0x0050, 9);
+        assert!(debugger.set_cheat(&mut cpu, index, true));
+
+        cpu.bus.borrow_mut().write(0x0050, 0);
+        debugger.apply_cheats(&mut cpu);
+        assert_eq!(cpu.bus.borrow().read(0x0050, true), 9);
+
+        assert!(debugger.undo(&mut cpu));
+        assert!(!debugger.cheats()[index].enabled);
+
+        cpu.bus.borrow_mut().write(0x0050, 0);
+        debugger.apply_cheats(&mut cpu);
+        assert_eq!(cpu.bus.borrow().read(0x0050, true), 0);
+
+        assert!(!debugger.set_cheat(&mut cpu, 5, true));
+    }
+}
@@ -0,0 +1,207 @@
+use crate::assembler::{self, Assembly};
+use crate::cpu6502;
+
+// The programs the emulator starts with, kept as source and assembled at
+// startup. Each ends by spinning at `done` and says what it should have
+// left in memory by then, so it doubles as a check on the CPU.
+
+pub struct Demo {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub source: &'static str,
+    // Address and the value expected there once `done` is reached
+    pub expected: &'static [(u16, u8)],
+}
+
+// Plenty for any of them
+const MAX_CYCLES: u32 = 100_000;
+
+pub const DEMOS: &[Demo] = &[
+    Demo {
+        name: "multiply",
+        description: "10 * 3 by repeated addition, or shift and add, into $02",
+        source: "
+            SHIFT_ADD = 0           ; 1 for shift and add
+            FIRST = $00
+            SECOND = $01
+            RESULT = $02
+            SHIFTED = $03
+
+                    LDX #10
+                    STX FIRST
+                    LDX #3
+                    STX SECOND
+            .if SHIFT_ADD
+                    LDA SECOND
+                    STA SHIFTED
+                    LDA #0
+                    LDX #8
+            bit:    ASL A
+                    ASL SHIFTED
+                    BCC skip
+                    CLC
+                    ADC FIRST
+            skip:   DEX
+                    BNE bit
+            .else
+                    LDY FIRST
+                    LDA #0
+                    CLC
+            add:    ADC SECOND
+                    DEY
+                    BNE add
+            .endif
+                    STA RESULT
+            done:   JMP done
+        ",
+        expected: &[(0x00, 10), (0x01, 3), (0x02, 30)],
+    },
+    Demo {
+        name: "fibonacci",
+        description: "the first 12 Fibonacci numbers from $10",
+        source: "
+            COUNT = 12
+            OUT = $10
+
+                    LDA #1
+                    STA OUT
+                    STA OUT+1
+                    LDX #0
+            next:   LDA OUT,X
+                    CLC
+                    ADC OUT+1,X
+                    STA OUT+2,X
+                    INX
+                    CPX #COUNT-2
+                    BNE next
+            done:   JMP done
+        ",
+        expected: &[
+            (0x10, 1), (0x11, 1), (0x12, 2), (0x13, 3), (0x14, 5), (0x15, 8),
+            (0x16, 13), (0x17, 21), (0x18, 34), (0x19, 55), (0x1A, 89), (0x1B, 144),
+        ],
+    },
+    Demo {
+        name: "sieve",
+        description: "primes below 64 by the sieve of Eratosthenes, count at $1f and the primes from $20",
+        source: "
+            LIMIT = 64
+            SIEVE = $0200           ; non-zero once crossed out
+            STEP = $00
+            COUNT = $1F
+            PRIMES = $20
+
+                    LDX #0
+                    LDA #0
+            clear:  STA SIEVE,X
+                    INX
+                    CPX #LIMIT
+                    BNE clear
+                    STA COUNT
+                    LDX #2
+            next:   LDA SIEVE,X
+                    BNE skip
+                    TXA
+                    LDY COUNT
+                    STA PRIMES,Y
+                    INC COUNT
+                    STX STEP
+            cross:  CLC
+                    ADC STEP
+                    BCS skip
+                    CMP #LIMIT
+                    BCS skip
+                    TAY
+                    LDA #1
+                    STA SIEVE,Y
+                    TYA
+                    JMP cross
+            skip:   INX
+                    CPX #LIMIT
+                    BNE next
+            done:   JMP done
+        ",
+        expected: &[
+            (0x1F, 18),
+            (0x20, 2), (0x21, 3), (0x22, 5), (0x23, 7), (0x24, 11), (0x25, 13), (0x26, 17), (0x27, 19), (0x28, 23),
+            (0x29, 29), (0x2A, 31), (0x2B, 37), (0x2C, 41), (0x2D, 43), (0x2E, 47), (0x2F, 53), (0x30, 59), (0x31, 61),
+        ],
+    },
+];
+
+pub fn find(name: &str) -> Option<&'static Demo> {
+    DEMOS.iter().find(|demo| demo.name == name)
+}
+
+pub fn names() -> Vec<&'static str> {
+    DEMOS.iter().map(|demo| demo.name).collect()
+}
+
+impl Demo {
+    pub fn assemble(&self) -> Result<Assembly, String> {
+        assembler::assemble(self.source).map_err(|e| std::format!("{}: {}", self.name, e))
+    }
+
+    // Assembles the demo into `cpu`, points the reset vector at it and
+    // resets
+    pub fn load(&self, cpu: &mut cpu6502) -> Result<Assembly, String> {
+        let assembly = self.assemble()?;
+        cpu.load_program(&assembly.bytes, assembly.origin);
+        cpu.set_reset_vector(assembly.origin);
+        cpu.reset();
+        Ok(assembly)
+    }
+
+    // Runs the demo on a machine of its own and lists whatever didn't come
+    // out as expected, empty when it all did
+    pub fn verify(&self) -> Vec<String> {
+        let mut cpu = cpu6502::new();
+        let assembly = match self.load(&mut cpu) {
+            Ok(assembly) => assembly,
+            Err(e) => return vec![e],
+        };
+        let done = match assembly.label("done") {
+            Some(done) => done,
+            None => return vec![std::format!("{} has no done label", self.name)],
+        };
+
+        let mut finished = false;
+        for _ in 0..MAX_CYCLES {
+            cpu.clock();
+            if cpu.complete() && cpu.pc == done {
+                finished = true;
+                break;
+            }
+        }
+        if !finished {
+            return vec![std::format!("{} never reached ${:04x}, stopped at ${:04x}", self.name, done, cpu.pc)];
+        }
+
+        let bus = cpu.bus.borrow();
+        self.expected
+            .iter()
+            .filter(|(addr, expected)| bus.read(*addr, true) != *expected)
+            .map(|(addr, expected)| std::format!("{}: ${:04x} is {}, expected {}", self.name, addr, bus.read(*addr, true), expected))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_demo_checks_out() {
+        for demo in DEMOS {
+            assert_eq!(demo.verify(), Vec::<String>::new(), "{}", demo.name);
+        }
+    }
+
+    #[test]
+    fn both_multiplications_agree() {
+        let demo = find("multiply").unwrap();
+        let shift_add = Demo { source: demo.source.replace("SHIFT_ADD = 0", "SHIFT_ADD = 1").leak(), ..*demo };
+        assert_ne!(shift_add.assemble().unwrap().bytes, demo.assemble().unwrap().bytes);
+        assert!(shift_add.verify().is_empty());
+    }
+}
@@ -0,0 +1,93 @@
+use std::any::Any;
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+// Something that answers for a range of the CPU's address space. Offsets
+// are relative to the start of the range it was mapped at. Reading a
+// register often has side effects (a FIFO advancing, a flag clearing), so
+// `read` takes &mut self and `peek` is the side effect free version the
+// debugger and disassembler use. Devices go wherever the CPU goes, which
+// can be another thread, so they have to be Send.
+pub trait BusDevice: Send {
+    fn read(&mut self, offset: u16) -> u8;
+    fn peek(&self, offset: u16) -> u8;
+    fn write(&mut self, offset: u16, data: u8);
+
+    fn reset(&mut self) {}
+
+    // Cycles an access to `offset` holds RDY low for, for a device slower
+    // than the CPU. The NMOS part ignores RDY on a write cycle, so only
+    // the 65C02 waits on writes.
+    fn wait_states(&self, _offset: u16) -> u8 {
+        0
+    }
+
+    // Sound made since the last call, mono at frame::AUDIO_SAMPLE_RATE
+    // between -1.0 and 1.0. Most devices make none.
+    fn take_samples(&mut self) -> Vec<f32> {
+        Vec::new()
+    }
+
+    // For code that put a device on the bus and wants it back as its own
+    // type
+    fn as_any(&self) -> Option<&dyn Any> {
+        None
+    }
+}
+
+// A device and the inclusive range it answers for. Chips often decode
+// fewer address lines than the range they're selected over, so their
+// registers repeat through it: with `mirror` set the offsets the device
+// sees wrap every `mirror` bytes, and only the bits in `mask` of them
+// reach it.
+pub struct Mapping {
+    pub name: String,
+    pub start: u16,
+    pub end: u16,
+    pub mirror: Option<u16>,
+    pub mask: u16,
+    pub device: Mutex<Box<dyn BusDevice>>,
+}
+
+impl Mapping {
+    pub fn contains(&self, addr: u16) -> bool {
+        self.start <= addr && addr <= self.end
+    }
+
+    // What the device sees for an address in the range
+    pub fn offset(&self, addr: u16) -> u16 {
+        let offset = addr - self.start;
+        let offset = match self.mirror {
+            Some(size) => offset % size,
+            None => offset,
+        };
+        offset & self.mask
+    }
+
+    pub fn device(&self) -> MutexGuard<'_, Box<dyn BusDevice>> {
+        self.device.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+// ROM, or an EEPROM as far as the CPU can tell: reads see the image,
+// repeated if it's smaller than where it's mapped, and writes are lost
+pub struct Rom {
+    data: Vec<u8>,
+}
+
+impl Rom {
+    pub fn new(image: &[u8]) -> Self {
+        Rom { data: if image.is_empty() { vec![0] } else { image.to_vec() } }
+    }
+}
+
+impl BusDevice for Rom {
+    fn read(&mut self, offset: u16) -> u8 {
+        self.peek(offset)
+    }
+
+    fn peek(&self, offset: u16) -> u8 {
+        self.data[offset as usize % self.data.len()]
+    }
+
+    fn write(&mut self, _offset: u16, _data: u8) {}
+}
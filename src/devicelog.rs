@@ -0,0 +1,293 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+use crate::analysis::json_escape;
+use crate::cpu6502;
+use crate::scheduler::EventQueue;
+
+// One timeline of what the devices did, for working on device code: the
+// register writes the program made to each mapped device, the IRQ lines
+// going up and down, and whatever a device logs for itself, such as a
+// timer running out. Every event is stamped with the scheduler's cycle, so
+// a run from the same state logs the same thing. Chips worked out lazily
+// log what they find when they catch up, on the cycle they catch up on.
+//
+// Logging is off until something turns it on, and costs a load per event
+// while it's off.
+
+// Events kept, the oldest go first
+const CAPACITY: usize = 10_000;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DeviceEventKind {
+    // A write to one of the device's registers
+    Register,
+    Timer,
+    Irq,
+    Nmi,
+    // Anything else worth knowing
+    Note,
+}
+
+impl DeviceEventKind {
+    pub const ALL: [DeviceEventKind; 5] = [DeviceEventKind::Register, DeviceEventKind::Timer, DeviceEventKind::Irq, DeviceEventKind::Nmi, DeviceEventKind::Note];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            DeviceEventKind::Register => "register",
+            DeviceEventKind::Timer => "timer",
+            DeviceEventKind::Irq => "irq",
+            DeviceEventKind::Nmi => "nmi",
+            DeviceEventKind::Note => "note",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<DeviceEventKind> {
+        DeviceEventKind::ALL.into_iter().find(|kind| kind.name().eq_ignore_ascii_case(name))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DeviceEvent {
+    pub cycle: u64,
+    // The device's mapping or interrupt source name
+    pub device: String,
+    pub kind: DeviceEventKind,
+    pub text: String,
+}
+
+impl DeviceEvent {
+    pub fn describe(&self) -> String {
+        std::format!("{:>10}  {:<8} {:<8} {}", self.cycle, self.device, self.kind.name(), self.text)
+    }
+}
+
+// Which events are shown or written out, everything when both are empty
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DeviceLogFilter {
+    pub devices: Vec<String>,
+    pub kinds: Vec<DeviceEventKind>,
+}
+
+impl DeviceLogFilter {
+    // Words naming a kind pick kinds, the rest pick devices:
+    // "cia1 timer irq"
+    pub fn parse(words: &[&str]) -> DeviceLogFilter {
+        let mut filter = DeviceLogFilter::default();
+        for word in words {
+            match DeviceEventKind::from_name(word) {
+                Some(kind) => filter.kinds.push(kind),
+                None => filter.devices.push(word.to_string()),
+            }
+        }
+        filter
+    }
+
+    pub fn matches(&self, event: &DeviceEvent) -> bool {
+        (self.devices.is_empty() || self.devices.iter().any(|device| device.eq_ignore_ascii_case(&event.device)))
+            && (self.kinds.is_empty() || self.kinds.contains(&event.kind))
+    }
+
+    pub fn describe(&self) -> String {
+        let words: Vec<&str> = self.devices.iter().map(String::as_str).chain(self.kinds.iter().map(|kind| kind.name())).collect();
+        if words.is_empty() {
+            "everything".to_string()
+        } else {
+            words.join(" ")
+        }
+    }
+}
+
+struct Shared {
+    enabled: AtomicBool,
+    events: Mutex<VecDeque<DeviceEvent>>,
+}
+
+// The log itself, shared by the CPU, the devices and the host
+#[derive(Clone)]
+pub struct DeviceLog {
+    shared: Arc<Shared>,
+    clock: EventQueue,
+}
+
+impl DeviceLog {
+    // Stamps events with `clock`'s cycle
+    pub fn new(clock: EventQueue) -> Self {
+        let shared = Shared { enabled: AtomicBool::new(false), events: Mutex::new(VecDeque::new()) };
+        DeviceLog { shared: Arc::new(shared), clock }
+    }
+
+    fn events_mut(&self) -> MutexGuard<'_, VecDeque<DeviceEvent>> {
+        self.shared.events.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.shared.enabled.load(Ordering::Relaxed)
+    }
+
+    pub fn set_enabled(&self, enabled: bool) {
+        self.shared.enabled.store(enabled, Ordering::Relaxed);
+    }
+
+    // A handle for one device, which logs under `device`
+    pub fn logger(&self, device: &str) -> DeviceLogger {
+        DeviceLogger { device: device.to_string(), log: self.clone() }
+    }
+
+    // `text` is only formatted while logging is on
+    pub fn record(&self, device: &str, kind: DeviceEventKind, text: fmt::Arguments) {
+        if !self.is_enabled() {
+            return;
+        }
+        let event = DeviceEvent { cycle: self.clock.now(), device: device.to_string(), kind, text: text.to_string() };
+        let mut events = self.events_mut();
+        events.push_back(event);
+        if events.len() > CAPACITY {
+            events.pop_front();
+        }
+    }
+
+    // Oldest first
+    pub fn events(&self, filter: &DeviceLogFilter) -> Vec<DeviceEvent> {
+        self.events_mut().iter().filter(|event| filter.matches(event)).cloned().collect()
+    }
+
+    // The newest `count` that match, oldest first
+    pub fn last(&self, count: usize, filter: &DeviceLogFilter) -> Vec<DeviceEvent> {
+        let events = self.events_mut();
+        let mut last: Vec<DeviceEvent> = events.iter().rev().filter(|event| filter.matches(event)).take(count).cloned().collect();
+        last.reverse();
+        last
+    }
+
+    pub fn clear(&self) {
+        self.events_mut().clear();
+    }
+
+    // One JSON object per line
+    pub fn write_json_lines<W: Write>(&self, out: &mut W, filter: &DeviceLogFilter) -> io::Result<()> {
+        for event in self.events(filter) {
+            writeln!(
+                out,
+                "{{\"cycle\":{},\"device\":\"{}\",\"kind\":\"{}\",\"text\":\"{}\"}}",
+                event.cycle,
+                json_escape(&event.device),
+                event.kind.name(),
+                json_escape(&event.text)
+            )?;
+        }
+        Ok(())
+    }
+
+    // JSON lines if the name ends in .json or .jsonl, the lines the pane
+    // shows otherwise
+    pub fn save<P: AsRef<Path>>(&self, path: P, filter: &DeviceLogFilter) -> io::Result<usize> {
+        let json = matches!(path.as_ref().extension().and_then(|e| e.to_str()), Some("json" | "jsonl"));
+        let mut out = BufWriter::new(File::create(path)?);
+        if json {
+            self.write_json_lines(&mut out, filter)?;
+        } else {
+            for event in self.events(filter) {
+                writeln!(out, "{}", event.describe())?;
+            }
+        }
+        out.flush()?;
+        Ok(self.events(filter).len())
+    }
+}
+
+// What a device holds to log under its own name, and to see the cycle
+#[derive(Clone)]
+pub struct DeviceLogger {
+    device: String,
+    log: DeviceLog,
+}
+
+impl DeviceLogger {
+    pub fn now(&self) -> u64 {
+        self.log.clock.now()
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.log.is_enabled()
+    }
+
+    pub fn log(&self, kind: DeviceEventKind, text: fmt::Arguments) {
+        self.log.record(&self.device, kind, text);
+    }
+}
+
+impl cpu6502 {
+    // The log devices write to, see devicelog::DeviceLog
+    pub fn device_log(&self) -> DeviceLog {
+        self.device_log.clone()
+    }
+
+    pub(crate) fn log_device_write(&self, addr: u16, data: u8) {
+        if !self.device_log.is_enabled() {
+            return;
+        }
+        let bus = self.bus.borrow();
+        if let Some(mapping) = bus.mapping_at(addr) {
+            self.device_log.record(&mapping.name, DeviceEventKind::Register, format_args!("${:02x} <- ${:02x}", mapping.offset(addr), data));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::riot::RiotPort;
+
+    #[test]
+    fn filters_and_writes_out() {
+        let cpu = cpu6502::new();
+        let log = cpu.device_log();
+        let timer = log.logger("timer");
+
+        timer.log(DeviceEventKind::Timer, format_args!("off, not kept"));
+        log.set_enabled(true);
+        timer.log(DeviceEventKind::Timer, format_args!("ran out"));
+        log.record("uart", DeviceEventKind::Irq, format_args!("asserted"));
+        log.record("uart", DeviceEventKind::Note, format_args!("said \"hi\""));
+
+        assert_eq!(log.events(&DeviceLogFilter::default()).len(), 3);
+        let filter = DeviceLogFilter::parse(&["UART", "irq"]);
+        assert_eq!(filter.describe(), "UART irq");
+        let picked = log.events(&filter);
+        assert_eq!((picked.len(), picked[0].text.as_str()), (1, "asserted"));
+        assert_eq!(log.last(1, &DeviceLogFilter::default())[0].kind, DeviceEventKind::Note);
+
+        let mut out = Vec::new();
+        log.write_json_lines(&mut out, &DeviceLogFilter::parse(&["note"])).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "{\"cycle\":0,\"device\":\"uart\",\"kind\":\"note\",\"text\":\"said \\\"hi\\\"\"}\n");
+    }
+
+    #[test]
+    fn one_timeline_of_writes_timers_and_irqs() {
+        // Timer set to run out in 8 x 8 cycles with its interrupt on
+        let mut cpu = cpu6502::new();
+        cpu.load_program(&[0xA9, 0x08, 0x8D, 0x1D, 0x06, 0x4C, 0x05, 0x80], 0x8000);
+        cpu.set_reset_vector(0x8000);
+        let _: RiotPort = cpu.attach_riot(0x0400);
+        cpu.reset();
+        cpu.device_log().set_enabled(true);
+        for _ in 0..100 {
+            cpu.clock();
+        }
+
+        let events = cpu.device_log().events(&DeviceLogFilter::default());
+        let seen: Vec<(&str, DeviceEventKind)> = events.iter().map(|event| (event.device.as_str(), event.kind)).collect();
+        assert_eq!(seen, [("riot", DeviceEventKind::Register), ("riot", DeviceEventKind::Timer), ("riot", DeviceEventKind::Irq)]);
+        assert_eq!(events[0].text, "$21d <- $08");
+        assert!(events.windows(2).all(|pair| pair[0].cycle <= pair[1].cycle));
+        // Passing zero a cycle after the 8 x 8, the line going up the
+        // cycle after that
+        assert_eq!((events[1].cycle - events[0].cycle, events[2].cycle - events[1].cycle), (65, 1));
+    }
+}
@@ -0,0 +1,115 @@
+use std::collections::VecDeque;
+
+// Oldest diagnostics are dropped once this many are waiting to be read
+const MAX_PENDING: usize = 256;
+
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+pub enum Severity {
+    Info,
+    Warning,
+    Error,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DiagnosticKind {
+    // RTS/RTI returned somewhere other than where the matching call came from
+    ReturnMismatch { expected: u16, actual: u16 },
+    // RTS/RTI with no call on the shadow stack, usually a jump-table trick
+    UnmatchedReturn { target: u16 },
+    // Calls whose return address was dropped from the stack without returning
+    AbandonedFrames { count: usize },
+    // SP went round, below $00 on a push (overflow) or above $ff on a pull
+    StackWrapped { overflow: bool },
+    // The CPU ran a JAM opcode and locked up
+    Jammed { opcode: u8 },
+    // A read or write nothing answered, while unmapped accesses are faults
+    UnmappedAccess { addr: u16, write: bool },
+    // The trace sink failed and was removed
+    TraceStopped { error: String },
+    // A call to an OS entry point the shim doesn't implement, see mos.rs
+    UnhandledOsCall { call: &'static str, a: u8 },
+    // IRQs arriving faster than their handlers complete
+    InterruptStorm {
+        per_window: usize,
+        window: u64,
+        back_to_back: u32,
+        reentered: bool,
+        sources: Vec<String>,
+    },
+}
+
+// Something the emulator noticed about the guest program while it ran
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub cycle: u64,
+    pub pc: u16,
+    pub severity: Severity,
+    pub kind: DiagnosticKind,
+}
+
+impl Diagnostic {
+    pub fn message(&self) -> String {
+        match &self.kind {
+            DiagnosticKind::ReturnMismatch { expected, actual } => {
+                std::format!("return to ${:04x}, expected ${:04x} (stack corrupted?)", actual, expected)
+            }
+            DiagnosticKind::UnmatchedReturn { target } => {
+                std::format!("return to ${:04x} without a matching call", target)
+            }
+            DiagnosticKind::AbandonedFrames { count } => {
+                std::format!("{} call frame(s) dropped without returning", count)
+            }
+            DiagnosticKind::StackWrapped { overflow: true } => "stack overflow, SP wrapped from $00 to $ff".to_string(),
+            DiagnosticKind::StackWrapped { overflow: false } => "stack underflow, SP wrapped from $ff to $00".to_string(),
+            DiagnosticKind::Jammed { opcode } => std::format!("CPU jammed on ${:02x}, only reset gets it going", opcode),
+            DiagnosticKind::UnmappedAccess { addr, write } => {
+                std::format!("{} unmapped ${:04x}", if *write { "write to" } else { "read from" }, addr)
+            }
+            DiagnosticKind::TraceStopped { error } => std::format!("trace stopped: {}", error),
+            DiagnosticKind::UnhandledOsCall { call, a } => std::format!("{} with A=${:02x} isn't emulated, ignored", call, a),
+            DiagnosticKind::InterruptStorm { per_window, window, back_to_back, reentered, sources } => {
+                let cause = if *reentered {
+                    "handlers re-entered"
+                } else if *back_to_back > 0 {
+                    "source never acknowledged"
+                } else {
+                    "rate too high"
+                };
+                std::format!("IRQ storm from {}: {} IRQs in {} cycles, {} back-to-back ({})", sources.join(", "), per_window, window, back_to_back, cause)
+            }
+        }
+    }
+}
+
+// Queue the CPU reports diagnostics into and front-ends drain
+pub struct Diagnostics {
+    pending: VecDeque<Diagnostic>,
+}
+
+impl Diagnostics {
+    pub fn new() -> Self {
+        Diagnostics {
+            pending: VecDeque::new(),
+        }
+    }
+
+    pub fn report(&mut self, diagnostic: Diagnostic) {
+        match diagnostic.severity {
+            Severity::Info => log_info!(target: "crust_6502::diagnostics", cycle = diagnostic.cycle, pc = diagnostic.pc, "{}", diagnostic.message()),
+            Severity::Warning => log_warn!(target: "crust_6502::diagnostics", cycle = diagnostic.cycle, pc = diagnostic.pc, "{}", diagnostic.message()),
+            Severity::Error => log_error!(target: "crust_6502::diagnostics", cycle = diagnostic.cycle, pc = diagnostic.pc, "{}", diagnostic.message()),
+        }
+        self.pending.push_back(diagnostic);
+        if self.pending.len() > MAX_PENDING {
+            self.pending.pop_front();
+        }
+    }
+
+    pub fn drain(&mut self) -> Vec<Diagnostic> {
+        self.pending.drain(..).collect()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+}
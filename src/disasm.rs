@@ -0,0 +1,193 @@
+use crate::analysis::json_escape;
+use crate::cpu6502;
+use crate::symbols::SymbolTable;
+
+// An instruction taken apart rather than already made into a line, so the
+// same decode gives the debugger's listing, a listing lined up in columns
+// for reading, and JSON for editors and scripts that would otherwise have
+// to pick the text apart again.
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DisasmFormat {
+    Text,
+    Json,
+}
+
+impl DisasmFormat {
+    pub fn parse(name: &str) -> Option<DisasmFormat> {
+        match name {
+            "" | "text" | "txt" => Some(DisasmFormat::Text),
+            "json" => Some(DisasmFormat::Json),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Instruction {
+    pub address: u16,
+    pub bytes: Vec<u8>,
+    pub mnemonic: String,
+    // Labels in place of addresses where there's a symbol, empty for implied
+    pub operand: String,
+    pub mode: &'static str,
+    // The address the operand names, the branch destination for REL and the
+    // pointer for the indirect modes. None for implied and immediate.
+    pub target: Option<u16>,
+}
+
+impl Instruction {
+    pub fn next(&self) -> u16 {
+        self.address.wrapping_add(self.bytes.len() as u16)
+    }
+
+    // "$8000: JSR sub_8010 {ABS}", as the debugger has always shown it
+    pub fn line(&self) -> String {
+        if self.operand.is_empty() {
+            std::format!("${:04x}: {} {{{}}}", self.address, self.mnemonic, self.mode)
+        } else {
+            std::format!("${:04x}: {} {} {{{}}}", self.address, self.mnemonic, self.operand, self.mode)
+        }
+    }
+
+    // Address, bytes, mnemonic and operand in columns, the mode last
+    pub fn aligned(&self) -> String {
+        let bytes: Vec<String> = self.bytes.iter().map(|byte| std::format!("{:02x}", byte)).collect();
+        let text = std::format!("${:04x}  {:<8}  {:<3} {:<20}  ; {}", self.address, bytes.join(" "), self.mnemonic, self.operand, self.mode);
+        text.trim_end().to_string()
+    }
+
+    pub fn to_json(&self) -> String {
+        let bytes: Vec<String> = self.bytes.iter().map(|byte| byte.to_string()).collect();
+        let target = self.target.map_or("null".to_string(), |target| target.to_string());
+        std::format!(
+            "{{\"address\":{},\"bytes\":[{}],\"mnemonic\":\"{}\",\"operand\":\"{}\",\"mode\":\"{}\",\"target\":{}}}",
+            self.address,
+            bytes.join(","),
+            json_escape(&self.mnemonic),
+            json_escape(&self.operand),
+            self.mode,
+            target
+        )
+    }
+}
+
+impl cpu6502 {
+    // The one instruction at `addr`, read without side effects
+    pub fn decode_instruction(&self, addr: u16, symbols: &SymbolTable) -> Instruction {
+        let label = |target: u16, digits: usize| match symbols.name_of(target) {
+            Some(name) => name.to_string(),
+            None => std::format!("${:0width$x}", target, width = digits),
+        };
+
+        let opcode = self.bus.borrow().read(addr, true);
+        let len = self.instruction_len(opcode);
+        let bytes: Vec<u8> = (0..len).map(|i| self.bus.borrow().read(addr.wrapping_add(i), true)).collect();
+        let lo = bytes.get(1).copied().unwrap_or(0);
+        let word = u16::from_le_bytes([lo, bytes.get(2).copied().unwrap_or(0)]);
+
+        let mode = self.addr_mode_name(opcode);
+        let (operand, target) = match mode {
+            "IMP" => (String::new(), None),
+            "IMM" => (std::format!("#${:02x}", lo), None),
+            "ZP0" => (label(lo as u16, 2), Some(lo as u16)),
+            "ZPX" => (std::format!("{}, X", label(lo as u16, 2)), Some(lo as u16)),
+            "ZPY" => (std::format!("{}, Y", label(lo as u16, 2)), Some(lo as u16)),
+            "IZX" => (std::format!("({}, X)", label(lo as u16, 2)), Some(lo as u16)),
+            "IZY" => (std::format!("({}), Y", label(lo as u16, 2)), Some(lo as u16)),
+            "ABS" => (label(word, 4), Some(word)),
+            "ABX" => (std::format!("{}, X", label(word, 4)), Some(word)),
+            "ABY" => (std::format!("{}, Y", label(word, 4)), Some(word)),
+            "IND" => (std::format!("({})", label(word, 4)), Some(word)),
+            _ => {
+                // REL, the offset is signed, relative to the next instruction
+                let destination = addr.wrapping_add(2).wrapping_add(lo as i8 as u16);
+                (label(destination, 4), Some(destination))
+            }
+        };
+
+        Instruction {
+            address: addr,
+            bytes,
+            mnemonic: self.lookup[opcode as usize].name.to_string(),
+            operand,
+            mode,
+            target,
+        }
+    }
+
+    // Everything from `start` up to the instruction covering `end`
+    pub fn decode_range(&self, start: u16, end: u16, symbols: &SymbolTable) -> Vec<Instruction> {
+        let mut instructions = Vec::new();
+        let mut addr = start;
+        loop {
+            let instruction = self.decode_instruction(addr, symbols);
+            let next = instruction.next();
+            instructions.push(instruction);
+            if addr >= end || next <= addr {
+                break;
+            }
+            addr = next;
+        }
+        instructions
+    }
+
+    pub fn disassembly_listing(&self, start: u16, end: u16, symbols: &SymbolTable, format: DisasmFormat) -> String {
+        let instructions = self.decode_range(start, end, symbols);
+        match format {
+            DisasmFormat::Text => instructions.iter().map(|instruction| instruction.aligned() + "\n").collect(),
+            DisasmFormat::Json => {
+                let rows: Vec<String> = instructions.iter().map(|instruction| std::format!("  {}", instruction.to_json())).collect();
+                std::format!("[\n{}\n]\n", rows.join(",\n"))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn program() -> cpu6502 {
+        let mut cpu = cpu6502::new();
+        // JSR $8010 / LDA ($20), Y / STA $10, X / BNE $8000 / ASL
+        cpu.load_program(&[0x20, 0x10, 0x80, 0xB1, 0x20, 0x95, 0x10, 0xD0, 0xF7, 0x0A], 0x8000);
+        cpu
+    }
+
+    #[test]
+    fn decodes_fields() {
+        let cpu = program();
+        let mut symbols = SymbolTable::new();
+        symbols.insert("sub", 0x8010);
+        let instructions = cpu.decode_range(0x8000, 0x8009, &symbols);
+
+        let summary: Vec<(&str, &str, &str, Option<u16>)> = instructions.iter().map(|i| (i.mnemonic.as_str(), i.operand.as_str(), i.mode, i.target)).collect();
+        assert_eq!(
+            summary,
+            [
+                ("JSR", "sub", "ABS", Some(0x8010)),
+                ("LDA", "($20), Y", "IZY", Some(0x20)),
+                ("STA", "$10, X", "ZPX", Some(0x10)),
+                ("BNE", "$8000", "REL", Some(0x8000)),
+                ("ASL", "", "IMP", None),
+            ]
+        );
+        assert_eq!(instructions[0].bytes, [0x20, 0x10, 0x80]);
+        assert_eq!(instructions[4].line(), "$8009: ASL {IMP}");
+        assert_eq!(instructions[0].aligned(), "$8000  20 10 80  JSR sub                   ; ABS");
+        assert_eq!(instructions[4].aligned(), "$8009  0a        ASL                       ; IMP");
+    }
+
+    #[test]
+    fn json_listing() {
+        let cpu = program();
+        let json = cpu.disassembly_listing(0x8007, 0x8009, &SymbolTable::new(), DisasmFormat::Json);
+        assert_eq!(
+            json,
+            "[\n  {\"address\":32775,\"bytes\":[208,247],\"mnemonic\":\"BNE\",\"operand\":\"$8000\",\"mode\":\"REL\",\"target\":32768},\n  {\"address\":32777,\"bytes\":[10],\"mnemonic\":\"ASL\",\"operand\":\"\",\"mode\":\"IMP\",\"target\":null}\n]\n"
+        );
+        assert_eq!(DisasmFormat::parse("json"), Some(DisasmFormat::Json));
+        assert_eq!(DisasmFormat::parse("xml"), None);
+    }
+}
@@ -0,0 +1,265 @@
+use std::collections::VecDeque;
+
+use crate::cpu6502;
+use crate::device::BusDevice;
+use crate::scheduler::EventQueue;
+use crate::snapshot::{Snapshot, StateReader, StateWriter};
+
+// Block copies done by something other than the CPU while the CPU waits.
+// A transfer takes the bus for `setup` cycles, then a read cycle and a
+// write cycle per byte; the CPU does nothing meanwhile, but the clock and
+// anything scheduled on it carry on. A transfer started part way through
+// an instruction counts as part of it, so that instruction isn't complete
+// until the copy is, and a debugger step takes in the whole stall.
+//
+// Devices start transfers from their register writes through the event
+// queue, the same as they raise interrupts.
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DmaTransfer {
+    pub source: u16,
+    pub dest: u16,
+    pub len: u16,
+    // Every byte goes to `dest`, a data port rather than memory
+    pub fixed_dest: bool,
+    // Cycles taken before the first read
+    pub setup: u8,
+    // Reads only start on an even cycle, one more wait if need be
+    pub align: bool,
+}
+
+impl DmaTransfer {
+    // `len` bytes from $source to $dest, both counting up
+    pub fn block(source: u16, dest: u16, len: u16) -> Self {
+        DmaTransfer { source, dest, len, fixed_dest: false, setup: 1, align: false }
+    }
+
+    // The NES's sprite DMA: page $xx00-$xxFF into OAMDATA at $2004, 513
+    // cycles or 514 from an odd one
+    pub fn oam(page: u8) -> Self {
+        DmaTransfer { source: (page as u16) << 8, dest: 0x2004, len: 256, fixed_dest: true, setup: 1, align: true }
+    }
+
+    // Cycles the transfer holds the CPU for when started on `cycle`
+    pub fn cycles(&self, cycle: u64) -> u64 {
+        self.wait(cycle) + 2 * self.len as u64
+    }
+
+    fn wait(&self, cycle: u64) -> u64 {
+        self.setup as u64 + (self.align && cycle % 2 == 1) as u64
+    }
+}
+
+pub(crate) struct Dma {
+    transfer: DmaTransfer,
+    wait: u64,
+    done: u16,
+    // The byte read, waiting for its write cycle
+    byte: Option<u8>,
+}
+
+impl cpu6502 {
+    // Starts `transfer` on the next cycle, or after any already going
+    pub fn start_dma(&mut self, transfer: DmaTransfer) {
+        let wait = transfer.wait(self.clock_count);
+        self.dma.push_back(Dma { transfer, wait, done: 0, byte: None });
+    }
+
+    pub fn dma_active(&self) -> bool {
+        !self.dma.is_empty()
+    }
+
+    // One cycle of the transfer in front
+    pub(crate) fn dma_cycle(&mut self) {
+        let dma = match self.dma.front_mut() {
+            Some(dma) => dma,
+            None => return,
+        };
+
+        if dma.wait > 0 {
+            dma.wait -= 1;
+        } else if let Some(byte) = dma.byte.take() {
+            let transfer = dma.transfer;
+            let dest = if transfer.fixed_dest { transfer.dest } else { transfer.dest.wrapping_add(dma.done) };
+            dma.done += 1;
+            if dma.done >= transfer.len {
+                self.dma.pop_front();
+            }
+            self.write(dest, byte);
+        } else if dma.done < dma.transfer.len {
+            let source = dma.transfer.source.wrapping_add(dma.done);
+            let byte = self.read(source);
+            if let Some(dma) = self.dma.front_mut() {
+                dma.byte = Some(byte);
+            }
+        } else {
+            self.dma.pop_front();
+        }
+    }
+}
+
+// Every transfer still queued, with how far the one in front has got
+impl Snapshot for VecDeque<Dma> {
+    fn save_state(&self) -> Vec<u8> {
+        let mut w = StateWriter::new();
+
+        w.u32(self.len() as u32);
+        for dma in self {
+            let transfer = &dma.transfer;
+            w.u16(transfer.source);
+            w.u16(transfer.dest);
+            w.u16(transfer.len);
+            w.bool(transfer.fixed_dest);
+            w.u8(transfer.setup);
+            w.bool(transfer.align);
+            w.u64(dma.wait);
+            w.u16(dma.done);
+            w.bool(dma.byte.is_some());
+            w.u8(dma.byte.unwrap_or(0));
+        }
+
+        w.finish()
+    }
+
+    fn load_state(&mut self, data: &[u8], _version: u32) -> Result<(), String> {
+        let mut r = StateReader::new(data);
+
+        let mut queue = VecDeque::new();
+        for _ in 0..r.u32()? {
+            let transfer = DmaTransfer {
+                source: r.u16()?,
+                dest: r.u16()?,
+                len: r.u16()?,
+                fixed_dest: r.bool()?,
+                setup: r.u8()?,
+                align: r.bool()?,
+            };
+            let (wait, done) = (r.u64()?, r.u16()?);
+            let byte = (r.bool()?, r.u8()?);
+            queue.push_back(Dma { transfer, wait, done, byte: if byte.0 { Some(byte.1) } else { None } });
+        }
+
+        *self = queue;
+        Ok(())
+    }
+}
+
+// The register at $4014 a write of page number to starts sprite DMA
+pub struct OamDma {
+    events: EventQueue,
+}
+
+impl OamDma {
+    pub fn new(events: EventQueue) -> Self {
+        OamDma { events }
+    }
+}
+
+impl BusDevice for OamDma {
+    fn read(&mut self, _offset: u16) -> u8 {
+        0
+    }
+
+    fn peek(&self, _offset: u16) -> u8 {
+        0
+    }
+
+    fn write(&mut self, _offset: u16, data: u8) {
+        self.events.schedule_in(0, move |cpu| cpu.start_dma(DmaTransfer::oam(data)));
+    }
+}
+
+impl cpu6502 {
+    pub fn attach_oam_dma(&mut self) {
+        let device = OamDma::new(self.events());
+        self.bus.borrow_mut().map("oam-dma", 0x4014, 0x4014, Box::new(device));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::CaptureSink;
+    use crate::snapshot::MachineState;
+
+    #[test]
+    fn oam_dma_stalls_the_cpu() {
+        let mut cpu = cpu6502::new();
+        let oam = CaptureSink::new();
+        let written = oam.output();
+        cpu.bus.borrow_mut().map("oamdata", 0x2004, 0x2004, Box::new(oam));
+        cpu.attach_oam_dma();
+
+        let page: Vec<u8> = (0..=255).collect();
+        cpu.load_program(&page, 0x0300);
+        // LDA #$03 / STA $4014 / NOP
+        cpu.load_program(&[0xA9, 0x03, 0x8D, 0x14, 0x40, 0xEA], 0x8000);
+        cpu.set_reset_vector(0x8000);
+        cpu.reset();
+
+        // Stepped an instruction at a time, after the reset, the STA takes
+        // the copy with it
+        let mut steps = Vec::new();
+        for _ in 0..4 {
+            let start = cpu.clock_count();
+            loop {
+                cpu.clock();
+                if cpu.complete() {
+                    break;
+                }
+            }
+            steps.push(cpu.clock_count() - start);
+        }
+
+        assert_eq!(*written.lock().unwrap(), page);
+        let stall = steps[2] - 4;
+        assert!(stall == 513 || stall == 514, "{:?}", steps);
+        assert_eq!((steps[1], steps[3]), (2, 2));
+        assert!(!cpu.dma_active());
+    }
+
+    #[test]
+    fn block_copies_queue_up() {
+        let mut cpu = cpu6502::new();
+        cpu.load_program(&[1, 2, 3, 4], 0x1000);
+        cpu.start_dma(DmaTransfer::block(0x1000, 0x2000, 4));
+        cpu.start_dma(DmaTransfer::block(0x2000, 0x3002, 2));
+
+        let expected = DmaTransfer::block(0x1000, 0x2000, 4).cycles(0) + DmaTransfer::block(0x2000, 0x3002, 2).cycles(0);
+        let mut cycles = 0;
+        while cpu.dma_active() {
+            cpu.clock();
+            cycles += 1;
+        }
+        assert_eq!(cycles, expected);
+
+        let bus = cpu.bus.borrow();
+        let copied: Vec<u8> = [0x2000, 0x2001, 0x2002, 0x2003, 0x3002, 0x3003].iter().map(|&addr| bus.read(addr, true)).collect();
+        assert_eq!(copied, vec![1, 2, 3, 4, 1, 2]);
+    }
+
+    #[test]
+    fn snapshot_keeps_transfers_in_flight() {
+        let mut cpu = cpu6502::new();
+        cpu.load_program(&[1, 2, 3, 4], 0x1000);
+        cpu.start_dma(DmaTransfer::block(0x1000, 0x2000, 4));
+        cpu.start_dma(DmaTransfer::block(0x1000, 0x3000, 2));
+        for _ in 0..4 {
+            cpu.clock();
+        }
+
+        let state = MachineState::capture(&cpu);
+        while cpu.dma_active() {
+            cpu.clock();
+        }
+        cpu.bus.borrow_mut().write(0x2003, 0);
+
+        state.restore(&mut cpu).unwrap();
+        assert!(cpu.dma_active());
+        while cpu.dma_active() {
+            cpu.clock();
+        }
+        assert_eq!(cpu.bus.borrow().read(0x2003, true), 4);
+        assert_eq!(cpu.bus.borrow().read(0x3001, true), 2);
+    }
+}
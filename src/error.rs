@@ -0,0 +1,58 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+
+// What can go wrong loading programs and putting a machine together. Most
+// of the crate still reports with plain strings, so an EmuError turns into
+// one with `?` where that's what's wanted.
+#[derive(Debug)]
+pub enum EmuError {
+    // Text that should have been hex bytes, and why it isn't
+    InvalidHex(String),
+    Io { path: PathBuf, source: io::Error },
+    // A cartridge header that isn't one or doesn't add up
+    BadHeader(String),
+    UnsupportedMapper(u16),
+    // A program image that can't be loaded as it stands: a bad record, no
+    // origin for a raw binary, data running past $ffff
+    BadImage(String),
+    // Data destined for somewhere nothing answers, that would be lost
+    UnmappedAddress(u16),
+    NoSuchCpu(usize),
+}
+
+impl EmuError {
+    pub fn io<P: Into<PathBuf>>(path: P, source: io::Error) -> EmuError {
+        EmuError::Io { path: path.into(), source }
+    }
+}
+
+impl fmt::Display for EmuError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EmuError::InvalidHex(why) => write!(f, "invalid hex: {}", why),
+            EmuError::Io { path, source } => write!(f, "{}: {}", path.display(), source),
+            EmuError::BadHeader(why) => write!(f, "bad header: {}", why),
+            EmuError::UnsupportedMapper(id) => write!(f, "mapper {} is not registered", id),
+            EmuError::BadImage(why) => write!(f, "{}", why),
+            EmuError::UnmappedAddress(addr) => write!(f, "nothing is mapped at ${:04x}", addr),
+            EmuError::NoSuchCpu(index) => write!(f, "no CPU {}", index),
+        }
+    }
+}
+
+impl Error for EmuError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            EmuError::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+impl From<EmuError> for String {
+    fn from(error: EmuError) -> String {
+        error.to_string()
+    }
+}
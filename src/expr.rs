@@ -0,0 +1,374 @@
+use crate::cpu6502;
+use crate::symbols::SymbolTable;
+
+// Expressions typed into the debugger, e.g. `$0200 + x`, `[reset_vec] | 1`
+// or `>buffer`. Bare numbers are hex like in every machine monitor, `#`
+// marks a decimal number, `%` a binary one and `[addr]` reads a byte.
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(i64),
+    Ident(String),
+    Plus,
+    Minus,
+    Star,
+    Slash,
+    Amp,
+    Pipe,
+    Caret,
+    Tilde,
+    Shl,
+    Shr,
+    Less,
+    Greater,
+    LParen,
+    RParen,
+    LBracket,
+    RBracket,
+}
+
+fn tokenize(s: &str) -> Result<Vec<Token>, String> {
+    let chars: Vec<char> = s.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+
+    let take_while = |i: &mut usize, f: &dyn Fn(char) -> bool| -> String {
+        let start = *i;
+        while *i < chars.len() && f(chars[*i]) {
+            *i += 1;
+        }
+        chars[start..*i].iter().collect()
+    };
+
+    while i < chars.len() {
+        let c = chars[i];
+
+        if c.is_whitespace() {
+            i += 1;
+            continue;
+        }
+
+        let radix_number = |i: &mut usize, radix: u32| -> Result<Token, String> {
+            *i += 1;
+            let digits = take_while(i, &|c: char| c.is_digit(radix));
+            i64::from_str_radix(&digits, radix)
+                .map(Token::Number)
+                .map_err(|_| std::format!("bad number near '{}'", c))
+        };
+
+        let token = match c {
+            '$' => radix_number(&mut i, 16)?,
+            '%' => radix_number(&mut i, 2)?,
+            '#' => radix_number(&mut i, 10)?,
+            '0' if i + 1 < chars.len() && (chars[i + 1] == 'x' || chars[i + 1] == 'X') => {
+                i += 1;
+                radix_number(&mut i, 16)?
+            }
+            c if c.is_ascii_alphanumeric() || c == '_' || c == '.' => {
+                let word = take_while(&mut i, &|c: char| c.is_ascii_alphanumeric() || c == '_' || c == '.');
+                if word.chars().next().map_or(false, |c| c.is_ascii_digit()) {
+                    // Anything starting with a digit is a bare hex number
+                    i64::from_str_radix(&word, 16)
+                        .map(Token::Number)
+                        .map_err(|_| std::format!("bad number '{}'", word))?
+                } else {
+                    Token::Ident(word)
+                }
+            }
+            _ => {
+                let two: String = chars[i..(i + 2).min(chars.len())].iter().collect();
+                let token = match two.as_str() {
+                    "<<" => Some(Token::Shl),
+                    ">>" => Some(Token::Shr),
+                    _ => None,
+                };
+
+                if let Some(token) = token {
+                    i += 2;
+                    token
+                } else {
+                    i += 1;
+                    match c {
+                        '+' => Token::Plus,
+                        '-' => Token::Minus,
+                        '*' => Token::Star,
+                        '/' => Token::Slash,
+                        '&' => Token::Amp,
+                        '|' => Token::Pipe,
+                        '^' => Token::Caret,
+                        '~' => Token::Tilde,
+                        '<' => Token::Less,
+                        '>' => Token::Greater,
+                        '(' => Token::LParen,
+                        ')' => Token::RParen,
+                        '[' => Token::LBracket,
+                        ']' => Token::RBracket,
+                        _ => return Err(std::format!("unexpected '{}'", c)),
+                    }
+                }
+            }
+        };
+
+        tokens.push(token);
+    }
+
+    Ok(tokens)
+}
+
+pub fn register_value(cpu: &cpu6502, name: &str) -> Option<i64> {
+    match name.to_ascii_lowercase().as_str() {
+        "a" => Some(cpu.a as i64),
+        "x" => Some(cpu.x as i64),
+        "y" => Some(cpu.y as i64),
+        "sp" => Some(cpu.stkp as i64),
+        "pc" => Some(cpu.pc as i64),
+        "p" => Some(cpu.status as i64),
+        _ => None,
+    }
+}
+
+struct Parser<'a> {
+    tokens: Vec<Token>,
+    pos: usize,
+    cpu: &'a cpu6502,
+    symbols: &'a SymbolTable,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn expect(&mut self, token: Token) -> Result<(), String> {
+        match self.next() {
+            Some(t) if t == token => Ok(()),
+            _ => Err(std::format!("expected {:?}", token)),
+        }
+    }
+
+    // Binary operators from loosest to tightest binding
+    fn binary(&mut self, level: usize) -> Result<i64, String> {
+        const LEVELS: [&[Token]; 5] = [
+            &[Token::Pipe],
+            &[Token::Caret],
+            &[Token::Amp],
+            &[Token::Shl, Token::Shr],
+            &[Token::Plus, Token::Minus],
+        ];
+
+        if level == LEVELS.len() {
+            return self.product();
+        }
+
+        let mut value = self.binary(level + 1)?;
+
+        while let Some(op) = self.peek().cloned() {
+            if !LEVELS[level].contains(&op) {
+                break;
+            }
+            self.pos += 1;
+
+            let rhs = self.binary(level + 1)?;
+            value = match op {
+                Token::Pipe => value | rhs,
+                Token::Caret => value ^ rhs,
+                Token::Amp => value & rhs,
+                Token::Shl => value.wrapping_shl(rhs as u32),
+                Token::Shr => value.wrapping_shr(rhs as u32),
+                Token::Plus => value.wrapping_add(rhs),
+                _ => value.wrapping_sub(rhs),
+            };
+        }
+
+        Ok(value)
+    }
+
+    fn product(&mut self) -> Result<i64, String> {
+        let mut value = self.unary()?;
+
+        while let Some(op) = self.peek().cloned() {
+            match op {
+                Token::Star => {
+                    self.pos += 1;
+                    value = value.wrapping_mul(self.unary()?);
+                }
+                Token::Slash => {
+                    self.pos += 1;
+                    let rhs = self.unary()?;
+                    if rhs == 0 {
+                        return Err("division by zero".to_string());
+                    }
+                    // i64::MIN / -1 doesn't fit
+                    value = value.checked_div(rhs).ok_or("division overflows")?;
+                }
+                _ => break,
+            }
+        }
+
+        Ok(value)
+    }
+
+    fn unary(&mut self) -> Result<i64, String> {
+        match self.peek() {
+            Some(Token::Minus) => {
+                self.pos += 1;
+                Ok(self.unary()?.wrapping_neg())
+            }
+            Some(Token::Tilde) => {
+                self.pos += 1;
+                Ok(!self.unary()? & 0xFFFF)
+            }
+            // Low and high byte, as in 6502 assembler source
+            Some(Token::Less) => {
+                self.pos += 1;
+                Ok(self.unary()? & 0x00FF)
+            }
+            Some(Token::Greater) => {
+                self.pos += 1;
+                Ok((self.unary()? >> 8) & 0x00FF)
+            }
+            _ => self.primary(),
+        }
+    }
+
+    fn primary(&mut self) -> Result<i64, String> {
+        match self.next() {
+            Some(Token::Number(n)) => Ok(n),
+            Some(Token::Ident(name)) => {
+                if let Some(value) = register_value(self.cpu, &name) {
+                    Ok(value)
+                } else if let Some(addr) = self.symbols.lookup(&name) {
+                    Ok(addr as i64)
+                } else {
+                    // Words like "ff" or "c000" are still hex numbers
+                    i64::from_str_radix(&name, 16).map_err(|_| std::format!("unknown symbol '{}'", name))
+                }
+            }
+            Some(Token::LParen) => {
+                let value = self.binary(0)?;
+                self.expect(Token::RParen)?;
+                Ok(value)
+            }
+            Some(Token::LBracket) => {
+                let addr = self.binary(0)?;
+                self.expect(Token::RBracket)?;
+                Ok(self.cpu.bus.borrow().read(addr as u16, true) as i64)
+            }
+            Some(token) => Err(std::format!("unexpected {:?}", token)),
+            None => Err("unexpected end of expression".to_string()),
+        }
+    }
+}
+
+pub fn eval(s: &str, cpu: &cpu6502, symbols: &SymbolTable) -> Result<i64, String> {
+    let tokens = tokenize(s)?;
+    if tokens.is_empty() {
+        return Err("empty expression".to_string());
+    }
+
+    let mut parser = Parser { tokens, pos: 0, cpu, symbols };
+    let value = parser.binary(0)?;
+
+    if parser.pos < parser.tokens.len() {
+        return Err(std::format!("unexpected {:?}", parser.tokens[parser.pos]));
+    }
+
+    Ok(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn eval_with(s: &str, cpu: &cpu6502, symbols: &SymbolTable) -> i64 {
+        eval(s, cpu, symbols).unwrap()
+    }
+
+    fn value(s: &str) -> i64 {
+        eval_with(s, &cpu6502::new(), &SymbolTable::new())
+    }
+
+    #[test]
+    fn literals() {
+        assert_eq!(value("ff"), 0xFF);
+        assert_eq!(value("$ff"), 0xFF);
+        assert_eq!(value("0x1F"), 0x1F);
+        assert_eq!(value("#10"), 10);
+        assert_eq!(value("%101"), 5);
+        assert_eq!(value("0200"), 0x200);
+    }
+
+    #[test]
+    fn precedence() {
+        assert_eq!(value("2 + 3 * 4"), 14);
+        assert_eq!(value("(2 + 3) * 4"), 20);
+        assert_eq!(value("1 + 1 << 4"), 0x20);
+        assert_eq!(value("ff & f0 | 1"), 0xF1);
+        assert_eq!(value("1 | 6 ^ 3 & 2"), 1 | (6 ^ (3 & 2)));
+        assert_eq!(value("#10 - #3 - #2"), 5);
+        assert_eq!(value("-1 + 3"), 2);
+        assert_eq!(value("~0"), 0xFFFF);
+    }
+
+    #[test]
+    fn byte_operators() {
+        assert_eq!(value("<$1234"), 0x34);
+        assert_eq!(value(">$1234"), 0x12);
+        assert_eq!(value(">$1234 + 1"), 0x13);
+        assert_eq!(value("<($12ff + 1)"), 0x00);
+    }
+
+    #[test]
+    fn memory_reads() {
+        let cpu = cpu6502::new();
+        cpu.bus.borrow_mut().write(0x0200, 0x42);
+        cpu.bus.borrow_mut().write(0x0042, 0x07);
+
+        let symbols = SymbolTable::new();
+        assert_eq!(eval_with("[$0200]", &cpu, &symbols), 0x42);
+        assert_eq!(eval_with("[[$0200]]", &cpu, &symbols), 0x07);
+        assert_eq!(eval_with("[$01ff + 1] + 1", &cpu, &symbols), 0x43);
+    }
+
+    #[test]
+    fn registers_win_over_hex() {
+        let mut cpu = cpu6502::new();
+        cpu.a = 0x12;
+        cpu.pc = 0x8000;
+
+        let symbols = SymbolTable::new();
+        assert_eq!(eval_with("a", &cpu, &symbols), 0x12);
+        assert_eq!(eval_with("$a", &cpu, &symbols), 0x0A);
+        assert_eq!(eval_with("0a", &cpu, &symbols), 0x0A);
+        assert_eq!(eval_with("pc + 2", &cpu, &symbols), 0x8002);
+    }
+
+    #[test]
+    fn symbols_then_hex_fallback() {
+        let cpu = cpu6502::new();
+        let mut symbols = SymbolTable::new();
+        symbols.insert("reset", 0x8000);
+        symbols.insert("beef", 0x1234);
+
+        assert_eq!(eval_with("reset + 3", &cpu, &symbols), 0x8003);
+        assert_eq!(eval_with("beef", &cpu, &symbols), 0x1234);
+        assert_eq!(eval_with("cafe", &cpu, &symbols), 0xCAFE);
+        assert!(eval("nosuchlabel", &cpu, &symbols).is_err());
+    }
+
+    #[test]
+    fn errors() {
+        let cpu = cpu6502::new();
+        let symbols = SymbolTable::new();
+
+        for bad in ["", "1 +", "(1", "[2", "1 2", "1 / 0", "(1<<3f)/-1", "@", "$", "%2"] {
+            assert!(eval(bad, &cpu, &symbols).is_err(), "{:?} should fail", bad);
+        }
+    }
+}
@@ -0,0 +1,271 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+use crate::annotations::parse_range;
+use crate::device::BusDevice;
+use crate::Bus;
+
+// Stand-ins for the hardware a routine expects, so a driver can be tested
+// without a model of the real chip behind it.
+
+// Steps through a list of values, one per read, then keeps returning the
+// last one. A write can queue new replies, for "write a command, poll the
+// status" handshakes.
+pub struct ScriptedRegister {
+    script: VecDeque<u8>,
+    last: u8,
+    replies: HashMap<u8, Vec<u8>>,
+}
+
+impl ScriptedRegister {
+    pub fn new(script: &[u8]) -> Self {
+        ScriptedRegister {
+            script: script.iter().copied().collect(),
+            last: 0,
+            replies: HashMap::new(),
+        }
+    }
+
+    // Writing `command` replaces whatever is left of the script
+    pub fn on_write(&mut self, command: u8, replies: &[u8]) {
+        self.replies.insert(command, replies.to_vec());
+    }
+}
+
+impl BusDevice for ScriptedRegister {
+    fn read(&mut self, _offset: u16) -> u8 {
+        if let Some(value) = self.script.pop_front() {
+            self.last = value;
+        }
+        self.last
+    }
+
+    fn peek(&self, _offset: u16) -> u8 {
+        self.script.front().copied().unwrap_or(self.last)
+    }
+
+    fn write(&mut self, _offset: u16, data: u8) {
+        if let Some(replies) = self.replies.get(&data) {
+            self.script = replies.iter().copied().collect();
+        }
+    }
+}
+
+// A receive-only serial port. Reading +0 takes the next byte, +1 reads
+// `ready` while bytes are waiting and 0 once they've run out.
+pub struct ByteFeeder {
+    input: Arc<Mutex<VecDeque<u8>>>,
+    pub ready: u8,
+}
+
+impl ByteFeeder {
+    pub fn new(bytes: &[u8]) -> Self {
+        ByteFeeder {
+            input: Arc::new(Mutex::new(bytes.iter().copied().collect())),
+            ready: 0x01,
+        }
+    }
+
+    // Keep this to feed more bytes in once the feeder is on the bus
+    pub fn input(&self) -> Arc<Mutex<VecDeque<u8>>> {
+        self.input.clone()
+    }
+}
+
+impl BusDevice for ByteFeeder {
+    fn read(&mut self, offset: u16) -> u8 {
+        match offset {
+            0 => lock(&self.input).pop_front().unwrap_or(0),
+            _ => self.peek(offset),
+        }
+    }
+
+    fn peek(&self, offset: u16) -> u8 {
+        let input = lock(&self.input);
+
+        match offset {
+            0 => input.front().copied().unwrap_or(0),
+            1 if !input.is_empty() => self.ready,
+            _ => 0,
+        }
+    }
+
+    fn write(&mut self, _offset: u16, _data: u8) {}
+}
+
+// Shared with the test holding the other end, which may be on another
+// thread
+fn lock<T>(shared: &Mutex<T>) -> MutexGuard<'_, T> {
+    shared.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+// Keeps every byte written to it, reads give back the last one
+pub struct CaptureSink {
+    output: Arc<Mutex<Vec<u8>>>,
+}
+
+impl CaptureSink {
+    pub fn new() -> Self {
+        CaptureSink { output: Arc::new(Mutex::new(Vec::new())) }
+    }
+
+    // Keep this to look at what was written once the sink is on the bus
+    pub fn output(&self) -> Arc<Mutex<Vec<u8>>> {
+        self.output.clone()
+    }
+}
+
+impl BusDevice for CaptureSink {
+    fn read(&mut self, offset: u16) -> u8 {
+        self.peek(offset)
+    }
+
+    fn peek(&self, _offset: u16) -> u8 {
+        lock(&self.output).last().copied().unwrap_or(0)
+    }
+
+    fn write(&mut self, _offset: u16, data: u8) {
+        lock(&self.output).push(data);
+    }
+}
+
+// What a sink has captured, by the name it was mapped under
+pub type Captures = HashMap<String, Arc<Mutex<Vec<u8>>>>;
+
+// Maps fixtures described one per line, "<kind> <name> <address> [bytes]":
+//
+//   script  status $d000 00 00 80   reads step through the bytes
+//   feed    serial $d010 48 49      data at +0, +1 is non-zero while bytes wait
+//   capture out    $d020            keeps every byte written
+//
+// Addresses can be ranges, "$d000-$d00f". Blank lines and lines starting
+// with ';' are skipped. Returns the sinks so their output can be checked.
+pub fn map_str(bus: &mut Bus, text: &str) -> Result<Captures, String> {
+    let mut captures = Captures::new();
+
+    for line in text.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with(';') {
+            continue;
+        }
+
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let (kind, name, range, bytes) = match fields.as_slice() {
+            [kind, name, range, bytes @ ..] => (*kind, *name, *range, bytes),
+            _ => return Err(std::format!("bad fixture '{}'", line)),
+        };
+
+        let (start, end) = parse_range(range).ok_or_else(|| std::format!("bad address '{}'", range))?;
+        let bytes = bytes
+            .iter()
+            .map(|b| u8::from_str_radix(b, 16).map_err(|_| std::format!("bad byte '{}'", b)))
+            .collect::<Result<Vec<u8>, String>>()?;
+
+        match kind {
+            "script" => bus.map(name, start, end, Box::new(ScriptedRegister::new(&bytes))),
+            // Data and status, unless a range says otherwise
+            "feed" => bus.map(name, start, if start == end { start.wrapping_add(1) } else { end }, Box::new(ByteFeeder::new(&bytes))),
+            "capture" => {
+                let sink = CaptureSink::new();
+                captures.insert(name.to_string(), sink.output());
+                bus.map(name, start, end, Box::new(sink));
+            }
+            _ => return Err(std::format!("unknown fixture '{}'", kind)),
+        }
+    }
+
+    Ok(captures)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu6502;
+
+    #[test]
+    fn scripted_register_steps_and_replies() {
+        let mut register = ScriptedRegister::new(&[0x00, 0x00, 0x80]);
+        register.on_write(0x01, &[0x40]);
+
+        assert_eq!(register.peek(0), 0x00);
+        let reads: Vec<u8> = (0..4).map(|_| register.read(0)).collect();
+        assert_eq!(reads, vec![0x00, 0x00, 0x80, 0x80]);
+
+        register.write(0, 0x02);
+        assert_eq!(register.read(0), 0x80);
+        register.write(0, 0x01);
+        assert_eq!(register.read(0), 0x40);
+    }
+
+    #[test]
+    fn feeder_has_data_and_status() {
+        let mut bus = Bus::new();
+        let feeder = ByteFeeder::new(b"HI");
+        let input = feeder.input();
+        bus.map("serial", 0xD010, 0xD011, Box::new(feeder));
+
+        // Peeking from the debugger doesn't use the bytes up
+        assert_eq!(bus.read(0xD010, true), b'H');
+        assert_eq!(bus.read(0xD011, false), 0x01);
+        assert_eq!(bus.read(0xD010, false), b'H');
+        assert_eq!(bus.read(0xD010, false), b'I');
+        assert_eq!(bus.read(0xD011, false), 0x00);
+
+        input.lock().unwrap().push_back(b'!');
+        assert_eq!((bus.read(0xD011, false), bus.read(0xD010, false)), (0x01, b'!'));
+    }
+
+    #[test]
+    fn mapped_devices_sit_in_front_of_ram() {
+        let mut bus = Bus::new();
+        bus.write(0xD000, 0x11);
+
+        bus.map("status", 0xD000, 0xD000, Box::new(ScriptedRegister::new(&[0x22])));
+        assert_eq!(bus.read(0xD000, false), 0x22);
+        assert_eq!(bus.read(0xD001, false), 0x00);
+
+        assert!(bus.unmap("status").is_some());
+        assert!(bus.unmap("status").is_none());
+        assert_eq!(bus.read(0xD000, false), 0x11);
+    }
+
+    #[test]
+    fn fixtures_from_text() {
+        let cpu = cpu6502::new();
+        let captures = map_str(&mut cpu.bus.borrow_mut(), "
+            ; a status register and somewhere to print
+            script  status $d000 00 80
+            feed    serial $d010 41
+            capture out    $d020-$d021
+        ").unwrap();
+
+        let names: Vec<String> = cpu.bus.borrow().mappings().map(|m| m.name.clone()).collect();
+        assert_eq!(names, vec!["out", "serial", "status"]);
+        assert_eq!(cpu.bus.borrow().mappings().find(|m| m.name == "serial").map(|m| m.end), Some(0xD011));
+
+        cpu.bus.borrow_mut().write(0xD020, b'o');
+        cpu.bus.borrow_mut().write(0xD021, b'k');
+        assert_eq!(captures["out"].lock().unwrap().as_slice(), b"ok");
+
+        let mut bus = Bus::new();
+        assert!(map_str(&mut bus, "script status").is_err());
+        assert!(map_str(&mut bus, "blink led $d000").is_err());
+        assert!(map_str(&mut bus, "feed serial $d010 4g").is_err());
+    }
+
+    #[test]
+    fn programs_write_to_a_sink() {
+        // LDX #$00 / loop: TXA / STA $d020 / INX / CPX #$03 / BNE loop / JMP *
+        let mut cpu = cpu6502::new();
+        cpu.load_program(&[0xA2, 0x00, 0x8A, 0x8D, 0x20, 0xD0, 0xE8, 0xE0, 0x03, 0xD0, 0xF7, 0x4C, 0x0B, 0x80], 0x8000);
+        cpu.set_reset_vector(0x8000);
+        let captures = map_str(&mut cpu.bus.borrow_mut(), "capture out $d020").unwrap();
+
+        cpu.reset();
+        for _ in 0..200 {
+            cpu.clock();
+        }
+
+        assert_eq!(captures["out"].lock().unwrap().as_slice(), &[0x00, 0x01, 0x02]);
+    }
+}
@@ -0,0 +1,180 @@
+use crate::machine::Machine;
+use crate::tia::{FRAME_LINES, FRAME_WIDTH};
+use crate::trace::TraceRecord;
+use crate::vicii;
+use crate::{cpu6502, CYCLES_PER_FRAME};
+
+// A frame at a time for hosts with a game loop of their own, libretro
+// style: hand over the input, get back the picture and what happened. The
+// host owns the window, the timing and the sound device, so nothing here
+// knows about minifb or SDL.
+//
+// A 2600 runs until the TIA finishes a picture and shows it. Anything else
+// runs a frame's worth of cycles and shows the VIC-II's last frame or its
+// linear framebuffer, or without either the whole address space, a grey
+// pixel per byte.
+
+// Last key typed, as ASCII. Zero page $ff like the easy6502 convention so
+// small demos can poll it.
+pub const KEYBOARD_ADDR: u16 = 0x00FF;
+
+// What devices give their samples at, see BusDevice::take_samples
+pub const AUDIO_SAMPLE_RATE: u32 = 44_100;
+
+// How long a 2600 frame may take before it's given up on, VSYNC never
+// coming
+pub const MAX_FRAME_CYCLES: u32 = 2 * CYCLES_PER_FRAME;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct InputState {
+    // The left joystick, on a 2600
+    pub up: bool,
+    pub down: bool,
+    pub left: bool,
+    pub right: bool,
+    pub fire: bool,
+    // Written to KEYBOARD_ADDR before the frame runs
+    pub key: Option<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Framebuffer {
+    pub width: usize,
+    pub height: usize,
+    // How many times wider than tall a pixel is shown, the TIA's being
+    // about twice as wide on a TV
+    pub pixel_aspect: usize,
+    // 0x00RRGGBB, a row at a time
+    pub pixels: Vec<u32>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FrameOutput {
+    pub framebuffer: Framebuffer,
+    // Every device's sound from the frame mixed together, empty when
+    // nothing on the bus makes any
+    pub audio_samples: Vec<f32>,
+    // The instructions the first CPU ran during the frame, when it has its
+    // trace ring switched on
+    pub trace: Vec<TraceRecord>,
+}
+
+impl Machine {
+    pub fn run_frame(&mut self, input: &InputState) -> FrameOutput {
+        let atari = (self.cpu_count() > 0).then(|| self.cpu(0).atari2600()).flatten();
+        let start = (self.cpu_count() > 0).then(|| self.cpu(0).clock_count);
+
+        // The left joystick pulls port A's top four bits low, and INPT4
+        if let Some(atari) = &atari {
+            let directions = [(input.up, 0x10), (input.down, 0x20), (input.left, 0x40), (input.right, 0x80)];
+            let pressed = directions.iter().filter(|(down, _)| *down).fold(0, |bits, (_, bit)| bits | bit);
+            atari.riot.set_port_a(!pressed);
+            atari.tia.set_fire(0, input.fire);
+        }
+        if let Some(key) = input.key {
+            self.bus.borrow_mut().write(KEYBOARD_ADDR, key);
+        }
+
+        match &atari {
+            Some(atari) => {
+                let frames = atari.tia.frames();
+                for _ in 0..MAX_FRAME_CYCLES {
+                    self.clock();
+                    if atari.tia.frames() != frames {
+                        break;
+                    }
+                }
+            }
+            None => {
+                for _ in 0..CYCLES_PER_FRAME {
+                    self.clock();
+                }
+            }
+        }
+
+        let trace = start
+            .and_then(|start| Some((start, self.cpu(0).trace_ring()?)))
+            .map_or_else(Vec::new, |(start, ring)| ring.last(ring.len()).filter(|record| record.cycle >= start).copied().collect());
+
+        FrameOutput { framebuffer: self.framebuffer(), audio_samples: self.take_audio(), trace }
+    }
+
+    // Collects what the devices have made since the last call and adds it
+    // up, clipped to -1.0 to 1.0. One that's made fewer samples than the
+    // rest is silent for the remainder.
+    pub fn take_audio(&mut self) -> Vec<f32> {
+        let mut mix: Vec<f32> = Vec::new();
+        for mapping in &self.bus.borrow().mapped {
+            let samples = mapping.device().take_samples();
+            if samples.len() > mix.len() {
+                mix.resize(samples.len(), 0.0);
+            }
+            for (out, sample) in mix.iter_mut().zip(samples) {
+                *out += sample;
+            }
+        }
+        mix.iter_mut().for_each(|sample| *sample = sample.clamp(-1.0, 1.0));
+        mix
+    }
+
+    // The picture as it is now, without running anything
+    pub fn framebuffer(&self) -> Framebuffer {
+        let cpu = (self.cpu_count() > 0).then(|| self.cpu(0));
+        if let Some(atari) = cpu.and_then(cpu6502::atari2600) {
+            return Framebuffer { width: FRAME_WIDTH, height: FRAME_LINES, pixel_aspect: 2, pixels: atari.tia.frame() };
+        }
+        if let Some(vic) = cpu.and_then(cpu6502::vic) {
+            return Framebuffer { width: vicii::FRAME_WIDTH, height: vicii::FRAME_LINES, pixel_aspect: 1, pixels: vic.frame() };
+        }
+        if let Some(framebuffer) = cpu.and_then(cpu6502::linear_framebuffer) {
+            let config = framebuffer.config();
+            let pixels = framebuffer.picture(&self.bus.borrow());
+            return Framebuffer { width: config.width as usize, height: config.height as usize, pixel_aspect: 1, pixels };
+        }
+
+        let bus = self.bus.borrow();
+        let pixels = (0..=0xFFFFu16).map(|addr| bus.read(addr, true) as u32 * 0x010101).collect();
+        Framebuffer { width: 256, height: 256, pixel_aspect: 1, pixels }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn runs_a_frame_and_reports_it() {
+        // Copies the key to $0200 forever: LDA $ff / STA $0200 / JMP $8000
+        let mut cpu = cpu6502::new();
+        cpu.load_program(&[0xA5, 0xFF, 0x8D, 0x00, 0x02, 0x4C, 0x00, 0x80], 0x8000);
+        cpu.set_reset_vector(0x8000);
+        cpu.set_trace_ring(Some(100_000));
+        cpu.reset();
+        let mut machine = Machine::from_cpu(cpu);
+
+        let first = machine.run_frame(&InputState { key: Some(b'A'), ..InputState::default() });
+        assert_eq!((first.framebuffer.width, first.framebuffer.height, first.framebuffer.pixels.len()), (256, 256, 0x10000));
+        assert_eq!(first.framebuffer.pixels[0x0200], 0x414141);
+        assert!(first.audio_samples.is_empty());
+        assert_eq!(first.trace.first().map(|record| record.pc), Some(0x8000));
+
+        // Only this frame's instructions come back
+        let second = machine.run_frame(&InputState::default());
+        assert!(second.trace.first().unwrap().cycle > first.trace.last().unwrap().cycle);
+        assert_eq!(machine.master_clock(), 2 * CYCLES_PER_FRAME as u64);
+    }
+
+    #[test]
+    fn shows_the_vic_frame() {
+        let mut cpu = cpu6502::new();
+        let vic = cpu.attach_vic(0xD000, 0xD800);
+        cpu.set_reset_vector(0x8000);
+        cpu.reset();
+        let mut machine = Machine::from_cpu(cpu);
+
+        let frame = machine.run_frame(&InputState::default()).framebuffer;
+        assert_eq!((frame.width, frame.height), (vicii::FRAME_WIDTH, vicii::FRAME_LINES));
+        assert!(vic.frames() >= 1);
+        assert_eq!(frame.pixels, vic.frame());
+    }
+}
@@ -0,0 +1,135 @@
+use std::fs;
+use std::path::Path;
+
+use crate::{cpu6502, CYCLES_PER_FRAME};
+
+// Headless rendering regression checks: run a program for some frames,
+// take a CRC32 of what's on screen after each one and compare the list
+// against hashes saved from a run known to be good. What counts as the
+// screen is up to the caller, anything that turns the machine into bytes.
+
+// Turns the machine into the bytes a frame is hashed from
+pub type Capture<'a> = dyn FnMut(&cpu6502) -> Vec<u8> + 'a;
+
+// CRC-32 as zip and PNG use it
+pub fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFFu32;
+    for &byte in bytes {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+// Captures $start-$start+len-1, for machines whose screen is plain memory
+pub fn memory_frame(start: u16, len: usize) -> impl FnMut(&cpu6502) -> Vec<u8> {
+    move |cpu| {
+        let bus = cpu.bus.borrow();
+        (0..len).map(|i| bus.read(start.wrapping_add(i as u16), true)).collect()
+    }
+}
+
+impl cpu6502 {
+    // Runs `frames` frames, hashing what `capture` sees at the end of each
+    pub fn frame_hashes(&mut self, frames: u32, capture: &mut Capture) -> Vec<u32> {
+        (0..frames)
+            .map(|_| {
+                for _ in 0..CYCLES_PER_FRAME {
+                    self.clock();
+                }
+                crc32(&capture(self))
+            })
+            .collect()
+    }
+}
+
+// The golden file has a line per frame, "<frame> <crc32>" in hex, with
+// blank lines and ; comments ignored
+pub fn load_golden<P: AsRef<Path>>(path: P) -> Result<Vec<u32>, String> {
+    let path = path.as_ref();
+    let text = fs::read_to_string(path).map_err(|e| std::format!("{}: {}", path.display(), e))?;
+
+    let mut hashes = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        let line = line.split(';').next().unwrap_or("").trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        let mut fields = line.split_whitespace();
+        let frame = fields.next().and_then(|f| usize::from_str_radix(f, 16).ok());
+        let hash = fields.next().and_then(|h| u32::from_str_radix(h, 16).ok());
+        match (frame, hash) {
+            (Some(frame), Some(hash)) if frame == hashes.len() => hashes.push(hash),
+            (Some(frame), Some(_)) => return Err(std::format!("{}: line {}: expected frame {:x}, found {:x}", path.display(), i + 1, hashes.len(), frame)),
+            _ => return Err(std::format!("{}: line {}: expected \"<frame> <crc32>\"", path.display(), i + 1)),
+        }
+    }
+
+    Ok(hashes)
+}
+
+pub fn save_golden<P: AsRef<Path>>(path: P, hashes: &[u32]) -> Result<(), String> {
+    let mut text = String::from("; frame crc32\n");
+    for (frame, hash) in hashes.iter().enumerate() {
+        text.push_str(&std::format!("{:x} {:08x}\n", frame, hash));
+    }
+
+    let path = path.as_ref();
+    fs::write(path, text).map_err(|e| std::format!("{}: {}", path.display(), e))
+}
+
+// Lists every frame that differs, empty when they all match
+pub fn compare(golden: &[u32], actual: &[u32]) -> Vec<String> {
+    let mut problems: Vec<String> = golden
+        .iter()
+        .zip(actual)
+        .enumerate()
+        .filter(|(_, (expected, found))| expected != found)
+        .map(|(frame, (expected, found))| std::format!("frame {}: {:08x}, expected {:08x}", frame, found, expected))
+        .collect();
+
+    if golden.len() != actual.len() {
+        problems.push(std::format!("{} frames, expected {}", actual.len(), golden.len()));
+    }
+
+    problems
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::demos;
+
+    #[test]
+    fn standard_check_value() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+        assert_eq!(crc32(b""), 0);
+    }
+
+    #[test]
+    fn golden_round_trip_catches_changes() {
+        let mut cpu = cpu6502::new();
+        demos::find("fibonacci").unwrap().load(&mut cpu).unwrap();
+        let hashes = cpu.frame_hashes(3, &mut memory_frame(0x0010, 12));
+        assert_eq!(hashes.len(), 3);
+
+        let path = std::env::temp_dir().join(std::format!("crust-framehash-{}.txt", std::process::id()));
+        save_golden(&path, &hashes).unwrap();
+        let golden = load_golden(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert!(compare(&golden, &hashes).is_empty());
+
+        // Something else on screen from the second frame on
+        cpu.bus.borrow_mut().write(0x0010, 0xFF);
+        let mut changed = hashes[..1].to_vec();
+        changed.extend(cpu.frame_hashes(2, &mut memory_frame(0x0010, 12)));
+        assert_eq!(compare(&golden, &changed), vec![
+            std::format!("frame 1: {:08x}, expected {:08x}", changed[1], hashes[1]),
+            std::format!("frame 2: {:08x}, expected {:08x}", changed[2], hashes[2]),
+        ]);
+        assert_eq!(compare(&golden, &hashes[..2]), vec!["2 frames, expected 3".to_string()]);
+    }
+}
@@ -0,0 +1,809 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use minifb::{InputCallback, Window, WindowOptions};
+
+use crate::analysis::{describe_xrefs, ControlFlowGraph, EdgeKind, XRefs};
+use crate::annotations::Annotations;
+use crate::charset::Charset;
+use crate::codeview::CodeView;
+use crate::console::Console;
+use crate::devicelog::DeviceLogFilter;
+use crate::memview::MemoryView;
+use crate::perf::PerfCounters;
+use crate::ppuview::{GREYS, PATTERN_TABLE_SIZE};
+use crate::stack;
+use crate::symbols::SymbolTable;
+use crate::vectors::Vector;
+use crate::{cpu6502, FLAGS6502};
+
+pub const WIDTH: usize = 800;
+pub const HEIGHT: usize = 600;
+
+pub fn draw_cpu(status: &StatusText, cpu: &cpu6502, screen: &mut Vec<u32>, x: u32, y: u32) {
+    status.draw(screen, (x as usize, y as usize), "STATUS: ", 1);
+
+
+    status.draw(screen, ((x + 64) as usize, (y) as usize), "N", if cpu.status & (FLAGS6502::N as u8) != 0 { 0xFF00FFFF } else { 0xFF0000FF });
+    status.draw(screen, ((x + 80) as usize, (y) as usize), "V", if cpu.status & (FLAGS6502::V as u8) != 0 { 0xFF00FFFF } else { 0xFF0000FF });
+    status.draw(screen, ((x + 96) as usize, (y) as usize), "-", if cpu.status & (FLAGS6502::U as u8) != 0 { 0xFF00FFFF } else { 0xFF0000FF });
+    status.draw(screen, ((x + 112) as usize, (y) as usize), "B", if cpu.status & (FLAGS6502::B as u8) != 0 { 0xFF00FFFF } else { 0xFF0000FF });
+    status.draw(screen, ((x + 128) as usize, (y) as usize), "D", if cpu.status & (FLAGS6502::D as u8) != 0 { 0xFF00FFFF } else { 0xFF0000FF });
+    status.draw(screen, ((x + 144) as usize, (y) as usize), "I", if cpu.status & (FLAGS6502::I as u8) != 0 { 0xFF00FFFF } else { 0xFF0000FF });
+    status.draw(screen, ((x + 160) as usize, (y) as usize), "Z", if cpu.status & (FLAGS6502::Z as u8) != 0 { 0xFF00FFFF } else { 0xFF0000FF });
+    status.draw(screen, ((x + 178) as usize, (y) as usize), "C", if cpu.status & (FLAGS6502::C as u8) != 0 { 0xFF00FFFF } else { 0xFF0000FF });
+
+    status.draw(screen, (x as usize, (y + 10) as usize), std::format!("PC: ${:04x}", cpu.pc).as_str(), 1);
+    status.draw(screen, (x as usize, (y + 20) as usize), std::format!("A : ${:02x}", cpu.a).as_str(), 1);
+    status.draw(screen, (x as usize, (y + 30) as usize), std::format!("X : ${:02x}", cpu.x).as_str(), 1);
+    status.draw(screen, (x as usize, (y + 40) as usize), std::format!("Y : ${:02x}", cpu.y).as_str(), 1);
+    status.draw(screen, (x as usize, (y + 50) as usize), std::format!("Stack P: ${:#04x}", cpu.stkp).as_str(), 1);
+}
+
+// Host timings next to the registers, the emulation share turns red when
+// the emulator is using most of the frame
+pub fn draw_perf(status: &StatusText, perf: &PerfCounters, screen: &mut Vec<u32>, x: u32, y: u32) {
+    for row in y as usize..(y as usize + 40).min(HEIGHT) {
+        for pixel in &mut screen[row * WIDTH + x as usize..row * WIDTH + (x as usize + 15 * 8).min(WIDTH)] {
+            *pixel = 0;
+        }
+    }
+
+    let busy = perf.summary().utilization > 0.8;
+    for (i, line) in perf.describe().iter().enumerate() {
+        let color = if i == 0 && busy { 0xFF00FFFF } else { 1 };
+        status.draw(screen, (x as usize, y as usize + i * 10), line.as_str(), color);
+    }
+}
+
+// The most executed opcodes with a bar for their share of instructions
+pub fn draw_stats(status: &StatusText, cpu: &cpu6502, screen: &mut Vec<u32>, x: u32, y: u32, lines: u32) {
+    for row in y as usize..((y + lines * 10) as usize).min(HEIGHT) {
+        for pixel in &mut screen[row * WIDTH + x as usize..row * WIDTH + (x as usize + 55 * 8).min(WIDTH)] {
+            *pixel = 0;
+        }
+    }
+
+    let stats = cpu.stats();
+    status.draw(screen, (x as usize, y as usize), std::format!("OPCODES {} instructions, {} cycles", stats.instructions, stats.cycles).as_str(), 0xFF00FFFF);
+
+    let mut line_y = y + 10;
+    for entry in stats.opcodes.iter().take(lines as usize - 1) {
+        let share = entry.count as f64 / stats.instructions as f64;
+        let bar = "#".repeat((share * 20.0).ceil() as usize);
+        let line = std::format!("{:<8} {:>10} {:>5.1}% {}", entry.name, entry.count, share * 100.0, bar);
+        status.draw(screen, (x as usize, line_y as usize), line.as_str(), 1);
+        line_y += 10;
+    }
+}
+
+// The newest device events that pass the filter, oldest at the top
+pub fn draw_device_log(status: &StatusText, cpu: &cpu6502, filter: &DeviceLogFilter, screen: &mut Vec<u32>, x: u32, y: u32, lines: u32) {
+    for row in y as usize..((y + lines * 10) as usize).min(HEIGHT) {
+        for pixel in &mut screen[row * WIDTH + x as usize..row * WIDTH + (x as usize + 55 * 8).min(WIDTH)] {
+            *pixel = 0;
+        }
+    }
+
+    let log = cpu.device_log();
+    let title = if log.is_enabled() { std::format!("DEVICES {}", filter.describe()) } else { "DEVICES logging off, devlog on".to_string() };
+    status.draw(screen, (x as usize, y as usize), title.as_str(), 0xFF00FFFF);
+
+    let mut line_y = y + 10;
+    for event in log.last(lines as usize - 1, filter) {
+        let mut line = event.describe();
+        line.truncate(55);
+        status.draw(screen, (x as usize, line_y as usize), line.as_str(), 1);
+        line_y += 10;
+    }
+}
+
+// The calls that led to the PC, innermost first. A return address that's
+// been changed on the stack is marked with a !.
+pub fn draw_calls(status: &StatusText, cpu: &cpu6502, screen: &mut Vec<u32>, x: u32, y: u32, lines: u32, symbols: &SymbolTable, selected: Option<usize>) {
+    for row in y as usize..((y + lines * 10) as usize).min(HEIGHT) {
+        for pixel in &mut screen[row * WIDTH + x as usize..row * WIDTH + (x as usize + 55 * 8).min(WIDTH)] {
+            *pixel = 0;
+        }
+    }
+
+    let calls = cpu.backtrace();
+    status.draw(screen, (x as usize, y as usize), std::format!("CALLS {} deep, at ${:04x}", calls.len(), cpu.pc).as_str(), 0xFF00FFFF);
+
+    let mut line_y = y + 10;
+    for (i, call) in calls.iter().enumerate().take(lines as usize - 1) {
+        let mark = if call.is_intact(cpu) { ' ' } else { '!' };
+        let line: String = std::format!("{:>2}{} {}", i, mark, call.describe(symbols)).chars().take(55).collect();
+        let color = if selected == Some(i) { 0xFF0000FF } else { 1 };
+        status.draw(screen, (x as usize, line_y as usize), line.as_str(), color);
+        line_y += 10;
+    }
+}
+
+// The vector last taken is green, the picked one yellow. While a new
+// address is being typed for the picked one it's shown in its place.
+pub fn draw_vectors(status: &StatusText, cpu: &cpu6502, screen: &mut Vec<u32>, x: u32, y: u32, lines: u32, symbols: &SymbolTable, selected: Option<usize>, typed: &str) {
+    for row in y as usize..((y + lines * 10) as usize).min(HEIGHT) {
+        for pixel in &mut screen[row * WIDTH + x as usize..row * WIDTH + (x as usize + 55 * 8).min(WIDTH)] {
+            *pixel = 0;
+        }
+    }
+
+    let last = cpu.last_vector();
+    let header = match last {
+        Some(taken) => std::format!("VECTORS, last {}", taken.describe()),
+        None => "VECTORS".to_string(),
+    };
+    status.draw(screen, (x as usize, y as usize), header.as_str(), 0xFF00FFFF);
+
+    let mut line_y = y + 10;
+    for (i, vector) in Vector::ALL.iter().enumerate() {
+        let line: String = if selected == Some(i) && !typed.is_empty() {
+            std::format!("{:<5} ${}_", vector.name(), typed)
+        } else {
+            cpu.describe_vector(*vector, symbols).chars().take(55).collect()
+        };
+        let color = if selected == Some(i) {
+            0xFF0000FF
+        } else if last.map(|taken| taken.vector) == Some(*vector) {
+            0x00FF00FF
+        } else {
+            1
+        };
+        status.draw(screen, (x as usize, line_y as usize), line.as_str(), color);
+        line_y += 10;
+    }
+}
+
+// Both of the cartridge's pattern tables side by side in greys, redrawn
+// each frame so CHR RAM shows what's been written to it
+pub fn draw_chr(status: &StatusText, cpu: &cpu6502, screen: &mut Vec<u32>, x: u32, y: u32, lines: u32) {
+    for row in y as usize..((y + lines * 10) as usize).min(HEIGHT) {
+        for pixel in &mut screen[row * WIDTH + x as usize..row * WIDTH + (x as usize + 55 * 8).min(WIDTH)] {
+            *pixel = 0;
+        }
+    }
+
+    let bus = cpu.bus.borrow();
+    let header = match bus.cartridge() {
+        Some(cart) => std::format!("CHR $0000 / $1000, mapper {}, {}", cart.mapper_id(), if cart.header().chr_rom_size > 0 { "ROM" } else { "RAM" }),
+        None => "CHR, no cartridge".to_string(),
+    };
+    status.draw(screen, (x as usize, y as usize), header.as_str(), 0xFF00FFFF);
+
+    for table in 0..2 {
+        if let Some(image) = bus.chr_pattern_table(table, GREYS) {
+            let left = x + table as u32 * (PATTERN_TABLE_SIZE as u32 + 8);
+            draw_picture(screen, left, y + 12, &image, PATTERN_TABLE_SIZE, 1);
+        }
+    }
+}
+
+// Pokes and Game Genie codes, switched off ones dimmed
+pub fn draw_patches(status: &StatusText, cpu: &cpu6502, screen: &mut Vec<u32>, x: u32, y: u32, lines: u32) {
+    for row in y as usize..((y + lines * 10) as usize).min(HEIGHT) {
+        for pixel in &mut screen[row * WIDTH + x as usize..row * WIDTH + (x as usize + 55 * 8).min(WIDTH)] {
+            *pixel = 0;
+        }
+    }
+
+    let patches = cpu.patches();
+    status.draw(screen, (x as usize, y as usize), std::format!("PATCHES {}, {} on", patches.len(), patches.iter().filter(|entry| entry.enabled).count()).as_str(), 0xFF00FFFF);
+
+    let mut line_y = y + 10;
+    for (i, entry) in patches.iter().enumerate().take(lines as usize - 1) {
+        let color = if entry.enabled { 0x00FF00FF } else { 1 };
+        let line = std::format!("{:>3} {:<3} {}", i, if entry.enabled { "on" } else { "off" }, entry.patch.describe());
+        status.draw(screen, (x as usize, line_y as usize), line.as_str(), color);
+        line_y += 10;
+    }
+}
+
+// Bytes covered by a note are drawn in yellow
+// The view's header, then rows of `columns` bytes below it, each with the
+// bytes as characters in `charset` after it. The bytes of the match the
+// view's on show in green.
+pub fn draw_ram(status: &StatusText, cpu: &cpu6502, screen: &mut Vec<u32>, x: u32, y: u32, view: &MemoryView, charset: &Charset, lines: u32, columns: u32, notes: &Annotations)
+{
+    // The header's as wide as the other panes, the rows can be wider
+    let width = 55.max(6 + columns as usize * 4 + 1);
+    clear_area(screen, x as usize, y as usize, width * 8, lines as usize * 10);
+
+    let header: String = view.header().chars().take(55).collect();
+    status.draw(screen, (x as usize, y as usize), header.as_str(), 0xFF00FFFF);
+
+    let ram_x = x as usize;
+    let mut ram_y = y as usize + 10;
+    let mut naddr = view.addr;
+
+    for _ in 1..lines {
+        let offset = std::format!("${:04x}:", naddr);
+        status.draw(screen, (ram_x, ram_y), offset.as_str(), 1);
+
+        let mut byte_x = ram_x + offset.len() * 8;
+        let mut char_x = byte_x + (columns as usize * 3 + 1) * 8;
+        for _ in 0..columns {
+            let data = cpu.bus.borrow().read(naddr, true);
+            let byte = std::format!(" {:02x}", data);
+            let color = if view.in_match(naddr) {
+                0x00FF00FF
+            } else if notes.is_annotated(naddr) {
+                0xFF0000FF
+            } else {
+                1
+            };
+            status.draw(screen, (byte_x, ram_y), byte.as_str(), color);
+            byte_x += byte.len() * 8;
+            status.draw(screen, (char_x, ram_y), charset.decode(data).encode_utf8(&mut [0; 4]), color);
+            char_x += 8;
+
+            naddr = naddr.wrapping_add(1);
+        }
+
+        ram_y += 10;
+    }
+}
+
+// How many frames a zero page byte stays highlighted after it changes
+const CHANGE_FRAMES: u8 = 30;
+
+// Remembers the zero page from one frame to the next so the bytes the
+// program is touching stand out
+pub struct ZeroPageView {
+    previous: [u8; 256],
+    // Frames left to highlight each byte for
+    age: [u8; 256],
+    primed: bool,
+}
+
+impl ZeroPageView {
+    pub fn new() -> Self {
+        ZeroPageView { previous: [0; 256], age: [0; 256], primed: false }
+    }
+
+    // Call once per frame, the first call only takes a copy
+    pub fn update(&mut self, cpu: &cpu6502) {
+        let bus = cpu.bus.borrow();
+
+        for addr in 0..256 {
+            let value = bus.read(addr as u16, true);
+
+            if self.primed && value != self.previous[addr] {
+                self.age[addr] = CHANGE_FRAMES;
+            } else {
+                self.age[addr] = self.age[addr].saturating_sub(1);
+            }
+
+            self.previous[addr] = value;
+        }
+
+        self.primed = true;
+    }
+
+    pub fn is_changed(&self, addr: u8) -> bool {
+        self.age[addr as usize] > 0
+    }
+}
+
+// A machine's own picture, `width` pixels to a line and each of them
+// `scale` screen pixels wide
+pub fn draw_picture(screen: &mut Vec<u32>, x: u32, y: u32, picture: &[u32], width: usize, scale: usize) {
+    for (row, line) in picture.chunks(width).enumerate() {
+        let screen_y = y as usize + row;
+        if screen_y >= HEIGHT {
+            break;
+        }
+
+        for (column, &pixel) in line.iter().enumerate() {
+            let screen_x = x as usize + column * scale;
+            let end = (screen_x + scale).min(WIDTH);
+            if screen_x < end {
+                screen[screen_y * WIDTH + screen_x..screen_y * WIDTH + end].fill(pixel);
+            }
+        }
+    }
+}
+
+// Bytes that changed recently are drawn in red, noted ones in yellow
+pub fn draw_zero_page(status: &StatusText, view: &ZeroPageView, screen: &mut Vec<u32>, x: u32, y: u32, notes: &Annotations) {
+    for row in 0..16u32 {
+        let offset = std::format!("${:04x}:", row * 16);
+        let line_y = (y + row * 10) as usize;
+        status.draw(screen, (x as usize, line_y), offset.as_str(), 1);
+
+        let mut byte_x = x as usize + offset.len() * 8;
+        for column in 0..16u32 {
+            let addr = (row * 16 + column) as u8;
+            let byte = std::format!(" {:02x}", view.previous[addr as usize]);
+            let color = if view.is_changed(addr) {
+                0xFF00FFFF
+            } else if notes.is_annotated(addr as u16) {
+                0xFF0000FF
+            } else {
+                1
+            };
+            status.draw(screen, (byte_x, line_y), byte.as_str(), color);
+            byte_x += byte.len() * 8;
+        }
+    }
+}
+
+// The live part of the stack, top first, with return addresses decoded and
+// tagged with the JSR or interrupt that pushed them
+pub fn draw_stack(status: &StatusText, cpu: &cpu6502, screen: &mut Vec<u32>, x: u32, y: u32, lines: u32, symbols: &SymbolTable) {
+    // Entries come and go, so wipe the old text first
+    for row in y as usize..((y + lines * 10) as usize).min(HEIGHT) {
+        for pixel in &mut screen[row * WIDTH + x as usize..row * WIDTH + (x as usize + 55 * 8).min(WIDTH)] {
+            *pixel = 0;
+        }
+    }
+
+    let entries = stack::decode(cpu);
+    status.draw(screen, (x as usize, y as usize), std::format!("STACK ${:04x}, {} bytes", 0x0100 + cpu.stkp as u16, 0xFF - cpu.stkp).as_str(), 0xFF00FFFF);
+
+    let mut line_y = y + 10;
+    for entry in entries.iter().take(lines as usize - 1) {
+        let color = match entry {
+            stack::StackEntry::Byte { .. } => 1,
+            _ => 0x00FF00FF,
+        };
+        let line: String = entry.describe(symbols).chars().take(55).collect();
+        status.draw(screen, (x as usize, line_y as usize), line.as_str(), color);
+        line_y += 10;
+    }
+}
+
+// A note on an instruction's address is shown as a trailing comment, then
+// what goes to it
+fn code_line(addr: u16, line: &str, notes: &Annotations, symbols: &SymbolTable, xrefs: &XRefs) -> String {
+    // "$8000: LDX ..." becomes "$8000 reset: LDX ..." when it has a label
+    let line = match (symbols.name_of(addr), line.split_once(": ")) {
+        (Some(name), Some((address, rest))) => std::format!("{} {}: {}", address, name, rest),
+        _ => line.to_string(),
+    };
+
+    let line = match notes.at(addr) {
+        Some(note) => std::format!("{} ; {}", line, note.text),
+        None => line,
+    };
+
+    let line = match xrefs.get(&addr) {
+        Some(refs) => std::format!("{} ; {}", line, describe_xrefs(refs)),
+        None => line,
+    };
+
+    // Keep long notes from running off the side of the window
+    line.chars().take(43).collect()
+}
+
+pub fn draw_code(status: &StatusText, cpu: &cpu6502, screen: &mut Vec<u32>, x: u32, y: u32, lines: u32, code: &mut CodeView, notes: &Annotations, symbols: &SymbolTable, xrefs: &XRefs) {
+    // Decoded fresh around the PC each frame, or wherever the view's been
+    // sent, with that line in the middle
+    let at = code.focus().unwrap_or(cpu.pc);
+    let before = (lines >> 1) as usize;
+    let window = code.around(cpu, at, before, lines as usize - before, symbols);
+    let at_row = window.iter().position(|(addr, _)| *addr == at).unwrap_or(0);
+
+    for row in y as usize..((y + lines * 10) as usize).min(HEIGHT) {
+        for pixel in &mut screen[row * WIDTH + x as usize..row * WIDTH + (x as usize + 43 * 8).min(WIDTH)] {
+            *pixel = 0;
+        }
+    }
+
+    for (row, (addr, line)) in window.iter().enumerate() {
+        let line_y = y as usize + (before + row - at_row) * 10;
+        let color = match *addr {
+            addr if addr == cpu.pc => 0x00FF00FF,
+            addr if addr == at => 0xFF0000FF,
+            _ => 1,
+        };
+        status.draw(screen, (x as usize, line_y), code_line(*addr, line, notes, symbols, xrefs).as_str(), color);
+    }
+}
+
+// Basic blocks one after another, each headed by where it can go next.
+// Scrolls so the block holding the PC is on screen.
+pub fn draw_cfg(status: &StatusText, cpu: &cpu6502, cfg: &ControlFlowGraph, code: &mut CodeView, screen: &mut Vec<u32>, x: u32, y: u32, lines: u32, symbols: &SymbolTable) {
+    for row in y as usize..((y + lines * 10) as usize).min(HEIGHT) {
+        for pixel in &mut screen[row * WIDTH + x as usize..(row + 1) * WIDTH] {
+            *pixel = 0;
+        }
+    }
+
+    let max_chars = (WIDTH - x as usize) / 8 - 1;
+    let mut text: Vec<(String, u32)> = Vec::new();
+    let mut pc_row = 0;
+
+    for block in cfg.blocks.values() {
+        let edges: Vec<String> = block.successors.iter().map(|(target, kind)| match kind {
+            EdgeKind::FallThrough => std::format!("${:04x}", target),
+            EdgeKind::Taken => std::format!("${:04x}T", target),
+            EdgeKind::Jump => std::format!("${:04x}J", target),
+        }).collect();
+        let exits = if edges.is_empty() { "exit".to_string() } else { edges.join(" ") };
+        text.push((std::format!("[${:04x}] -> {}", block.start, exits), 0xFF00FFFF));
+
+        for addr in &block.instructions {
+            if *addr == cpu.pc {
+                pc_row = text.len();
+            }
+            let color = if *addr == cpu.pc { 0x00FF00FF } else { 1 };
+            text.push((std::format!("  {}", code.line(cpu, *addr, symbols).0), color));
+        }
+    }
+
+    let first = pc_row.saturating_sub(lines as usize / 2).min(text.len().saturating_sub(lines as usize));
+
+    let mut line_y = y;
+    for (line, color) in text.iter().skip(first).take(lines as usize) {
+        let line: String = line.chars().take(max_chars).collect();
+        status.draw(screen, (x as usize, line_y as usize), line.as_str(), *color);
+        line_y += 10;
+    }
+}
+
+pub fn draw_console(status: &StatusText, console: &Console, screen: &mut Vec<u32>, x: u32, y: u32, lines: u32) {
+    // Nothing else draws down here, so wipe the old text first
+    let top = y as usize * WIDTH;
+    let bottom = ((y + (lines + 1) * 10) as usize * WIDTH).min(screen.len());
+    for pixel in &mut screen[top..bottom] {
+        *pixel = 0;
+    }
+
+    if !console.open {
+        return;
+    }
+
+    let max_chars = (WIDTH - x as usize) / 8 - 1;
+    let first = console.output.len().saturating_sub(lines as usize);
+
+    let mut line_y = y;
+    for line in console.output.iter().skip(first) {
+        let line: String = line.chars().take(max_chars).collect();
+        status.draw(screen, (x as usize, line_y as usize), line.as_str(), 1);
+        line_y += 10;
+    }
+
+    let prompt = std::format!("> {}_", console.input);
+    let skip = prompt.chars().count().saturating_sub(max_chars);
+    let prompt: String = prompt.chars().skip(skip).collect();
+    status.draw(screen, (x as usize, (y + lines * 10) as usize), prompt.as_str(), 0x00FF00FF);
+}
+
+// Collects typed characters from minifb, which knows the keyboard layout
+pub struct ConsoleInput {
+    pub chars: Rc<RefCell<Vec<char>>>,
+}
+
+impl InputCallback for ConsoleInput {
+    fn add_char(&mut self, uni_char: u32) {
+        if let Some(c) = char::from_u32(uni_char) {
+            self.chars.borrow_mut().push(c);
+        }
+    }
+}
+
+// Panes that can be moved out of the main window into their own
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Detachable {
+    Ram,
+    Code,
+}
+
+impl Detachable {
+    pub fn parse(name: &str) -> Option<Detachable> {
+        match name {
+            "ram" => Some(Detachable::Ram),
+            "code" => Some(Detachable::Code),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Detachable::Ram => "ram",
+            Detachable::Code => "code",
+        }
+    }
+
+    // Lines of text the pane gets on its own
+    pub fn lines(&self) -> u32 {
+        match self {
+            Detachable::Ram => 48,
+            Detachable::Code => 58,
+        }
+    }
+}
+
+// A window of its own for a detached pane. It's as wide as the main one
+// and its buffer has the same stride, so the draw_* helpers draw into it
+// unchanged, there's just more height to give them.
+pub struct DetachedPane {
+    pub pane: Detachable,
+    pub buffer: Vec<u32>,
+    window: Window,
+    height: usize,
+}
+
+impl DetachedPane {
+    pub fn open(pane: Detachable) -> Result<Self, minifb::Error> {
+        let height = pane.lines() as usize * 10 + 4;
+        let window = Window::new(std::format!("{} - close to put it back", pane.name()).as_str(), WIDTH, height, WindowOptions::default())?;
+        Ok(DetachedPane { pane, buffer: vec![0; WIDTH * HEIGHT], window, height })
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.window.is_open()
+    }
+
+    // Shows what's been drawn into the buffer
+    pub fn present(&mut self) {
+        if let Err(e) = self.window.update_with_buffer(&self.buffer, WIDTH, self.height) {
+            eprintln!("can't draw the {} window: {}", self.pane.name(), e);
+        }
+    }
+
+    // Keeps the window responsive on frames nothing is drawn
+    pub fn update(&mut self) {
+        self.window.update();
+    }
+}
+
+// Blanks a pane's space in the main window once its pane has gone
+pub fn clear_area(screen: &mut [u32], x: usize, y: usize, width: usize, height: usize) {
+    for row in y..(y + height).min(HEIGHT) {
+        screen[row * WIDTH + x..row * WIDTH + (x + width).min(WIDTH)].fill(0);
+    }
+}
+
+pub struct StatusText {
+    texture: Vec<u32>,
+    width: usize,
+    //height: usize,
+    scale: usize,
+}
+
+#[inline(always)]
+fn color_from_bit(bit: u8) -> u32 {
+    if bit == 0 {
+        0x00000000
+    } else {
+        0xFFFFFFFF
+    }
+}
+
+impl StatusText {
+    pub fn new(width: usize, _height: usize, scale: usize) -> Self {
+        // unpack texture for easier drawing
+        let mut texture = Vec::with_capacity(128 * 128);
+
+        for t in MICROKNIGHT_FONT {
+            texture.push(color_from_bit((t >> 7) & 1));
+            texture.push(color_from_bit((t >> 6) & 1));
+            texture.push(color_from_bit((t >> 5) & 1));
+            texture.push(color_from_bit((t >> 4) & 1));
+            texture.push(color_from_bit((t >> 3) & 1));
+            texture.push(color_from_bit((t >> 2) & 1));
+            texture.push(color_from_bit((t >> 1) & 1));
+            texture.push(color_from_bit(t & 1));
+        }
+
+        Self {
+            texture,
+            width,
+            //height,
+            scale,
+        }
+    }
+
+    pub fn draw(&self, screen: &mut [u32], pos: (usize, usize), text: &str, color: u32) {
+        let mut x = pos.0;
+        let y = pos.1;
+        for c in text.chars() {
+            let mut index = c as usize - ' ' as usize;
+            if index > MICROKNIGHT_LAYOUT.len() as usize {
+                index = 0;
+            }
+
+            let layout = MICROKNIGHT_LAYOUT[index];
+            let texture_offset = (layout.1 as usize * 128) + layout.0 as usize;
+
+            for fy in 0..8 * self.scale {
+                let ty = fy / self.scale;
+                for fx in 0..8 * self.scale {
+                    let tx = fx / self.scale;
+                    let pixel = texture_offset + (ty * 128) + tx;
+                    if pixel != 0 {
+                        screen[((y + fy) * self.width) + fx + x] = self.texture[pixel] * color;
+                    }
+                }
+            }
+
+            x += 8 * self.scale;
+        }
+    }
+}
+
+
+// Microknight font (128x128 packed with 1 bit per pixel)
+#[rustfmt::skip]
+pub static MICROKNIGHT_FONT: &[u8] = &[
+    0x00, 0x0c, 0x1b, 0x0d, 0x81, 0x03, 0x01, 0xc0, 0x30, 0x18, 0x18, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x0c, 0x1b, 0x0d, 0x87, 0xc4, 0xb3, 0x60, 0x30, 0x30, 0x0c, 0x1b, 0x03, 0x00, 0x00, 0x00,
+    0x00, 0x0c, 0x09, 0x1f, 0xcd, 0x03, 0xe1, 0xc0, 0x10, 0x60, 0x06, 0x0e, 0x03, 0x00, 0x00, 0x00,
+    0x00, 0x0c, 0x00, 0x0d, 0x87, 0xc0, 0xc3, 0xd8, 0x20, 0x60, 0x06, 0x3f, 0x8f, 0xc0, 0x03, 0xe0,
+    0x00, 0x0c, 0x00, 0x1f, 0xc1, 0x61, 0x83, 0x70, 0x00, 0x60, 0x06, 0x0e, 0x03, 0x01, 0x80, 0x00,
+    0x00, 0x00, 0x00, 0x0d, 0x81, 0x63, 0x63, 0x60, 0x00, 0x30, 0x0c, 0x1b, 0x03, 0x01, 0x80, 0x00,
+    0x00, 0x0c, 0x00, 0x0d, 0x87, 0xc6, 0x91, 0xf0, 0x00, 0x18, 0x18, 0x00, 0x00, 0x00, 0x80, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x60, 0x18, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x03, 0x07, 0xc1, 0xe0, 0x61, 0xf0, 0x70, 0x7f, 0x1e, 0x0f, 0x00, 0x00, 0x00,
+    0x00, 0x03, 0x1e, 0x03, 0x00, 0x60, 0x30, 0x61, 0x80, 0xc0, 0x03, 0x33, 0x19, 0x81, 0x80, 0xc0,
+    0x00, 0x06, 0x33, 0x07, 0x03, 0xc0, 0xe0, 0xc1, 0xf8, 0xfc, 0x06, 0x1f, 0x18, 0xc1, 0x80, 0xc0,
+    0x00, 0x0c, 0x37, 0x83, 0x06, 0x00, 0x31, 0xb0, 0x0c, 0xc6, 0x0c, 0x31, 0x98, 0xc0, 0x00, 0x00,
+    0x00, 0x18, 0x3d, 0x83, 0x0c, 0x02, 0x33, 0x30, 0x8c, 0xc6, 0x0c, 0x31, 0x8f, 0xc0, 0x00, 0x00,
+    0x18, 0x30, 0x39, 0x83, 0x0c, 0x06, 0x33, 0xf9, 0x98, 0xcc, 0x0c, 0x33, 0x00, 0xc1, 0x80, 0xc0,
+    0x18, 0x60, 0x1f, 0x0f, 0xcf, 0xe3, 0xe0, 0x30, 0xf0, 0x78, 0x0c, 0x1e, 0x03, 0x81, 0x80, 0x40,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x80,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x0f, 0x83, 0x83, 0xc3, 0xe0, 0xf0, 0xf8, 0x7f, 0x3f, 0x87, 0x0c, 0x63, 0xf0,
+    0x18, 0x00, 0x0c, 0x18, 0xc6, 0xc6, 0x63, 0x31, 0x98, 0xcc, 0x60, 0x30, 0x0c, 0x0c, 0x60, 0xc0,
+    0x30, 0x3e, 0x06, 0x00, 0xcd, 0xe6, 0x33, 0xf1, 0x80, 0xc6, 0x7e, 0x3f, 0x18, 0x0c, 0x60, 0xc0,
+    0x60, 0x00, 0x03, 0x07, 0x8f, 0x67, 0xf3, 0x19, 0x80, 0xc6, 0x60, 0x30, 0x19, 0xcf, 0xe0, 0xc0,
+    0x30, 0x3e, 0x06, 0x06, 0x0d, 0xe6, 0x33, 0x19, 0x80, 0xc6, 0x60, 0x30, 0x18, 0xcc, 0x60, 0xc0,
+    0x18, 0x00, 0x0c, 0x00, 0x0c, 0x06, 0x33, 0x31, 0x8c, 0xc6, 0x60, 0x30, 0x18, 0xcc, 0x60, 0xc0,
+    0x00, 0x00, 0x00, 0x06, 0x06, 0x66, 0x33, 0xe0, 0xf8, 0xfc, 0x7f, 0x30, 0x0f, 0xcc, 0x63, 0xf0,
+    0x00, 0x00, 0x00, 0x00, 0x03, 0xc0, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xc0, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x0e, 0x63, 0x30, 0x18, 0xcc, 0x63, 0xc3, 0xe0, 0xf0, 0xf8, 0x3c, 0x1f, 0x98, 0xcc, 0x66, 0x30,
+    0x06, 0x66, 0x30, 0x1d, 0xce, 0x66, 0x63, 0x31, 0x98, 0xcc, 0x60, 0x06, 0x18, 0xcc, 0x66, 0x30,
+    0x06, 0x6c, 0x30, 0x1f, 0xcf, 0x66, 0x33, 0x19, 0x8c, 0xc6, 0x3e, 0x06, 0x18, 0xcc, 0x66, 0x30,
+    0x06, 0x78, 0x30, 0x1a, 0xcd, 0xe6, 0x33, 0x19, 0x8c, 0xc6, 0x03, 0x06, 0x18, 0xc6, 0xc6, 0xb0,
+    0xc6, 0x6c, 0x30, 0x18, 0xcc, 0xe6, 0x33, 0xf1, 0x8c, 0xfc, 0x23, 0x06, 0x18, 0xc6, 0xc7, 0xf0,
+    0xc6, 0x66, 0x30, 0x18, 0xcc, 0x66, 0x33, 0x01, 0xac, 0xd8, 0x63, 0x06, 0x18, 0xc3, 0x87, 0x70,
+    0x7c, 0x63, 0x3f, 0x98, 0xcc, 0x63, 0xe3, 0x00, 0xf8, 0xcc, 0x3e, 0x06, 0x0f, 0x83, 0x86, 0x30,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x18, 0x06, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0xc6, 0x63, 0x3f, 0x87, 0x00, 0x01, 0xc0, 0x40, 0x00, 0x18, 0x00, 0x30, 0x00, 0x00, 0x60, 0x00,
+    0x6c, 0x63, 0x03, 0x06, 0x0c, 0x00, 0xc0, 0xe0, 0x00, 0x18, 0x1e, 0x3e, 0x0f, 0x03, 0xe3, 0xc0,
+    0x38, 0x63, 0x06, 0x06, 0x06, 0x00, 0xc1, 0xb0, 0x00, 0x10, 0x03, 0x33, 0x19, 0x86, 0x66, 0x60,
+    0x38, 0x3e, 0x0c, 0x06, 0x03, 0x00, 0xc0, 0x00, 0x00, 0x08, 0x3f, 0x31, 0x98, 0x0c, 0x67, 0xe0,
+    0x6c, 0x06, 0x18, 0x06, 0x01, 0x80, 0xc0, 0x00, 0x00, 0x00, 0x63, 0x31, 0x98, 0x0c, 0x66, 0x00,
+    0xc6, 0x06, 0x30, 0x06, 0x00, 0xc0, 0xc0, 0x00, 0x00, 0x00, 0x63, 0x31, 0x98, 0xcc, 0x66, 0x30,
+    0xc6, 0x06, 0x3f, 0x87, 0x00, 0x61, 0xc0, 0x00, 0x00, 0x00, 0x3f, 0x3f, 0x0f, 0x87, 0xe3, 0xe0,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0xfc, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x38, 0x00, 0x30, 0x03, 0x00, 0xc6, 0x00, 0xe0, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x6c, 0x3f, 0x3e, 0x00, 0x00, 0x06, 0x60, 0x61, 0x88, 0xf8, 0x3c, 0x3e, 0x07, 0xcf, 0xc3, 0xc0,
+    0x60, 0x63, 0x33, 0x07, 0x01, 0xc6, 0xc0, 0x61, 0xdc, 0xcc, 0x66, 0x33, 0x0c, 0xcc, 0x66, 0x00,
+    0x78, 0x63, 0x31, 0x83, 0x00, 0xc7, 0x80, 0x61, 0xfc, 0xc6, 0x63, 0x31, 0x98, 0xcc, 0x03, 0xe0,
+    0x60, 0x63, 0x31, 0x83, 0x00, 0xc6, 0xc0, 0x61, 0xac, 0xc6, 0x63, 0x31, 0x98, 0xcc, 0x00, 0x30,
+    0x60, 0x3f, 0x31, 0x83, 0x00, 0xc6, 0x60, 0x61, 0x8c, 0xc6, 0x63, 0x31, 0x98, 0xcc, 0x06, 0x30,
+    0x60, 0x03, 0x31, 0x8f, 0xc4, 0xc6, 0x31, 0xf9, 0x8c, 0xc6, 0x3e, 0x3f, 0x0f, 0xcc, 0x03, 0xe0,
+    0x60, 0x3e, 0x00, 0x00, 0x03, 0x80, 0x00, 0x00, 0x00, 0x00, 0x00, 0x30, 0x00, 0xc0, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x30, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x18, 0x18, 0x18, 0x1c, 0x87, 0x00, 0x00, 0xc0,
+    0x7c, 0x63, 0x31, 0x98, 0xcc, 0x66, 0x33, 0xf8, 0x30, 0x18, 0x0c, 0x27, 0x0e, 0x00, 0x00, 0x00,
+    0x30, 0x63, 0x31, 0x9a, 0xc6, 0xc6, 0x30, 0x30, 0x30, 0x18, 0x0c, 0x00, 0x1c, 0x00, 0x00, 0xc0,
+    0x30, 0x63, 0x1b, 0x1f, 0xc3, 0x86, 0x30, 0x60, 0x60, 0x18, 0x06, 0x00, 0x18, 0x20, 0x00, 0xc0,
+    0x30, 0x63, 0x1b, 0x0f, 0x83, 0x86, 0x30, 0xc0, 0x30, 0x18, 0x0c, 0x00, 0x10, 0x60, 0x00, 0xc0,
+    0x32, 0x63, 0x0e, 0x0d, 0x86, 0xc3, 0xf1, 0x80, 0x30, 0x18, 0x0c, 0x00, 0x00, 0xe0, 0x00, 0xc0,
+    0x1c, 0x3f, 0x0e, 0x08, 0x8c, 0x60, 0x33, 0xf8, 0x18, 0x18, 0x18, 0x00, 0x01, 0xc0, 0x00, 0xc0,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x03, 0xe0, 0x00, 0x00, 0x18, 0x00, 0x00, 0x03, 0x80, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x30, 0x1c, 0x00, 0x18, 0xc1, 0x83, 0xc1, 0xb0, 0x78, 0x00, 0x00, 0x1f, 0x00, 0x03, 0xc3, 0xe0,
+    0x78, 0x36, 0x31, 0x98, 0xc1, 0x86, 0x00, 0x00, 0x84, 0x7e, 0x1b, 0x03, 0x00, 0x04, 0x20, 0x00,
+    0xcc, 0x30, 0x1f, 0x18, 0xc1, 0x83, 0xe0, 0x01, 0x32, 0xc6, 0x36, 0x00, 0x00, 0x0b, 0x90, 0x00,
+    0xc0, 0x7c, 0x31, 0x8f, 0x80, 0x06, 0x30, 0x01, 0x42, 0xc6, 0x6c, 0x00, 0x0f, 0x8a, 0x50, 0x00,
+    0xc0, 0x30, 0x31, 0x81, 0x81, 0x86, 0x30, 0x01, 0x42, 0x7e, 0x36, 0x00, 0x00, 0x0b, 0x90, 0x00,
+    0xc6, 0x30, 0x1f, 0x07, 0xc1, 0x83, 0xe0, 0x01, 0x32, 0x00, 0x1b, 0x00, 0x00, 0x0a, 0x50, 0x00,
+    0x7c, 0x7f, 0x31, 0x81, 0x81, 0x80, 0x30, 0x00, 0x84, 0x7c, 0x00, 0x00, 0x00, 0x04, 0x20, 0x00,
+    0x30, 0x00, 0x00, 0x00, 0x00, 0x01, 0xe0, 0x00, 0x78, 0x00, 0x00, 0x00, 0x00, 0x03, 0xc0, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x38, 0x00, 0x1c, 0x0e, 0x01, 0x80, 0x00, 0x00, 0x00, 0x00, 0x18, 0x00, 0x00, 0x06, 0x03, 0x00,
+    0x6c, 0x08, 0x06, 0x03, 0x03, 0x06, 0x31, 0xf8, 0x00, 0x00, 0x38, 0x1f, 0x1b, 0x0e, 0x67, 0x30,
+    0x6c, 0x3e, 0x0c, 0x06, 0x06, 0x06, 0x33, 0xd0, 0x30, 0x00, 0x18, 0x31, 0x8d, 0x86, 0xc3, 0x60,
+    0x38, 0x08, 0x18, 0x03, 0x00, 0x06, 0x31, 0xd0, 0x30, 0x00, 0x18, 0x31, 0x86, 0xc7, 0xa3, 0xc0,
+    0x00, 0x00, 0x1e, 0x0e, 0x00, 0x06, 0x30, 0x50, 0x00, 0x00, 0x3c, 0x1f, 0x0d, 0x83, 0x61, 0xf0,
+    0x00, 0x3e, 0x00, 0x00, 0x00, 0x06, 0x30, 0x50, 0x00, 0x00, 0x00, 0x00, 0x1b, 0x06, 0xf3, 0x18,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x07, 0xe0, 0x50, 0x00, 0x18, 0x00, 0x1f, 0x00, 0x0c, 0xf6, 0x70,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x06, 0x00, 0x00, 0x00, 0x30, 0x00, 0x00, 0x00, 0x00, 0x30, 0xf8,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0xe0, 0x18, 0x0c, 0x03, 0x03, 0x03, 0x91, 0xb0, 0xf0, 0x3f, 0x3c, 0x0c, 0x03, 0x01, 0x83, 0x60,
+    0x36, 0x00, 0x02, 0x04, 0x0c, 0xc4, 0xe0, 0x01, 0x98, 0x6c, 0x66, 0x02, 0x04, 0x06, 0x60, 0x00,
+    0x6c, 0x18, 0x1e, 0x0f, 0x07, 0x83, 0xc1, 0xe0, 0xf0, 0xcf, 0x60, 0x3f, 0x9f, 0xcf, 0xe7, 0xf0,
+    0x3a, 0x1e, 0x33, 0x19, 0x8c, 0xc6, 0x63, 0x31, 0x98, 0xfc, 0x60, 0x30, 0x18, 0x0c, 0x06, 0x00,
+    0xf6, 0x03, 0x3f, 0x9f, 0xcf, 0xe7, 0xf3, 0xf9, 0xfc, 0xcc, 0x60, 0x3f, 0x1f, 0x8f, 0xc7, 0xe0,
+    0x6f, 0x63, 0x31, 0x98, 0xcc, 0x66, 0x33, 0x19, 0x8c, 0xcc, 0x63, 0x30, 0x18, 0x0c, 0x06, 0x00,
+    0xcf, 0x3e, 0x31, 0x98, 0xcc, 0x66, 0x33, 0x19, 0x8c, 0xcf, 0x3e, 0x3f, 0x9f, 0xcf, 0xe7, 0xf0,
+    0x03, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x18, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x30, 0x0c, 0x06, 0x0d, 0x8f, 0x83, 0x90, 0xc0, 0x30, 0x30, 0x39, 0x1b, 0x00, 0x07, 0x81, 0x80,
+    0x08, 0x10, 0x19, 0x80, 0x0c, 0xc4, 0xe0, 0x20, 0x40, 0xcc, 0x4e, 0x00, 0x0f, 0x8c, 0xc0, 0x40,
+    0x7e, 0x3f, 0x1f, 0x8f, 0xcc, 0x67, 0x31, 0xe0, 0xf0, 0x78, 0x3c, 0x1e, 0x1a, 0xcd, 0xe6, 0x30,
+    0x18, 0x0c, 0x06, 0x03, 0x0e, 0x67, 0xb3, 0x31, 0x98, 0xcc, 0x66, 0x33, 0x1f, 0xef, 0x66, 0x30,
+    0x18, 0x0c, 0x06, 0x03, 0x0c, 0x66, 0xf3, 0x19, 0x8c, 0xc6, 0x63, 0x31, 0x9b, 0x6e, 0x66, 0x30,
+    0x18, 0x0c, 0x06, 0x03, 0x0c, 0x66, 0x73, 0x19, 0x8c, 0xc6, 0x63, 0x31, 0x98, 0xec, 0x66, 0x30,
+    0x7e, 0x3f, 0x1f, 0x8f, 0xcf, 0xc6, 0x31, 0xf0, 0xf8, 0x7c, 0x3e, 0x1f, 0x0f, 0xc7, 0xc3, 0xe0,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x18, 0x0c, 0x1b, 0x03, 0x0c, 0x00, 0x00, 0xc0, 0x30, 0x18, 0x39, 0x1b, 0x07, 0x80, 0x00, 0x00,
+    0x20, 0x33, 0x00, 0x04, 0x0f, 0x83, 0xc0, 0x20, 0x40, 0x66, 0x4e, 0x00, 0x0c, 0xc7, 0xe3, 0xc0,
+    0xc6, 0x63, 0x31, 0x98, 0xcc, 0xc6, 0x60, 0xf0, 0x78, 0x3c, 0x1e, 0x0f, 0x07, 0x81, 0xb6, 0x60,
+    0xc6, 0x63, 0x31, 0x98, 0xcc, 0x66, 0xe0, 0x18, 0x0c, 0x06, 0x03, 0x01, 0x80, 0xc7, 0xf6, 0x00,
+    0xc6, 0x63, 0x31, 0x8f, 0x8f, 0xc6, 0x31, 0xf8, 0xfc, 0x7e, 0x3f, 0x1f, 0x8f, 0xcd, 0x86, 0x00,
+    0xc6, 0x63, 0x31, 0x81, 0x8c, 0x06, 0x33, 0x19, 0x8c, 0xc6, 0x63, 0x31, 0x98, 0xcd, 0x86, 0x30,
+    0x7c, 0x3e, 0x1f, 0x01, 0x8c, 0x06, 0xe1, 0xf8, 0xfc, 0x7e, 0x3f, 0x1f, 0x8f, 0xc7, 0xf3, 0xe0,
+    0x00, 0x00, 0x00, 0x00, 0x0c, 0x06, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0x80,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x30, 0x0c, 0x0c, 0x0d, 0x83, 0x00, 0xc0, 0x60, 0xd8, 0x0c, 0x39, 0x0c, 0x03, 0x01, 0x83, 0x90,
+    0x08, 0x10, 0x33, 0x00, 0x00, 0x81, 0x01, 0x98, 0x00, 0x16, 0x4e, 0x02, 0x04, 0x06, 0x64, 0xe0,
+    0x78, 0x3c, 0x1e, 0x0f, 0x03, 0x81, 0xc0, 0xe0, 0x70, 0x3e, 0x7c, 0x1e, 0x0f, 0x07, 0x83, 0xc0,
+    0xfc, 0x7e, 0x3f, 0x1f, 0x81, 0x80, 0xc0, 0x60, 0x30, 0x66, 0x66, 0x33, 0x19, 0x8c, 0xc6, 0x60,
+    0xc0, 0x60, 0x30, 0x18, 0x01, 0x80, 0xc0, 0x60, 0x30, 0xc6, 0x63, 0x31, 0x98, 0xcc, 0x66, 0x30,
+    0xc6, 0x63, 0x31, 0x98, 0xc1, 0x80, 0xc0, 0x60, 0x30, 0xc6, 0x63, 0x31, 0x98, 0xcc, 0x66, 0x30,
+    0x7c, 0x3e, 0x1f, 0x0f, 0x87, 0xe3, 0xf1, 0xf8, 0xfc, 0x7e, 0x63, 0x1f, 0x0f, 0x87, 0xc3, 0xe0,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x6c, 0x00, 0x00, 0x06, 0x01, 0x80, 0xc1, 0xb0, 0x30, 0xc0, 0x36, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x0c, 0x1e, 0x01, 0x02, 0x03, 0x30, 0x00, 0x40, 0xc0, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x78, 0x00, 0x33, 0x18, 0xcc, 0x66, 0x33, 0x19, 0x8c, 0xf8, 0x63, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0xcc, 0x3f, 0x37, 0x98, 0xcc, 0x66, 0x33, 0x19, 0x8c, 0xcc, 0x63, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0xc6, 0x00, 0x3d, 0x98, 0xcc, 0x66, 0x33, 0x19, 0x8c, 0xc6, 0x63, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0xc6, 0x0c, 0x39, 0x98, 0xcc, 0x66, 0x33, 0x18, 0xfc, 0xfc, 0x3f, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x7c, 0x00, 0x1f, 0x0f, 0xc7, 0xe3, 0xf1, 0xf8, 0x0c, 0xc0, 0x03, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xf8, 0xc0, 0x3e, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+];
+
+// Font layout (generated from Angelcode Bitmap Font generator)
+#[rustfmt::skip]
+pub static MICROKNIGHT_LAYOUT: &[(u8, u8)] = &[
+    (0, 0), (9, 0), (18, 0), (27, 0), (36, 0), (45, 0), (54, 0), (63, 0), (72, 0), (81, 0), (90, 0), (99, 0), (108, 0),
+    (117, 0), (0, 9), (9, 9), (18, 9), (27, 9), (36, 9), (45, 9), (54, 9), (63, 9), (72, 9), (81, 9), (90, 9), (99, 9),
+    (108, 9), (117, 9), (0, 18), (9, 18), (18, 18), (27, 18), (36, 18), (45, 18), (54, 18), (63, 18), (72, 18), (81, 18),
+    (90, 18), (99, 18), (108, 18), (117, 18), (0, 27), (9, 27), (18, 27), (27, 27), (36, 27), (45, 27), (54, 27), (63, 27),
+    (72, 27), (81, 27), (90, 27), (99, 27), (108, 27), (117, 27), (0, 36), (9, 36), (18, 36), (27, 36), (36, 36), (45, 36),
+    (54, 36), (63, 36), (72, 36), (81, 36), (90, 36), (99, 36), (108, 36), (117, 36), (0, 45), (9, 45), (18, 45), (27, 45),
+    (36, 45), (45, 45), (54, 45), (63, 45), (72, 45), (81, 45), (90, 45), (99, 45), (108, 45), (117, 45), (0, 54), (9, 54),
+    (18, 54), (27, 54), (36, 54), (45, 54), (54, 54), (63, 54), (72, 54), (81, 54), (90, 54), (99, 54), (0, 0), (0, 0),
+    (0, 0), (0, 0), (0, 0), (0, 0), (0, 0), (0, 0), (0, 0), (0, 0), (0, 0), (0, 0), (0, 0), (0, 0), (0, 0), (0, 0), (0, 0),
+    (0, 0), (0, 0), (0, 0), (0, 0), (0, 0), (0, 0), (0, 0), (0, 0), (0, 0), (0, 0), (0, 0), (0, 0), (0, 0), (0, 0), (0, 0),
+    (108, 54), (117, 54), (0, 63), (9, 63), (18, 63), (27, 63), (36, 63), (45, 63), (54, 63), (63, 63), (72, 63), (81, 63),
+    (90, 63), (99, 63), (108, 63), (117, 63), (0, 72), (9, 72), (18, 72), (27, 72), (36, 72), (45, 72), (54, 72), (63, 72),
+    (72, 72), (81, 72), (90, 72), (99, 72), (108, 72), (117, 72), (0, 81), (9, 81), (18, 81), (27, 81), (36, 81), (45, 81),
+    (54, 81), (63, 81), (72, 81), (81, 81), (90, 81), (99, 81), (108, 81), (117, 81), (0, 90), (9, 90), (18, 90), (27, 90),
+    (36, 90), (45, 90), (54, 90), (63, 90), (72, 90), (81, 90), (90, 90), (99, 90), (108, 90), (117, 90), (0, 99), (9, 99),
+    (18, 99), (27, 99), (36, 99), (45, 99), (54, 99), (63, 99), (72, 99), (81, 99), (90, 99), (99, 99), (108, 99), (117, 99),
+    (0, 108), (9, 108), (18, 108), (27, 108), (36, 108), (45, 108), (54, 108), (63, 108), (72, 108), (81, 108), (90, 108),
+    (99, 108), (108, 108), (117, 108), (0, 117), (9, 117), (18, 117), (27, 117), (36, 117), (45, 117), (54, 117), (63, 117),
+    (72, 117), (81, 117),
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn zero_page_changes_fade() {
+        let cpu = cpu6502::new();
+        let mut view = ZeroPageView::new();
+
+        // Whatever is there to begin with doesn't count as a change
+        cpu.bus.borrow_mut().write(0x0010, 0x42);
+        view.update(&cpu);
+        assert!(!view.is_changed(0x10));
+
+        cpu.bus.borrow_mut().write(0x0020, 0x01);
+        view.update(&cpu);
+        assert!(view.is_changed(0x20));
+        assert!(!view.is_changed(0x10));
+
+        for _ in 0..CHANGE_FRAMES {
+            view.update(&cpu);
+        }
+        assert!(!view.is_changed(0x20));
+    }
+}
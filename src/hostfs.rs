@@ -0,0 +1,286 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::PathBuf;
+
+use crate::cpu6502;
+use crate::device::BusDevice;
+
+// A "virtual disk" that hands guest programs files in one host directory,
+// one file open at a time. The guest writes a name a character at a time,
+// issues a command, then moves bytes through the data port. There's no
+// timing, commands are done by the time the write that issued them is.
+//
+//   +0  command  write runs a command, read gives the last error
+//   +1  status
+//   +2  data     read takes the next byte of the file, write appends one
+//   +3  name     write adds a character to the name, read gives its length
+//
+// Names are plain file names in the directory, letters, digits and . _ -
+// and not starting with a dot, so nothing outside it can be reached.
+
+pub const COMMAND_OPEN_READ: u8 = 0x01;
+// Creates the file or empties it
+pub const COMMAND_OPEN_WRITE: u8 = 0x02;
+pub const COMMAND_OPEN_APPEND: u8 = 0x03;
+pub const COMMAND_CLOSE: u8 = 0x04;
+pub const COMMAND_CLEAR_NAME: u8 = 0x05;
+pub const COMMAND_DELETE: u8 = 0x06;
+
+pub const STATUS_ERROR: u8 = 0x80;
+// Reading and nothing left to read
+pub const STATUS_EOF: u8 = 0x02;
+pub const STATUS_OPEN: u8 = 0x01;
+
+pub const ERROR_NONE: u8 = 0;
+pub const ERROR_NOT_FOUND: u8 = 1;
+// Empty, too long, or with characters that aren't allowed
+pub const ERROR_BAD_NAME: u8 = 2;
+pub const ERROR_IO: u8 = 3;
+// Data moved with no file open, or the wrong way for how it was opened
+pub const ERROR_NOT_OPEN: u8 = 4;
+pub const ERROR_BAD_COMMAND: u8 = 5;
+
+const MAX_NAME: usize = 64;
+
+enum Open {
+    Closed,
+    // The whole file, read in when it was opened, and how far the guest is
+    Reading { data: Vec<u8>, pos: usize },
+    Writing(File),
+}
+
+pub struct HostFs {
+    root: PathBuf,
+    name: Vec<u8>,
+    open: Open,
+    error: u8,
+}
+
+impl HostFs {
+    pub fn new<P: Into<PathBuf>>(root: P) -> Self {
+        HostFs { root: root.into(), name: Vec::new(), open: Open::Closed, error: ERROR_NONE }
+    }
+
+    // The host path for the name written so far, if it's one allowed
+    fn path(&self) -> Option<PathBuf> {
+        let name = std::str::from_utf8(&self.name).ok()?;
+        let allowed = |c: char| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-');
+        if name.is_empty() || name.starts_with('.') || !name.chars().all(allowed) {
+            return None;
+        }
+        Some(self.root.join(name))
+    }
+
+    fn command(&mut self, command: u8) -> Result<(), u8> {
+        match command {
+            COMMAND_CLOSE => {
+                self.open = Open::Closed;
+                return Ok(());
+            }
+            COMMAND_CLEAR_NAME => {
+                self.name.clear();
+                return Ok(());
+            }
+            COMMAND_OPEN_READ | COMMAND_OPEN_WRITE | COMMAND_OPEN_APPEND | COMMAND_DELETE => (),
+            _ => return Err(ERROR_BAD_COMMAND),
+        }
+
+        // Whatever was open is closed first, even if this fails
+        self.open = Open::Closed;
+        let path = self.path().ok_or(ERROR_BAD_NAME)?;
+        let io_error = |error: std::io::Error| match error.kind() {
+            std::io::ErrorKind::NotFound => ERROR_NOT_FOUND,
+            _ => ERROR_IO,
+        };
+
+        self.open = match command {
+            COMMAND_OPEN_READ => Open::Reading { data: fs::read(&path).map_err(io_error)?, pos: 0 },
+            COMMAND_OPEN_WRITE => Open::Writing(File::create(&path).map_err(io_error)?),
+            COMMAND_OPEN_APPEND => Open::Writing(OpenOptions::new().append(true).create(true).open(&path).map_err(io_error)?),
+            _ => {
+                fs::remove_file(&path).map_err(io_error)?;
+                Open::Closed
+            }
+        };
+        Ok(())
+    }
+
+    fn status(&self) -> u8 {
+        let mut status = if self.error != ERROR_NONE { STATUS_ERROR } else { 0 };
+        match &self.open {
+            Open::Closed => (),
+            Open::Reading { data, pos } => {
+                status |= STATUS_OPEN;
+                if *pos >= data.len() {
+                    status |= STATUS_EOF;
+                }
+            }
+            Open::Writing(_) => status |= STATUS_OPEN,
+        }
+        status
+    }
+}
+
+impl BusDevice for HostFs {
+    fn read(&mut self, offset: u16) -> u8 {
+        if offset & 3 != 2 {
+            return self.peek(offset);
+        }
+        match &mut self.open {
+            Open::Reading { data, pos } => match data.get(*pos) {
+                Some(&byte) => {
+                    *pos += 1;
+                    byte
+                }
+                None => 0,
+            },
+            _ => {
+                self.error = ERROR_NOT_OPEN;
+                0
+            }
+        }
+    }
+
+    fn peek(&self, offset: u16) -> u8 {
+        match offset & 3 {
+            0 => self.error,
+            1 => self.status(),
+            2 => match &self.open {
+                Open::Reading { data, pos } => data.get(*pos).copied().unwrap_or(0),
+                _ => 0,
+            },
+            _ => self.name.len() as u8,
+        }
+    }
+
+    fn write(&mut self, offset: u16, data: u8) {
+        match offset & 3 {
+            0 => self.error = self.command(data).err().unwrap_or(ERROR_NONE),
+            2 => match &mut self.open {
+                Open::Writing(file) => {
+                    if file.write_all(&[data]).is_err() {
+                        self.error = ERROR_IO;
+                    }
+                }
+                _ => self.error = ERROR_NOT_OPEN,
+            },
+            3 => {
+                if self.name.len() < MAX_NAME {
+                    self.name.push(data);
+                } else {
+                    self.error = ERROR_BAD_NAME;
+                }
+            }
+            _ => (),
+        }
+    }
+
+    fn reset(&mut self) {
+        self.name.clear();
+        self.open = Open::Closed;
+        self.error = ERROR_NONE;
+    }
+}
+
+impl cpu6502 {
+    // Maps a host filesystem device at $base-$base+3 giving the guest the
+    // files in `root`
+    pub fn attach_host_fs<P: Into<PathBuf>>(&mut self, base: u16, root: P) {
+        let device = HostFs::new(root);
+        self.bus.borrow_mut().map("hostfs", base, base.wrapping_add(3), Box::new(device));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scratch(test: &str) -> PathBuf {
+        let root = std::env::temp_dir().join(std::format!("crust-hostfs-{}-{}", test, std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(&root).unwrap();
+        root
+    }
+
+    fn name(fs: &mut HostFs, name: &str) {
+        fs.write(0, COMMAND_CLEAR_NAME);
+        for &c in name.as_bytes() {
+            fs.write(3, c);
+        }
+    }
+
+    #[test]
+    fn names_stay_in_the_directory() {
+        let root = scratch("names");
+        let mut fs = HostFs::new(&root);
+
+        for bad in ["", "../escape", "/etc/passwd", ".hidden", "sub/file"] {
+            name(&mut fs, bad);
+            fs.write(0, COMMAND_OPEN_WRITE);
+            assert_eq!((fs.read(0), fs.read(1)), (ERROR_BAD_NAME, STATUS_ERROR), "{:?}", bad);
+        }
+
+        name(&mut fs, "missing.dat");
+        fs.write(0, COMMAND_OPEN_READ);
+        assert_eq!(fs.read(0), ERROR_NOT_FOUND);
+
+        // Data with nothing open
+        fs.write(2, 0x55);
+        assert_eq!(fs.read(0), ERROR_NOT_OPEN);
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn write_append_read_back() {
+        let root = scratch("files");
+        let mut hostfs = HostFs::new(&root);
+
+        name(&mut hostfs, "SAVE.DAT");
+        hostfs.write(0, COMMAND_OPEN_WRITE);
+        assert_eq!((hostfs.read(0), hostfs.read(1)), (ERROR_NONE, STATUS_OPEN));
+        for &byte in b"hi" {
+            hostfs.write(2, byte);
+        }
+        hostfs.write(0, COMMAND_OPEN_APPEND);
+        hostfs.write(2, b'!');
+        hostfs.write(0, COMMAND_CLOSE);
+        assert_eq!(fs::read(root.join("SAVE.DAT")).unwrap(), b"hi!");
+
+        hostfs.write(0, COMMAND_OPEN_READ);
+        let read: Vec<u8> = (0..3).map(|_| hostfs.read(2)).collect();
+        assert_eq!(read, b"hi!");
+        assert_eq!(hostfs.read(1), STATUS_OPEN | STATUS_EOF);
+
+        hostfs.write(0, COMMAND_DELETE);
+        assert!(!root.join("SAVE.DAT").exists());
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn guest_saves_a_file() {
+        let root = scratch("guest");
+        let mut cpu = cpu6502::new();
+        cpu.attach_host_fs(0xBF00, &root);
+
+        // Writes the name "A", opens it for writing, writes $42 and closes:
+        // LDA #'A' / STA $BF03 / LDA #$02 / STA $BF00 / LDA #$42 / STA $BF02
+        // LDA #$04 / STA $BF00 / loop: JMP loop
+        cpu.load_program(
+            &[
+                0xA9, b'A', 0x8D, 0x03, 0xBF, 0xA9, 0x02, 0x8D, 0x00, 0xBF, 0xA9, 0x42, 0x8D, 0x02, 0xBF, 0xA9, 0x04, 0x8D, 0x00, 0xBF, 0x4C,
+                0x14, 0x80,
+            ],
+            0x8000,
+        );
+        cpu.set_reset_vector(0x8000);
+        cpu.reset();
+        for _ in 0..100 {
+            cpu.clock();
+        }
+
+        assert_eq!(fs::read(root.join("A")).unwrap(), [0x42]);
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
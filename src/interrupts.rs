@@ -0,0 +1,408 @@
+use std::collections::VecDeque;
+
+use crate::diagnostics::{Diagnostic, DiagnosticKind, Severity};
+use crate::snapshot::{Snapshot, StateReader, StateWriter};
+
+// Handle for a device wired to the IRQ line, numbered in registration order
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct IrqSource(pub usize);
+
+// Every controller starts with the host's own source, see cpu6502::assert_irq
+pub const HOST_IRQ: IrqSource = IrqSource(0);
+
+// What pushed an interrupt frame, so RTI knows which kind of handler ended
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum InterruptKind {
+    Irq,
+    Nmi,
+    Brk,
+}
+
+// Each frame takes 3 bytes of the 256 byte stack, any more than this and
+// the oldest have been overwritten
+const MAX_FRAMES: usize = 85;
+
+// An IRQ taken within this many cycles of the previous RTI counts as
+// back-to-back, i.e. the handler returned with the source still asserted
+const BACK_TO_BACK_CYCLES: u64 = 16;
+
+#[derive(Debug, Clone, Copy)]
+pub struct StormLimits {
+    // Length of the sliding window IRQ rates are measured over
+    pub window: u64,
+    // More IRQs than this inside one window is a storm
+    pub max_per_window: usize,
+    // More back-to-back IRQs than this in a row is a storm
+    pub max_back_to_back: u32,
+    // Handlers nested deeper than this (re-entered after a CLI) is a storm
+    pub max_depth: u32,
+}
+
+impl Default for StormLimits {
+    fn default() -> Self {
+        StormLimits {
+            window: crate::CYCLES_PER_FRAME as u64,
+            max_per_window: 1000,
+            max_back_to_back: 64,
+            max_depth: 4,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct IrqStats {
+    pub taken: u64,
+    // How often each source was asserting when an IRQ was taken
+    pub per_source: Vec<u64>,
+    pub storms: u64,
+    // Deepest handler nesting seen, above 1 means handlers were re-entered
+    pub max_depth: u32,
+}
+
+// The IRQ line, as the wired-OR of every device that can pull it low, plus
+// the bookkeeping needed to notice when a device never lets go of it.
+pub struct InterruptController {
+    names: Vec<String>,
+    asserted: Vec<bool>,
+    pub limits: StormLimits,
+    pub stats: IrqStats,
+    recent: VecDeque<u64>,
+    // Interrupts whose handlers haven't returned yet, innermost last
+    frames: VecDeque<InterruptKind>,
+    // IRQ handlers among those frames
+    depth: u32,
+    last_rti: Option<u64>,
+    back_to_back: u32,
+    // Set while a storm is in progress so we report it once, not every IRQ
+    in_storm: bool,
+}
+
+impl InterruptController {
+    pub fn new() -> Self {
+        let mut controller = InterruptController {
+            names: Vec::new(),
+            asserted: Vec::new(),
+            limits: StormLimits::default(),
+            stats: IrqStats::default(),
+            recent: VecDeque::new(),
+            frames: VecDeque::new(),
+            depth: 0,
+            last_rti: None,
+            back_to_back: 0,
+            in_storm: false,
+        };
+
+        controller.register_source("host");
+        controller
+    }
+
+    pub fn register_source(&mut self, name: &str) -> IrqSource {
+        self.names.push(name.to_string());
+        self.asserted.push(false);
+        self.stats.per_source.push(0);
+
+        IrqSource(self.names.len() - 1)
+    }
+
+    pub fn source_name(&self, source: IrqSource) -> &str {
+        &self.names[source.0]
+    }
+
+    pub fn source_names(&self) -> &[String] {
+        &self.names
+    }
+
+    // Devices should go through cpu6502::set_irq so the change is recorded
+    pub fn set_irq(&mut self, source: IrqSource, asserted: bool) {
+        self.asserted[source.0] = asserted;
+    }
+
+    pub fn irq_line(&self) -> bool {
+        self.asserted.iter().any(|a| *a)
+    }
+
+    pub fn asserted_sources(&self) -> Vec<&str> {
+        self.names
+            .iter()
+            .zip(&self.asserted)
+            .filter(|(_, asserted)| **asserted)
+            .map(|(name, _)| name.as_str())
+            .collect()
+    }
+
+    pub fn reset(&mut self) {
+        self.recent.clear();
+        self.frames.clear();
+        self.depth = 0;
+        self.last_rti = None;
+        self.back_to_back = 0;
+        self.in_storm = false;
+    }
+
+    fn push_frame(&mut self, kind: InterruptKind) {
+        self.frames.push_back(kind);
+        if self.frames.len() > MAX_FRAMES {
+            if self.frames.pop_front() == Some(InterruptKind::Irq) {
+                self.depth = self.depth.saturating_sub(1);
+            }
+        }
+    }
+
+    // NMI and BRK don't count towards IRQ nesting, but their RTIs mustn't
+    // be mistaken for the end of an IRQ handler either
+    pub fn on_nmi_taken(&mut self) {
+        self.push_frame(InterruptKind::Nmi);
+    }
+
+    pub fn on_brk(&mut self) {
+        self.push_frame(InterruptKind::Brk);
+    }
+
+    pub fn on_rti(&mut self, cycle: u64) {
+        if self.frames.pop_back() == Some(InterruptKind::Irq) {
+            self.depth = self.depth.saturating_sub(1);
+            self.last_rti = Some(cycle);
+        }
+    }
+
+    // IRQ handlers currently running, above 1 means they were re-entered
+    pub fn depth(&self) -> u32 {
+        self.depth
+    }
+
+    // Called by the CPU whenever it takes an IRQ. Returns a diagnostic when
+    // this IRQ tips the line over into a storm.
+    pub fn on_irq_taken(&mut self, cycle: u64, pc: u16) -> Option<Diagnostic> {
+        self.stats.taken += 1;
+        for (count, asserted) in self.stats.per_source.iter_mut().zip(&self.asserted) {
+            if *asserted {
+                *count += 1;
+            }
+        }
+
+        self.push_frame(InterruptKind::Irq);
+        self.depth += 1;
+        self.stats.max_depth = self.stats.max_depth.max(self.depth);
+        let reentered = self.depth > self.limits.max_depth;
+
+        match self.last_rti {
+            Some(rti) if cycle.saturating_sub(rti) <= BACK_TO_BACK_CYCLES => self.back_to_back += 1,
+            _ => self.back_to_back = 0,
+        }
+
+        self.recent.push_back(cycle);
+        while let Some(oldest) = self.recent.front() {
+            if cycle.saturating_sub(*oldest) > self.limits.window {
+                self.recent.pop_front();
+            } else {
+                break;
+            }
+        }
+
+        let storm = reentered
+            || self.recent.len() > self.limits.max_per_window
+            || self.back_to_back > self.limits.max_back_to_back;
+
+        if !storm {
+            // Once the rate has calmed down a new storm gets reported again
+            if self.recent.len() <= self.limits.max_per_window / 2 && self.back_to_back == 0 {
+                self.in_storm = false;
+            }
+            return None;
+        }
+
+        if self.in_storm {
+            return None;
+        }
+
+        self.in_storm = true;
+        self.stats.storms += 1;
+
+        let sources = self.asserted_sources().iter().map(|s| s.to_string()).collect();
+
+        Some(Diagnostic {
+            cycle,
+            pc,
+            severity: Severity::Error,
+            kind: DiagnosticKind::InterruptStorm {
+                per_window: self.recent.len(),
+                window: self.limits.window,
+                back_to_back: self.back_to_back,
+                reentered,
+                sources,
+            },
+        })
+    }
+}
+
+impl InterruptKind {
+    fn from_u8(value: u8) -> Result<InterruptKind, String> {
+        match value {
+            0 => Ok(InterruptKind::Irq),
+            1 => Ok(InterruptKind::Nmi),
+            2 => Ok(InterruptKind::Brk),
+            _ => Err("bad interrupt kind".to_string()),
+        }
+    }
+}
+
+// The line levels, running handlers and storm bookkeeping. The sources
+// themselves are registered by the host, so the image has to agree on
+// how many there are. Limits are configuration and aren't saved.
+impl Snapshot for InterruptController {
+    fn save_state(&self) -> Vec<u8> {
+        let mut w = StateWriter::new();
+
+        w.u32(self.asserted.len() as u32);
+        for (asserted, count) in self.asserted.iter().zip(&self.stats.per_source) {
+            w.bool(*asserted);
+            w.u64(*count);
+        }
+
+        w.u32(self.frames.len() as u32);
+        for kind in &self.frames {
+            w.u8(*kind as u8);
+        }
+
+        w.u32(self.recent.len() as u32);
+        for cycle in &self.recent {
+            w.u64(*cycle);
+        }
+
+        w.u32(self.depth);
+        w.bool(self.last_rti.is_some());
+        w.u64(self.last_rti.unwrap_or(0));
+        w.u32(self.back_to_back);
+        w.bool(self.in_storm);
+        w.u64(self.stats.taken);
+        w.u64(self.stats.storms);
+        w.u32(self.stats.max_depth);
+
+        w.finish()
+    }
+
+    fn load_state(&mut self, data: &[u8], _version: u32) -> Result<(), String> {
+        let mut r = StateReader::new(data);
+
+        let sources = r.u32()? as usize;
+        if sources != self.asserted.len() {
+            return Err(std::format!("image has {} IRQ sources, this machine has {}", sources, self.asserted.len()));
+        }
+
+        let mut asserted = Vec::with_capacity(sources);
+        let mut per_source = Vec::with_capacity(sources);
+        for _ in 0..sources {
+            asserted.push(r.bool()?);
+            per_source.push(r.u64()?);
+        }
+
+        let mut frames = VecDeque::new();
+        for _ in 0..r.u32()? {
+            frames.push_back(InterruptKind::from_u8(r.u8()?)?);
+        }
+
+        let mut recent = VecDeque::new();
+        for _ in 0..r.u32()? {
+            recent.push_back(r.u64()?);
+        }
+
+        let depth = r.u32()?;
+        let last_rti = (r.bool()?, r.u64()?);
+        let back_to_back = r.u32()?;
+        let in_storm = r.bool()?;
+        let (taken, storms, max_depth) = (r.u64()?, r.u64()?, r.u32()?);
+
+        self.asserted = asserted;
+        self.frames = frames;
+        self.recent = recent;
+        self.depth = depth;
+        self.last_rti = if last_rti.0 { Some(last_rti.1) } else { None };
+        self.back_to_back = back_to_back;
+        self.in_storm = in_storm;
+        self.stats = IrqStats { taken, per_source, storms, max_depth };
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn line_is_the_or_of_its_sources() {
+        let mut controller = InterruptController::new();
+        let timer = controller.register_source("timer");
+        let uart = controller.register_source("uart");
+
+        assert!(!controller.irq_line());
+        controller.set_irq(timer, true);
+        controller.set_irq(uart, true);
+        controller.set_irq(timer, false);
+        assert!(controller.irq_line());
+        assert_eq!(controller.asserted_sources(), vec!["uart"]);
+    }
+
+    #[test]
+    fn only_irq_handlers_count_towards_depth() {
+        let mut controller = InterruptController::new();
+
+        controller.on_irq_taken(0, 0x8000);
+        controller.on_nmi_taken();
+        assert_eq!(controller.depth(), 1);
+
+        // The NMI handler's RTI leaves the IRQ handler running
+        controller.on_rti(10);
+        assert_eq!(controller.depth(), 1);
+
+        controller.on_brk();
+        controller.on_rti(20);
+        assert_eq!(controller.depth(), 1);
+
+        controller.on_rti(30);
+        assert_eq!(controller.depth(), 0);
+    }
+
+    #[test]
+    fn reentry_is_a_storm() {
+        let mut controller = InterruptController::new();
+        controller.limits.max_depth = 1;
+
+        assert!(controller.on_irq_taken(0, 0x8000).is_none());
+
+        // An NMI comes and goes inside the IRQ handler, which then CLIs
+        // and is re-entered
+        controller.on_nmi_taken();
+        controller.on_rti(50);
+
+        let storm = controller.on_irq_taken(100, 0x8000).expect("nested IRQ is a storm");
+        match storm.kind {
+            DiagnosticKind::InterruptStorm { reentered, .. } => assert!(reentered),
+            kind => panic!("unexpected {:?}", kind),
+        }
+        assert_eq!(controller.stats.max_depth, 2);
+    }
+
+    #[test]
+    fn back_to_back_irqs_are_a_storm() {
+        let mut controller = InterruptController::new();
+        controller.limits.max_back_to_back = 3;
+        let stuck = controller.register_source("stuck");
+        controller.set_irq(stuck, true);
+
+        let mut cycle = 0;
+        let mut storms = Vec::new();
+        for _ in 0..10 {
+            storms.extend(controller.on_irq_taken(cycle, 0x8000));
+            controller.on_rti(cycle + 20);
+            cycle += 30;
+        }
+
+        // Reported once, not on every IRQ
+        assert_eq!(storms.len(), 1);
+        match &storms[0].kind {
+            DiagnosticKind::InterruptStorm { sources, .. } => assert_eq!(sources, &vec!["stuck".to_string()]),
+            kind => panic!("unexpected {:?}", kind),
+        }
+    }
+}
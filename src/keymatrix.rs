@@ -0,0 +1,290 @@
+use std::collections::{BTreeSet, HashMap};
+use std::fs;
+use std::path::Path;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+use crate::cpu6502;
+use crate::device::BusDevice;
+
+// A keyboard wired as a matrix, the way the C64, VIC-20 and most home
+// computers scan theirs: the program selects rows by writing to one
+// register and reads back which columns have a key down in them. Which
+// host key sits where comes from a layout file.
+//
+//   +0  row select, reads back what was written
+//   +1  columns of the selected rows, read only
+//
+// Up to 8 rows and 8 columns.
+
+pub const C64_LAYOUT: &str = include_str!("../layouts/c64.toml");
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct KeyLayout {
+    pub rows: u8,
+    pub columns: u8,
+    // Select rows and report keys with 0 bits rather than 1, as the CIA
+    // and VIA ports these usually hang off do
+    pub active_low: bool,
+    // Host key name, as minifb's Key prints, to the [row, column]s it
+    // presses. Several for keys that need a shift on the guest.
+    pub keys: HashMap<String, Vec<(u8, u8)>>,
+}
+
+impl KeyLayout {
+    pub fn c64() -> KeyLayout {
+        KeyLayout::parse(C64_LAYOUT).expect("built-in C64 layout")
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<KeyLayout, String> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path).map_err(|e| std::format!("{}: {}", path.display(), e))?;
+        KeyLayout::parse(&text).map_err(|e| std::format!("{}: {}", path.display(), e))
+    }
+
+    // The part of TOML layouts use: `rows`, `columns` and `active_low` at
+    // the top, then a [keys] table of `Name = [row, column]` or
+    // `Name = [[row, column], ...]`, with # comments
+    pub fn parse(text: &str) -> Result<KeyLayout, String> {
+        let mut layout = KeyLayout { rows: 8, columns: 8, active_low: false, keys: HashMap::new() };
+        let mut in_keys = false;
+
+        for (i, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            let error = |message: &str| std::format!("line {}: {}", i + 1, message);
+
+            if line.is_empty() {
+                continue;
+            }
+            if line.starts_with('[') && !line.contains('=') {
+                match line {
+                    "[keys]" => in_keys = true,
+                    _ => return Err(error("only a [keys] table is allowed")),
+                }
+                continue;
+            }
+
+            let (name, value) = line.split_once('=').ok_or_else(|| error("expected name = value"))?;
+            let (name, value) = (name.trim().trim_matches('"'), value.trim());
+
+            if in_keys {
+                let positions = parse_positions(value).ok_or_else(|| error("expected [row, column] or a list of them"))?;
+                layout.keys.insert(name.to_string(), positions);
+                continue;
+            }
+
+            match name {
+                "rows" => layout.rows = value.parse().map_err(|_| error("rows is a number"))?,
+                "columns" => layout.columns = value.parse().map_err(|_| error("columns is a number"))?,
+                "active_low" => layout.active_low = value.parse().map_err(|_| error("active_low is true or false"))?,
+                _ => return Err(error(&std::format!("unknown setting {}", name))),
+            }
+        }
+
+        if !(1..=8).contains(&layout.rows) || !(1..=8).contains(&layout.columns) {
+            return Err("rows and columns have to be 1 to 8".to_string());
+        }
+        for (name, positions) in &layout.keys {
+            if positions.iter().any(|&(row, column)| row >= layout.rows || column >= layout.columns) {
+                return Err(std::format!("{} is outside the {}x{} matrix", name, layout.rows, layout.columns));
+            }
+        }
+
+        Ok(layout)
+    }
+}
+
+// "[1, 2]" or "[[1, 2], [3, 4]]"
+fn parse_positions(value: &str) -> Option<Vec<(u8, u8)>> {
+    let inner = value.strip_prefix('[')?.strip_suffix(']')?.trim();
+
+    if !inner.starts_with('[') {
+        return parse_position(inner).map(|position| vec![position]);
+    }
+
+    inner
+        .split(']')
+        .map(|part| part.trim().trim_start_matches(',').trim())
+        .filter(|part| !part.is_empty())
+        .map(|part| parse_position(part.strip_prefix('[')?))
+        .collect()
+}
+
+fn parse_position(pair: &str) -> Option<(u8, u8)> {
+    let (row, column) = pair.split_once(',')?;
+    Some((row.trim().parse().ok()?, column.trim().parse().ok()?))
+}
+
+#[derive(Default)]
+struct Matrix {
+    // Bit n of row_keys[r] is set while the key at row r, column n is down
+    row_keys: [u8; 8],
+    select: u8,
+}
+
+// The host's side of the keyboard, which can be on another thread
+#[derive(Clone)]
+pub struct KeyboardPort {
+    layout: Arc<KeyLayout>,
+    matrix: Arc<Mutex<Matrix>>,
+}
+
+impl KeyboardPort {
+    fn matrix(&self) -> MutexGuard<'_, Matrix> {
+        self.matrix.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    pub fn layout(&self) -> &KeyLayout {
+        &self.layout
+    }
+
+    // Replaces the keys held down with `keys`. Names the layout doesn't
+    // have are ignored.
+    pub fn set_pressed<S: AsRef<str>>(&self, keys: &[S]) {
+        let mut row_keys = [0u8; 8];
+        let positions: BTreeSet<(u8, u8)> = keys
+            .iter()
+            .filter_map(|key| self.layout.keys.get(key.as_ref()))
+            .flatten()
+            .copied()
+            .collect();
+        for (row, column) in positions {
+            row_keys[row as usize] |= 1 << column;
+        }
+
+        self.matrix().row_keys = row_keys;
+    }
+
+    pub fn release_all(&self) {
+        self.matrix().row_keys = [0; 8];
+    }
+
+    // The columns with a key down in the rows `select` picks, for chips
+    // that scan the matrix through ports of their own
+    pub fn scan(&self, select: u8) -> u8 {
+        let layout = &self.layout;
+        let matrix = self.matrix();
+        let select = if layout.active_low { !select } else { select };
+
+        let down = (0..layout.rows as usize)
+            .filter(|&row| select & (1 << row) != 0)
+            .fold(0, |columns, row| columns | matrix.row_keys[row]);
+
+        if layout.active_low { !down } else { down }
+    }
+}
+
+pub struct KeyMatrix {
+    port: KeyboardPort,
+}
+
+impl KeyMatrix {
+    pub fn new(layout: KeyLayout) -> Self {
+        KeyMatrix { port: KeyboardPort { layout: Arc::new(layout), matrix: Arc::default() } }
+    }
+
+    pub fn port(&self) -> KeyboardPort {
+        self.port.clone()
+    }
+
+    fn columns(&self) -> u8 {
+        let select = self.port.matrix().select;
+        self.port.scan(select)
+    }
+}
+
+impl BusDevice for KeyMatrix {
+    fn read(&mut self, offset: u16) -> u8 {
+        self.peek(offset)
+    }
+
+    fn peek(&self, offset: u16) -> u8 {
+        match offset & 1 {
+            0 => self.port.matrix().select,
+            _ => self.columns(),
+        }
+    }
+
+    fn write(&mut self, offset: u16, data: u8) {
+        if offset & 1 == 0 {
+            self.port.matrix().select = data;
+        }
+    }
+
+    fn reset(&mut self) {
+        // Nothing selected
+        self.port.matrix().select = if self.port.layout.active_low { 0xFF } else { 0x00 };
+    }
+}
+
+impl cpu6502 {
+    // Maps a keyboard matrix at $base-$base+1 and returns the host's end
+    // of it
+    pub fn attach_keyboard(&mut self, base: u16, layout: KeyLayout) -> KeyboardPort {
+        let mut keyboard = KeyMatrix::new(layout);
+        keyboard.reset();
+        let port = keyboard.port();
+        self.bus.borrow_mut().map("keyboard", base, base.wrapping_add(1), Box::new(keyboard));
+        port
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_layouts() {
+        let layout = KeyLayout::parse("rows = 2\ncolumns = 3 # small\n\n[keys]\nA = [0, 2]\nUp = [[1, 0], [0, 1]]\n").unwrap();
+        assert_eq!((layout.rows, layout.columns, layout.active_low), (2, 3, false));
+        assert_eq!(layout.keys["A"], vec![(0, 2)]);
+        assert_eq!(layout.keys["Up"], vec![(1, 0), (0, 1)]);
+
+        assert!(KeyLayout::parse("rows = 2\n[keys]\nA = [2, 0]\n").unwrap_err().contains("outside"));
+        assert!(KeyLayout::parse("[keys]\nA = 3\n").unwrap_err().starts_with("line 2"));
+        assert!(KeyLayout::parse("speed = 1\n").is_err());
+
+        let c64 = KeyLayout::c64();
+        assert!(c64.active_low);
+        assert_eq!(c64.keys["Tab"], vec![(7, 7)]);
+    }
+
+    #[test]
+    fn scanning_rows() {
+        let mut keyboard = KeyMatrix::new(KeyLayout::c64());
+        let port = keyboard.port();
+        keyboard.reset();
+
+        // A is row 1 column 2, shifted up arrow adds row 1 column 7
+        port.set_pressed(&["A", "Up", "F13"]);
+        keyboard.write(0, !0x02);
+        assert_eq!(keyboard.read(1), !0x84);
+        keyboard.write(0, !0x01);
+        assert_eq!(keyboard.read(1), !0x80);
+        keyboard.write(0, !0x04);
+        assert_eq!(keyboard.read(1), 0xFF);
+
+        // Every row at once, the usual "any key down?" check
+        keyboard.write(0, 0x00);
+        assert_eq!(keyboard.read(1), !0x84);
+        assert_eq!(keyboard.read(0), 0x00);
+
+        port.release_all();
+        assert_eq!(keyboard.read(1), 0xFF);
+    }
+
+    #[test]
+    fn program_scans_a_key() {
+        let mut cpu = cpu6502::new();
+        // LDA #$FD / STA $DC00 / LDA $DC01 / STA $10 / done: JMP done
+        cpu.load_program(&[0xA9, 0xFD, 0x8D, 0x00, 0xDC, 0xAD, 0x01, 0xDC, 0x85, 0x10, 0x4C, 0x0A, 0x80], 0x8000);
+        cpu.set_reset_vector(0x8000);
+        let port = cpu.attach_keyboard(0xDC00, KeyLayout::c64());
+        port.set_pressed(&["S"]);
+        cpu.reset();
+
+        for _ in 0..100 {
+            cpu.clock();
+        }
+        assert_eq!(cpu.bus.borrow().read(0x10, true), !0x20);
+    }
+}
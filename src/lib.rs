@@ -0,0 +1,4697 @@
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::{Arc, Mutex, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::ops::BitOr;
+use crate::FLAGS6502::B;
+use std::fmt::{Debug, LowerHex, Write};
+
+#[macro_use(concat_string)]
+extern crate concat_string;
+
+#[macro_use]
+mod logging;
+
+pub mod accesslog;
+pub mod acia;
+#[cfg(test)]
+mod addressing;
+pub mod analysis;
+pub mod annotations;
+pub mod apple1;
+pub mod assembler;
+pub mod atari2600;
+pub mod audit;
+pub mod banked;
+pub mod benchmarks;
+pub mod c64;
+pub mod callstack;
+pub mod cartridge;
+pub mod charset;
+pub mod cia;
+pub mod codeview;
+#[cfg(test)]
+mod conformance;
+pub mod console;
+pub mod cpu65816;
+pub mod crash;
+pub mod d64;
+pub mod cyclecounter;
+#[cfg(test)]
+mod cycles;
+pub mod debugger;
+pub mod demos;
+pub mod device;
+pub mod devicelog;
+pub mod diagnostics;
+pub mod disasm;
+pub mod dma;
+pub mod error;
+pub mod expr;
+pub mod fixtures;
+pub mod frame;
+pub mod framehash;
+#[cfg(feature = "gui")]
+pub mod gui;
+pub mod hostfs;
+pub mod interrupts;
+pub mod keymatrix;
+pub mod linearfb;
+pub mod loader;
+pub mod machine;
+pub mod memview;
+pub mod mos;
+pub mod optable;
+pub mod patch;
+pub mod perf;
+pub mod pia;
+pub mod pins;
+pub mod ppuview;
+pub mod profiler;
+pub mod project;
+pub mod raminit;
+pub mod rdy;
+pub mod registers;
+pub mod replay;
+pub mod riot;
+pub mod romdb;
+pub mod run;
+pub mod scheduler;
+pub mod selftest;
+pub mod shadow_stack;
+pub mod snapshot;
+pub mod source;
+pub mod stack;
+pub mod stats;
+pub mod symbols;
+pub mod testbus;
+pub mod tia;
+pub mod timeline;
+pub mod timing;
+pub mod trace;
+pub mod traps;
+pub mod tstates;
+pub mod vectors;
+pub mod vicii;
+pub mod watch;
+pub mod watchdog;
+pub mod wav;
+
+use crate::accesslog::{AccessKind, AccessLog};
+use crate::callstack::{CallKind, CallStack};
+use crate::cartridge::Cartridge;
+use crate::crash::PcHistory;
+use crate::d64::KernalShim;
+use crate::device::{BusDevice, Mapping};
+use crate::devicelog::{DeviceEventKind, DeviceLog};
+use crate::dma::Dma;
+use crate::diagnostics::{Diagnostic, DiagnosticKind, Diagnostics, Severity};
+use crate::error::EmuError;
+use crate::interrupts::{InterruptController, IrqSource, HOST_IRQ};
+use crate::mos::OsShim;
+use crate::patch::Patches;
+use crate::pins::PinTracker;
+use crate::profiler::{ProfileEntry, ProfileSort, Profiler};
+use crate::raminit::{RamInit, Xorshift64};
+use crate::rdy::RdyLine;
+use crate::replay::{Event, Player, Recording, Stimulus};
+use crate::romdb::RomDatabase;
+use crate::scheduler::{AlarmId, EventQueue, Scheduler};
+use crate::shadow_stack::ShadowStack;
+use crate::snapshot::CpuState;
+use crate::stats::OpcodeCounts;
+use crate::symbols::SymbolTable;
+use crate::timeline::{SpanKind, Timeline};
+use crate::trace::{TraceRecord, TraceRing, TraceSink};
+use crate::traps::Traps;
+use crate::vectors::{Cause, Vector, VectorLog};
+use crate::watchdog::Watchdog;
+
+type RamArray = [u8; 64 * 1024];
+
+// Several CPUs can sit on the same bus, each holding a handle to it. The
+// handle (and the CPU holding it) can be moved to another thread, so
+// emulation can run away from the UI.
+#[derive(Clone)]
+pub struct SharedBus(Arc<RwLock<Bus>>);
+
+impl SharedBus {
+    pub fn new(bus: Bus) -> Self {
+        SharedBus(Arc::new(RwLock::new(bus)))
+    }
+
+    // A panic elsewhere while the bus was held leaves it as it was, which
+    // is still a bus, so poisoning is ignored
+    pub fn borrow(&self) -> RwLockReadGuard<'_, Bus> {
+        self.0.read().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    pub fn borrow_mut(&self) -> RwLockWriteGuard<'_, Bus> {
+        self.0.write().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    pub fn is_same(&self, other: &SharedBus) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+// What a read returns where nothing answers it
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OpenBus {
+    // Whatever was last on the data bus, which is what a real 6502 sees
+    // as the value floats. Usually the last byte of the instruction.
+    Latch,
+    Fixed(u8),
+    // Noise, the same sequence every time for the same seed
+    Random(u64),
+}
+
+// A read or write that nothing answered
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct UnmappedAccess {
+    pub addr: u16,
+    pub write: bool,
+}
+
+pub struct Bus {
+    ram: RamArray,
+    cart: Option<Cartridge>,
+    // Game Genie codes and the pokes made on reset
+    patches: Patches,
+    mapped: Vec<Mapping>,
+    // Ranges with no RAM behind them, empty means RAM everywhere
+    unmapped: Vec<(u16, u16)>,
+    // RAM ranges that repeat every `size` bytes, as (start, end, size)
+    ram_mirrors: Vec<(u16, u16, u16)>,
+    // RAM ranges that only see some address lines, as (start, end, mask)
+    ram_decoding: Vec<(u16, u16, u16)>,
+    open_bus: OpenBus,
+    latch: AtomicU8,
+    // Where OpenBus::Random has got to
+    noise: Mutex<Xorshift64>,
+    // Collected only while the debugger wants them as faults
+    report_unmapped: bool,
+    unmapped_accesses: Mutex<Vec<UnmappedAccess>>,
+    // Asked for by slow devices since the CPU last looked
+    wait_states: AtomicU8,
+}
+
+impl Bus {
+    pub fn new() -> Self {
+        return Bus {
+            ram: [0; 64 * 1024],
+            cart: None,
+            patches: Patches::new(),
+            mapped: Vec::new(),
+            unmapped: Vec::new(),
+            ram_mirrors: Vec::new(),
+            ram_decoding: Vec::new(),
+            open_bus: OpenBus::Latch,
+            latch: AtomicU8::new(0),
+            noise: Mutex::new(Xorshift64::new(0)),
+            report_unmapped: false,
+            unmapped_accesses: Mutex::new(Vec::new()),
+            wait_states: AtomicU8::new(0),
+        };
+    }
+
+    // A bus with only `size` bytes of RAM, from $0000 up. Above that
+    // nothing answers until something is mapped there.
+    pub fn with_ram(size: usize) -> Self {
+        let mut bus = Bus::new();
+        bus.limit_ram(size);
+        bus
+    }
+
+    // A bus whose RAM powers on holding `init` rather than all zeroes
+    pub fn with_ram_init(init: RamInit) -> Self {
+        let mut bus = Bus::new();
+        bus.fill_ram(init);
+        bus
+    }
+
+    // Puts RAM back as it was at power on, in the pattern `init`
+    pub fn fill_ram(&mut self, init: RamInit) {
+        init.fill(&mut self.ram);
+    }
+
+    pub fn limit_ram(&mut self, size: usize) {
+        if size < 0x10000 {
+            self.set_unmapped(size as u16, 0xFFFF);
+        }
+    }
+
+    // Takes the RAM out from under $start-$end. Devices and the cartridge
+    // still answer there, anything else reads as open bus and writes go
+    // nowhere.
+    pub fn set_unmapped(&mut self, start: u16, end: u16) {
+        self.unmapped.push((start.min(end), start.max(end)));
+    }
+
+    pub fn clear_unmapped(&mut self) {
+        self.unmapped.clear();
+    }
+
+    pub fn set_open_bus(&mut self, open_bus: OpenBus) {
+        self.open_bus = open_bus;
+        if let OpenBus::Random(seed) = open_bus {
+            *self.noise.lock().unwrap_or_else(PoisonError::into_inner) = Xorshift64::new(seed);
+        }
+    }
+
+    pub fn open_bus(&self) -> OpenBus {
+        self.open_bus
+    }
+
+    // The last value driven on the data bus
+    pub fn latch(&self) -> u8 {
+        self.latch.load(Ordering::Relaxed)
+    }
+
+    pub fn report_unmapped(&mut self, report: bool) {
+        self.report_unmapped = report;
+        self.unmapped_accesses.lock().unwrap_or_else(PoisonError::into_inner).clear();
+    }
+
+    pub fn take_unmapped_accesses(&self) -> Vec<UnmappedAccess> {
+        std::mem::take(&mut *self.unmapped_accesses.lock().unwrap_or_else(PoisonError::into_inner))
+    }
+
+    // The wait states the devices asked for on the last access, see
+    // BusDevice::wait_states
+    pub fn take_wait_states(&self) -> u8 {
+        self.wait_states.swap(0, Ordering::Relaxed)
+    }
+
+    // Makes RAM from $start-$end repeat every `size` bytes, so on the NES
+    // mirror_ram(0x0000, 0x1FFF, 0x0800) gives 2K seen four times over
+    pub fn mirror_ram(&mut self, start: u16, end: u16, size: u16) {
+        self.ram_mirrors.push((start.min(end), start.max(end), size.max(1)));
+    }
+
+    pub fn clear_ram_mirrors(&mut self) {
+        self.ram_mirrors.clear();
+    }
+
+    // Partial address decoding: RAM selected all through $start-$end that
+    // only has the address lines in `mask` wired to it, so it repeats
+    // wherever the others change. A 32K chip selected by A15 low is
+    // decode_ram(0x0000, 0x7FFF, 0x7FFF), the NES's 2K with A11 and A12
+    // ignored decode_ram(0x0000, 0x1FFF, 0x07FF). The range is RAM even
+    // where it's been taken away, so a small bus can still mirror.
+    pub fn decode_ram(&mut self, start: u16, end: u16, mask: u16) {
+        self.ram_decoding.push((start.min(end), start.max(end), mask));
+    }
+
+    pub fn clear_ram_decoding(&mut self) {
+        self.ram_decoding.clear();
+    }
+
+    fn ram_decoding_at(&self, addr: u16) -> Option<u16> {
+        self.ram_decoding.iter().find(|&&(start, end, _)| start <= addr && addr <= end).map(|&(_, _, mask)| mask)
+    }
+
+    // What's in RAM behind `addr`, whatever device or cartridge answers
+    // there, the way a video chip with its own path to RAM sees it
+    pub fn peek_ram(&self, addr: u16) -> u8 {
+        self.ram[self.ram_addr(addr)]
+    }
+
+    // The RAM cell behind `addr` once decoding and mirrors are folded away
+    fn ram_addr(&self, addr: u16) -> usize {
+        if let Some(mask) = self.ram_decoding_at(addr) {
+            return (addr & mask) as usize;
+        }
+
+        match self.ram_mirrors.iter().find(|&&(start, end, _)| start <= addr && addr <= end) {
+            Some(&(start, _, size)) => (start + (addr - start) % size) as usize,
+            None => addr as usize,
+        }
+    }
+
+    fn is_unmapped(&self, addr: u16) -> bool {
+        self.ram_decoding_at(addr).is_none() && self.unmapped.iter().any(|&(start, end)| start <= addr && addr <= end)
+    }
+
+    // Writes to `addr` would be lost: no device or cartridge answers and
+    // there's no RAM there
+    fn goes_nowhere(&self, addr: u16) -> bool {
+        self.is_unmapped(addr)
+            && self.mapping_at(addr).is_none()
+            && self.cart.as_ref().map_or(true, |cart| cart.cpu_read(addr).is_none())
+    }
+
+    fn unmapped_access(&self, addr: u16, write: bool) {
+        if self.report_unmapped {
+            self.unmapped_accesses.lock().unwrap_or_else(PoisonError::into_inner).push(UnmappedAccess { addr, write });
+        }
+    }
+
+    pub fn insert_cartridge(&mut self, cart: Cartridge) {
+        self.cart = Some(cart);
+    }
+
+    pub fn cartridge(&self) -> Option<&Cartridge> {
+        self.cart.as_ref()
+    }
+
+    pub fn cartridge_mut(&mut self) -> Option<&mut Cartridge> {
+        self.cart.as_mut()
+    }
+
+    // Maps a device over $start-$end, in front of the cartridge and RAM.
+    // A device mapped later wins where ranges overlap.
+    pub fn map(&mut self, name: &str, start: u16, end: u16, device: Box<dyn BusDevice>) {
+        self.insert_mapping(name, start, end, None, 0xFFFF, device);
+    }
+
+    // Maps a device of `size` bytes that repeats through $start-$end, e.g.
+    // the NES PPU's 8 registers at $2000-$3FFF
+    pub fn map_mirrored(&mut self, name: &str, start: u16, end: u16, size: u16, device: Box<dyn BusDevice>) {
+        self.insert_mapping(name, start, end, Some(size.max(1)), 0xFFFF, device);
+    }
+
+    // Maps a device that only sees the address lines in `mask` of its
+    // offset into $start-$end, e.g. a 6522 selected over $6000-$7FFF with
+    // just A0-A3 on its register selects is mask 0x000F
+    pub fn map_decoded(&mut self, name: &str, start: u16, end: u16, mask: u16, device: Box<dyn BusDevice>) {
+        self.insert_mapping(name, start, end, None, mask, device);
+    }
+
+    fn insert_mapping(&mut self, name: &str, start: u16, end: u16, mirror: Option<u16>, mask: u16, device: Box<dyn BusDevice>) {
+        let (start, end) = (start.min(end), start.max(end));
+        self.mapped.insert(0, Mapping { name: name.to_string(), start, end, mirror, mask, device: Mutex::new(device) });
+    }
+
+    pub fn unmap(&mut self, name: &str) -> Option<Box<dyn BusDevice>> {
+        let index = self.mapped.iter().position(|mapping| mapping.name == name)?;
+        Some(self.mapped.remove(index).device.into_inner().unwrap_or_else(PoisonError::into_inner))
+    }
+
+    pub fn mappings(&self) -> impl Iterator<Item = &Mapping> {
+        self.mapped.iter()
+    }
+
+    fn mapping_at(&self, addr: u16) -> Option<&Mapping> {
+        self.mapped.iter().find(|mapping| mapping.contains(addr))
+    }
+
+    pub fn write(&mut self, addr: u16, data: u8) {
+        self.latch.store(data, Ordering::Relaxed);
+
+        if let Some(mapping) = self.mapping_at(addr) {
+            let mut device = mapping.device();
+            let offset = mapping.offset(addr);
+            self.wait_states.store(device.wait_states(offset), Ordering::Relaxed);
+            device.write(offset, data);
+            return;
+        }
+
+        // The cartridge gets first pick of the rest
+        if let Some(cart) = &mut self.cart {
+            if cart.cpu_write(addr, data) {
+                return;
+            }
+        }
+
+        if self.is_unmapped(addr) {
+            self.unmapped_access(addr, true);
+            return;
+        }
+
+        if addr >= 0x0000 && addr <= 0xFFFF {
+            let cell = self.ram_addr(addr);
+            self.ram[cell] = data;
+        }
+    }
+
+    // Reads that aren't read_only leave their value in the latch
+    pub fn read(&self, addr: u16, read_only: bool) -> u8 {
+        let data = self.drive(addr, read_only);
+        if !read_only {
+            self.latch.store(data, Ordering::Relaxed);
+        }
+        data
+    }
+
+    fn drive(&self, addr: u16, read_only: bool) -> u8 {
+        if let Some(mapping) = self.mapping_at(addr) {
+            return if read_only {
+                mapping.device().peek(mapping.offset(addr))
+            } else {
+                let mut device = mapping.device();
+                let offset = mapping.offset(addr);
+                self.wait_states.store(device.wait_states(offset), Ordering::Relaxed);
+                device.read(offset)
+            };
+        }
+
+        if let Some(cart) = &self.cart {
+            if let Some(data) = cart.cpu_read(addr) {
+                return self.patches.cartridge_read(addr, data);
+            }
+        }
+
+        if self.is_unmapped(addr) {
+            if !read_only {
+                self.unmapped_access(addr, false);
+            }
+            return match self.open_bus {
+                OpenBus::Latch => self.latch.load(Ordering::Relaxed),
+                OpenBus::Fixed(value) => value,
+                // Peeking shows what's next without using it up
+                OpenBus::Random(_) => {
+                    let mut noise = self.noise.lock().unwrap_or_else(PoisonError::into_inner);
+                    let mut next = *noise;
+                    let value = next.next_u8();
+                    if !read_only {
+                        *noise = next;
+                    }
+                    value
+                }
+            };
+        }
+
+        if addr >= 0x0000 && addr <= 0xFFFF {
+            // let v = self.ram.get(addr).expect("Failed to read value from array").collect();
+            return self.ram[self.ram_addr(addr)];
+        }
+
+        return 0x00;
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[repr(u8)]
+pub enum FLAGS6502 {
+    C = (1 << 0),
+    // Carry Bit
+    Z = (1 << 1),
+    // Zero
+    I = (1 << 2),
+    // Disable Interrupts
+    D = (1 << 3),
+    // Decimal Mode (unused in this implementation)
+    B = (1 << 4),
+    // Break
+    U = (1 << 5),
+    // Unused
+    V = (1 << 6),
+    // Overflow
+    N = (1 << 7), // Negative
+}
+
+// Which member of the family to emulate. The 65C02 only adds WAI and STP
+// so far and drops the JAMs, everything else still behaves like the NMOS
+// part.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Variant {
+    Nmos6502,
+    Cmos65C02,
+}
+
+// Opcodes that jam an NMOS 6502, the 65C02 runs them as NOPs
+const JAM_OPCODES: [u8; 12] = [0x02, 0x12, 0x22, 0x32, 0x42, 0x52, 0x62, 0x72, 0x92, 0xB2, 0xD2, 0xF2];
+
+// Why the CPU isn't fetching instructions
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Halt {
+    Running,
+    // WAI, until the IRQ line is asserted or an NMI or reset arrives
+    Waiting,
+    // STP, until reset
+    Stopped,
+    // A JAM (KIL) opcode on the NMOS part locked it up, until reset
+    Jammed,
+}
+
+// What the CPU saw the last time it looked at its interrupt lines, acted
+// on at the next instruction boundary
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct InterruptPoll {
+    // NMI is edge triggered, an edge is latched here until the next poll
+    pub nmi_edge: bool,
+    pub nmi_pending: bool,
+    pub irq_pending: bool,
+    // CLI, SEI and PLP change I after the poll, so it sees the old flag
+    pub i_seen: Option<bool>,
+    // Set while an interrupt or reset sequence runs, nothing is polled then
+    pub servicing: bool,
+}
+
+type OperateFn = fn(&mut cpu6502) -> u8;
+type AddrModeFn = OperateFn;
+
+struct INSTRUCTION {
+    pub name: String,
+    pub operate: OperateFn,
+    pub addr_mode: AddrModeFn,
+    pub cycles: u8,
+    // Takes a cycle more when the indexed address crosses a page. Reads
+    // do, stores and read-modify-write instructions always take the fix
+    // up cycle so it's already in `cycles`.
+    pub page_penalty: bool,
+}
+
+pub struct cpu6502 {
+    pub a: u8,
+    // Accumulator Register
+    pub x: u8,
+    // X Register
+    pub y: u8,
+    // Y Register
+    pub stkp: u8,
+    // Stack Pointer (points to location on bus)
+    pub pc: u16,
+    // Program Counter
+    pub status: u8,
+    // Status Register
+    fetched: u8,
+    addr_abs: u16,
+    addr_rel: u16,
+    opcode: u8,
+    cycles: u8,
+    lookup: Vec<INSTRUCTION>,
+    pub bus: SharedBus,
+    clock_count: u64,
+    temp: u16,
+    profiler: Option<Profiler>,
+    timeline: Option<Timeline>,
+    access_log: Option<AccessLog>,
+    // Where the instruction running now started
+    instruction_pc: u16,
+    opcode_counts: OpcodeCounts,
+    trace: Option<Box<dyn TraceSink>>,
+    trace_ring: Option<TraceRing>,
+    traps: Traps,
+    trap_output: Box<dyn std::io::Write + Send>,
+    exit_code: Option<u8>,
+    os_shim: Option<OsShim>,
+    kernal: Option<KernalShim>,
+    scheduler: Scheduler,
+    recording: Option<Recording>,
+    player: Option<Player>,
+    shadow_stack: Option<ShadowStack>,
+    variant: Variant,
+    halt: Halt,
+    // RDY is held low until this cycle
+    held_until: u64,
+    // Transfers waiting for the bus, the one in front has it
+    dma: VecDeque<Dma>,
+    poll: InterruptPoll,
+    pub diagnostics: Diagnostics,
+    pub interrupts: InterruptController,
+    // Where the last few instructions were, for crash reports
+    pc_history: PcHistory,
+    call_stack: CallStack,
+    vectors: VectorLog,
+    watchdog: Option<Watchdog>,
+    // Known cartridges, to correct the headers of those loaded
+    rom_database: RomDatabase,
+    device_log: DeviceLog,
+    pins: PinTracker,
+    rdy: RdyLine,
+    // SO is being held low, V was set when it went
+    so_low: bool,
+}
+
+type cpu = cpu6502;
+
+impl cpu6502 {
+    pub fn new() -> Self {
+        let lookup: Vec<INSTRUCTION> = vec![
+            INSTRUCTION {
+                name: "BRK".to_string(),
+                operate: cpu::BRK,
+                addr_mode: cpu::IMM,
+                cycles: 7,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "ORA".to_string(),
+                operate: cpu::ORA,
+                addr_mode: cpu::IZX,
+                cycles: 6,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "???".to_string(),
+                operate: cpu::XXX,
+                addr_mode: cpu::IMP,
+                cycles: 2,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "???".to_string(),
+                operate: cpu::XXX,
+                addr_mode: cpu::IMP,
+                cycles: 8,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "???".to_string(),
+                operate: cpu::NOP,
+                addr_mode: cpu::IMP,
+                cycles: 3,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "ORA".to_string(),
+                operate: cpu::ORA,
+                addr_mode: cpu::ZP0,
+                cycles: 3,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "ASL".to_string(),
+                operate: cpu::ASL,
+                addr_mode: cpu::ZP0,
+                cycles: 5,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "???".to_string(),
+                operate: cpu::XXX,
+                addr_mode: cpu::IMP,
+                cycles: 5,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "PHP".to_string(),
+                operate: cpu::PHP,
+                addr_mode: cpu::IMP,
+                cycles: 3,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "ORA".to_string(),
+                operate: cpu::ORA,
+                addr_mode: cpu::IMM,
+                cycles: 2,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "ASL".to_string(),
+                operate: cpu::ASL,
+                addr_mode: cpu::IMP,
+                cycles: 2,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "???".to_string(),
+                operate: cpu::XXX,
+                addr_mode: cpu::IMP,
+                cycles: 2,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "???".to_string(),
+                operate: cpu::NOP,
+                addr_mode: cpu::IMP,
+                cycles: 4,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "ORA".to_string(),
+                operate: cpu::ORA,
+                addr_mode: cpu::ABS,
+                cycles: 4,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "ASL".to_string(),
+                operate: cpu::ASL,
+                addr_mode: cpu::ABS,
+                cycles: 6,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "???".to_string(),
+                operate: cpu::XXX,
+                addr_mode: cpu::IMP,
+                cycles: 6,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "BPL".to_string(),
+                operate: cpu::BPL,
+                addr_mode: cpu::REL,
+                cycles: 2,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "ORA".to_string(),
+                operate: cpu::ORA,
+                addr_mode: cpu::IZY,
+                cycles: 5,
+                page_penalty: true,
+            },
+            INSTRUCTION {
+                name: "???".to_string(),
+                operate: cpu::XXX,
+                addr_mode: cpu::IMP,
+                cycles: 2,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "???".to_string(),
+                operate: cpu::XXX,
+                addr_mode: cpu::IMP,
+                cycles: 8,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "???".to_string(),
+                operate: cpu::NOP,
+                addr_mode: cpu::IMP,
+                cycles: 4,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "ORA".to_string(),
+                operate: cpu::ORA,
+                addr_mode: cpu::ZPX,
+                cycles: 4,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "ASL".to_string(),
+                operate: cpu::ASL,
+                addr_mode: cpu::ZPX,
+                cycles: 6,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "???".to_string(),
+                operate: cpu::XXX,
+                addr_mode: cpu::IMP,
+                cycles: 6,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "CLC".to_string(),
+                operate: cpu::CLC,
+                addr_mode: cpu::IMP,
+                cycles: 2,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "ORA".to_string(),
+                operate: cpu::ORA,
+                addr_mode: cpu::ABY,
+                cycles: 4,
+                page_penalty: true,
+            },
+            INSTRUCTION {
+                name: "???".to_string(),
+                operate: cpu::NOP,
+                addr_mode: cpu::IMP,
+                cycles: 2,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "???".to_string(),
+                operate: cpu::XXX,
+                addr_mode: cpu::IMP,
+                cycles: 7,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "???".to_string(),
+                operate: cpu::NOP,
+                addr_mode: cpu::IMP,
+                cycles: 4,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "ORA".to_string(),
+                operate: cpu::ORA,
+                addr_mode: cpu::ABX,
+                cycles: 4,
+                page_penalty: true,
+            },
+            INSTRUCTION {
+                name: "ASL".to_string(),
+                operate: cpu::ASL,
+                addr_mode: cpu::ABX,
+                cycles: 7,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "???".to_string(),
+                operate: cpu::XXX,
+                addr_mode: cpu::IMP,
+                cycles: 7,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "JSR".to_string(),
+                operate: cpu::JSR,
+                addr_mode: cpu::ABS,
+                cycles: 6,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "AND".to_string(),
+                operate: cpu::AND,
+                addr_mode: cpu::IZX,
+                cycles: 6,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "???".to_string(),
+                operate: cpu::XXX,
+                addr_mode: cpu::IMP,
+                cycles: 2,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "???".to_string(),
+                operate: cpu::XXX,
+                addr_mode: cpu::IMP,
+                cycles: 8,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "BIT".to_string(),
+                operate: cpu::BIT,
+                addr_mode: cpu::ZP0,
+                cycles: 3,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "AND".to_string(),
+                operate: cpu::AND,
+                addr_mode: cpu::ZP0,
+                cycles: 3,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "ROL".to_string(),
+                operate: cpu::ROL,
+                addr_mode: cpu::ZP0,
+                cycles: 5,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "???".to_string(),
+                operate: cpu::XXX,
+                addr_mode: cpu::IMP,
+                cycles: 5,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "PLP".to_string(),
+                operate: cpu::PLP,
+                addr_mode: cpu::IMP,
+                cycles: 4,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "AND".to_string(),
+                operate: cpu::AND,
+                addr_mode: cpu::IMM,
+                cycles: 2,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "ROL".to_string(),
+                operate: cpu::ROL,
+                addr_mode: cpu::IMP,
+                cycles: 2,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "???".to_string(),
+                operate: cpu::XXX,
+                addr_mode: cpu::IMP,
+                cycles: 2,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "BIT".to_string(),
+                operate: cpu::BIT,
+                addr_mode: cpu::ABS,
+                cycles: 4,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "AND".to_string(),
+                operate: cpu::AND,
+                addr_mode: cpu::ABS,
+                cycles: 4,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "ROL".to_string(),
+                operate: cpu::ROL,
+                addr_mode: cpu::ABS,
+                cycles: 6,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "???".to_string(),
+                operate: cpu::XXX,
+                addr_mode: cpu::IMP,
+                cycles: 6,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "BMI".to_string(),
+                operate: cpu::BMI,
+                addr_mode: cpu::REL,
+                cycles: 2,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "AND".to_string(),
+                operate: cpu::AND,
+                addr_mode: cpu::IZY,
+                cycles: 5,
+                page_penalty: true,
+            },
+            INSTRUCTION {
+                name: "???".to_string(),
+                operate: cpu::XXX,
+                addr_mode: cpu::IMP,
+                cycles: 2,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "???".to_string(),
+                operate: cpu::XXX,
+                addr_mode: cpu::IMP,
+                cycles: 8,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "???".to_string(),
+                operate: cpu::NOP,
+                addr_mode: cpu::IMP,
+                cycles: 4,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "AND".to_string(),
+                operate: cpu::AND,
+                addr_mode: cpu::ZPX,
+                cycles: 4,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "ROL".to_string(),
+                operate: cpu::ROL,
+                addr_mode: cpu::ZPX,
+                cycles: 6,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "???".to_string(),
+                operate: cpu::XXX,
+                addr_mode: cpu::IMP,
+                cycles: 6,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "SEC".to_string(),
+                operate: cpu::SEC,
+                addr_mode: cpu::IMP,
+                cycles: 2,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "AND".to_string(),
+                operate: cpu::AND,
+                addr_mode: cpu::ABY,
+                cycles: 4,
+                page_penalty: true,
+            },
+            INSTRUCTION {
+                name: "???".to_string(),
+                operate: cpu::NOP,
+                addr_mode: cpu::IMP,
+                cycles: 2,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "???".to_string(),
+                operate: cpu::XXX,
+                addr_mode: cpu::IMP,
+                cycles: 7,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "???".to_string(),
+                operate: cpu::NOP,
+                addr_mode: cpu::IMP,
+                cycles: 4,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "AND".to_string(),
+                operate: cpu::AND,
+                addr_mode: cpu::ABX,
+                cycles: 4,
+                page_penalty: true,
+            },
+            INSTRUCTION {
+                name: "ROL".to_string(),
+                operate: cpu::ROL,
+                addr_mode: cpu::ABX,
+                cycles: 7,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "???".to_string(),
+                operate: cpu::XXX,
+                addr_mode: cpu::IMP,
+                cycles: 7,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "RTI".to_string(),
+                operate: cpu::RTI,
+                addr_mode: cpu::IMP,
+                cycles: 6,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "EOR".to_string(),
+                operate: cpu::EOR,
+                addr_mode: cpu::IZX,
+                cycles: 6,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "???".to_string(),
+                operate: cpu::XXX,
+                addr_mode: cpu::IMP,
+                cycles: 2,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "???".to_string(),
+                operate: cpu::XXX,
+                addr_mode: cpu::IMP,
+                cycles: 8,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "???".to_string(),
+                operate: cpu::NOP,
+                addr_mode: cpu::IMP,
+                cycles: 3,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "EOR".to_string(),
+                operate: cpu::EOR,
+                addr_mode: cpu::ZP0,
+                cycles: 3,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "LSR".to_string(),
+                operate: cpu::LSR,
+                addr_mode: cpu::ZP0,
+                cycles: 5,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "???".to_string(),
+                operate: cpu::XXX,
+                addr_mode: cpu::IMP,
+                cycles: 5,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "PHA".to_string(),
+                operate: cpu::PHA,
+                addr_mode: cpu::IMP,
+                cycles: 3,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "EOR".to_string(),
+                operate: cpu::EOR,
+                addr_mode: cpu::IMM,
+                cycles: 2,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "LSR".to_string(),
+                operate: cpu::LSR,
+                addr_mode: cpu::IMP,
+                cycles: 2,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "???".to_string(),
+                operate: cpu::XXX,
+                addr_mode: cpu::IMP,
+                cycles: 2,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "JMP".to_string(),
+                operate: cpu::JMP,
+                addr_mode: cpu::ABS,
+                cycles: 3,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "EOR".to_string(),
+                operate: cpu::EOR,
+                addr_mode: cpu::ABS,
+                cycles: 4,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "LSR".to_string(),
+                operate: cpu::LSR,
+                addr_mode: cpu::ABS,
+                cycles: 6,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "???".to_string(),
+                operate: cpu::XXX,
+                addr_mode: cpu::IMP,
+                cycles: 6,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "BVC".to_string(),
+                operate: cpu::BVC,
+                addr_mode: cpu::REL,
+                cycles: 2,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "EOR".to_string(),
+                operate: cpu::EOR,
+                addr_mode: cpu::IZY,
+                cycles: 5,
+                page_penalty: true,
+            },
+            INSTRUCTION {
+                name: "???".to_string(),
+                operate: cpu::XXX,
+                addr_mode: cpu::IMP,
+                cycles: 2,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "???".to_string(),
+                operate: cpu::XXX,
+                addr_mode: cpu::IMP,
+                cycles: 8,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "???".to_string(),
+                operate: cpu::NOP,
+                addr_mode: cpu::IMP,
+                cycles: 4,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "EOR".to_string(),
+                operate: cpu::EOR,
+                addr_mode: cpu::ZPX,
+                cycles: 4,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "LSR".to_string(),
+                operate: cpu::LSR,
+                addr_mode: cpu::ZPX,
+                cycles: 6,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "???".to_string(),
+                operate: cpu::XXX,
+                addr_mode: cpu::IMP,
+                cycles: 6,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "CLI".to_string(),
+                operate: cpu::CLI,
+                addr_mode: cpu::IMP,
+                cycles: 2,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "EOR".to_string(),
+                operate: cpu::EOR,
+                addr_mode: cpu::ABY,
+                cycles: 4,
+                page_penalty: true,
+            },
+            INSTRUCTION {
+                name: "???".to_string(),
+                operate: cpu::NOP,
+                addr_mode: cpu::IMP,
+                cycles: 2,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "???".to_string(),
+                operate: cpu::XXX,
+                addr_mode: cpu::IMP,
+                cycles: 7,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "???".to_string(),
+                operate: cpu::NOP,
+                addr_mode: cpu::IMP,
+                cycles: 4,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "EOR".to_string(),
+                operate: cpu::EOR,
+                addr_mode: cpu::ABX,
+                cycles: 4,
+                page_penalty: true,
+            },
+            INSTRUCTION {
+                name: "LSR".to_string(),
+                operate: cpu::LSR,
+                addr_mode: cpu::ABX,
+                cycles: 7,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "???".to_string(),
+                operate: cpu::XXX,
+                addr_mode: cpu::IMP,
+                cycles: 7,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "RTS".to_string(),
+                operate: cpu::RTS,
+                addr_mode: cpu::IMP,
+                cycles: 6,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "ADC".to_string(),
+                operate: cpu::ADC,
+                addr_mode: cpu::IZX,
+                cycles: 6,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "???".to_string(),
+                operate: cpu::XXX,
+                addr_mode: cpu::IMP,
+                cycles: 2,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "???".to_string(),
+                operate: cpu::XXX,
+                addr_mode: cpu::IMP,
+                cycles: 8,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "???".to_string(),
+                operate: cpu::NOP,
+                addr_mode: cpu::IMP,
+                cycles: 3,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "ADC".to_string(),
+                operate: cpu::ADC,
+                addr_mode: cpu::ZP0,
+                cycles: 3,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "ROR".to_string(),
+                operate: cpu::ROR,
+                addr_mode: cpu::ZP0,
+                cycles: 5,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "???".to_string(),
+                operate: cpu::XXX,
+                addr_mode: cpu::IMP,
+                cycles: 5,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "PLA".to_string(),
+                operate: cpu::PLA,
+                addr_mode: cpu::IMP,
+                cycles: 4,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "ADC".to_string(),
+                operate: cpu::ADC,
+                addr_mode: cpu::IMM,
+                cycles: 2,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "ROR".to_string(),
+                operate: cpu::ROR,
+                addr_mode: cpu::IMP,
+                cycles: 2,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "???".to_string(),
+                operate: cpu::XXX,
+                addr_mode: cpu::IMP,
+                cycles: 2,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "JMP".to_string(),
+                operate: cpu::JMP,
+                addr_mode: cpu::IND,
+                cycles: 5,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "ADC".to_string(),
+                operate: cpu::ADC,
+                addr_mode: cpu::ABS,
+                cycles: 4,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "ROR".to_string(),
+                operate: cpu::ROR,
+                addr_mode: cpu::ABS,
+                cycles: 6,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "???".to_string(),
+                operate: cpu::XXX,
+                addr_mode: cpu::IMP,
+                cycles: 6,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "BVS".to_string(),
+                operate: cpu::BVS,
+                addr_mode: cpu::REL,
+                cycles: 2,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "ADC".to_string(),
+                operate: cpu::ADC,
+                addr_mode: cpu::IZY,
+                cycles: 5,
+                page_penalty: true,
+            },
+            INSTRUCTION {
+                name: "???".to_string(),
+                operate: cpu::XXX,
+                addr_mode: cpu::IMP,
+                cycles: 2,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "???".to_string(),
+                operate: cpu::XXX,
+                addr_mode: cpu::IMP,
+                cycles: 8,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "???".to_string(),
+                operate: cpu::NOP,
+                addr_mode: cpu::IMP,
+                cycles: 4,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "ADC".to_string(),
+                operate: cpu::ADC,
+                addr_mode: cpu::ZPX,
+                cycles: 4,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "ROR".to_string(),
+                operate: cpu::ROR,
+                addr_mode: cpu::ZPX,
+                cycles: 6,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "???".to_string(),
+                operate: cpu::XXX,
+                addr_mode: cpu::IMP,
+                cycles: 6,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "SEI".to_string(),
+                operate: cpu::SEI,
+                addr_mode: cpu::IMP,
+                cycles: 2,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "ADC".to_string(),
+                operate: cpu::ADC,
+                addr_mode: cpu::ABY,
+                cycles: 4,
+                page_penalty: true,
+            },
+            INSTRUCTION {
+                name: "???".to_string(),
+                operate: cpu::NOP,
+                addr_mode: cpu::IMP,
+                cycles: 2,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "???".to_string(),
+                operate: cpu::XXX,
+                addr_mode: cpu::IMP,
+                cycles: 7,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "???".to_string(),
+                operate: cpu::NOP,
+                addr_mode: cpu::IMP,
+                cycles: 4,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "ADC".to_string(),
+                operate: cpu::ADC,
+                addr_mode: cpu::ABX,
+                cycles: 4,
+                page_penalty: true,
+            },
+            INSTRUCTION {
+                name: "ROR".to_string(),
+                operate: cpu::ROR,
+                addr_mode: cpu::ABX,
+                cycles: 7,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "???".to_string(),
+                operate: cpu::XXX,
+                addr_mode: cpu::IMP,
+                cycles: 7,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "???".to_string(),
+                operate: cpu::NOP,
+                addr_mode: cpu::IMP,
+                cycles: 2,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "STA".to_string(),
+                operate: cpu::STA,
+                addr_mode: cpu::IZX,
+                cycles: 6,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "???".to_string(),
+                operate: cpu::NOP,
+                addr_mode: cpu::IMP,
+                cycles: 2,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "???".to_string(),
+                operate: cpu::XXX,
+                addr_mode: cpu::IMP,
+                cycles: 6,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "STY".to_string(),
+                operate: cpu::STY,
+                addr_mode: cpu::ZP0,
+                cycles: 3,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "STA".to_string(),
+                operate: cpu::STA,
+                addr_mode: cpu::ZP0,
+                cycles: 3,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "STX".to_string(),
+                operate: cpu::STX,
+                addr_mode: cpu::ZP0,
+                cycles: 3,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "???".to_string(),
+                operate: cpu::XXX,
+                addr_mode: cpu::IMP,
+                cycles: 3,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "DEY".to_string(),
+                operate: cpu::DEY,
+                addr_mode: cpu::IMP,
+                cycles: 2,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "???".to_string(),
+                operate: cpu::NOP,
+                addr_mode: cpu::IMP,
+                cycles: 2,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "TXA".to_string(),
+                operate: cpu::TXA,
+                addr_mode: cpu::IMP,
+                cycles: 2,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "???".to_string(),
+                operate: cpu::XXX,
+                addr_mode: cpu::IMP,
+                cycles: 2,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "STY".to_string(),
+                operate: cpu::STY,
+                addr_mode: cpu::ABS,
+                cycles: 4,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "STA".to_string(),
+                operate: cpu::STA,
+                addr_mode: cpu::ABS,
+                cycles: 4,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "STX".to_string(),
+                operate: cpu::STX,
+                addr_mode: cpu::ABS,
+                cycles: 4,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "???".to_string(),
+                operate: cpu::XXX,
+                addr_mode: cpu::IMP,
+                cycles: 4,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "BCC".to_string(),
+                operate: cpu::BCC,
+                addr_mode: cpu::REL,
+                cycles: 2,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "STA".to_string(),
+                operate: cpu::STA,
+                addr_mode: cpu::IZY,
+                cycles: 6,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "???".to_string(),
+                operate: cpu::XXX,
+                addr_mode: cpu::IMP,
+                cycles: 2,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "???".to_string(),
+                operate: cpu::XXX,
+                addr_mode: cpu::IMP,
+                cycles: 6,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "STY".to_string(),
+                operate: cpu::STY,
+                addr_mode: cpu::ZPX,
+                cycles: 4,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "STA".to_string(),
+                operate: cpu::STA,
+                addr_mode: cpu::ZPX,
+                cycles: 4,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "STX".to_string(),
+                operate: cpu::STX,
+                addr_mode: cpu::ZPY,
+                cycles: 4,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "???".to_string(),
+                operate: cpu::XXX,
+                addr_mode: cpu::IMP,
+                cycles: 4,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "TYA".to_string(),
+                operate: cpu::TYA,
+                addr_mode: cpu::IMP,
+                cycles: 2,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "STA".to_string(),
+                operate: cpu::STA,
+                addr_mode: cpu::ABY,
+                cycles: 5,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "TXS".to_string(),
+                operate: cpu::TXS,
+                addr_mode: cpu::IMP,
+                cycles: 2,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "???".to_string(),
+                operate: cpu::XXX,
+                addr_mode: cpu::IMP,
+                cycles: 5,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "???".to_string(),
+                operate: cpu::NOP,
+                addr_mode: cpu::IMP,
+                cycles: 5,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "STA".to_string(),
+                operate: cpu::STA,
+                addr_mode: cpu::ABX,
+                cycles: 5,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "???".to_string(),
+                operate: cpu::XXX,
+                addr_mode: cpu::IMP,
+                cycles: 5,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "???".to_string(),
+                operate: cpu::XXX,
+                addr_mode: cpu::IMP,
+                cycles: 5,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "LDY".to_string(),
+                operate: cpu::LDY,
+                addr_mode: cpu::IMM,
+                cycles: 2,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "LDA".to_string(),
+                operate: cpu::LDA,
+                addr_mode: cpu::IZX,
+                cycles: 6,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "LDX".to_string(),
+                operate: cpu::LDX,
+                addr_mode: cpu::IMM,
+                cycles: 2,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "???".to_string(),
+                operate: cpu::XXX,
+                addr_mode: cpu::IMP,
+                cycles: 6,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "LDY".to_string(),
+                operate: cpu::LDY,
+                addr_mode: cpu::ZP0,
+                cycles: 3,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "LDA".to_string(),
+                operate: cpu::LDA,
+                addr_mode: cpu::ZP0,
+                cycles: 3,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "LDX".to_string(),
+                operate: cpu::LDX,
+                addr_mode: cpu::ZP0,
+                cycles: 3,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "???".to_string(),
+                operate: cpu::XXX,
+                addr_mode: cpu::IMP,
+                cycles: 3,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "TAY".to_string(),
+                operate: cpu::TAY,
+                addr_mode: cpu::IMP,
+                cycles: 2,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "LDA".to_string(),
+                operate: cpu::LDA,
+                addr_mode: cpu::IMM,
+                cycles: 2,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "TAX".to_string(),
+                operate: cpu::TAX,
+                addr_mode: cpu::IMP,
+                cycles: 2,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "???".to_string(),
+                operate: cpu::XXX,
+                addr_mode: cpu::IMP,
+                cycles: 2,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "LDY".to_string(),
+                operate: cpu::LDY,
+                addr_mode: cpu::ABS,
+                cycles: 4,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "LDA".to_string(),
+                operate: cpu::LDA,
+                addr_mode: cpu::ABS,
+                cycles: 4,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "LDX".to_string(),
+                operate: cpu::LDX,
+                addr_mode: cpu::ABS,
+                cycles: 4,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "???".to_string(),
+                operate: cpu::XXX,
+                addr_mode: cpu::IMP,
+                cycles: 4,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "BCS".to_string(),
+                operate: cpu::BCS,
+                addr_mode: cpu::REL,
+                cycles: 2,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "LDA".to_string(),
+                operate: cpu::LDA,
+                addr_mode: cpu::IZY,
+                cycles: 5,
+                page_penalty: true,
+            },
+            INSTRUCTION {
+                name: "???".to_string(),
+                operate: cpu::XXX,
+                addr_mode: cpu::IMP,
+                cycles: 2,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "???".to_string(),
+                operate: cpu::XXX,
+                addr_mode: cpu::IMP,
+                cycles: 5,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "LDY".to_string(),
+                operate: cpu::LDY,
+                addr_mode: cpu::ZPX,
+                cycles: 4,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "LDA".to_string(),
+                operate: cpu::LDA,
+                addr_mode: cpu::ZPX,
+                cycles: 4,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "LDX".to_string(),
+                operate: cpu::LDX,
+                addr_mode: cpu::ZPY,
+                cycles: 4,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "???".to_string(),
+                operate: cpu::XXX,
+                addr_mode: cpu::IMP,
+                cycles: 4,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "CLV".to_string(),
+                operate: cpu::CLV,
+                addr_mode: cpu::IMP,
+                cycles: 2,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "LDA".to_string(),
+                operate: cpu::LDA,
+                addr_mode: cpu::ABY,
+                cycles: 4,
+                page_penalty: true,
+            },
+            INSTRUCTION {
+                name: "TSX".to_string(),
+                operate: cpu::TSX,
+                addr_mode: cpu::IMP,
+                cycles: 2,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "???".to_string(),
+                operate: cpu::XXX,
+                addr_mode: cpu::IMP,
+                cycles: 4,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "LDY".to_string(),
+                operate: cpu::LDY,
+                addr_mode: cpu::ABX,
+                cycles: 4,
+                page_penalty: true,
+            },
+            INSTRUCTION {
+                name: "LDA".to_string(),
+                operate: cpu::LDA,
+                addr_mode: cpu::ABX,
+                cycles: 4,
+                page_penalty: true,
+            },
+            INSTRUCTION {
+                name: "LDX".to_string(),
+                operate: cpu::LDX,
+                addr_mode: cpu::ABY,
+                cycles: 4,
+                page_penalty: true,
+            },
+            INSTRUCTION {
+                name: "???".to_string(),
+                operate: cpu::XXX,
+                addr_mode: cpu::IMP,
+                cycles: 4,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "CPY".to_string(),
+                operate: cpu::CPY,
+                addr_mode: cpu::IMM,
+                cycles: 2,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "CMP".to_string(),
+                operate: cpu::CMP,
+                addr_mode: cpu::IZX,
+                cycles: 6,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "???".to_string(),
+                operate: cpu::NOP,
+                addr_mode: cpu::IMP,
+                cycles: 2,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "???".to_string(),
+                operate: cpu::XXX,
+                addr_mode: cpu::IMP,
+                cycles: 8,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "CPY".to_string(),
+                operate: cpu::CPY,
+                addr_mode: cpu::ZP0,
+                cycles: 3,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "CMP".to_string(),
+                operate: cpu::CMP,
+                addr_mode: cpu::ZP0,
+                cycles: 3,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "DEC".to_string(),
+                operate: cpu::DEC,
+                addr_mode: cpu::ZP0,
+                cycles: 5,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "???".to_string(),
+                operate: cpu::XXX,
+                addr_mode: cpu::IMP,
+                cycles: 5,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "INY".to_string(),
+                operate: cpu::INY,
+                addr_mode: cpu::IMP,
+                cycles: 2,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "CMP".to_string(),
+                operate: cpu::CMP,
+                addr_mode: cpu::IMM,
+                cycles: 2,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "DEX".to_string(),
+                operate: cpu::DEX,
+                addr_mode: cpu::IMP,
+                cycles: 2,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "???".to_string(),
+                operate: cpu::XXX,
+                addr_mode: cpu::IMP,
+                cycles: 2,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "CPY".to_string(),
+                operate: cpu::CPY,
+                addr_mode: cpu::ABS,
+                cycles: 4,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "CMP".to_string(),
+                operate: cpu::CMP,
+                addr_mode: cpu::ABS,
+                cycles: 4,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "DEC".to_string(),
+                operate: cpu::DEC,
+                addr_mode: cpu::ABS,
+                cycles: 6,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "???".to_string(),
+                operate: cpu::XXX,
+                addr_mode: cpu::IMP,
+                cycles: 6,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "BNE".to_string(),
+                operate: cpu::BNE,
+                addr_mode: cpu::REL,
+                cycles: 2,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "CMP".to_string(),
+                operate: cpu::CMP,
+                addr_mode: cpu::IZY,
+                cycles: 5,
+                page_penalty: true,
+            },
+            INSTRUCTION {
+                name: "???".to_string(),
+                operate: cpu::XXX,
+                addr_mode: cpu::IMP,
+                cycles: 2,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "???".to_string(),
+                operate: cpu::XXX,
+                addr_mode: cpu::IMP,
+                cycles: 8,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "???".to_string(),
+                operate: cpu::NOP,
+                addr_mode: cpu::IMP,
+                cycles: 4,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "CMP".to_string(),
+                operate: cpu::CMP,
+                addr_mode: cpu::ZPX,
+                cycles: 4,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "DEC".to_string(),
+                operate: cpu::DEC,
+                addr_mode: cpu::ZPX,
+                cycles: 6,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "???".to_string(),
+                operate: cpu::XXX,
+                addr_mode: cpu::IMP,
+                cycles: 6,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "CLD".to_string(),
+                operate: cpu::CLD,
+                addr_mode: cpu::IMP,
+                cycles: 2,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "CMP".to_string(),
+                operate: cpu::CMP,
+                addr_mode: cpu::ABY,
+                cycles: 4,
+                page_penalty: true,
+            },
+            INSTRUCTION {
+                name: "NOP".to_string(),
+                operate: cpu::NOP,
+                addr_mode: cpu::IMP,
+                cycles: 2,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "???".to_string(),
+                operate: cpu::XXX,
+                addr_mode: cpu::IMP,
+                cycles: 7,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "???".to_string(),
+                operate: cpu::NOP,
+                addr_mode: cpu::IMP,
+                cycles: 4,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "CMP".to_string(),
+                operate: cpu::CMP,
+                addr_mode: cpu::ABX,
+                cycles: 4,
+                page_penalty: true,
+            },
+            INSTRUCTION {
+                name: "DEC".to_string(),
+                operate: cpu::DEC,
+                addr_mode: cpu::ABX,
+                cycles: 7,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "???".to_string(),
+                operate: cpu::XXX,
+                addr_mode: cpu::IMP,
+                cycles: 7,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "CPX".to_string(),
+                operate: cpu::CPX,
+                addr_mode: cpu::IMM,
+                cycles: 2,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "SBC".to_string(),
+                operate: cpu::SBC,
+                addr_mode: cpu::IZX,
+                cycles: 6,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "???".to_string(),
+                operate: cpu::NOP,
+                addr_mode: cpu::IMP,
+                cycles: 2,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "???".to_string(),
+                operate: cpu::XXX,
+                addr_mode: cpu::IMP,
+                cycles: 8,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "CPX".to_string(),
+                operate: cpu::CPX,
+                addr_mode: cpu::ZP0,
+                cycles: 3,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "SBC".to_string(),
+                operate: cpu::SBC,
+                addr_mode: cpu::ZP0,
+                cycles: 3,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "INC".to_string(),
+                operate: cpu::INC,
+                addr_mode: cpu::ZP0,
+                cycles: 5,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "???".to_string(),
+                operate: cpu::XXX,
+                addr_mode: cpu::IMP,
+                cycles: 5,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "INX".to_string(),
+                operate: cpu::INX,
+                addr_mode: cpu::IMP,
+                cycles: 2,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "SBC".to_string(),
+                operate: cpu::SBC,
+                addr_mode: cpu::IMM,
+                cycles: 2,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "NOP".to_string(),
+                operate: cpu::NOP,
+                addr_mode: cpu::IMP,
+                cycles: 2,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "???".to_string(),
+                operate: cpu::SBC,
+                addr_mode: cpu::IMP,
+                cycles: 2,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "CPX".to_string(),
+                operate: cpu::CPX,
+                addr_mode: cpu::ABS,
+                cycles: 4,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "SBC".to_string(),
+                operate: cpu::SBC,
+                addr_mode: cpu::ABS,
+                cycles: 4,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "INC".to_string(),
+                operate: cpu::INC,
+                addr_mode: cpu::ABS,
+                cycles: 6,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "???".to_string(),
+                operate: cpu::XXX,
+                addr_mode: cpu::IMP,
+                cycles: 6,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "BEQ".to_string(),
+                operate: cpu::BEQ,
+                addr_mode: cpu::REL,
+                cycles: 2,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "SBC".to_string(),
+                operate: cpu::SBC,
+                addr_mode: cpu::IZY,
+                cycles: 5,
+                page_penalty: true,
+            },
+            INSTRUCTION {
+                name: "???".to_string(),
+                operate: cpu::XXX,
+                addr_mode: cpu::IMP,
+                cycles: 2,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "???".to_string(),
+                operate: cpu::XXX,
+                addr_mode: cpu::IMP,
+                cycles: 8,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "???".to_string(),
+                operate: cpu::NOP,
+                addr_mode: cpu::IMP,
+                cycles: 4,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "SBC".to_string(),
+                operate: cpu::SBC,
+                addr_mode: cpu::ZPX,
+                cycles: 4,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "INC".to_string(),
+                operate: cpu::INC,
+                addr_mode: cpu::ZPX,
+                cycles: 6,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "???".to_string(),
+                operate: cpu::XXX,
+                addr_mode: cpu::IMP,
+                cycles: 6,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "SED".to_string(),
+                operate: cpu::SED,
+                addr_mode: cpu::IMP,
+                cycles: 2,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "SBC".to_string(),
+                operate: cpu::SBC,
+                addr_mode: cpu::ABY,
+                cycles: 4,
+                page_penalty: true,
+            },
+            INSTRUCTION {
+                name: "NOP".to_string(),
+                operate: cpu::NOP,
+                addr_mode: cpu::IMP,
+                cycles: 2,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "???".to_string(),
+                operate: cpu::XXX,
+                addr_mode: cpu::IMP,
+                cycles: 7,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "???".to_string(),
+                operate: cpu::NOP,
+                addr_mode: cpu::IMP,
+                cycles: 4,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "SBC".to_string(),
+                operate: cpu::SBC,
+                addr_mode: cpu::ABX,
+                cycles: 4,
+                page_penalty: true,
+            },
+            INSTRUCTION {
+                name: "INC".to_string(),
+                operate: cpu::INC,
+                addr_mode: cpu::ABX,
+                cycles: 7,
+                page_penalty: false,
+            },
+            INSTRUCTION {
+                name: "???".to_string(),
+                operate: cpu::XXX,
+                addr_mode: cpu::IMP,
+                cycles: 7,
+                page_penalty: false,
+            },
+        ];
+
+        let scheduler = Scheduler::new();
+        let device_log = DeviceLog::new(scheduler.events());
+        let mut cpu = Self {
+            a: 0,
+            x: 0,
+            y: 0,
+            stkp: 0,
+            pc: 0,
+            status: FLAGS6502::U as u8,
+            fetched: 0,
+            addr_abs: 0,
+            addr_rel: 0,
+            opcode: 0,
+            cycles: 0,
+            lookup,
+            bus: SharedBus::new(Bus::new()),
+            clock_count: 0,
+            temp: 0,
+            profiler: None,
+            timeline: None,
+            access_log: None,
+            instruction_pc: 0,
+            opcode_counts: OpcodeCounts::new(),
+            trace: None,
+            trace_ring: None,
+            traps: Traps::default(),
+            trap_output: Box::new(std::io::stdout()),
+            exit_code: None,
+            os_shim: None,
+            kernal: None,
+            scheduler,
+            recording: None,
+            player: None,
+            shadow_stack: None,
+            variant: Variant::Nmos6502,
+            halt: Halt::Running,
+            held_until: 0,
+            dma: VecDeque::new(),
+            poll: InterruptPoll::default(),
+            diagnostics: Diagnostics::new(),
+            interrupts: InterruptController::new(),
+            pc_history: PcHistory::new(),
+            call_stack: CallStack::new(),
+            vectors: VectorLog::new(),
+            watchdog: None,
+            rom_database: RomDatabase::bundled(),
+            device_log,
+            pins: PinTracker::default(),
+            rdy: RdyLine::default(),
+            so_low: false,
+        };
+        cpu.set_variant(Variant::Nmos6502);
+        cpu
+    }
+
+    fn get_flag(&self, f: FLAGS6502) -> u8 {
+        let f = f as u8;
+        if (self.status & f) > 0 {
+            1
+        } else {
+            0
+        }
+    }
+
+    pub fn set_flag(&mut self, f: FLAGS6502, v: bool) {
+        if v {
+            self.status |= f as u8
+        } else {
+            self.status &= !(f as u8)
+        }
+    }
+
+    // The register has no B, and bit 5 isn't wired to anything so it
+    // always reads 1. Whatever's written in their place, PLP and RTI
+    // pulling it or a debugger setting P, is dropped.
+    pub fn set_status(&mut self, p: u8) {
+        self.status = (p & !(FLAGS6502::B as u8)) | FLAGS6502::U as u8;
+    }
+
+    // What PHP, BRK and interrupts put on the stack. B only exists here,
+    // set by the instructions and clear for IRQ and NMI, so a handler can
+    // tell BRK from IRQ.
+    fn pushed_status(&self, brk: bool) -> u8 {
+        let b = if brk { FLAGS6502::B as u8 } else { 0 };
+        self.status | b | FLAGS6502::U as u8
+    }
+
+    // Addressing Modes
+    fn IMP(cpu: &mut cpu6502) -> u8 {
+        cpu.fetched = cpu.a;
+        0
+    }
+    fn IMM(cpu: &mut cpu6502) -> u8 {
+        cpu.addr_abs = cpu.pc;
+        cpu.pc = cpu.pc.wrapping_add(1);
+        0
+    }
+    fn ZP0(cpu: &mut cpu6502) -> u8 {
+        cpu.addr_abs = cpu.read(cpu.pc) as u16;
+        cpu.pc = cpu.pc.wrapping_add(1);
+        cpu.addr_abs &= 0x00FF;
+
+        0
+    }
+
+    fn ZPX(cpu: &mut cpu6502) -> u8 {
+        cpu.addr_abs = cpu.read(cpu.pc).wrapping_add(cpu.x) as u16;
+        cpu.pc = cpu.pc.wrapping_add(1);
+        cpu.addr_abs &= 0x00FF;
+
+        return 0;
+    }
+
+    fn ZPY(cpu: &mut cpu6502) -> u8 {
+        cpu.addr_abs = cpu.read(cpu.pc).wrapping_add(cpu.y) as u16;
+        cpu.pc = cpu.pc.wrapping_add(1);
+        cpu.addr_abs &= 0x00FF;
+
+        0
+    }
+    fn REL(cpu: &mut cpu6502) -> u8 {
+        cpu.addr_rel = cpu.read(cpu.pc) as u16;
+        cpu.pc = cpu.pc.wrapping_add(1);
+        if cpu.addr_rel & 0x80 != 0 {
+            cpu.addr_rel |= 0xFF00;
+        }
+        0
+    }
+
+
+    fn ABS(cpu: &mut cpu6502) -> u8 {
+        let lo = cpu.read(cpu.pc) as u16;
+        cpu.pc = cpu.pc.wrapping_add(1);
+        let hi = cpu.read(cpu.pc) as u16;
+        cpu.pc = cpu.pc.wrapping_add(1);
+
+        cpu.addr_abs = ((hi << 8) | lo) as u16;
+
+        0
+    }
+
+
+    fn ABX(cpu: &mut cpu6502) -> u8 {
+        let lo = cpu.read(cpu.pc) as u16;
+        cpu.pc = cpu.pc.wrapping_add(1);
+        let hi = cpu.read(cpu.pc) as u16;
+        cpu.pc = cpu.pc.wrapping_add(1);
+
+        cpu.addr_abs = ((hi << 8) | lo) as u16;
+        cpu.addr_abs = cpu.addr_abs.wrapping_add(cpu.x as u16);
+
+        if (cpu.addr_abs & 0xFF00) != (hi << 8) as u16 {
+            1
+        } else {
+            0
+        }
+    }
+
+
+    fn ABY(cpu: &mut cpu6502) -> u8 {
+        let lo = cpu.read(cpu.pc) as u16;
+        cpu.pc = cpu.pc.wrapping_add(1);
+        let hi = cpu.read(cpu.pc) as u16;
+        cpu.pc = cpu.pc.wrapping_add(1);
+
+        cpu.addr_abs = ((hi << 8) | lo);
+        cpu.addr_abs = cpu.addr_abs.wrapping_add(cpu.y as u16);
+
+        if (cpu.addr_abs & 0xFF00) != (hi << 8) {
+            1
+        } else {
+            0
+        }
+    }
+
+
+    fn IND(cpu: &mut cpu6502) -> u8 {
+        let ptr_lo = cpu.read(cpu.pc) as u16;
+        cpu.pc = cpu.pc.wrapping_add(1);
+        let ptr_hi = cpu.read(cpu.pc) as u16;
+        cpu.pc = cpu.pc.wrapping_add(1);
+
+        let ptr = (ptr_hi << 8) | ptr_lo;
+
+        if ptr_lo == 0x00FF
+        // Simulate page boundary hardware bug
+        {
+            cpu.addr_abs = (cpu.read(ptr & 0xFF00) as u16) << 8 | (cpu.read(ptr + 0) as u16);
+        } else
+        // Behave normally
+        {
+            cpu.addr_abs = ((cpu.read(ptr + 1) as u16) << 8) | (cpu.read(ptr + 0) as u16);
+        }
+
+        0
+    }
+
+
+    fn IZX(cpu: &mut cpu6502) -> u8 {
+        let t = cpu.read(cpu.pc) as u16;
+        cpu.pc = cpu.pc.wrapping_add(1);
+
+        let lo = cpu.read(((t + (cpu.x as u16)) & 0x00FF)) as u16;
+        let hi = cpu.read(((t + ((cpu.x as u16) + 1u16)) & 0x00FF)) as u16;
+
+        cpu.addr_abs = ((hi << 8) | lo) as u16;
+
+        0
+    }
+
+
+    fn IZY(cpu: &mut cpu6502) -> u8 {
+        let t = cpu.read(cpu.pc) as u16;
+        cpu.pc = cpu.pc.wrapping_add(1);
+
+        let lo = cpu.read((t & 0x00FF)) as u16;
+        let hi = cpu.read(((t + 1) & 0x00FF)) as u16;
+
+        cpu.addr_abs = ((hi << 8) | lo);
+        cpu.addr_abs = cpu.addr_abs.wrapping_add(cpu.y as u16);
+
+        if (cpu.addr_abs & 0xFF00) != (hi << 8) {
+            1
+        } else {
+            0
+        }
+    }
+
+    //opcodes
+    fn ADC(cpu: &mut cpu6502) -> u8 {
+        // Grab the data that we are adding to the accumulator
+        cpu.fetch();
+
+        // Add is performed in 16-bit domain for emulation to capture any
+        // carry bit, which will exist in bit 8 of the 16-bit word
+        cpu.temp = ((cpu.a as u16) + (cpu.fetched as u16) + (cpu.get_flag(FLAGS6502::C) as u16));
+
+        // The carry flag out exists in the high byte bit 0
+        cpu.set_flag(FLAGS6502::C, cpu.temp > 255);
+
+        // The Zero flag is set if the result is 0
+        cpu.set_flag(FLAGS6502::Z, (cpu.temp & 0x00FF) == 0);
+
+        // The signed Overflow flag is set based on all that up there! :D
+        cpu.set_flag(
+            FLAGS6502::V,
+            (!((cpu.a as u16) ^ (cpu.fetched as u16)) & ((cpu.a as u16) ^ (cpu.temp as u16))) & 0x0080 != 0,
+        );
+
+        // The negative flag is set to the most significant bit of the result
+        //Tawanda verify this
+        cpu.set_flag(FLAGS6502::N, cpu.temp & 0x80 != 0);
+
+        // Load the result into the accumulator (it's 8-bit dont forget!)
+        cpu.a = (cpu.temp & 0x00FF) as u8;
+
+        // This instruction has the potential to require an additional clock cycle
+        return 1;
+    }
+
+    fn AND(cpu: &mut cpu6502) -> u8 {
+        cpu.fetch();
+        cpu.a = cpu.a & cpu.fetched;
+        cpu.set_flag(FLAGS6502::Z, cpu.a == 0x00);
+        cpu.set_flag(FLAGS6502::N, cpu.a & 0x80 != 0);
+        return 1;
+    }
+    fn ASL(cpu: &mut cpu6502) -> u8 {
+        cpu.fetch();
+        cpu.temp = ((cpu.fetched as u16) << 1);
+        cpu.set_flag(FLAGS6502::C, (cpu.temp & 0xFF00) > 0);
+        cpu.set_flag(FLAGS6502::Z, (cpu.temp & 0x00FF) == 0x00);
+        cpu.set_flag(FLAGS6502::N, cpu.temp & 0x80 != 0);
+        if cpu.lookup[cpu.opcode as usize].addr_mode == cpu6502::IMP {
+            cpu.a = (cpu.temp & 0x00FF) as u8;
+        } else {
+            cpu.write(cpu.addr_abs, (cpu.temp & 0x00FF) as u8);
+        }
+
+        return 0;
+    }
+    fn BCC(cpu: &mut cpu6502) -> u8 {
+        if cpu.get_flag(FLAGS6502::C) == 0 {
+            cpu.cycles += 1;
+            cpu.addr_abs = cpu.pc.wrapping_add(cpu.addr_rel);
+
+            if (cpu.addr_abs & 0xFF00) != (cpu.pc & 0xFF00) {
+                cpu.cycles += 1;
+            }
+
+            cpu.pc = cpu.addr_abs;
+        }
+        return 0;
+    }
+    fn BCS(cpu: &mut cpu6502) -> u8 {
+        if cpu.get_flag(FLAGS6502::C) == 1 {
+            cpu.cycles += 1;
+            cpu.addr_abs = cpu.pc.wrapping_add(cpu.addr_rel);
+
+            if ((cpu.addr_abs & 0xFF00) != (cpu.pc & 0xFF00)) {
+                cpu.cycles += 1;
+            }
+
+            cpu.pc = cpu.addr_abs;
+        }
+        return 0;
+    }
+    fn BEQ(cpu: &mut cpu6502) -> u8 {
+        if cpu.get_flag(FLAGS6502::Z) == 1 {
+            cpu.cycles += 1;
+            cpu.addr_abs = cpu.pc.wrapping_add(cpu.addr_rel);
+
+            if (cpu.addr_abs & 0xFF00) != (cpu.pc & 0xFF00) {
+                cpu.cycles += 1;
+            }
+
+            cpu.pc = cpu.addr_abs;
+        }
+        0
+    }
+    fn BIT(cpu: &mut cpu6502) -> u8 {
+        cpu.fetch();
+        cpu.temp = (cpu.a & cpu.fetched) as u16;
+        cpu.set_flag(FLAGS6502::Z, (cpu.temp & 0x00FF) == 0x00);
+        cpu.set_flag(FLAGS6502::N, cpu.fetched & (1 << 7) != 0);
+        cpu.set_flag(FLAGS6502::V, cpu.fetched & (1 << 6) != 0);
+
+        0
+    }
+
+    fn BMI(cpu: &mut cpu6502) -> u8 {
+        if cpu.get_flag(FLAGS6502::N) == 1 {
+            cpu.cycles += 1;
+            cpu.addr_abs = cpu.pc.wrapping_add(cpu.addr_rel);
+
+            if (cpu.addr_abs & 0xFF00) != (cpu.pc & 0xFF00) {
+                cpu.cycles += 1;
+            }
+
+            cpu.pc = cpu.addr_abs;
+        }
+        return 0;
+    }
+
+    fn BNE(cpu: &mut cpu6502) -> u8 {
+        if cpu.get_flag(FLAGS6502::Z) == 0 {
+            cpu.cycles += 1;
+            cpu.addr_abs = cpu.pc.wrapping_add(cpu.addr_rel);
+
+            if (cpu.addr_abs & 0xFF00) != (cpu.pc & 0xFF00) {
+                cpu.cycles += 1;
+            }
+
+            cpu.pc = cpu.addr_abs;
+        }
+
+        0
+    }
+
+    fn BPL(cpu: &mut cpu6502) -> u8 {
+        if cpu.get_flag(FLAGS6502::N) == 0 {
+            cpu.cycles += 1;
+            cpu.addr_abs = cpu.pc.wrapping_add(cpu.addr_rel);
+
+            if (cpu.addr_abs & 0xFF00) != (cpu.pc & 0xFF00) {
+                cpu.cycles += 1;
+            }
+
+            cpu.pc = cpu.addr_abs;
+        }
+
+        0
+    }
+
+
+    // The IMM addressing mode has already stepped over the padding byte, so
+    // the return address is two past the opcode
+    fn BRK(cpu: &mut cpu6502) -> u8 {
+        cpu.write(0x0100 + cpu.stkp as u16, ((cpu.pc >> 8) & 0x00FF) as u8);
+        cpu.stkp = cpu.stkp.wrapping_sub(1);
+        cpu.write(0x0100 + cpu.stkp as u16, (cpu.pc & 0x00FF) as u8);
+        cpu.stkp = cpu.stkp.wrapping_sub(1);
+
+        cpu.write(0x0100 + cpu.stkp as u16, cpu.pushed_status(true));
+        cpu.stkp = cpu.stkp.wrapping_sub(1);
+        cpu.set_flag(FLAGS6502::I, true);
+
+        cpu.shadow_interrupt();
+        cpu.interrupts.on_brk();
+
+        cpu.pc = (cpu.read(0xFFFE) as u16) | ((cpu.read(0xFFFF) as u16) << 8);
+        cpu.timeline_interrupt(SpanKind::Brk);
+        cpu.enter_interrupt(CallKind::Brk);
+        cpu.took_vector(Vector::Irq, Cause::Brk);
+        log_debug!(target: "crust_6502::cpu", cycle = cpu.clock_count, handler = cpu.pc, "BRK");
+
+        0
+    }
+
+    fn BVC(cpu: &mut cpu6502) -> u8 {
+        if cpu.get_flag(FLAGS6502::V) == 0
+        {
+            cpu.cycles += 1;
+            cpu.addr_abs = cpu.pc.wrapping_add(cpu.addr_rel);
+
+            if (cpu.addr_abs & 0xFF00) != (cpu.pc & 0xFF00) {
+                cpu.cycles += 1;
+            }
+
+
+            cpu.pc = cpu.addr_abs;
+        }
+
+        0
+    }
+
+
+    fn BVS(cpu: &mut cpu6502) -> u8 {
+        if cpu.get_flag(FLAGS6502::V) == 1
+        {
+            cpu.cycles += 1;
+            cpu.addr_abs = cpu.pc.wrapping_add(cpu.addr_rel);
+
+            if (cpu.addr_abs & 0xFF00) != (cpu.pc & 0xFF00) {
+                cpu.cycles += 1;
+            }
+
+
+            cpu.pc = cpu.addr_abs;
+        }
+
+
+        0
+    }
+
+
+    fn CLC(cpu: &mut cpu6502) -> u8 {
+        cpu.set_flag(FLAGS6502::C, false);
+
+        0
+    }
+
+
+    fn CLD(cpu: &mut cpu6502) -> u8 {
+        cpu.set_flag(FLAGS6502::D, false);
+
+        0
+    }
+
+    fn CLI(cpu: &mut cpu6502) -> u8 {
+        cpu.set_flag(FLAGS6502::I, false);
+        0
+    }
+
+    fn CLV(cpu: &mut cpu6502) -> u8 {
+        cpu.set_flag(FLAGS6502::V, false);
+
+        0
+    }
+
+    fn CMP(cpu: &mut cpu6502) -> u8 {
+        cpu.fetch();
+        cpu.temp = (cpu.a as u16).wrapping_sub(cpu.fetched as u16);
+        cpu.set_flag(FLAGS6502::C, cpu.a >= cpu.fetched);
+        cpu.set_flag(FLAGS6502::Z, (cpu.temp & 0x00FF) == 0x0000);
+        cpu.set_flag(FLAGS6502::N, (cpu.temp & 0x0080) != 0);
+
+        0
+    }
+
+
+    fn CPX(cpu: &mut cpu6502) -> u8 {
+        cpu.fetch();
+        cpu.temp = (cpu.x as u16).wrapping_sub(cpu.fetched as u16);
+        cpu.set_flag(FLAGS6502::C, cpu.x >= cpu.fetched);
+        cpu.set_flag(FLAGS6502::Z, (cpu.temp & 0x00FF) == 0x0000);
+        cpu.set_flag(FLAGS6502::N, (cpu.temp & 0x0080) != 0);
+
+        0
+    }
+
+    fn CPY(cpu: &mut cpu6502) -> u8 {
+        cpu.fetch();
+        cpu.temp = (cpu.y as u16).wrapping_sub(cpu.fetched as u16);
+        cpu.set_flag(FLAGS6502::C, cpu.y >= cpu.fetched);
+        cpu.set_flag(FLAGS6502::Z, (cpu.temp & 0x00FF) == 0x0000);
+        cpu.set_flag(FLAGS6502::N, (cpu.temp & 0x0080) != 0);
+
+        0
+    }
+
+    fn DEC(cpu: &mut cpu6502) -> u8 {
+        cpu.fetch();
+        cpu.temp = (cpu.fetched as u16).wrapping_sub(1);
+        cpu.write(cpu.addr_abs, (cpu.temp & 0x00FF) as u8);
+        cpu.set_flag(FLAGS6502::Z, (cpu.temp & 0x00FF) == 0x0000);
+        cpu.set_flag(FLAGS6502::N, (cpu.temp & 0x0080) != 0);
+
+        0
+    }
+
+    fn DEX(cpu: &mut cpu6502) -> u8 {
+        cpu.x = cpu.x.wrapping_sub(1);
+        cpu.set_flag(FLAGS6502::Z, cpu.x == 0x00);
+        cpu.set_flag(FLAGS6502::N, (cpu.x & 0x80) != 0);
+
+        0
+    }
+
+
+    fn DEY(cpu: &mut cpu6502) -> u8 {
+        cpu.y = cpu.y.wrapping_sub(1);
+        cpu.set_flag(FLAGS6502::Z, cpu.y == 0x00);
+        cpu.set_flag(FLAGS6502::N, (cpu.y & 0x80) != 0);
+
+        0
+    }
+
+
+    fn EOR(cpu: &mut cpu6502) -> u8 {
+        cpu.fetch();
+        cpu.a = cpu.a ^ cpu.fetched;
+
+        cpu.set_flag(FLAGS6502::Z, cpu.a == 0x00);
+        cpu.set_flag(FLAGS6502::N, (cpu.a & 0x80) != 0);
+
+        0
+    }
+
+
+    fn INC(cpu: &mut cpu6502) -> u8 {
+        cpu.fetch();
+        cpu.temp = cpu.fetched.wrapping_add(1) as u16;
+        cpu.write(cpu.addr_abs, (cpu.temp & 0x00FF) as u8);
+        cpu.set_flag(FLAGS6502::Z, (cpu.temp & 0x00FF) == 0x0000);
+        cpu.set_flag(FLAGS6502::N, (cpu.temp & 0x0080) != 0);
+
+        0
+    }
+
+
+    fn INX(cpu: &mut cpu6502) -> u8 {
+        cpu.x = cpu.x.wrapping_add(1);
+
+        cpu.set_flag(FLAGS6502::Z, cpu.x == 0x00);
+        cpu.set_flag(FLAGS6502::N, (cpu.x & 0x80) != 0);
+
+        0
+    }
+
+
+    fn INY(cpu: &mut cpu6502) -> u8 {
+        cpu.y = cpu.y.wrapping_add(1);
+
+        cpu.set_flag(FLAGS6502::Z, cpu.y == 0x00);
+        cpu.set_flag(FLAGS6502::N, (cpu.y & 0x80) != 0);
+
+        0
+    }
+
+    fn JMP(cpu: &mut cpu6502) -> u8 {
+        cpu.pc = cpu.addr_abs;
+
+        0
+    }
+
+    fn JSR(cpu: &mut cpu6502) -> u8 {
+        cpu.pc = cpu.pc.wrapping_sub(1);
+
+        cpu.write(0x0100u16 + (cpu.stkp as u16), ((cpu.pc >> 8) & 0x00FF) as u8);
+        cpu.stkp = cpu.stkp.wrapping_sub(1);
+        cpu.write(0x0100u16 + (cpu.stkp as u16), (cpu.pc & 0x00FF) as u8);
+        cpu.stkp = cpu.stkp.wrapping_sub(1);
+
+        cpu.pc = cpu.addr_abs;
+
+        0
+    }
+
+
+    fn LDA(cpu: &mut cpu6502) -> u8 {
+        cpu.fetch();
+        cpu.a = cpu.fetched;
+        cpu.set_flag(FLAGS6502::Z, cpu.a == 0x00);
+        cpu.set_flag(FLAGS6502::N, (cpu.a & 0x80) != 0);
+
+        1
+    }
+    fn LDX(cpu: &mut cpu6502) -> u8 {
+        cpu.fetch();
+        cpu.x = cpu.fetched;
+        cpu.set_flag(FLAGS6502::Z, cpu.x == 0x00);
+        cpu.set_flag(FLAGS6502::N, (cpu.x & 0x80) != 0);
+
+
+        1
+    }
+    fn LDY(cpu: &mut cpu6502) -> u8 {
+        cpu.fetch();
+        cpu.y = cpu.fetched;
+        cpu.set_flag(FLAGS6502::Z, cpu.y == 0x00);
+        cpu.set_flag(FLAGS6502::N, (cpu.y & 0x80) != 0);
+
+        1
+    }
+    fn LSR(cpu: &mut cpu6502) -> u8 {
+        cpu.fetch();
+        cpu.set_flag(FLAGS6502::C, (cpu.fetched & 0x0001) != 0);
+        cpu.temp = (cpu.fetched >> 1) as u16;
+        cpu.set_flag(FLAGS6502::Z, (cpu.temp & 0x00FF) == 0x0000);
+        cpu.set_flag(FLAGS6502::N, (cpu.temp & 0x0080) != 0);
+
+
+        if cpu.lookup[cpu.opcode as usize].addr_mode == cpu6502::IMP {
+            cpu.a = (cpu.temp & 0x00FF) as u8;
+        } else {
+            cpu.write(cpu.addr_abs, (cpu.temp & 0x00FF) as u8);
+        }
+
+        0
+    }
+
+    fn NOP(cpu: &mut cpu6502) -> u8 {
+        let return_code = match cpu.opcode {
+            0x1C => { 1 }
+            0x3C => { 1 }
+            0x5C => { 1 }
+            0x7C => { 1 }
+            0xDC => { 1 }
+            0xFC => { 1 }
+            _ => { 0 }
+        };
+
+        return_code
+    }
+
+    fn ORA(cpu: &mut cpu6502) -> u8 {
+        cpu.fetch();
+        cpu.a = cpu.a | cpu.fetched;
+        cpu.set_flag(FLAGS6502::Z, cpu.a == 0x00);
+        cpu.set_flag(FLAGS6502::N, (cpu.a & 0x80) != 0);
+
+        1
+    }
+    fn PHA(cpu: &mut cpu6502) -> u8 {
+        cpu.write(0x0100u16 + (cpu.stkp as u16), cpu.a);
+        cpu.stkp = cpu.stkp.wrapping_sub(1);
+
+        0
+    }
+    fn PHP(cpu: &mut cpu6502) -> u8 {
+        cpu.write(0x0100u16 + (cpu.stkp as u16), cpu.pushed_status(true));
+        cpu.stkp = cpu.stkp.wrapping_sub(1);
+
+        0
+    }
+    fn PLA(cpu: &mut cpu6502) -> u8 {
+        cpu.stkp = cpu.stkp.wrapping_add(1);
+        cpu.a = cpu.read(0x0100u16 + cpu.stkp as u16);
+        cpu.set_flag(FLAGS6502::Z, cpu.a == 0x00);
+        cpu.set_flag(FLAGS6502::N, (cpu.a & 0x80) != 0);
+
+        0
+    }
+
+    // B isn't a real flag, whatever was pushed in its place is dropped
+    fn PLP(cpu: &mut cpu6502) -> u8 {
+        cpu.stkp = cpu.stkp.wrapping_add(1);
+        let p = cpu.read(0x0100u16 + cpu.stkp as u16);
+        cpu.set_status(p);
+
+
+        0
+    }
+
+    fn ROL(cpu: &mut cpu6502) -> u8 {
+        cpu.fetch();
+        cpu.temp = ((cpu.fetched as u16) << 1) | cpu.get_flag(FLAGS6502::C) as u16;
+        cpu.set_flag(FLAGS6502::C, (cpu.temp & 0xFF00) != 0);
+        cpu.set_flag(FLAGS6502::Z, (cpu.temp & 0x00FF) == 0x00);
+        cpu.set_flag(FLAGS6502::N, (cpu.temp & 0x0080) != 0);
+
+
+        if cpu.lookup[cpu.opcode as usize].addr_mode == cpu6502::IMP {
+            cpu.a = (cpu.temp & 0x00FF) as u8;
+        } else {
+            cpu.write(cpu.addr_abs, (cpu.temp & 0x00FF) as u8);
+        }
+
+
+        0
+    }
+    fn ROR(cpu: &mut cpu6502) -> u8 {
+        cpu.fetch();
+        cpu.temp = ((cpu.get_flag(FLAGS6502::C) << 7) | (cpu.fetched >> 1)) as u16;
+        cpu.set_flag(FLAGS6502::C, (cpu.fetched & 0x01) != 0);
+        cpu.set_flag(FLAGS6502::Z, (cpu.temp & 0x00FF) == 0x00);
+        cpu.set_flag(FLAGS6502::N, (cpu.temp & 0x0080) != 0);
+
+
+        if cpu.lookup[cpu.opcode as usize].addr_mode == cpu6502::IMP {
+            cpu.a = (cpu.temp & 0x00FF) as u8;
+        } else {
+            cpu.write(cpu.addr_abs, (cpu.temp & 0x00FF) as u8);
+        }
+
+        0
+    }
+
+
+    fn RTI(cpu: &mut cpu6502) -> u8 {
+        cpu.stkp = cpu.stkp.wrapping_add(1);
+        let p = cpu.read(0x0100u16 + cpu.stkp as u16);
+        cpu.set_status(p);
+
+        cpu.stkp = cpu.stkp.wrapping_add(1);
+        cpu.pc = cpu.read(0x0100u16 + cpu.stkp as u16) as u16;
+        cpu.stkp = cpu.stkp.wrapping_add(1);
+        cpu.pc |= (cpu.read(0x0100u16 + cpu.stkp as u16) as u16) << 8;
+
+        0
+    }
+
+
+    fn RTS(cpu: &mut cpu6502) -> u8 {
+        cpu.stkp = cpu.stkp.wrapping_add(1);
+        cpu.pc = cpu.read(0x0100u16 + cpu.stkp as u16) as u16;
+        cpu.stkp = cpu.stkp.wrapping_add(1);
+        cpu.pc |= (cpu.read(0x0100u16 + cpu.stkp as u16) as u16) << 8;
+
+        cpu.pc = cpu.pc.wrapping_add(1);
+
+        0
+    }
+    fn SBC(cpu: &mut cpu6502) -> u8 {
+        cpu.fetch();
+
+        // Operating in 16-bit domain to capture carry out
+
+        // We can invert the bottom 8 bits with bitwise xor
+        let value = (cpu.fetched as u16) ^ 0x00FF;
+
+        // Notice this is exactly the same as addition from here!
+        cpu.temp = ((cpu.a as u16) + value + (cpu.get_flag(FLAGS6502::C) as u16));
+        cpu.set_flag(FLAGS6502::C, cpu.temp & 0xFF00 != 0);
+        cpu.set_flag(FLAGS6502::Z, ((cpu.temp & 0x00FF) == 0));
+        cpu.set_flag(FLAGS6502::V, ((cpu.temp ^ (cpu.a as u16)) & (cpu.temp ^ (value)) & 0x0080) != 0);
+        cpu.set_flag(FLAGS6502::N, (cpu.temp & 0x0080) != 0);
+        cpu.a = (cpu.temp & 0x00FF) as u8;
+
+        1
+    }
+    fn SEC(cpu: &mut cpu6502) -> u8 {
+        cpu.set_flag(FLAGS6502::C, true);
+
+        0
+    }
+    fn SED(cpu: &mut cpu6502) -> u8 {
+        cpu.set_flag(FLAGS6502::D, true);
+
+        0
+    }
+    fn SEI(cpu: &mut cpu6502) -> u8 {
+        cpu.set_flag(FLAGS6502::I, true);
+
+        0
+    }
+
+    fn STA(cpu: &mut cpu6502) -> u8 {
+        cpu.write(cpu.addr_abs, cpu.a);
+
+        0
+    }
+
+    fn STX(cpu: &mut cpu6502) -> u8 {
+        cpu.write(cpu.addr_abs, cpu.x);
+
+        0
+    }
+    fn STY(cpu: &mut cpu6502) -> u8 {
+        cpu.write(cpu.addr_abs, cpu.y);
+
+        0
+    }
+    fn TAX(cpu: &mut cpu6502) -> u8 {
+        cpu.x = cpu.a;
+
+        cpu.set_flag(FLAGS6502::Z, cpu.x == 0x00);
+        cpu.set_flag(FLAGS6502::N, (cpu.x & 0x80) != 0);
+
+        0
+    }
+    fn TAY(cpu: &mut cpu6502) -> u8 {
+        cpu.y = cpu.a;
+
+        cpu.set_flag(FLAGS6502::Z, cpu.y == 0x00);
+        cpu.set_flag(FLAGS6502::N, (cpu.y & 0x80) != 0);
+
+        0
+    }
+    fn TSX(cpu: &mut cpu6502) -> u8 {
+        cpu.x = cpu.stkp;
+
+        cpu.set_flag(FLAGS6502::Z, cpu.x == 0x00);
+        cpu.set_flag(FLAGS6502::N, (cpu.x & 0x80) != 0);
+
+        0
+    }
+
+
+    fn TXA(cpu: &mut cpu6502) -> u8 {
+        cpu.a = cpu.x;
+
+        cpu.set_flag(FLAGS6502::Z, cpu.a == 0x00);
+        cpu.set_flag(FLAGS6502::N, (cpu.a & 0x80) != 0);
+
+        0
+    }
+
+
+    fn TXS(cpu: &mut cpu6502) -> u8 {
+        cpu.stkp = cpu.x;
+
+        0
+    }
+
+
+    fn TYA(cpu: &mut cpu6502) -> u8 {
+        cpu.a = cpu.y;
+
+        cpu.set_flag(FLAGS6502::Z, cpu.a == 0x00);
+        cpu.set_flag(FLAGS6502::N, (cpu.a & 0x80) != 0);
+
+        0
+    }
+
+    // I capture all "unofficial" opcodes with this function. It is
+    // functionally identical to a NOP
+    fn XXX(cpu: &mut cpu6502) -> u8 {
+        0
+    }
+
+    // 65C02 only: Wait for Interrupt
+    // Function:    Stop fetching until the IRQ line is asserted or an NMI arrives
+    fn WAI(cpu: &mut cpu6502) -> u8 {
+        cpu.halt = Halt::Waiting;
+        0
+    }
+
+    // 65C02 only: Stop the Processor
+    // Function:    Stop fetching until reset
+    fn STP(cpu: &mut cpu6502) -> u8 {
+        cpu.halt = Halt::Stopped;
+        0
+    }
+
+    // NMOS only: the twelve opcodes that wedge the decoder, nothing but
+    // reset gets it going again. PC is left on the opcode.
+    fn JAM(cpu: &mut cpu6502) -> u8 {
+        cpu.halt = Halt::Jammed;
+        cpu.pc = cpu.instruction_pc;
+        cpu.diagnostics.report(Diagnostic {
+            cycle: cpu.clock_count,
+            pc: cpu.instruction_pc,
+            severity: Severity::Error,
+            kind: DiagnosticKind::Jammed { opcode: cpu.opcode },
+        });
+        0
+    }
+
+    pub fn clock(&mut self) {
+        // Replay recorded stimuli at exactly the cycle they originally arrived on
+        while let Some(stimulus) = self.player.as_mut().and_then(|player| player.poll(self.clock_count)) {
+            self.apply_stimulus(stimulus);
+        }
+
+        if self.player.as_ref().map_or(false, |player| player.is_finished()) {
+            self.player = None;
+        }
+
+        // While DMA has the bus the CPU waits, whatever it was doing
+        if self.dma_active() {
+            self.pins.start_dma();
+            self.dma_cycle();
+            self.tick_pins(false);
+            self.clock_count += 1;
+            self.run_due_alarms();
+            return;
+        }
+
+        // WAI wakes on any interrupt, an IRQ is only taken if I is clear
+        if self.cycles == 0 && self.halt == Halt::Waiting && (self.interrupts.irq_line() || self.poll.nmi_edge) {
+            self.halt = Halt::Running;
+            self.poll_interrupts();
+        }
+
+        // A halted or held CPU fetches nothing but time still passes for
+        // the devices
+        let held = self.clock_count < self.held_until || self.rdy_low();
+        if self.cycles == 0 && (self.halt != Halt::Running || held) {
+            self.rdy.stopped = held;
+            self.tick_pins(!held);
+            self.clock_count += 1;
+            self.run_due_alarms();
+            return;
+        }
+
+        // An instruction or an interrupt sequence starts here. Whatever the
+        // last poll saw is taken between instructions, NMI first, and its
+        // pushes belong to the instruction it interrupted. The handler's
+        // first instruction starts on a later clock, once the sequence's
+        // cycles have gone by.
+        if self.cycles == 0 {
+            self.instruction_pc = self.pc;
+            self.pins.begin();
+            if self.poll.nmi_pending {
+                self.nmi();
+            } else if self.poll.irq_pending {
+                self.irq();
+            }
+        }
+
+        if self.cycles == 0 {
+            self.pc_history.push(self.pc);
+            self.opcode = self.read(self.pc);
+            self.pins.mark_sync();
+            self.poll.servicing = false;
+            self.poll.i_seen = match self.opcode {
+                // CLI, SEI, PLP
+                0x58 | 0x78 | 0x28 => Some(self.get_flag(FLAGS6502::I) != 0),
+                _ => None,
+            };
+
+            // Anything that set P directly goes through the same mask as
+            // set_status, so traces never show a B
+            self.set_status(self.status);
+
+            if self.trace.is_some() || self.trace_ring.is_some() {
+                self.trace_instruction();
+            }
+            log_trace!(
+                target: "crust_6502::cpu",
+                cycle = self.clock_count,
+                pc = self.pc,
+                a = self.a,
+                x = self.x,
+                y = self.y,
+                p = self.status,
+                s = self.stkp,
+                "{:04x} {}",
+                self.pc,
+                self.lookup[self.opcode as usize].name
+            );
+
+            let stkp = self.stkp;
+            let op_pc = self.pc;
+
+            // Increment program counter, we read the opcode byte
+            self.pc = self.pc.wrapping_add(1);
+
+            // Get Starting number of cycles
+            self.cycles = self.lookup[self.opcode as usize].cycles;
+
+            // Perform fetch of intermmediate data using the
+            // required addressing mode, which says if it crossed a page
+            let page_crossed = (self.lookup[self.opcode as usize].addr_mode)(self) != 0;
+
+            // Perform operation. Branches add their own cycles.
+            (self.lookup[self.opcode as usize].operate)(self);
+
+            if page_crossed && self.lookup[self.opcode as usize].page_penalty {
+                self.cycles += 1;
+            }
+
+            self.opcode_counts.record(self.opcode, self.cycles);
+
+            // A call to a trap has already returned, so as far as the
+            // profiler and shadow stack know it never happened
+            let trapped = self.opcode == 0x20 && self.trap_call();
+
+            if let Some(profiler) = self.profiler.as_mut().filter(|_| !trapped) {
+                let end = self.clock_count + self.cycles as u64;
+                profiler.on_instruction(self.opcode, stkp, self.pc, end);
+            }
+            if let Some(timeline) = self.timeline.as_mut().filter(|_| !trapped) {
+                timeline.on_instruction(self.opcode, stkp, self.pc, self.clock_count + self.cycles as u64);
+            }
+
+            if self.opcode == 0x40 {
+                self.interrupts.on_rti(self.clock_count);
+            }
+
+            if !trapped {
+                self.call_stack.on_instruction(self.opcode, op_pc, self.pc, self.stkp);
+            }
+
+            if let Some(shadow_stack) = self.shadow_stack.as_mut().filter(|_| !trapped) {
+                for diagnostic in shadow_stack.on_instruction(self.opcode, op_pc, stkp, self.pc, self.clock_count) {
+                    self.diagnostics.report(diagnostic);
+                }
+
+                // BRK's pushes are checked with the interrupts'
+                let push = match self.opcode {
+                    0x08 | 0x20 | 0x48 => Some(true),
+                    0x28 | 0x40 | 0x60 | 0x68 => Some(false),
+                    _ => None,
+                };
+                if let Some(diagnostic) = push.and_then(|push| shadow_stack.check_wrap(op_pc, stkp, self.stkp, push, self.clock_count)) {
+                    self.diagnostics.report(diagnostic);
+                }
+            }
+
+            for access in self.bus.borrow().take_unmapped_accesses() {
+                self.diagnostics.report(Diagnostic {
+                    cycle: self.clock_count,
+                    pc: op_pc,
+                    severity: Severity::Error,
+                    kind: DiagnosticKind::UnmappedAccess { addr: access.addr, write: access.write },
+                });
+            }
+        }
+
+        // RDY low stops the CPU in the cycle it's in, which goes again
+        // once it's let go
+        let stalled = self.rdy_stalls();
+        self.tick_pins(!stalled);
+        if stalled {
+            self.clock_count += 1;
+            self.run_due_alarms();
+            return;
+        }
+
+        // Increment global clock count - This is actually unused unless logging is enabled
+        // but I've kept it in because its a handy watch variable for debugging
+        self.clock_count += 1;
+
+        // The lines are looked at during the last cycle but one
+        if self.cycles == 2 && !self.poll.servicing {
+            self.poll_interrupts();
+        }
+
+        // Decrement the number of cycles remaining for this instruction
+        self.cycles -= 1;
+
+        self.run_due_alarms();
+    }
+
+    fn poll_interrupts(&mut self) {
+        if self.poll.nmi_edge {
+            self.poll.nmi_edge = false;
+            self.poll.nmi_pending = true;
+        }
+
+        let i = self.poll.i_seen.unwrap_or(self.get_flag(FLAGS6502::I) != 0);
+        self.poll.irq_pending = self.interrupts.irq_line() && !i;
+    }
+
+    // The host's own hold on the IRQ line, e.g. a key held down
+    pub fn assert_irq(&mut self, asserted: bool) {
+        self.set_irq(HOST_IRQ, asserted);
+    }
+
+    // An edge on NMI, taken at the next instruction boundary after the CPU
+    // polls it. Edges that arrive before then are seen as one.
+    pub fn trigger_nmi(&mut self) {
+        self.stimulate(Stimulus::Nmi);
+    }
+
+    // The SO input: V is set as it goes low, for hardware that signals
+    // the CPU faster than an interrupt, like a disk controller with a byte
+    // ready for a BVC loop. The core sets V between ticks, so an
+    // instruction already under way doesn't see it. Holding it low does
+    // nothing more, it has to be let go before it can set V again.
+    pub fn set_so(&mut self, low: bool) {
+        self.stimulate(Stimulus::SoLine { low });
+    }
+
+    pub fn so_low(&self) -> bool {
+        self.so_low
+    }
+
+    fn run_due_alarms(&mut self) {
+        self.scheduler.take_requests(self.clock_count);
+
+        if let Some(deadline) = self.scheduler.next_deadline() {
+            if deadline <= self.clock_count {
+                scheduler::dispatch(self);
+            }
+        }
+    }
+
+    // While halted nothing can happen until an alarm fires or a replayed
+    // stimulus arrives, so jump the clock straight there instead of
+    // clocking through the gap. Returns how many cycles were skipped.
+    pub fn skip_idle(&mut self) -> u64 {
+        if self.cycles != 0 || self.halt == Halt::Running || self.dma_active() {
+            return 0;
+        }
+
+        if self.halt == Halt::Waiting && (self.interrupts.irq_line() || self.poll.nmi_edge) {
+            return 0;
+        }
+
+        // Anything a device or another thread asked for since the last clock
+        // counts as well
+        self.scheduler.take_requests(self.clock_count);
+
+        // Alarms fire on the clock that reaches their deadline, stimuli are
+        // applied at the start of the clock on their cycle
+        let alarm = self.scheduler.next_deadline().map(|deadline| deadline.saturating_sub(1));
+        let stimulus = self.player.as_ref().and_then(|player| player.next_cycle());
+
+        let target = match (alarm, stimulus) {
+            (Some(alarm), Some(stimulus)) => alarm.min(stimulus),
+            (Some(cycle), None) | (None, Some(cycle)) => cycle,
+            (None, None) => return 0,
+        };
+
+        if target <= self.clock_count {
+            return 0;
+        }
+
+        let skipped = target - self.clock_count;
+        self.clock_count = target;
+        skipped
+    }
+
+    fn read(&mut self, address: u16) -> u8 {
+        let (value, wait) = {
+            let bus = self.bus.borrow();
+            (bus.read(address, false), bus.take_wait_states())
+        };
+        self.log_access(address, value, AccessKind::Read);
+        self.pins.record(address, value, AccessKind::Read, wait);
+        value
+    }
+
+    fn write(&mut self, address: u16, value: u8) {
+        let wait = {
+            let mut bus = self.bus.borrow_mut();
+            bus.write(address, value);
+            bus.take_wait_states()
+        };
+        self.log_access(address, value, AccessKind::Write);
+        self.pins.record(address, value, AccessKind::Write, wait);
+        self.log_device_write(address, value);
+        self.trap_write(address, value);
+    }
+
+
+    pub fn reset(&mut self) {
+        if let Some(cart) = &mut self.bus.borrow_mut().cart {
+            cart.reset();
+        }
+
+        for mapping in &self.bus.borrow().mapped {
+            mapping.device().reset();
+        }
+        self.apply_pokes();
+
+        // Get address to set program counter to
+        self.addr_abs = 0xFFFC;
+
+
+        let lo = self.read(self.addr_abs + 0) as u16;
+        let hi = self.read(self.addr_abs + 1) as u16;
+
+        // Set it
+        self.pc = ((hi << 8) | lo);
+
+
+        // Reset internal registers
+        self.a = 0;
+        self.x = 0;
+        self.y = 0;
+        self.stkp = 0xFD;
+        self.status = 0x00 | (FLAGS6502::U as u8);
+
+        // Clear internal helper variables
+        self.addr_rel = 0x0000;
+        self.addr_abs = 0x0000;
+        self.fetched = 0x00;
+
+        self.interrupts.reset();
+        self.halt = Halt::Running;
+        self.held_until = 0;
+        self.dma.clear();
+        self.exit_code = None;
+        self.poll = InterruptPoll { servicing: true, ..InterruptPoll::default() };
+
+        // Nothing pushed before the reset is ever coming back
+        self.call_stack.clear();
+        if let Some(shadow_stack) = &mut self.shadow_stack {
+            shadow_stack.clear();
+        }
+        if let Some(profiler) = &mut self.profiler {
+            profiler.reset();
+        }
+        if let Some(timeline) = &mut self.timeline {
+            timeline.close_all(self.clock_count);
+        }
+        self.took_vector(Vector::Reset, Cause::Reset);
+
+        log_debug!(target: "crust_6502::cpu", cycle = self.clock_count, pc = self.pc, "reset");
+
+        // Reset takes time
+        self.cycles = 8;
+    }
+
+
+    // Only ever started from clock() once a poll found the line asserted
+    // with I clear, hosts raise the line with assert_irq or set_irq
+    fn irq(&mut self) {
+        self.poll.irq_pending = false;
+        self.poll.servicing = true;
+
+        // Push the program counter to the stack. It's 16-bits dont
+        // forget so that takes two pushes
+        self.write(
+            (0x0100u16 + self.stkp as u16),
+            ((self.pc >> 8) & 0x00FF) as u8,
+        );
+        self.stkp = self.stkp.wrapping_sub(1);
+        self.write((0x0100u16 + self.stkp as u16), (self.pc & 0x00FF) as u8);
+        self.stkp = self.stkp.wrapping_sub(1);
+
+        // Then Push the status register to the stack, the handler's RTI
+        // brings back I as it was before the interrupt
+        self.write(0x0100u16 + self.stkp as u16, self.pushed_status(false));
+        self.stkp = self.stkp.wrapping_sub(1);
+        self.set_flag(FLAGS6502::I, true);
+
+        self.shadow_interrupt();
+
+        if let Some(diagnostic) = self.interrupts.on_irq_taken(self.clock_count, self.pc) {
+            self.diagnostics.report(diagnostic);
+        }
+
+        // Read new program counter location from fixed address
+        self.addr_abs = 0xFFFE;
+        let lo = self.read(self.addr_abs + 0) as u16;
+        let hi = self.read(self.addr_abs + 1) as u16;
+        self.pc = ((hi << 8u16) | lo) as u16;
+        self.timeline_interrupt(SpanKind::Irq);
+        self.enter_interrupt(CallKind::Irq);
+        self.took_vector(Vector::Irq, Cause::Irq);
+        log_debug!(target: "crust_6502::cpu", cycle = self.clock_count, handler = self.pc, "IRQ taken");
+
+        // IRQs take time
+        self.cycles = 7;
+    }
+
+    // Started from clock() after a poll latched an edge, see trigger_nmi
+    fn nmi(&mut self) {
+        // The IRQ gets polled again once the handler is running
+        self.poll.nmi_pending = false;
+        self.poll.irq_pending = false;
+        self.poll.servicing = true;
+
+        self.write(
+            0x0100u16 + self.stkp as u16,
+            ((self.pc >> 8) & 0x00FF) as u8,
+        );
+        self.stkp = self.stkp.wrapping_sub(1);
+        self.write(0x0100u16 + self.stkp as u16, (self.pc & 0x00FF) as u8);
+        self.stkp = self.stkp.wrapping_sub(1);
+
+        self.write(0x0100u16 + self.stkp as u16, self.pushed_status(false));
+        self.stkp = self.stkp.wrapping_sub(1);
+        self.set_flag(FLAGS6502::I, true);
+
+        self.shadow_interrupt();
+        self.interrupts.on_nmi_taken();
+
+        self.addr_abs = 0xFFFA;
+        let lo = self.read(self.addr_abs + 0) as u16;
+        let hi = self.read(self.addr_abs + 1) as u16;
+        self.pc = ((hi << 8) | lo) as u16;
+        self.timeline_interrupt(SpanKind::Nmi);
+        self.enter_interrupt(CallKind::Nmi);
+        self.took_vector(Vector::Nmi, Cause::Nmi);
+        log_debug!(target: "crust_6502::cpu", cycle = self.clock_count, handler = self.pc, "NMI taken");
+
+        self.cycles = 8;
+    }
+
+    fn fetch(&mut self) -> u8 {
+        if !(self.lookup[self.opcode as usize].addr_mode == cpu::IMP) {
+            self.fetched = self.read(self.addr_abs);
+        }
+
+        return self.fetched;
+    }
+
+    pub fn complete(&mut self) -> bool {
+        self.cycles == 0 && !self.dma_active()
+    }
+
+    // Deliver an external stimulus, logging it if a recording is running.
+    // Host code should come through here rather than calling irq()/nmi() itself.
+    pub fn stimulate(&mut self, stimulus: Stimulus) {
+        if let Some(recording) = &mut self.recording {
+            recording.events.push(Event { cycle: self.clock_count, stimulus });
+        }
+
+        self.apply_stimulus(stimulus);
+    }
+
+    fn apply_stimulus(&mut self, stimulus: Stimulus) {
+        match stimulus {
+            Stimulus::Reset => self.reset(),
+            Stimulus::Nmi => self.poll.nmi_edge = true,
+            Stimulus::Write { addr, data } => self.bus.borrow_mut().write(addr, data),
+            Stimulus::Register { reg, value } => reg.set(self, value),
+            Stimulus::IrqLine { source, asserted } => self.interrupts.set_irq(source, asserted),
+            Stimulus::SoLine { low } => {
+                if low && !self.so_low {
+                    self.set_flag(FLAGS6502::V, true);
+                }
+                self.so_low = low;
+            }
+        }
+    }
+
+    // Devices raise and release their IRQ through here so recordings see it
+    pub fn set_irq(&mut self, source: IrqSource, asserted: bool) {
+        if self.device_log.is_enabled() {
+            let change = if asserted { "asserted" } else { "released" };
+            self.device_log.record(self.interrupts.source_name(source), DeviceEventKind::Irq, format_args!("IRQ {}", change));
+        }
+        self.stimulate(Stimulus::IrqLine { source, asserted });
+    }
+
+    pub fn start_recording(&mut self) {
+        self.recording = Some(Recording::capture(self));
+    }
+
+    pub fn stop_recording(&mut self) -> Option<Recording> {
+        self.recording.take()
+    }
+
+    // Rewind to the recording's starting state and play its stimuli back
+    pub fn start_replay(&mut self, recording: Recording) -> Result<(), String> {
+        recording.restore(self)?;
+        self.player = Some(Player::new(recording));
+        Ok(())
+    }
+
+    // Call `callback` once, when the clock count reaches `cycle`
+    pub fn add_alarm_at<F>(&mut self, cycle: u64, callback: F) -> AlarmId
+        where F: FnMut(&mut cpu6502) + Send + 'static
+    {
+        self.scheduler.add_alarm(cycle, None, Box::new(callback))
+    }
+
+    // Call `callback` every `period` cycles, starting `period` cycles from now
+    pub fn add_alarm_every<F>(&mut self, period: u64, callback: F) -> AlarmId
+        where F: FnMut(&mut cpu6502) + Send + 'static
+    {
+        let deadline = self.clock_count + period;
+        self.scheduler.add_alarm(deadline, Some(period), Box::new(callback))
+    }
+
+    pub fn cancel_alarm(&mut self, id: AlarmId) -> bool {
+        self.scheduler.cancel(id)
+    }
+
+    // A handle devices and other threads can schedule alarms through, see
+    // scheduler::EventQueue
+    pub fn events(&self) -> EventQueue {
+        self.scheduler.events()
+    }
+
+    // Track pushed return addresses and report returns that don't match
+    pub fn enable_shadow_stack(&mut self) {
+        self.shadow_stack = Some(ShadowStack::new());
+    }
+
+    pub fn disable_shadow_stack(&mut self) {
+        self.shadow_stack = None;
+    }
+
+    // A strict shadow stack, which stops the debugger on SP wrapping and
+    // on returns to addresses that were never pushed
+    pub fn enable_stack_checks(&mut self) {
+        self.shadow_stack = Some(ShadowStack::strict());
+    }
+
+    // Called right after an interrupt (or BRK) pushed PC and status
+    fn shadow_interrupt(&mut self) {
+        if let Some(shadow_stack) = &mut self.shadow_stack {
+            shadow_stack.push_interrupt(self.pc, self.stkp);
+            if let Some(diagnostic) = shadow_stack.check_wrap(self.pc, self.stkp.wrapping_add(3), self.stkp, true, self.clock_count) {
+                self.diagnostics.report(diagnostic);
+            }
+        }
+    }
+
+    // Installs a sink for the execution trace, or with None stops tracing.
+    // Whatever the old sink buffered is flushed.
+    pub fn set_trace(&mut self, sink: Option<Box<dyn TraceSink>>) {
+        if let Some(mut old) = std::mem::replace(&mut self.trace, sink) {
+            if let Err(error) = old.flush() {
+                self.trace_failed(error);
+            }
+        }
+    }
+
+    pub fn is_tracing(&self) -> bool {
+        self.trace.is_some()
+    }
+
+    fn trace_instruction(&mut self) {
+        let len = self.instruction_len(self.opcode);
+        let bus = self.bus.borrow();
+        let bytes = [self.opcode, bus.read(self.pc.wrapping_add(1), true), bus.read(self.pc.wrapping_add(2), true)];
+        drop(bus);
+
+        let record = TraceRecord { cycle: self.clock_count, pc: self.pc, bytes, len, a: self.a, x: self.x, y: self.y, p: self.status, s: self.stkp };
+        if let Some(ring) = &mut self.trace_ring {
+            ring.push(record);
+        }
+
+        // A sink that fails once (a closed socket, a full disk) is dropped
+        // rather than failing again on every instruction
+        let event = record.event(self.lookup[self.opcode as usize].name.as_str());
+        if let Some(Err(error)) = self.trace.as_mut().map(|sink| sink.event(&event)) {
+            self.trace = None;
+            self.trace_failed(error);
+        }
+    }
+
+    // Keeps the last `capacity` instructions in memory, or with None stops
+    // and forgets them
+    pub fn set_trace_ring(&mut self, capacity: Option<usize>) {
+        self.trace_ring = capacity.map(TraceRing::new);
+    }
+
+    pub fn trace_ring(&self) -> Option<&TraceRing> {
+        self.trace_ring.as_ref()
+    }
+
+    // Writes the newest `count` instructions in the ring to `sink`, oldest
+    // first, and returns how many there were
+    pub fn dump_trace_ring(&self, count: usize, sink: &mut dyn TraceSink) -> std::io::Result<usize> {
+        let ring = match &self.trace_ring {
+            Some(ring) => ring,
+            None => return Ok(0),
+        };
+
+        let mut written = 0;
+        for record in ring.last(count) {
+            sink.event(&record.event(self.lookup[record.bytes[0] as usize].name.as_str()))?;
+            written += 1;
+        }
+        sink.flush()?;
+        Ok(written)
+    }
+
+    fn trace_failed(&mut self, error: std::io::Error) {
+        self.diagnostics.report(Diagnostic {
+            cycle: self.clock_count,
+            pc: self.pc,
+            severity: Severity::Warning,
+            kind: DiagnosticKind::TraceStopped { error: error.to_string() },
+        });
+    }
+
+    pub fn enable_profiler(&mut self) {
+        self.profiler = Some(Profiler::new());
+    }
+
+    pub fn disable_profiler(&mut self) {
+        self.profiler = None;
+    }
+
+    pub fn is_profiling(&self) -> bool {
+        self.profiler.is_some()
+    }
+
+    // Subroutines seen since the profiler was enabled, hottest first
+    pub fn profile_report(&self, sort: ProfileSort) -> Vec<ProfileEntry> {
+        match &self.profiler {
+            Some(profiler) => profiler.report(sort),
+            None => Vec::new(),
+        }
+    }
+
+    // Switches the opcodes that differ between variants in and out of the
+    // lookup table, so the disassembler follows along
+    pub fn set_variant(&mut self, variant: Variant) {
+        let (wai, stp) = match variant {
+            Variant::Nmos6502 => (
+                INSTRUCTION { name: "???".to_string(), operate: cpu::XXX, addr_mode: cpu::IMP, cycles: 2, page_penalty: false },
+                INSTRUCTION { name: "???".to_string(), operate: cpu::XXX, addr_mode: cpu::IMP, cycles: 7, page_penalty: false },
+            ),
+            Variant::Cmos65C02 => (
+                INSTRUCTION { name: "WAI".to_string(), operate: cpu::WAI, addr_mode: cpu::IMP, cycles: 3, page_penalty: false },
+                INSTRUCTION { name: "STP".to_string(), operate: cpu::STP, addr_mode: cpu::IMP, cycles: 3, page_penalty: false },
+            ),
+        };
+
+        self.lookup[0xCB] = wai;
+        self.lookup[0xDB] = stp;
+        for opcode in JAM_OPCODES {
+            self.lookup[opcode as usize] = match variant {
+                Variant::Nmos6502 => INSTRUCTION { name: "JAM".to_string(), operate: cpu::JAM, addr_mode: cpu::IMP, cycles: 2, page_penalty: false },
+                Variant::Cmos65C02 => INSTRUCTION { name: "???".to_string(), operate: cpu::XXX, addr_mode: cpu::IMP, cycles: 2, page_penalty: false },
+            };
+        }
+        self.variant = variant;
+    }
+
+    pub fn variant(&self) -> Variant {
+        self.variant
+    }
+
+    pub fn halt_state(&self) -> Halt {
+        self.halt
+    }
+
+    // Cycles run since the CPU was created
+    pub fn clock_count(&self) -> u64 {
+        self.clock_count
+    }
+
+    // Pulls RDY low until cycle `until`: the instruction under way
+    // finishes, then nothing more is fetched before then. What the 2600's
+    // TIA does to the 6507 on a write to WSYNC.
+    pub fn hold_until(&mut self, until: u64) {
+        self.held_until = self.held_until.max(until);
+    }
+
+    pub fn connect_bus(&mut self, bus: SharedBus) {
+        self.bus = bus
+    }
+
+    // Memory as the debugger sees it, without the side effects a read
+    // can have on devices
+    pub fn peek(&self, addr: u16) -> u8 {
+        self.bus.borrow().read(addr, true)
+    }
+
+    // `len` bytes from `start` on, wrapping past $FFFF
+    pub fn peek_bytes(&self, start: u16, len: usize) -> Vec<u8> {
+        let bus = self.bus.borrow();
+        (0..len).map(|i| bus.read(start.wrapping_add(i as u16), true)).collect()
+    }
+
+    // Copies a program straight into memory, wrapping past $FFFF. Writes
+    // go to the bus directly so recordings don't pick them up.
+    pub fn load_program(&mut self, program: &[u8], load_addr: u16) {
+        let mut bus = self.bus.borrow_mut();
+        let mut addr = load_addr;
+
+        for byte in program {
+            bus.write(addr, *byte);
+            addr = addr.wrapping_add(1);
+        }
+    }
+
+    // JSRs to `addr` with whatever is in the registers and runs until the
+    // matching RTS comes back, for testing one routine at a time from Rust.
+    // The call pushes a return address to the current PC, nothing runs there.
+    pub fn run_subroutine(&mut self, addr: u16, max_cycles: u64) -> Result<CpuState, String> {
+        if self.halt == Halt::Stopped {
+            return Err("the cpu is stopped".to_string());
+        }
+
+        // Let whatever instruction is in flight finish first
+        while !self.complete() {
+            self.clock();
+        }
+
+        let return_to = self.pc;
+        let stkp = self.stkp;
+
+        // Exactly what JSR pushes, the address of its own last byte
+        let pushed = return_to.wrapping_sub(1);
+        self.write(0x0100 + self.stkp as u16, (pushed >> 8) as u8);
+        self.stkp = self.stkp.wrapping_sub(1);
+        self.write(0x0100 + self.stkp as u16, pushed as u8);
+        self.stkp = self.stkp.wrapping_sub(1);
+
+        if let Some(shadow_stack) = &mut self.shadow_stack {
+            shadow_stack.on_instruction(0x20, return_to.wrapping_sub(3), stkp, addr, self.clock_count);
+        }
+        self.call_stack.on_instruction(0x20, return_to.wrapping_sub(3), addr, self.stkp);
+
+        self.pc = addr;
+        self.halt = Halt::Running;
+
+        let deadline = self.clock_count + max_cycles;
+        while self.clock_count < deadline {
+            self.clock();
+
+            if self.complete() && self.pc == return_to && self.stkp == stkp {
+                return Ok(CpuState::of(self));
+            }
+        }
+
+        Err(std::format!("subroutine at ${:04x} didn't return within {} cycles", addr, max_cycles))
+    }
+
+    // Where reset() starts running from
+    pub fn set_reset_vector(&mut self, addr: u16) {
+        self.set_vector(0xFFFC, addr);
+    }
+
+    pub fn set_irq_vector(&mut self, addr: u16) {
+        self.set_vector(0xFFFE, addr);
+    }
+
+    pub fn set_nmi_vector(&mut self, addr: u16) {
+        self.set_vector(0xFFFA, addr);
+    }
+
+    fn set_vector(&mut self, vector: u16, addr: u16) {
+        self.load_program(&[addr as u8, (addr >> 8) as u8], vector);
+    }
+
+
+    pub fn instruction_name(&self, opcode: u8) -> &str {
+        self.lookup[opcode as usize].name.as_str()
+    }
+
+    // False for the opcodes that do nothing here or jam the CPU, which code
+    // doesn't run through and listings show as data
+    pub fn is_instruction(&self, opcode: u8) -> bool {
+        !matches!(self.instruction_name(opcode), "???" | "JAM")
+    }
+
+    // Bytes taken by an instruction including its operand
+    pub fn instruction_len(&self, opcode: u8) -> u16 {
+        let mode = self.lookup[opcode as usize].addr_mode;
+
+        if mode == cpu::IMP {
+            1
+        } else if mode == cpu::ABS || mode == cpu::ABX || mode == cpu::ABY || mode == cpu::IND {
+            3
+        } else {
+            2
+        }
+    }
+
+    pub fn disassemble(&mut self, start: u16, stop: u16) -> BTreeMap<u16, String> {
+        self.disassemble_with_symbols(start, stop, &SymbolTable::new())
+    }
+
+    // Same as disassemble, but operands that land on a known symbol show
+    // its name instead of the raw address
+    pub fn disassemble_with_symbols(&mut self, start: u16, stop: u16, symbols: &SymbolTable) -> BTreeMap<u16, String> {
+        let mut addr = start;
+        let mut map_lines: BTreeMap<u16, String> = BTreeMap::new();
+
+        loop {
+            let line_addr = addr;
+            let (line, next) = self.disassemble_line(line_addr, symbols);
+            addr = next;
+
+            // Add the formed string to a std::map, using the instruction's
+            // address as the key. This makes it convenient to look for later
+            // as the instructions are variable in length, so a straight up
+            // incremental index is not sufficient.
+
+            map_lines.insert(line_addr, line);
+
+            // Stop at the end of the range, or once an instruction has run
+            // off the top of memory and wrapped round to $0000
+            if line_addr >= stop || addr <= line_addr {
+                break;
+            }
+        }
+
+        map_lines
+    }
+
+    // The one instruction at `addr`, and the address of the one after it
+    pub fn disassemble_line(&self, addr: u16, symbols: &SymbolTable) -> (String, u16) {
+        let instruction = self.decode_instruction(addr, symbols);
+        (instruction.line(), instruction.next())
+    }
+}
+
+
+pub fn decode_hex(s: &str) -> Result<Vec<u8>, EmuError> {
+    if s.len() % 2 != 0 {
+        return Err(EmuError::InvalidHex(std::format!("{} digits, not whole bytes", s.len())));
+    }
+
+    let digit = |c: u8| (c as char).to_digit(16);
+    s.as_bytes()
+        .chunks(2)
+        .enumerate()
+        .map(|(i, pair)| match (digit(pair[0]), digit(pair[1])) {
+            (Some(hi), Some(lo)) => Ok((hi << 4 | lo) as u8),
+            _ => Err(EmuError::InvalidHex(std::format!("byte {} isn't two hex digits", i))),
+        })
+        .collect()
+}
+
+pub fn encode_hex(bytes: &[u8]) -> String {
+    let mut s = String::with_capacity(bytes.len() * 2);
+    for &b in bytes {
+        write!(&mut s, "{:02x}", b).unwrap();
+    }
+    s
+}
+
+pub fn to_hex<T: LowerHex>(number: T, d: u16) -> String {
+    let mut s = String::new();
+
+    if d == 2 {
+        write!(&mut s, "{:02x}", number).unwrap();
+    }
+
+    if d == 4 {
+        write!(&mut s, "{:04x}", number).unwrap();
+    }
+
+    s
+}
+
+pub fn print_cpu(cpu: &mut cpu6502)
+{
+    println!("pc: {:02x}", cpu.pc);
+    println!("Acc register: {:02x} [{}]", cpu.a, cpu.a);
+    println!("X register: {:02x} [{}]", cpu.x, cpu.x);
+    println!("Y register: {:02x} [{}]", cpu.y, cpu.y);
+    println!("Status Register: {:02x} [{}] [{:b}]", cpu.status, cpu.status, cpu.status);
+    println!("Stack Pointer: {:02x}", cpu.stkp);
+    println!("cycles: {:02x}", cpu.cycles);
+    println!("fetched: {}", cpu.fetched);
+    println!("Cycles comeplete: {:?}", cpu.complete());
+}
+
+// One NTSC video frame worth of CPU time, how far we run per update when not stepping
+pub const CYCLES_PER_FRAME: u32 = 29780;
+
+// The 151 documented NMOS opcodes, a bit per opcode and a word per high
+// nibble
+const DOCUMENTED: [u16; 16] = [
+    0x6763, 0x6363, 0x7773, 0x6363, 0x7763, 0x6363, 0x7763, 0x6363,
+    0x7572, 0x2773, 0x7777, 0x7773, 0x7773, 0x6363, 0x7773, 0x6363,
+];
+
+pub fn is_documented(opcode: u8) -> bool {
+    DOCUMENTED[opcode as usize / 16] & (1 << (opcode % 16)) != 0
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hex_decoding() {
+        assert_eq!(decode_hex("a9FF00").unwrap(), vec![0xA9, 0xFF, 0x00]);
+        assert!(decode_hex("").unwrap().is_empty());
+
+        // These used to panic slicing the string
+        assert!(matches!(decode_hex("a9f"), Err(EmuError::InvalidHex(_))));
+        assert!(matches!(decode_hex("é"), Err(EmuError::InvalidHex(_))));
+        assert!(matches!(decode_hex("+f"), Err(EmuError::InvalidHex(_))));
+    }
+
+    #[test]
+    fn machines_can_move_between_threads() {
+        fn send<T: Send>() {}
+        fn send_sync<T: Send + Sync>() {}
+
+        send::<cpu6502>();
+        send::<crate::machine::Machine>();
+        send::<crate::cpu65816::cpu65816>();
+        send_sync::<SharedBus>();
+        send_sync::<Bus>();
+    }
+
+    #[test]
+    fn program_and_vectors_through_the_api() {
+        let mut cpu = cpu6502::new();
+        cpu.load_program(&[0xEA, 0xEA], 0x8000);
+        cpu.set_reset_vector(0x8000);
+        cpu.set_irq_vector(0x9000);
+        cpu.set_nmi_vector(0xA000);
+
+        let bus = cpu.bus.borrow();
+        let vector = |addr| bus.read(addr, true) as u16 | (bus.read(addr + 1, true) as u16) << 8;
+        assert_eq!((vector(0xFFFC), vector(0xFFFE), vector(0xFFFA)), (0x8000, 0x9000, 0xA000));
+        assert_eq!(bus.read(0x8001, true), 0xEA);
+        drop(bus);
+
+        cpu.reset();
+        assert_eq!(cpu.pc, 0x8000);
+
+        // Eight cycles of reset, then the NOP the NMI is polled during
+        cpu.trigger_nmi();
+        run(&mut cpu, 10);
+        assert_eq!(cpu.pc, 0x8001);
+        run(&mut cpu, 1);
+        assert_eq!(cpu.pc, 0xA000);
+    }
+
+    // CLI / WAI / INX / JMP $8002, the IRQ handler at $9000 does INY / RTI
+    fn waiting_cpu(variant: Variant) -> cpu6502 {
+        let mut cpu = cpu6502::new();
+        cpu.set_variant(variant);
+        cpu.load_program(&[0x58, 0xCB, 0xE8, 0x4C, 0x02, 0x80], 0x8000);
+        cpu.load_program(&[0xC8, 0x40], 0x9000);
+        cpu.set_reset_vector(0x8000);
+        cpu.set_irq_vector(0x9000);
+        cpu.set_nmi_vector(0x9000);
+        cpu.reset();
+        cpu
+    }
+
+    fn run(cpu: &mut cpu6502, cycles: u64) {
+        for _ in 0..cycles {
+            cpu.clock();
+        }
+    }
+
+    #[test]
+    fn nmos_treats_wai_as_a_nop() {
+        let mut cpu = waiting_cpu(Variant::Nmos6502);
+        run(&mut cpu, 100);
+        assert_eq!(cpu.halt_state(), Halt::Running);
+        assert!(cpu.x > 0);
+        assert_eq!(cpu.instruction_name(0xCB), "???");
+    }
+
+    #[test]
+    fn wai_sleeps_until_the_irq_line_goes_low() {
+        let mut cpu = waiting_cpu(Variant::Cmos65C02);
+        let timer = cpu.interrupts.register_source("timer");
+        assert_eq!(cpu.instruction_name(0xCB), "WAI");
+
+        run(&mut cpu, 100);
+        assert_eq!(cpu.halt_state(), Halt::Waiting);
+        assert_eq!(cpu.pc, 0x8002);
+        assert_eq!(cpu.x, 0);
+
+        // The handler runs, then execution carries on after the WAI
+        cpu.set_irq(timer, true);
+        run(&mut cpu, 1);
+        assert_eq!(cpu.halt_state(), Halt::Running);
+        assert_eq!(cpu.pc, 0x9000);
+        cpu.set_irq(timer, false);
+
+        run(&mut cpu, 30);
+        assert_eq!(cpu.y, 1);
+        assert!(cpu.x > 0);
+    }
+
+    #[test]
+    fn wai_with_interrupts_disabled_just_resumes() {
+        let mut cpu = waiting_cpu(Variant::Cmos65C02);
+        cpu.load_program(&[0x78], 0x8000);
+        let timer = cpu.interrupts.register_source("timer");
+
+        run(&mut cpu, 100);
+        assert_eq!(cpu.halt_state(), Halt::Waiting);
+
+        cpu.set_irq(timer, true);
+        run(&mut cpu, 30);
+        assert_eq!(cpu.halt_state(), Halt::Running);
+        assert_eq!(cpu.y, 0);
+        assert!(cpu.x > 0);
+    }
+
+    #[test]
+    fn nmi_wakes_wai() {
+        let mut cpu = waiting_cpu(Variant::Cmos65C02);
+        run(&mut cpu, 100);
+
+        cpu.trigger_nmi();
+        run(&mut cpu, 1);
+        assert_eq!((cpu.halt_state(), cpu.pc), (Halt::Running, 0x9000));
+    }
+
+    #[test]
+    fn stp_only_leaves_on_reset() {
+        let mut cpu = waiting_cpu(Variant::Cmos65C02);
+        cpu.load_program(&[0xDB], 0x8001);
+        let timer = cpu.interrupts.register_source("timer");
+
+        run(&mut cpu, 100);
+        assert_eq!(cpu.halt_state(), Halt::Stopped);
+
+        cpu.set_irq(timer, true);
+        cpu.trigger_nmi();
+        run(&mut cpu, 100);
+        assert_eq!((cpu.halt_state(), cpu.pc, cpu.y), (Halt::Stopped, 0x8002, 0));
+
+        cpu.stimulate(Stimulus::Reset);
+        assert_eq!((cpu.halt_state(), cpu.pc), (Halt::Running, 0x8000));
+    }
+
+    #[test]
+    fn idle_cpu_skips_to_the_next_alarm() {
+        let mut cpu = waiting_cpu(Variant::Cmos65C02);
+        let timer = cpu.interrupts.register_source("timer");
+        cpu.add_alarm_at(10_000, move |cpu| cpu.interrupts.set_irq(timer, true));
+
+        assert_eq!(cpu.skip_idle(), 0);
+        run(&mut cpu, 20);
+        assert_eq!(cpu.halt_state(), Halt::Waiting);
+
+        let skipped = cpu.skip_idle();
+        assert_eq!(cpu.clock_count, 9_999);
+        assert_eq!(skipped, 9_979);
+
+        // The alarm is still the one to wake the CPU
+        run(&mut cpu, 2);
+        assert_eq!((cpu.halt_state(), cpu.pc), (Halt::Running, 0x9000));
+        assert_eq!(cpu.skip_idle(), 0);
+    }
+
+    // $8000 INX x4 / JMP $8004, IRQ handler JMP $9000, NMI handler INY / RTI
+    fn interrupt_cpu() -> cpu6502 {
+        let mut cpu = cpu6502::new();
+        cpu.load_program(&[0xE8, 0xE8, 0xE8, 0xE8, 0x4C, 0x04, 0x80], 0x8000);
+        cpu.load_program(&[0x4C, 0x00, 0x90], 0x9000);
+        cpu.load_program(&[0xC8, 0x40], 0xA000);
+        cpu.set_reset_vector(0x8000);
+        cpu.set_irq_vector(0x9000);
+        cpu.set_nmi_vector(0xA000);
+        cpu.reset();
+        run(&mut cpu, 8);
+        cpu
+    }
+
+    fn run_until_pc(cpu: &mut cpu6502, pc: u16) -> bool {
+        for _ in 0..200 {
+            cpu.clock();
+            if cpu.pc == pc {
+                return true;
+            }
+        }
+        false
+    }
+
+    // Low byte of the return address an interrupt pushed from SP $FD
+    fn pushed_return(cpu: &cpu6502) -> u8 {
+        cpu.bus.borrow().read(0x01FC, true)
+    }
+
+    #[test]
+    fn irq_is_taken_after_the_instruction_it_was_polled_in() {
+        let mut cpu = interrupt_cpu();
+        cpu.assert_irq(true);
+
+        run(&mut cpu, 1);
+        assert_eq!(cpu.pc, 0x8001);
+        run(&mut cpu, 1);
+        assert_eq!(cpu.pc, 0x8001);
+        run(&mut cpu, 1);
+        assert_eq!((cpu.pc, cpu.x, pushed_return(&cpu)), (0x9000, 1, 0x01));
+    }
+
+    #[test]
+    fn irq_raised_in_the_last_cycle_waits_an_instruction() {
+        let mut cpu = interrupt_cpu();
+
+        // Polled in the first cycle of INX, the line goes low in the second
+        run(&mut cpu, 1);
+        cpu.assert_irq(true);
+
+        assert!(run_until_pc(&mut cpu, 0x9000));
+        assert_eq!((cpu.x, pushed_return(&cpu)), (2, 0x02));
+    }
+
+    #[test]
+    fn cli_delays_a_pending_irq_by_one_instruction() {
+        let mut cpu = interrupt_cpu();
+        cpu.load_program(&[0x58], 0x8000);
+        cpu.status |= FLAGS6502::I as u8;
+        cpu.assert_irq(true);
+
+        // CLI, then one INX still runs before the IRQ
+        assert!(run_until_pc(&mut cpu, 0x9000));
+        assert_eq!((cpu.x, pushed_return(&cpu)), (1, 0x02));
+    }
+
+    #[test]
+    fn sei_lets_one_irq_through() {
+        let mut cpu = interrupt_cpu();
+        cpu.load_program(&[0x78], 0x8001);
+
+        // The line goes low during the INX before SEI, SEI's poll still
+        // sees I clear
+        run(&mut cpu, 2);
+        cpu.assert_irq(true);
+
+        assert!(run_until_pc(&mut cpu, 0x9000));
+        assert_eq!(pushed_return(&cpu), 0x02);
+    }
+
+    #[test]
+    fn plp_changes_i_after_the_poll() {
+        let mut cpu = interrupt_cpu();
+        // PHP with I set, CLI, PLP: the poll in PLP sees I clear
+        cpu.load_program(&[0x78, 0x08, 0x58, 0x28], 0x8000);
+        run(&mut cpu, 2 + 3 + 2);
+        cpu.assert_irq(true);
+
+        assert!(run_until_pc(&mut cpu, 0x9000));
+        assert_eq!(pushed_return(&cpu), 0x04);
+    }
+
+    #[test]
+    fn nmi_edges_are_latched_once() {
+        let mut cpu = interrupt_cpu();
+        cpu.trigger_nmi();
+        cpu.trigger_nmi();
+
+        run(&mut cpu, 100);
+        assert_eq!(cpu.y, 1);
+
+        // A later edge is a new NMI, even with I set
+        cpu.status |= FLAGS6502::I as u8;
+        cpu.trigger_nmi();
+        run(&mut cpu, 100);
+        assert_eq!(cpu.y, 2);
+    }
+
+    #[test]
+    fn so_sets_overflow_as_it_goes_low() {
+        // CLV / wait: BVC wait / INX / loop: JMP loop
+        let mut cpu = cpu6502::new();
+        cpu.load_program(&[0xB8, 0x50, 0xFE, 0xE8, 0x4C, 0x04, 0x80], 0x8000);
+        cpu.set_reset_vector(0x8000);
+        cpu.reset();
+        run(&mut cpu, 8 + 2 + 30);
+        assert_eq!((cpu.pc, cpu.x), (0x8001, 0));
+
+        cpu.set_so(true);
+        assert!(cpu.get_flag(FLAGS6502::V) != 0);
+        run(&mut cpu, 10);
+        assert_eq!(cpu.x, 1);
+
+        // Held low it doesn't set V again, the next edge does
+        cpu.set_flag(FLAGS6502::V, false);
+        cpu.set_so(true);
+        assert_eq!(cpu.get_flag(FLAGS6502::V), 0);
+        cpu.set_so(false);
+        cpu.set_so(true);
+        assert!(cpu.get_flag(FLAGS6502::V) != 0);
+    }
+
+    #[test]
+    fn nmi_goes_before_irq() {
+        let mut cpu = interrupt_cpu();
+        cpu.assert_irq(true);
+        cpu.trigger_nmi();
+
+        // NMI first, and the IRQ doesn't cut in before the handler's INY
+        assert!(run_until_pc(&mut cpu, 0xA000));
+        assert_eq!(pushed_return(&cpu), 0x01);
+        run(&mut cpu, 7 + 1);
+        assert_eq!((cpu.pc, cpu.y), (0xA001, 1));
+
+        // RTI brings I back clear, so the IRQ still waiting gets its turn
+        assert!(run_until_pc(&mut cpu, 0x9000));
+        assert_eq!(cpu.y, 1);
+    }
+
+    #[test]
+    fn subroutines_run_until_they_return() {
+        let mut cpu = cpu6502::new();
+        // double: TXA / ASL A / TAX / JSR inc / RTS, inc: INX / RTS
+        cpu.load_program(&[0x8A, 0x0A, 0xAA, 0x20, 0x10, 0x90, 0x60], 0x9000);
+        cpu.load_program(&[0xE8, 0x60], 0x9010);
+        cpu.enable_shadow_stack();
+        cpu.pc = 0x8000;
+        cpu.stkp = 0xFD;
+
+        cpu.x = 20;
+        let state = cpu.run_subroutine(0x9000, 1000).unwrap();
+        assert_eq!((state.x, state.a, state.pc, state.stkp), (41, 40, 0x8000, 0xFD));
+
+        // Calls can be made one after another
+        cpu.x = state.x;
+        assert_eq!(cpu.run_subroutine(0x9000, 1000).unwrap().x, 83);
+        assert!(cpu.diagnostics.drain().is_empty());
+    }
+
+    #[test]
+    fn subroutines_that_never_return() {
+        let mut cpu = cpu6502::new();
+        cpu.load_program(&[0x4C, 0x00, 0x90], 0x9000);
+        cpu.pc = 0x8000;
+
+        let err = cpu.run_subroutine(0x9000, 100).unwrap_err();
+        assert_eq!(err, "subroutine at $9000 didn't return within 100 cycles");
+    }
+
+    #[test]
+    fn programs_wrap_past_the_top_of_memory() {
+        let mut cpu = cpu6502::new();
+        cpu.load_program(&[0x01, 0x02, 0x03], 0xFFFF);
+
+        let bus = cpu.bus.borrow();
+        assert_eq!((bus.read(0xFFFF, true), bus.read(0x0000, true), bus.read(0x0001, true)), (0x01, 0x02, 0x03));
+    }
+
+    // LDA $4000 / STA $4001 / NOP with nothing at $4000-$5fff
+    fn open_bus_cpu() -> cpu6502 {
+        let mut cpu = cpu6502::new();
+        cpu.load_program(&[0xAD, 0x00, 0x40, 0x8D, 0x01, 0x40, 0xEA], 0x8000);
+        cpu.set_reset_vector(0x8000);
+        cpu.bus.borrow_mut().set_unmapped(0x5FFF, 0x4000);
+        cpu.reset();
+        cpu
+    }
+
+    #[test]
+    fn unmapped_reads_see_the_last_value_on_the_bus() {
+        let mut cpu = open_bus_cpu();
+        assert!(run_until_pc(&mut cpu, 0x8003));
+        // The high byte of the operand was the last thing fetched
+        assert_eq!(cpu.a, 0x40);
+
+        assert!(run_until_pc(&mut cpu, 0x8006));
+        assert_eq!(cpu.bus.borrow().latch(), 0x40);
+        cpu.bus.borrow_mut().clear_unmapped();
+        assert_eq!(cpu.bus.borrow().read(0x4001, true), 0x00);
+        assert!(cpu.diagnostics.drain().is_empty());
+
+        let mut cpu = open_bus_cpu();
+        cpu.bus.borrow_mut().set_open_bus(OpenBus::Fixed(0xFF));
+        assert!(run_until_pc(&mut cpu, 0x8003));
+        assert_eq!(cpu.a, 0xFF);
+    }
+
+    #[test]
+    fn unmapped_accesses_can_be_faults() {
+        let mut cpu = open_bus_cpu();
+        cpu.bus.borrow_mut().report_unmapped(true);
+        assert!(run_until_pc(&mut cpu, 0x8006));
+
+        let faults: Vec<(u16, String)> = cpu.diagnostics.drain().iter().map(|d| (d.pc, d.message())).collect();
+        assert_eq!(faults, vec![
+            (0x8000, "read from unmapped $4000".to_string()),
+            (0x8003, "write to unmapped $4001".to_string()),
+        ]);
+    }
+
+    #[test]
+    fn ram_and_devices_repeat_through_mirrors() {
+        let mut bus = Bus::new();
+        bus.mirror_ram(0x0000, 0x1FFF, 0x0800);
+        bus.write(0x0801, 0xAB);
+        assert_eq!((bus.read(0x0001, true), bus.read(0x1801, true)), (0xAB, 0xAB));
+        // Outside the mirror RAM is its own
+        assert_eq!(bus.read(0x2001, true), 0x00);
+
+        let sink = crate::fixtures::CaptureSink::new();
+        let output = sink.output();
+        bus.map_mirrored("ppu", 0x2000, 0x3FFF, 8, Box::new(sink));
+        bus.write(0x2006, 0x01);
+        bus.write(0x3FFE, 0x02);
+        assert_eq!(*output.lock().unwrap(), vec![0x01, 0x02]);
+
+        let mapping = bus.mappings().find(|mapping| mapping.name == "ppu").unwrap();
+        assert_eq!((mapping.offset(0x2006), mapping.offset(0x3FFE), mapping.offset(0x2008)), (6, 6, 0));
+
+        bus.clear_ram_mirrors();
+        assert_eq!(bus.read(0x1801, true), 0x00);
+    }
+
+    #[test]
+    fn small_ram_and_partial_decoding() {
+        // 2K, nothing above it until it's decoded to repeat through 8K
+        let mut bus = Bus::with_ram(0x0800);
+        bus.set_open_bus(OpenBus::Fixed(0xEE));
+        bus.write(0x0801, 0xAB);
+        assert_eq!((bus.read(0x0001, true), bus.read(0x0801, true)), (0x00, 0xEE));
+
+        bus.decode_ram(0x0000, 0x1FFF, 0x07FF);
+        bus.write(0x1801, 0xAB);
+        assert_eq!((bus.read(0x0001, true), bus.read(0x0801, true), bus.read(0x2001, true)), (0xAB, 0xAB, 0xEE));
+
+        // A mask needn't be a run of low bits: A8 left out folds each page
+        // onto the one below it
+        bus.decode_ram(0x4000, 0x4FFF, 0x4EFF);
+        bus.write(0x4142, 0x17);
+        assert_eq!(bus.read(0x4042, true), 0x17);
+
+        // A device seeing A0-A3 of its offset
+        let sink = crate::fixtures::CaptureSink::new();
+        bus.map_decoded("via", 0x6000, 0x7FFF, 0x000F, Box::new(sink));
+        let mapping = bus.mappings().find(|mapping| mapping.name == "via").unwrap();
+        assert_eq!((mapping.offset(0x6003), mapping.offset(0x7FF3), mapping.offset(0x6010)), (3, 3, 0));
+
+        bus.clear_ram_decoding();
+        assert_eq!(bus.read(0x1801, true), 0xEE);
+    }
+
+    #[test]
+    fn power_on_patterns_and_noise() {
+        let bus = Bus::with_ram_init(RamInit::AlternatePages);
+        assert_eq!((bus.read(0x00FF, true), bus.read(0x0100, true)), (0x00, 0xFF));
+
+        let noise = |bus: &mut Bus| -> Vec<u8> {
+            bus.set_unmapped(0x8000, 0xFFFF);
+            bus.set_open_bus(OpenBus::Random(3));
+            // Peeking doesn't use any up
+            assert_eq!(bus.read(0x9000, true), bus.read(0x9000, true));
+            (0..8).map(|_| bus.read(0x9000, false)).collect()
+        };
+        let first = noise(&mut Bus::new());
+        assert_eq!(first, noise(&mut Bus::new()));
+        assert!(first.windows(2).any(|pair| pair[0] != pair[1]));
+    }
+
+    #[test]
+    fn breadboard_ram_and_rom() {
+        // 32K of RAM under a 32K EEPROM holding LDA #$42 / STA $7FFF / JMP $8000
+        let mut image = vec![0xEA; 0x8000];
+        image[..8].copy_from_slice(&[0xA9, 0x42, 0x8D, 0xFF, 0x7F, 0x4C, 0x00, 0x80]);
+        image[0x7FFC] = 0x00;
+        image[0x7FFD] = 0x80;
+
+        let mut cpu = cpu6502::new();
+        cpu.connect_bus(SharedBus::new(Bus::with_ram(0x8000)));
+        cpu.bus.borrow_mut().map("rom", 0x8000, 0xFFFF, Box::new(crate::device::Rom::new(&image)));
+        cpu.reset();
+        assert!(run_until_pc(&mut cpu, 0x8005));
+
+        let mut bus = cpu.bus.borrow_mut();
+        assert_eq!(bus.read(0x7FFF, true), 0x42);
+        bus.write(0x8000, 0x00);
+        assert_eq!(bus.read(0x8000, true), 0xA9);
+    }
+
+    // Guest code is arbitrary, so no opcode may panic whatever state it
+    // starts from. Tests build with overflow checks, which is what catches
+    // a register, index or the stack pointer being added to without
+    // wrapping. Each opcode runs from the corners (everything $00 or $ff,
+    // PC about to wrap) and from random states.
+    #[test]
+    fn every_opcode_from_any_state_is_panic_free() {
+        let mut rng = Xorshift64::new(600);
+
+        for variant in [Variant::Nmos6502, Variant::Cmos65C02] {
+            for opcode in 0..=0xFFu8 {
+                for round in 0..24 {
+                    let mut cpu = cpu6502::new();
+                    cpu.set_variant(variant);
+                    // Rounds 0 and 1 fill everything with $00 and $ff
+                    let mut byte = || match round {
+                        0 => 0x00,
+                        1 => 0xFF,
+                        _ => rng.next_u8(),
+                    };
+                    let (a, x, y, stkp, status) = (byte(), byte(), byte(), byte(), byte());
+                    let pc = match round {
+                        0 | 1 => 0xFFFF,
+                        2 => 0xFFFE,
+                        _ => (byte() as u16) << 8 | byte() as u16,
+                    };
+                    {
+                        let mut bus = cpu.bus.borrow_mut();
+                        // Operands, the zero page pointers, the stack and the vectors
+                        let around_pc = (1..3).map(|offset| pc.wrapping_add(offset));
+                        for addr in (0x0000..0x0200).chain(0xFFFA..=0xFFFF).chain(around_pc) {
+                            bus.write(addr, byte());
+                        }
+                        bus.write(pc, opcode);
+                    }
+                    (cpu.a, cpu.x, cpu.y, cpu.stkp, cpu.status, cpu.pc) = (a, x, y, stkp, status, pc);
+
+                    // The opcode and whatever it lands on after
+                    for _ in 0..32 {
+                        cpu.clock();
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,262 @@
+use std::any::Any;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+use crate::cpu6502;
+use crate::device::BusDevice;
+use crate::error::EmuError;
+use crate::Bus;
+
+// A plain linear framebuffer for programs written for this emulator rather
+// than for a real machine: `width` by `height` pixels of 1, 4 or 8 bits,
+// packed a row at a time into RAM from `base`, the leftmost pixel of each
+// byte in its top bits. The picture is taken from RAM whenever the host
+// wants one, there's no beam or timing to it.
+//
+// The registers are a small block of their own:
+//
+//   +0  palette index
+//   +1  red    +2  green    +3  blue, writing blue sets the entry and
+//                               moves the index on to the next
+//   +4  bits per pixel, read only
+//   +5  width, low then high, read only
+//   +7  height, low then high, read only
+//
+// Reading +1-+3 gives the entry at the index. Until they're set, the
+// colours are black and white at 1 bit, the 16 CGA colours at 4, and
+// 3 bits of red, 3 of green and 2 of blue at 8.
+
+pub const REGISTERS_SIZE: u16 = 9;
+
+const CGA: [u32; 16] = [
+    0x000000, 0x0000AA, 0x00AA00, 0x00AAAA, 0xAA0000, 0xAA00AA, 0xAA5500, 0xAAAAAA,
+    0x555555, 0x5555FF, 0x55FF55, 0x55FFFF, 0xFF5555, 0xFF55FF, 0xFFFF55, 0xFFFFFF,
+];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FramebufferConfig {
+    pub base: u16,
+    pub width: u16,
+    pub height: u16,
+    pub bits_per_pixel: u8,
+    // Where the register block goes
+    pub registers: u16,
+}
+
+impl Default for FramebufferConfig {
+    fn default() -> Self {
+        FramebufferConfig { base: 0x2000, width: 128, height: 128, bits_per_pixel: 4, registers: 0xBE00 }
+    }
+}
+
+impl FramebufferConfig {
+    // "128x96x4", width by height by bits per pixel, at the default
+    // addresses
+    pub fn parse(text: &str) -> Option<FramebufferConfig> {
+        let mut parts = text.split('x').map(|part| part.trim().parse::<u16>().ok());
+        let (width, height, bits_per_pixel) = (parts.next()??, parts.next()??, parts.next()??);
+        if parts.next().is_some() {
+            return None;
+        }
+        Some(FramebufferConfig { width, height, bits_per_pixel: u8::try_from(bits_per_pixel).ok()?, ..FramebufferConfig::default() })
+    }
+
+    // Bytes of RAM the picture takes
+    pub fn size(&self) -> usize {
+        self.width as usize * self.height as usize * self.bits_per_pixel as usize / 8
+    }
+
+    fn check(&self) -> Result<(), EmuError> {
+        if ![1, 4, 8].contains(&self.bits_per_pixel) {
+            return Err(EmuError::BadImage(std::format!("a framebuffer has 1, 4 or 8 bits per pixel, not {}", self.bits_per_pixel)));
+        }
+        if self.width == 0 || self.height == 0 || self.width as usize * self.bits_per_pixel as usize % 8 != 0 {
+            return Err(EmuError::BadImage(std::format!("a {}x{} framebuffer doesn't make whole bytes a row", self.width, self.height)));
+        }
+        if self.base as usize + self.size() > 0x10000 {
+            return Err(EmuError::BadImage(std::format!("a {} byte framebuffer at ${:04x} runs past $ffff", self.size(), self.base)));
+        }
+        Ok(())
+    }
+}
+
+struct Registers {
+    palette: [u32; 256],
+    index: u8,
+    // Red and green, waiting for blue
+    staged: [u8; 2],
+}
+
+// The host's side: the picture, and the palette as the program has set it
+#[derive(Clone)]
+pub struct FramebufferPort {
+    config: FramebufferConfig,
+    registers: Arc<Mutex<Registers>>,
+}
+
+impl FramebufferPort {
+    fn registers(&self) -> MutexGuard<'_, Registers> {
+        self.registers.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    pub fn config(&self) -> FramebufferConfig {
+        self.config
+    }
+
+    pub fn palette(&self) -> [u32; 256] {
+        self.registers().palette
+    }
+
+    // The picture in RAM as it is now, width by height, 0x00RRGGBB
+    pub fn picture(&self, bus: &Bus) -> Vec<u32> {
+        let config = self.config;
+        let palette = self.palette();
+        let bits = config.bits_per_pixel as usize;
+        let per_byte = 8 / bits;
+        let mask = ((1u16 << bits) - 1) as u8;
+
+        let mut pixels = Vec::with_capacity(config.width as usize * config.height as usize);
+        for i in 0..config.width as usize * config.height as usize {
+            let byte = bus.peek_ram(config.base.wrapping_add((i / per_byte) as u16));
+            let shift = 8 - bits * (i % per_byte + 1);
+            pixels.push(palette[((byte >> shift) & mask) as usize]);
+        }
+        pixels
+    }
+}
+
+pub struct LinearFramebuffer {
+    port: FramebufferPort,
+}
+
+impl LinearFramebuffer {
+    pub fn new(config: FramebufferConfig) -> Result<Self, EmuError> {
+        config.check()?;
+        let mut palette = [0; 256];
+        match config.bits_per_pixel {
+            1 => palette[1] = 0xFFFFFF,
+            4 => palette[..16].copy_from_slice(&CGA),
+            _ => {
+                for (i, colour) in palette.iter_mut().enumerate() {
+                    let (red, green, blue) = ((i >> 5) & 7, (i >> 2) & 7, i & 3);
+                    *colour = (((red * 255 / 7) << 16) | ((green * 255 / 7) << 8) | (blue * 255 / 3)) as u32;
+                }
+            }
+        }
+        let registers = Registers { palette, index: 0, staged: [0; 2] };
+        Ok(LinearFramebuffer { port: FramebufferPort { config, registers: Arc::new(Mutex::new(registers)) } })
+    }
+
+    pub fn port(&self) -> FramebufferPort {
+        self.port.clone()
+    }
+}
+
+impl BusDevice for LinearFramebuffer {
+    fn read(&mut self, offset: u16) -> u8 {
+        self.peek(offset)
+    }
+
+    fn peek(&self, offset: u16) -> u8 {
+        let config = self.port.config;
+        let registers = self.port.registers();
+        let colour = registers.palette[registers.index as usize];
+        match offset {
+            0 => registers.index,
+            1..=3 => (colour >> (8 * (3 - offset))) as u8,
+            4 => config.bits_per_pixel,
+            5 => config.width as u8,
+            6 => (config.width >> 8) as u8,
+            7 => config.height as u8,
+            8 => (config.height >> 8) as u8,
+            _ => 0,
+        }
+    }
+
+    fn write(&mut self, offset: u16, data: u8) {
+        let mut registers = self.port.registers();
+        match offset {
+            0 => registers.index = data,
+            1 | 2 => registers.staged[offset as usize - 1] = data,
+            3 => {
+                let [red, green] = registers.staged;
+                let index = registers.index as usize;
+                registers.palette[index] = ((red as u32) << 16) | ((green as u32) << 8) | data as u32;
+                registers.index = registers.index.wrapping_add(1);
+            }
+            _ => {}
+        }
+    }
+
+    fn as_any(&self) -> Option<&dyn Any> {
+        Some(self)
+    }
+}
+
+impl cpu6502 {
+    // Maps a framebuffer's registers, the pixels being in RAM already, and
+    // returns the host's end of it
+    pub fn attach_framebuffer(&mut self, config: FramebufferConfig) -> Result<FramebufferPort, EmuError> {
+        let framebuffer = LinearFramebuffer::new(config)?;
+        let port = framebuffer.port();
+        let mut bus = self.bus.borrow_mut();
+        bus.unmap("framebuffer");
+        bus.map("framebuffer", config.registers, config.registers.wrapping_add(REGISTERS_SIZE - 1), Box::new(framebuffer));
+        Ok(port)
+    }
+
+    // The framebuffer attached last, if one still is
+    pub fn linear_framebuffer(&self) -> Option<FramebufferPort> {
+        let bus = self.bus.borrow();
+        let mapping = bus.mappings().find(|mapping| mapping.name == "framebuffer")?;
+        let device = mapping.device();
+        device.as_any()?.downcast_ref::<LinearFramebuffer>().map(LinearFramebuffer::port)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_and_checks_configs() {
+        let config = FramebufferConfig::parse("128x96x4").unwrap();
+        assert_eq!((config.width, config.height, config.bits_per_pixel, config.size()), (128, 96, 4, 6144));
+        assert_eq!(FramebufferConfig::parse("128x96"), None);
+        assert_eq!(FramebufferConfig::parse("128x96x4x1"), None);
+
+        assert!(LinearFramebuffer::new(FramebufferConfig { bits_per_pixel: 2, ..config }).is_err());
+        assert!(LinearFramebuffer::new(FramebufferConfig { width: 3, bits_per_pixel: 1, ..config }).is_err());
+        assert!(LinearFramebuffer::new(FramebufferConfig { base: 0xF000, ..config }).is_err());
+    }
+
+    #[test]
+    fn draws_from_ram_through_the_palette() {
+        let mut cpu = cpu6502::new();
+        let config = FramebufferConfig { base: 0x3000, width: 8, height: 2, bits_per_pixel: 4, registers: 0xBE00 };
+        cpu.attach_framebuffer(config).unwrap();
+
+        // Entry 5 made orange by the program, then two rows of pixels
+        let mut bus = cpu.bus.borrow_mut();
+        for (addr, data) in [(0xBE00, 0x05), (0xBE01, 0xFF), (0xBE02, 0x80), (0xBE03, 0x00), (0xBE00, 0x05)] {
+            bus.write(addr, data);
+        }
+        assert_eq!((bus.read(0xBE01, true), bus.read(0xBE04, true), bus.read(0xBE05, true)), (0xFF, 4, 8));
+        bus.write(0x3000, 0x5F);
+        bus.write(0x3004, 0x01);
+        drop(bus);
+
+        let port = cpu.linear_framebuffer().unwrap();
+        let picture = port.picture(&cpu.bus.borrow());
+        assert_eq!(picture.len(), 16);
+        assert_eq!(&picture[..2], &[0xFF8000, 0xFFFFFF]);
+        assert_eq!((picture[2], picture[8], picture[9]), (0x000000, 0x000000, 0x0000AA));
+
+        // Each pixel a bit at 1 bpp, and 3-3-2 colour at 8
+        let mono = LinearFramebuffer::new(FramebufferConfig { bits_per_pixel: 1, ..config }).unwrap().port();
+        cpu.bus.borrow_mut().write(0x3000, 0x81);
+        let picture = mono.picture(&cpu.bus.borrow());
+        assert_eq!(&picture[..8], &[0xFFFFFF, 0, 0, 0, 0, 0, 0, 0xFFFFFF]);
+        let rgb = LinearFramebuffer::new(FramebufferConfig { bits_per_pixel: 8, ..config }).unwrap().port();
+        assert_eq!(rgb.picture(&cpu.bus.borrow())[0], 0x910055);
+    }
+}
@@ -0,0 +1,491 @@
+use std::fs;
+use std::path::Path;
+
+use crate::atari2600::Banking;
+use crate::cartridge::{Cartridge, CartridgeHeader, MapperRegistry};
+use crate::cpu6502;
+use crate::d64::{self, D64};
+use crate::error::EmuError;
+use crate::romdb::RomHashes;
+
+// Program images the emulator can load, told apart by their first bytes
+// and falling back on the file extension.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    // Bytes to copy as they are, to an origin given by the caller
+    Raw,
+    // Commodore style, a little endian load address then the bytes
+    Prg,
+    INes,
+    // An Atari 2600 cartridge, which takes over the whole address space
+    Atari2600,
+    IntelHex,
+    Srec,
+    // A 1541 disk, whose first program is loaded and which is then left
+    // in the drive for it to LOAD from
+    D64,
+}
+
+impl Format {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Format::Raw => "raw binary",
+            Format::Prg => "PRG",
+            Format::INes => "iNES",
+            Format::Atari2600 => "Atari 2600 cartridge",
+            Format::IntelHex => "Intel HEX",
+            Format::Srec => "S-record",
+            Format::D64 => "D64 disk image",
+        }
+    }
+
+    pub fn detect(path: &Path, bytes: &[u8]) -> Format {
+        let extension = path.extension().and_then(|e| e.to_str()).unwrap_or("").to_ascii_lowercase();
+
+        if bytes.starts_with(b"NES\x1A") {
+            Format::INes
+        } else if bytes.first() == Some(&b':') || matches!(extension.as_str(), "hex" | "ihex" | "ihx") {
+            Format::IntelHex
+        } else if bytes.len() >= 2 && bytes[0] == b'S' && bytes[1].is_ascii_digit() || matches!(extension.as_str(), "srec" | "s19" | "s28" | "s37" | "mot") {
+            Format::Srec
+        } else if extension == "d64" {
+            Format::D64
+        } else if extension == "a26" {
+            Format::Atari2600
+        } else if extension == "prg" {
+            Format::Prg
+        } else {
+            Format::Raw
+        }
+    }
+}
+
+// An inclusive range of addresses the loader wrote
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Region {
+    pub start: u16,
+    pub end: u16,
+}
+
+impl Region {
+    pub fn len(&self) -> usize {
+        (self.end - self.start) as usize + 1
+    }
+
+    pub fn contains(&self, addr: u16) -> bool {
+        self.start <= addr && addr <= self.end
+    }
+}
+
+// What a load did, for the CLI and debugger to show
+#[derive(Debug, Clone, PartialEq)]
+pub struct LoadReport {
+    pub format: Format,
+    pub regions: Vec<Region>,
+    // The start address the file gives, else the reset vector if the file
+    // covers it, else where the first byte went
+    pub entry: u16,
+    // Anything else worth knowing about the image, a line each
+    pub details: Vec<String>,
+}
+
+impl LoadReport {
+    // Whether the image brought its own reset vector
+    pub fn sets_reset_vector(&self) -> bool {
+        self.regions.iter().any(|region| region.contains(0xFFFC) && region.contains(0xFFFD))
+    }
+
+    // "Intel HEX, 2 regions, entry $8000" then a line per region
+    pub fn describe(&self) -> Vec<String> {
+        let plural = if self.regions.len() == 1 { "" } else { "s" };
+        let mut lines = vec![std::format!("{}, {} region{}, entry ${:04x}", self.format.name(), self.regions.len(), plural, self.entry)];
+
+        for region in &self.regions {
+            lines.push(std::format!("  ${:04x}-${:04x} {} bytes", region.start, region.end, region.len()));
+        }
+        lines.extend(self.details.iter().cloned());
+
+        lines
+    }
+}
+
+impl cpu6502 {
+    // Loads a program image of any supported format. Raw binaries go to
+    // `org`, which they can't do without.
+    pub fn load_any<P: AsRef<Path>>(&mut self, path: P, org: Option<u16>) -> Result<LoadReport, EmuError> {
+        let path = path.as_ref();
+        let bytes = fs::read(path).map_err(|e| EmuError::io(path, e))?;
+
+        let mut report = self.load_bytes(Format::detect(path, &bytes), &bytes, org)?;
+
+        // Battery RAM lives in a .sav beside the ROM
+        if report.format == Format::INes {
+            if let Some(cart) = self.bus.borrow_mut().cartridge_mut() {
+                let save = Cartridge::save_path_for(path);
+                cart.attach_save(&save).map_err(|e| EmuError::io(&save, e))?;
+                if cart.has_battery() {
+                    report.details.push(std::format!("battery RAM kept in {}", save.display()));
+                }
+            }
+        }
+
+        Ok(report)
+    }
+
+    pub fn load_bytes(&mut self, format: Format, bytes: &[u8], org: Option<u16>) -> Result<LoadReport, EmuError> {
+        let (chunks, start) = match format {
+            Format::Raw => {
+                let org = org.ok_or_else(|| EmuError::BadImage("a raw binary needs an origin".to_string()))?;
+                (vec![(org as u32, bytes.to_vec())], None)
+            }
+            Format::Prg => {
+                if bytes.len() < 2 {
+                    return Err(EmuError::BadImage("PRG file has no load address".to_string()));
+                }
+                let addr = bytes[0] as u16 | (bytes[1] as u16) << 8;
+                (vec![(org.unwrap_or(addr) as u32, bytes[2..].to_vec())], None)
+            }
+            Format::INes => {
+                // A header the ROM database knows better is put right
+                // before the cartridge is built from it
+                let mut header = CartridgeHeader::parse(bytes)?;
+                let hashes = RomHashes::of_ines(bytes, &header);
+                let mut details = Vec::new();
+                match self.rom_database().lookup(&hashes) {
+                    Some(entry) => {
+                        let changes = entry.apply(&mut header);
+                        details.push(std::format!("{}, \"{}\" in the ROM database", hashes.describe(), entry.name));
+                        if !changes.is_empty() {
+                            details.push(std::format!("header corrected: {}", changes.join(", ")));
+                        }
+                    }
+                    None => details.push(std::format!("{}, not in the ROM database", hashes.describe())),
+                }
+                let cart = Cartridge::from_header(header, bytes, &MapperRegistry::new())?;
+                details.insert(0, describe_header(cart.header()));
+                self.bus.borrow_mut().insert_cartridge(cart);
+
+                let regions = vec![Region { start: 0x8000, end: 0xFFFF }];
+                let bus = self.bus.borrow();
+                let entry = bus.read(0xFFFC, true) as u16 | (bus.read(0xFFFD, true) as u16) << 8;
+                return Ok(LoadReport { format, regions, entry, details });
+            }
+            Format::Atari2600 => {
+                self.attach_atari2600(bytes)?;
+                let banking = Banking::for_size(bytes.len()).unwrap_or(Banking::None);
+                let details = vec![std::format!("{}K, {}", bytes.len() / 1024, banking.name())];
+
+                let regions = vec![Region { start: 0xF000, end: 0xFFFF }];
+                let bus = self.bus.borrow();
+                let entry = bus.read(0xFFFC, true) as u16 | (bus.read(0xFFFD, true) as u16) << 8;
+                return Ok(LoadReport { format, regions, entry, details });
+            }
+            Format::D64 => {
+                let disk = D64::from_bytes(bytes)?;
+                let entry = disk.find(b"*")?.ok_or_else(|| EmuError::BadImage("no program on the disk".to_string()))?;
+                let file = disk.read_file(&entry)?;
+
+                let mut report = self.load_bytes(Format::Prg, &file, org)?;
+                report.format = format;
+                report.details.push(std::format!("disk \"{}\", loaded \"{}\"", disk.disk_name(), entry.display_name()));
+                if let Some(sys) = d64::sys_address(&file).filter(|_| org.is_none()) {
+                    report.entry = sys;
+                    report.details.push(std::format!("started at SYS {}", sys));
+                }
+                self.insert_disk(disk);
+                return Ok(report);
+            }
+            Format::IntelHex => parse_intel_hex(&text(bytes)?).map_err(EmuError::BadImage)?,
+            Format::Srec => parse_srec(&text(bytes)?).map_err(EmuError::BadImage)?,
+        };
+
+        // Check everything fits, and has somewhere to go, before writing
+        // anything
+        for (addr, data) in &chunks {
+            if *addr as usize + data.len() > 0x10000 {
+                return Err(EmuError::BadImage(std::format!("data at ${:x} runs past $ffff", addr)));
+            }
+
+            let bus = self.bus.borrow();
+            if let Some(lost) = (0..data.len() as u32).map(|i| (addr + i) as u16).find(|&addr| bus.goes_nowhere(addr)) {
+                return Err(EmuError::UnmappedAddress(lost));
+            }
+        }
+
+        let mut regions: Vec<Region> = Vec::new();
+        for (addr, data) in chunks.iter().filter(|(_, data)| !data.is_empty()) {
+            let start = *addr as u16;
+            self.load_program(data, start);
+
+            let end = start + (data.len() - 1) as u16;
+            match regions.last_mut() {
+                Some(last) if last.end as u32 + 1 == *addr => last.end = end,
+                _ => regions.push(Region { start, end }),
+            }
+        }
+
+        let mut report = LoadReport { format, regions, entry: 0, details: Vec::new() };
+        report.entry = match start {
+            Some(start) => start,
+            None if report.sets_reset_vector() => {
+                let bus = self.bus.borrow();
+                bus.read(0xFFFC, true) as u16 | (bus.read(0xFFFD, true) as u16) << 8
+            }
+            None => report.regions.first().map_or(0, |region| region.start),
+        };
+
+        Ok(report)
+    }
+}
+
+// "NES 2.0, mapper 1.0, PAL, 256K PRG, CHR RAM, 8K PRG RAM (battery)"
+fn describe_header(header: &CartridgeHeader) -> String {
+    let mut parts = vec![
+        if header.nes2 { "NES 2.0" } else { "iNES" }.to_string(),
+        std::format!("mapper {}.{}", header.mapper_id, header.submapper),
+        header.region.name().to_string(),
+        std::format!("{}K PRG", header.prg_rom_size / 1024),
+    ];
+
+    if header.chr_rom_size > 0 {
+        parts.push(std::format!("{}K CHR", header.chr_rom_size / 1024));
+    } else {
+        parts.push("CHR RAM".to_string());
+    }
+
+    let prg_ram = header.prg_ram_size.max(header.prg_nvram_size);
+    if prg_ram > 0 {
+        let battery = if header.battery { " (battery)" } else { "" };
+        parts.push(std::format!("{}K PRG RAM{}", (prg_ram / 1024).max(1), battery));
+    }
+
+    parts.join(", ")
+}
+
+fn text(bytes: &[u8]) -> Result<String, EmuError> {
+    String::from_utf8(bytes.to_vec()).map_err(|_| EmuError::BadImage("not a text file".to_string()))
+}
+
+// The bytes of a hex record after its start character
+fn record_bytes(hex: &str, line: usize) -> Result<Vec<u8>, String> {
+    if hex.is_empty() || hex.len() % 2 != 0 || !hex.is_ascii() {
+        return Err(std::format!("line {}: bad hex", line));
+    }
+    crate::decode_hex(hex).map_err(|_| std::format!("line {}: bad hex", line))
+}
+
+// Data records with their addresses and the start address, if one is given
+type Chunks = (Vec<(u32, Vec<u8>)>, Option<u16>);
+
+// ":LLAAAATT<data>CC", the checksum makes the bytes sum to zero
+fn parse_intel_hex(text: &str) -> Result<Chunks, String> {
+    let mut chunks = Vec::new();
+    let mut start = None;
+    let mut base: u32 = 0;
+
+    for (i, line) in text.lines().enumerate().map(|(i, line)| (i + 1, line.trim())) {
+        if line.is_empty() {
+            continue;
+        }
+
+        let hex = line.strip_prefix(':').ok_or_else(|| std::format!("line {}: expected ':'", i))?;
+        let bytes = record_bytes(hex, i)?;
+        if bytes.len() < 5 || bytes.len() != bytes[0] as usize + 5 {
+            return Err(std::format!("line {}: wrong length", i));
+        }
+        if bytes.iter().fold(0u8, |sum, b| sum.wrapping_add(*b)) != 0 {
+            return Err(std::format!("line {}: bad checksum", i));
+        }
+
+        let addr = (bytes[1] as u32) << 8 | bytes[2] as u32;
+        let data = &bytes[4..bytes.len() - 1];
+
+        match bytes[3] {
+            0x00 => chunks.push((base + addr, data.to_vec())),
+            0x01 => break,
+            // Extended segment and linear addresses, only useful here when
+            // they leave us inside 64K
+            0x02 if data.len() == 2 => base = ((data[0] as u32) << 8 | data[1] as u32) << 4,
+            0x04 if data.len() == 2 => base = ((data[0] as u32) << 8 | data[1] as u32) << 16,
+            // Start segment (CS:IP) and start linear address
+            0x03 if data.len() == 4 => start = Some((((data[0] as u32) << 8 | data[1] as u32) * 16 + ((data[2] as u32) << 8 | data[3] as u32)) as u16),
+            0x05 if data.len() == 4 => start = Some(((data[2] as u16) << 8) | data[3] as u16),
+            kind => return Err(std::format!("line {}: bad record type {:02x}", i, kind)),
+        }
+    }
+
+    Ok((chunks, start))
+}
+
+// "S<type><count><address><data><checksum>", the count covers everything
+// after it and the checksum is the ones' complement of their sum
+fn parse_srec(text: &str) -> Result<Chunks, String> {
+    let mut chunks = Vec::new();
+    let mut start = None;
+
+    for (i, line) in text.lines().enumerate().map(|(i, line)| (i + 1, line.trim())) {
+        if line.is_empty() {
+            continue;
+        }
+
+        let rest = line.strip_prefix('S').ok_or_else(|| std::format!("line {}: expected 'S'", i))?;
+        let kind = rest.chars().next().ok_or_else(|| std::format!("line {}: no record type", i))?;
+        let bytes = record_bytes(&rest[kind.len_utf8()..], i)?;
+        if bytes.len() != bytes[0] as usize + 1 {
+            return Err(std::format!("line {}: wrong length", i));
+        }
+        if bytes.iter().fold(0u8, |sum, b| sum.wrapping_add(*b)) != 0xFF {
+            return Err(std::format!("line {}: bad checksum", i));
+        }
+
+        let address_len = match kind {
+            '0' | '1' | '5' | '9' => 2,
+            '2' | '6' | '8' => 3,
+            '3' | '7' => 4,
+            _ => return Err(std::format!("line {}: bad record type S{}", i, kind)),
+        };
+        if bytes.len() < address_len + 2 {
+            return Err(std::format!("line {}: wrong length", i));
+        }
+
+        let addr = bytes[1..=address_len].iter().fold(0u32, |addr, b| addr << 8 | *b as u32);
+        let data = &bytes[address_len + 1..bytes.len() - 1];
+
+        match kind {
+            '1' | '2' | '3' => chunks.push((addr, data.to_vec())),
+            '7' | '8' | '9' if addr <= 0xFFFF => start = Some(addr as u16),
+            '7' | '8' | '9' => return Err(std::format!("line {}: start address ${:x} is past $ffff", i, addr)),
+            // Header and record counts
+            _ => {}
+        }
+    }
+
+    Ok((chunks, start))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read(cpu: &cpu6502, addr: u16, len: u16) -> Vec<u8> {
+        (0..len).map(|i| cpu.bus.borrow().read(addr + i, true)).collect()
+    }
+
+    #[test]
+    fn formats_are_detected() {
+        assert_eq!(Format::detect(Path::new("game.nes"), b"NES\x1A\x02"), Format::INes);
+        assert_eq!(Format::detect(Path::new("a.txt"), b":10000000"), Format::IntelHex);
+        assert_eq!(Format::detect(Path::new("a.hex"), b""), Format::IntelHex);
+        assert_eq!(Format::detect(Path::new("a"), b"S00600004844521B"), Format::Srec);
+        assert_eq!(Format::detect(Path::new("demo.PRG"), &[0x01, 0x08]), Format::Prg);
+        assert_eq!(Format::detect(Path::new("combat.a26"), &[0x78, 0xD8]), Format::Atari2600);
+        assert_eq!(Format::detect(Path::new("rom.bin"), &[0xA9, 0x00]), Format::Raw);
+    }
+
+    #[test]
+    fn raw_and_prg() {
+        let mut cpu = cpu6502::new();
+        assert!(cpu.load_bytes(Format::Raw, &[0xEA], None).is_err());
+
+        let report = cpu.load_bytes(Format::Raw, &[0xA9, 0x01, 0xEA], Some(0x8000)).unwrap();
+        assert_eq!(report, LoadReport { format: Format::Raw, regions: vec![Region { start: 0x8000, end: 0x8002 }], entry: 0x8000, details: Vec::new() });
+        assert_eq!(read(&cpu, 0x8000, 3), vec![0xA9, 0x01, 0xEA]);
+
+        let report = cpu.load_bytes(Format::Prg, &[0x01, 0x08, 0x0B, 0x08], None).unwrap();
+        assert_eq!((report.regions[0], report.entry), (Region { start: 0x0801, end: 0x0802 }, 0x0801));
+
+        assert!(cpu.load_bytes(Format::Raw, &[0; 0x10], Some(0xFFF8)).is_err());
+        assert!(cpu.load_bytes(Format::Prg, &[0x01], None).is_err());
+    }
+
+    #[test]
+    fn images_with_their_own_vectors() {
+        let mut cpu = cpu6502::new();
+        let mut rom = vec![0xEA; 0x4000];
+        rom[0x3FFC] = 0x34;
+        rom[0x3FFD] = 0xC0;
+
+        let report = cpu.load_bytes(Format::Raw, &rom, Some(0xC000)).unwrap();
+        assert!(report.sets_reset_vector());
+        assert_eq!(report.entry, 0xC034);
+    }
+
+    #[test]
+    fn intel_hex() {
+        let mut cpu = cpu6502::new();
+        let text = "\
+            :03800000A901EAE9\n\
+            :02800300E8E8AB\n\
+            :028010006060AE\n\
+            :040000050000800077\n\
+            :00000001FF\n";
+
+        let report = cpu.load_bytes(Format::IntelHex, text.as_bytes(), None).unwrap();
+        assert_eq!(report.regions, vec![Region { start: 0x8000, end: 0x8004 }, Region { start: 0x8010, end: 0x8011 }]);
+        assert_eq!(report.entry, 0x8000);
+        assert_eq!(read(&cpu, 0x8000, 5), vec![0xA9, 0x01, 0xEA, 0xE8, 0xE8]);
+        assert_eq!(report.describe(), vec![
+            "Intel HEX, 2 regions, entry $8000",
+            "  $8000-$8004 5 bytes",
+            "  $8010-$8011 2 bytes",
+        ]);
+
+        assert!(cpu.load_bytes(Format::IntelHex, b":03800000A901EAE8\n", None).unwrap_err().to_string().contains("checksum"));
+        assert!(cpu.load_bytes(Format::IntelHex, b":020000040001F9\n:01000000EA15\n", None).unwrap_err().to_string().contains("past $ffff"));
+    }
+
+    #[test]
+    fn s_records() {
+        let mut cpu = cpu6502::new();
+        let text = "S00600004844521B\nS1068000A901EAE5\nS5030001FB\nS9039000 6C\n".replace(' ', "");
+
+        let report = cpu.load_bytes(Format::Srec, text.as_bytes(), None).unwrap();
+        assert_eq!(report.regions, vec![Region { start: 0x8000, end: 0x8002 }]);
+        assert_eq!(report.entry, 0x9000);
+        assert_eq!(read(&cpu, 0x8000, 3), vec![0xA9, 0x01, 0xEA]);
+
+        assert!(cpu.load_bytes(Format::Srec, b"S1068000A901EAE6\n", None).unwrap_err().to_string().contains("checksum"));
+        assert!(cpu.load_bytes(Format::Srec, b"S4030000FC\n", None).is_err());
+        assert!(cpu.load_bytes(Format::Srec, b"S1068000A901EAE\n", None).unwrap_err().to_string().contains("bad hex"));
+    }
+
+    #[test]
+    fn refuses_to_load_where_nothing_answers() {
+        let mut cpu = cpu6502::new();
+        cpu.bus.borrow_mut().set_unmapped(0x4000, 0x7FFF);
+
+        let err = cpu.load_bytes(Format::Raw, &[0xEA, 0xEA, 0xEA], Some(0x3FFE)).unwrap_err();
+        assert!(matches!(err, EmuError::UnmappedAddress(0x4000)));
+        // Nothing was written
+        assert_eq!(read(&cpu, 0x3FFE, 2), vec![0x00, 0x00]);
+    }
+
+    #[test]
+    fn disk_images() {
+        // 10 SYS 2061, then INC $D020 / RTS
+        let prg = [0x01, 0x08, 0x0C, 0x08, 0x0A, 0x00, 0x9E, b'2', b'0', b'6', b'1', 0x00, 0x00, 0x00, 0xEE, 0x20, 0xD0, 0x60];
+        let image = crate::d64::tests::disk(&[("DEMO", &prg)]);
+
+        let mut cpu = cpu6502::new();
+        assert_eq!(Format::detect(Path::new("demo.d64"), &image), Format::D64);
+        let report = cpu.load_bytes(Format::D64, &image, None).unwrap();
+        assert_eq!(report.regions, vec![Region { start: 0x0801, end: 0x0810 }]);
+        assert_eq!(report.entry, 2061);
+        assert_eq!(report.details, ["disk \"TEST\", loaded \"DEMO\"", "started at SYS 2061"]);
+        assert_eq!(read(&cpu, 0x080D, 3), [0xEE, 0x20, 0xD0]);
+        assert!(cpu.kernal_shim().is_some());
+    }
+
+    #[test]
+    fn files_by_path() {
+        let path = std::env::temp_dir().join(std::format!("crust-loader-{}.prg", std::process::id()));
+        fs::write(&path, [0x00, 0xC0, 0x60]).unwrap();
+
+        let mut cpu = cpu6502::new();
+        let report = cpu.load_any(&path, None).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!((report.format, report.entry), (Format::Prg, 0xC000));
+        assert!(matches!(cpu.load_any(&path, None), Err(EmuError::Io { .. })));
+    }
+}
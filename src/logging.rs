@@ -0,0 +1,54 @@
+// Structured log events through the `tracing` crate with the "tracing"
+// feature, and compiled away without it, so a library user only hears
+// from the emulator by installing a subscriber. The emulator binary's
+// goes to stderr, filtered by RUST_LOG, e.g. RUST_LOG=crust_6502::cpu=trace
+// for every instruction.
+//
+// Targets are crust_6502::cpu for instructions (TRACE), interrupts and
+// resets (DEBUG), and crust_6502::diagnostics for diagnostics as they are
+// reported.
+
+macro_rules! log_trace {
+    ($($arg:tt)*) => {{
+        #[cfg(feature = "tracing")]
+        {
+            tracing::trace!($($arg)*);
+        }
+    }};
+}
+
+macro_rules! log_debug {
+    ($($arg:tt)*) => {{
+        #[cfg(feature = "tracing")]
+        {
+            tracing::debug!($($arg)*);
+        }
+    }};
+}
+
+macro_rules! log_info {
+    ($($arg:tt)*) => {{
+        #[cfg(feature = "tracing")]
+        {
+            tracing::info!($($arg)*);
+        }
+    }};
+}
+
+macro_rules! log_warn {
+    ($($arg:tt)*) => {{
+        #[cfg(feature = "tracing")]
+        {
+            tracing::warn!($($arg)*);
+        }
+    }};
+}
+
+macro_rules! log_error {
+    ($($arg:tt)*) => {{
+        #[cfg(feature = "tracing")]
+        {
+            tracing::error!($($arg)*);
+        }
+    }};
+}
@@ -0,0 +1,174 @@
+use crate::error::EmuError;
+use crate::{cpu6502, Bus, SharedBus};
+
+struct CpuSlot {
+    cpu: cpu6502,
+    // The CPU gets `numerator` clocks for every `denominator` master clock
+    // ticks, so 3:2 pairings work as well as plain dividers
+    numerator: u32,
+    denominator: u32,
+    // Fractional clocks owed to the CPU, always below `denominator`
+    owed: u32,
+}
+
+// A set of CPUs sharing one bus, e.g. a main CPU plus a 6502-based sound
+// coprocessor. Each CPU runs off the master clock at its own ratio and the
+// machine interleaves them one master tick at a time, in the order they
+// were added.
+pub struct Machine {
+    pub bus: SharedBus,
+    cpus: Vec<CpuSlot>,
+    master_clock: u64,
+}
+
+impl Machine {
+    pub fn new() -> Self {
+        Machine::with_bus(SharedBus::new(Bus::new()))
+    }
+
+    pub fn with_bus(bus: SharedBus) -> Self {
+        Machine {
+            bus,
+            cpus: Vec::new(),
+            master_clock: 0,
+        }
+    }
+
+    // A machine around a CPU that's already set up, on its bus and clocked
+    // 1:1, for hosts that load a program first and then want frames
+    pub fn from_cpu(cpu: cpu6502) -> Self {
+        let mut machine = Machine::with_bus(cpu.bus.clone());
+        machine.cpus.push(CpuSlot { cpu, numerator: 1, denominator: 1, owed: 0 });
+        machine
+    }
+
+    // Attach a new CPU to the bus and return its index. It is clocked
+    // `numerator` times per `denominator` master ticks, 1:2 runs it at
+    // half speed and 3:2 gives it three clocks for every two ticks.
+    pub fn add_cpu(&mut self, numerator: u32, denominator: u32) -> usize {
+        let mut cpu = cpu6502::new();
+        cpu.connect_bus(self.bus.clone());
+
+        self.cpus.push(CpuSlot {
+            cpu,
+            numerator,
+            denominator: denominator.max(1),
+            owed: 0,
+        });
+
+        self.cpus.len() - 1
+    }
+
+    pub fn cpu(&self, index: usize) -> &cpu6502 {
+        &self.cpus[index].cpu
+    }
+
+    pub fn cpu_mut(&mut self, index: usize) -> &mut cpu6502 {
+        &mut self.cpus[index].cpu
+    }
+
+    pub fn cpu_count(&self) -> usize {
+        self.cpus.len()
+    }
+
+    pub fn set_ratio(&mut self, index: usize, numerator: u32, denominator: u32) -> Result<(), EmuError> {
+        let slot = self.cpus.get_mut(index).ok_or(EmuError::NoSuchCpu(index))?;
+        slot.numerator = numerator;
+        slot.denominator = denominator.max(1);
+        slot.owed = 0;
+        Ok(())
+    }
+
+    pub fn ratio(&self, index: usize) -> (u32, u32) {
+        let slot = &self.cpus[index];
+        (slot.numerator, slot.denominator)
+    }
+
+    pub fn master_clock(&self) -> u64 {
+        self.master_clock
+    }
+
+    pub fn reset(&mut self) {
+        for slot in &mut self.cpus {
+            slot.cpu.reset();
+        }
+    }
+
+    // Advance the master clock by one tick, clocking every CPU as many
+    // times as it is owed
+    pub fn clock(&mut self) {
+        for slot in &mut self.cpus {
+            slot.owed += slot.numerator;
+
+            while slot.owed >= slot.denominator {
+                slot.cpu.clock();
+                slot.owed -= slot.denominator;
+            }
+        }
+
+        self.master_clock += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn load(machine: &Machine, addr: u16, program: &[u8]) {
+        let mut bus = machine.bus.borrow_mut();
+        for (i, byte) in program.iter().enumerate() {
+            bus.write(addr + i as u16, *byte);
+        }
+    }
+
+    #[test]
+    fn ratios_share_out_clocks() {
+        let mut machine = Machine::new();
+        let main = machine.add_cpu(1, 1);
+        let half = machine.add_cpu(1, 2);
+        let fast = machine.add_cpu(3, 2);
+
+        for _ in 0..100 {
+            machine.clock();
+        }
+
+        assert_eq!(machine.master_clock(), 100);
+        assert_eq!(machine.cpu(main).clock_count, 100);
+        assert_eq!(machine.cpu(half).clock_count, 50);
+        assert_eq!(machine.cpu(fast).clock_count, 150);
+        assert_eq!(machine.ratio(fast), (3, 2));
+
+        machine.set_ratio(half, 1, 4).unwrap();
+        assert_eq!(machine.ratio(half), (1, 4));
+        assert!(matches!(machine.set_ratio(3, 1, 1), Err(EmuError::NoSuchCpu(3))));
+    }
+
+    #[test]
+    fn cpus_talk_through_the_shared_bus() {
+        let mut machine = Machine::new();
+
+        // Writer at $8000 waits a while, then posts $9010 to the mailbox:
+        // LDX #$20 / DEX / BNE -3 / LDA #$10 / STA $0200 / JMP $800b
+        load(&machine, 0x8000, &[0xA2, 0x20, 0xCA, 0xD0, 0xFD, 0xA9, 0x10, 0x8D, 0x00, 0x02, 0x4C, 0x0A, 0x80]);
+        // Reader at $9000 jumps through the mailbox until it changes:
+        // JMP ($0200), then at $9010 LDX #$01 / STX $0300 / JMP $9015
+        load(&machine, 0x9000, &[0x6C, 0x00, 0x02]);
+        load(&machine, 0x9010, &[0xA2, 0x01, 0x8E, 0x00, 0x03, 0x4C, 0x15, 0x90]);
+        load(&machine, 0x0200, &[0x00, 0x90]);
+
+        let writer = machine.add_cpu(3, 2);
+        let reader = machine.add_cpu(1, 2);
+        machine.reset();
+        machine.cpu_mut(writer).pc = 0x8000;
+        machine.cpu_mut(reader).pc = 0x9000;
+
+        for _ in 0..1000 {
+            machine.clock();
+        }
+
+        assert_eq!(machine.bus.borrow().read(0x0200, true), 0x10);
+        assert_eq!(machine.bus.borrow().read(0x0300, true), 0x01);
+        assert_eq!(machine.cpu(writer).pc, 0x800A);
+        assert_eq!(machine.cpu(reader).pc, 0x9015);
+    }
+}
@@ -1,3131 +1,955 @@
-use std::cell::{RefCell, RefMut};
-use std::collections::{Bound, BTreeMap, HashMap};
-use std::num::ParseIntError;
-use std::ops::BitOr;
+use std::cell::RefCell;
+use std::path::{Path, PathBuf};
 use std::rc::Rc;
-use crate::FLAGS6502::B;
-use std::fmt::{Debug, LowerHex, Write};
-use minifb::{Key, KeyRepeat, Window, WindowOptions};
+use std::time::{Duration, Instant};
+
+use minifb::{Key, KeyRepeat, MouseButton, MouseMode, Window, WindowOptions};
+
+use crust_6502_emulator::accesslog::AccessLog;
+use crust_6502_emulator::annotations::{self, Annotations};
+use crust_6502_emulator::c64::C64Roms;
+use crust_6502_emulator::charset::Charset;
+use crust_6502_emulator::codeview::CodeView;
+use crust_6502_emulator::console::Console;
+use crust_6502_emulator::crash::CrashReport;
+use crust_6502_emulator::debugger::Debugger;
+use crust_6502_emulator::demos::{self, Demo};
+use crust_6502_emulator::diagnostics::Severity;
+use crust_6502_emulator::disasm::DisasmFormat;
+use crust_6502_emulator::frame::KEYBOARD_ADDR;
+use crust_6502_emulator::gui::{draw_calls, draw_cfg, draw_chr, draw_code, draw_console, draw_cpu, draw_device_log, clear_area, draw_patches, draw_perf, draw_picture, draw_ram, draw_stack, draw_stats, draw_vectors, draw_zero_page, ConsoleInput, DetachedPane, Detachable, StatusText, ZeroPageView, HEIGHT, WIDTH};
+use crust_6502_emulator::keymatrix::KeyLayout;
+use crust_6502_emulator::linearfb::FramebufferConfig;
+use crust_6502_emulator::memview::{Field, MemoryView};
+use crust_6502_emulator::mos::{OsEntries, OsShim};
+use crust_6502_emulator::optable::OpTableFormat;
+use crust_6502_emulator::patch::Patch;
+use crust_6502_emulator::perf::PerfCounters;
+use crust_6502_emulator::raminit::RamInit;
+use crust_6502_emulator::profiler::{format_report, ProfileSort};
+use crust_6502_emulator::project::Project;
+use crust_6502_emulator::selftest;
+use crust_6502_emulator::replay::{Recording, Stimulus};
+use crust_6502_emulator::symbols::SymbolTable;
+use crust_6502_emulator::tia::FRAME_WIDTH;
+use crust_6502_emulator::timeline;
+use crust_6502_emulator::trace::{self, TraceFormat};
+use crust_6502_emulator::traps::Traps;
+use crust_6502_emulator::vectors::Vector;
+use crust_6502_emulator::vicii;
+use crust_6502_emulator::watch::FileWatcher;
+use crust_6502_emulator::{cpu6502, Variant, CYCLES_PER_FRAME};
+
+// Battery RAM goes out to the .sav this often as well as on exit, so a
+// crash loses at most a few seconds of it
+const SAVE_FLUSH_FRAMES: u32 = 300;
+
+// How often the program file is checked for a rebuild, twice a second
+const RELOAD_POLL_FRAMES: u32 = 30;
+
+// How often the screen is drawn, ~60 fps, and so in turbo how much of the
+// time isn't spent emulating
+const REDRAW_INTERVAL: Duration = Duration::from_micros(16600);
+
+// Where a crash report goes when there's no program file to put it beside
+const CRASH_FILE: &str = "crash.txt";
+
+// Top left of the calls pane, which clicks are worked out against
+const CALLS_PANE: (usize, usize) = (2, 182);
+
+// And the vectors pane, in the same place
+const VECTORS_PANE: (usize, usize) = (2, 182);
+
+// Bytes a row in the RAM pane, half what the detached window shows so the
+// characters fit beside them
+const RAM_COLUMNS: u32 = 8;
 
-#[macro_use(concat_string)]
-extern crate concat_string;
+fn main() {
+    // "selftest" checks the build without opening a window
+    if std::env::args().nth(1).as_deref() == Some("selftest") {
+        let report = selftest::run();
+        for line in report.describe() {
+            println!("{}", line);
+        }
+        std::process::exit(if report.passed() { 0 } else { 1 });
+    }
 
-type RamArray = [u8; 64 * 1024];
+    // RUST_LOG picks what's logged, nothing by default
+    #[cfg(feature = "tracing")]
+    tracing_subscriber::fmt().with_env_filter(tracing_subscriber::EnvFilter::from_default_env()).with_writer(std::io::stderr).init();
 
-struct Bus {
-    ram: RamArray,
-}
+    let mut cpu = cpu6502::new();
 
-impl Bus {
-    fn new() -> Self {
-        return Bus {
-            ram: [0; 64 * 1024],
-        };
-    }
+    let mut value = 0;
 
-    fn write(&mut self, addr: u16, data: u8) {
-        if addr >= 0x0000 && addr <= 0xFFFF {
-            self.ram[addr as usize] = data;
-        }
+    while value <= 0xFFFF {
+        value += 1;
     }
 
-    fn read(&self, addr: u16, read_only: bool) -> u8 {
-        if addr >= 0x0000 && addr <= 0xFFFF {
-            // let v = self.ram.get(addr).expect("Failed to read value from array").collect();
-            return self.ram[addr as usize];
+    let mut rom_path = None;
+    let mut symbol_path = None;
+    let mut record_path = None;
+    let mut replay_path = None;
+    let mut notes_path = None;
+    let mut org = None;
+    let mut print_stats = false;
+    let mut chrome_trace_path = None;
+    let mut access_log_path = None;
+    let mut device_log_path = None;
+    let mut detach = Vec::new();
+    let mut access_log_range = (0x0000, 0xFFFF);
+    let mut trace_target = None;
+    let mut trace_format = TraceFormat::Nestest;
+    let mut traps = Traps::default();
+    let mut demo = &demos::DEMOS[0];
+    let mut keyboard_layout = None;
+    let mut charset = Charset::default();
+    let mut keyboard_addr = 0xDC00;
+    let mut c64_roms = None;
+    let mut host_fs = None;
+    let mut host_fs_addr = 0xBF00;
+    let mut cycle_counter = None;
+    let mut framebuffer_config = None;
+    let mut framebuffer_base = None;
+    let mut framebuffer_registers = None;
+    let mut dump_optable = None;
+    let mut disassemble = None;
+    let mut patches = Vec::new();
+    let mut disasm_format = DisasmFormat::Text;
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--symbols" => symbol_path = args.next(),
+            "--romdb" => {
+                let path = args.next().expect("--romdb takes a DAT or nes20db.xml");
+                cpu.rom_database_mut().load_file(&path).unwrap_or_else(|e| panic!("--romdb: {}", e));
+            }
+            "--record" => record_path = args.next(),
+            "--replay" => replay_path = args.next(),
+            "--notes" => notes_path = args.next().map(std::path::PathBuf::from),
+            "--shadow-stack" => cpu.enable_shadow_stack(),
+            "--stack-checks" => cpu.enable_stack_checks(),
+            "--stats" => print_stats = true,
+            "--chrome-trace" => chrome_trace_path = args.next(),
+            "--access-log" => access_log_path = args.next(),
+            "--device-log" => device_log_path = args.next(),
+            "--detach" => {
+                for name in args.next().unwrap_or_default().split(',') {
+                    detach.push(Detachable::parse(name).unwrap_or_else(|| panic!("--detach takes ram and code, not '{}'", name)));
+                }
+            }
+            "--access-log-range" => {
+                let range = args.next().unwrap_or_default();
+                access_log_range = annotations::parse_range(&range).unwrap_or_else(|| panic!("--access-log-range takes <start>-<end>, not '{}'", range));
+            }
+            "--trace" => trace_target = args.next(),
+            "--trace-ring" => cpu.set_trace_ring(Some(args.next().and_then(|n| n.parse().ok()).expect("--trace-ring takes a count"))),
+            "--trace-format" => trace_format = TraceFormat::parse(args.next().unwrap_or_default().as_str()).expect("bad --trace-format"),
+            "--65c02" => cpu.set_variant(Variant::Cmos65C02),
+            "--org" => org = args.next().map(|s| hex_addr(&s, "--org")),
+            "--char-out" => traps.char_out = args.next().map(|s| hex_addr(&s, "--char-out")),
+            "--exit-trap" => traps.exit = args.next().map(|s| hex_addr(&s, "--exit-trap")),
+            "--demo" => {
+                let name = args.next().unwrap_or_default();
+                demo = demos::find(&name).unwrap_or_else(|| panic!("no demo called '{}', try {}", name, demos::names().join(", ")));
+            }
+            "--bbc-os" => cpu.set_os_shim(Some(OsShim::new(OsEntries::default()))),
+            "--keyboard" => keyboard_layout = args.next(),
+            "--c64" => c64_roms = args.next(),
+            "--keyboard-at" => keyboard_addr = args.next().map_or(keyboard_addr, |s| hex_addr(&s, "--keyboard-at")),
+            "--host-fs" => host_fs = args.next(),
+            "--host-fs-at" => host_fs_addr = args.next().map_or(host_fs_addr, |s| hex_addr(&s, "--host-fs-at")),
+            "--framebuffer" => {
+                let size = args.next().unwrap_or_default();
+                framebuffer_config = Some(FramebufferConfig::parse(&size).unwrap_or_else(|| panic!("--framebuffer takes <width>x<height>x<bpp>, not '{}'", size)));
+            }
+            "--cycle-counter-at" => cycle_counter = args.next().map(|s| hex_addr(&s, "--cycle-counter-at")),
+            "--framebuffer-at" => framebuffer_base = args.next().map(|s| hex_addr(&s, "--framebuffer-at")),
+            "--framebuffer-regs-at" => framebuffer_registers = args.next().map(|s| hex_addr(&s, "--framebuffer-regs-at")),
+            "--charset" => charset = Charset::parse(&args.next().unwrap_or_default()).unwrap_or_else(|e| panic!("--charset: {}", e)),
+            "--ram-init" => {
+                let pattern = RamInit::parse(&args.next().unwrap_or_default()).unwrap_or_else(|e| panic!("--ram-init: {}", e));
+                cpu.bus.borrow_mut().fill_ram(pattern);
+            }
+            "--ram" => {
+                let size = args.next().and_then(|size| parse_size(&size)).expect("--ram takes a size, 32K or a byte count");
+                cpu.bus.borrow_mut().limit_ram(size);
+            }
+            "--dump-optable" => {
+                let format = args.next().unwrap_or_default();
+                dump_optable = Some(OpTableFormat::parse(&format).unwrap_or_else(|| panic!("--dump-optable takes md or csv, not '{}'", format)));
+            }
+            "--patch" => {
+                let patch = args.next().unwrap_or_default();
+                patches.push(Patch::parse(&patch).unwrap_or_else(|e| panic!("--patch: {}", e)));
+            }
+            "--disassemble" => {
+                let range = args.next().unwrap_or_default();
+                disassemble = Some(annotations::parse_range(&range).unwrap_or_else(|| panic!("--disassemble takes <start>-<end>, not '{}'", range)));
+            }
+            "--format" => {
+                let format = args.next().unwrap_or_default();
+                disasm_format = DisasmFormat::parse(&format).unwrap_or_else(|| panic!("--format takes text or json, not '{}'", format));
+            }
+            _ => rom_path = Some(arg),
         }
-
-        return 0x00;
     }
-}
-
-#[derive(Debug)]
-#[repr(u8)]
-enum FLAGS6502 {
-    C = (1 << 0),
-    // Carry Bit
-    Z = (1 << 1),
-    // Zero
-    I = (1 << 2),
-    // Disable Interrupts
-    D = (1 << 3),
-    // Decimal Mode (unused in this implementation)
-    B = (1 << 4),
-    // Break
-    U = (1 << 5),
-    // Unused
-    V = (1 << 6),
-    // Overflow
-    N = (1 << 7), // Negative
-}
-
-type OperateFn = fn(&mut cpu6502) -> u8;
-type AddrModeFn = OperateFn;
-
-struct INSTRUCTION {
-    pub name: String,
-    pub operate: OperateFn,
-    pub addr_mode: AddrModeFn,
-    pub cycles: u8,
-}
 
-struct cpu6502 {
-    a: u8,
-    // Accumulator Register
-    x: u8,
-    // X Register
-    y: u8,
-    // Y Register
-    stkp: u8,
-    // Stack Pointer (points to location on bus)
-    pc: u16,
-    // Program Counter
-    status: u8,
-    // Status Register
-    fetched: u8,
-    addr_abs: u16,
-    addr_rel: u16,
-    opcode: u8,
-    cycles: u8,
-    lookup: Vec<INSTRUCTION>,
-    bus: Bus,
-    clock_count: u32,
-    temp: u16,
-}
+    // Prints the opcode table, after --65c02 if that was given, and stops
+    if let Some(format) = dump_optable {
+        print!("{}", cpu.dump_optable(format));
+        return;
+    }
 
-type cpu = cpu6502;
-
-impl cpu6502 {
-    fn new() -> Self {
-        let lookup: Vec<INSTRUCTION> = vec![
-            INSTRUCTION {
-                name: "BRK".to_string(),
-                operate: cpu::BRK,
-                addr_mode: cpu::IMM,
-                cycles: 7,
-            },
-            INSTRUCTION {
-                name: "ORA".to_string(),
-                operate: cpu::ORA,
-                addr_mode: cpu::IZX,
-                cycles: 6,
-            },
-            INSTRUCTION {
-                name: "???".to_string(),
-                operate: cpu::XXX,
-                addr_mode: cpu::IMP,
-                cycles: 2,
-            },
-            INSTRUCTION {
-                name: "???".to_string(),
-                operate: cpu::XXX,
-                addr_mode: cpu::IMP,
-                cycles: 8,
-            },
-            INSTRUCTION {
-                name: "???".to_string(),
-                operate: cpu::NOP,
-                addr_mode: cpu::IMP,
-                cycles: 3,
-            },
-            INSTRUCTION {
-                name: "ORA".to_string(),
-                operate: cpu::ORA,
-                addr_mode: cpu::ZP0,
-                cycles: 3,
-            },
-            INSTRUCTION {
-                name: "ASL".to_string(),
-                operate: cpu::ASL,
-                addr_mode: cpu::ZP0,
-                cycles: 5,
-            },
-            INSTRUCTION {
-                name: "???".to_string(),
-                operate: cpu::XXX,
-                addr_mode: cpu::IMP,
-                cycles: 5,
-            },
-            INSTRUCTION {
-                name: "PHP".to_string(),
-                operate: cpu::PHP,
-                addr_mode: cpu::IMP,
-                cycles: 3,
-            },
-            INSTRUCTION {
-                name: "ORA".to_string(),
-                operate: cpu::ORA,
-                addr_mode: cpu::IMM,
-                cycles: 2,
-            },
-            INSTRUCTION {
-                name: "ASL".to_string(),
-                operate: cpu::ASL,
-                addr_mode: cpu::IMP,
-                cycles: 2,
-            },
-            INSTRUCTION {
-                name: "???".to_string(),
-                operate: cpu::XXX,
-                addr_mode: cpu::IMP,
-                cycles: 2,
-            },
-            INSTRUCTION {
-                name: "???".to_string(),
-                operate: cpu::NOP,
-                addr_mode: cpu::IMP,
-                cycles: 4,
-            },
-            INSTRUCTION {
-                name: "ORA".to_string(),
-                operate: cpu::ORA,
-                addr_mode: cpu::ABS,
-                cycles: 4,
-            },
-            INSTRUCTION {
-                name: "ASL".to_string(),
-                operate: cpu::ASL,
-                addr_mode: cpu::ABS,
-                cycles: 6,
-            },
-            INSTRUCTION {
-                name: "???".to_string(),
-                operate: cpu::XXX,
-                addr_mode: cpu::IMP,
-                cycles: 6,
-            },
-            INSTRUCTION {
-                name: "BPL".to_string(),
-                operate: cpu::BPL,
-                addr_mode: cpu::REL,
-                cycles: 2,
-            },
-            INSTRUCTION {
-                name: "ORA".to_string(),
-                operate: cpu::ORA,
-                addr_mode: cpu::IZY,
-                cycles: 5,
-            },
-            INSTRUCTION {
-                name: "???".to_string(),
-                operate: cpu::XXX,
-                addr_mode: cpu::IMP,
-                cycles: 2,
-            },
-            INSTRUCTION {
-                name: "???".to_string(),
-                operate: cpu::XXX,
-                addr_mode: cpu::IMP,
-                cycles: 8,
-            },
-            INSTRUCTION {
-                name: "???".to_string(),
-                operate: cpu::NOP,
-                addr_mode: cpu::IMP,
-                cycles: 4,
-            },
-            INSTRUCTION {
-                name: "ORA".to_string(),
-                operate: cpu::ORA,
-                addr_mode: cpu::ZPX,
-                cycles: 4,
-            },
-            INSTRUCTION {
-                name: "ASL".to_string(),
-                operate: cpu::ASL,
-                addr_mode: cpu::ZPX,
-                cycles: 6,
-            },
-            INSTRUCTION {
-                name: "???".to_string(),
-                operate: cpu::XXX,
-                addr_mode: cpu::IMP,
-                cycles: 6,
-            },
-            INSTRUCTION {
-                name: "CLC".to_string(),
-                operate: cpu::CLC,
-                addr_mode: cpu::IMP,
-                cycles: 2,
-            },
-            INSTRUCTION {
-                name: "ORA".to_string(),
-                operate: cpu::ORA,
-                addr_mode: cpu::ABY,
-                cycles: 4,
-            },
-            INSTRUCTION {
-                name: "???".to_string(),
-                operate: cpu::NOP,
-                addr_mode: cpu::IMP,
-                cycles: 2,
-            },
-            INSTRUCTION {
-                name: "???".to_string(),
-                operate: cpu::XXX,
-                addr_mode: cpu::IMP,
-                cycles: 7,
-            },
-            INSTRUCTION {
-                name: "???".to_string(),
-                operate: cpu::NOP,
-                addr_mode: cpu::IMP,
-                cycles: 4,
-            },
-            INSTRUCTION {
-                name: "ORA".to_string(),
-                operate: cpu::ORA,
-                addr_mode: cpu::ABX,
-                cycles: 4,
-            },
-            INSTRUCTION {
-                name: "ASL".to_string(),
-                operate: cpu::ASL,
-                addr_mode: cpu::ABX,
-                cycles: 7,
-            },
-            INSTRUCTION {
-                name: "???".to_string(),
-                operate: cpu::XXX,
-                addr_mode: cpu::IMP,
-                cycles: 7,
-            },
-            INSTRUCTION {
-                name: "JSR".to_string(),
-                operate: cpu::JSR,
-                addr_mode: cpu::ABS,
-                cycles: 6,
-            },
-            INSTRUCTION {
-                name: "AND".to_string(),
-                operate: cpu::AND,
-                addr_mode: cpu::IZX,
-                cycles: 6,
-            },
-            INSTRUCTION {
-                name: "???".to_string(),
-                operate: cpu::XXX,
-                addr_mode: cpu::IMP,
-                cycles: 2,
-            },
-            INSTRUCTION {
-                name: "???".to_string(),
-                operate: cpu::XXX,
-                addr_mode: cpu::IMP,
-                cycles: 8,
-            },
-            INSTRUCTION {
-                name: "BIT".to_string(),
-                operate: cpu::BIT,
-                addr_mode: cpu::ZP0,
-                cycles: 3,
-            },
-            INSTRUCTION {
-                name: "AND".to_string(),
-                operate: cpu::AND,
-                addr_mode: cpu::ZP0,
-                cycles: 3,
-            },
-            INSTRUCTION {
-                name: "ROL".to_string(),
-                operate: cpu::ROL,
-                addr_mode: cpu::ZP0,
-                cycles: 5,
-            },
-            INSTRUCTION {
-                name: "???".to_string(),
-                operate: cpu::XXX,
-                addr_mode: cpu::IMP,
-                cycles: 5,
-            },
-            INSTRUCTION {
-                name: "PLP".to_string(),
-                operate: cpu::PLP,
-                addr_mode: cpu::IMP,
-                cycles: 4,
-            },
-            INSTRUCTION {
-                name: "AND".to_string(),
-                operate: cpu::AND,
-                addr_mode: cpu::IMM,
-                cycles: 2,
-            },
-            INSTRUCTION {
-                name: "ROL".to_string(),
-                operate: cpu::ROL,
-                addr_mode: cpu::IMP,
-                cycles: 2,
-            },
-            INSTRUCTION {
-                name: "???".to_string(),
-                operate: cpu::XXX,
-                addr_mode: cpu::IMP,
-                cycles: 2,
-            },
-            INSTRUCTION {
-                name: "BIT".to_string(),
-                operate: cpu::BIT,
-                addr_mode: cpu::ABS,
-                cycles: 4,
-            },
-            INSTRUCTION {
-                name: "AND".to_string(),
-                operate: cpu::AND,
-                addr_mode: cpu::ABS,
-                cycles: 4,
-            },
-            INSTRUCTION {
-                name: "ROL".to_string(),
-                operate: cpu::ROL,
-                addr_mode: cpu::ABS,
-                cycles: 6,
-            },
-            INSTRUCTION {
-                name: "???".to_string(),
-                operate: cpu::XXX,
-                addr_mode: cpu::IMP,
-                cycles: 6,
-            },
-            INSTRUCTION {
-                name: "BMI".to_string(),
-                operate: cpu::BMI,
-                addr_mode: cpu::REL,
-                cycles: 2,
-            },
-            INSTRUCTION {
-                name: "AND".to_string(),
-                operate: cpu::AND,
-                addr_mode: cpu::IZY,
-                cycles: 5,
-            },
-            INSTRUCTION {
-                name: "???".to_string(),
-                operate: cpu::XXX,
-                addr_mode: cpu::IMP,
-                cycles: 2,
-            },
-            INSTRUCTION {
-                name: "???".to_string(),
-                operate: cpu::XXX,
-                addr_mode: cpu::IMP,
-                cycles: 8,
-            },
-            INSTRUCTION {
-                name: "???".to_string(),
-                operate: cpu::NOP,
-                addr_mode: cpu::IMP,
-                cycles: 4,
-            },
-            INSTRUCTION {
-                name: "AND".to_string(),
-                operate: cpu::AND,
-                addr_mode: cpu::ZPX,
-                cycles: 4,
-            },
-            INSTRUCTION {
-                name: "ROL".to_string(),
-                operate: cpu::ROL,
-                addr_mode: cpu::ZPX,
-                cycles: 6,
-            },
-            INSTRUCTION {
-                name: "???".to_string(),
-                operate: cpu::XXX,
-                addr_mode: cpu::IMP,
-                cycles: 6,
-            },
-            INSTRUCTION {
-                name: "SEC".to_string(),
-                operate: cpu::SEC,
-                addr_mode: cpu::IMP,
-                cycles: 2,
-            },
-            INSTRUCTION {
-                name: "AND".to_string(),
-                operate: cpu::AND,
-                addr_mode: cpu::ABY,
-                cycles: 4,
-            },
-            INSTRUCTION {
-                name: "???".to_string(),
-                operate: cpu::NOP,
-                addr_mode: cpu::IMP,
-                cycles: 2,
-            },
-            INSTRUCTION {
-                name: "???".to_string(),
-                operate: cpu::XXX,
-                addr_mode: cpu::IMP,
-                cycles: 7,
-            },
-            INSTRUCTION {
-                name: "???".to_string(),
-                operate: cpu::NOP,
-                addr_mode: cpu::IMP,
-                cycles: 4,
-            },
-            INSTRUCTION {
-                name: "AND".to_string(),
-                operate: cpu::AND,
-                addr_mode: cpu::ABX,
-                cycles: 4,
-            },
-            INSTRUCTION {
-                name: "ROL".to_string(),
-                operate: cpu::ROL,
-                addr_mode: cpu::ABX,
-                cycles: 7,
-            },
-            INSTRUCTION {
-                name: "???".to_string(),
-                operate: cpu::XXX,
-                addr_mode: cpu::IMP,
-                cycles: 7,
-            },
-            INSTRUCTION {
-                name: "RTI".to_string(),
-                operate: cpu::RTI,
-                addr_mode: cpu::IMP,
-                cycles: 6,
-            },
-            INSTRUCTION {
-                name: "EOR".to_string(),
-                operate: cpu::EOR,
-                addr_mode: cpu::IZX,
-                cycles: 6,
-            },
-            INSTRUCTION {
-                name: "???".to_string(),
-                operate: cpu::XXX,
-                addr_mode: cpu::IMP,
-                cycles: 2,
-            },
-            INSTRUCTION {
-                name: "???".to_string(),
-                operate: cpu::XXX,
-                addr_mode: cpu::IMP,
-                cycles: 8,
-            },
-            INSTRUCTION {
-                name: "???".to_string(),
-                operate: cpu::NOP,
-                addr_mode: cpu::IMP,
-                cycles: 3,
-            },
-            INSTRUCTION {
-                name: "EOR".to_string(),
-                operate: cpu::EOR,
-                addr_mode: cpu::ZP0,
-                cycles: 3,
-            },
-            INSTRUCTION {
-                name: "LSR".to_string(),
-                operate: cpu::LSR,
-                addr_mode: cpu::ZP0,
-                cycles: 5,
-            },
-            INSTRUCTION {
-                name: "???".to_string(),
-                operate: cpu::XXX,
-                addr_mode: cpu::IMP,
-                cycles: 5,
-            },
-            INSTRUCTION {
-                name: "PHA".to_string(),
-                operate: cpu::PHA,
-                addr_mode: cpu::IMP,
-                cycles: 3,
-            },
-            INSTRUCTION {
-                name: "EOR".to_string(),
-                operate: cpu::EOR,
-                addr_mode: cpu::IMM,
-                cycles: 2,
-            },
-            INSTRUCTION {
-                name: "LSR".to_string(),
-                operate: cpu::LSR,
-                addr_mode: cpu::IMP,
-                cycles: 2,
-            },
-            INSTRUCTION {
-                name: "???".to_string(),
-                operate: cpu::XXX,
-                addr_mode: cpu::IMP,
-                cycles: 2,
-            },
-            INSTRUCTION {
-                name: "JMP".to_string(),
-                operate: cpu::JMP,
-                addr_mode: cpu::ABS,
-                cycles: 3,
-            },
-            INSTRUCTION {
-                name: "EOR".to_string(),
-                operate: cpu::EOR,
-                addr_mode: cpu::ABS,
-                cycles: 4,
-            },
-            INSTRUCTION {
-                name: "LSR".to_string(),
-                operate: cpu::LSR,
-                addr_mode: cpu::ABS,
-                cycles: 6,
-            },
-            INSTRUCTION {
-                name: "???".to_string(),
-                operate: cpu::XXX,
-                addr_mode: cpu::IMP,
-                cycles: 6,
-            },
-            INSTRUCTION {
-                name: "BVC".to_string(),
-                operate: cpu::BVC,
-                addr_mode: cpu::REL,
-                cycles: 2,
-            },
-            INSTRUCTION {
-                name: "EOR".to_string(),
-                operate: cpu::EOR,
-                addr_mode: cpu::IZY,
-                cycles: 5,
-            },
-            INSTRUCTION {
-                name: "???".to_string(),
-                operate: cpu::XXX,
-                addr_mode: cpu::IMP,
-                cycles: 2,
-            },
-            INSTRUCTION {
-                name: "???".to_string(),
-                operate: cpu::XXX,
-                addr_mode: cpu::IMP,
-                cycles: 8,
-            },
-            INSTRUCTION {
-                name: "???".to_string(),
-                operate: cpu::NOP,
-                addr_mode: cpu::IMP,
-                cycles: 4,
-            },
-            INSTRUCTION {
-                name: "EOR".to_string(),
-                operate: cpu::EOR,
-                addr_mode: cpu::ZPX,
-                cycles: 4,
-            },
-            INSTRUCTION {
-                name: "LSR".to_string(),
-                operate: cpu::LSR,
-                addr_mode: cpu::ZPX,
-                cycles: 6,
-            },
-            INSTRUCTION {
-                name: "???".to_string(),
-                operate: cpu::XXX,
-                addr_mode: cpu::IMP,
-                cycles: 6,
-            },
-            INSTRUCTION {
-                name: "CLI".to_string(),
-                operate: cpu::CLI,
-                addr_mode: cpu::IMP,
-                cycles: 2,
-            },
-            INSTRUCTION {
-                name: "EOR".to_string(),
-                operate: cpu::EOR,
-                addr_mode: cpu::ABY,
-                cycles: 4,
-            },
-            INSTRUCTION {
-                name: "???".to_string(),
-                operate: cpu::NOP,
-                addr_mode: cpu::IMP,
-                cycles: 2,
-            },
-            INSTRUCTION {
-                name: "???".to_string(),
-                operate: cpu::XXX,
-                addr_mode: cpu::IMP,
-                cycles: 7,
-            },
-            INSTRUCTION {
-                name: "???".to_string(),
-                operate: cpu::NOP,
-                addr_mode: cpu::IMP,
-                cycles: 4,
-            },
-            INSTRUCTION {
-                name: "EOR".to_string(),
-                operate: cpu::EOR,
-                addr_mode: cpu::ABX,
-                cycles: 4,
-            },
-            INSTRUCTION {
-                name: "LSR".to_string(),
-                operate: cpu::LSR,
-                addr_mode: cpu::ABX,
-                cycles: 7,
-            },
-            INSTRUCTION {
-                name: "???".to_string(),
-                operate: cpu::XXX,
-                addr_mode: cpu::IMP,
-                cycles: 7,
-            },
-            INSTRUCTION {
-                name: "RTS".to_string(),
-                operate: cpu::RTS,
-                addr_mode: cpu::IMP,
-                cycles: 6,
-            },
-            INSTRUCTION {
-                name: "ADC".to_string(),
-                operate: cpu::ADC,
-                addr_mode: cpu::IZX,
-                cycles: 6,
-            },
-            INSTRUCTION {
-                name: "???".to_string(),
-                operate: cpu::XXX,
-                addr_mode: cpu::IMP,
-                cycles: 2,
-            },
-            INSTRUCTION {
-                name: "???".to_string(),
-                operate: cpu::XXX,
-                addr_mode: cpu::IMP,
-                cycles: 8,
-            },
-            INSTRUCTION {
-                name: "???".to_string(),
-                operate: cpu::NOP,
-                addr_mode: cpu::IMP,
-                cycles: 3,
-            },
-            INSTRUCTION {
-                name: "ADC".to_string(),
-                operate: cpu::ADC,
-                addr_mode: cpu::ZP0,
-                cycles: 3,
-            },
-            INSTRUCTION {
-                name: "ROR".to_string(),
-                operate: cpu::ROR,
-                addr_mode: cpu::ZP0,
-                cycles: 5,
-            },
-            INSTRUCTION {
-                name: "???".to_string(),
-                operate: cpu::XXX,
-                addr_mode: cpu::IMP,
-                cycles: 5,
-            },
-            INSTRUCTION {
-                name: "PLA".to_string(),
-                operate: cpu::PLA,
-                addr_mode: cpu::IMP,
-                cycles: 4,
-            },
-            INSTRUCTION {
-                name: "ADC".to_string(),
-                operate: cpu::ADC,
-                addr_mode: cpu::IMM,
-                cycles: 2,
-            },
-            INSTRUCTION {
-                name: "ROR".to_string(),
-                operate: cpu::ROR,
-                addr_mode: cpu::IMP,
-                cycles: 2,
-            },
-            INSTRUCTION {
-                name: "???".to_string(),
-                operate: cpu::XXX,
-                addr_mode: cpu::IMP,
-                cycles: 2,
-            },
-            INSTRUCTION {
-                name: "JMP".to_string(),
-                operate: cpu::JMP,
-                addr_mode: cpu::IND,
-                cycles: 5,
-            },
-            INSTRUCTION {
-                name: "ADC".to_string(),
-                operate: cpu::ADC,
-                addr_mode: cpu::ABS,
-                cycles: 4,
-            },
-            INSTRUCTION {
-                name: "ROR".to_string(),
-                operate: cpu::ROR,
-                addr_mode: cpu::ABS,
-                cycles: 6,
-            },
-            INSTRUCTION {
-                name: "???".to_string(),
-                operate: cpu::XXX,
-                addr_mode: cpu::IMP,
-                cycles: 6,
-            },
-            INSTRUCTION {
-                name: "BVS".to_string(),
-                operate: cpu::BVS,
-                addr_mode: cpu::REL,
-                cycles: 2,
-            },
-            INSTRUCTION {
-                name: "ADC".to_string(),
-                operate: cpu::ADC,
-                addr_mode: cpu::IZY,
-                cycles: 5,
-            },
-            INSTRUCTION {
-                name: "???".to_string(),
-                operate: cpu::XXX,
-                addr_mode: cpu::IMP,
-                cycles: 2,
-            },
-            INSTRUCTION {
-                name: "???".to_string(),
-                operate: cpu::XXX,
-                addr_mode: cpu::IMP,
-                cycles: 8,
-            },
-            INSTRUCTION {
-                name: "???".to_string(),
-                operate: cpu::NOP,
-                addr_mode: cpu::IMP,
-                cycles: 4,
-            },
-            INSTRUCTION {
-                name: "ADC".to_string(),
-                operate: cpu::ADC,
-                addr_mode: cpu::ZPX,
-                cycles: 4,
-            },
-            INSTRUCTION {
-                name: "ROR".to_string(),
-                operate: cpu::ROR,
-                addr_mode: cpu::ZPX,
-                cycles: 6,
-            },
-            INSTRUCTION {
-                name: "???".to_string(),
-                operate: cpu::XXX,
-                addr_mode: cpu::IMP,
-                cycles: 6,
-            },
-            INSTRUCTION {
-                name: "SEI".to_string(),
-                operate: cpu::SEI,
-                addr_mode: cpu::IMP,
-                cycles: 2,
-            },
-            INSTRUCTION {
-                name: "ADC".to_string(),
-                operate: cpu::ADC,
-                addr_mode: cpu::ABY,
-                cycles: 4,
-            },
-            INSTRUCTION {
-                name: "???".to_string(),
-                operate: cpu::NOP,
-                addr_mode: cpu::IMP,
-                cycles: 2,
-            },
-            INSTRUCTION {
-                name: "???".to_string(),
-                operate: cpu::XXX,
-                addr_mode: cpu::IMP,
-                cycles: 7,
-            },
-            INSTRUCTION {
-                name: "???".to_string(),
-                operate: cpu::NOP,
-                addr_mode: cpu::IMP,
-                cycles: 4,
-            },
-            INSTRUCTION {
-                name: "ADC".to_string(),
-                operate: cpu::ADC,
-                addr_mode: cpu::ABX,
-                cycles: 4,
-            },
-            INSTRUCTION {
-                name: "ROR".to_string(),
-                operate: cpu::ROR,
-                addr_mode: cpu::ABX,
-                cycles: 7,
-            },
-            INSTRUCTION {
-                name: "???".to_string(),
-                operate: cpu::XXX,
-                addr_mode: cpu::IMP,
-                cycles: 7,
-            },
-            INSTRUCTION {
-                name: "???".to_string(),
-                operate: cpu::NOP,
-                addr_mode: cpu::IMP,
-                cycles: 2,
-            },
-            INSTRUCTION {
-                name: "STA".to_string(),
-                operate: cpu::STA,
-                addr_mode: cpu::IZX,
-                cycles: 6,
-            },
-            INSTRUCTION {
-                name: "???".to_string(),
-                operate: cpu::NOP,
-                addr_mode: cpu::IMP,
-                cycles: 2,
-            },
-            INSTRUCTION {
-                name: "???".to_string(),
-                operate: cpu::XXX,
-                addr_mode: cpu::IMP,
-                cycles: 6,
-            },
-            INSTRUCTION {
-                name: "STY".to_string(),
-                operate: cpu::STY,
-                addr_mode: cpu::ZP0,
-                cycles: 3,
-            },
-            INSTRUCTION {
-                name: "STA".to_string(),
-                operate: cpu::STA,
-                addr_mode: cpu::ZP0,
-                cycles: 3,
-            },
-            INSTRUCTION {
-                name: "STX".to_string(),
-                operate: cpu::STX,
-                addr_mode: cpu::ZP0,
-                cycles: 3,
-            },
-            INSTRUCTION {
-                name: "???".to_string(),
-                operate: cpu::XXX,
-                addr_mode: cpu::IMP,
-                cycles: 3,
-            },
-            INSTRUCTION {
-                name: "DEY".to_string(),
-                operate: cpu::DEY,
-                addr_mode: cpu::IMP,
-                cycles: 2,
-            },
-            INSTRUCTION {
-                name: "???".to_string(),
-                operate: cpu::NOP,
-                addr_mode: cpu::IMP,
-                cycles: 2,
-            },
-            INSTRUCTION {
-                name: "TXA".to_string(),
-                operate: cpu::TXA,
-                addr_mode: cpu::IMP,
-                cycles: 2,
-            },
-            INSTRUCTION {
-                name: "???".to_string(),
-                operate: cpu::XXX,
-                addr_mode: cpu::IMP,
-                cycles: 2,
-            },
-            INSTRUCTION {
-                name: "STY".to_string(),
-                operate: cpu::STY,
-                addr_mode: cpu::ABS,
-                cycles: 4,
-            },
-            INSTRUCTION {
-                name: "STA".to_string(),
-                operate: cpu::STA,
-                addr_mode: cpu::ABS,
-                cycles: 4,
-            },
-            INSTRUCTION {
-                name: "STX".to_string(),
-                operate: cpu::STX,
-                addr_mode: cpu::ABS,
-                cycles: 4,
-            },
-            INSTRUCTION {
-                name: "???".to_string(),
-                operate: cpu::XXX,
-                addr_mode: cpu::IMP,
-                cycles: 4,
-            },
-            INSTRUCTION {
-                name: "BCC".to_string(),
-                operate: cpu::BCC,
-                addr_mode: cpu::REL,
-                cycles: 2,
-            },
-            INSTRUCTION {
-                name: "STA".to_string(),
-                operate: cpu::STA,
-                addr_mode: cpu::IZY,
-                cycles: 6,
-            },
-            INSTRUCTION {
-                name: "???".to_string(),
-                operate: cpu::XXX,
-                addr_mode: cpu::IMP,
-                cycles: 2,
-            },
-            INSTRUCTION {
-                name: "???".to_string(),
-                operate: cpu::XXX,
-                addr_mode: cpu::IMP,
-                cycles: 6,
-            },
-            INSTRUCTION {
-                name: "STY".to_string(),
-                operate: cpu::STY,
-                addr_mode: cpu::ZPX,
-                cycles: 4,
-            },
-            INSTRUCTION {
-                name: "STA".to_string(),
-                operate: cpu::STA,
-                addr_mode: cpu::ZPX,
-                cycles: 4,
-            },
-            INSTRUCTION {
-                name: "STX".to_string(),
-                operate: cpu::STX,
-                addr_mode: cpu::ZPY,
-                cycles: 4,
-            },
-            INSTRUCTION {
-                name: "???".to_string(),
-                operate: cpu::XXX,
-                addr_mode: cpu::IMP,
-                cycles: 4,
-            },
-            INSTRUCTION {
-                name: "TYA".to_string(),
-                operate: cpu::TYA,
-                addr_mode: cpu::IMP,
-                cycles: 2,
-            },
-            INSTRUCTION {
-                name: "STA".to_string(),
-                operate: cpu::STA,
-                addr_mode: cpu::ABY,
-                cycles: 5,
-            },
-            INSTRUCTION {
-                name: "TXS".to_string(),
-                operate: cpu::TXS,
-                addr_mode: cpu::IMP,
-                cycles: 2,
-            },
-            INSTRUCTION {
-                name: "???".to_string(),
-                operate: cpu::XXX,
-                addr_mode: cpu::IMP,
-                cycles: 5,
-            },
-            INSTRUCTION {
-                name: "???".to_string(),
-                operate: cpu::NOP,
-                addr_mode: cpu::IMP,
-                cycles: 5,
-            },
-            INSTRUCTION {
-                name: "STA".to_string(),
-                operate: cpu::STA,
-                addr_mode: cpu::ABX,
-                cycles: 5,
-            },
-            INSTRUCTION {
-                name: "???".to_string(),
-                operate: cpu::XXX,
-                addr_mode: cpu::IMP,
-                cycles: 5,
-            },
-            INSTRUCTION {
-                name: "???".to_string(),
-                operate: cpu::XXX,
-                addr_mode: cpu::IMP,
-                cycles: 5,
-            },
-            INSTRUCTION {
-                name: "LDY".to_string(),
-                operate: cpu::LDY,
-                addr_mode: cpu::IMM,
-                cycles: 2,
-            },
-            INSTRUCTION {
-                name: "LDA".to_string(),
-                operate: cpu::LDA,
-                addr_mode: cpu::IZX,
-                cycles: 6,
-            },
-            INSTRUCTION {
-                name: "LDX".to_string(),
-                operate: cpu::LDX,
-                addr_mode: cpu::IMM,
-                cycles: 2,
-            },
-            INSTRUCTION {
-                name: "???".to_string(),
-                operate: cpu::XXX,
-                addr_mode: cpu::IMP,
-                cycles: 6,
-            },
-            INSTRUCTION {
-                name: "LDY".to_string(),
-                operate: cpu::LDY,
-                addr_mode: cpu::ZP0,
-                cycles: 3,
-            },
-            INSTRUCTION {
-                name: "LDA".to_string(),
-                operate: cpu::LDA,
-                addr_mode: cpu::ZP0,
-                cycles: 3,
-            },
-            INSTRUCTION {
-                name: "LDX".to_string(),
-                operate: cpu::LDX,
-                addr_mode: cpu::ZP0,
-                cycles: 3,
-            },
-            INSTRUCTION {
-                name: "???".to_string(),
-                operate: cpu::XXX,
-                addr_mode: cpu::IMP,
-                cycles: 3,
-            },
-            INSTRUCTION {
-                name: "TAY".to_string(),
-                operate: cpu::TAY,
-                addr_mode: cpu::IMP,
-                cycles: 2,
-            },
-            INSTRUCTION {
-                name: "LDA".to_string(),
-                operate: cpu::LDA,
-                addr_mode: cpu::IMM,
-                cycles: 2,
-            },
-            INSTRUCTION {
-                name: "TAX".to_string(),
-                operate: cpu::TAX,
-                addr_mode: cpu::IMP,
-                cycles: 2,
-            },
-            INSTRUCTION {
-                name: "???".to_string(),
-                operate: cpu::XXX,
-                addr_mode: cpu::IMP,
-                cycles: 2,
-            },
-            INSTRUCTION {
-                name: "LDY".to_string(),
-                operate: cpu::LDY,
-                addr_mode: cpu::ABS,
-                cycles: 4,
-            },
-            INSTRUCTION {
-                name: "LDA".to_string(),
-                operate: cpu::LDA,
-                addr_mode: cpu::ABS,
-                cycles: 4,
-            },
-            INSTRUCTION {
-                name: "LDX".to_string(),
-                operate: cpu::LDX,
-                addr_mode: cpu::ABS,
-                cycles: 4,
-            },
-            INSTRUCTION {
-                name: "???".to_string(),
-                operate: cpu::XXX,
-                addr_mode: cpu::IMP,
-                cycles: 4,
-            },
-            INSTRUCTION {
-                name: "BCS".to_string(),
-                operate: cpu::BCS,
-                addr_mode: cpu::REL,
-                cycles: 2,
-            },
-            INSTRUCTION {
-                name: "LDA".to_string(),
-                operate: cpu::LDA,
-                addr_mode: cpu::IZY,
-                cycles: 5,
-            },
-            INSTRUCTION {
-                name: "???".to_string(),
-                operate: cpu::XXX,
-                addr_mode: cpu::IMP,
-                cycles: 2,
-            },
-            INSTRUCTION {
-                name: "???".to_string(),
-                operate: cpu::XXX,
-                addr_mode: cpu::IMP,
-                cycles: 5,
-            },
-            INSTRUCTION {
-                name: "LDY".to_string(),
-                operate: cpu::LDY,
-                addr_mode: cpu::ZPX,
-                cycles: 4,
-            },
-            INSTRUCTION {
-                name: "LDA".to_string(),
-                operate: cpu::LDA,
-                addr_mode: cpu::ZPX,
-                cycles: 4,
-            },
-            INSTRUCTION {
-                name: "LDX".to_string(),
-                operate: cpu::LDX,
-                addr_mode: cpu::ZPY,
-                cycles: 4,
-            },
-            INSTRUCTION {
-                name: "???".to_string(),
-                operate: cpu::XXX,
-                addr_mode: cpu::IMP,
-                cycles: 4,
-            },
-            INSTRUCTION {
-                name: "CLV".to_string(),
-                operate: cpu::CLV,
-                addr_mode: cpu::IMP,
-                cycles: 2,
-            },
-            INSTRUCTION {
-                name: "LDA".to_string(),
-                operate: cpu::LDA,
-                addr_mode: cpu::ABY,
-                cycles: 4,
-            },
-            INSTRUCTION {
-                name: "TSX".to_string(),
-                operate: cpu::TSX,
-                addr_mode: cpu::IMP,
-                cycles: 2,
-            },
-            INSTRUCTION {
-                name: "???".to_string(),
-                operate: cpu::XXX,
-                addr_mode: cpu::IMP,
-                cycles: 4,
-            },
-            INSTRUCTION {
-                name: "LDY".to_string(),
-                operate: cpu::LDY,
-                addr_mode: cpu::ABX,
-                cycles: 4,
-            },
-            INSTRUCTION {
-                name: "LDA".to_string(),
-                operate: cpu::LDA,
-                addr_mode: cpu::ABX,
-                cycles: 4,
-            },
-            INSTRUCTION {
-                name: "LDX".to_string(),
-                operate: cpu::LDX,
-                addr_mode: cpu::ABY,
-                cycles: 4,
-            },
-            INSTRUCTION {
-                name: "???".to_string(),
-                operate: cpu::XXX,
-                addr_mode: cpu::IMP,
-                cycles: 4,
-            },
-            INSTRUCTION {
-                name: "CPY".to_string(),
-                operate: cpu::CPY,
-                addr_mode: cpu::IMM,
-                cycles: 2,
-            },
-            INSTRUCTION {
-                name: "CMP".to_string(),
-                operate: cpu::CMP,
-                addr_mode: cpu::IZX,
-                cycles: 6,
-            },
-            INSTRUCTION {
-                name: "???".to_string(),
-                operate: cpu::NOP,
-                addr_mode: cpu::IMP,
-                cycles: 2,
-            },
-            INSTRUCTION {
-                name: "???".to_string(),
-                operate: cpu::XXX,
-                addr_mode: cpu::IMP,
-                cycles: 8,
-            },
-            INSTRUCTION {
-                name: "CPY".to_string(),
-                operate: cpu::CPY,
-                addr_mode: cpu::ZP0,
-                cycles: 3,
-            },
-            INSTRUCTION {
-                name: "CMP".to_string(),
-                operate: cpu::CMP,
-                addr_mode: cpu::ZP0,
-                cycles: 3,
-            },
-            INSTRUCTION {
-                name: "DEC".to_string(),
-                operate: cpu::DEC,
-                addr_mode: cpu::ZP0,
-                cycles: 5,
-            },
-            INSTRUCTION {
-                name: "???".to_string(),
-                operate: cpu::XXX,
-                addr_mode: cpu::IMP,
-                cycles: 5,
-            },
-            INSTRUCTION {
-                name: "INY".to_string(),
-                operate: cpu::INY,
-                addr_mode: cpu::IMP,
-                cycles: 2,
-            },
-            INSTRUCTION {
-                name: "CMP".to_string(),
-                operate: cpu::CMP,
-                addr_mode: cpu::IMM,
-                cycles: 2,
-            },
-            INSTRUCTION {
-                name: "DEX".to_string(),
-                operate: cpu::DEX,
-                addr_mode: cpu::IMP,
-                cycles: 2,
-            },
-            INSTRUCTION {
-                name: "???".to_string(),
-                operate: cpu::XXX,
-                addr_mode: cpu::IMP,
-                cycles: 2,
-            },
-            INSTRUCTION {
-                name: "CPY".to_string(),
-                operate: cpu::CPY,
-                addr_mode: cpu::ABS,
-                cycles: 4,
-            },
-            INSTRUCTION {
-                name: "CMP".to_string(),
-                operate: cpu::CMP,
-                addr_mode: cpu::ABS,
-                cycles: 4,
-            },
-            INSTRUCTION {
-                name: "DEC".to_string(),
-                operate: cpu::DEC,
-                addr_mode: cpu::ABS,
-                cycles: 6,
-            },
-            INSTRUCTION {
-                name: "???".to_string(),
-                operate: cpu::XXX,
-                addr_mode: cpu::IMP,
-                cycles: 6,
-            },
-            INSTRUCTION {
-                name: "BNE".to_string(),
-                operate: cpu::BNE,
-                addr_mode: cpu::REL,
-                cycles: 2,
-            },
-            INSTRUCTION {
-                name: "CMP".to_string(),
-                operate: cpu::CMP,
-                addr_mode: cpu::IZY,
-                cycles: 5,
-            },
-            INSTRUCTION {
-                name: "???".to_string(),
-                operate: cpu::XXX,
-                addr_mode: cpu::IMP,
-                cycles: 2,
-            },
-            INSTRUCTION {
-                name: "???".to_string(),
-                operate: cpu::XXX,
-                addr_mode: cpu::IMP,
-                cycles: 8,
-            },
-            INSTRUCTION {
-                name: "???".to_string(),
-                operate: cpu::NOP,
-                addr_mode: cpu::IMP,
-                cycles: 4,
-            },
-            INSTRUCTION {
-                name: "CMP".to_string(),
-                operate: cpu::CMP,
-                addr_mode: cpu::ZPX,
-                cycles: 4,
-            },
-            INSTRUCTION {
-                name: "DEC".to_string(),
-                operate: cpu::DEC,
-                addr_mode: cpu::ZPX,
-                cycles: 6,
-            },
-            INSTRUCTION {
-                name: "???".to_string(),
-                operate: cpu::XXX,
-                addr_mode: cpu::IMP,
-                cycles: 6,
-            },
-            INSTRUCTION {
-                name: "CLD".to_string(),
-                operate: cpu::CLD,
-                addr_mode: cpu::IMP,
-                cycles: 2,
-            },
-            INSTRUCTION {
-                name: "CMP".to_string(),
-                operate: cpu::CMP,
-                addr_mode: cpu::ABY,
-                cycles: 4,
-            },
-            INSTRUCTION {
-                name: "NOP".to_string(),
-                operate: cpu::NOP,
-                addr_mode: cpu::IMP,
-                cycles: 2,
-            },
-            INSTRUCTION {
-                name: "???".to_string(),
-                operate: cpu::XXX,
-                addr_mode: cpu::IMP,
-                cycles: 7,
-            },
-            INSTRUCTION {
-                name: "???".to_string(),
-                operate: cpu::NOP,
-                addr_mode: cpu::IMP,
-                cycles: 4,
-            },
-            INSTRUCTION {
-                name: "CMP".to_string(),
-                operate: cpu::CMP,
-                addr_mode: cpu::ABX,
-                cycles: 4,
-            },
-            INSTRUCTION {
-                name: "DEC".to_string(),
-                operate: cpu::DEC,
-                addr_mode: cpu::ABX,
-                cycles: 7,
-            },
-            INSTRUCTION {
-                name: "???".to_string(),
-                operate: cpu::XXX,
-                addr_mode: cpu::IMP,
-                cycles: 7,
-            },
-            INSTRUCTION {
-                name: "CPX".to_string(),
-                operate: cpu::CPX,
-                addr_mode: cpu::IMM,
-                cycles: 2,
-            },
-            INSTRUCTION {
-                name: "SBC".to_string(),
-                operate: cpu::SBC,
-                addr_mode: cpu::IZX,
-                cycles: 6,
-            },
-            INSTRUCTION {
-                name: "???".to_string(),
-                operate: cpu::NOP,
-                addr_mode: cpu::IMP,
-                cycles: 2,
-            },
-            INSTRUCTION {
-                name: "???".to_string(),
-                operate: cpu::XXX,
-                addr_mode: cpu::IMP,
-                cycles: 8,
-            },
-            INSTRUCTION {
-                name: "CPX".to_string(),
-                operate: cpu::CPX,
-                addr_mode: cpu::ZP0,
-                cycles: 3,
-            },
-            INSTRUCTION {
-                name: "SBC".to_string(),
-                operate: cpu::SBC,
-                addr_mode: cpu::ZP0,
-                cycles: 3,
-            },
-            INSTRUCTION {
-                name: "INC".to_string(),
-                operate: cpu::INC,
-                addr_mode: cpu::ZP0,
-                cycles: 5,
-            },
-            INSTRUCTION {
-                name: "???".to_string(),
-                operate: cpu::XXX,
-                addr_mode: cpu::IMP,
-                cycles: 5,
-            },
-            INSTRUCTION {
-                name: "INX".to_string(),
-                operate: cpu::INX,
-                addr_mode: cpu::IMP,
-                cycles: 2,
-            },
-            INSTRUCTION {
-                name: "SBC".to_string(),
-                operate: cpu::SBC,
-                addr_mode: cpu::IMM,
-                cycles: 2,
-            },
-            INSTRUCTION {
-                name: "NOP".to_string(),
-                operate: cpu::NOP,
-                addr_mode: cpu::IMP,
-                cycles: 2,
-            },
-            INSTRUCTION {
-                name: "???".to_string(),
-                operate: cpu::SBC,
-                addr_mode: cpu::IMP,
-                cycles: 2,
-            },
-            INSTRUCTION {
-                name: "CPX".to_string(),
-                operate: cpu::CPX,
-                addr_mode: cpu::ABS,
-                cycles: 4,
-            },
-            INSTRUCTION {
-                name: "SBC".to_string(),
-                operate: cpu::SBC,
-                addr_mode: cpu::ABS,
-                cycles: 4,
-            },
-            INSTRUCTION {
-                name: "INC".to_string(),
-                operate: cpu::INC,
-                addr_mode: cpu::ABS,
-                cycles: 6,
-            },
-            INSTRUCTION {
-                name: "???".to_string(),
-                operate: cpu::XXX,
-                addr_mode: cpu::IMP,
-                cycles: 6,
-            },
-            INSTRUCTION {
-                name: "BEQ".to_string(),
-                operate: cpu::BEQ,
-                addr_mode: cpu::REL,
-                cycles: 2,
-            },
-            INSTRUCTION {
-                name: "SBC".to_string(),
-                operate: cpu::SBC,
-                addr_mode: cpu::IZY,
-                cycles: 5,
-            },
-            INSTRUCTION {
-                name: "???".to_string(),
-                operate: cpu::XXX,
-                addr_mode: cpu::IMP,
-                cycles: 2,
-            },
-            INSTRUCTION {
-                name: "???".to_string(),
-                operate: cpu::XXX,
-                addr_mode: cpu::IMP,
-                cycles: 8,
-            },
-            INSTRUCTION {
-                name: "???".to_string(),
-                operate: cpu::NOP,
-                addr_mode: cpu::IMP,
-                cycles: 4,
-            },
-            INSTRUCTION {
-                name: "SBC".to_string(),
-                operate: cpu::SBC,
-                addr_mode: cpu::ZPX,
-                cycles: 4,
-            },
-            INSTRUCTION {
-                name: "INC".to_string(),
-                operate: cpu::INC,
-                addr_mode: cpu::ZPX,
-                cycles: 6,
-            },
-            INSTRUCTION {
-                name: "???".to_string(),
-                operate: cpu::XXX,
-                addr_mode: cpu::IMP,
-                cycles: 6,
-            },
-            INSTRUCTION {
-                name: "SED".to_string(),
-                operate: cpu::SED,
-                addr_mode: cpu::IMP,
-                cycles: 2,
-            },
-            INSTRUCTION {
-                name: "SBC".to_string(),
-                operate: cpu::SBC,
-                addr_mode: cpu::ABY,
-                cycles: 4,
-            },
-            INSTRUCTION {
-                name: "NOP".to_string(),
-                operate: cpu::NOP,
-                addr_mode: cpu::IMP,
-                cycles: 2,
-            },
-            INSTRUCTION {
-                name: "???".to_string(),
-                operate: cpu::XXX,
-                addr_mode: cpu::IMP,
-                cycles: 7,
-            },
-            INSTRUCTION {
-                name: "???".to_string(),
-                operate: cpu::NOP,
-                addr_mode: cpu::IMP,
-                cycles: 4,
-            },
-            INSTRUCTION {
-                name: "SBC".to_string(),
-                operate: cpu::SBC,
-                addr_mode: cpu::ABX,
-                cycles: 4,
-            },
-            INSTRUCTION {
-                name: "INC".to_string(),
-                operate: cpu::INC,
-                addr_mode: cpu::ABX,
-                cycles: 7,
-            },
-            INSTRUCTION {
-                name: "???".to_string(),
-                operate: cpu::XXX,
-                addr_mode: cpu::IMP,
-                cycles: 7,
-            },
-        ];
-
-        return Self {
-            a: 0,
-            x: 0,
-            y: 0,
-            stkp: 0,
-            pc: 0,
-            status: 0,
-            fetched: 0,
-            addr_abs: 0,
-            addr_rel: 0,
-            opcode: 0,
-            cycles: 0,
-            lookup,
-            bus: Bus::new(),
-            clock_count: 0,
-            temp: 0,
+    // A matrix keyboard takes the host keys as they're held, "c64" or a
+    // layout file
+    let mut keyboard = keyboard_layout.map(|layout| {
+        let layout = match layout.as_str() {
+            "c64" => KeyLayout::c64(),
+            path => KeyLayout::load(path).expect("failed to load keyboard layout"),
         };
-    }
+        cpu.attach_keyboard(keyboard_addr, layout)
+    });
+
+    // A C64 booted from the ROMs in a directory, with its keyboard on the
+    // host's keys and its picture in place of the memory views
+    let c64 = c64_roms.map(|dir| {
+        let ports = cpu.attach_c64();
+        let roms = C64Roms::load(&dir).expect("failed to load the C64 ROMs");
+        cpu.load_c64_roms(&roms, &ports).expect("failed to load the C64 ROMs");
+        keyboard = Some(ports.keyboard.clone());
+        ports
+    });
+    if let Some(root) = host_fs {
+        cpu.attach_host_fs(host_fs_addr, root);
+    }
+
+    if let Some(base) = cycle_counter {
+        cpu.attach_cycle_counter(base);
+    }
+
+    // A framebuffer's picture is shown in place of the memory views
+    let framebuffer = framebuffer_config.map(|config| {
+        let config = FramebufferConfig {
+            base: framebuffer_base.unwrap_or(config.base),
+            registers: framebuffer_registers.unwrap_or(config.registers),
+            ..config
+        };
+        cpu.attach_framebuffer(config).unwrap_or_else(|e| panic!("--framebuffer: {}", e))
+    });
 
-    fn get_flag(&self, f: FLAGS6502) -> u8 {
-        let f = f as u8;
-        if (self.status & f) > 0 {
-            1
-        } else {
-            0
-        }
-    }
+    // The ROM's project brings back its breakpoints, symbol files and
+    // views. Symbol files it names are relative to where it is.
+    let project_path = rom_path.as_ref().map(Project::path_for_rom);
+    let mut project = match project_path.as_ref().filter(|path| path.exists()) {
+        Some(path) => Project::load(path).expect("failed to load project"),
+        None => Project::new(),
+    };
+    let project_dir = project_path.as_ref().and_then(|path| path.parent()).map(Path::to_path_buf).unwrap_or_default();
 
-    fn set_flag(&mut self, f: FLAGS6502, v: bool) {
-        if v {
-            self.status |= f as u8
-        } else {
-            self.status &= !(f as u8)
+    let mut symbols = SymbolTable::new();
+    for path in &project.symbols {
+        symbols.load_file(project_dir.join(path)).expect("failed to load symbols");
+    }
+    if let Some(symbol_path) = symbol_path {
+        symbols.load_file(&symbol_path).expect("failed to load symbols");
+        let absolute = std::fs::canonicalize(&symbol_path).map_or(symbol_path, |path| path.display().to_string());
+        if !project.symbols.contains(&absolute) {
+            project.symbols.push(absolute);
         }
     }
 
-    // Addressing Modes
-    fn IMP(cpu: &mut cpu6502) -> u8 {
-        cpu.fetched = cpu.a;
-        0
+    // Notes are kept in the project, unless a file was given explicitly or
+    // the ROM still has one of its own beside it
+    if notes_path.is_none() {
+        notes_path = rom_path.as_ref().map(Annotations::path_for_rom).filter(|path| path.exists());
     }
-    fn IMM(cpu: &mut cpu6502) -> u8 {
-        cpu.pc += 1u16;
-        cpu.addr_abs = cpu.pc;
-        0
-    }
-    fn ZP0(cpu: &mut cpu6502) -> u8 {
-        cpu.addr_abs = cpu.read(cpu.pc) as u16;
-        cpu.pc += 1;
-        cpu.addr_abs &= 0x00FF;
 
-        0
+    let mut notes = Annotations::new();
+    for comment in &project.comments {
+        notes.add(comment.start, comment.end, &comment.text);
     }
-
-    fn ZPX(cpu: &mut cpu6502) -> u8 {
-        cpu.addr_abs = (cpu.read(cpu.pc) + cpu.x) as u16;
-        cpu.pc += 1;
-        cpu.addr_abs &= 0x00FF;
-
-        return 0;
+    if let Some(notes_path) = notes_path.as_ref().filter(|path| path.exists()) {
+        notes.load_file(notes_path).expect("failed to load notes");
     }
 
-    fn ZPY(cpu: &mut cpu6502) -> u8 {
-        cpu.addr_abs = (cpu.read(cpu.pc) + cpu.y) as u16;
-        cpu.pc += 1;
-        cpu.addr_abs &= 0x00FF;
-
-        0
+    // A program on the command line replaces the demo, anything without
+    // its own reset vector is started at its entry point
+    let mut load_report = Vec::new();
+    if rom_path.is_none() && c64.is_none() {
+        load_report = load_demo(&mut cpu, demo);
     }
-    fn REL(cpu: &mut cpu6502) -> u8 {
-        cpu.addr_rel = cpu.read(cpu.pc) as u16;
-        cpu.pc += 1;
-        if cpu.addr_rel & 0x80 != 0 {
-            cpu.addr_rel |= 0xFF00;
+    if let Some(rom_path) = &rom_path {
+        load_report = load_program(&mut cpu, Path::new(rom_path), org).expect("failed to load program");
+        #[cfg(feature = "tracing")]
+        for line in &load_report {
+            tracing::info!(target: "crust_6502::loader", "{}", line);
         }
-        0
     }
 
-
-    fn ABS(cpu: &mut cpu6502) -> u8 {
-        let lo = cpu.read(cpu.pc) as u16;
-        cpu.pc += 1;
-        let hi = cpu.read(cpu.pc) as u16;
-        cpu.pc += 1;
-
-        cpu.addr_abs = ((hi << 8) | lo) as u16;
-
-        0
+    // Lists the program with its symbols for other tools to read, and stops
+    if let Some((start, end)) = disassemble {
+        print!("{}", cpu.disassembly_listing(start, end, &symbols, disasm_format));
+        return;
     }
 
+    // Rebuilding the program is noticed and L loads it again, keeping
+    // breakpoints and cheats
+    let mut watcher = rom_path.as_ref().map(FileWatcher::new);
 
-    fn ABX(cpu: &mut cpu6502) -> u8 {
-        let lo = cpu.read(cpu.pc) as u16;
-        cpu.pc += 1;
-        let hi = cpu.read(cpu.pc) as u16;
-        cpu.pc += 1;
+    cpu.set_traps(traps);
 
-        cpu.addr_abs = ((hi << 8) | lo) as u16;
-        cpu.addr_abs += cpu.x as u16;
-
-        if (cpu.addr_abs & 0xFF00) != (hi << 8) as u16 {
-            1
-        } else {
-            0
-        }
+    if let Some(target) = &trace_target {
+        cpu.set_trace(Some(trace::open(target, trace_format).expect("failed to open trace")));
     }
 
-
-    fn ABY(cpu: &mut cpu6502) -> u8 {
-        let lo = cpu.read(cpu.pc) as u16;
-        cpu.pc += 1;
-        let hi = cpu.read(cpu.pc) as u16;
-        cpu.pc += 1;
-
-        cpu.addr_abs = ((hi << 8) | lo);
-        cpu.addr_abs += cpu.y as u16;
-
-        if (cpu.addr_abs & 0xFF00) != (hi << 8) {
-            1
-        } else {
-            0
-        }
+    // Pokes go in at the reset below, codes as soon as they're added
+    for patch in patches {
+        cpu.add_patch(patch);
     }
 
+    let mut code_view = CodeView::new();
 
-    fn IND(cpu: &mut cpu6502) -> u8 {
-        let ptr_lo = cpu.read(cpu.pc) as u16;
-        cpu.pc += 1;
-        let ptr_hi = cpu.read(cpu.pc) as u16;
-        cpu.pc += 1;
-
-        let ptr = (ptr_hi << 8) | ptr_lo;
+    // A 2600 cartridge shows its picture in place of the memory views
+    let mut atari = cpu.atari2600();
 
-        if ptr_lo == 0x00FF
-        // Simulate page boundary hardware bug
-        {
-            cpu.addr_abs = (cpu.read(ptr & 0xFFu16) as u16) << 8 | (cpu.read(ptr + 0) as u16);
-        } else
-        // Behave normally
-        {
-            cpu.addr_abs = ((cpu.read(ptr + 1) as u16) << 8) | (cpu.read(ptr + 0) as u16);
-        }
-
-        0
+    cpu.reset();
+    if chrome_trace_path.is_some() {
+        cpu.enable_timeline();
+    }
+    if access_log_path.is_some() {
+        cpu.set_access_log(Some(AccessLog::range(access_log_range.0, access_log_range.1)));
+    }
+    if device_log_path.is_some() {
+        cpu.device_log().set_enabled(true);
     }
 
-
-    fn IZX(cpu: &mut cpu6502) -> u8 {
-        let t = cpu.read(cpu.pc) as u16;
-        cpu.pc += 1;
-
-        let lo = cpu.read(((t + (cpu.x as u16)) & 0x00FF)) as u16;
-        let hi = cpu.read(((t + ((cpu.x as u16) + 1u16)) & 0x00FF)) as u16;
-
-        cpu.addr_abs = ((hi << 8) | lo) as u16;
-
-        0
+    if let Some(replay_path) = &replay_path {
+        let recording = Recording::load(replay_path).expect("failed to load replay");
+        cpu.start_replay(recording).expect("failed to start replay");
+    } else if record_path.is_some() {
+        cpu.start_recording();
     }
 
 
-    fn IZY(cpu: &mut cpu6502) -> u8 {
-        let t = cpu.read(cpu.pc) as u16;
-        cpu.pc += 1;
+    let mut buffer: Vec<u32> = vec![0; WIDTH * HEIGHT];
 
-        let lo = cpu.read((t & 0x00FF)) as u16;
-        let hi = cpu.read(((t + 1) & 0x00FF)) as u16;
+    let mut window = Window::new(
+        "Test - ESC to exit",
+        WIDTH,
+        HEIGHT,
+        WindowOptions::default(),
+    )
+        .unwrap_or_else(|e| {
+            panic!("{}", e);
+        });
 
-        cpu.addr_abs = ((hi << 8) | lo);
-        cpu.addr_abs += cpu.y as u16;
+    // Limit to max ~60 fps update rate
+    window.limit_update_rate(Some(REDRAW_INTERVAL));
 
-        if (cpu.addr_abs & 0xFF00) != (hi << 8) {
-            1
-        } else {
-            0
+    // Panes in windows of their own, from the command line or as the
+    // project left them. Closing one puts its pane back.
+    for pane in [Detachable::Ram, Detachable::Code] {
+        if project.shows(&detached_key(pane)) && !detach.contains(&pane) {
+            detach.push(pane);
         }
     }
+    let mut detached: Vec<DetachedPane> = detach.iter().map(|&pane| DetachedPane::open(pane).unwrap_or_else(|e| panic!("{}", e))).collect();
 
-    //opcodes
-    fn ADC(cpu: &mut cpu6502) -> u8 {
-        // Grab the data that we are adding to the accumulator
-        cpu.fetch();
-
-        // Add is performed in 16-bit domain for emulation to capture any
-        // carry bit, which will exist in bit 8 of the 16-bit word
-        cpu.temp = ((cpu.a as u16) + (cpu.fetched as u16) + (cpu.get_flag(FLAGS6502::C) as u16));
-
-        // The carry flag out exists in the high byte bit 0
-        cpu.set_flag(FLAGS6502::C, cpu.temp > 255);
-
-        // The Zero flag is set if the result is 0
-        cpu.set_flag(FLAGS6502::Z, (cpu.temp & 0x00FF) == 0);
-
-        // The signed Overflow flag is set based on all that up there! :D
-        cpu.set_flag(
-            FLAGS6502::V,
-            (!((cpu.a as u16) ^ (cpu.fetched as u16)) & ((cpu.a as u16) ^ (cpu.temp as u16))) & 0x0080 != 0,
-        );
+    let status_text = StatusText::new(WIDTH, HEIGHT, 1);
 
-        // The negative flag is set to the most significant bit of the result
-        //Tawanda verify this
-        cpu.set_flag(FLAGS6502::N, cpu.temp & 0x80 != 0);
+    let mut debugger = Debugger::new();
+    debugger.breakpoints.extend(project.breakpoints.iter().copied());
+    debugger.watches = project.watches.clone();
+    let mut console = Console::new();
+    console.charset = charset;
+    for line in load_report {
+        console.print(line);
+    }
+    let mut zero_page = ZeroPageView::new();
+    let mut memory_view = MemoryView::new(0x8000);
+    let mut show_stack = project.shows("stack");
+    let mut show_stats = project.shows("stats");
+    let mut show_patches = project.shows("patches");
+    let mut show_calls = project.shows("calls");
+    // The call picked in the calls pane, the code view shows where it was
+    // made from. None follows the PC.
+    let mut selected_call: Option<usize> = None;
+    let mut show_vectors = project.shows("vectors");
+    let mut show_chr = project.shows("chr");
+    let mut show_devices = project.shows("devices");
+    if show_devices {
+        cpu.device_log().set_enabled(true);
+    }
+    // The vector picked in the vectors pane and the hex typed for it so far
+    let mut selected_vector: Option<usize> = None;
+    let mut vector_typed = String::new();
+    let mut irq_key = false;
+    let mut frame: u32 = 0;
+    let mut perf = PerfCounters::new();
+    let mut show_perf = project.shows("perf");
+    let mut turbo = false;
+    let mut last_redraw = Instant::now();
+
+    let typed_chars = Rc::new(RefCell::new(Vec::new()));
+    window.set_input_callback(Box::new(ConsoleInput { chars: typed_chars.clone() }));
+
+    while window.is_open() && !window.is_key_down(Key::Escape) && cpu.exit_code().is_none() {
+        // ~ opens the console, while it's open the keyboard belongs to it
+        if window.is_key_pressed(Key::Backquote, KeyRepeat::No) {
+            console.open = !console.open;
+        }
+
+        let typed: Vec<char> = typed_chars.borrow_mut().drain(..).collect();
+
+        if console.open {
+            for &c in &typed {
+                if c != '`' && c != '~' && !c.is_control() {
+                    console.input.push(c);
+                }
+            }
 
-        // Load the result into the accumulator (it's 8-bit dont forget!)
-        cpu.a = (cpu.temp & 0x00FF) as u8;
+            if window.is_key_pressed(Key::Backspace, KeyRepeat::Yes) {
+                console.input.pop();
+            }
 
-        // This instruction has the potential to require an additional clock cycle
-        return 1;
-    }
+            if window.is_key_pressed(Key::Enter, KeyRepeat::No) {
+                let known = symbols.len();
+                console.submit(&mut cpu, &mut debugger, &mut symbols, &mut notes);
 
-    fn AND(cpu: &mut cpu6502) -> u8 {
-        cpu.fetch();
-        cpu.a = cpu.a & cpu.fetched;
-        cpu.set_flag(FLAGS6502::Z, cpu.a == 0x00);
-        cpu.set_flag(FLAGS6502::N, cpu.a & 0x80 != 0);
-        return 1;
-    }
-    fn ASL(cpu: &mut cpu6502) -> u8 {
-        cpu.fetch();
-        cpu.temp = ((cpu.fetched as u16) << 1);
-        cpu.set_flag(FLAGS6502::C, (cpu.temp & 0xFF00) > 0);
-        cpu.set_flag(FLAGS6502::Z, (cpu.temp & 0x00FF) == 0x00);
-        cpu.set_flag(FLAGS6502::N, cpu.temp & 0x80 != 0);
-        if cpu.lookup[cpu.opcode as usize].addr_mode == cpu6502::IMP {
-            cpu.a = (cpu.temp & 0x00FF) as u8;
-        } else {
-            cpu.write(cpu.addr_abs, (cpu.temp & 0x00FF) as u8);
+                // "analyze" can name new subroutines, show them in the code view
+                if symbols.len() != known {
+                    code_view.clear();
+                }
+            }
         }
 
-        return 0;
-    }
-    fn BCC(cpu: &mut cpu6502) -> u8 {
-        if cpu.get_flag(FLAGS6502::C) == 0 {
-            cpu.cycles += 1;
-            cpu.addr_abs = cpu.pc + cpu.addr_rel;
-
-            if (cpu.addr_abs & 0xFF00) != (cpu.pc & 0xFF00) {
-                cpu.cycles += 1;
+        // The RAM viewer's go-to and find fields have the keyboard to
+        // themselves while they're open, the way the console does
+        let typing = console.open || memory_view.field.is_some();
+        if !console.open && memory_view.field.is_some() {
+            memory_view.typed.extend(typed.iter().filter(|c| !c.is_control()));
+            if window.is_key_pressed(Key::Backspace, KeyRepeat::Yes) {
+                memory_view.typed.pop();
+            }
+            if window.is_key_pressed(Key::Enter, KeyRepeat::No) {
+                memory_view.submit(&cpu, &symbols);
             }
-
-            cpu.pc = cpu.addr_abs;
         }
-        return 0;
-    }
-    fn BCS(cpu: &mut cpu6502) -> u8 {
-        if cpu.get_flag(FLAGS6502::C) == 1 {
-            cpu.cycles += 1;
-            cpu.addr_abs = cpu.pc + cpu.addr_rel;
 
-            if ((cpu.addr_abs & 0xFF00) != (cpu.pc & 0xFF00)) {
-                cpu.cycles += 1;
+        if let Some(keyboard) = &keyboard {
+            if typing {
+                keyboard.release_all();
+            } else {
+                let held: Vec<String> = window.get_keys().iter().map(|key| std::format!("{:?}", key)).collect();
+                keyboard.set_pressed(&held);
             }
-
-            cpu.pc = cpu.addr_abs;
         }
-        return 0;
-    }
-    fn BEQ(cpu: &mut cpu6502) -> u8 {
-        if cpu.get_flag(FLAGS6502::Z) == 1 {
-            cpu.cycles += 1;
-            cpu.addr_abs = cpu.pc + cpu.addr_rel;
 
-            if (cpu.addr_abs & 0xFF00) != (cpu.pc & 0xFF00) {
-                cpu.cycles += 1;
+        // Keys go to the program through stimulate so recordings pick them up,
+        // unless they're a new address for a vector or it's a C64 reading its
+        // keyboard matrix instead
+        let editing_vector = show_vectors && selected_vector.is_some() && atari.is_none();
+        if !typing && debugger.running && !editing_vector && c64.is_none() {
+            for c in typed.iter().filter(|c| c.is_ascii()) {
+                cpu.stimulate(Stimulus::Write { addr: KEYBOARD_ADDR, data: *c as u8 });
             }
-
-            cpu.pc = cpu.addr_abs;
         }
-        0
-    }
-    fn BIT(cpu: &mut cpu6502) -> u8 {
-        cpu.fetch();
-        cpu.temp = (cpu.a & cpu.fetched) as u16;
-        cpu.set_flag(FLAGS6502::Z, (cpu.temp & 0x00FF) == 0x00);
-        cpu.set_flag(FLAGS6502::N, cpu.fetched & (1 << 7) != 0);
-        cpu.set_flag(FLAGS6502::V, cpu.fetched & (1 << 6) != 0);
-
-        0
-    }
-
-    fn BMI(cpu: &mut cpu6502) -> u8 {
-        if cpu.get_flag(FLAGS6502::N) == 1 {
-            cpu.cycles += 1;
-            cpu.addr_abs = cpu.pc + cpu.addr_rel;
 
-            if (cpu.addr_abs & 0xFF00) != (cpu.pc & 0xFF00) {
-                cpu.cycles += 1;
+        // 1, 2, 3... swap in the built-in demos
+        let demo_keys = [Key::Key1, Key::Key2, Key::Key3, Key::Key4, Key::Key5, Key::Key6, Key::Key7, Key::Key8, Key::Key9];
+        for (key, demo) in demo_keys.iter().zip(demos::DEMOS) {
+            if !typing && window.is_key_pressed(*key, KeyRepeat::No) {
+                for line in load_demo(&mut cpu, demo) {
+                    console.print(line);
+                }
             }
-
-            cpu.pc = cpu.addr_abs;
         }
-        return 0;
-    }
 
-    fn BNE(cpu: &mut cpu6502) -> u8 {
-        if cpu.get_flag(FLAGS6502::Z) == 0 {
-            cpu.cycles += 1;
-            cpu.addr_abs = cpu.pc + cpu.addr_rel;
-
-            if (cpu.addr_abs & 0xFF00) != (cpu.pc & 0xFF00) {
-                cpu.cycles += 1;
+        if let Some(watcher) = watcher.as_mut().filter(|_| frame % RELOAD_POLL_FRAMES == 0) {
+            if watcher.changed() {
+                console.print(std::format!("{} changed, L reloads it", watcher.path().display()));
             }
-
-            cpu.pc = cpu.addr_abs;
         }
 
-        0
-    }
-
-    fn BPL(cpu: &mut cpu6502) -> u8 {
-        if cpu.get_flag(FLAGS6502::N) == 0 {
-            cpu.cycles += 1;
-            cpu.addr_abs = cpu.pc + cpu.addr_rel;
-
-            if (cpu.addr_abs & 0xFF00) != (cpu.pc & 0xFF00) {
-                cpu.cycles += 1;
+        if let Some(watcher) = watcher.as_ref().filter(|_| !typing && window.is_key_pressed(Key::L, KeyRepeat::No)) {
+            match load_program(&mut cpu, watcher.path(), org) {
+                Ok(report) => {
+                    atari = cpu.atari2600();
+                    cpu.reset();
+                    for line in report {
+                        console.print(line);
+                    }
+                }
+                Err(e) => console.print(std::format!("reload failed: {}", e)),
             }
-
-            cpu.pc = cpu.addr_abs;
         }
 
-        0
-    }
-
+        if !typing && window.is_key_pressed(Key::R, KeyRepeat::No) {
+            cpu.stimulate(Stimulus::Reset);
+        }
 
-    fn BRK(cpu: &mut cpu6502) -> u8 {
-        cpu.pc += 1;
+        // IRQ is a level, it stays asserted for as long as the key is held
+        let irq_held = !typing && window.is_key_down(Key::I);
+        if irq_held != irq_key {
+            cpu.assert_irq(irq_held);
+            irq_key = irq_held;
+        }
 
-        cpu.set_flag(FLAGS6502::I, true);
-        cpu.write(0x0100 + cpu.stkp as u16, ((cpu.pc >> 8) & 0x00FF) as u8);
-        cpu.stkp -= 1;
-        cpu.write(0x0100 + cpu.stkp as u16, (cpu.pc & 0x00FF) as u8);
-        cpu.stkp -= 1;
+        if !typing && window.is_key_pressed(Key::N, KeyRepeat::No) {
+            cpu.trigger_nmi();
+        }
 
-        cpu.set_flag(FLAGS6502::B, true);
-        cpu.write(0x0100 + cpu.stkp as u16, cpu.status);
-        cpu.stkp -= 1;
-        cpu.set_flag(FLAGS6502::B, false);
+        if !typing && window.is_key_pressed(Key::S, KeyRepeat::No) {
+            show_stack = !show_stack;
+        }
 
-        cpu.pc = (cpu.read(0xFFFE) as u16) | ((cpu.read(0xFFFF) as u16) << 8);
+        if !typing && window.is_key_pressed(Key::H, KeyRepeat::No) {
+            show_stats = !show_stats;
+        }
 
-        0
-    }
+        if !typing && window.is_key_pressed(Key::G, KeyRepeat::No) {
+            show_patches = !show_patches;
+        }
 
-    fn BVC(cpu: &mut cpu6502) -> u8 {
-        if cpu.get_flag(FLAGS6502::V) == 0
-        {
-            cpu.cycles += 1;
-            cpu.addr_abs = cpu.pc + cpu.addr_rel;
+        if !typing && window.is_key_pressed(Key::C, KeyRepeat::No) {
+            show_calls = !show_calls;
+            selected_call = None;
+        }
 
-            if (cpu.addr_abs & 0xFF00) != (cpu.pc & 0xFF00) {
-                cpu.cycles += 1;
+        // Up and down pick a call, or a click on one. Above the innermost
+        // is back to following the PC.
+        if show_calls && !typing && atari.is_none() {
+            let depth = cpu.backtrace().len();
+            if window.is_key_pressed(Key::Down, KeyRepeat::Yes) && depth > 0 {
+                selected_call = Some(selected_call.map_or(0, |i| (i + 1).min(depth - 1)));
+            }
+            if window.is_key_pressed(Key::Up, KeyRepeat::Yes) {
+                selected_call = selected_call.and_then(|i| i.checked_sub(1));
+            }
+            if window.get_mouse_down(MouseButton::Left) {
+                if let Some((x, y)) = window.get_mouse_pos(MouseMode::Discard) {
+                    let row = (y as usize).checked_sub(CALLS_PANE.1 + 10).map(|offset| offset / 10);
+                    if x < 55.0 * 8.0 {
+                        selected_call = row.filter(|&row| row < depth).or(selected_call);
+                    }
+                }
             }
-
-
-            cpu.pc = cpu.addr_abs;
         }
 
-        0
-    }
-
-
-    fn BVS(cpu: &mut cpu6502) -> u8 {
-        if cpu.get_flag(FLAGS6502::V) == 1
-        {
-            cpu.cycles += 1;
-            cpu.addr_abs = cpu.pc + cpu.addr_rel;
+        if !typing && window.is_key_pressed(Key::V, KeyRepeat::No) {
+            show_vectors = !show_vectors;
+            selected_vector = None;
+            vector_typed.clear();
+        }
 
-            if (cpu.addr_abs & 0xFF00) != (cpu.pc & 0xFF00) {
-                cpu.cycles += 1;
+        // Pick a vector with up and down or a click, then type its new
+        // address in hex and Enter writes it, undoably
+        if show_vectors && !typing && atari.is_none() {
+            if window.is_key_pressed(Key::Down, KeyRepeat::Yes) {
+                selected_vector = Some(selected_vector.map_or(0, |i| (i + 1).min(Vector::ALL.len() - 1)));
+                vector_typed.clear();
+            }
+            if window.is_key_pressed(Key::Up, KeyRepeat::Yes) {
+                selected_vector = selected_vector.and_then(|i| i.checked_sub(1));
+                vector_typed.clear();
+            }
+            if window.get_mouse_down(MouseButton::Left) {
+                if let Some((x, y)) = window.get_mouse_pos(MouseMode::Discard) {
+                    let row = (y as usize).checked_sub(VECTORS_PANE.1 + 10).map(|offset| offset / 10);
+                    if x < 55.0 * 8.0 && row.is_some_and(|row| row < Vector::ALL.len()) && row != selected_vector {
+                        selected_vector = row;
+                        vector_typed.clear();
+                    }
+                }
             }
 
-
-            cpu.pc = cpu.addr_abs;
+            if let Some(i) = selected_vector {
+                for c in typed.iter().filter(|c| c.is_ascii_hexdigit()) {
+                    if vector_typed.len() < 4 {
+                        vector_typed.push(c.to_ascii_lowercase());
+                    }
+                }
+                if window.is_key_pressed(Key::Backspace, KeyRepeat::Yes) {
+                    vector_typed.pop();
+                }
+                if window.is_key_pressed(Key::Enter, KeyRepeat::No) {
+                    if let Ok(addr) = u16::from_str_radix(&vector_typed, 16) {
+                        debugger.set_vector(&mut cpu, Vector::ALL[i], addr);
+                        console.print(cpu.describe_vector(Vector::ALL[i], &symbols));
+                    }
+                    vector_typed.clear();
+                }
+            }
         }
 
+        // M and / open the RAM viewer's fields, F3 finds the next match
+        // and Shift+F3 the one before
+        if !typing && window.is_key_pressed(Key::M, KeyRepeat::No) {
+            memory_view.open(Field::GoTo);
+        }
 
-        0
-    }
-
-
-    fn CLC(cpu: &mut cpu6502) -> u8 {
-        cpu.set_flag(FLAGS6502::C, false);
-
-        0
-    }
-
-
-    fn CLD(cpu: &mut cpu6502) -> u8 {
-        cpu.set_flag(FLAGS6502::D, false);
-
-        0
-    }
-
-    fn CLI(cpu: &mut cpu6502) -> u8 {
-        cpu.set_flag(FLAGS6502::I, false);
-        0
-    }
-
-    fn CLV(cpu: &mut cpu6502) -> u8 {
-        cpu.set_flag(FLAGS6502::V, false);
-
-        0
-    }
-
-    fn CMP(cpu: &mut cpu6502) -> u8 {
-        cpu.fetch();
-        cpu.temp = (cpu.a - cpu.fetched) as u16;
-        cpu.set_flag(FLAGS6502::C, cpu.a >= cpu.fetched);
-        cpu.set_flag(FLAGS6502::Z, (cpu.temp & 0x00FF) == 0x0000);
-        cpu.set_flag(FLAGS6502::N, (cpu.temp & 0x0080) != 0);
-
-        0
-    }
-
-
-    fn CPX(cpu: &mut cpu6502) -> u8 {
-        cpu.fetch();
-        cpu.temp = (cpu.x - cpu.fetched) as u16;
-        cpu.set_flag(FLAGS6502::C, cpu.x >= cpu.fetched);
-        cpu.set_flag(FLAGS6502::Z, (cpu.temp & 0x00FF) == 0x0000);
-        cpu.set_flag(FLAGS6502::N, (cpu.temp & 0x0080) != 0);
-
-        0
-    }
-
-    fn CPY(cpu: &mut cpu6502) -> u8 {
-        cpu.fetch();
-        cpu.temp = (cpu.y - cpu.fetched) as u16;
-        cpu.set_flag(FLAGS6502::C, cpu.y >= cpu.fetched);
-        cpu.set_flag(FLAGS6502::Z, (cpu.temp & 0x00FF) == 0x0000);
-        cpu.set_flag(FLAGS6502::N, (cpu.temp & 0x0080) != 0);
-
-        0
-    }
-
-    fn DEC(cpu: &mut cpu6502) -> u8 {
-        cpu.fetch();
-        cpu.temp = (cpu.fetched - 1) as u16;
-        cpu.write(cpu.addr_abs, (cpu.temp & 0x00FF) as u8);
-        cpu.set_flag(FLAGS6502::Z, (cpu.temp & 0x00FF) == 0x0000);
-        cpu.set_flag(FLAGS6502::N, (cpu.temp & 0x0080) != 0);
-
-        0
-    }
-
-    fn DEX(cpu: &mut cpu6502) -> u8 {
-        cpu.x -= 1;
-        cpu.set_flag(FLAGS6502::Z, cpu.x == 0x00);
-        cpu.set_flag(FLAGS6502::N, (cpu.x & 0x80) != 0);
-
-        0
-    }
-
-
-    fn DEY(cpu: &mut cpu6502) -> u8 {
-        cpu.y -= 1;
-        cpu.set_flag(FLAGS6502::Z, cpu.y == 0x00);
-        cpu.set_flag(FLAGS6502::N, (cpu.y & 0x80) != 0);
-
-        0
-    }
-
-
-    fn EOR(cpu: &mut cpu6502) -> u8 {
-        cpu.fetch();
-        cpu.a = cpu.a ^ cpu.fetched;
-
-        cpu.set_flag(FLAGS6502::Z, cpu.a == 0x00);
-        cpu.set_flag(FLAGS6502::N, (cpu.a & 0x80) != 0);
-
-        0
-    }
-
-
-    fn INC(cpu: &mut cpu6502) -> u8 {
-        cpu.fetch();
-        cpu.temp = (cpu.fetched + 1) as u16;
-        cpu.write(cpu.addr_abs, (cpu.temp & 0x00FF) as u8);
-        cpu.set_flag(FLAGS6502::Z, (cpu.temp & 0x00FF) == 0x0000);
-        cpu.set_flag(FLAGS6502::N, (cpu.temp & 0x0080) != 0);
-
-        0
-    }
-
-
-    fn INX(cpu: &mut cpu6502) -> u8 {
-        cpu.x += 1;
-
-        cpu.set_flag(FLAGS6502::Z, cpu.x == 0x00);
-        cpu.set_flag(FLAGS6502::N, (cpu.x & 0x80) != 0);
-
-        0
-    }
-
-
-    fn INY(cpu: &mut cpu6502) -> u8 {
-        cpu.y += 1;
-
-        cpu.set_flag(FLAGS6502::Z, cpu.y == 0x00);
-        cpu.set_flag(FLAGS6502::N, (cpu.y & 0x80) != 0);
-
-        0
-    }
-
-    fn JMP(cpu: &mut cpu6502) -> u8 {
-        cpu.pc = cpu.addr_abs;
-
-        0
-    }
-
-    fn JSR(cpu: &mut cpu6502) -> u8 {
-        cpu.pc -= 1;
-
-        cpu.write(0x0100u16 + (cpu.stkp as u16), ((cpu.pc >> 8) & 0x00FF) as u8);
-        cpu.stkp -= 1;
-        cpu.write(0x0100u16 + (cpu.stkp as u16), (cpu.pc & 0x00FF) as u8);
-        cpu.stkp -= 1;
-
-        cpu.pc = cpu.addr_abs;
-
-        0
-    }
-
-
-    fn LDA(cpu: &mut cpu6502) -> u8 {
-        cpu.fetch();
-        cpu.a = cpu.fetched;
-        cpu.set_flag(FLAGS6502::Z, cpu.a == 0x00);
-        cpu.set_flag(FLAGS6502::N, (cpu.a & 0x80) != 0);
+        if !typing && window.is_key_pressed(Key::Slash, KeyRepeat::No) {
+            memory_view.open(Field::Search);
+        }
 
-        1
-    }
-    fn LDX(cpu: &mut cpu6502) -> u8 {
-        cpu.fetch();
-        cpu.x = cpu.fetched;
-        cpu.set_flag(FLAGS6502::Z, cpu.x == 0x00);
-        cpu.set_flag(FLAGS6502::N, (cpu.x & 0x80) != 0);
+        if !typing && window.is_key_pressed(Key::F3, KeyRepeat::Yes) {
+            let shift = window.is_key_down(Key::LeftShift) || window.is_key_down(Key::RightShift);
+            memory_view.step(&cpu, !shift);
+        }
 
+        // Page Up and Page Down move it by about as much as it shows, in
+        // rows of 16
+        let ram_rows = if detached.iter().any(|pane| pane.pane == Detachable::Ram) { Detachable::Ram.lines() - 1 } else { 15 * RAM_COLUMNS / 16 };
+        if !typing && window.is_key_pressed(Key::PageUp, KeyRepeat::Yes) {
+            memory_view.scroll(-(ram_rows as i32));
+        }
 
-        1
-    }
-    fn LDY(cpu: &mut cpu6502) -> u8 {
-        cpu.fetch();
-        cpu.y = cpu.fetched;
-        cpu.set_flag(FLAGS6502::Z, cpu.y == 0x00);
-        cpu.set_flag(FLAGS6502::N, (cpu.y & 0x80) != 0);
+        if !typing && window.is_key_pressed(Key::PageDown, KeyRepeat::Yes) {
+            memory_view.scroll(ram_rows as i32);
+        }
 
-        1
-    }
-    fn LSR(cpu: &mut cpu6502) -> u8 {
-        cpu.fetch();
-        cpu.set_flag(FLAGS6502::C, (cpu.fetched & 0x0001) != 0);
-        cpu.temp = (cpu.fetched >> 1) as u16;
-        cpu.set_flag(FLAGS6502::Z, (cpu.temp & 0x00FF) == 0x0000);
-        cpu.set_flag(FLAGS6502::N, (cpu.temp & 0x0080) != 0);
+        if !typing && window.is_key_pressed(Key::K, KeyRepeat::No) {
+            console.charset = console.charset.next();
+        }
 
+        if !typing && window.is_key_pressed(Key::X, KeyRepeat::No) {
+            show_chr = !show_chr;
+        }
 
-        if cpu.lookup[cpu.opcode as usize].addr_mode == cpu6502::IMP {
-            cpu.a = (cpu.temp & 0x00FF) as u8;
-        } else {
-            cpu.write(cpu.addr_abs, (cpu.temp & 0x00FF) as u8);
+        // Showing the pane starts the log, hiding it leaves it running
+        if !typing && window.is_key_pressed(Key::E, KeyRepeat::No) {
+            show_devices = !show_devices;
+            if show_devices {
+                cpu.device_log().set_enabled(true);
+            }
         }
 
-        0
-    }
+        if !typing && window.is_key_pressed(Key::F, KeyRepeat::No) {
+            show_perf = !show_perf;
+        }
 
-    fn NOP(cpu: &mut cpu6502) -> u8 {
-        let return_code = match cpu.opcode {
-            0x1C => { 1 }
-            0x3C => { 1 }
-            0x5C => { 1 }
-            0x7C => { 1 }
-            0xDC => { 1 }
-            0xFC => { 1 }
-            _ => { 0 }
-        };
+        if !typing && window.is_key_pressed(Key::T, KeyRepeat::No) {
+            turbo = !turbo;
+            window.limit_update_rate(if turbo { None } else { Some(REDRAW_INTERVAL) });
+        }
 
-        return_code
-    }
+        if !typing && window.is_key_pressed(Key::P, KeyRepeat::No) {
+            if cpu.is_profiling() {
+                for line in format_report(&cpu.profile_report(ProfileSort::Inclusive), &symbols).lines() {
+                    console.print(line.to_string());
+                }
+                console.open = true;
+                cpu.disable_profiler();
+            } else {
+                cpu.enable_profiler();
+            }
+        }
 
-    fn ORA(cpu: &mut cpu6502) -> u8 {
-        cpu.fetch();
-        cpu.a = cpu.a | cpu.fetched;
-        cpu.set_flag(FLAGS6502::Z, cpu.a == 0x00);
-        cpu.set_flag(FLAGS6502::N, (cpu.a & 0x80) != 0);
+        // The arrows are the left joystick and Enter its button, pulling
+        // port A's top four bits and INPT4 low
+        if let Some(atari) = atari.as_ref().filter(|_| !typing) {
+            let directions = [(Key::Up, 0x10), (Key::Down, 0x20), (Key::Left, 0x40), (Key::Right, 0x80)];
+            let pressed = directions.iter().filter(|(key, _)| window.is_key_down(*key)).fold(0, |bits, (_, bit)| bits | bit);
+            atari.riot.set_port_a(!pressed);
+            atari.tia.set_fire(0, window.is_key_down(Key::Enter));
+        }
 
-        1
-    }
-    fn PHA(cpu: &mut cpu6502) -> u8 {
-        cpu.write(0x0100u16 + (cpu.stkp as u16), cpu.a);
-        cpu.stkp -= 1;
+        let ctrl = window.is_key_down(Key::LeftCtrl) || window.is_key_down(Key::RightCtrl);
 
-        0
-    }
-    fn PHP(cpu: &mut cpu6502) -> u8 {
-        cpu.write(0x0100u16 + (cpu.stkp as u16), cpu.status | (FLAGS6502::B as u8) | (FLAGS6502::U as u8));
-        cpu.set_flag(FLAGS6502::B, false);
-        cpu.set_flag(FLAGS6502::U, false);
-        cpu.stkp -= 1;
+        if ctrl && window.is_key_pressed(Key::Z, KeyRepeat::Yes) {
+            debugger.undo(&mut cpu);
+        }
 
-        0
-    }
-    fn PLA(cpu: &mut cpu6502) -> u8 {
-        cpu.stkp += 1;
-        cpu.a = cpu.read(0x0100u16 + cpu.stkp as u16);
-        cpu.set_flag(FLAGS6502::Z, cpu.a == 0x00);
-        cpu.set_flag(FLAGS6502::N, (cpu.a & 0x80) != 0);
+        if ctrl && window.is_key_pressed(Key::Y, KeyRepeat::Yes) {
+            debugger.redo(&mut cpu);
+        }
 
-        0
-    }
+        let emulation_start = Instant::now();
+        let start_cycle = cpu.clock_count();
+        let mut instructions = 0;
 
-    fn PLP(cpu: &mut cpu6502) -> u8 {
-        cpu.stkp += 1;
-        cpu.status = cpu.read(0x0100u16 + cpu.stkp as u16);
-        cpu.set_flag(FLAGS6502::U, true);
+        if !typing && window.is_key_pressed(Key::Space, KeyRepeat::No) {
+            loop {
+                cpu.clock();
 
+                if cpu.complete() {
+                    instructions += 1;
+                    break;
+                }
+            }
+        }
 
-        0
-    }
+        // . runs one video frame's worth of cycles while stopped
+        if !typing && !debugger.running && window.is_key_pressed(Key::Period, KeyRepeat::Yes) {
+            debugger.apply_cheats(&mut cpu);
+            instructions += run_cycles(&mut cpu, &mut debugger, &mut console, CYCLES_PER_FRAME);
+        }
 
-    fn ROL(cpu: &mut cpu6502) -> u8 {
-        cpu.fetch();
-        cpu.temp = ((cpu.get_flag(FLAGS6502::C) << 7) | (cpu.fetched >> 1)) as u16;
-        cpu.set_flag(FLAGS6502::C, (cpu.fetched & 0x01) != 0);
-        cpu.set_flag(FLAGS6502::Z, (cpu.temp & 0x00FF) == 0x00);
-        cpu.set_flag(FLAGS6502::N, (cpu.temp & 0x0080) != 0);
+        if debugger.running {
+            debugger.apply_cheats(&mut cpu);
+            instructions += run_cycles(&mut cpu, &mut debugger, &mut console, CYCLES_PER_FRAME);
+        }
 
+        perf.add_emulation(emulation_start.elapsed(), cpu.clock_count() - start_cycle, instructions);
 
-        if cpu.lookup[cpu.opcode as usize].addr_mode == cpu6502::IMP {
-            cpu.a = (cpu.temp & 0x00FF) as u8;
+        // In turbo the screen is only drawn as often as it would be
+        // normally, the rest of the time goes to emulating
+        if turbo && last_redraw.elapsed() < REDRAW_INTERVAL {
+            window.update();
+            for pane in &mut detached {
+                pane.update();
+            }
+            perf.end_frame();
+            continue;
+        }
+        last_redraw = Instant::now();
+
+        let render_start = Instant::now();
+
+        zero_page.update(&cpu);
+        detached.retain(DetachedPane::is_open);
+        let ram_detached = detached.iter().any(|pane| pane.pane == Detachable::Ram);
+        let code_detached = detached.iter().any(|pane| pane.pane == Detachable::Code);
+        if let Some(atari) = &atari {
+            draw_picture(&mut buffer, 2, 2, &atari.tia.frame(), FRAME_WIDTH, 2);
+        } else if let Some(c64) = &c64 {
+            draw_picture(&mut buffer, 2, 2, &c64.vic.frame(), vicii::FRAME_WIDTH, 1);
+        } else if let Some(framebuffer) = &framebuffer {
+            // Scaled up by whole pixels to fill the space
+            let config = framebuffer.config();
+            let (width, height) = (config.width as usize, config.height as usize);
+            let scale = (440 / width).min(350 / height).max(1);
+            let picture: Vec<u32> = framebuffer
+                .picture(&cpu.bus.borrow())
+                .chunks(width)
+                .flat_map(|row| std::iter::repeat(row).take(scale).flatten().copied())
+                .collect();
+            draw_picture(&mut buffer, 2, 2, &picture, width, scale);
         } else {
-            cpu.write(cpu.addr_abs, (cpu.temp & 0x00FF) as u8);
+            draw_zero_page(&status_text, &zero_page, &mut buffer, 2, 2, &notes);
+            if show_chr {
+                draw_chr(&status_text, &cpu, &mut buffer, 2, 182, 16);
+            } else if show_devices {
+                draw_device_log(&status_text, &cpu, &console.device_filter, &mut buffer, 2, 182, 16);
+            } else if show_vectors {
+                draw_vectors(&status_text, &cpu, &mut buffer, VECTORS_PANE.0 as u32, VECTORS_PANE.1 as u32, 16, &symbols, selected_vector, &vector_typed);
+            } else if show_stats {
+                draw_stats(&status_text, &cpu, &mut buffer, 2, 182, 16);
+            } else if show_patches {
+                draw_patches(&status_text, &cpu, &mut buffer, 2, 182, 16);
+            } else if show_calls {
+                draw_calls(&status_text, &cpu, &mut buffer, CALLS_PANE.0 as u32, CALLS_PANE.1 as u32, 16, &symbols, selected_call);
+            } else if show_stack {
+                draw_stack(&status_text, &cpu, &mut buffer, 2, 182, 16, &symbols);
+            } else if ram_detached {
+                clear_area(&mut buffer, 2, 182, 55 * 8, 16 * 10);
+            } else {
+                draw_ram(&status_text, &cpu, &mut buffer, 2, 182, &memory_view, &console.charset, 16, RAM_COLUMNS, &notes);
+            }
         }
-
-
-        0
-    }
-    fn ROR(cpu: &mut cpu6502) -> u8 {
-        cpu.fetch();
-        cpu.temp = ((cpu.get_flag(FLAGS6502::C) << 7) | (cpu.fetched >> 1)) as u16;
-        cpu.set_flag(FLAGS6502::C, (cpu.fetched & 0x01) != 0);
-        cpu.set_flag(FLAGS6502::Z, (cpu.temp & 0x00FF) == 0x00);
-        cpu.set_flag(FLAGS6502::N, (cpu.temp & 0x0080) != 0);
-
-
-        if cpu.lookup[cpu.opcode as usize].addr_mode == cpu6502::IMP {
-            cpu.a = (cpu.temp & 0x00FF) as u8;
+        draw_cpu(&status_text, &cpu, &mut buffer, 448, 2);
+        if show_perf {
+            draw_perf(&status_text, &perf, &mut buffer, 664, 2);
+        }
+        // A call that's returned since it was picked can't be shown
+        selected_call = selected_call.filter(|&i| show_calls && i < cpu.backtrace().len());
+        code_view.set_focus(selected_call.map(|i| cpu.backtrace()[i].site));
+        if code_detached {
+            clear_area(&mut buffer, 448, 72, 43 * 8, 29 * 10);
         } else {
-            cpu.write(cpu.addr_abs, (cpu.temp & 0x00FF) as u8);
+            match &console.cfg {
+                Some(cfg) => draw_cfg(&status_text, &cpu, cfg, &mut code_view, &mut buffer, 448, 72, 29, &symbols),
+                None => draw_code(&status_text, &cpu, &mut buffer, 448, 72, 26, &mut code_view, &notes, &symbols, &console.xrefs),
+            }
+        }
+        for pane in &mut detached {
+            let lines = pane.pane.lines();
+            match (pane.pane, &console.cfg) {
+                (Detachable::Ram, _) => draw_ram(&status_text, &cpu, &mut pane.buffer, 2, 2, &memory_view, &console.charset, lines, 16, &notes),
+                (Detachable::Code, Some(cfg)) => draw_cfg(&status_text, &cpu, cfg, &mut code_view, &mut pane.buffer, 2, 2, lines, &symbols),
+                (Detachable::Code, None) => draw_code(&status_text, &cpu, &mut pane.buffer, 2, 2, lines, &mut code_view, &notes, &symbols, &console.xrefs),
+            }
+            pane.present();
         }
-
-        0
-    }
-
-
-    fn RTI(cpu: &mut cpu6502) -> u8 {
-        cpu.stkp += 1;
-        cpu.status = cpu.read(0x0100u16 + cpu.stkp as u16);
-        cpu.status &= !(FLAGS6502::B as u8);
-        cpu.status &= !(FLAGS6502::U as u8);
-
-        cpu.stkp += 1;
-        cpu.pc = cpu.read(0x0100u16 + cpu.stkp as u16) as u16;
-        cpu.stkp += 1;
-        cpu.pc |= (cpu.read(0x0100u16 + cpu.stkp as u16) as u16) << 8;
-
-        0
-    }
-
-
-    fn RTS(cpu: &mut cpu6502) -> u8 {
-        cpu.stkp += 1;
-        cpu.pc = cpu.read(0x0100u16 + cpu.stkp as u16) as u16;
-        cpu.stkp += 1;
-        cpu.pc |= (cpu.read(0x0100u16 + cpu.stkp as u16) as u16) << 8;
-
-        cpu.pc += 1;
-
-        0
-    }
-    fn SBC(cpu: &mut cpu6502) -> u8 {
-        cpu.fetch();
-
-        // Operating in 16-bit domain to capture carry out
-
-        // We can invert the bottom 8 bits with bitwise xor
-        let value = (cpu.fetched as u16) ^ 0x00FF;
-
-        // Notice this is exactly the same as addition from here!
-        cpu.temp = ((cpu.a as u16) + value + (cpu.get_flag(FLAGS6502::C) as u16));
-        cpu.set_flag(FLAGS6502::C, cpu.temp & 0xFF00 != 0);
-        cpu.set_flag(FLAGS6502::Z, ((cpu.temp & 0x00FF) == 0));
-        cpu.set_flag(FLAGS6502::V, ((cpu.temp ^ (cpu.a as u16)) & (cpu.temp ^ (value)) & 0x0080) != 0);
-        cpu.set_flag(FLAGS6502::N, (cpu.temp & 0x0080) != 0);
-        cpu.a = (cpu.temp & 0x00FF) as u8;
-
-        1
-    }
-    fn SEC(cpu: &mut cpu6502) -> u8 {
-        cpu.set_flag(FLAGS6502::C, true);
-
-        0
-    }
-    fn SED(cpu: &mut cpu6502) -> u8 {
-        cpu.set_flag(FLAGS6502::D, true);
-
-        0
-    }
-    fn SEI(cpu: &mut cpu6502) -> u8 {
-        cpu.set_flag(FLAGS6502::I, true);
-
-        0
-    }
-
-    fn STA(cpu: &mut cpu6502) -> u8 {
-        cpu.write(cpu.addr_abs, cpu.a);
-
-        0
-    }
-
-    fn STX(cpu: &mut cpu6502) -> u8 {
-        cpu.write(cpu.addr_abs, cpu.x);
-
-        0
-    }
-    fn STY(cpu: &mut cpu6502) -> u8 {
-        cpu.write(cpu.addr_abs, cpu.y);
-
-        0
-    }
-    fn TAX(cpu: &mut cpu6502) -> u8 {
-        cpu.x = cpu.a;
-
-        cpu.set_flag(FLAGS6502::Z, cpu.x == 0x00);
-        cpu.set_flag(FLAGS6502::N, (cpu.x & 0x80) != 0);
-
-        0
-    }
-    fn TAY(cpu: &mut cpu6502) -> u8 {
-        cpu.y = cpu.a;
-
-        cpu.set_flag(FLAGS6502::Z, cpu.y == 0x00);
-        cpu.set_flag(FLAGS6502::N, (cpu.y & 0x80) != 0);
-
-        0
-    }
-    fn TSX(cpu: &mut cpu6502) -> u8 {
-        cpu.x = cpu.stkp;
-
-        cpu.set_flag(FLAGS6502::Z, cpu.x == 0x00);
-        cpu.set_flag(FLAGS6502::N, (cpu.x & 0x80) != 0);
-
-        0
-    }
 
 
-    fn TXA(cpu: &mut cpu6502) -> u8 {
-        cpu.a = cpu.x;
+        status_text.draw(&mut buffer, (10, 360), std::format!("X = NES pattern tables    E = Device log    K = RAM characters: {:<30.30}", console.charset.name()).as_str(), 1);
+        status_text.draw(&mut buffer, (10, 370), "SPACE / . = Step / Frame    R = RESET    L = Reload    I = IRQ (hold)    N = NMI    C = Calls", 1);
+        status_text.draw(&mut buffer, (10, 380), std::format!("CTRL+Z = Undo ({})    CTRL+Y = Redo ({})    P = Profiler {:<3}    T = Turbo {:<3} {:>6.2} MHz", debugger.undo_depth(), debugger.redo_depth(), if cpu.is_profiling() { "ON" } else { "OFF" }, if turbo { "ON" } else { "OFF" }, perf.summary().mhz).as_str(), 1);
+        for diagnostic in cpu.diagnostics.drain() {
+            console.print(std::format!("[{:04x}] {}", diagnostic.pc, diagnostic.message()));
 
-        cpu.set_flag(FLAGS6502::Z, cpu.a == 0x00);
-        cpu.set_flag(FLAGS6502::N, (cpu.a & 0x80) != 0);
+            if diagnostic.severity == Severity::Error && debugger.running {
+                debugger.running = false;
+                console.open = true;
+                print_trace_ring_hint(&cpu, &mut console);
+                write_crash_report(&cpu, &diagnostic.message(), rom_path.as_deref(), &symbols, &mut console);
+            }
+        }
 
-        0
-    }
+        status_text.draw(&mut buffer, (10, 390), "~ = Console    S = Stack    H = Opcodes    G = Patches    V = Vectors    F = Timings    1-3 = Demos", 1);
+        draw_console(&status_text, &console, &mut buffer, 10, 404, 17);
+        perf.add_render(render_start.elapsed());
 
+        // We unwrap here as we want this code to exit if it fails. Real applications may want to handle this in a different way
+        window
+            .update_with_buffer(&buffer, WIDTH, HEIGHT)
+            .unwrap();
 
-    fn TXS(cpu: &mut cpu6502) -> u8 {
-        cpu.stkp = cpu.x;
+        perf.end_frame();
 
-        0
+        frame = frame.wrapping_add(1);
+        if frame % SAVE_FLUSH_FRAMES == 0 {
+            if let Err(e) = cpu.flush_cartridge_save() {
+                console.print(std::format!("can't write battery RAM: {}", e));
+            }
+        }
     }
 
+    cpu.flush_cartridge_save().expect("failed to write battery RAM");
 
-    fn TYA(cpu: &mut cpu6502) -> u8 {
-        cpu.a = cpu.y;
-
-        cpu.set_flag(FLAGS6502::Z, cpu.a == 0x00);
-        cpu.set_flag(FLAGS6502::N, (cpu.a & 0x80) != 0);
-
-        0
-    }
 
-    // I capture all "unofficial" opcodes with this function. It is
-    // functionally identical to a NOP
-    fn XXX(cpu: &mut cpu6502) -> u8 {
-        0
+    match notes_path {
+        Some(notes_path) if notes_path.exists() || !notes.is_empty() => notes.save(&notes_path).expect("failed to save notes"),
+        Some(_) => (),
+        None => project.comments = notes.iter().cloned().collect(),
     }
 
-    fn clock(&mut self) {
-        if self.cycles == 0 {
-            self.opcode = self.read(self.pc);
-
-
-            println!("{}", self.lookup[self.opcode as usize].name);
-
-
-            // Always set the unused status flag bit to 1
-            self.set_flag(FLAGS6502::U, true);
-
-            // Increment program counter, we read the opcode byte
-            self.pc += 1;
-
-            // Get Starting number of cycles
-            self.cycles = self.lookup[self.opcode as usize].cycles;
-
-            // Perform fetch of intermmediate data using the
-            // required addressing mode
-            let additional_cycle1 = (self.lookup[self.opcode as usize].addr_mode)(self);
-
-            // Perform operation
-            let additional_cycle2 = (self.lookup[self.opcode as usize].operate)(self);
-
-            // The addressmode and opcode may have altered the number
-            // of cycles this instruction requires before its completed
-            self.cycles += (additional_cycle1 & additional_cycle2);
-
-            // Always set the unused status flag bit to 1
-            self.set_flag(FLAGS6502::U, true);
-
-            println!("Value: {:02x}", self.read(self.addr_abs));
+    if let Some(project_path) = project_path {
+        project.breakpoints = debugger.breakpoints.clone();
+        project.watches = debugger.watches.clone();
+        for (view, shown) in [("stack", show_stack), ("stats", show_stats), ("patches", show_patches), ("calls", show_calls), ("vectors", show_vectors), ("chr", show_chr), ("devices", show_devices), ("perf", show_perf)] {
+            project.layout.insert(view.to_string(), shown);
+        }
+        for pane in [Detachable::Ram, Detachable::Code] {
+            project.layout.insert(detached_key(pane), detached.iter().any(|window| window.pane == pane));
+        }
+        let worth_keeping = !project.breakpoints.is_empty() || !project.symbols.is_empty() || !project.watches.is_empty() || !project.comments.is_empty() || project.layout.values().any(|&shown| shown);
+        if project_path.exists() || worth_keeping {
+            project.save(&project_path).expect("failed to save project");
         }
-
-        // Increment global clock count - This is actually unused unless logging is enabled
-        // but I've kept it in because its a handy watch variable for debugging
-        self.clock_count += 1;
-
-        // Decrement the number of cycles remaining for this instruction
-        self.cycles -= 1;
-    }
-
-    fn read(&mut self, address: u16) -> u8 {
-        self.bus.read(address, false)
     }
 
-    fn write(&mut self, address: u16, value: u8) {
-        self.bus.write(address, value)
+    if let (Some(record_path), Some(recording)) = (record_path, cpu.stop_recording()) {
+        recording.save(&record_path).expect("failed to save recording");
     }
 
+    // Flushes whatever the trace still has buffered
+    cpu.set_trace(None);
 
-    fn reset(&mut self) {
-        // Get address to set program counter to
-        self.addr_abs = 0xFFFC;
-
-
-        let lo = self.read(self.addr_abs + 0) as u16;
-        let hi = self.read(self.addr_abs + 1) as u16;
-
-        println!("lo: {}, hi: {}", lo, hi);
-
-        // Set it
-        self.pc = ((hi << 8) | lo);
-
-        println!("pc: {}", self.pc);
-
-        // Reset internal registers
-        self.a = 0;
-        self.x = 0;
-        self.y = 0;
-        self.stkp = 0xFD;
-        self.status = 0x00 | (FLAGS6502::U as u8);
-
-        // Clear internal helper variables
-        self.addr_rel = 0x0000;
-        self.addr_abs = 0x0000;
-        self.fetched = 0x00;
-
-        // Reset takes time
-        self.cycles = 8;
+    if let (Some(path), Some(timeline)) = (chrome_trace_path, cpu.take_timeline()) {
+        timeline::save(&timeline, &path, &symbols).expect("failed to write Chrome trace");
     }
 
-
-    fn irq(&mut self) {
-        if (self.get_flag(FLAGS6502::I) == 0) {
-            // Push the program counter to the stack. It's 16-bits dont
-            // forget so that takes two pushes
-            self.write(
-                (0x0100u16 + self.stkp as u16),
-                ((self.pc >> 8) & 0x00FF) as u8,
-            );
-            self.stkp -= 1;
-            self.write((0x0100u16 + self.stkp as u16), (self.pc & 0x00FF) as u8);
-            self.stkp -= 1;
-
-            // Then Push the status register to the stack
-            self.set_flag(FLAGS6502::B, false);
-            self.set_flag(FLAGS6502::U, true);
-            self.set_flag(FLAGS6502::I, true);
-            self.write(0x0100u16 + self.stkp as u16, self.status);
-            self.stkp -= 1;
-
-            // Read new program counter location from fixed address
-            self.addr_abs = 0xFFFE;
-            let lo = self.read(self.addr_abs + 0) as u16;
-            let hi = self.read(self.addr_abs + 1) as u16;
-            self.pc = ((hi << 8u16) | lo) as u16;
-
-            // IRQs take time
-            self.cycles = 7;
-        }
+    if let (Some(path), Some(log)) = (access_log_path, cpu.take_access_log()) {
+        log.save(&path).expect("failed to write access log");
     }
 
-    //  #[allow(arithmetic_overflow)]
-    fn nmi(&mut self) {
-        self.write(
-            0x0100u16 + self.stkp as u16,
-            ((self.pc >> 8) & 0x00FF) as u8,
-        );
-        self.stkp -= 1;
-        self.write(0x0100u16 + self.stkp as u16, (self.pc & 0x00FF) as u8);
-        self.stkp -= 1;
-
-        self.set_flag(FLAGS6502::B, false);
-        self.set_flag(FLAGS6502::U, true);
-        self.set_flag(FLAGS6502::I, true);
-        self.write(0x0100u16 + self.stkp as u16, self.status);
-        self.stkp -= 1;
-
-        self.addr_abs = 0xFFFA;
-        let lo = self.read(self.addr_abs + 0) as u16;
-        let hi = self.read(self.addr_abs + 1) as u16;
-        self.pc = ((hi << 8) | lo) as u16;
-
-        self.cycles = 8;
+    if let Some(path) = device_log_path {
+        cpu.device_log().save(&path, &console.device_filter).expect("failed to write device log");
     }
 
-    fn fetch(&mut self) -> u8 {
-        if !(self.lookup[self.opcode as usize].addr_mode == cpu::IMP) {
-            self.fetched = self.read(self.addr_abs - 1);
+    if print_stats {
+        for line in cpu.stats().describe(20) {
+            println!("{}", line);
         }
-
-        return self.fetched;
     }
 
-    fn complete(&mut self) -> bool {
-        self.cycles == 0
+    if let Some(code) = cpu.exit_code() {
+        std::process::exit(code as i32);
     }
+}
 
-    fn connect_bus(&mut self, bus: Bus) {
-        self.bus = bus
-    }
+// Runs up to `cycles` cycles, stopping early at a breakpoint. Returns how
+// many instructions finished.
+fn run_cycles(cpu: &mut cpu6502, debugger: &mut Debugger, console: &mut Console, cycles: u32) -> u64 {
+    let mut instructions = 0;
 
+    for _ in 0..cycles {
+        cpu.clock();
 
-    fn disassemble(&mut self, start: u16, stop: u16) -> BTreeMap<u16, String> {
-        let mut addr = start;
-        let mut value = 0x00u8;
-        let mut lo = 0x00u8;
-        let mut hi = 0x00u8;
-
-        let mut line_addr = 0u16;
-
-        let mut map_lines: BTreeMap<u16, String> = BTreeMap::new();
-
-        while (addr as u32) <= 0xFFFF {
-            line_addr = addr;
-
-            let mut addr_hex = std::format!("${:04x}: ", addr);
-
-            let opcode = self.bus.read(addr, true) as usize;
-            addr += 1;
-
-            addr_hex.push_str(std::format!("{} ", self.lookup[opcode].name).as_str());
-
-            if self.lookup[opcode].addr_mode == cpu::IMP
-            {
-                addr_hex.push_str(" {IMP}");
-            } else if self.lookup[opcode].addr_mode == cpu::IMM
-            {
-                value = self.bus.read(addr, true);
-                addr += 1;
-
-                addr_hex.push_str(std::format!("#${:02x} {}", value, "{IMM}").as_str());
-            } else if self.lookup[opcode].addr_mode == cpu::ZP0
-            {
-                lo = self.bus.read(addr, true);
-                addr += 1;
-                hi = 0x00;
-                addr_hex.push_str(std::format!("${:02x} {}", lo, "{ZP0}").as_str());
-            } else if self.lookup[opcode].addr_mode == cpu::ZPX
-            {
-                lo = self.bus.read(addr, true);
-                addr += 1;
-                hi = 0x00;
-                addr_hex.push_str(std::format!("${:02x} {}", lo, "{ZPX}").as_str());
-            } else if self.lookup[opcode].addr_mode == cpu::ZPY
-            {
-                lo = self.bus.read(addr, true);
-                addr += 1;
-                hi = 0x00;
-                addr_hex.push_str(std::format!("${:02x}, Y {}", lo, "{ZPY}").as_str());
-            } else if self.lookup[opcode].addr_mode == cpu::IZX
-            {
-                lo = self.bus.read(addr, true);
-                addr += 1;
-                hi = 0x00;
-                addr_hex.push_str(std::format!("(${:02x}, X) {}", lo, "{IZX}").as_str());
-            } else if self.lookup[opcode].addr_mode == cpu::IZY
-            {
-                lo = self.bus.read(addr, true);
-                addr += 1;
-                hi = 0x00;
-                addr_hex.push_str(std::format!("(${:02x}, Y) {}", lo, "{IZY}").as_str());
-            } else if self.lookup[opcode].addr_mode == cpu::ABS
-            {
-                lo = self.bus.read(addr, true);
-                addr += 1;
-                hi = self.bus.read(addr, true);
-                addr += 1;
-                addr_hex.push_str(std::format!("${:04x} {}", ((hi as u16) << 8) | (lo as u16), "{ABS}").as_str());
-            } else if self.lookup[opcode].addr_mode == cpu::ABX
-            {
-                lo = self.bus.read(addr, true);
-                addr += 1;
-                hi = self.bus.read(addr, true);
-                addr += 1;
-                addr_hex.push_str(std::format!("${:04x}, X {}", (((hi as u16) << 8) as u16) | (lo as u16), "{ABX}").as_str());
-            } else if self.lookup[opcode].addr_mode == cpu::ABY
-            {
-                lo = self.bus.read(addr, true);
-                addr += 1;
-                hi = self.bus.read(addr, true);
-                addr += 1;
-                addr_hex.push_str(std::format!("${:04x}, Y {}", (((hi as u16) << 8) as u16) | (lo as u16), "{ABY}").as_str());
-            } else if self.lookup[opcode].addr_mode == cpu::IND
-            {
-                lo = self.bus.read(addr, true);
-                addr += 1;
-                hi = self.bus.read(addr, true);
-                addr += 1;
-                addr_hex.push_str(std::format!("$({:04x}) {}", ((hi as u16) << 8) | (lo as u16), "{IND}").as_str());
-            } else if self.lookup[opcode].addr_mode == cpu::REL
-            {
-                value = self.bus.read(addr, true);
-                addr += 1;
-
-                addr_hex.push_str(std::format!("$[{:04x}] {}", (addr + (value as u16)), "{REL}").as_str());
-            }
+        if cpu.complete() {
+            instructions += 1;
 
-            if addr == (0xFFFF - 1) {
+            if debugger.is_breakpoint(cpu.pc) {
+                debugger.running = false;
+                console.print(std::format!("break at ${:04x}", cpu.pc));
+                print_trace_ring_hint(cpu, console);
                 break;
             }
-
-            // Add the formed string to a std::map, using the instruction's
-            // address as the key. This makes it convenient to look for later
-            // as the instructions are variable in length, so a straight up
-            // incremental index is not sufficient.
-
-            map_lines.insert(line_addr, addr_hex);
         }
-
-
-        return map_lines;
     }
-}
 
-
-pub fn decode_hex(s: &str) -> Result<Vec<u8>, ParseIntError> {
-    (0..s.len())
-        .step_by(2)
-        .map(|i| u8::from_str_radix(&s[i..i + 2], 16))
-        .collect()
+    instructions
 }
 
-pub fn encode_hex(bytes: &[u8]) -> String {
-    let mut s = String::with_capacity(bytes.len() * 2);
-    for &b in bytes {
-        write!(&mut s, "{:02x}", b).unwrap();
+// Loads a program file, starting anything without its own reset vector at
+// its entry point. Battery RAM from a cartridge being replaced is written
+// out first. Returns what to tell the user.
+fn load_program(cpu: &mut cpu6502, path: &Path, org: Option<u16>) -> Result<Vec<String>, String> {
+    cpu.flush_cartridge_save().map_err(|e| e.to_string())?;
+
+    let report = cpu.load_any(path, org)?;
+    if !report.sets_reset_vector() {
+        cpu.set_reset_vector(report.entry);
     }
-    s
+    Ok(report.describe())
 }
 
-fn to_hex<T: LowerHex>(number: T, d: u16) -> String {
-    let mut s = String::new();
-
-    if d == 2 {
-        write!(&mut s, "{:02x}", number).unwrap();
+// Assembles `demo` over whatever was loaded and restarts from it. Returns
+// what to tell the user, including how the demo's self-check went.
+fn load_demo(cpu: &mut cpu6502, demo: &Demo) -> Vec<String> {
+    let mut lines = vec![std::format!("demo {}: {}", demo.name, demo.description)];
+    if let Err(e) = demo.load(cpu) {
+        lines.push(e);
+        return lines;
     }
 
-    if d == 4 {
-        write!(&mut s, "{:04x}", number).unwrap();
+    let failures = demo.verify();
+    if failures.is_empty() {
+        lines.push("self-check passed".to_string());
     }
-
-    s
-}
-
-fn print_cpu(cpu: &mut cpu6502)
-{
-    println!("pc: {:02x}", cpu.pc);
-    println!("Acc register: {:02x} [{}]", cpu.a, cpu.a);
-    println!("X register: {:02x} [{}]", cpu.x, cpu.x);
-    println!("Y register: {:02x} [{}]", cpu.y, cpu.y);
-    println!("Status Register: {:02x} [{}] [{:b}]", cpu.status, cpu.status, cpu.status);
-    println!("Stack Pointer: {:02x}", cpu.stkp);
-    println!("cycles: {:02x}", cpu.cycles);
-    println!("fetched: {}", cpu.fetched);
-    println!("Cycles comeplete: {:?}", cpu.complete());
+    lines.extend(failures);
+    lines
 }
 
-const WIDTH: usize = 800;
-const HEIGHT: usize = 600;
-
-fn draw_cpu(status: &StatusText, cpu: &cpu6502, screen: &mut Vec<u32>, x: u32, y: u32) {
-    status.draw(screen, (x as usize, y as usize), "STATUS: ", 1);
-
-
-    status.draw(screen, ((x + 64) as usize, (y) as usize), "N", if cpu.status & (FLAGS6502::N as u8) != 0 { 0xFF00FFFF } else { 0xFF0000FF });
-    status.draw(screen, ((x + 80) as usize, (y) as usize), "V", if cpu.status & (FLAGS6502::V as u8) != 0 { 0xFF00FFFF } else { 0xFF0000FF });
-    status.draw(screen, ((x + 96) as usize, (y) as usize), "-", if cpu.status & (FLAGS6502::U as u8) != 0 { 0xFF00FFFF } else { 0xFF0000FF });
-    status.draw(screen, ((x + 112) as usize, (y) as usize), "B", if cpu.status & (FLAGS6502::B as u8) != 0 { 0xFF00FFFF } else { 0xFF0000FF });
-    status.draw(screen, ((x + 128) as usize, (y) as usize), "D", if cpu.status & (FLAGS6502::D as u8) != 0 { 0xFF00FFFF } else { 0xFF0000FF });
-    status.draw(screen, ((x + 144) as usize, (y) as usize), "I", if cpu.status & (FLAGS6502::I as u8) != 0 { 0xFF00FFFF } else { 0xFF0000FF });
-    status.draw(screen, ((x + 160) as usize, (y) as usize), "Z", if cpu.status & (FLAGS6502::Z as u8) != 0 { 0xFF00FFFF } else { 0xFF0000FF });
-    status.draw(screen, ((x + 178) as usize, (y) as usize), "C", if cpu.status & (FLAGS6502::C as u8) != 0 { 0xFF00FFFF } else { 0xFF0000FF });
-
-    status.draw(screen, (x as usize, (y + 10) as usize), std::format!("PC: ${:04x}", cpu.pc).as_str(), 1);
-    status.draw(screen, (x as usize, (y + 20) as usize), std::format!("A : ${:02x}", cpu.a).as_str(), 1);
-    status.draw(screen, (x as usize, (y + 30) as usize), std::format!("X : ${:02x}", cpu.x).as_str(), 1);
-    status.draw(screen, (x as usize, (y + 40) as usize), std::format!("Y : ${:02x}", cpu.y).as_str(), 1);
-    status.draw(screen, (x as usize, (y + 50) as usize), std::format!("Stack P: ${:#04x}", cpu.stkp).as_str(), 1);
-}
-
-fn draw_ram(status: &StatusText, cpu: &cpu6502, screen: &mut Vec<u32>, x: u32, y: u32, addr: u16, rows: u32, columns: u32)
-{
-    let mut ram_x = x as usize;
-    let mut ram_y = y as usize;
-    let mut naddr = addr;
-
-
-    for row in 0..rows {
-        let mut offset = std::format!("${:04x}:", naddr);
-
-        for column in 0..columns {
-            offset.push_str(std::format!(" {:02x}", cpu.bus.read(naddr, true)).as_str());
-
-            naddr += 1;
-        }
-
-        status.draw(screen, (ram_x, ram_y), offset.as_str(), 1);
-        ram_y += 10;
+// After a stop, points out that the run up to it can still be written out
+fn print_trace_ring_hint(cpu: &cpu6502, console: &mut Console) {
+    if let Some(ring) = cpu.trace_ring().filter(|ring| !ring.is_empty()) {
+        console.print(std::format!("the last {} instructions are kept, \"trace dump\" writes them out", ring.len()));
     }
 }
 
-fn draw_code(status: &StatusText, cpu: &cpu6502, screen: &mut Vec<u32>, x: u32, y: u32, lines: u32, map_lines: &mut BTreeMap<u16, String>) {
-
-    let mut line_y = (lines >> 1) * 10 + y;
-
-
-
-
-    if let Some(instruction) = map_lines.get(&cpu.pc) {
-        status.draw(screen, (x as usize, line_y as usize), instruction, 0x00FF00FF);
-
-        let mut it = map_lines.range_mut((Bound::Excluded(&cpu.pc), Bound::Unbounded));
-
-        while line_y < (lines * 10) + y {
-            line_y += 10;
-
-            if let Some(next_asm) = &it.next() {
-                status.draw(screen, (x as usize, line_y as usize), next_asm.1, 1);
-            } else {
-                break;
-            }
-        }
+// Shows the report in the console and writes it beside the program, or to
+// CRASH_FILE for a demo
+fn write_crash_report(cpu: &cpu6502, reason: &str, rom_path: Option<&str>, symbols: &SymbolTable, console: &mut Console) {
+    let report = CrashReport::capture(cpu, reason, symbols);
+    for line in report.lines() {
+        console.print(line);
     }
 
-    line_y = (lines >> 1) * 10 + y;
-
-    if let Some(instruction) = map_lines.get(&cpu.pc) {
-
-        let mut it = map_lines.range_mut((Bound::Unbounded, Bound::Excluded(&cpu.pc)));
-
-        line_y = (lines >> 1) * 10 + y;
-        while line_y > y {
-            line_y -= 10;
-
-            if let Some(prev_asm) = it.next_back() {
-                status.draw(screen, (x as usize, line_y as usize), prev_asm.1, 1);
-            } else {
-                break;
-            }
-        }
+    let path = rom_path.map_or_else(|| PathBuf::from(CRASH_FILE), CrashReport::path_for_rom);
+    match report.save(&path) {
+        Ok(()) => console.print(std::format!("crash report written to {}", path.display())),
+        Err(e) => console.print(std::format!("can't write crash report: {}", e)),
     }
 }
 
-
-fn main() {
-    let mut code_assemble_bin = String::from("A2 0A 8E 00 00 A2 03 8E 01 00 AC 00 00 A9 00 18 6D 01 00 88 D0 FA 8D 02 00 EA EA EA");
-    let code_assemble_bin = code_assemble_bin.replace(" ", "");
-
-    let code_bin_result = decode_hex(code_assemble_bin.as_str());
-
-    let code_bin = code_bin_result.expect("failed to get result");
-
-    let mut ram_offset = 0x8000;
-
-    let mut cpu = cpu6502::new();
-
-
-    for byte_code in code_bin {
-        cpu.bus.write(ram_offset, byte_code);
-        ram_offset += 1;
+// "32K", or a plain number of bytes
+fn parse_size(s: &str) -> Option<usize> {
+    match s.strip_suffix(['K', 'k']) {
+        Some(kilobytes) => kilobytes.parse::<usize>().ok().map(|k| k * 1024),
+        None => s.parse().ok(),
     }
-
-    let mut value = 0;
-
-    while value <= 0xFFFF {
-        value += 1;
-    }
-
-
-    cpu.bus.write(0xFFFC, 0x00);
-    cpu.bus.write(0xFFFD, 0x80);
-    let mut map_lines = cpu.disassemble(0x0000, 0xFFFF);
-
-    cpu.reset();
-
-
-    let mut buffer: Vec<u32> = vec![0; WIDTH * HEIGHT];
-
-    let mut window = Window::new(
-        "Test - ESC to exit",
-        WIDTH,
-        HEIGHT,
-        WindowOptions::default(),
-    )
-        .unwrap_or_else(|e| {
-            panic!("{}", e);
-        });
-
-    // Limit to max ~60 fps update rate
-    window.limit_update_rate(Some(std::time::Duration::from_micros(16600)));
-
-    let status_text = StatusText::new(WIDTH, HEIGHT, 1);
-
-    while window.is_open() && !window.is_key_down(Key::Escape) {
-        if window.is_key_pressed(Key::R, KeyRepeat::No) {
-            cpu.reset();
-        }
-
-        if window.is_key_pressed(Key::Space, KeyRepeat::No) {
-            loop {
-                cpu.clock();
-
-                if cpu.complete() {
-                    break;
-                }
-            }
-        }
-
-
-        draw_ram(&status_text, &cpu, &mut buffer, 2, 2, 0x0000, 16, 16);
-        draw_ram(&status_text, &cpu, &mut buffer, 2, 182, 0x8000, 16, 16);
-        draw_cpu(&status_text, &cpu, &mut buffer, 448, 2);
-        draw_code(&status_text, &cpu, &mut buffer, 448, 72, 26, &mut map_lines);
-
-
-        status_text.draw(&mut buffer, (10, 370), "SPACE = Step Instruction    R = RESET    I = IRQ    N = NMI", 1);
-
-        // We unwrap here as we want this code to exit if it fails. Real applications may want to handle this in a different way
-        window
-            .update_with_buffer(&buffer, WIDTH, HEIGHT)
-            .unwrap();
-    }
-
-
-    println!("Hello, world! {:?}", FLAGS6502::N as i8);
 }
 
-
-pub struct StatusText {
-    texture: Vec<u32>,
-    width: usize,
-    //height: usize,
-    scale: usize,
+fn hex_addr(s: &str, option: &str) -> u16 {
+    u16::from_str_radix(s.trim_start_matches('$').trim_start_matches("0x"), 16).unwrap_or_else(|_| panic!("{} takes a hex address", option))
 }
 
-#[inline(always)]
-fn color_from_bit(bit: u8) -> u32 {
-    if bit == 0 {
-        0x00000000
-    } else {
-        0xFFFFFFFF
-    }
-}
-
-impl StatusText {
-    pub fn new(width: usize, _height: usize, scale: usize) -> Self {
-        // unpack texture for easier drawing
-        let mut texture = Vec::with_capacity(128 * 128);
-
-        for t in MICROKNIGHT_FONT {
-            texture.push(color_from_bit((t >> 7) & 1));
-            texture.push(color_from_bit((t >> 6) & 1));
-            texture.push(color_from_bit((t >> 5) & 1));
-            texture.push(color_from_bit((t >> 4) & 1));
-            texture.push(color_from_bit((t >> 3) & 1));
-            texture.push(color_from_bit((t >> 2) & 1));
-            texture.push(color_from_bit((t >> 1) & 1));
-            texture.push(color_from_bit(t & 1));
-        }
-
-        Self {
-            texture,
-            width,
-            //height,
-            scale,
-        }
-    }
-
-    pub fn draw(&self, screen: &mut [u32], pos: (usize, usize), text: &str, color: u32) {
-        let mut x = pos.0;
-        let y = pos.1;
-        for c in text.chars() {
-            let mut index = c as usize - ' ' as usize;
-            if index > MICROKNIGHT_LAYOUT.len() as usize {
-                index = 0;
-            }
-
-            let layout = MICROKNIGHT_LAYOUT[index];
-            let texture_offset = (layout.1 as usize * 128) + layout.0 as usize;
-
-            for fy in 0..8 * self.scale {
-                let ty = fy / self.scale;
-                for fx in 0..8 * self.scale {
-                    let tx = fx / self.scale;
-                    let pixel = texture_offset + (ty * 128) + tx;
-                    if pixel != 0 {
-                        screen[((y + fy) * self.width) + fx + x] = self.texture[pixel] * color;
-                    }
-                }
-            }
-
-            x += 8 * self.scale;
-        }
-    }
+// The project layout entry that remembers a pane was in its own window
+fn detached_key(pane: Detachable) -> String {
+    std::format!("detached_{}", pane.name())
 }
-
-
-// Microknight font (128x128 packed with 1 bit per pixel)
-#[rustfmt::skip]
-pub static MICROKNIGHT_FONT: &[u8] = &[
-    0x00, 0x0c, 0x1b, 0x0d, 0x81, 0x03, 0x01, 0xc0, 0x30, 0x18, 0x18, 0x00, 0x00, 0x00, 0x00, 0x00,
-    0x00, 0x0c, 0x1b, 0x0d, 0x87, 0xc4, 0xb3, 0x60, 0x30, 0x30, 0x0c, 0x1b, 0x03, 0x00, 0x00, 0x00,
-    0x00, 0x0c, 0x09, 0x1f, 0xcd, 0x03, 0xe1, 0xc0, 0x10, 0x60, 0x06, 0x0e, 0x03, 0x00, 0x00, 0x00,
-    0x00, 0x0c, 0x00, 0x0d, 0x87, 0xc0, 0xc3, 0xd8, 0x20, 0x60, 0x06, 0x3f, 0x8f, 0xc0, 0x03, 0xe0,
-    0x00, 0x0c, 0x00, 0x1f, 0xc1, 0x61, 0x83, 0x70, 0x00, 0x60, 0x06, 0x0e, 0x03, 0x01, 0x80, 0x00,
-    0x00, 0x00, 0x00, 0x0d, 0x81, 0x63, 0x63, 0x60, 0x00, 0x30, 0x0c, 0x1b, 0x03, 0x01, 0x80, 0x00,
-    0x00, 0x0c, 0x00, 0x0d, 0x87, 0xc6, 0x91, 0xf0, 0x00, 0x18, 0x18, 0x00, 0x00, 0x00, 0x80, 0x00,
-    0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x60, 0x18, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00,
-    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
-    0x00, 0x00, 0x00, 0x03, 0x07, 0xc1, 0xe0, 0x61, 0xf0, 0x70, 0x7f, 0x1e, 0x0f, 0x00, 0x00, 0x00,
-    0x00, 0x03, 0x1e, 0x03, 0x00, 0x60, 0x30, 0x61, 0x80, 0xc0, 0x03, 0x33, 0x19, 0x81, 0x80, 0xc0,
-    0x00, 0x06, 0x33, 0x07, 0x03, 0xc0, 0xe0, 0xc1, 0xf8, 0xfc, 0x06, 0x1f, 0x18, 0xc1, 0x80, 0xc0,
-    0x00, 0x0c, 0x37, 0x83, 0x06, 0x00, 0x31, 0xb0, 0x0c, 0xc6, 0x0c, 0x31, 0x98, 0xc0, 0x00, 0x00,
-    0x00, 0x18, 0x3d, 0x83, 0x0c, 0x02, 0x33, 0x30, 0x8c, 0xc6, 0x0c, 0x31, 0x8f, 0xc0, 0x00, 0x00,
-    0x18, 0x30, 0x39, 0x83, 0x0c, 0x06, 0x33, 0xf9, 0x98, 0xcc, 0x0c, 0x33, 0x00, 0xc1, 0x80, 0xc0,
-    0x18, 0x60, 0x1f, 0x0f, 0xcf, 0xe3, 0xe0, 0x30, 0xf0, 0x78, 0x0c, 0x1e, 0x03, 0x81, 0x80, 0x40,
-    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x80,
-    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
-    0x00, 0x00, 0x00, 0x0f, 0x83, 0x83, 0xc3, 0xe0, 0xf0, 0xf8, 0x7f, 0x3f, 0x87, 0x0c, 0x63, 0xf0,
-    0x18, 0x00, 0x0c, 0x18, 0xc6, 0xc6, 0x63, 0x31, 0x98, 0xcc, 0x60, 0x30, 0x0c, 0x0c, 0x60, 0xc0,
-    0x30, 0x3e, 0x06, 0x00, 0xcd, 0xe6, 0x33, 0xf1, 0x80, 0xc6, 0x7e, 0x3f, 0x18, 0x0c, 0x60, 0xc0,
-    0x60, 0x00, 0x03, 0x07, 0x8f, 0x67, 0xf3, 0x19, 0x80, 0xc6, 0x60, 0x30, 0x19, 0xcf, 0xe0, 0xc0,
-    0x30, 0x3e, 0x06, 0x06, 0x0d, 0xe6, 0x33, 0x19, 0x80, 0xc6, 0x60, 0x30, 0x18, 0xcc, 0x60, 0xc0,
-    0x18, 0x00, 0x0c, 0x00, 0x0c, 0x06, 0x33, 0x31, 0x8c, 0xc6, 0x60, 0x30, 0x18, 0xcc, 0x60, 0xc0,
-    0x00, 0x00, 0x00, 0x06, 0x06, 0x66, 0x33, 0xe0, 0xf8, 0xfc, 0x7f, 0x30, 0x0f, 0xcc, 0x63, 0xf0,
-    0x00, 0x00, 0x00, 0x00, 0x03, 0xc0, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xc0, 0x00, 0x00,
-    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
-    0x0e, 0x63, 0x30, 0x18, 0xcc, 0x63, 0xc3, 0xe0, 0xf0, 0xf8, 0x3c, 0x1f, 0x98, 0xcc, 0x66, 0x30,
-    0x06, 0x66, 0x30, 0x1d, 0xce, 0x66, 0x63, 0x31, 0x98, 0xcc, 0x60, 0x06, 0x18, 0xcc, 0x66, 0x30,
-    0x06, 0x6c, 0x30, 0x1f, 0xcf, 0x66, 0x33, 0x19, 0x8c, 0xc6, 0x3e, 0x06, 0x18, 0xcc, 0x66, 0x30,
-    0x06, 0x78, 0x30, 0x1a, 0xcd, 0xe6, 0x33, 0x19, 0x8c, 0xc6, 0x03, 0x06, 0x18, 0xc6, 0xc6, 0xb0,
-    0xc6, 0x6c, 0x30, 0x18, 0xcc, 0xe6, 0x33, 0xf1, 0x8c, 0xfc, 0x23, 0x06, 0x18, 0xc6, 0xc7, 0xf0,
-    0xc6, 0x66, 0x30, 0x18, 0xcc, 0x66, 0x33, 0x01, 0xac, 0xd8, 0x63, 0x06, 0x18, 0xc3, 0x87, 0x70,
-    0x7c, 0x63, 0x3f, 0x98, 0xcc, 0x63, 0xe3, 0x00, 0xf8, 0xcc, 0x3e, 0x06, 0x0f, 0x83, 0x86, 0x30,
-    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x18, 0x06, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
-    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
-    0xc6, 0x63, 0x3f, 0x87, 0x00, 0x01, 0xc0, 0x40, 0x00, 0x18, 0x00, 0x30, 0x00, 0x00, 0x60, 0x00,
-    0x6c, 0x63, 0x03, 0x06, 0x0c, 0x00, 0xc0, 0xe0, 0x00, 0x18, 0x1e, 0x3e, 0x0f, 0x03, 0xe3, 0xc0,
-    0x38, 0x63, 0x06, 0x06, 0x06, 0x00, 0xc1, 0xb0, 0x00, 0x10, 0x03, 0x33, 0x19, 0x86, 0x66, 0x60,
-    0x38, 0x3e, 0x0c, 0x06, 0x03, 0x00, 0xc0, 0x00, 0x00, 0x08, 0x3f, 0x31, 0x98, 0x0c, 0x67, 0xe0,
-    0x6c, 0x06, 0x18, 0x06, 0x01, 0x80, 0xc0, 0x00, 0x00, 0x00, 0x63, 0x31, 0x98, 0x0c, 0x66, 0x00,
-    0xc6, 0x06, 0x30, 0x06, 0x00, 0xc0, 0xc0, 0x00, 0x00, 0x00, 0x63, 0x31, 0x98, 0xcc, 0x66, 0x30,
-    0xc6, 0x06, 0x3f, 0x87, 0x00, 0x61, 0xc0, 0x00, 0x00, 0x00, 0x3f, 0x3f, 0x0f, 0x87, 0xe3, 0xe0,
-    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0xfc, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
-    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
-    0x38, 0x00, 0x30, 0x03, 0x00, 0xc6, 0x00, 0xe0, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
-    0x6c, 0x3f, 0x3e, 0x00, 0x00, 0x06, 0x60, 0x61, 0x88, 0xf8, 0x3c, 0x3e, 0x07, 0xcf, 0xc3, 0xc0,
-    0x60, 0x63, 0x33, 0x07, 0x01, 0xc6, 0xc0, 0x61, 0xdc, 0xcc, 0x66, 0x33, 0x0c, 0xcc, 0x66, 0x00,
-    0x78, 0x63, 0x31, 0x83, 0x00, 0xc7, 0x80, 0x61, 0xfc, 0xc6, 0x63, 0x31, 0x98, 0xcc, 0x03, 0xe0,
-    0x60, 0x63, 0x31, 0x83, 0x00, 0xc6, 0xc0, 0x61, 0xac, 0xc6, 0x63, 0x31, 0x98, 0xcc, 0x00, 0x30,
-    0x60, 0x3f, 0x31, 0x83, 0x00, 0xc6, 0x60, 0x61, 0x8c, 0xc6, 0x63, 0x31, 0x98, 0xcc, 0x06, 0x30,
-    0x60, 0x03, 0x31, 0x8f, 0xc4, 0xc6, 0x31, 0xf9, 0x8c, 0xc6, 0x3e, 0x3f, 0x0f, 0xcc, 0x03, 0xe0,
-    0x60, 0x3e, 0x00, 0x00, 0x03, 0x80, 0x00, 0x00, 0x00, 0x00, 0x00, 0x30, 0x00, 0xc0, 0x00, 0x00,
-    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
-    0x30, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x18, 0x18, 0x18, 0x1c, 0x87, 0x00, 0x00, 0xc0,
-    0x7c, 0x63, 0x31, 0x98, 0xcc, 0x66, 0x33, 0xf8, 0x30, 0x18, 0x0c, 0x27, 0x0e, 0x00, 0x00, 0x00,
-    0x30, 0x63, 0x31, 0x9a, 0xc6, 0xc6, 0x30, 0x30, 0x30, 0x18, 0x0c, 0x00, 0x1c, 0x00, 0x00, 0xc0,
-    0x30, 0x63, 0x1b, 0x1f, 0xc3, 0x86, 0x30, 0x60, 0x60, 0x18, 0x06, 0x00, 0x18, 0x20, 0x00, 0xc0,
-    0x30, 0x63, 0x1b, 0x0f, 0x83, 0x86, 0x30, 0xc0, 0x30, 0x18, 0x0c, 0x00, 0x10, 0x60, 0x00, 0xc0,
-    0x32, 0x63, 0x0e, 0x0d, 0x86, 0xc3, 0xf1, 0x80, 0x30, 0x18, 0x0c, 0x00, 0x00, 0xe0, 0x00, 0xc0,
-    0x1c, 0x3f, 0x0e, 0x08, 0x8c, 0x60, 0x33, 0xf8, 0x18, 0x18, 0x18, 0x00, 0x01, 0xc0, 0x00, 0xc0,
-    0x00, 0x00, 0x00, 0x00, 0x00, 0x03, 0xe0, 0x00, 0x00, 0x18, 0x00, 0x00, 0x03, 0x80, 0x00, 0x00,
-    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
-    0x30, 0x1c, 0x00, 0x18, 0xc1, 0x83, 0xc1, 0xb0, 0x78, 0x00, 0x00, 0x1f, 0x00, 0x03, 0xc3, 0xe0,
-    0x78, 0x36, 0x31, 0x98, 0xc1, 0x86, 0x00, 0x00, 0x84, 0x7e, 0x1b, 0x03, 0x00, 0x04, 0x20, 0x00,
-    0xcc, 0x30, 0x1f, 0x18, 0xc1, 0x83, 0xe0, 0x01, 0x32, 0xc6, 0x36, 0x00, 0x00, 0x0b, 0x90, 0x00,
-    0xc0, 0x7c, 0x31, 0x8f, 0x80, 0x06, 0x30, 0x01, 0x42, 0xc6, 0x6c, 0x00, 0x0f, 0x8a, 0x50, 0x00,
-    0xc0, 0x30, 0x31, 0x81, 0x81, 0x86, 0x30, 0x01, 0x42, 0x7e, 0x36, 0x00, 0x00, 0x0b, 0x90, 0x00,
-    0xc6, 0x30, 0x1f, 0x07, 0xc1, 0x83, 0xe0, 0x01, 0x32, 0x00, 0x1b, 0x00, 0x00, 0x0a, 0x50, 0x00,
-    0x7c, 0x7f, 0x31, 0x81, 0x81, 0x80, 0x30, 0x00, 0x84, 0x7c, 0x00, 0x00, 0x00, 0x04, 0x20, 0x00,
-    0x30, 0x00, 0x00, 0x00, 0x00, 0x01, 0xe0, 0x00, 0x78, 0x00, 0x00, 0x00, 0x00, 0x03, 0xc0, 0x00,
-    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
-    0x38, 0x00, 0x1c, 0x0e, 0x01, 0x80, 0x00, 0x00, 0x00, 0x00, 0x18, 0x00, 0x00, 0x06, 0x03, 0x00,
-    0x6c, 0x08, 0x06, 0x03, 0x03, 0x06, 0x31, 0xf8, 0x00, 0x00, 0x38, 0x1f, 0x1b, 0x0e, 0x67, 0x30,
-    0x6c, 0x3e, 0x0c, 0x06, 0x06, 0x06, 0x33, 0xd0, 0x30, 0x00, 0x18, 0x31, 0x8d, 0x86, 0xc3, 0x60,
-    0x38, 0x08, 0x18, 0x03, 0x00, 0x06, 0x31, 0xd0, 0x30, 0x00, 0x18, 0x31, 0x86, 0xc7, 0xa3, 0xc0,
-    0x00, 0x00, 0x1e, 0x0e, 0x00, 0x06, 0x30, 0x50, 0x00, 0x00, 0x3c, 0x1f, 0x0d, 0x83, 0x61, 0xf0,
-    0x00, 0x3e, 0x00, 0x00, 0x00, 0x06, 0x30, 0x50, 0x00, 0x00, 0x00, 0x00, 0x1b, 0x06, 0xf3, 0x18,
-    0x00, 0x00, 0x00, 0x00, 0x00, 0x07, 0xe0, 0x50, 0x00, 0x18, 0x00, 0x1f, 0x00, 0x0c, 0xf6, 0x70,
-    0x00, 0x00, 0x00, 0x00, 0x00, 0x06, 0x00, 0x00, 0x00, 0x30, 0x00, 0x00, 0x00, 0x00, 0x30, 0xf8,
-    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
-    0xe0, 0x18, 0x0c, 0x03, 0x03, 0x03, 0x91, 0xb0, 0xf0, 0x3f, 0x3c, 0x0c, 0x03, 0x01, 0x83, 0x60,
-    0x36, 0x00, 0x02, 0x04, 0x0c, 0xc4, 0xe0, 0x01, 0x98, 0x6c, 0x66, 0x02, 0x04, 0x06, 0x60, 0x00,
-    0x6c, 0x18, 0x1e, 0x0f, 0x07, 0x83, 0xc1, 0xe0, 0xf0, 0xcf, 0x60, 0x3f, 0x9f, 0xcf, 0xe7, 0xf0,
-    0x3a, 0x1e, 0x33, 0x19, 0x8c, 0xc6, 0x63, 0x31, 0x98, 0xfc, 0x60, 0x30, 0x18, 0x0c, 0x06, 0x00,
-    0xf6, 0x03, 0x3f, 0x9f, 0xcf, 0xe7, 0xf3, 0xf9, 0xfc, 0xcc, 0x60, 0x3f, 0x1f, 0x8f, 0xc7, 0xe0,
-    0x6f, 0x63, 0x31, 0x98, 0xcc, 0x66, 0x33, 0x19, 0x8c, 0xcc, 0x63, 0x30, 0x18, 0x0c, 0x06, 0x00,
-    0xcf, 0x3e, 0x31, 0x98, 0xcc, 0x66, 0x33, 0x19, 0x8c, 0xcf, 0x3e, 0x3f, 0x9f, 0xcf, 0xe7, 0xf0,
-    0x03, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x18, 0x00, 0x00, 0x00, 0x00, 0x00,
-    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
-    0x30, 0x0c, 0x06, 0x0d, 0x8f, 0x83, 0x90, 0xc0, 0x30, 0x30, 0x39, 0x1b, 0x00, 0x07, 0x81, 0x80,
-    0x08, 0x10, 0x19, 0x80, 0x0c, 0xc4, 0xe0, 0x20, 0x40, 0xcc, 0x4e, 0x00, 0x0f, 0x8c, 0xc0, 0x40,
-    0x7e, 0x3f, 0x1f, 0x8f, 0xcc, 0x67, 0x31, 0xe0, 0xf0, 0x78, 0x3c, 0x1e, 0x1a, 0xcd, 0xe6, 0x30,
-    0x18, 0x0c, 0x06, 0x03, 0x0e, 0x67, 0xb3, 0x31, 0x98, 0xcc, 0x66, 0x33, 0x1f, 0xef, 0x66, 0x30,
-    0x18, 0x0c, 0x06, 0x03, 0x0c, 0x66, 0xf3, 0x19, 0x8c, 0xc6, 0x63, 0x31, 0x9b, 0x6e, 0x66, 0x30,
-    0x18, 0x0c, 0x06, 0x03, 0x0c, 0x66, 0x73, 0x19, 0x8c, 0xc6, 0x63, 0x31, 0x98, 0xec, 0x66, 0x30,
-    0x7e, 0x3f, 0x1f, 0x8f, 0xcf, 0xc6, 0x31, 0xf0, 0xf8, 0x7c, 0x3e, 0x1f, 0x0f, 0xc7, 0xc3, 0xe0,
-    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
-    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
-    0x18, 0x0c, 0x1b, 0x03, 0x0c, 0x00, 0x00, 0xc0, 0x30, 0x18, 0x39, 0x1b, 0x07, 0x80, 0x00, 0x00,
-    0x20, 0x33, 0x00, 0x04, 0x0f, 0x83, 0xc0, 0x20, 0x40, 0x66, 0x4e, 0x00, 0x0c, 0xc7, 0xe3, 0xc0,
-    0xc6, 0x63, 0x31, 0x98, 0xcc, 0xc6, 0x60, 0xf0, 0x78, 0x3c, 0x1e, 0x0f, 0x07, 0x81, 0xb6, 0x60,
-    0xc6, 0x63, 0x31, 0x98, 0xcc, 0x66, 0xe0, 0x18, 0x0c, 0x06, 0x03, 0x01, 0x80, 0xc7, 0xf6, 0x00,
-    0xc6, 0x63, 0x31, 0x8f, 0x8f, 0xc6, 0x31, 0xf8, 0xfc, 0x7e, 0x3f, 0x1f, 0x8f, 0xcd, 0x86, 0x00,
-    0xc6, 0x63, 0x31, 0x81, 0x8c, 0x06, 0x33, 0x19, 0x8c, 0xc6, 0x63, 0x31, 0x98, 0xcd, 0x86, 0x30,
-    0x7c, 0x3e, 0x1f, 0x01, 0x8c, 0x06, 0xe1, 0xf8, 0xfc, 0x7e, 0x3f, 0x1f, 0x8f, 0xc7, 0xf3, 0xe0,
-    0x00, 0x00, 0x00, 0x00, 0x0c, 0x06, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0x80,
-    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
-    0x30, 0x0c, 0x0c, 0x0d, 0x83, 0x00, 0xc0, 0x60, 0xd8, 0x0c, 0x39, 0x0c, 0x03, 0x01, 0x83, 0x90,
-    0x08, 0x10, 0x33, 0x00, 0x00, 0x81, 0x01, 0x98, 0x00, 0x16, 0x4e, 0x02, 0x04, 0x06, 0x64, 0xe0,
-    0x78, 0x3c, 0x1e, 0x0f, 0x03, 0x81, 0xc0, 0xe0, 0x70, 0x3e, 0x7c, 0x1e, 0x0f, 0x07, 0x83, 0xc0,
-    0xfc, 0x7e, 0x3f, 0x1f, 0x81, 0x80, 0xc0, 0x60, 0x30, 0x66, 0x66, 0x33, 0x19, 0x8c, 0xc6, 0x60,
-    0xc0, 0x60, 0x30, 0x18, 0x01, 0x80, 0xc0, 0x60, 0x30, 0xc6, 0x63, 0x31, 0x98, 0xcc, 0x66, 0x30,
-    0xc6, 0x63, 0x31, 0x98, 0xc1, 0x80, 0xc0, 0x60, 0x30, 0xc6, 0x63, 0x31, 0x98, 0xcc, 0x66, 0x30,
-    0x7c, 0x3e, 0x1f, 0x0f, 0x87, 0xe3, 0xf1, 0xf8, 0xfc, 0x7e, 0x63, 0x1f, 0x0f, 0x87, 0xc3, 0xe0,
-    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
-    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
-    0x6c, 0x00, 0x00, 0x06, 0x01, 0x80, 0xc1, 0xb0, 0x30, 0xc0, 0x36, 0x00, 0x00, 0x00, 0x00, 0x00,
-    0x00, 0x0c, 0x1e, 0x01, 0x02, 0x03, 0x30, 0x00, 0x40, 0xc0, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
-    0x78, 0x00, 0x33, 0x18, 0xcc, 0x66, 0x33, 0x19, 0x8c, 0xf8, 0x63, 0x00, 0x00, 0x00, 0x00, 0x00,
-    0xcc, 0x3f, 0x37, 0x98, 0xcc, 0x66, 0x33, 0x19, 0x8c, 0xcc, 0x63, 0x00, 0x00, 0x00, 0x00, 0x00,
-    0xc6, 0x00, 0x3d, 0x98, 0xcc, 0x66, 0x33, 0x19, 0x8c, 0xc6, 0x63, 0x00, 0x00, 0x00, 0x00, 0x00,
-    0xc6, 0x0c, 0x39, 0x98, 0xcc, 0x66, 0x33, 0x18, 0xfc, 0xfc, 0x3f, 0x00, 0x00, 0x00, 0x00, 0x00,
-    0x7c, 0x00, 0x1f, 0x0f, 0xc7, 0xe3, 0xf1, 0xf8, 0x0c, 0xc0, 0x03, 0x00, 0x00, 0x00, 0x00, 0x00,
-    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xf8, 0xc0, 0x3e, 0x00, 0x00, 0x00, 0x00, 0x00,
-    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
-    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
-    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
-];
-
-// Font layout (generated from Angelcode Bitmap Font generator)
-#[rustfmt::skip]
-pub static MICROKNIGHT_LAYOUT: &[(u8, u8)] = &[
-    (0, 0), (9, 0), (18, 0), (27, 0), (36, 0), (45, 0), (54, 0), (63, 0), (72, 0), (81, 0), (90, 0), (99, 0), (108, 0),
-    (117, 0), (0, 9), (9, 9), (18, 9), (27, 9), (36, 9), (45, 9), (54, 9), (63, 9), (72, 9), (81, 9), (90, 9), (99, 9),
-    (108, 9), (117, 9), (0, 18), (9, 18), (18, 18), (27, 18), (36, 18), (45, 18), (54, 18), (63, 18), (72, 18), (81, 18),
-    (90, 18), (99, 18), (108, 18), (117, 18), (0, 27), (9, 27), (18, 27), (27, 27), (36, 27), (45, 27), (54, 27), (63, 27),
-    (72, 27), (81, 27), (90, 27), (99, 27), (108, 27), (117, 27), (0, 36), (9, 36), (18, 36), (27, 36), (36, 36), (45, 36),
-    (54, 36), (63, 36), (72, 36), (81, 36), (90, 36), (99, 36), (108, 36), (117, 36), (0, 45), (9, 45), (18, 45), (27, 45),
-    (36, 45), (45, 45), (54, 45), (63, 45), (72, 45), (81, 45), (90, 45), (99, 45), (108, 45), (117, 45), (0, 54), (9, 54),
-    (18, 54), (27, 54), (36, 54), (45, 54), (54, 54), (63, 54), (72, 54), (81, 54), (90, 54), (99, 54), (0, 0), (0, 0),
-    (0, 0), (0, 0), (0, 0), (0, 0), (0, 0), (0, 0), (0, 0), (0, 0), (0, 0), (0, 0), (0, 0), (0, 0), (0, 0), (0, 0), (0, 0),
-    (0, 0), (0, 0), (0, 0), (0, 0), (0, 0), (0, 0), (0, 0), (0, 0), (0, 0), (0, 0), (0, 0), (0, 0), (0, 0), (0, 0), (0, 0),
-    (108, 54), (117, 54), (0, 63), (9, 63), (18, 63), (27, 63), (36, 63), (45, 63), (54, 63), (63, 63), (72, 63), (81, 63),
-    (90, 63), (99, 63), (108, 63), (117, 63), (0, 72), (9, 72), (18, 72), (27, 72), (36, 72), (45, 72), (54, 72), (63, 72),
-    (72, 72), (81, 72), (90, 72), (99, 72), (108, 72), (117, 72), (0, 81), (9, 81), (18, 81), (27, 81), (36, 81), (45, 81),
-    (54, 81), (63, 81), (72, 81), (81, 81), (90, 81), (99, 81), (108, 81), (117, 81), (0, 90), (9, 90), (18, 90), (27, 90),
-    (36, 90), (45, 90), (54, 90), (63, 90), (72, 90), (81, 90), (90, 90), (99, 90), (108, 90), (117, 90), (0, 99), (9, 99),
-    (18, 99), (27, 99), (36, 99), (45, 99), (54, 99), (63, 99), (72, 99), (81, 99), (90, 99), (99, 99), (108, 99), (117, 99),
-    (0, 108), (9, 108), (18, 108), (27, 108), (36, 108), (45, 108), (54, 108), (63, 108), (72, 108), (81, 108), (90, 108),
-    (99, 108), (108, 108), (117, 108), (0, 117), (9, 117), (18, 117), (27, 117), (36, 117), (45, 117), (54, 117), (63, 117),
-    (72, 117), (81, 117),
-];
@@ -0,0 +1,238 @@
+use crate::cpu6502;
+use crate::expr;
+use crate::symbols::SymbolTable;
+
+// Where the RAM viewer is looking and what it's been asked to find. M
+// opens a field for an address to go to, anything expr.rs takes, and /
+// one for a pattern to search the whole address space for. F3 moves on
+// to the next match and Shift+F3 back to the one before, wrapping round
+// at the ends.
+//
+// Patterns are hex bytes, spaced or not, with ?? matching any byte:
+//
+//   a9 00 8d ?? 02
+//
+// or text in quotes, matched byte for byte:
+//
+//   "READY"
+//
+// Searching reads through the bus without side effects, so it sees what
+// the CPU would see, cartridge banks and devices included.
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Pattern(Vec<Option<u8>>);
+
+impl Pattern {
+    pub fn parse(text: &str) -> Result<Pattern, String> {
+        let text = text.trim();
+        if let Some(quoted) = text.strip_prefix('"') {
+            let quoted = quoted.strip_suffix('"').unwrap_or(quoted);
+            if quoted.is_empty() || !quoted.is_ascii() {
+                return Err(std::format!("can't search for {}", text));
+            }
+            return Ok(Pattern(quoted.bytes().map(Some).collect()));
+        }
+
+        let digits: Vec<char> = text.chars().filter(|c| !c.is_whitespace()).collect();
+        if digits.is_empty() || !digits.len().is_multiple_of(2) {
+            return Err(std::format!("{} isn't whole bytes of hex", text));
+        }
+        let bytes = digits
+            .chunks(2)
+            .map(|pair| match pair {
+                ['?', '?'] => Ok(None),
+                [high, low] => u8::from_str_radix(&std::format!("{}{}", high, low), 16).map(Some).map_err(|_| std::format!("{}{} isn't a byte", high, low)),
+                _ => unreachable!(),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Pattern(bytes))
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl cpu6502 {
+    // Every address a match starts at, in order. Matches can run off the
+    // top of memory and round to the bottom.
+    pub fn find_pattern(&self, pattern: &Pattern) -> Vec<u16> {
+        let bus = self.bus.borrow();
+        (0..=0xFFFFu16)
+            .filter(|&start| {
+                pattern.0.iter().enumerate().all(|(i, byte)| byte.is_none_or(|byte| bus.read(start.wrapping_add(i as u16), true) == byte))
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Field {
+    GoTo,
+    Search,
+}
+
+pub struct MemoryView {
+    // The first byte shown, always the start of a row
+    pub addr: u16,
+    // The field being typed into, and what's been typed
+    pub field: Option<Field>,
+    pub typed: String,
+    pattern: Option<Pattern>,
+    // The match the view was last moved to
+    found: Option<u16>,
+    // The outcome of the last go-to or search, for the header
+    message: String,
+}
+
+impl MemoryView {
+    pub fn new(addr: u16) -> Self {
+        MemoryView { addr: addr & 0xFFF0, field: None, typed: String::new(), pattern: None, found: None, message: String::new() }
+    }
+
+    pub fn open(&mut self, field: Field) {
+        self.field = Some(field);
+        self.typed.clear();
+    }
+
+    // Enter on an empty field just closes it
+    pub fn submit(&mut self, cpu: &cpu6502, symbols: &SymbolTable) {
+        let typed = std::mem::take(&mut self.typed);
+        let field = self.field.take();
+        if typed.trim().is_empty() {
+            return;
+        }
+
+        match field {
+            Some(Field::GoTo) => match expr::eval(&typed, cpu, symbols) {
+                Ok(addr) => {
+                    self.show(addr as u16);
+                    self.message.clear();
+                }
+                Err(e) => self.message = e,
+            },
+            Some(Field::Search) => match Pattern::parse(&typed) {
+                Ok(pattern) => {
+                    self.pattern = Some(pattern);
+                    // The first match from the top of the view on
+                    self.found = Some(self.addr.wrapping_sub(1));
+                    self.step(cpu, true);
+                }
+                Err(e) => self.message = e,
+            },
+            None => {}
+        }
+    }
+
+    // To the next match after the one shown, or the one before it
+    pub fn step(&mut self, cpu: &cpu6502, forward: bool) {
+        let Some(pattern) = &self.pattern else {
+            self.message = "nothing to find, / searches".to_string();
+            return;
+        };
+
+        let matches = cpu.find_pattern(pattern);
+        let from = self.found.unwrap_or(self.addr);
+        let next = if forward {
+            matches.iter().position(|&addr| addr > from).or((!matches.is_empty()).then_some(0))
+        } else {
+            matches.iter().rposition(|&addr| addr < from).or(matches.len().checked_sub(1))
+        };
+
+        match next {
+            Some(i) => {
+                self.found = Some(matches[i]);
+                self.show(matches[i]);
+                self.message = std::format!("${:04x}, {} of {}", matches[i], i + 1, matches.len());
+            }
+            None => {
+                self.found = None;
+                self.message = "not found".to_string();
+            }
+        }
+    }
+
+    // Scrolls by whole rows of 16, wrapping round
+    pub fn scroll(&mut self, rows: i32) {
+        self.addr = self.addr.wrapping_add((rows * 16) as u16);
+    }
+
+    // Whether `addr` is in the match shown, for highlighting
+    pub fn in_match(&self, addr: u16) -> bool {
+        match (self.found, &self.pattern) {
+            (Some(found), Some(pattern)) => (addr.wrapping_sub(found) as usize) < pattern.len(),
+            _ => false,
+        }
+    }
+
+    // The line above the bytes: the field while it's being typed into,
+    // otherwise what the last go-to or search came to
+    pub fn header(&self) -> String {
+        match self.field {
+            Some(Field::GoTo) => std::format!("GO TO {}_", self.typed),
+            Some(Field::Search) => std::format!("FIND {}_", self.typed),
+            None if self.message.is_empty() => "RAM    M = Go to    / = Find    F3 = Next".to_string(),
+            None => std::format!("RAM    {}", self.message),
+        }
+    }
+
+    // The row with `addr` in it goes to the top
+    fn show(&mut self, addr: u16) {
+        self.addr = addr & 0xFFF0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn patterns() {
+        assert_eq!(Pattern::parse("a9 00 8D").unwrap(), Pattern(vec![Some(0xA9), Some(0x00), Some(0x8D)]));
+        assert_eq!(Pattern::parse("a9??8d").unwrap(), Pattern(vec![Some(0xA9), None, Some(0x8D)]));
+        assert_eq!(Pattern::parse("\"OK\"").unwrap(), Pattern(vec![Some(b'O'), Some(b'K')]));
+        assert!(Pattern::parse("a9 0").is_err());
+        assert!(Pattern::parse("zz").is_err());
+        assert!(Pattern::parse("\"\"").is_err());
+    }
+
+    #[test]
+    fn search_and_go_to() {
+        let mut cpu = cpu6502::new();
+        cpu.load_program(b"HELLO", 0x1234);
+        cpu.load_program(b"HELP", 0xC000);
+        cpu.load_program(b"HE", 0xFFFE);
+        cpu.load_program(b"LLO", 0x0000);
+        let symbols = SymbolTable::new();
+        let mut view = MemoryView::new(0x8000);
+
+        view.open(Field::Search);
+        view.typed.push_str("\"HEL\"");
+        view.submit(&cpu, &symbols);
+        assert_eq!((view.addr, view.header().as_str()), (0xC000, "RAM    $c000, 2 of 3"));
+        assert!(view.in_match(0xC002) && !view.in_match(0xC003));
+
+        // On past the top of memory and round, and back again
+        view.step(&cpu, true);
+        assert_eq!(view.addr, 0xFFF0);
+        assert!(view.in_match(0xFFFF) && view.in_match(0x0000));
+        view.step(&cpu, true);
+        assert_eq!(view.addr, 0x1230);
+        view.step(&cpu, false);
+        assert_eq!(view.addr, 0xFFF0);
+
+        view.open(Field::GoTo);
+        view.typed.push_str("$2000 + 5");
+        view.submit(&cpu, &symbols);
+        assert_eq!((view.addr, view.field), (0x2000, None));
+
+        view.open(Field::Search);
+        view.typed.push_str("de ad");
+        view.submit(&cpu, &symbols);
+        assert_eq!((view.addr, view.header().as_str()), (0x2000, "RAM    not found"));
+    }
+}
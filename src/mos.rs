@@ -0,0 +1,285 @@
+use std::io::{BufRead, BufReader};
+
+use crate::diagnostics::{Diagnostic, DiagnosticKind, Severity};
+use crate::{cpu6502, FLAGS6502};
+
+// Just enough of the BBC Micro operating system for language ROMs and test
+// programs written against it to run without the rest of the machine. A
+// JSR to one of the entry points below is serviced here instead, then
+// returns as the real routine would. Characters go wherever the character
+// out trap writes to, see traps.rs.
+
+// Where each call lives, the BBC's own addresses unless moved
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OsEntries {
+    pub osrdch: u16,
+    pub osasci: u16,
+    pub osnewl: u16,
+    pub oswrch: u16,
+    pub osword: u16,
+    pub osbyte: u16,
+}
+
+impl Default for OsEntries {
+    fn default() -> Self {
+        OsEntries { osrdch: 0xFFE0, osasci: 0xFFE3, osnewl: 0xFFE7, oswrch: 0xFFEE, osword: 0xFFF1, osbyte: 0xFFF4 }
+    }
+}
+
+// What OSBYTE $83 and $84 report, the bottom and top of memory a language
+// may use. A model B in mode 7 with no filing system.
+const OSHWM: u16 = 0x0E00;
+const HIMEM: u16 = 0x7C00;
+
+// The character the BBC reads when escape is pressed, returned with the
+// carry set once the input runs out
+const ESCAPE: u8 = 0x1B;
+
+pub struct OsShim {
+    entries: OsEntries,
+    input: Box<dyn BufRead + Send>,
+}
+
+impl OsShim {
+    // Reads keys from stdin
+    pub fn new(entries: OsEntries) -> Self {
+        OsShim::with_input(entries, Box::new(BufReader::new(std::io::stdin())))
+    }
+
+    pub fn with_input(entries: OsEntries, input: Box<dyn BufRead + Send>) -> Self {
+        OsShim { entries, input }
+    }
+
+    pub fn entries(&self) -> OsEntries {
+        self.entries
+    }
+
+    // One key, None at the end of the input. Line ends come back as the
+    // carriage return the BBC uses.
+    fn read_key(&mut self) -> Option<u8> {
+        let key = self.input.fill_buf().ok()?.first().copied()?;
+        self.input.consume(1);
+        Some(if key == b'\n' { b'\r' } else { key })
+    }
+
+    // A whole line without its ending, None at the end of the input
+    fn read_line(&mut self) -> Option<String> {
+        let mut line = String::new();
+        match self.input.read_line(&mut line) {
+            Ok(0) | Err(_) => None,
+            Ok(_) => Some(line.trim_end_matches(['\r', '\n']).to_string()),
+        }
+    }
+}
+
+impl cpu6502 {
+    pub fn set_os_shim(&mut self, shim: Option<OsShim>) {
+        self.os_shim = shim;
+    }
+
+    pub fn os_shim(&self) -> Option<&OsShim> {
+        self.os_shim.as_ref()
+    }
+
+    // Called after a JSR, with the same contract as trap_call: true if the
+    // target was an OS entry point, which has then already returned
+    pub(crate) fn os_call(&mut self) -> bool {
+        let entries = match &self.os_shim {
+            Some(shim) => shim.entries,
+            None => return false,
+        };
+
+        match self.pc {
+            pc if pc == entries.oswrch => self.oswrch(self.a),
+            pc if pc == entries.osasci => self.osasci(self.a),
+            pc if pc == entries.osnewl => {
+                self.osasci(b'\r');
+                self.a = b'\r';
+            }
+            pc if pc == entries.osrdch => self.osrdch(),
+            pc if pc == entries.osbyte => self.osbyte(),
+            pc if pc == entries.osword => self.osword(),
+            _ => return false,
+        }
+
+        true
+    }
+
+    fn oswrch(&mut self, c: u8) {
+        self.trap_char(c);
+    }
+
+    // OSWRCH, except a carriage return starts a new line
+    fn osasci(&mut self, c: u8) {
+        self.trap_char(if c == b'\r' { b'\n' } else { c });
+    }
+
+    fn osrdch(&mut self) {
+        let key = self.os_shim.as_mut().and_then(OsShim::read_key);
+        self.a = key.unwrap_or(ESCAPE);
+        self.set_flag(FLAGS6502::C, key.is_none());
+    }
+
+    fn osbyte(&mut self) {
+        let (x, y) = match self.a {
+            // OS version, 1.20
+            0x00 => (0x01, self.y),
+            // Acknowledge escape
+            0x7E => (0x00, self.y),
+            // INKEY, which never has to wait here
+            0x81 => {
+                let key = self.os_shim.as_mut().and_then(OsShim::read_key);
+                self.set_flag(FLAGS6502::C, key.is_none());
+                match key {
+                    Some(key) => (key, 0x00),
+                    None => (self.x, 0xFF),
+                }
+            }
+            // High order address, an I/O processor
+            0x82 => (0xFF, 0xFF),
+            0x83 => (OSHWM as u8, (OSHWM >> 8) as u8),
+            0x84 => (HIMEM as u8, (HIMEM >> 8) as u8),
+            // Text cursor position, which output to a console doesn't have
+            0x86 => (0x00, 0x00),
+            _ => {
+                self.unhandled_os_call("OSBYTE");
+                (self.x, self.y)
+            }
+        };
+
+        self.x = x;
+        self.y = y;
+    }
+
+    fn osword(&mut self) {
+        if self.a != 0x00 {
+            self.unhandled_os_call("OSWORD");
+            return;
+        }
+
+        // Read a line into the buffer the control block at YX points to
+        let block = ((self.y as u16) << 8) | self.x as u16;
+        let buffer = ((self.read(block.wrapping_add(1)) as u16) << 8) | self.read(block) as u16;
+        let max = self.read(block.wrapping_add(2));
+        let (lowest, highest) = (self.read(block.wrapping_add(3)), self.read(block.wrapping_add(4)));
+
+        let line = match self.os_shim.as_mut().and_then(OsShim::read_line) {
+            Some(line) => line,
+            None => {
+                self.set_flag(FLAGS6502::C, true);
+                return;
+            }
+        };
+
+        // Like the keyboard, characters out of range are refused and the
+        // line stops at its maximum length
+        let accepted: Vec<u8> = line.bytes().filter(|c| (lowest..=highest).contains(c)).take(max as usize).collect();
+        for (i, c) in accepted.iter().enumerate() {
+            self.write(buffer.wrapping_add(i as u16), *c);
+        }
+        self.write(buffer.wrapping_add(accepted.len() as u16), b'\r');
+
+        self.y = accepted.len() as u8;
+        self.set_flag(FLAGS6502::C, false);
+    }
+
+    fn unhandled_os_call(&mut self, call: &'static str) {
+        self.diagnostics.report(Diagnostic {
+            cycle: self.clock_count,
+            pc: self.pc,
+            severity: Severity::Warning,
+            kind: DiagnosticKind::UnhandledOsCall { call, a: self.a },
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{self, Cursor, Write};
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+
+    struct Captured(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Captured {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    // Runs `code` until it reaches `end` with `input` as the keyboard
+    fn run(code: &[u8], end: u16, input: &str) -> (cpu6502, String) {
+        let mut cpu = cpu6502::new();
+        cpu.load_program(code, 0x8000);
+        cpu.set_reset_vector(0x8000);
+        cpu.set_os_shim(Some(OsShim::with_input(OsEntries::default(), Box::new(Cursor::new(input.as_bytes().to_vec())))));
+
+        let output = Arc::new(Mutex::new(Vec::new()));
+        cpu.set_trap_output(Box::new(Captured(output.clone())));
+        cpu.reset();
+
+        for _ in 0..10_000 {
+            cpu.clock();
+            if cpu.complete() && cpu.pc == end {
+                let text = String::from_utf8(output.lock().unwrap().clone()).unwrap();
+                return (cpu, text);
+            }
+        }
+        panic!("never reached ${:04x}", end);
+    }
+
+    #[test]
+    fn console_output() {
+        // LDA #'H' / JSR OSWRCH / LDA #'i' / JSR OSASCI / LDA #$0D / JSR OSASCI / JSR OSNEWL
+        let code = [0xA9, b'H', 0x20, 0xEE, 0xFF, 0xA9, b'i', 0x20, 0xE3, 0xFF, 0xA9, 0x0D, 0x20, 0xE3, 0xFF, 0x20, 0xE7, 0xFF];
+        let (cpu, output) = run(&code, 0x8012, "");
+
+        assert_eq!(output, "Hi\n\n");
+        assert_eq!((cpu.a, cpu.stkp), (0x0D, 0xFD));
+    }
+
+    #[test]
+    fn keys_then_escape_at_the_end_of_input() {
+        // JSR OSRDCH / STA $10 / JSR OSRDCH / STA $11 / JSR OSRDCH / STA $12
+        let code = [0x20, 0xE0, 0xFF, 0x85, 0x10, 0x20, 0xE0, 0xFF, 0x85, 0x11, 0x20, 0xE0, 0xFF, 0x85, 0x12];
+        let (cpu, _) = run(&code, 0x800F, "y\n");
+
+        let keys: Vec<u8> = (0x10..0x13).map(|addr| cpu.bus.borrow().read(addr, true)).collect();
+        assert_eq!(keys, vec![b'y', b'\r', ESCAPE]);
+        assert_eq!(cpu.get_flag(FLAGS6502::C), 1);
+    }
+
+    #[test]
+    fn osword_reads_a_line() {
+        // Control block at $0070: buffer $0300, at most 4 characters, ' ' to '~'
+        let mut code = Vec::new();
+        for (addr, value) in [(0x70, 0x00), (0x71, 0x03), (0x72, 0x04), (0x73, 0x20), (0x74, 0x7E)] {
+            code.extend_from_slice(&[0xA9, value, 0x85, addr]);
+        }
+        // LDX #$70 / LDY #$00 / LDA #$00 / JSR OSWORD
+        code.extend_from_slice(&[0xA2, 0x70, 0xA0, 0x00, 0xA9, 0x00, 0x20, 0xF1, 0xFF]);
+        let (cpu, _) = run(&code, 0x801D, "RUN\tNOW\n");
+
+        // The tab is refused and the line cut at 4
+        let line: Vec<u8> = (0x0300..0x0305).map(|addr| cpu.bus.borrow().read(addr, true)).collect();
+        assert_eq!(line, b"RUNN\r");
+        assert_eq!((cpu.y, cpu.get_flag(FLAGS6502::C)), (4, 0));
+    }
+
+    #[test]
+    fn osbyte_and_unhandled_calls() {
+        // LDA #$84 / JSR OSBYTE / STX $10 / STY $11 / LDA #$EF / JSR OSBYTE
+        let code = [0xA9, 0x84, 0x20, 0xF4, 0xFF, 0x86, 0x10, 0x84, 0x11, 0xA9, 0xEF, 0x20, 0xF4, 0xFF];
+        let (mut cpu, _) = run(&code, 0x800E, "");
+
+        assert_eq!(cpu.bus.borrow().read(0x10, true), 0x00);
+        assert_eq!(cpu.bus.borrow().read(0x11, true), 0x7C);
+        assert!(cpu.diagnostics.drain().iter().any(|d| d.kind == DiagnosticKind::UnhandledOsCall { call: "OSBYTE", a: 0xEF }));
+    }
+}
@@ -0,0 +1,112 @@
+use crate::{cpu, cpu6502, is_documented, AddrModeFn};
+
+// The opcode table as the CPU has it, written out for people checking it
+// against a reference and for tools that want it as data. What's listed is
+// whatever `lookup` holds, so picking a variant first changes the output.
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OpTableFormat {
+    Markdown,
+    Csv,
+}
+
+impl OpTableFormat {
+    pub fn parse(name: &str) -> Option<OpTableFormat> {
+        match name {
+            "" | "md" | "markdown" => Some(OpTableFormat::Markdown),
+            "csv" => Some(OpTableFormat::Csv),
+            _ => None,
+        }
+    }
+}
+
+impl cpu6502 {
+    // The addressing mode's usual short name, IMP for implied
+    pub fn addr_mode_name(&self, opcode: u8) -> &'static str {
+        let mode = self.lookup[opcode as usize].addr_mode;
+        let modes: [(AddrModeFn, &str); 12] = [
+            (cpu::IMP, "IMP"),
+            (cpu::IMM, "IMM"),
+            (cpu::ZP0, "ZP0"),
+            (cpu::ZPX, "ZPX"),
+            (cpu::ZPY, "ZPY"),
+            (cpu::IZX, "IZX"),
+            (cpu::IZY, "IZY"),
+            (cpu::ABS, "ABS"),
+            (cpu::ABX, "ABX"),
+            (cpu::ABY, "ABY"),
+            (cpu::IND, "IND"),
+            (cpu::REL, "REL"),
+        ];
+        modes.iter().find(|(f, _)| *f == mode).map_or("???", |(_, name)| name)
+    }
+
+    // Base cycles, and whether crossing a page takes one more
+    pub fn instruction_cycles(&self, opcode: u8) -> (u8, bool) {
+        let instruction = &self.lookup[opcode as usize];
+        (instruction.cycles, instruction.page_penalty)
+    }
+
+    pub fn dump_optable(&self, format: OpTableFormat) -> String {
+        let mut out = String::new();
+        match format {
+            OpTableFormat::Markdown => {
+                out.push_str("| Opcode | Mnemonic | Mode | Bytes | Cycles | Documented |\n");
+                out.push_str("|--------|----------|------|-------|--------|------------|\n");
+            }
+            OpTableFormat::Csv => out.push_str("opcode,mnemonic,mode,bytes,cycles,page_penalty,documented\n"),
+        }
+
+        for opcode in 0..=0xFFu8 {
+            let (cycles, penalty) = self.instruction_cycles(opcode);
+            let name = self.instruction_name(opcode);
+            let mode = self.addr_mode_name(opcode);
+            let bytes = self.instruction_len(opcode);
+            let documented = is_documented(opcode);
+
+            let line = match format {
+                OpTableFormat::Markdown => {
+                    let cycles = if penalty { std::format!("{}+", cycles) } else { cycles.to_string() };
+                    let documented = if documented { "yes" } else { "no" };
+                    std::format!("| ${:02X} | {} | {} | {} | {} | {} |\n", opcode, name, mode, bytes, cycles, documented)
+                }
+                OpTableFormat::Csv => {
+                    std::format!("{:02X},{},{},{},{},{},{}\n", opcode, name, mode, bytes, cycles, penalty, documented)
+                }
+            };
+            out.push_str(&line);
+        }
+
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn markdown_and_csv() {
+        let cpu = cpu6502::new();
+
+        let markdown = cpu.dump_optable(OpTableFormat::Markdown);
+        assert_eq!(markdown.lines().count(), 2 + 256);
+        assert!(markdown.contains("| $BD | LDA | ABX | 3 | 4+ | yes |"));
+        // BRK has a padding byte after it
+        assert!(markdown.contains("| $00 | BRK | IMM | 2 | 7 | yes |"));
+
+        let csv = cpu.dump_optable(OpTableFormat::Csv);
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines.len(), 1 + 256);
+        assert_eq!(lines[1 + 0x6C], "6C,JMP,IND,3,5,false,true");
+        assert_eq!(lines[1 + 0x91], "91,STA,IZY,2,6,false,true");
+    }
+
+    #[test]
+    fn follows_the_variant() {
+        let mut cpu = cpu6502::new();
+        assert!(cpu.dump_optable(OpTableFormat::Csv).contains("\nCB,???,"));
+        cpu.set_variant(crate::Variant::Cmos65C02);
+        assert!(cpu.dump_optable(OpTableFormat::Csv).contains("\nCB,WAI,IMP,1,3,false,"));
+    }
+}
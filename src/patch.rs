@@ -0,0 +1,217 @@
+use crate::cpu6502;
+
+// Changes to a program that aren't in its image. A poke is a byte written
+// into memory every time the machine is reset, for RAM a program sets up
+// once or a loaded program that sits in RAM. ROM on a cartridge can't be
+// written, so for that there are Game Genie codes, which swap the byte the
+// cartridge gives back for a read of one address. Cheats that hold RAM
+// while a program runs are the debugger's.
+
+// The letters a code is written in, each worth its position
+const GENIE_LETTERS: &[u8; 16] = b"APZLGITYEOXUKSVN";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GameGenie {
+    pub addr: u16,
+    pub value: u8,
+    // Eight letter codes only replace the byte when the cartridge has this
+    // there, so they leave other banks alone
+    pub compare: Option<u8>,
+}
+
+impl GameGenie {
+    // Six or eight letters, as printed in the code books
+    pub fn decode(code: &str) -> Result<GameGenie, String> {
+        let n: Vec<u16> = code
+            .trim()
+            .bytes()
+            .map(|c| GENIE_LETTERS.iter().position(|&letter| letter == c.to_ascii_uppercase()).map(|n| n as u16))
+            .collect::<Option<_>>()
+            .ok_or_else(|| std::format!("'{}' isn't a Game Genie code, they use the letters {}", code.trim(), String::from_utf8_lossy(GENIE_LETTERS)))?;
+        if n.len() != 6 && n.len() != 8 {
+            return Err(std::format!("Game Genie codes are 6 or 8 letters, '{}' is {}", code.trim(), n.len()));
+        }
+
+        // The bits of each half are shuffled across the letters
+        let addr = 0x8000
+            | (n[3] & 7) << 12
+            | (n[5] & 7) << 8
+            | (n[4] & 8) << 8
+            | (n[2] & 7) << 4
+            | (n[1] & 8) << 4
+            | (n[4] & 7)
+            | (n[3] & 8);
+        let value = (n[1] & 7) << 4 | (n[0] & 8) << 4 | (n[0] & 7);
+
+        Ok(if n.len() == 6 {
+            GameGenie { addr, value: (value | (n[5] & 8)) as u8, compare: None }
+        } else {
+            let compare = (n[7] & 7) << 4 | (n[6] & 8) << 4 | (n[6] & 7) | (n[5] & 8);
+            GameGenie { addr, value: (value | (n[7] & 8)) as u8, compare: Some(compare as u8) }
+        })
+    }
+
+    // What a read of the cartridge gives with the code in
+    pub fn apply(&self, addr: u16, data: u8) -> u8 {
+        match self.compare {
+            _ if addr != self.addr => data,
+            Some(compare) if compare != data => data,
+            _ => self.value,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Patch {
+    Poke { addr: u16, value: u8 },
+    Genie(GameGenie),
+}
+
+impl Patch {
+    // "<addr>:<value>" in hex, or a Game Genie code
+    pub fn parse(s: &str) -> Result<Patch, String> {
+        let s = s.trim();
+        let hex = |s: &str| {
+            let s = s.trim();
+            let s = s.strip_prefix('$').or_else(|| s.strip_prefix("0x")).unwrap_or(s);
+            u16::from_str_radix(s, 16).map_err(|_| std::format!("'{}' isn't a hex number", s))
+        };
+
+        match s.split_once(':') {
+            Some((addr, value)) => {
+                let value = hex(value)?;
+                if value > 0xFF {
+                    return Err(std::format!("${:x} doesn't fit in a byte", value));
+                }
+                Ok(Patch::Poke { addr: hex(addr)?, value: value as u8 })
+            }
+            None => GameGenie::decode(s).map(Patch::Genie),
+        }
+    }
+
+    pub fn describe(&self) -> String {
+        match self {
+            Patch::Poke { addr, value } => std::format!("${:04x} = ${:02x} on reset", addr, value),
+            Patch::Genie(GameGenie { addr, value, compare: None }) => std::format!("${:04x} reads ${:02x}", addr, value),
+            Patch::Genie(GameGenie { addr, value, compare: Some(compare) }) => std::format!("${:04x} reads ${:02x} if ${:02x}", addr, value, compare),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PatchEntry {
+    pub patch: Patch,
+    pub enabled: bool,
+}
+
+// Kept on the bus, which needs the codes for every cartridge read. Patches
+// are only ever added, so their numbers stay the same.
+#[derive(Debug, Clone, Default)]
+pub struct Patches {
+    entries: Vec<PatchEntry>,
+}
+
+impl Patches {
+    pub fn new() -> Self {
+        Patches { entries: Vec::new() }
+    }
+
+    pub fn entries(&self) -> &[PatchEntry] {
+        &self.entries
+    }
+
+    // Adds a patch, switched on, and returns its number
+    pub fn add(&mut self, patch: Patch) -> usize {
+        self.entries.push(PatchEntry { patch, enabled: true });
+        self.entries.len() - 1
+    }
+
+    pub fn set(&mut self, index: usize, enabled: bool) -> bool {
+        match self.entries.get_mut(index) {
+            Some(entry) => {
+                entry.enabled = enabled;
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn pokes(&self) -> impl Iterator<Item = (u16, u8)> + '_ {
+        self.entries.iter().filter(|entry| entry.enabled).filter_map(|entry| match entry.patch {
+            Patch::Poke { addr, value } => Some((addr, value)),
+            Patch::Genie(_) => None,
+        })
+    }
+
+    // A byte read from the cartridge, after any codes for its address
+    pub fn cartridge_read(&self, addr: u16, data: u8) -> u8 {
+        self.entries.iter().filter(|entry| entry.enabled).fold(data, |data, entry| match &entry.patch {
+            Patch::Genie(code) => code.apply(addr, data),
+            Patch::Poke { .. } => data,
+        })
+    }
+}
+
+impl cpu6502 {
+    // Pokes take effect at the next reset, codes straight away
+    pub fn add_patch(&mut self, patch: Patch) -> usize {
+        self.bus.borrow_mut().patches.add(patch)
+    }
+
+    pub fn set_patch(&mut self, index: usize, enabled: bool) -> bool {
+        self.bus.borrow_mut().patches.set(index, enabled)
+    }
+
+    pub fn patches(&self) -> Vec<PatchEntry> {
+        self.bus.borrow().patches.entries().to_vec()
+    }
+
+    pub(crate) fn apply_pokes(&mut self) {
+        let pokes: Vec<(u16, u8)> = self.bus.borrow().patches.pokes().collect();
+        for (addr, value) in pokes {
+            self.bus.borrow_mut().write(addr, value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::{Cartridge, MapperRegistry};
+
+    #[test]
+    fn decodes_game_genie_codes() {
+        assert_eq!(GameGenie::decode("GOSSIP"), Ok(GameGenie { addr: 0xD1DD, value: 0x14, compare: None }));
+        assert_eq!(GameGenie::decode("zexpygla"), Ok(GameGenie { addr: 0x94A7, value: 0x02, compare: Some(0x03) }));
+        assert!(GameGenie::decode("GOSSI").is_err());
+        assert!(GameGenie::decode("GOSSIB").is_err());
+
+        assert_eq!(Patch::parse("$0750:09"), Ok(Patch::Poke { addr: 0x0750, value: 0x09 }));
+        assert!(Patch::parse("0750:109").is_err());
+    }
+
+    #[test]
+    fn codes_change_cartridge_reads_and_pokes_land_on_reset() {
+        // NROM-128 with the reset vector at $c000
+        let mut image = b"NES\x1A\x01\x01\x00\x00".to_vec();
+        image.resize(16 + 0x4000 + 0x2000, 0);
+        image[16 + 0x3FFD] = 0xC0;
+        image[16 + 0x11DD] = 0x33;
+        let mut cpu = cpu6502::new();
+        cpu.bus.borrow_mut().insert_cartridge(Cartridge::from_bytes(&image, &MapperRegistry::new()).unwrap());
+
+        // GOSSIP patches $d1dd, which NROM-128 mirrors at $91dd
+        let code = cpu.add_patch(Patch::parse("GOSSIP").unwrap());
+        cpu.add_patch(Patch::Genie(GameGenie { addr: 0x91DD, value: 0x99, compare: Some(0x00) }));
+        assert_eq!(cpu.bus.borrow().read(0xD1DD, true), 0x14);
+        assert_eq!(cpu.bus.borrow().read(0x91DD, true), 0x33);
+        cpu.set_patch(code, false);
+        assert_eq!(cpu.bus.borrow().read(0xD1DD, true), 0x33);
+
+        cpu.add_patch(Patch::Poke { addr: 0x0750, value: 0x09 });
+        assert_eq!(cpu.bus.borrow().read(0x0750, true), 0x00);
+        cpu.reset();
+        assert_eq!((cpu.pc, cpu.bus.borrow().read(0x0750, true)), (0xC000, 0x09));
+        assert_eq!(cpu.patches().len(), 3);
+    }
+}
@@ -0,0 +1,191 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+// How the host is keeping up, so slowness can be pinned on the emulator or
+// on the frontend around it. The frontend reports how long it spent
+// emulating and rendering each frame and the counters average the last
+// few frames.
+
+// Frames the averages are taken over, a second at 60 fps
+const WINDOW: usize = 60;
+
+// Where one frame's wall clock time went
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct FrameTimes {
+    pub emulation: Duration,
+    pub render: Duration,
+    // Start of this frame to the start of the next, including waiting for
+    // vsync and whatever else the frontend does
+    pub total: Duration,
+    pub cycles: u64,
+    pub instructions: u64,
+}
+
+// Averages over the window
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct PerfSummary {
+    // Share of wall clock time spent inside the emulator, 0 to 1
+    pub utilization: f64,
+    pub ns_per_instruction: f64,
+    pub ns_per_cycle: f64,
+    pub render_ms: f64,
+    pub frame_ms: f64,
+    pub fps: f64,
+    // Emulated cycles per wall clock second, in millions
+    pub mhz: f64,
+}
+
+pub struct PerfCounters {
+    frames: VecDeque<FrameTimes>,
+    current: FrameTimes,
+    frame_start: Option<Instant>,
+}
+
+impl PerfCounters {
+    pub fn new() -> Self {
+        PerfCounters {
+            frames: VecDeque::with_capacity(WINDOW),
+            current: FrameTimes::default(),
+            frame_start: None,
+        }
+    }
+
+    pub fn add_emulation(&mut self, elapsed: Duration, cycles: u64, instructions: u64) {
+        self.current.emulation += elapsed;
+        self.current.cycles += cycles;
+        self.current.instructions += instructions;
+    }
+
+    pub fn add_render(&mut self, elapsed: Duration) {
+        self.current.render += elapsed;
+    }
+
+    // Call once per frame, the time between calls is the frame time. The
+    // first call only starts the clock.
+    pub fn end_frame(&mut self) {
+        let now = Instant::now();
+
+        if let Some(start) = self.frame_start {
+            let frame = FrameTimes { total: now - start, ..self.current };
+            self.record(frame);
+        }
+
+        self.current = FrameTimes::default();
+        self.frame_start = Some(now);
+    }
+
+    // A finished frame, for frontends that time frames themselves
+    pub fn record(&mut self, frame: FrameTimes) {
+        if self.frames.len() == WINDOW {
+            self.frames.pop_front();
+        }
+        self.frames.push_back(frame);
+    }
+
+    pub fn frames(&self) -> impl Iterator<Item = &FrameTimes> {
+        self.frames.iter()
+    }
+
+    pub fn summary(&self) -> PerfSummary {
+        let count = self.frames.len();
+        if count == 0 {
+            return PerfSummary::default();
+        }
+
+        let sum = |f: fn(&FrameTimes) -> Duration| self.frames.iter().map(f).sum::<Duration>().as_secs_f64();
+        let emulation = sum(|frame| frame.emulation);
+        let render = sum(|frame| frame.render);
+        let total = sum(|frame| frame.total);
+        let cycles: u64 = self.frames.iter().map(|frame| frame.cycles).sum();
+        let instructions: u64 = self.frames.iter().map(|frame| frame.instructions).sum();
+
+        let per = |seconds: f64, n: u64| if n == 0 { 0.0 } else { seconds * 1e9 / n as f64 };
+
+        PerfSummary {
+            utilization: if total > 0.0 { (emulation / total).min(1.0) } else { 0.0 },
+            ns_per_instruction: per(emulation, instructions),
+            ns_per_cycle: per(emulation, cycles),
+            render_ms: render * 1000.0 / count as f64,
+            frame_ms: total * 1000.0 / count as f64,
+            fps: if total > 0.0 { count as f64 / total } else { 0.0 },
+            mhz: if total > 0.0 { cycles as f64 / total / 1e6 } else { 0.0 },
+        }
+    }
+
+    // Four short lines for the overlay
+    pub fn describe(&self) -> Vec<String> {
+        let s = self.summary();
+
+        vec![
+            std::format!("emu    {:>5.1}%", s.utilization * 100.0),
+            std::format!("insn   {:>5.0}ns", s.ns_per_instruction),
+            std::format!("render {:>5.2}ms", s.render_ms),
+            std::format!("frame  {:>5.1}ms", s.frame_ms),
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(emulation_ms: u64, render_ms: u64, total_ms: u64, instructions: u64) -> FrameTimes {
+        FrameTimes {
+            emulation: Duration::from_millis(emulation_ms),
+            render: Duration::from_millis(render_ms),
+            total: Duration::from_millis(total_ms),
+            cycles: instructions * 3,
+            instructions,
+        }
+    }
+
+    #[test]
+    fn averages_over_the_window() {
+        let mut perf = PerfCounters::new();
+        assert_eq!(perf.summary(), PerfSummary::default());
+
+        perf.record(frame(4, 1, 20, 1000));
+        perf.record(frame(6, 3, 20, 1000));
+
+        let s = perf.summary();
+        assert!((s.utilization - 0.25).abs() < 1e-9);
+        assert!((s.ns_per_instruction - 5000.0).abs() < 1e-6);
+        assert!((s.render_ms - 2.0).abs() < 1e-9);
+        assert!((s.frame_ms - 20.0).abs() < 1e-9);
+        assert!((s.fps - 50.0).abs() < 1e-9);
+        // 6000 cycles in 40ms
+        assert!((s.mhz - 0.15).abs() < 1e-9);
+        assert_eq!(perf.describe()[0], "emu     25.0%");
+    }
+
+    #[test]
+    fn old_frames_drop_out() {
+        let mut perf = PerfCounters::new();
+        perf.record(frame(20, 0, 20, 0));
+        for _ in 0..WINDOW {
+            perf.record(frame(0, 0, 20, 0));
+        }
+
+        assert_eq!(perf.frames().count(), WINDOW);
+        assert_eq!(perf.summary().utilization, 0.0);
+        assert_eq!(perf.summary().ns_per_instruction, 0.0);
+    }
+
+    #[test]
+    fn frames_are_timed_between_calls() {
+        let mut perf = PerfCounters::new();
+        perf.end_frame();
+        perf.add_emulation(Duration::from_millis(1), 100, 40);
+        perf.add_render(Duration::from_millis(2));
+        std::thread::sleep(Duration::from_millis(5));
+        perf.end_frame();
+
+        let frame = *perf.frames().next().unwrap();
+        assert_eq!((frame.cycles, frame.instructions, frame.render), (100, 40, Duration::from_millis(2)));
+        assert!(frame.total >= Duration::from_millis(5));
+
+        // The next frame starts empty
+        perf.end_frame();
+        assert_eq!(perf.frames().nth(1).unwrap().instructions, 0);
+    }
+}
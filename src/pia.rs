@@ -0,0 +1,416 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+use crate::cpu6502;
+use crate::device::BusDevice;
+use crate::interrupts::IrqSource;
+use crate::scheduler::EventQueue;
+
+// A 6520/6821 PIA: two 8 bit ports, each with a direction register, a
+// control register and two control lines.
+//
+//   +0  port A data, or its direction register while CRA bit 2 is clear
+//   +1  CRA
+//   +2  port B data, or its direction register while CRB bit 2 is clear
+//   +3  CRB
+//
+// Control registers:
+//
+//   bit 7    C1 went active, read only
+//   bit 6    C2 went active (C2 an input), read only
+//   bits 5-3 C2: 0ei an input, e picks the rising edge, i lets it interrupt
+//                100 handshake, low after a port A read (port B write)
+//                    and high again on the next C1 edge
+//                101 pulse, low for a cycle after the read (write)
+//                11v held at v
+//   bit 2    data register rather than direction
+//   bits 1-0 C1: bit 1 picks the rising edge, bit 0 lets it interrupt
+//
+// Reading a port's data register clears both of its flags. The cycle long
+// pulse is over by the time anything could look, so it's only seen as a
+// strobe, which is kept for the host along with the port's pins then.
+
+const CR_FLAG1: u8 = 0x80;
+const CR_FLAG2: u8 = 0x40;
+const CR_C2_OUTPUT: u8 = 0x20;
+const CR_DATA: u8 = 0x04;
+
+// Strobes kept for the host before the oldest are dropped
+const MAX_STROBES: usize = 1024;
+
+struct Side {
+    output: u8,
+    direction: u8,
+    control: u8,
+    // What the host has on the pins and the control lines
+    input: u8,
+    c1: bool,
+    c2_in: bool,
+    // C2 as the chip drives it, when it's an output
+    c2_out: bool,
+    // The pins each time C2 strobed
+    strobes: VecDeque<u8>,
+}
+
+impl Side {
+    fn new() -> Side {
+        // Nothing pulling the pins or the lines down
+        Side { output: 0, direction: 0, control: 0, input: 0xFF, c1: true, c2_in: true, c2_out: true, strobes: VecDeque::new() }
+    }
+
+    fn pins(&self) -> u8 {
+        (self.output & self.direction) | (self.input & !self.direction)
+    }
+
+    fn irq(&self) -> bool {
+        let c1 = self.control & CR_FLAG1 != 0 && self.control & 0x01 != 0;
+        let c2 = self.control & CR_FLAG2 != 0 && self.control & (CR_C2_OUTPUT | 0x08) == 0x08;
+        c1 || c2
+    }
+
+    fn c2(&self) -> bool {
+        if self.control & CR_C2_OUTPUT != 0 {
+            self.c2_out
+        } else {
+            self.c2_in
+        }
+    }
+
+    fn set_c1(&mut self, level: bool) {
+        if level != self.c1 && level == (self.control & 0x02 != 0) {
+            self.control |= CR_FLAG1;
+            // The edge that ends a handshake
+            if self.control & 0x38 == 0x20 {
+                self.c2_out = true;
+            }
+        }
+        self.c1 = level;
+    }
+
+    fn set_c2(&mut self, level: bool) {
+        if self.control & CR_C2_OUTPUT == 0 && level != self.c2_in && level == (self.control & 0x10 != 0) {
+            self.control |= CR_FLAG2;
+        }
+        self.c2_in = level;
+    }
+
+    // A port A read or port B write, which handshake and pulse modes answer
+    fn strobe(&mut self) {
+        match self.control & 0x38 {
+            0x20 => self.c2_out = false,
+            0x28 => self.c2_out = true,
+            _ => return,
+        }
+        if self.strobes.len() == MAX_STROBES {
+            self.strobes.pop_front();
+        }
+        let pins = self.pins();
+        self.strobes.push_back(pins);
+    }
+
+    fn write_control(&mut self, data: u8) {
+        self.control = (self.control & (CR_FLAG1 | CR_FLAG2)) | (data & 0x3F);
+        // Manual output follows the bit, a C2 turned output has no flag
+        match data & 0x38 {
+            0x30 => self.c2_out = false,
+            0x38 => self.c2_out = true,
+            0x20 | 0x28 => {}
+            _ => return,
+        }
+        self.control &= !CR_FLAG2;
+    }
+}
+
+struct Chip {
+    sides: [Side; 2],
+    // Where the IRQ outputs go once attached, and what they were last set to
+    wiring: Option<(EventQueue, IrqSource)>,
+    irq_raised: bool,
+}
+
+impl Chip {
+    // IRQA and IRQB are open collector, tied together they're one line
+    fn update_irq(&mut self) {
+        let asserted = self.sides[0].irq() || self.sides[1].irq();
+        if asserted == self.irq_raised {
+            return;
+        }
+        if let Some((events, source)) = &self.wiring {
+            let source = *source;
+            events.schedule_in(0, move |cpu| cpu.set_irq(source, asserted));
+            self.irq_raised = asserted;
+        }
+    }
+}
+
+// The host's side of the ports and control lines, which can be on another
+// thread. Side 0 is A and side 1 is B.
+#[derive(Clone)]
+pub struct PiaPort(Arc<Mutex<Chip>>);
+
+impl PiaPort {
+    fn chip(&self) -> MutexGuard<'_, Chip> {
+        self.0.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn with_side<T>(&self, side: usize, f: impl FnOnce(&mut Side) -> T) -> T {
+        let mut chip = self.chip();
+        let result = f(&mut chip.sides[side]);
+        chip.update_irq();
+        result
+    }
+
+    // What's on the pins: the chip's outputs where the direction register
+    // says it drives them, the host's inputs elsewhere
+    pub fn port_a(&self) -> u8 {
+        self.chip().sides[0].pins()
+    }
+
+    pub fn port_b(&self) -> u8 {
+        self.chip().sides[1].pins()
+    }
+
+    pub fn set_port_a(&self, data: u8) {
+        self.with_side(0, |side| side.input = data);
+    }
+
+    pub fn set_port_b(&self, data: u8) {
+        self.with_side(1, |side| side.input = data);
+    }
+
+    pub fn set_ca1(&self, level: bool) {
+        self.with_side(0, |side| side.set_c1(level));
+    }
+
+    pub fn set_cb1(&self, level: bool) {
+        self.with_side(1, |side| side.set_c1(level));
+    }
+
+    // Only heard while C2 is an input
+    pub fn set_ca2(&self, level: bool) {
+        self.with_side(0, |side| side.set_c2(level));
+    }
+
+    pub fn set_cb2(&self, level: bool) {
+        self.with_side(1, |side| side.set_c2(level));
+    }
+
+    pub fn ca2(&self) -> bool {
+        self.chip().sides[0].c2()
+    }
+
+    pub fn cb2(&self) -> bool {
+        self.chip().sides[1].c2()
+    }
+
+    // Port A's pins each time a read strobed CA2, since the last call
+    pub fn take_strobes_a(&self) -> Vec<u8> {
+        self.chip().sides[0].strobes.drain(..).collect()
+    }
+
+    // Port B's pins each time a write strobed CB2, since the last call
+    pub fn take_strobes_b(&self) -> Vec<u8> {
+        self.chip().sides[1].strobes.drain(..).collect()
+    }
+
+    // Whether the guest has yet to read port A since CA1 last went active
+    pub fn ca1_pending(&self) -> bool {
+        self.chip().sides[0].control & CR_FLAG1 != 0
+    }
+
+    pub fn irq_a(&self) -> bool {
+        self.chip().sides[0].irq()
+    }
+
+    pub fn irq_b(&self) -> bool {
+        self.chip().sides[1].irq()
+    }
+}
+
+pub struct Pia {
+    port: PiaPort,
+}
+
+impl Pia {
+    pub fn new() -> Self {
+        let chip = Chip { sides: [Side::new(), Side::new()], wiring: None, irq_raised: false };
+        Pia { port: PiaPort(Arc::new(Mutex::new(chip))) }
+    }
+
+    pub fn port(&self) -> PiaPort {
+        self.port.clone()
+    }
+
+    // Wires the IRQ outputs to `source`
+    pub fn connect_irq(&mut self, events: EventQueue, source: IrqSource) {
+        let mut chip = self.port.chip();
+        chip.wiring = Some((events, source));
+        chip.update_irq();
+    }
+}
+
+impl BusDevice for Pia {
+    fn read(&mut self, offset: u16) -> u8 {
+        let data = self.peek(offset);
+
+        let side = (offset as usize >> 1) & 1;
+        if offset & 1 == 0 {
+            self.port.with_side(side, |side_state| {
+                if side_state.control & CR_DATA != 0 {
+                    side_state.control &= !(CR_FLAG1 | CR_FLAG2);
+                    if side == 0 {
+                        side_state.strobe();
+                    }
+                }
+            });
+        }
+
+        data
+    }
+
+    fn peek(&self, offset: u16) -> u8 {
+        let chip = self.port.chip();
+        let side = &chip.sides[(offset as usize >> 1) & 1];
+        match offset & 1 {
+            1 => side.control,
+            _ if side.control & CR_DATA == 0 => side.direction,
+            _ => side.pins(),
+        }
+    }
+
+    fn write(&mut self, offset: u16, data: u8) {
+        let side = (offset as usize >> 1) & 1;
+        self.port.with_side(side, |side_state| match offset & 1 {
+            1 => side_state.write_control(data),
+            _ if side_state.control & CR_DATA == 0 => side_state.direction = data,
+            _ => {
+                side_state.output = data;
+                if side == 1 {
+                    side_state.strobe();
+                }
+            }
+        });
+    }
+
+    fn reset(&mut self) {
+        let mut chip = self.port.chip();
+        for side in &mut chip.sides {
+            side.output = 0;
+            side.direction = 0;
+            side.control = 0;
+            side.c2_out = true;
+        }
+        chip.update_irq();
+    }
+}
+
+impl cpu6502 {
+    // Maps a PIA at $base-$base+3 with its IRQ outputs wired to the CPU and
+    // returns the host's end of it
+    pub fn attach_pia(&mut self, base: u16) -> PiaPort {
+        let mut pia = Pia::new();
+        pia.connect_irq(self.events(), self.interrupts.register_source("pia"));
+        let port = pia.port();
+        self.bus.borrow_mut().map("pia", base, base.wrapping_add(3), Box::new(pia));
+        port
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ports_and_direction_registers() {
+        let mut pia = Pia::new();
+        let port = pia.port();
+
+        // Direction registers until bit 2 is set
+        pia.write(0, 0x0F);
+        pia.write(1, CR_DATA);
+        assert_eq!(pia.read(1), CR_DATA);
+        pia.write(0, 0x3C);
+        port.set_port_a(0xA5);
+        assert_eq!(pia.read(0), 0xAC);
+        assert_eq!(port.port_a(), 0xAC);
+        pia.write(1, 0);
+        assert_eq!(pia.read(0), 0x0F);
+
+        pia.write(2, 0xFF);
+        pia.write(3, CR_DATA);
+        pia.write(2, 0x42);
+        assert_eq!((pia.read(2), port.port_b()), (0x42, 0x42));
+    }
+
+    #[test]
+    fn control_lines_flag_and_handshake() {
+        let mut pia = Pia::new();
+        let port = pia.port();
+
+        // CA1 on the rising edge with its interrupt, CA2 handshaking
+        pia.write(1, 0x20 | CR_DATA | 0x03);
+        port.set_ca1(false);
+        assert!(!port.ca1_pending());
+        port.set_ca1(true);
+        assert!(port.ca1_pending() && port.irq_a());
+        assert_eq!(pia.peek(1) & CR_FLAG1, CR_FLAG1);
+        assert!(port.ca2());
+
+        // The read clears the flag and drops CA2 until the next CA1 edge
+        port.set_port_a(0x99);
+        assert_eq!(pia.read(0), 0x99);
+        assert!(!port.ca1_pending() && !port.irq_a());
+        assert!(!port.ca2());
+        assert_eq!(port.take_strobes_a(), [0x99]);
+        port.set_ca1(false);
+        port.set_ca1(true);
+        assert!(port.ca2());
+
+        // CB2 as an input, interrupting on the falling edge
+        pia.write(3, CR_DATA | 0x08);
+        port.set_cb2(false);
+        assert_eq!(pia.peek(3), CR_FLAG2 | CR_DATA | 0x08);
+        assert!(port.irq_b());
+        pia.read(2);
+        assert!(!port.irq_b());
+
+        // CB2 pulsing on each port B write, and held by hand
+        pia.write(3, 0);
+        pia.write(2, 0xFF);
+        pia.write(3, 0x28 | CR_DATA);
+        pia.write(2, b'H');
+        pia.write(2, b'I');
+        assert_eq!(port.take_strobes_b(), [b'H', b'I']);
+        assert!(port.cb2());
+        pia.write(3, 0x30 | CR_DATA);
+        assert!(!port.cb2());
+        pia.write(3, 0x38 | CR_DATA);
+        assert!(port.cb2());
+    }
+
+    #[test]
+    fn interrupts_the_cpu() {
+        let mut cpu = cpu6502::new();
+        // LDA #$05 / STA $A001 (CA1 falling edge, interrupting) / CLI / loop: JMP loop
+        cpu.load_program(&[0xA9, 0x05, 0x8D, 0x01, 0xA0, 0x58, 0x4C, 0x06, 0x80], 0x8000);
+        // irq: LDA $A000 / STA $10 / RTI, the read acknowledges it
+        cpu.load_program(&[0xAD, 0x00, 0xA0, 0x85, 0x10, 0x40], 0x9000);
+        cpu.set_reset_vector(0x8000);
+        cpu.set_irq_vector(0x9000);
+        let port = cpu.attach_pia(0xA000);
+        cpu.reset();
+
+        for _ in 0..20 {
+            cpu.clock();
+        }
+        port.set_port_a(0x5A);
+        port.set_ca1(false);
+        for _ in 0..100 {
+            cpu.clock();
+        }
+
+        assert_eq!(cpu.bus.borrow().read(0x10, true), 0x5A);
+        assert!(cpu.interrupts.asserted_sources().is_empty());
+        assert_eq!(cpu.interrupts.stats.taken, 1);
+    }
+}
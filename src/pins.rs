@@ -0,0 +1,258 @@
+use std::collections::VecDeque;
+
+use crate::accesslog::AccessKind;
+use crate::cpu6502;
+
+// The CPU's pins tick by tick, for lining a run up against a visual6502
+// trace or drawing a logic analyzer. The core makes an instruction's bus
+// cycles all at once on its first tick, so they're handed out here one a
+// tick in the order they were made: the opcode fetch with SYNC up, then
+// operands, data and pushes. The core doesn't make the dummy reads the
+// real part fills its internal cycles with, so the ticks left over at the
+// end of an instruction show the last address again, as a read.
+//
+// Every field is the level on the pin, true for high, so IRQ and NMI are
+// false while asserted and RDY is false while something holds the CPU.
+// The core only sees edges on NMI, so it's shown low from the edge until
+// the CPU takes it.
+//
+// Ticks are only kept once tracing is turned on.
+
+// Ticks kept, the oldest go first
+const HISTORY: usize = 4096;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PinState {
+    pub cycle: u64,
+    pub address: u16,
+    pub data: u8,
+    // High for a read
+    pub rw: bool,
+    // High on an opcode fetch
+    pub sync: bool,
+    pub irq: bool,
+    pub nmi: bool,
+    pub rdy: bool,
+    pub so: bool,
+}
+
+impl Default for PinState {
+    fn default() -> Self {
+        PinState { cycle: 0, address: 0, data: 0, rw: true, sync: false, irq: true, nmi: true, rdy: true, so: true }
+    }
+}
+
+impl PinState {
+    // Column names for describe()
+    pub const HEADER: &'static str = "     cycle  ab    db  rw sync irq nmi rdy so";
+
+    pub fn describe(&self) -> String {
+        std::format!(
+            "{:>10}  {:04x}  {:02x}  {}  {}    {}   {}   {}   {}",
+            self.cycle,
+            self.address,
+            self.data,
+            self.rw as u8,
+            self.sync as u8,
+            self.irq as u8,
+            self.nmi as u8,
+            self.rdy as u8,
+            self.so as u8
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct BusCycle {
+    address: u16,
+    data: u8,
+    rw: bool,
+    // Wait states still to come, see BusDevice::wait_states
+    wait: u8,
+}
+
+#[derive(Default)]
+pub(crate) struct PinTracker {
+    enabled: bool,
+    // The bus cycles of the instruction under way, and how many of them
+    // have been shown
+    cycles: Vec<BusCycle>,
+    shown: usize,
+    // Which of them fetched the opcode
+    sync_at: Option<usize>,
+    // While DMA has the bus accesses are its own, shown on the tick
+    // they're made rather than queued with the instruction's
+    dma: bool,
+    dma_cycle: Option<BusCycle>,
+    last: PinState,
+    history: VecDeque<PinState>,
+}
+
+impl PinTracker {
+    // A new instruction or interrupt sequence is starting
+    pub(crate) fn begin(&mut self) {
+        self.cycles.clear();
+        self.shown = 0;
+        self.sync_at = None;
+    }
+
+    // Kept whether tracing or not, as RDY needs to know what kind of
+    // cycle comes next
+    pub(crate) fn record(&mut self, address: u16, data: u8, kind: AccessKind, wait: u8) {
+        let cycle = BusCycle { address, data, rw: kind == AccessKind::Read, wait };
+        if self.dma {
+            self.dma_cycle = Some(cycle);
+        } else {
+            self.cycles.push(cycle);
+        }
+    }
+
+    // The access just recorded was the opcode fetch
+    pub(crate) fn mark_sync(&mut self) {
+        self.sync_at = self.cycles.len().checked_sub(1);
+    }
+
+    pub(crate) fn start_dma(&mut self) {
+        self.dma = true;
+    }
+
+    // Whether the CPU stops this tick rather than making its next bus
+    // cycle: RDY is low, or the device the cycle is with asked for wait
+    // states, and the cycle is one RDY stops. Left over internal cycles are
+    // reads.
+    pub(crate) fn stalls(&mut self, rdy_low: bool, stops_on_writes: bool) -> bool {
+        match self.cycles.get_mut(self.shown) {
+            Some(bus) if bus.rw || stops_on_writes => {
+                if bus.wait > 0 {
+                    bus.wait -= 1;
+                    true
+                } else {
+                    rdy_low
+                }
+            }
+            Some(_) => false,
+            None => rdy_low,
+        }
+    }
+
+    // With RDY low the cycle the CPU is stopped in is shown, and it's
+    // shown again once RDY lets it finish
+    fn tick(&mut self, cycle: u64, irq: bool, nmi: bool, rdy: bool, so: bool) {
+        let next = if self.dma { self.dma_cycle.take() } else { self.cycles.get(self.shown).copied() };
+        if rdy && !self.dma {
+            self.shown += 1;
+        }
+        let dma = std::mem::take(&mut self.dma);
+        if !self.enabled {
+            return;
+        }
+
+        let mut pins = PinState { cycle, address: self.last.address, data: self.last.data, rw: true, sync: false, irq, nmi, rdy, so };
+        if let Some(bus) = next {
+            pins.address = bus.address;
+            pins.data = bus.data;
+            pins.rw = bus.rw;
+            pins.sync = !dma && self.sync_at.is_some_and(|at| at + rdy as usize == self.shown);
+        }
+
+        self.last = pins;
+        self.history.push_back(pins);
+        if self.history.len() > HISTORY {
+            self.history.pop_front();
+        }
+    }
+}
+
+impl cpu6502 {
+    // Turning it on or off forgets the ticks seen so far
+    pub fn set_pin_tracing(&mut self, enabled: bool) {
+        self.pins = PinTracker { enabled, ..PinTracker::default() };
+    }
+
+    pub fn pin_tracing(&self) -> bool {
+        self.pins.enabled
+    }
+
+    // The pins on the last tick
+    pub fn pins(&self) -> PinState {
+        self.pins.last
+    }
+
+    // The last few thousand ticks, oldest first
+    pub fn pin_history(&self) -> Vec<PinState> {
+        self.pins.history.iter().copied().collect()
+    }
+
+    // Called once a tick, before the clock moves on
+    pub(crate) fn tick_pins(&mut self, rdy: bool) {
+        let irq = !self.interrupts.irq_line();
+        let nmi = !(self.poll.nmi_edge || self.poll.nmi_pending);
+        self.pins.tick(self.clock_count, irq, nmi, rdy, !self.so_low);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dma::DmaTransfer;
+
+    fn start(code: &[u8]) -> cpu6502 {
+        let mut cpu = cpu6502::new();
+        cpu.load_program(code, 0x8000);
+        cpu.set_reset_vector(0x8000);
+        cpu.reset();
+        cpu.run_instructions(0, 100);
+        cpu.set_pin_tracing(true);
+        cpu
+    }
+
+    #[test]
+    fn bus_cycles_one_a_tick() {
+        // LDA #$42 / STA $0200 / INC $10
+        let mut cpu = start(&[0xA9, 0x42, 0x8D, 0x00, 0x02, 0xE6, 0x10]);
+        cpu.bus.borrow_mut().write(0x0010, 0x7F);
+        cpu.run_instructions(3, 100);
+
+        let seen: Vec<(u16, u8, bool, bool)> = cpu.pin_history().iter().map(|pins| (pins.address, pins.data, pins.rw, pins.sync)).collect();
+        assert_eq!(
+            seen,
+            [
+                (0x8000, 0xA9, true, true),
+                (0x8001, 0x42, true, false),
+                (0x8002, 0x8D, true, true),
+                (0x8003, 0x00, true, false),
+                (0x8004, 0x02, true, false),
+                (0x0200, 0x42, false, false),
+                (0x8005, 0xE6, true, true),
+                (0x8006, 0x10, true, false),
+                (0x0010, 0x7F, true, false),
+                (0x0010, 0x80, false, false),
+                // The dummy cycle, which the core doesn't make
+                (0x0010, 0x80, true, false),
+            ]
+        );
+        let history = cpu.pin_history();
+        assert!(history.windows(2).all(|pair| pair[1].cycle == pair[0].cycle + 1));
+        assert!(history.iter().all(|pins| pins.irq && pins.nmi && pins.rdy));
+        assert_eq!(cpu.pins().describe(), std::format!("{:>10}  0010  80  1  0    1   1   1   1", cpu.pins().cycle));
+    }
+
+    #[test]
+    fn interrupts_and_rdy() {
+        // loop: JMP loop
+        let mut cpu = start(&[0x4C, 0x00, 0x80]);
+        cpu.assert_irq(true);
+        cpu.trigger_nmi();
+        cpu.start_dma(DmaTransfer::block(0x8000, 0x0300, 1));
+        cpu.clock();
+        cpu.clock();
+        cpu.clock();
+
+        let history = cpu.pin_history();
+        assert!(history.iter().all(|pins| !pins.irq));
+        // A cycle of setup, then the DMA's read and write with the CPU held
+        let dma: Vec<(bool, u16, bool)> = history.iter().map(|pins| (pins.rdy, pins.address, pins.rw)).collect();
+        assert_eq!(&dma[1..], [(false, 0x8000, true), (false, 0x0300, false)]);
+        assert!(!history[0].nmi && !history[0].rdy);
+    }
+}
@@ -0,0 +1,157 @@
+use crate::Bus;
+
+// Pictures of NES video memory for the debugger: the pattern tables, the
+// palette and the nametables, decoded the way the PPU would draw them.
+// They take the memory as it is, a closure for the PPU's address space
+// and plain slices for palette RAM and a nametable, so they work on
+// whatever holds it. For now that's only the cartridge's CHR, there's no
+// PPU yet to own palette RAM or the nametables.
+//
+// Pixels are 0x00RRGGBB like the rest of the window.
+
+// 16 tiles across and down, 8 pixels each
+pub const PATTERN_TABLE_SIZE: usize = 128;
+pub const NAMETABLE_WIDTH: usize = 256;
+pub const NAMETABLE_HEIGHT: usize = 240;
+
+// The 2C02's colours, indexed by what's in palette RAM
+pub const NES_PALETTE: [u32; 64] = [
+    0x545454, 0x001E74, 0x081090, 0x300088, 0x440064, 0x5C0030, 0x540400, 0x3C1800,
+    0x202A00, 0x083A00, 0x004000, 0x003C00, 0x00323C, 0x000000, 0x000000, 0x000000,
+    0x989698, 0x084CC4, 0x3032EC, 0x5C1EE4, 0x8814B0, 0xA01464, 0x982220, 0x783C00,
+    0x545A00, 0x287200, 0x087C00, 0x007628, 0x006678, 0x000000, 0x000000, 0x000000,
+    0xECEEEC, 0x4C9AEC, 0x787CEC, 0xB062EC, 0xE454EC, 0xEC58B4, 0xEC6A64, 0xD48820,
+    0xA0AA00, 0x74C400, 0x4CD020, 0x38CC6C, 0x38B4CC, 0x3C3C3C, 0x000000, 0x000000,
+    0xECEEEC, 0xA8CCEC, 0xBCBCEC, 0xD4B2EC, 0xECAEEC, 0xECAED4, 0xECB4B0, 0xE4C490,
+    0xCCD278, 0xB4DE78, 0xA8E290, 0x98E2B4, 0xA0D6E4, 0xA0A2A0, 0x000000, 0x000000,
+];
+
+// For pattern tables with no palette to go with them
+pub const GREYS: [u32; 4] = [0x000000, 0x555555, 0xAAAAAA, 0xFFFFFF];
+
+// A tile's 16 bytes, the low bit plane then the high, to a colour index
+// (0-3) for each pixel a row at a time
+pub fn decode_tile(planes: &[u8]) -> [u8; 64] {
+    let mut pixels = [0; 64];
+    for row in 0..8 {
+        let (low, high) = (planes[row], planes[row + 8]);
+        for column in 0..8 {
+            let bit = 7 - column;
+            pixels[row * 8 + column] = (low >> bit & 1) | (high >> bit & 1) << 1;
+        }
+    }
+    pixels
+}
+
+// Pattern table 0 ($0000) or 1 ($1000), 128x128 pixels
+pub fn pattern_table<F: Fn(u16) -> u8>(read: F, table: u16, colours: [u32; 4]) -> Vec<u32> {
+    let mut image = vec![0; PATTERN_TABLE_SIZE * PATTERN_TABLE_SIZE];
+    for tile in 0..256u16 {
+        let base = table * 0x1000 + tile * 16;
+        let planes: Vec<u8> = (0..16).map(|i| read(base + i)).collect();
+        let (tile_x, tile_y) = ((tile % 16) as usize * 8, (tile / 16) as usize * 8);
+        for (i, &index) in decode_tile(&planes).iter().enumerate() {
+            image[(tile_y + i / 8) * PATTERN_TABLE_SIZE + tile_x + i % 8] = colours[index as usize];
+        }
+    }
+    image
+}
+
+// Palette RAM's 32 entries as colours, the background's four palettes
+// then the sprites'
+pub fn palette_colours(palette_ram: &[u8; 32]) -> Vec<u32> {
+    palette_ram.iter().map(|&entry| NES_PALETTE[(entry & 0x3F) as usize]).collect()
+}
+
+// One of the eight four-colour palettes, 0-3 background and 4-7 sprites.
+// Colour 0 is always the shared backdrop at $3F00.
+pub fn sub_palette(palette_ram: &[u8; 32], palette: usize) -> [u32; 4] {
+    let colour = |i: usize| NES_PALETTE[(palette_ram[i] & 0x3F) as usize];
+    [colour(0), colour(palette * 4 + 1), colour(palette * 4 + 2), colour(palette * 4 + 3)]
+}
+
+// A nametable's 1K, tile numbers then the attribute table, drawn with the
+// background pattern table at `pattern_table` (0 or 1)
+pub fn nametable<F: Fn(u16) -> u8>(read: F, table: &[u8], pattern_table: u16, palette_ram: &[u8; 32]) -> Vec<u32> {
+    let mut image = vec![0; NAMETABLE_WIDTH * NAMETABLE_HEIGHT];
+    for cell in 0..960 {
+        let (column, row) = (cell % 32, cell / 32);
+        // Each attribute byte covers 4x4 tiles, two bits for each 2x2
+        let attribute = table[960 + row / 4 * 8 + column / 4];
+        let shift = (row % 4 / 2) * 4 + (column % 4 / 2) * 2;
+        let colours = sub_palette(palette_ram, (attribute >> shift & 3) as usize);
+
+        let base = pattern_table * 0x1000 + table[cell] as u16 * 16;
+        let planes: Vec<u8> = (0..16).map(|i| read(base + i)).collect();
+        for (i, &index) in decode_tile(&planes).iter().enumerate() {
+            image[(row * 8 + i / 8) * NAMETABLE_WIDTH + column * 8 + i % 8] = colours[index as usize];
+        }
+    }
+    image
+}
+
+impl Bus {
+    // The cartridge's CHR as the PPU sees it through the mapper, None
+    // without a cartridge
+    pub fn chr_pattern_table(&self, table: u16, colours: [u32; 4]) -> Option<Vec<u32>> {
+        let cart = self.cartridge()?;
+        Some(pattern_table(|addr| cart.ppu_read(addr).unwrap_or(0), table, colours))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A tile with a diagonal in colour 1 and the rest of its first row in
+    // colour 2
+    fn tile() -> [u8; 16] {
+        let mut planes = [0; 16];
+        for row in 0..8 {
+            planes[row] = 0x80 >> row;
+        }
+        planes[8] = 0x7F;
+        planes
+    }
+
+    #[test]
+    fn decodes_tiles_and_pattern_tables() {
+        let pixels = decode_tile(&tile());
+        assert_eq!(&pixels[..8], &[1, 2, 2, 2, 2, 2, 2, 2]);
+        assert_eq!((pixels[9], pixels[10], pixels[63]), (1, 0, 1));
+
+        // Tile 17 of table 1, one down and one across
+        let chr = |addr: u16| match addr {
+            0x1110..=0x111F => tile()[(addr - 0x1110) as usize],
+            _ => 0,
+        };
+        let image = pattern_table(chr, 1, GREYS);
+        assert_eq!(image.len(), PATTERN_TABLE_SIZE * PATTERN_TABLE_SIZE);
+        assert_eq!(image[8 * PATTERN_TABLE_SIZE + 8], GREYS[1]);
+        assert_eq!(image[8 * PATTERN_TABLE_SIZE + 9], GREYS[2]);
+        assert_eq!(image[0], GREYS[0]);
+        assert_eq!(pattern_table(chr, 0, GREYS)[8 * PATTERN_TABLE_SIZE + 8], GREYS[0]);
+    }
+
+    #[test]
+    fn nametables_take_colours_from_attributes() {
+        let mut palette_ram = [0x0F; 32];
+        palette_ram[0] = 0x21;
+        palette_ram[13] = 0x16;
+        assert_eq!(palette_colours(&palette_ram)[0], NES_PALETTE[0x21]);
+
+        // Every cell is tile 1, solid colour 1. The first attribute byte
+        // gives the 2x2 tiles at the top left palette 0 and the 2x2 to
+        // their right palette 3, the next byte gives its 4x4 palette 0.
+        let chr = |addr: u16| if (0x0010..0x0018).contains(&addr) { 0xFF } else { 0 };
+        let mut table = [1u8; 1024];
+        table[960..].fill(0);
+        table[960] = 0b0000_1100;
+        let image = nametable(chr, &table, 0, &palette_ram);
+
+        assert_eq!(image.len(), NAMETABLE_WIDTH * NAMETABLE_HEIGHT);
+        assert_eq!(image[0], NES_PALETTE[0x0F]);
+        assert_eq!(image[16], NES_PALETTE[0x16]);
+        assert_eq!(image[32], NES_PALETTE[0x0F]);
+    }
+}
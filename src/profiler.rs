@@ -0,0 +1,211 @@
+use std::collections::HashMap;
+
+use crate::symbols::SymbolTable;
+
+// Cycle totals for one subroutine entry point
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ProfileEntry {
+    pub addr: u16,
+    pub calls: u64,
+    // Cycles spent in the subroutine including everything it called
+    pub inclusive: u64,
+    // Cycles spent in the subroutine's own instructions only
+    pub exclusive: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ProfileSort {
+    Inclusive,
+    Exclusive,
+}
+
+struct Frame {
+    target: u16,
+    // Stack pointer right after the JSR pushed its return address. The
+    // matching RTS is the one executed with the same stack pointer.
+    stkp: u8,
+    entered_at: u64,
+    child_cycles: u64,
+}
+
+// Attributes elapsed cycles to subroutines by pairing up JSR and RTS
+pub struct Profiler {
+    stack: Vec<Frame>,
+    entries: HashMap<u16, ProfileEntry>,
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        Profiler {
+            stack: Vec::new(),
+            entries: HashMap::new(),
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.stack.clear();
+        self.entries.clear();
+    }
+
+    // Called by the CPU once per instruction. `stkp` is the stack pointer
+    // before the instruction ran, `end` the cycle at which it completes.
+    pub fn on_instruction(&mut self, opcode: u8, stkp: u8, new_pc: u16, end: u64) {
+        match opcode {
+            // JSR
+            0x20 => {
+                self.stack.push(Frame {
+                    target: new_pc,
+                    stkp: stkp.wrapping_sub(2),
+                    entered_at: end,
+                    child_cycles: 0,
+                });
+            }
+
+            // RTS
+            0x60 => {
+                // Frames below this stack pointer were abandoned (the guest
+                // dropped their return address), close them off too
+                while let Some(frame) = self.stack.last() {
+                    if frame.stkp < stkp {
+                        self.leave(end);
+                    } else {
+                        break;
+                    }
+                }
+
+                // An RTS that doesn't match any JSR is a stack trick, ignore it
+                if let Some(frame) = self.stack.last() {
+                    if frame.stkp == stkp {
+                        self.leave(end);
+                    }
+                }
+            }
+
+            _ => {}
+        }
+    }
+
+    fn leave(&mut self, end: u64) {
+        if let Some(frame) = self.stack.pop() {
+            let elapsed = end.saturating_sub(frame.entered_at);
+
+            let entry = self.entries.entry(frame.target).or_insert(ProfileEntry {
+                addr: frame.target,
+                ..Default::default()
+            });
+            entry.calls += 1;
+            entry.inclusive += elapsed;
+            entry.exclusive += elapsed.saturating_sub(frame.child_cycles);
+
+            if let Some(parent) = self.stack.last_mut() {
+                parent.child_cycles += elapsed;
+            }
+        }
+    }
+
+    pub fn report(&self, sort: ProfileSort) -> Vec<ProfileEntry> {
+        let mut entries: Vec<ProfileEntry> = self.entries.values().copied().collect();
+
+        match sort {
+            ProfileSort::Inclusive => entries.sort_by(|a, b| b.inclusive.cmp(&a.inclusive).then(a.addr.cmp(&b.addr))),
+            ProfileSort::Exclusive => entries.sort_by(|a, b| b.exclusive.cmp(&a.exclusive).then(a.addr.cmp(&b.addr))),
+        }
+
+        entries
+    }
+}
+
+// One line per subroutine, named from the symbol table where it can be
+pub fn format_report(entries: &[ProfileEntry], symbols: &SymbolTable) -> String {
+    let mut s = String::from("ADDR  NAME             CALLS      INCLUSIVE      EXCLUSIVE\n");
+
+    for entry in entries {
+        let name: String = symbols.name_of(entry.addr).unwrap_or("").chars().take(16).collect();
+        s.push_str(std::format!("${:04x} {:<16} {:>6} {:>14} {:>14}\n", entry.addr, name, entry.calls, entry.inclusive, entry.exclusive).as_str());
+    }
+
+    s
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const JSR: u8 = 0x20;
+    const RTS: u8 = 0x60;
+    const NOP: u8 = 0xEA;
+
+    fn entry(profiler: &Profiler, addr: u16) -> ProfileEntry {
+        *profiler.report(ProfileSort::Inclusive).iter().find(|entry| entry.addr == addr).unwrap()
+    }
+
+    #[test]
+    fn nested_calls_split_inclusive_and_exclusive() {
+        let mut profiler = Profiler::new();
+
+        // outer at $9000 runs from cycle 6 to 40 and calls inner from 16 to 28
+        profiler.on_instruction(JSR, 0xFD, 0x9000, 6);
+        profiler.on_instruction(NOP, 0xFB, 0x9001, 8);
+        profiler.on_instruction(JSR, 0xFB, 0xA000, 16);
+        profiler.on_instruction(RTS, 0xF9, 0x9005, 28);
+        profiler.on_instruction(RTS, 0xFB, 0x8003, 40);
+
+        let outer = entry(&profiler, 0x9000);
+        let inner = entry(&profiler, 0xA000);
+
+        assert_eq!((outer.calls, outer.inclusive, outer.exclusive), (1, 34, 22));
+        assert_eq!((inner.calls, inner.inclusive, inner.exclusive), (1, 12, 12));
+    }
+
+    #[test]
+    fn calls_accumulate_and_sort() {
+        let mut profiler = Profiler::new();
+
+        for i in 0..3 {
+            profiler.on_instruction(JSR, 0xFD, 0x9000, i * 100);
+            profiler.on_instruction(RTS, 0xFB, 0x8003, i * 100 + 10);
+        }
+        profiler.on_instruction(JSR, 0xFD, 0xA000, 1000);
+        profiler.on_instruction(RTS, 0xFB, 0x8003, 1050);
+
+        let report = profiler.report(ProfileSort::Exclusive);
+        assert_eq!(report[0].addr, 0xA000);
+        assert_eq!((report[1].calls, report[1].exclusive), (3, 30));
+    }
+
+    #[test]
+    fn abandoned_frames_are_closed() {
+        let mut profiler = Profiler::new();
+
+        // The inner routine drops its return address (PLA PLA) and the
+        // outer RTS returns straight past it
+        profiler.on_instruction(JSR, 0xFD, 0x9000, 0);
+        profiler.on_instruction(JSR, 0xFB, 0xA000, 10);
+        profiler.on_instruction(RTS, 0xFB, 0x8003, 30);
+
+        assert_eq!(entry(&profiler, 0xA000).inclusive, 20);
+        assert_eq!(entry(&profiler, 0x9000).inclusive, 30);
+        assert_eq!(entry(&profiler, 0x9000).exclusive, 10);
+    }
+
+    #[test]
+    fn unmatched_rts_is_ignored() {
+        let mut profiler = Profiler::new();
+
+        profiler.on_instruction(RTS, 0xFD, 0x1234, 10);
+        assert!(profiler.report(ProfileSort::Inclusive).is_empty());
+    }
+
+    #[test]
+    fn report_uses_symbols() {
+        let mut profiler = Profiler::new();
+        profiler.on_instruction(JSR, 0xFD, 0x9000, 0);
+        profiler.on_instruction(RTS, 0xFB, 0x8003, 10);
+
+        let mut symbols = SymbolTable::new();
+        symbols.insert("multiply", 0x9000);
+
+        let text = format_report(&profiler.report(ProfileSort::Inclusive), &symbols);
+        assert!(text.lines().nth(1).unwrap().starts_with("$9000 multiply"));
+    }
+}
@@ -0,0 +1,280 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::annotations::{parse_range, Annotation};
+
+// The debugging set up for one ROM, kept beside it so that opening the same
+// image again brings back where things were left: breakpoints, watch
+// expressions, symbol files, comments on addresses and which views were
+// open.
+//
+// It's written as TOML, but read back by the small parser below, which
+// takes the subset written here: strings, integers, booleans, arrays of
+// them on one line, and one level of tables.
+//
+//   symbols = ["game.sym"]
+//   breakpoints = [0x8000, 0x80f3]
+//   watches = ["$10", "x + y"]
+//
+//   [layout]
+//   stack = true
+//
+//   [comments]
+//   0x8000 = "reset, clears RAM"
+//   0x8100-0x81ff = "sprite table"
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Project {
+    pub symbols: Vec<String>,
+    pub breakpoints: BTreeSet<u16>,
+    pub watches: Vec<String>,
+    // Views by name and whether they're shown
+    pub layout: BTreeMap<String, bool>,
+    // The notes made in the debugger, keyed by the address or range
+    pub comments: Vec<Annotation>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Value {
+    Str(String),
+    Int(i64),
+    Bool(bool),
+    Array(Vec<Value>),
+}
+
+impl Project {
+    pub fn new() -> Self {
+        Project::default()
+    }
+
+    // "game.nes" keeps its project in "game.nes.crust.toml"
+    pub fn path_for_rom<P: AsRef<Path>>(rom: P) -> PathBuf {
+        let mut path = rom.as_ref().as_os_str().to_owned();
+        path.push(".crust.toml");
+        PathBuf::from(path)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == Project::default()
+    }
+
+    pub fn shows(&self, view: &str) -> bool {
+        self.layout.get(view).copied().unwrap_or(false)
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Project> {
+        let text = fs::read_to_string(path)?;
+        Project::parse(&text).map_err(|why| io::Error::new(io::ErrorKind::InvalidData, why))
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        fs::write(path, self.to_toml())
+    }
+
+    pub fn to_toml(&self) -> String {
+        let strings = |items: &[String]| items.iter().map(|item| quote(item)).collect::<Vec<String>>().join(", ");
+        let breakpoints: Vec<String> = self.breakpoints.iter().map(|addr| std::format!("0x{:04x}", addr)).collect();
+
+        let mut s = String::new();
+        s.push_str(&std::format!("symbols = [{}]\n", strings(&self.symbols)));
+        s.push_str(&std::format!("breakpoints = [{}]\n", breakpoints.join(", ")));
+        s.push_str(&std::format!("watches = [{}]\n", strings(&self.watches)));
+        if !self.layout.is_empty() {
+            s.push_str("\n[layout]\n");
+            for (view, shown) in &self.layout {
+                s.push_str(&std::format!("{} = {}\n", view, shown));
+            }
+        }
+        if !self.comments.is_empty() {
+            s.push_str("\n[comments]\n");
+            for comment in &self.comments {
+                let key = if comment.start == comment.end {
+                    std::format!("0x{:04x}", comment.start)
+                } else {
+                    std::format!("0x{:04x}-0x{:04x}", comment.start, comment.end)
+                };
+                s.push_str(&std::format!("{} = {}\n", key, quote(&comment.text)));
+            }
+        }
+        s
+    }
+
+    // Keys it doesn't know are skipped, so newer files still load
+    pub fn parse(text: &str) -> Result<Project, String> {
+        let mut project = Project::new();
+        let mut table = String::new();
+
+        for (number, line) in text.lines().enumerate() {
+            let line = strip_comment(line).trim();
+            if line.is_empty() {
+                continue;
+            }
+            let at = |why: String| std::format!("line {}: {}", number + 1, why);
+
+            if let Some(name) = line.strip_prefix('[').and_then(|rest| rest.strip_suffix(']')) {
+                table = name.trim().to_string();
+                continue;
+            }
+
+            let (key, value) = line.split_once('=').ok_or_else(|| at("expected key = value".to_string()))?;
+            let (key, value) = (key.trim(), parse_value(value.trim()).map_err(at)?);
+
+            match (table.as_str(), key, value) {
+                ("", "symbols", Value::Array(items)) => project.symbols = strings(items).map_err(at)?,
+                ("", "watches", Value::Array(items)) => project.watches = strings(items).map_err(at)?,
+                ("", "breakpoints", Value::Array(items)) => {
+                    for item in items {
+                        match item {
+                            Value::Int(addr @ 0..=0xFFFF) => project.breakpoints.insert(addr as u16),
+                            _ => return Err(at("breakpoints are addresses".to_string())),
+                        };
+                    }
+                }
+                ("layout", view, Value::Bool(shown)) => {
+                    project.layout.insert(view.to_string(), shown);
+                }
+                ("comments", range, Value::Str(text)) => {
+                    let (start, end) = parse_range(range).ok_or_else(|| at(std::format!("'{}' isn't an address or range", range)))?;
+                    project.comments.push(Annotation { start, end, text });
+                }
+                ("", "symbols" | "watches" | "breakpoints", _) | ("layout" | "comments", _, _) => return Err(at(std::format!("wrong type for {}", key))),
+                _ => (),
+            }
+        }
+
+        Ok(project)
+    }
+}
+
+fn strings(items: Vec<Value>) -> Result<Vec<String>, String> {
+    items
+        .into_iter()
+        .map(|item| match item {
+            Value::Str(s) => Ok(s),
+            _ => Err("expected strings".to_string()),
+        })
+        .collect()
+}
+
+fn quote(s: &str) -> String {
+    std::format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+// Everything before a # that isn't inside a string
+fn strip_comment(line: &str) -> &str {
+    let mut in_string = false;
+    let mut escaped = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if in_string => escaped = true,
+            '"' => in_string = !in_string,
+            '#' if !in_string => return &line[..i],
+            _ => (),
+        }
+    }
+    line
+}
+
+fn parse_value(text: &str) -> Result<Value, String> {
+    let (value, rest) = parse_partial(text)?;
+    if !rest.trim().is_empty() {
+        return Err(std::format!("unexpected '{}'", rest.trim()));
+    }
+    Ok(value)
+}
+
+// A value from the front of `text`, and what's left after it
+fn parse_partial(text: &str) -> Result<(Value, &str), String> {
+    let text = text.trim_start();
+
+    if let Some(mut rest) = text.strip_prefix('[') {
+        let mut items = Vec::new();
+        loop {
+            rest = rest.trim_start();
+            if let Some(after) = rest.strip_prefix(']') {
+                return Ok((Value::Array(items), after));
+            }
+            let (item, after) = parse_partial(rest)?;
+            items.push(item);
+            rest = after.trim_start();
+            rest = match (rest.strip_prefix(','), rest.starts_with(']')) {
+                (Some(after), _) => after,
+                (None, true) => rest,
+                _ => return Err("expected , or ] in array".to_string()),
+            };
+        }
+    }
+
+    if let Some(rest) = text.strip_prefix('"') {
+        let mut s = String::new();
+        let mut chars = rest.char_indices();
+        while let Some((i, c)) = chars.next() {
+            match c {
+                '"' => return Ok((Value::Str(s), &rest[i + 1..])),
+                '\\' => match chars.next() {
+                    Some((_, 'n')) => s.push('\n'),
+                    Some((_, 't')) => s.push('\t'),
+                    Some((_, c)) => s.push(c),
+                    None => break,
+                },
+                c => s.push(c),
+            }
+        }
+        return Err("unterminated string".to_string());
+    }
+
+    let end = text.find(|c: char| c == ',' || c == ']' || c.is_whitespace()).unwrap_or(text.len());
+    let (word, rest) = text.split_at(end);
+    let value = match word {
+        "true" => Value::Bool(true),
+        "false" => Value::Bool(false),
+        _ => {
+            let digits = word.replace('_', "");
+            let number = match digits.strip_prefix("0x") {
+                Some(hex) => i64::from_str_radix(hex, 16),
+                None => digits.parse(),
+            };
+            Value::Int(number.map_err(|_| std::format!("can't read '{}'", word))?)
+        }
+    };
+    Ok((value, rest))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let mut project = Project::new();
+        project.symbols.push("game.sym".to_string());
+        project.breakpoints.extend([0x8000, 0xC0F3]);
+        project.watches.push("peek(\"$10\") # lives".to_string());
+        project.layout.insert("stack".to_string(), true);
+        project.layout.insert("perf".to_string(), false);
+        project.comments.push(Annotation { start: 0x8000, end: 0x8000, text: "reset, \"cold\" start".to_string() });
+        project.comments.push(Annotation { start: 0x8100, end: 0x81FF, text: "sprite table # 64 entries".to_string() });
+
+        let text = project.to_toml();
+        assert!(text.contains("breakpoints = [0x8000, 0xc0f3]\n"), "{}", text);
+        assert!(text.contains("\n[comments]\n0x8000 = \"reset, \\\"cold\\\" start\"\n0x8100-0x81ff = "), "{}", text);
+        assert_eq!(Project::parse(&text), Ok(project.clone()));
+        assert!(project.shows("stack") && !project.shows("perf") && !project.shows("stats"));
+    }
+
+    #[test]
+    fn hand_written_files() {
+        let text = "# set up by hand\nversion = 2\nbreakpoints = [ 32768, 0x80_10, ]  # two\n\n[layout]\nstats = true\n[other]\nx = \"y\"\n";
+        let project = Project::parse(text).unwrap();
+        assert_eq!(project.breakpoints, BTreeSet::from([0x8000, 0x8010]));
+        assert!(project.shows("stats"));
+
+        assert_eq!(Project::parse("breakpoints = [\"main\"]"), Err("line 1: breakpoints are addresses".to_string()));
+        assert!(Project::parse("watches = [\"open").is_err());
+        assert!(Project::parse("[layout]\nstack = 1").is_err());
+        assert!(Project::parse("[comments]\nreset = \"start\"").is_err());
+    }
+}
@@ -0,0 +1,103 @@
+// What RAM holds at power on. Real chips come up in a pattern of their
+// own, and software that forgets to clear something behaves differently
+// on different machines, so which pattern is a choice, and a seeded random
+// one is the same every run with the same seed.
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RamInit {
+    Zero,
+    Ones,
+    // $00 through one page, $ff through the next, and so on
+    AlternatePages,
+    Random(u64),
+}
+
+// The seed "random" gets without one of its own
+pub const DEFAULT_SEED: u64 = 0x6502;
+
+impl RamInit {
+    // "zero", "ff", "alternate", "random" or "random:<seed>"
+    pub fn parse(text: &str) -> Result<RamInit, String> {
+        match text.split_once(':') {
+            Some(("random", seed)) => seed.parse().map(RamInit::Random).map_err(|_| std::format!("bad seed '{}'", seed)),
+            _ => match text {
+                "zero" | "00" => Ok(RamInit::Zero),
+                "ff" | "ones" => Ok(RamInit::Ones),
+                "alternate" => Ok(RamInit::AlternatePages),
+                "random" => Ok(RamInit::Random(DEFAULT_SEED)),
+                _ => Err(std::format!("unknown RAM pattern '{}', try zero, ff, alternate or random[:seed]", text)),
+            },
+        }
+    }
+
+    pub fn fill(&self, ram: &mut [u8]) {
+        match *self {
+            RamInit::Zero => ram.fill(0x00),
+            RamInit::Ones => ram.fill(0xFF),
+            RamInit::AlternatePages => {
+                for (page, cells) in ram.chunks_mut(0x100).enumerate() {
+                    cells.fill(if page % 2 == 0 { 0x00 } else { 0xFF });
+                }
+            }
+            RamInit::Random(seed) => {
+                let mut rng = Xorshift64::new(seed);
+                ram.iter_mut().for_each(|cell| *cell = rng.next_u8());
+            }
+        }
+    }
+}
+
+// Marsaglia's xorshift, small and plenty for noise that has to repeat
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Xorshift64 {
+    state: u64,
+}
+
+impl Xorshift64 {
+    pub fn new(seed: u64) -> Self {
+        // All zero is the one state it never leaves, so the seed is mixed
+        // into a constant first
+        Xorshift64 { state: (seed ^ 0x9E37_79B9_7F4A_7C15) | 1 }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 7;
+        self.state ^= self.state << 17;
+        self.state
+    }
+
+    pub fn next_u8(&mut self) -> u8 {
+        (self.next_u64() >> 56) as u8
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn patterns() {
+        let mut ram = [0x55u8; 0x400];
+        RamInit::Ones.fill(&mut ram);
+        assert!(ram.iter().all(|&cell| cell == 0xFF));
+        RamInit::AlternatePages.fill(&mut ram);
+        assert_eq!((ram[0x0FF], ram[0x100], ram[0x2FF], ram[0x3FF]), (0x00, 0xFF, 0x00, 0xFF));
+
+        let mut again = [0u8; 0x400];
+        RamInit::Random(7).fill(&mut ram);
+        RamInit::Random(7).fill(&mut again);
+        assert_eq!(ram, again);
+        RamInit::Random(8).fill(&mut again);
+        assert_ne!(ram, again);
+    }
+
+    #[test]
+    fn parsing() {
+        assert_eq!(RamInit::parse("ff"), Ok(RamInit::Ones));
+        assert_eq!(RamInit::parse("random"), Ok(RamInit::Random(DEFAULT_SEED)));
+        assert_eq!(RamInit::parse("random:42"), Ok(RamInit::Random(42)));
+        assert!(RamInit::parse("random:x").is_err());
+        assert!(RamInit::parse("checkerboard").is_err());
+    }
+}
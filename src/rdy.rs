@@ -0,0 +1,214 @@
+use crate::snapshot::{Snapshot, StateReader, StateWriter};
+use crate::{cpu6502, Variant};
+
+// The RDY input, for anything that needs the CPU to wait: a slow device
+// stretching its bus cycles, or a DMA engine taking the bus. Devices
+// register a source and pull the line low through the event queue, the
+// way they raise interrupts:
+//
+//   events.schedule_in(0, move |cpu| cpu.set_rdy(source, true));
+//
+// The line is low while any source holds it. The CPU stops on the next
+// read cycle it comes to and waits there, but the NMOS part carries on
+// through write cycles, so after pulling RDY low a DMA engine can see up
+// to three more writes (BRK's pushes) before the CPU stops. The 65C02
+// stops on writes too. stopped_by_rdy says when the CPU has.
+//
+// Devices that are just slow answer BusDevice::wait_states instead, and
+// RDY is held low for them on the cycles they're accessed.
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RdySource(pub usize);
+
+#[derive(Default)]
+pub(crate) struct RdyLine {
+    names: Vec<String>,
+    held: Vec<bool>,
+    // The last tick went by without the CPU doing anything, RDY held it
+    pub(crate) stopped: bool,
+}
+
+impl cpu6502 {
+    pub fn register_rdy_source(&mut self, name: &str) -> RdySource {
+        self.rdy.names.push(name.to_string());
+        self.rdy.held.push(false);
+        RdySource(self.rdy.names.len() - 1)
+    }
+
+    // `low` pulls the line low for `source`, false lets go of it
+    pub fn set_rdy(&mut self, source: RdySource, low: bool) {
+        self.rdy.held[source.0] = low;
+    }
+
+    pub fn rdy_low(&self) -> bool {
+        self.rdy.held.iter().any(|held| *held)
+    }
+
+    // The sources holding the line low
+    pub fn rdy_holders(&self) -> Vec<&str> {
+        self.rdy.names.iter().zip(&self.rdy.held).filter(|(_, held)| **held).map(|(name, _)| name.as_str()).collect()
+    }
+
+    // True once the CPU has actually stopped, which with RDY low can be
+    // a few cycles after it went low
+    pub fn stopped_by_rdy(&self) -> bool {
+        self.rdy.stopped
+    }
+
+    // Whether RDY stops the CPU on this tick, for clock()
+    pub(crate) fn rdy_stalls(&mut self) -> bool {
+        let stops_on_writes = self.variant == Variant::Cmos65C02;
+        let low = self.rdy_low();
+        self.rdy.stopped = self.pins.stalls(low, stops_on_writes);
+        self.rdy.stopped
+    }
+}
+
+// Who is holding the line and whether the CPU has stopped for it. The
+// sources are registered by the devices, so the image has to agree on
+// how many there are.
+impl Snapshot for RdyLine {
+    fn save_state(&self) -> Vec<u8> {
+        let mut w = StateWriter::new();
+
+        w.u32(self.held.len() as u32);
+        for held in &self.held {
+            w.bool(*held);
+        }
+        w.bool(self.stopped);
+
+        w.finish()
+    }
+
+    fn load_state(&mut self, data: &[u8], _version: u32) -> Result<(), String> {
+        let mut r = StateReader::new(data);
+
+        let sources = r.u32()? as usize;
+        if sources != self.held.len() {
+            return Err(std::format!("image has {} RDY sources, this machine has {}", sources, self.held.len()));
+        }
+
+        let held = (0..sources).map(|_| r.bool()).collect::<Result<Vec<bool>, String>>()?;
+        let stopped = r.bool()?;
+
+        self.held = held;
+        self.stopped = stopped;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device::BusDevice;
+    use crate::snapshot::MachineState;
+
+    // A ROM that takes two extra cycles on every read
+    struct SlowRom(Vec<u8>);
+
+    impl BusDevice for SlowRom {
+        fn read(&mut self, offset: u16) -> u8 {
+            self.0[offset as usize]
+        }
+        fn peek(&self, offset: u16) -> u8 {
+            self.0[offset as usize]
+        }
+        fn write(&mut self, _offset: u16, _data: u8) {}
+        fn wait_states(&self, _offset: u16) -> u8 {
+            2
+        }
+    }
+
+    fn start(code: &[u8]) -> cpu6502 {
+        let mut cpu = cpu6502::new();
+        cpu.load_program(code, 0x8000);
+        cpu.set_reset_vector(0x8000);
+        cpu.reset();
+        cpu.run_instructions(0, 100);
+        cpu
+    }
+
+    fn cycles_for(cpu: &mut cpu6502, instructions: u64) -> u64 {
+        let start = cpu.clock_count();
+        cpu.run_instructions(instructions, 1000);
+        cpu.clock_count() - start
+    }
+
+    #[test]
+    fn held_low_stops_on_reads_not_writes() {
+        // STA $0200 / loop: JMP loop
+        let mut cpu = start(&[0x8D, 0x00, 0x02, 0x4C, 0x03, 0x80]);
+        let dma = cpu.register_rdy_source("dma");
+
+        // Pulled low after the STA's three reads the write goes ahead, then
+        // the CPU stops before the next opcode fetch
+        for _ in 0..3 {
+            cpu.clock();
+        }
+        cpu.set_rdy(dma, true);
+        cpu.clock();
+        assert!(!cpu.stopped_by_rdy());
+        assert!(cpu.complete());
+        for _ in 0..10 {
+            cpu.clock();
+            assert!(cpu.stopped_by_rdy());
+        }
+        assert_eq!((cpu.pc, cpu.rdy_holders()), (0x8003, vec!["dma"]));
+
+        cpu.set_rdy(dma, false);
+        assert_eq!(cycles_for(&mut cpu, 1), 3);
+        assert!(!cpu.stopped_by_rdy());
+
+        // The 65C02 stops on the write as well
+        let mut cpu = start(&[0x8D, 0x00, 0x02]);
+        cpu.set_variant(Variant::Cmos65C02);
+        let dma = cpu.register_rdy_source("dma");
+        for _ in 0..3 {
+            cpu.clock();
+        }
+        cpu.set_rdy(dma, true);
+        cpu.clock();
+        assert!(cpu.stopped_by_rdy());
+        assert!(!cpu.complete());
+    }
+
+    #[test]
+    fn snapshot_keeps_the_line_held() {
+        // loop: JMP loop
+        let mut cpu = start(&[0x4C, 0x00, 0x80]);
+        let dma = cpu.register_rdy_source("dma");
+        cpu.set_rdy(dma, true);
+        for _ in 0..10 {
+            cpu.clock();
+        }
+        let state = MachineState::capture(&cpu);
+
+        cpu.set_rdy(dma, false);
+        cpu.clock();
+        assert!(!cpu.stopped_by_rdy());
+        state.restore(&mut cpu).unwrap();
+        assert_eq!(cpu.rdy_holders(), vec!["dma"]);
+        assert!(cpu.stopped_by_rdy());
+
+        // Restoring onto a machine with other sources is turned down
+        let mut other = start(&[0x4C, 0x00, 0x80]);
+        assert!(state.restore(&mut other).is_err());
+    }
+
+    #[test]
+    fn slow_devices_add_wait_states_to_reads() {
+        // LDA $9000 / STA $9000 / LDA #$01
+        let mut cpu = start(&[0xAD, 0x00, 0x90, 0x8D, 0x00, 0x90, 0xA9, 0x01]);
+        cpu.bus.borrow_mut().map("slow", 0x9000, 0x90FF, Box::new(SlowRom(vec![0x42; 256])));
+        cpu.set_pin_tracing(true);
+
+        assert_eq!(cycles_for(&mut cpu, 1), 4 + 2);
+        assert_eq!(cpu.a, 0x42);
+        // The write doesn't wait
+        assert_eq!(cycles_for(&mut cpu, 2), 4 + 2);
+
+        // The read held for two ticks, then let through
+        let held: Vec<(u16, bool)> = cpu.pin_history()[2..6].iter().map(|pins| (pins.address, pins.rdy)).collect();
+        assert_eq!(held, [(0x8002, true), (0x9000, false), (0x9000, false), (0x9000, true)]);
+    }
+}
@@ -0,0 +1,136 @@
+use std::fmt;
+
+use crate::{cpu6502, FLAGS6502};
+
+// The programmer visible registers in one plain value, for code embedding
+// the CPU that wants to look at or set them all at once without going
+// through the fields one by one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Registers {
+    pub a: u8,
+    pub x: u8,
+    pub y: u8,
+    pub sp: u8,
+    pub pc: u16,
+    pub p: Status,
+}
+
+// The processor status register, with a method per flag
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Status(pub u8);
+
+impl Status {
+    pub fn bits(self) -> u8 {
+        self.0
+    }
+
+    pub fn contains(self, flag: FLAGS6502) -> bool {
+        self.0 & flag as u8 != 0
+    }
+
+    pub fn set(&mut self, flag: FLAGS6502, value: bool) {
+        if value {
+            self.0 |= flag as u8;
+        } else {
+            self.0 &= !(flag as u8);
+        }
+    }
+
+    pub fn carry(self) -> bool {
+        self.contains(FLAGS6502::C)
+    }
+
+    pub fn zero(self) -> bool {
+        self.contains(FLAGS6502::Z)
+    }
+
+    pub fn interrupt_disable(self) -> bool {
+        self.contains(FLAGS6502::I)
+    }
+
+    pub fn decimal(self) -> bool {
+        self.contains(FLAGS6502::D)
+    }
+
+    // Only meaningful in the copy pushed by BRK or PHP, the register
+    // itself has no B bit
+    pub fn break_command(self) -> bool {
+        self.contains(FLAGS6502::B)
+    }
+
+    pub fn overflow(self) -> bool {
+        self.contains(FLAGS6502::V)
+    }
+
+    pub fn negative(self) -> bool {
+        self.contains(FLAGS6502::N)
+    }
+}
+
+// NV-BDIZC, upper case for set and lower case for clear
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (bit, name) in "NV-BDIZC".chars().enumerate() {
+            let set = self.0 & (0x80 >> bit) != 0;
+            let name = if set || name == '-' { name } else { name.to_ascii_lowercase() };
+            write!(f, "{}", name)?;
+        }
+        Ok(())
+    }
+}
+
+impl cpu6502 {
+    pub fn registers(&self) -> Registers {
+        Registers { a: self.a, x: self.x, y: self.y, sp: self.stkp, pc: self.pc, p: Status(self.status) }
+    }
+
+    // Takes effect from the next instruction, one that's part way through
+    // carries on with the new values
+    pub fn set_registers(&mut self, registers: Registers) {
+        self.a = registers.a;
+        self.x = registers.x;
+        self.y = registers.y;
+        self.stkp = registers.sp;
+        self.pc = registers.pc;
+        self.set_status(registers.p.bits());
+    }
+
+    pub fn flags(&self) -> Status {
+        Status(self.status)
+    }
+
+    pub fn flag(&self, flag: FLAGS6502) -> bool {
+        self.flags().contains(flag)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn registers_round_trip() {
+        let mut cpu = cpu6502::new();
+        let mut registers = cpu.registers();
+        registers.a = 0x12;
+        registers.sp = 0xF0;
+        registers.pc = 0x8000;
+        registers.p.set(FLAGS6502::C, true);
+        registers.p.set(FLAGS6502::N, true);
+        cpu.set_registers(registers);
+
+        assert_eq!((cpu.a, cpu.stkp, cpu.pc), (0x12, 0xF0, 0x8000));
+        assert_eq!(cpu.registers(), registers);
+        assert!(cpu.flag(FLAGS6502::C) && cpu.flags().negative());
+        assert!(!cpu.flags().zero());
+
+        cpu.set_flag(FLAGS6502::C, false);
+        assert!(!cpu.registers().p.carry());
+    }
+
+    #[test]
+    fn status_display() {
+        assert_eq!(Status(0xA1).to_string(), "Nv-bdizC");
+        assert_eq!(Status(0x00).to_string(), "nv-bdizc");
+    }
+}
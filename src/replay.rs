@@ -0,0 +1,245 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::debugger::Register;
+use crate::interrupts::IrqSource;
+use crate::cpu6502;
+use crate::snapshot::MachineState;
+
+// Everything that reaches the machine from outside the emulated program.
+// Host code feeds these through cpu6502::stimulate so they can be recorded.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Stimulus {
+    Reset,
+    // An edge on the NMI line
+    Nmi,
+    // A host write into the address space, e.g. a key press landing in a
+    // device register or a poke from the debugger
+    Write { addr: u16, data: u8 },
+    // A register changed from the debugger
+    Register { reg: Register, value: u16 },
+    // A device pulling its IRQ line low or letting go of it
+    IrqLine { source: IrqSource, asserted: bool },
+    // SO pulled low or let go
+    SoLine { low: bool },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Event {
+    pub cycle: u64,
+    pub stimulus: Stimulus,
+}
+
+#[derive(Debug, Clone)]
+pub struct Recording {
+    // The whole machine at the moment recording started
+    pub initial: MachineState,
+    pub events: Vec<Event>,
+}
+
+const HEADER: &str = "crust-replay 3";
+
+impl Recording {
+    pub fn capture(cpu: &cpu6502) -> Recording {
+        Recording {
+            initial: MachineState::capture(cpu),
+            events: Vec::new(),
+        }
+    }
+
+    // Put the machine back into the state the recording started from
+    pub fn restore(&self, cpu: &mut cpu6502) -> Result<(), String> {
+        self.initial.restore(cpu)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        fs::write(path, self.to_text())
+    }
+
+    // The starting state in the snapshot format, then the events
+    pub fn to_text(&self) -> String {
+        let mut s = String::new();
+
+        s.push_str(HEADER);
+        s.push('\n');
+        s.push_str(self.initial.to_text().as_str());
+        s.push_str("events\n");
+
+        for event in &self.events {
+            let line = match event.stimulus {
+                Stimulus::Reset => std::format!("{} reset\n", event.cycle),
+                Stimulus::Nmi => std::format!("{} nmi\n", event.cycle),
+                Stimulus::Write { addr, data } => std::format!("{} write {:04x} {:02x}\n", event.cycle, addr, data),
+                Stimulus::Register { reg, value } => std::format!("{} reg {} {:04x}\n", event.cycle, reg.name(), value),
+                Stimulus::IrqLine { source, asserted } => std::format!("{} line {} {}\n", event.cycle, source.0, asserted as u8),
+                Stimulus::SoLine { low } => std::format!("{} so {}\n", event.cycle, low as u8),
+            };
+            s.push_str(line.as_str());
+        }
+
+        s
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Recording> {
+        let text = fs::read_to_string(path)?;
+        Recording::parse(&text).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    pub fn parse(text: &str) -> Result<Recording, String> {
+        let mut lines = text.lines();
+
+        if lines.next() != Some(HEADER) {
+            return Err("not a replay file".to_string());
+        }
+
+        let state: Vec<&str> = lines.by_ref().take_while(|line| *line != "events").collect();
+        let initial = MachineState::parse(&state.join("\n"))?;
+
+        let hex8 = |s: &str| u8::from_str_radix(s, 16).map_err(|e| e.to_string());
+        let hex16 = |s: &str| u16::from_str_radix(s, 16).map_err(|e| e.to_string());
+
+        let mut events = Vec::new();
+
+        for line in lines {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if fields.is_empty() {
+                continue;
+            }
+
+            let cycle = fields[0].parse().map_err(|_| std::format!("bad event '{}'", line))?;
+            let stimulus = match fields[1..] {
+                ["reset"] => Stimulus::Reset,
+                ["nmi"] => Stimulus::Nmi,
+                ["write", addr, data] => Stimulus::Write { addr: hex16(addr)?, data: hex8(data)? },
+                ["reg", name, value] => match Register::from_name(name) {
+                    Some(reg) => Stimulus::Register { reg, value: hex16(value)? },
+                    None => return Err(std::format!("bad register '{}'", name)),
+                },
+                ["line", source, asserted @ ("0" | "1")] => Stimulus::IrqLine {
+                    source: IrqSource(source.parse().map_err(|_| std::format!("bad event '{}'", line))?),
+                    asserted: asserted == "1",
+                },
+                ["so", low @ ("0" | "1")] => Stimulus::SoLine { low: low == "1" },
+                _ => return Err(std::format!("bad event '{}'", line)),
+            };
+
+            events.push(Event { cycle, stimulus });
+        }
+
+        Ok(Recording { initial, events })
+    }
+}
+
+// Feeds a recording's events back into the CPU at their original cycles
+pub struct Player {
+    recording: Recording,
+    next: usize,
+}
+
+impl Player {
+    pub fn new(recording: Recording) -> Self {
+        Player { recording, next: 0 }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.next >= self.recording.events.len()
+    }
+
+    // Cycle of the next event still to come
+    pub fn next_cycle(&self) -> Option<u64> {
+        self.recording.events.get(self.next).map(|event| event.cycle)
+    }
+
+    // Next event due at or before `cycle`, if any
+    pub fn poll(&mut self, cycle: u64) -> Option<Stimulus> {
+        match self.recording.events.get(self.next) {
+            Some(event) if event.cycle <= cycle => {
+                self.next += 1;
+                Some(event.stimulus)
+            }
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::debugger::Debugger;
+
+    // LDX #$00 / INX / JMP $8002
+    fn counting_cpu() -> cpu6502 {
+        let mut cpu = cpu6502::new();
+        cpu.load_program(&[0xA2, 0x00, 0xE8, 0x4C, 0x02, 0x80], 0x8000);
+        cpu.set_reset_vector(0x8000);
+        cpu.reset();
+        cpu
+    }
+
+    fn run(cpu: &mut cpu6502, cycles: u64) {
+        for _ in 0..cycles {
+            cpu.clock();
+        }
+    }
+
+    #[test]
+    fn save_parse_round_trip() {
+        let mut cpu = counting_cpu();
+        cpu.start_recording();
+        run(&mut cpu, 20);
+        cpu.trigger_nmi();
+        cpu.stimulate(Stimulus::Write { addr: 0x00FF, data: 0x41 });
+        cpu.stimulate(Stimulus::Register { reg: Register::X, value: 0x10 });
+        let timer = cpu.interrupts.register_source("timer");
+        cpu.set_irq(timer, true);
+        cpu.set_irq(timer, false);
+        cpu.assert_irq(true);
+        cpu.stimulate(Stimulus::Reset);
+        let recording = cpu.stop_recording().unwrap();
+
+        let parsed = Recording::parse(&recording.to_text()).unwrap();
+        assert_eq!(parsed.events, recording.events);
+        assert_eq!(parsed.initial, recording.initial);
+        assert_eq!(parsed.to_text(), recording.to_text());
+    }
+
+    #[test]
+    fn rejects_bad_files() {
+        assert!(Recording::parse("").is_err());
+        assert!(Recording::parse("crust-replay 2\n").is_err());
+        assert!(Recording::parse("crust-replay 3\ncrust-state 1\ncpu 00\nevents\n").is_err());
+
+        let mut text = Recording::capture(&counting_cpu()).to_text();
+        assert!(Recording::parse(&text).is_ok());
+
+        text.push_str("12 reg q 0001\n");
+        assert!(Recording::parse(&text).is_err());
+    }
+
+    #[test]
+    fn debugger_edits_are_recorded_and_replayed() {
+        let mut cpu = counting_cpu();
+        let mut debugger = Debugger::new();
+
+        cpu.start_recording();
+        run(&mut cpu, 30);
+        debugger.poke(&mut cpu, 0x0200, 0x55);
+        debugger.set_register(&mut cpu, Register::X, 0x80);
+        run(&mut cpu, 30);
+        debugger.undo(&mut cpu);
+        run(&mut cpu, 30);
+
+        let recording = cpu.stop_recording().unwrap();
+        assert_eq!(recording.events.len(), 3);
+
+        let (x, ram) = (cpu.x, cpu.bus.borrow().read(0x0200, true));
+
+        let mut replayed = counting_cpu();
+        replayed.start_replay(recording).unwrap();
+        run(&mut replayed, 90);
+
+        assert_eq!(replayed.x, x);
+        assert_eq!(replayed.bus.borrow().read(0x0200, true), ram);
+    }
+}
@@ -0,0 +1,427 @@
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+use crate::cpu6502;
+use crate::device::BusDevice;
+use crate::devicelog::{DeviceEventKind, DeviceLogger};
+use crate::interrupts::IrqSource;
+use crate::scheduler::{AlarmId, EventQueue};
+
+// A 6532 RIOT: 128 bytes of RAM, two 8 bit ports and an interval timer.
+// Offsets are the chip's own address lines, with RS on bit 9:
+//
+//   RS=0  +00-7F  RAM
+//   RS=1  +00     port A data      +01  port A direction
+//         +02     port B data      +03  port B direction
+//         +04     timer, read      +05  interrupt flags, read
+//         +04-07  PA7 edge, write: bit 0 picks the rising edge, bit 1
+//                 lets it interrupt
+//         +14-17  timer, write: counts every 1, 8, 64 or 1024 cycles,
+//                 +1C-1F the same with the timer interrupt enabled
+//
+// Registers repeat wherever the lines they don't decode are. The timer
+// isn't clocked, its count is worked out from when it was last written.
+
+const RS: u16 = 0x200;
+
+const FLAG_TIMER: u8 = 0x80;
+const FLAG_PA7: u8 = 0x40;
+
+const INTERVALS: [u64; 4] = [1, 8, 64, 1024];
+
+#[derive(Clone, Copy)]
+struct Timer {
+    written_at: u64,
+    value: u8,
+    interval: u64,
+    irq_enabled: bool,
+    // Read since it passed zero, which takes the flag down
+    acknowledged: bool,
+}
+
+impl Timer {
+    // Cycles after the write that the count passes zero. It counts down
+    // once straight away, then every `interval` cycles.
+    fn underflow(&self) -> u64 {
+        self.value as u64 * self.interval + 1
+    }
+
+    // Past zero it carries on down from $ff, once every cycle
+    fn count(&self, now: u64) -> u8 {
+        let elapsed = now.saturating_sub(self.written_at);
+        let underflow = self.underflow();
+        if elapsed < underflow {
+            self.value - ((elapsed + self.interval - 1) / self.interval) as u8
+        } else {
+            (0xFF - (elapsed - underflow) % 256) as u8
+        }
+    }
+
+    fn flag(&self, now: u64) -> bool {
+        !self.acknowledged && now.saturating_sub(self.written_at) >= self.underflow()
+    }
+}
+
+struct Chip {
+    ram: [u8; 128],
+    // Output registers, direction registers (1 bits drive the pin) and
+    // what the host has on the pins
+    output: [u8; 2],
+    direction: [u8; 2],
+    input: [u8; 2],
+    timer: Timer,
+    pa7_rising: bool,
+    pa7_irq_enabled: bool,
+    pa7_flag: bool,
+    events: EventQueue,
+    // Where the IRQ output goes once attached, what it was last set to and
+    // the event waiting to raise it when the timer runs out
+    irq: Option<IrqSource>,
+    irq_raised: bool,
+    underflow_event: Option<AlarmId>,
+    log: Option<DeviceLogger>,
+}
+
+impl Chip {
+    fn pins(&self, port: usize) -> u8 {
+        (self.output[port] & self.direction[port]) | (self.input[port] & !self.direction[port])
+    }
+
+    fn flags(&self, now: u64) -> u8 {
+        let mut flags = 0;
+        if self.timer.flag(now) {
+            flags |= FLAG_TIMER;
+        }
+        if self.pa7_flag {
+            flags |= FLAG_PA7;
+        }
+        flags
+    }
+
+    fn irq(&self, now: u64) -> bool {
+        (self.timer.irq_enabled && self.timer.flag(now)) || (self.pa7_irq_enabled && self.pa7_flag)
+    }
+
+    // PA7 is watched whichever way it's driven
+    fn pins_changed(&mut self, pa7_before: bool) {
+        let pa7 = self.pins(0) & 0x80 != 0;
+        if pa7 != pa7_before && pa7 == self.pa7_rising {
+            self.pa7_flag = true;
+        }
+    }
+
+    fn write_timer(&mut self, now: u64, offset: u16, data: u8) {
+        self.timer = Timer {
+            written_at: now,
+            value: data,
+            interval: INTERVALS[(offset & 3) as usize],
+            irq_enabled: offset & 0x08 != 0,
+            acknowledged: false,
+        };
+    }
+
+    // Brings the IRQ line up to date and, with the timer interrupt on,
+    // arranges to look again when the timer runs out
+    fn update_irq(&mut self, port: &RiotPort) {
+        let source = match self.irq {
+            Some(source) => source,
+            None => return,
+        };
+        let now = self.events.now();
+
+        let asserted = self.irq(now);
+        if asserted != self.irq_raised {
+            self.irq_raised = asserted;
+            self.events.schedule_in(0, move |cpu| cpu.set_irq(source, asserted));
+        }
+
+        if let Some(id) = self.underflow_event.take() {
+            self.events.cancel(id);
+        }
+        let timer = self.timer;
+        if timer.irq_enabled && !timer.flag(now) {
+            // Requests are taken up at the end of the clock they're made
+            // in, the cycle after `now`
+            let delay = (timer.written_at + timer.underflow()).saturating_sub(now + 1);
+            let port = port.clone();
+            self.underflow_event = Some(self.events.schedule_in(delay, move |_| {
+                let mut chip = port.chip();
+                chip.underflow_event = None;
+                if let Some(log) = &chip.log {
+                    log.log(DeviceEventKind::Timer, format_args!("ran out"));
+                }
+                chip.update_irq(&port);
+            }));
+        }
+    }
+}
+
+// The host's side of the ports, which can be on another thread
+#[derive(Clone)]
+pub struct RiotPort(Arc<Mutex<Chip>>);
+
+impl RiotPort {
+    fn chip(&self) -> MutexGuard<'_, Chip> {
+        self.0.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    // What's on the pins of port A or B: the chip's outputs where the
+    // direction register says it drives them, the host's inputs elsewhere
+    pub fn port_a(&self) -> u8 {
+        self.chip().pins(0)
+    }
+
+    pub fn port_b(&self) -> u8 {
+        self.chip().pins(1)
+    }
+
+    // The host drives the pins the chip doesn't, 2600 joysticks pull
+    // theirs low
+    pub fn set_port_a(&self, data: u8) {
+        let mut chip = self.chip();
+        let pa7 = chip.pins(0) & 0x80 != 0;
+        chip.input[0] = data;
+        chip.pins_changed(pa7);
+        chip.update_irq(self);
+    }
+
+    pub fn set_port_b(&self, data: u8) {
+        self.chip().input[1] = data;
+    }
+
+    pub fn ram(&self) -> [u8; 128] {
+        self.chip().ram
+    }
+}
+
+pub struct Riot {
+    port: RiotPort,
+}
+
+impl Riot {
+    pub fn new(events: EventQueue) -> Self {
+        let timer = Timer { written_at: events.now(), value: 0, interval: 1024, irq_enabled: false, acknowledged: false };
+        let chip = Chip {
+            ram: [0; 128],
+            output: [0; 2],
+            direction: [0; 2],
+            // Nothing pulling the pins down
+            input: [0xFF; 2],
+            timer,
+            pa7_rising: false,
+            pa7_irq_enabled: false,
+            pa7_flag: false,
+            events,
+            irq: None,
+            irq_raised: false,
+            underflow_event: None,
+            log: None,
+        };
+        Riot { port: RiotPort(Arc::new(Mutex::new(chip))) }
+    }
+
+    pub fn port(&self) -> RiotPort {
+        self.port.clone()
+    }
+
+    // Wires the IRQ output to `source`
+    pub fn connect_irq(&mut self, source: IrqSource) {
+        let mut chip = self.port.chip();
+        chip.irq = Some(source);
+        chip.update_irq(&self.port);
+    }
+
+    // Logs the timer running out, while it's set to interrupt
+    pub fn connect_log(&mut self, log: DeviceLogger) {
+        self.port.chip().log = Some(log);
+    }
+}
+
+impl BusDevice for Riot {
+    fn read(&mut self, offset: u16) -> u8 {
+        let data = self.peek(offset);
+
+        let mut chip = self.port.chip();
+        let now = chip.events.now();
+        match offset & (RS | 0x05) {
+            // Reading the timer takes its flag down, and A3 turns its
+            // interrupt on or off
+            0x204 => {
+                if chip.timer.flag(now) {
+                    chip.timer.acknowledged = true;
+                }
+                chip.timer.irq_enabled = offset & 0x08 != 0;
+            }
+            0x205 => chip.pa7_flag = false,
+            _ => return data,
+        }
+        chip.update_irq(&self.port);
+
+        data
+    }
+
+    fn peek(&self, offset: u16) -> u8 {
+        let chip = self.port.chip();
+        if offset & RS == 0 {
+            return chip.ram[(offset & 0x7F) as usize];
+        }
+
+        let now = chip.events.now();
+        match offset & 0x07 {
+            0x00 => chip.pins(0),
+            0x01 => chip.direction[0],
+            0x02 => chip.pins(1),
+            0x03 => chip.direction[1],
+            0x04 | 0x06 => chip.timer.count(now),
+            _ => chip.flags(now),
+        }
+    }
+
+    fn write(&mut self, offset: u16, data: u8) {
+        let mut chip = self.port.chip();
+        if offset & RS == 0 {
+            chip.ram[(offset & 0x7F) as usize] = data;
+            return;
+        }
+
+        let now = chip.events.now();
+        let pa7 = chip.pins(0) & 0x80 != 0;
+        if offset & 0x04 == 0 {
+            match offset & 0x03 {
+                0x00 => chip.output[0] = data,
+                0x01 => chip.direction[0] = data,
+                0x02 => chip.output[1] = data,
+                _ => chip.direction[1] = data,
+            }
+        } else if offset & 0x10 != 0 {
+            chip.write_timer(now, offset, data);
+        } else {
+            chip.pa7_rising = offset & 0x01 != 0;
+            chip.pa7_irq_enabled = offset & 0x02 != 0;
+        }
+        chip.pins_changed(pa7);
+        chip.update_irq(&self.port);
+    }
+
+    fn reset(&mut self) {
+        let mut chip = self.port.chip();
+        chip.output = [0; 2];
+        chip.direction = [0; 2];
+        chip.pa7_irq_enabled = false;
+        chip.pa7_flag = false;
+        chip.timer.irq_enabled = false;
+        chip.update_irq(&self.port);
+    }
+}
+
+impl cpu6502 {
+    // Maps a RIOT at $base-$base+3FF, RAM in the first half and the ports
+    // and timer in the second, with its IRQ wired to the CPU. Returns the
+    // host's end of the ports.
+    pub fn attach_riot(&mut self, base: u16) -> RiotPort {
+        let mut riot = Riot::new(self.events());
+        riot.connect_irq(self.interrupts.register_source("riot"));
+        riot.connect_log(self.device_log().logger("riot"));
+        let port = riot.port();
+        self.bus.borrow_mut().map("riot", base, base.wrapping_add(0x3FF), Box::new(riot));
+        port
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(cpu: &mut cpu6502, cycles: u64) {
+        for _ in 0..cycles {
+            cpu.clock();
+        }
+    }
+
+    #[test]
+    fn ram_and_ports() {
+        let mut riot = Riot::new(EventQueue::default());
+        let port = riot.port();
+
+        riot.write(0x05, 0x42);
+        assert_eq!((riot.read(0x05), riot.read(0x85)), (0x42, 0x42));
+        assert_eq!(port.ram()[5], 0x42);
+
+        // Low nibble driven by the chip, the rest left to the host
+        port.set_port_a(0xA5);
+        riot.write(RS | 0x01, 0x0F);
+        riot.write(RS | 0x00, 0x3C);
+        assert_eq!(riot.read(RS | 0x00), 0xAC);
+        assert_eq!(port.port_a(), 0xAC);
+        assert_eq!(riot.read(RS | 0x01), 0x0F);
+
+        // Port B is the same at +2
+        port.set_port_b(0x0F);
+        assert_eq!(riot.read(RS | 0x02), 0x0F);
+    }
+
+    #[test]
+    fn pa7_edges() {
+        let mut riot = Riot::new(EventQueue::default());
+        let port = riot.port();
+
+        // Falling edge, the default
+        riot.write(RS | 0x04, 0);
+        port.set_port_a(0x7F);
+        assert_eq!(riot.peek(RS | 0x05), FLAG_PA7);
+        assert_eq!(riot.read(RS | 0x05), FLAG_PA7);
+        assert_eq!(riot.read(RS | 0x05), 0);
+
+        port.set_port_a(0xFF);
+        assert_eq!(riot.read(RS | 0x05), 0);
+    }
+
+    #[test]
+    fn timer_counts_at_each_interval() {
+        for (select, interval) in INTERVALS.iter().enumerate() {
+            let mut cpu = cpu6502::new();
+            let mut riot = Riot::new(cpu.events());
+
+            // Written with 3 at cycle 10
+            run(&mut cpu, 10);
+            riot.write(RS | 0x14 | select as u16, 3);
+            assert_eq!(riot.peek(RS | 0x04), 3);
+
+            // Down one straight away, then once an interval
+            run(&mut cpu, 1);
+            assert_eq!(riot.peek(RS | 0x04), 2, "interval {}", interval);
+            run(&mut cpu, interval * 3 - 1);
+            assert_eq!(riot.peek(RS | 0x04), 0, "interval {}", interval);
+            assert_eq!(riot.peek(RS | 0x05), 0);
+
+            // Through zero it counts every cycle and raises the flag
+            run(&mut cpu, 1);
+            assert_eq!(riot.peek(RS | 0x04), 0xFF, "interval {}", interval);
+            assert_eq!(riot.peek(RS | 0x05), FLAG_TIMER);
+            run(&mut cpu, 3);
+            assert_eq!(riot.peek(RS | 0x04), 0xFC);
+
+            // Reading the timer takes the flag down
+            riot.read(RS | 0x04);
+            assert_eq!(riot.peek(RS | 0x05), 0);
+        }
+    }
+
+    #[test]
+    fn timer_interrupts() {
+        let mut cpu = cpu6502::new();
+        // LDA #$02 / STA $061C (TIM1T, interrupt on) / CLI / loop: JMP loop
+        cpu.load_program(&[0xA9, 0x02, 0x8D, 0x1C, 0x06, 0x58, 0x4C, 0x06, 0x80], 0x8000);
+        // irq: INC $10 / LDA $0604 / RTI, reading the timer acknowledges it
+        cpu.load_program(&[0xE6, 0x10, 0xAD, 0x04, 0x06, 0x40], 0x9000);
+        cpu.set_reset_vector(0x8000);
+        cpu.set_irq_vector(0x9000);
+        cpu.attach_riot(0x0400);
+        cpu.reset();
+
+        run(&mut cpu, 200);
+
+        // Taken once, then quiet until the timer is written again
+        assert_eq!(cpu.bus.borrow().read(0x10, true), 1);
+        assert!(cpu.interrupts.asserted_sources().is_empty());
+    }
+}
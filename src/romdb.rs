@@ -0,0 +1,347 @@
+use std::fs;
+use std::path::Path;
+
+use crate::cartridge::{CartridgeHeader, Mirror, TvRegion};
+use crate::cpu6502;
+use crate::framehash::crc32;
+
+// Known cartridges by the hash of their contents, to put right what their
+// iNES headers get wrong: the wrong mapper, mirroring, a missing battery
+// or a PAL game marked NTSC. The hash covers PRG and CHR ROM, not the
+// header or trainer, the way No-Intro and the NES 2.0 database do it.
+//
+// Entries are read from XML in either of those layouts: No-Intro style
+// DATs, with a <rom crc="" sha1=""/> per <game>, and nes20db.xml, which
+// adds <pcb mapper="" submapper="" mirroring="" battery=""/> and
+// <console region=""/>. A DAT only gives names, so a match is reported
+// and nothing changes. The parser only looks for those tags and doesn't
+// take on XML in general.
+
+// Kept small, a full database is for --romdb
+const BUNDLED: &str = r#"
+<database>
+    <game name="Super Mario Bros. (World)">
+        <rom size="40960" crc32="3337EC46"/>
+        <pcb mapper="0" submapper="0" mirroring="V" battery="0"/>
+        <console type="0" region="0"/>
+    </game>
+</database>
+"#;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RomHashes {
+    pub crc32: u32,
+    pub sha1: [u8; 20],
+}
+
+impl RomHashes {
+    pub fn of(data: &[u8]) -> RomHashes {
+        RomHashes { crc32: crc32(data), sha1: sha1(data) }
+    }
+
+    // Of an iNES image's PRG and CHR, past the header and any trainer
+    pub fn of_ines(bytes: &[u8], header: &CartridgeHeader) -> RomHashes {
+        RomHashes::of(bytes.get(header.data_offset()..).unwrap_or(&[]))
+    }
+
+    // "CRC32 3337ec46, SHA1 ea343f..."
+    pub fn describe(&self) -> String {
+        std::format!("CRC32 {:08x}, SHA1 {}", self.crc32, hex(&self.sha1))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RomEntry {
+    pub name: String,
+    pub crc32: u32,
+    pub sha1: Option<[u8; 20]>,
+    // What the header should have said, None where the entry doesn't say
+    pub mapper: Option<u16>,
+    pub submapper: Option<u8>,
+    pub mirror: Option<Mirror>,
+    pub battery: Option<bool>,
+    pub region: Option<TvRegion>,
+}
+
+impl RomEntry {
+    // Puts the entry's settings over the header's, returning what changed
+    // as "mapper 1 -> 4" and so on
+    pub fn apply(&self, header: &mut CartridgeHeader) -> Vec<String> {
+        let mut changes = Vec::new();
+        if let Some(mapper) = self.mapper.filter(|&mapper| mapper != header.mapper_id) {
+            changes.push(std::format!("mapper {} -> {}", header.mapper_id, mapper));
+            header.mapper_id = mapper;
+        }
+        if let Some(submapper) = self.submapper.filter(|&submapper| submapper != header.submapper) {
+            changes.push(std::format!("submapper {} -> {}", header.submapper, submapper));
+            header.submapper = submapper;
+        }
+        if let Some(mirror) = self.mirror.filter(|&mirror| mirror != header.mirror) {
+            changes.push(std::format!("mirroring {:?} -> {:?}", header.mirror, mirror));
+            header.mirror = mirror;
+        }
+        if let Some(battery) = self.battery.filter(|&battery| battery != header.battery) {
+            changes.push(std::format!("battery {} -> {}", header.battery, battery));
+            header.battery = battery;
+            // Plain iNES headers only give PRG RAM a size with a battery
+            if battery && header.prg_ram_size == 0 {
+                header.prg_ram_size = 8192;
+                header.prg_nvram_size = 8192;
+            }
+        }
+        if let Some(region) = self.region.filter(|&region| region != header.region) {
+            changes.push(std::format!("{} -> {}", header.region.name(), region.name()));
+            header.region = region;
+        }
+        changes
+    }
+}
+
+pub struct RomDatabase {
+    entries: Vec<RomEntry>,
+}
+
+impl Default for RomDatabase {
+    fn default() -> Self {
+        RomDatabase::new()
+    }
+}
+
+impl RomDatabase {
+    pub fn new() -> Self {
+        RomDatabase { entries: Vec::new() }
+    }
+
+    // The few entries built in
+    pub fn bundled() -> Self {
+        let mut database = RomDatabase::new();
+        database.load_str(BUNDLED).expect("the bundled ROM database parses");
+        database
+    }
+
+    // Adds the games in a DAT or nes20db.xml, returning how many. Later
+    // entries win over earlier ones for the same ROM.
+    pub fn load_file<P: AsRef<Path>>(&mut self, path: P) -> Result<usize, String> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path).map_err(|e| std::format!("{}: {}", path.display(), e))?;
+        self.load_str(&text).map_err(|e| std::format!("{}: {}", path.display(), e))
+    }
+
+    pub fn load_str(&mut self, text: &str) -> Result<usize, String> {
+        let entries = parse_xml(text)?;
+        let count = entries.len();
+        self.entries.extend(entries);
+        Ok(count)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    // The CRC has to match, and the SHA1 too where the entry has one
+    pub fn lookup(&self, hashes: &RomHashes) -> Option<&RomEntry> {
+        self.entries.iter().rev().find(|entry| entry.crc32 == hashes.crc32 && entry.sha1.map_or(true, |sha1| sha1 == hashes.sha1))
+    }
+}
+
+impl cpu6502 {
+    // What iNES images are checked against as they load, the bundled
+    // entries unless replaced
+    pub fn rom_database(&self) -> &RomDatabase {
+        &self.rom_database
+    }
+
+    pub fn rom_database_mut(&mut self) -> &mut RomDatabase {
+        &mut self.rom_database
+    }
+}
+
+fn parse_xml(text: &str) -> Result<Vec<RomEntry>, String> {
+    let mut entries = Vec::new();
+    let mut rest = text;
+    while let Some(start) = rest.find("<game") {
+        let end = rest[start..].find("</game>").map(|end| start + end).ok_or("a <game> isn't closed")?;
+        let game = &rest[start..end];
+        rest = &rest[end + "</game>".len()..];
+
+        let name = tag(game, "game").and_then(|attrs| attr(attrs, "name")).unwrap_or_default();
+        let rom = tag(game, "rom").ok_or_else(|| std::format!("\"{}\" has no <rom>", name))?;
+        let crc = attr(rom, "crc32").or_else(|| attr(rom, "crc")).ok_or_else(|| std::format!("\"{}\" has no CRC", name))?;
+        let crc32 = u32::from_str_radix(&crc, 16).map_err(|_| std::format!("\"{}\" has a bad CRC '{}'", name, crc))?;
+        let sha1 = match attr(rom, "sha1") {
+            Some(sha1) => Some(parse_sha1(&sha1).ok_or_else(|| std::format!("\"{}\" has a bad SHA1 '{}'", name, sha1))?),
+            None => None,
+        };
+
+        let pcb = tag(game, "pcb");
+        let number = |name: &str| pcb.and_then(|pcb| attr(pcb, name)).and_then(|value| value.parse::<u16>().ok());
+        let mirror = pcb.and_then(|pcb| attr(pcb, "mirroring")).and_then(|mirroring| match mirroring.as_str() {
+            "H" | "h" => Some(Mirror::Horizontal),
+            "V" | "v" => Some(Mirror::Vertical),
+            _ => None,
+        });
+        let region = tag(game, "console").and_then(|console| attr(console, "region")).and_then(|region| match region.as_str() {
+            "0" => Some(TvRegion::Ntsc),
+            "1" => Some(TvRegion::Pal),
+            "2" => Some(TvRegion::Multi),
+            "3" => Some(TvRegion::Dendy),
+            _ => None,
+        });
+
+        entries.push(RomEntry {
+            name,
+            crc32,
+            sha1,
+            mapper: number("mapper"),
+            submapper: number("submapper").map(|submapper| submapper as u8),
+            mirror,
+            battery: number("battery").map(|battery| battery != 0),
+            region,
+        });
+    }
+    Ok(entries)
+}
+
+// The attributes of the first <name ...> in `text`
+fn tag<'a>(text: &'a str, name: &str) -> Option<&'a str> {
+    let open = std::format!("<{}", name);
+    let mut from = 0;
+    while let Some(at) = text[from..].find(&open).map(|at| from + at + open.len()) {
+        if text[at..].starts_with(char::is_whitespace) || text[at..].starts_with('>') || text[at..].starts_with('/') {
+            let end = text[at..].find('>').map_or(text.len(), |end| at + end);
+            return Some(&text[at..end]);
+        }
+        from = at;
+    }
+    None
+}
+
+fn attr(attrs: &str, name: &str) -> Option<String> {
+    let key = std::format!("{}=\"", name);
+    let mut from = 0;
+    while let Some(at) = attrs[from..].find(&key).map(|at| from + at) {
+        // Not the tail of a longer name, "crc" inside "crc32"
+        if at == 0 || attrs[..at].ends_with(char::is_whitespace) {
+            let value = &attrs[at + key.len()..];
+            let value = &value[..value.find('"')?];
+            return Some(value.replace("&quot;", "\"").replace("&apos;", "'").replace("&lt;", "<").replace("&gt;", ">").replace("&amp;", "&"));
+        }
+        from = at + key.len();
+    }
+    None
+}
+
+fn parse_sha1(text: &str) -> Option<[u8; 20]> {
+    if text.len() != 40 {
+        return None;
+    }
+    let mut sha1 = [0; 20];
+    for (i, byte) in sha1.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(text.get(i * 2..i * 2 + 2)?, 16).ok()?;
+    }
+    Some(sha1)
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| std::format!("{:02x}", byte)).collect()
+}
+
+pub fn sha1(data: &[u8]) -> [u8; 20] {
+    let mut h: [u32; 5] = [0x6745_2301, 0xEFCD_AB89, 0x98BA_DCFE, 0x1032_5476, 0xC3D2_E1F0];
+
+    let mut message = data.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    message.extend_from_slice(&(data.len() as u64 * 8).to_be_bytes());
+
+    for block in message.chunks_exact(64) {
+        let mut w = [0u32; 80];
+        for (i, word) in block.chunks_exact(4).enumerate() {
+            w[i] = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+        }
+        for i in 16..80 {
+            w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e] = h;
+        for (i, &word) in w.iter().enumerate() {
+            let (f, k) = match i {
+                0..=19 => ((b & c) | (!b & d), 0x5A82_7999),
+                20..=39 => (b ^ c ^ d, 0x6ED9_EBA1),
+                40..=59 => ((b & c) | (b & d) | (c & d), 0x8F1B_BCDC),
+                _ => (b ^ c ^ d, 0xCA62_C1D6),
+            };
+            let temp = a.rotate_left(5).wrapping_add(f).wrapping_add(e).wrapping_add(k).wrapping_add(word);
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = temp;
+        }
+
+        for (h, v) in h.iter_mut().zip([a, b, c, d, e]) {
+            *h = h.wrapping_add(v);
+        }
+    }
+
+    let mut digest = [0; 20];
+    for (out, word) in digest.chunks_exact_mut(4).zip(h) {
+        out.copy_from_slice(&word.to_be_bytes());
+    }
+    digest
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hashes() {
+        assert_eq!(hex(&sha1(b"abc")), "a9993e364706816aba3e25717850c26c9cd0d89d");
+        assert_eq!(hex(&sha1(&[0x61; 1000])), "291e9a6c66994949b57ba5e650361e98fc36b1ba");
+        assert_eq!(RomHashes::of(b"123456789").crc32, 0xCBF4_3926);
+    }
+
+    #[test]
+    fn looks_up_and_corrects_headers() {
+        // 16K of PRG and 8K of CHR, mapper 0 with horizontal mirroring
+        let mut image = b"NES\x1A\x01\x01\x00\x00".to_vec();
+        image.resize(16 + 0x6000, 0xEA);
+        let mut header = CartridgeHeader::parse(&image).unwrap();
+        let hashes = RomHashes::of_ines(&image, &header);
+
+        let dat = std::format!(
+            r#"<datafile>
+                <game name="Other"><rom name="a.nes" size="1" crc="00000000"/></game>
+                <game name="Test &amp; Co">
+                    <rom size="24576" crc32="{:08X}" sha1="{}"/>
+                    <pcb mapper="4" submapper="0" mirroring="V" battery="1"/>
+                    <console type="0" region="1"/>
+                </game>
+            </datafile>"#,
+            hashes.crc32,
+            hex(&hashes.sha1)
+        );
+        let mut database = RomDatabase::bundled();
+        let bundled = database.len();
+        assert_eq!(database.load_str(&dat), Ok(2));
+        assert_eq!(database.len(), bundled + 2);
+
+        let entry = database.lookup(&hashes).unwrap().clone();
+        assert_eq!(entry.name, "Test & Co");
+        assert_eq!(entry.apply(&mut header), ["mapper 0 -> 4", "mirroring Horizontal -> Vertical", "battery false -> true", "NTSC -> PAL"]);
+        assert_eq!((header.mapper_id, header.prg_ram_size), (4, 8192));
+        assert!(entry.apply(&mut header).is_empty());
+
+        // A different SHA1 is a different ROM, whatever the CRC says
+        let wrong = RomHashes { sha1: [0; 20], ..hashes };
+        assert!(database.lookup(&wrong).is_none());
+        assert!(database.load_str("<game name=\"x\"><rom crc=\"zz\"/></game>").is_err());
+    }
+}
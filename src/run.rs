@@ -0,0 +1,191 @@
+use crate::vectors::{Cause, Vector, VectorTaken};
+use crate::{cpu6502, Halt};
+
+// Running the CPU from Rust until something happens, for tests and
+// automation that would otherwise each write their own clock loop. Every
+// run has a cycle cap so a guest that never gets there can't hang the
+// host, and with a watchdog set it stops early once the guest has plainly
+// halted. Conditions are looked at between instructions.
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StopReason {
+    ConditionMet,
+    // The cap ran out first
+    CycleLimit,
+    // The next instruction is a BRK, which is left to run. A run that
+    // starts on it runs it rather than stopping there again.
+    Break,
+    // The exit trap fired, with its exit code, or STP stopped the CPU
+    Trap(Option<u8>),
+    // A JAM opcode locked the CPU up
+    Jammed,
+    // The watchdog saw the guest loop forever at this address
+    Halted(u16),
+}
+
+impl cpu6502 {
+    // Runs until `condition` holds between two instructions, checking it
+    // before the first one as well, or until `max_cycles` have gone by.
+    // An instruction already in flight is finished first.
+    pub fn run_until<F: FnMut(&cpu6502) -> bool>(&mut self, mut condition: F, max_cycles: u64) -> StopReason {
+        let entry = self.clock_count;
+        let deadline = entry.saturating_add(max_cycles);
+        // How many instructions in a row have started at last_pc
+        let mut last_pc = None;
+        let mut repeats = 0;
+
+        loop {
+            if self.complete() {
+                if self.halt == Halt::Stopped {
+                    return StopReason::Trap(self.exit_code);
+                }
+                if self.halt == Halt::Jammed {
+                    return StopReason::Jammed;
+                }
+                if condition(self) {
+                    return StopReason::ConditionMet;
+                }
+                if self.clock_count != entry && self.halt == Halt::Running && self.bus.borrow().read(self.pc, true) == 0x00 {
+                    return StopReason::Break;
+                }
+                if let Some(watchdog) = self.watchdog() {
+                    repeats = if last_pc == Some(self.pc) { repeats + 1 } else { 1 };
+                    last_pc = Some(self.pc);
+                    let too_many = watchdog.max_repeats.is_some_and(|max| repeats > max);
+                    if too_many || (watchdog.self_loops && self.at_self_loop()) {
+                        return StopReason::Halted(self.pc);
+                    }
+                }
+            }
+
+            if self.clock_count >= deadline {
+                return StopReason::CycleLimit;
+            }
+            self.clock();
+        }
+    }
+
+    // Runs `count` whole instructions, ConditionMet once they're done
+    pub fn run_instructions(&mut self, count: u64, max_cycles: u64) -> StopReason {
+        let target = self.opcode_counts.total() + count;
+        self.run_until(|cpu| cpu.opcode_counts.total() >= target, max_cycles)
+    }
+
+    // Runs until the next NMI has been taken, stopping on the handler's
+    // first instruction
+    pub fn run_until_nmi(&mut self, max_cycles: u64) -> StopReason {
+        self.run_until_taken(|taken| taken.vector == Vector::Nmi, max_cycles)
+    }
+
+    // The same for the IRQ line, a BRK going through its vector doesn't
+    // count
+    pub fn run_until_irq(&mut self, max_cycles: u64) -> StopReason {
+        self.run_until_taken(|taken| taken.cause == Cause::Irq, max_cycles)
+    }
+
+    // Until the CPU next goes through the vector at `addr`, $FFFA, $FFFC or
+    // $FFFE, whatever sent it there. Nothing else is a vector, so any
+    // other address runs to the cap.
+    pub fn run_until_vector(&mut self, addr: u16, max_cycles: u64) -> StopReason {
+        self.run_until_taken(|taken| taken.vector.addr() == addr, max_cycles)
+    }
+
+    fn run_until_taken<F: Fn(&VectorTaken) -> bool>(&mut self, wanted: F, max_cycles: u64) -> StopReason {
+        let since = self.clock_count;
+        let deadline = since.saturating_add(max_cycles);
+        loop {
+            let reason = self.run_until(
+                |cpu| Vector::ALL.iter().filter_map(|&vector| cpu.vector_taken(vector)).any(|taken| taken.cycle > since && wanted(&taken)),
+                deadline.saturating_sub(self.clock_count),
+            );
+
+            // run_until stops in front of a BRK, before it gets to $FFFE.
+            // If that's what's wanted, go again, which runs it.
+            let brk = VectorTaken { vector: Vector::Irq, cause: Cause::Brk, cycle: self.clock_count, handler: self.vector(Vector::Irq) };
+            if reason != StopReason::Break || !wanted(&brk) {
+                return reason;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::traps::Traps;
+
+    fn start(program: &[u8]) -> cpu6502 {
+        let mut cpu = cpu6502::new();
+        cpu.load_program(program, 0x8000);
+        cpu.set_reset_vector(0x8000);
+        cpu.reset();
+        cpu
+    }
+
+    #[test]
+    fn conditions_and_limits() {
+        // loop: INX / JMP loop
+        let mut cpu = start(&[0xE8, 0x4C, 0x00, 0x80]);
+        assert_eq!(cpu.run_until(|cpu| cpu.x == 5, 1000), StopReason::ConditionMet);
+        assert_eq!((cpu.x, cpu.pc), (5, 0x8001));
+
+        assert_eq!(cpu.run_until(|_| false, 100), StopReason::CycleLimit);
+
+        let x = cpu.x;
+        assert_eq!(cpu.run_instructions(4, 1000), StopReason::ConditionMet);
+        assert_eq!(cpu.x, x.wrapping_add(2));
+        assert_eq!(cpu.run_instructions(1000, 10), StopReason::CycleLimit);
+
+        // LDA #$01 / BRK / padding / INX / BRK, the second run goes past
+        // the first BRK and on to the next
+        let mut cpu = start(&[0xA9, 0x01, 0x00, 0xEA, 0xE8, 0x00]);
+        cpu.set_irq_vector(0x8004);
+        assert_eq!(cpu.run_until(|_| false, 1000), StopReason::Break);
+        assert_eq!(cpu.pc, 0x8002);
+        assert_eq!(cpu.run_until(|_| false, 1000), StopReason::Break);
+        assert_eq!((cpu.pc, cpu.x), (0x8005, 1));
+    }
+
+    #[test]
+    fn breaks_and_traps() {
+        // LDA #$01 / BRK
+        let mut cpu = start(&[0xA9, 0x01, 0x00]);
+        assert_eq!(cpu.run_until(|_| false, 1000), StopReason::Break);
+        assert_eq!((cpu.pc, cpu.a), (0x8002, 0x01));
+
+        // LDA #$07 / STA $F002, the exit trap
+        let mut cpu = start(&[0xA9, 0x07, 0x8D, 0x02, 0xF0]);
+        cpu.set_traps(Traps { char_out: None, exit: Some(0xF002) });
+        assert_eq!(cpu.run_until(|_| false, 1000), StopReason::Trap(Some(7)));
+    }
+
+    #[test]
+    fn until_interrupts_are_taken() {
+        // CLI / loop: INX / JMP loop, with RTI for both handlers
+        let mut cpu = start(&[0x58, 0xE8, 0x4C, 0x01, 0x80]);
+        cpu.load_program(&[0x40], 0x9000);
+        cpu.load_program(&[0x40], 0xA000);
+        cpu.set_irq_vector(0x9000);
+        cpu.set_nmi_vector(0xA000);
+
+        cpu.add_alarm_at(cpu.clock_count() + 50, |cpu| cpu.trigger_nmi());
+        assert_eq!(cpu.run_until_nmi(1000), StopReason::ConditionMet);
+        assert_eq!(cpu.pc, 0xA000);
+        assert_eq!(cpu.run_until_irq(1000), StopReason::CycleLimit);
+
+        cpu.add_alarm_at(cpu.clock_count() + 100, |cpu| cpu.assert_irq(true));
+        assert_eq!(cpu.run_until_vector(0xFFFE, 1000), StopReason::ConditionMet);
+        assert_eq!(cpu.pc, 0x9000);
+        assert!(cpu.clock_count() >= 1100);
+        assert_eq!(cpu.run_until_vector(0xFFFA, 1000), StopReason::CycleLimit);
+
+        // NOP / BRK, which goes through $FFFE but isn't an IRQ
+        let mut cpu = start(&[0xEA, 0x00]);
+        cpu.load_program(&[0x40], 0x9000);
+        cpu.set_irq_vector(0x9000);
+        assert_eq!(cpu.run_until_irq(1000), StopReason::Break);
+        assert_eq!(cpu.run_until_vector(0xFFFE, 1000), StopReason::ConditionMet);
+        assert_eq!(cpu.pc, 0x9000);
+        assert_eq!(cpu.vector_taken(Vector::Irq).unwrap().cause, Cause::Brk);
+    }
+}
@@ -0,0 +1,416 @@
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+
+use crate::cpu6502;
+use crate::snapshot::{Snapshot, StateReader, StateWriter};
+
+pub type AlarmId = u64;
+pub type AlarmCallback = Box<dyn FnMut(&mut cpu6502) + Send>;
+
+struct Alarm {
+    deadline: u64,
+    // Periodic alarms are re-armed this many cycles after they fire
+    period: Option<u64>,
+    callback: AlarmCallback,
+}
+
+enum Request {
+    Add { id: AlarmId, delay: u64, period: Option<u64>, callback: AlarmCallback },
+    Cancel(AlarmId),
+}
+
+#[derive(Default)]
+struct Requests {
+    // Set while `list` has anything in it, so the CPU can check with a load
+    // instead of taking the lock every clock
+    pending: AtomicBool,
+    next_id: AtomicU64,
+    // The CPU's cycle count as of its last clock
+    now: AtomicU64,
+    list: Mutex<Vec<Request>>,
+}
+
+// A handle on the scheduler for the things that can't get at the CPU:
+// devices on the bus and host threads. A device arms a timer when it's
+// written to rather than checking one every cycle. Requests are taken up
+// at the end of the clock they were made in, delays count from there, so
+// a delay of 0 runs the callback before the next clock.
+#[derive(Clone, Default)]
+pub struct EventQueue {
+    requests: Arc<Requests>,
+}
+
+impl EventQueue {
+    fn next_id(&self) -> AlarmId {
+        self.requests.next_id.fetch_add(1, Ordering::Relaxed)
+    }
+
+    fn push(&self, request: Request) {
+        self.requests.list.lock().unwrap_or_else(PoisonError::into_inner).push(request);
+        self.requests.pending.store(true, Ordering::Release);
+    }
+
+    // Call `callback` once, `delay` cycles from now
+    pub fn schedule_in<F>(&self, delay: u64, callback: F) -> AlarmId
+        where F: FnMut(&mut cpu6502) + Send + 'static
+    {
+        let id = self.next_id();
+        self.push(Request::Add { id, delay, period: None, callback: Box::new(callback) });
+        id
+    }
+
+    // Call `callback` every `period` cycles, starting `period` cycles from now
+    pub fn schedule_every<F>(&self, period: u64, callback: F) -> AlarmId
+        where F: FnMut(&mut cpu6502) + Send + 'static
+    {
+        let id = self.next_id();
+        self.push(Request::Add { id, delay: period, period: Some(period), callback: Box::new(callback) });
+        id
+    }
+
+    pub fn cancel(&self, id: AlarmId) {
+        self.push(Request::Cancel(id));
+    }
+
+    // The cycle the CPU is on. A device read or written by an instruction
+    // sees the cycle that instruction started on, which is near enough for
+    // timers to be worked out from when they were last set instead of
+    // counting every cycle.
+    pub fn now(&self) -> u64 {
+        self.requests.now.load(Ordering::Relaxed)
+    }
+}
+
+// Keeps host callbacks ordered by the emulated cycle they are due on, so
+// the CPU only has to compare one number per clock to know nothing is due.
+pub struct Scheduler {
+    queue: BinaryHeap<Reverse<(u64, AlarmId)>>,
+    alarms: HashMap<AlarmId, Alarm>,
+    // Hands out the ids too, so alarms added either way never share one
+    events: EventQueue,
+    // The alarm whose callback is running, and whether it cancelled itself
+    firing: Option<AlarmId>,
+    firing_cancelled: bool,
+}
+
+impl Scheduler {
+    pub fn new() -> Self {
+        Scheduler {
+            queue: BinaryHeap::new(),
+            alarms: HashMap::new(),
+            events: EventQueue::default(),
+            firing: None,
+            firing_cancelled: false,
+        }
+    }
+
+    pub fn add_alarm(&mut self, deadline: u64, period: Option<u64>, callback: AlarmCallback) -> AlarmId {
+        let id = self.events.next_id();
+        self.insert(id, deadline, period, callback);
+        id
+    }
+
+    fn insert(&mut self, id: AlarmId, deadline: u64, period: Option<u64>, callback: AlarmCallback) {
+        self.queue.push(Reverse((deadline, id)));
+        self.alarms.insert(id, Alarm { deadline, period, callback });
+    }
+
+    pub fn events(&self) -> EventQueue {
+        self.events.clone()
+    }
+
+    // Takes up whatever was asked for through the event queue since the
+    // last call, timing it from `now`
+    pub fn take_requests(&mut self, now: u64) {
+        self.events.requests.now.store(now, Ordering::Relaxed);
+
+        if !self.events.requests.pending.swap(false, Ordering::Acquire) {
+            return;
+        }
+
+        let requests = std::mem::take(&mut *self.events.requests.list.lock().unwrap_or_else(PoisonError::into_inner));
+        for request in requests {
+            match request {
+                Request::Add { id, delay, period, callback } => self.insert(id, now + delay, period, callback),
+                Request::Cancel(id) => {
+                    self.cancel(id);
+                }
+            }
+        }
+    }
+
+    pub fn cancel(&mut self, id: AlarmId) -> bool {
+        if self.firing == Some(id) {
+            self.firing_cancelled = true;
+            return true;
+        }
+
+        let removed = self.alarms.remove(&id).is_some();
+        self.purge();
+        removed
+    }
+
+    // Drop queue entries at the head that no longer belong to a live alarm,
+    // so next_deadline doesn't wake the CPU for an alarm that was cancelled.
+    // Entries further back are skipped by pop_due and purged once they
+    // reach the head.
+    fn purge(&mut self) {
+        while let Some(Reverse((deadline, id))) = self.queue.peek().copied() {
+            match self.alarms.get(&id) {
+                Some(alarm) if alarm.deadline == deadline => break,
+                _ => {
+                    self.queue.pop();
+                }
+            }
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.alarms.len()
+    }
+
+    pub fn next_deadline(&self) -> Option<u64> {
+        self.queue.peek().map(|Reverse((deadline, _))| *deadline)
+    }
+
+    // Remove and return the next alarm that is due at or before `now`.
+    // The caller runs the callback and hands the alarm back with `rearm`.
+    fn pop_due(&mut self, now: u64) -> Option<(AlarmId, Alarm)> {
+        while let Some(Reverse((deadline, id))) = self.queue.peek().copied() {
+            if deadline > now {
+                return None;
+            }
+
+            self.queue.pop();
+
+            // Skip cancelled alarms and stale entries of re-armed ones
+            match self.alarms.get(&id) {
+                Some(alarm) if alarm.deadline == deadline => {
+                    self.firing = Some(id);
+                    self.firing_cancelled = false;
+                    return self.alarms.remove(&id).map(|alarm| (id, alarm));
+                }
+                _ => continue,
+            }
+        }
+
+        None
+    }
+
+    fn rearm(&mut self, id: AlarmId, mut alarm: Alarm) {
+        self.firing = None;
+
+        if let (Some(period), false) = (alarm.period, self.firing_cancelled) {
+            alarm.deadline += period.max(1);
+            self.queue.push(Reverse((alarm.deadline, id)));
+            self.alarms.insert(id, alarm);
+        }
+
+        self.purge();
+    }
+}
+
+// Callbacks are host code and can't be saved, so a snapshot only keeps the
+// timing of each alarm. Loading re-times the alarms that still exist and
+// leaves alone any that were added since.
+impl Snapshot for Scheduler {
+    fn save_state(&self) -> Vec<u8> {
+        let mut w = StateWriter::new();
+
+        let mut ids: Vec<&AlarmId> = self.alarms.keys().collect();
+        ids.sort();
+
+        w.u64(self.events.requests.next_id.load(Ordering::Relaxed));
+        w.u32(ids.len() as u32);
+        for id in ids {
+            let alarm = &self.alarms[id];
+            w.u64(*id);
+            w.u64(alarm.deadline);
+            w.bool(alarm.period.is_some());
+            w.u64(alarm.period.unwrap_or(0));
+        }
+
+        w.finish()
+    }
+
+    fn load_state(&mut self, data: &[u8], _version: u32) -> Result<(), String> {
+        let mut r = StateReader::new(data);
+
+        let next_id = r.u64()?;
+        let mut timings = Vec::new();
+        for _ in 0..r.u32()? {
+            let (id, deadline, periodic, period) = (r.u64()?, r.u64()?, r.bool()?, r.u64()?);
+            timings.push((id, deadline, if periodic { Some(period) } else { None }));
+        }
+
+        for (id, deadline, period) in timings {
+            if let Some(alarm) = self.alarms.get_mut(&id) {
+                alarm.deadline = deadline;
+                alarm.period = period;
+            }
+        }
+
+        // Ids must never be handed out twice
+        self.events.requests.next_id.fetch_max(next_id, Ordering::Relaxed);
+
+        self.queue = self.alarms.iter().map(|(id, alarm)| Reverse((alarm.deadline, *id))).collect();
+
+        Ok(())
+    }
+}
+
+// Run every alarm due at the CPU's current cycle. Alarms are taken out of
+// the scheduler while their callback runs so the callback is free to
+// schedule or cancel other alarms.
+pub fn dispatch(cpu: &mut cpu6502) {
+    let now = cpu.clock_count;
+
+    while let Some((id, mut alarm)) = cpu.scheduler.pop_due(now) {
+        (alarm.callback)(cpu);
+        cpu.scheduler.rearm(id, alarm);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+
+    fn run(cpu: &mut cpu6502, cycles: u64) {
+        for _ in 0..cycles {
+            cpu.clock();
+        }
+    }
+
+    // Records the cycle each call happened on
+    fn log() -> (Arc<Mutex<Vec<u64>>>, impl FnMut(&mut cpu6502) + Send + 'static) {
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let sink = calls.clone();
+        (calls, move |cpu: &mut cpu6502| sink.lock().unwrap().push(cpu.clock_count))
+    }
+
+    #[test]
+    fn one_shot_fires_once() {
+        let mut cpu = cpu6502::new();
+        let (calls, callback) = log();
+
+        cpu.add_alarm_at(25, callback);
+        run(&mut cpu, 100);
+
+        assert_eq!(*calls.lock().unwrap(), vec![25]);
+        assert_eq!(cpu.scheduler.len(), 0);
+        assert_eq!(cpu.scheduler.next_deadline(), None);
+    }
+
+    #[test]
+    fn periodic_rearms() {
+        let mut cpu = cpu6502::new();
+        let (calls, callback) = log();
+
+        cpu.add_alarm_every(30, callback);
+        run(&mut cpu, 100);
+
+        assert_eq!(*calls.lock().unwrap(), vec![30, 60, 90]);
+        assert_eq!(cpu.scheduler.next_deadline(), Some(120));
+    }
+
+    #[test]
+    fn catches_up_on_missed_periods() {
+        let mut cpu = cpu6502::new();
+        let calls = Arc::new(Mutex::new(0));
+        let sink = calls.clone();
+
+        cpu.scheduler.add_alarm(0, Some(10), Box::new(move |_| *sink.lock().unwrap() += 1));
+        cpu.clock_count = 35;
+        dispatch(&mut cpu);
+
+        // Due at 0, 10, 20 and 30
+        assert_eq!(*calls.lock().unwrap(), 4);
+        assert_eq!(cpu.scheduler.next_deadline(), Some(40));
+    }
+
+    #[test]
+    fn callback_can_cancel_itself() {
+        let mut cpu = cpu6502::new();
+        let id = Arc::new(Mutex::new(None));
+        let calls = Arc::new(Mutex::new(0));
+
+        let (own_id, sink) = (id.clone(), calls.clone());
+        let alarm = cpu.add_alarm_every(10, move |cpu| {
+            *sink.lock().unwrap() += 1;
+            if *sink.lock().unwrap() == 2 {
+                cpu.cancel_alarm(own_id.lock().unwrap().unwrap());
+            }
+        });
+        *id.lock().unwrap() = Some(alarm);
+
+        run(&mut cpu, 100);
+
+        assert_eq!(*calls.lock().unwrap(), 2);
+        assert_eq!(cpu.scheduler.len(), 0);
+    }
+
+    #[test]
+    fn cancel_purges_the_queue() {
+        let mut cpu = cpu6502::new();
+        let (calls, callback) = log();
+
+        let early = cpu.add_alarm_at(10, |_| panic!("cancelled alarm fired"));
+        let middle = cpu.add_alarm_at(20, |_| panic!("cancelled alarm fired"));
+        cpu.add_alarm_at(30, callback);
+
+        // Cancelling something behind the head leaves the head alone
+        assert!(cpu.cancel_alarm(middle));
+        assert_eq!(cpu.scheduler.next_deadline(), Some(10));
+
+        // Cancelling the head skips straight past the stale middle entry
+        assert!(cpu.cancel_alarm(early));
+        assert_eq!(cpu.scheduler.next_deadline(), Some(30));
+        assert!(!cpu.cancel_alarm(early));
+
+        run(&mut cpu, 50);
+        assert_eq!(*calls.lock().unwrap(), vec![30]);
+    }
+
+    #[test]
+    fn queued_events_count_from_pickup() {
+        let mut cpu = cpu6502::new();
+        let events = cpu.events();
+        let (calls, callback) = log();
+        let (ticks, tick) = log();
+
+        run(&mut cpu, 10);
+        events.schedule_in(0, callback);
+        events.schedule_every(25, tick);
+        assert_eq!(cpu.scheduler.len(), 0);
+
+        // Taken up at the end of the 11th clock
+        run(&mut cpu, 70);
+        assert_eq!(*calls.lock().unwrap(), vec![11]);
+        assert_eq!(*ticks.lock().unwrap(), vec![36, 61]);
+    }
+
+    #[test]
+    fn queued_cancel_and_other_threads() {
+        let mut cpu = cpu6502::new();
+        let events = cpu.events();
+        let (calls, callback) = log();
+
+        let doomed = events.schedule_in(5, |_| panic!("cancelled event fired"));
+        events.cancel(doomed);
+
+        // Ids from the queue and from the CPU never clash
+        let alarm = cpu.add_alarm_at(1000, |_| {});
+        assert_ne!(alarm, doomed);
+
+        let remote = events.clone();
+        std::thread::spawn(move || remote.schedule_in(20, callback)).join().unwrap();
+
+        run(&mut cpu, 50);
+        assert_eq!(*calls.lock().unwrap(), vec![21]);
+        assert_eq!(cpu.scheduler.len(), 1);
+    }
+}
@@ -0,0 +1,325 @@
+use crate::benchmarks;
+use crate::demos;
+use crate::tstates;
+use crate::snapshot::MachineState;
+use crate::{cpu6502, is_documented, FLAGS6502};
+
+// A quick battery of checks built into the binary, so a build can be
+// trusted on a new platform before anything is run on it. Each check is
+// small and self-contained, the whole lot takes milliseconds.
+
+// Sums 5..1 in a loop, calls a subroutine, round trips the stack and reads
+// a table indexed by Y, leaving each result in zero page, then spins at
+// DONE
+const PROGRAM: &[u8] = &[
+    0xA2, 0xFF, //       LDX #$FF
+    0x9A, //             TXS
+    0xA9, 0x00, //       LDA #$00
+    0xA2, 0x05, //       LDX #$05
+    0x86, 0x11, // loop: STX $11
+    0x18, //             CLC
+    0x65, 0x11, //       ADC $11
+    0xCA, //             DEX
+    0xD0, 0xF8, //       BNE loop
+    0x85, 0x10, //       STA $10
+    0x20, 0x28, 0x80, // JSR sub
+    0x85, 0x12, //       STA $12
+    0xA9, 0x37, //       LDA #$37
+    0x48, //             PHA
+    0xA9, 0x00, //       LDA #$00
+    0x68, //             PLA
+    0x85, 0x13, //       STA $13
+    0xA0, 0x02, //       LDY #$02
+    0xB9, 0x30, 0x80, // LDA table,Y
+    0x85, 0x14, //       STA $14
+    0x4C, 0x25, 0x80, // DONE: JMP DONE
+    0xA9, 0x42, // sub:  LDA #$42
+    0x60, //             RTS
+    0xEA, 0xEA, 0xEA, 0xEA, 0xEA,
+    0x11, 0x22, 0x33, // table
+];
+
+const ORIGIN: u16 = 0x8000;
+const DONE: u16 = 0x8025;
+
+// What PROGRAM leaves at $10-$14
+const EXPECTED: [u8; 5] = [15, 1, 0x42, 0x37, 0x33];
+
+// Plenty for PROGRAM, which needs about 130 cycles
+const MAX_CYCLES: u32 = 10_000;
+
+pub struct Check {
+    pub name: &'static str,
+    // Empty when the check passed
+    pub failures: Vec<String>,
+    // How long it took the guest, for the benchmarks
+    pub cycles: Option<u64>,
+}
+
+pub struct SelfTestReport {
+    pub checks: Vec<Check>,
+}
+
+impl SelfTestReport {
+    pub fn passed(&self) -> bool {
+        self.checks.iter().all(|check| check.failures.is_empty())
+    }
+
+    pub fn describe(&self) -> Vec<String> {
+        let mut lines = Vec::new();
+
+        for check in &self.checks {
+            let verdict = if check.failures.is_empty() { "ok" } else { "FAILED" };
+            match check.cycles {
+                Some(cycles) => lines.push(std::format!("{:<24} {:<6} {} cycles", check.name, verdict, cycles)),
+                None => lines.push(std::format!("{:<24} {}", check.name, verdict)),
+            }
+            lines.extend(check.failures.iter().map(|failure| std::format!("    {}", failure)));
+        }
+
+        let failed = self.checks.iter().filter(|check| !check.failures.is_empty()).count();
+        lines.push(std::format!("{} checks, {} failed", self.checks.len(), failed));
+        lines
+    }
+}
+
+pub fn run() -> SelfTestReport {
+    let checks: [(&'static str, fn() -> Vec<String>); 6] = [
+        ("opcode table", opcode_table),
+        ("functional program", functional_program),
+        ("flags", flags),
+        ("t-state audit", tstates::audit),
+        ("save/load round trip", save_load),
+        ("demo programs", demo_programs),
+    ];
+
+    let mut report = SelfTestReport {
+        checks: checks.iter().map(|(name, check)| Check { name, failures: check(), cycles: None }).collect(),
+    };
+
+    // A line each for the benchmarks, with what they took
+    for benchmark in benchmarks::BENCHMARKS {
+        let result = benchmark.run();
+        report.checks.push(Check { name: benchmark.name, failures: result.failures, cycles: result.cycles });
+    }
+
+    report
+}
+
+fn opcode_table() -> Vec<String> {
+    let cpu = cpu6502::new();
+    let mut failures = Vec::new();
+
+    if cpu.lookup.len() != 256 {
+        failures.push(std::format!("{} entries, expected 256", cpu.lookup.len()));
+        return failures;
+    }
+
+    for opcode in 0..256 {
+        if is_documented(opcode as u8) && cpu.lookup[opcode].name == "???" {
+            failures.push(std::format!("${:02x} is documented but missing", opcode));
+        }
+    }
+
+    for (opcode, instruction) in cpu.lookup.iter().enumerate() {
+        if instruction.name != "???" && !(2..=7).contains(&instruction.cycles) {
+            failures.push(std::format!("${:02x} {} takes {} cycles", opcode, instruction.name, instruction.cycles));
+        }
+    }
+
+    // One of each addressing mode, and the odd ones out
+    let spots: [(u8, &str, u16, u8); 14] = [
+        (0x00, "BRK", 2, 7),
+        (0xA9, "LDA", 2, 2),
+        (0xA5, "LDA", 2, 3),
+        (0xB5, "LDA", 2, 4),
+        (0xB6, "LDX", 2, 4),
+        (0xAD, "LDA", 3, 4),
+        (0xBD, "LDA", 3, 4),
+        (0xB9, "LDA", 3, 4),
+        (0xA1, "LDA", 2, 6),
+        (0xB1, "LDA", 2, 5),
+        (0x6C, "JMP", 3, 5),
+        (0x20, "JSR", 3, 6),
+        (0xD0, "BNE", 2, 2),
+        (0xEA, "NOP", 1, 2),
+    ];
+
+    for (opcode, name, len, cycles) in spots {
+        let instruction = &cpu.lookup[opcode as usize];
+        let found = (instruction.name.as_str(), cpu.instruction_len(opcode), instruction.cycles);
+        if found != (name, len, cycles) {
+            failures.push(std::format!("${:02x} is {} ({} bytes, {} cycles), expected {} ({} bytes, {} cycles)", opcode, found.0, found.1, found.2, name, len, cycles));
+        }
+    }
+
+    failures
+}
+
+fn program() -> cpu6502 {
+    let mut cpu = cpu6502::new();
+    cpu.load_program(PROGRAM, ORIGIN);
+    cpu.set_reset_vector(ORIGIN);
+    cpu.reset();
+    cpu
+}
+
+fn run_to_done(cpu: &mut cpu6502) -> bool {
+    for _ in 0..MAX_CYCLES {
+        cpu.clock();
+        if cpu.complete() && cpu.pc == DONE {
+            return true;
+        }
+    }
+    false
+}
+
+fn functional_program() -> Vec<String> {
+    let mut cpu = program();
+    if !run_to_done(&mut cpu) {
+        return vec![std::format!("never reached ${:04x}, stopped at ${:04x}", DONE, cpu.pc)];
+    }
+
+    let mut failures = Vec::new();
+    for (i, expected) in EXPECTED.iter().enumerate() {
+        let addr = 0x10 + i as u16;
+        let found = cpu.bus.borrow().read(addr, true);
+        if found != *expected {
+            failures.push(std::format!("${:02x} is ${:02x}, expected ${:02x}", addr, found, expected));
+        }
+    }
+
+    if cpu.stkp != 0xFF {
+        failures.push(std::format!("stack pointer ${:02x} at the end, expected $ff", cpu.stkp));
+    }
+
+    failures
+}
+
+// Runs one instruction from `a` with the carry as given and returns A and
+// the status register after it
+fn one(code: &[u8], a: u8, carry: bool) -> (u8, u8) {
+    let mut cpu = cpu6502::new();
+    cpu.load_program(code, ORIGIN);
+    cpu.load_program(&[0xC0], 0x0020);
+    cpu.set_reset_vector(ORIGIN);
+    cpu.reset();
+    while !cpu.complete() {
+        cpu.clock();
+    }
+
+    cpu.a = a;
+    cpu.set_flag(FLAGS6502::C, carry);
+    loop {
+        cpu.clock();
+        if cpu.complete() {
+            return (cpu.a, cpu.status);
+        }
+    }
+}
+
+fn flags() -> Vec<String> {
+    const C: u8 = FLAGS6502::C as u8;
+    const Z: u8 = FLAGS6502::Z as u8;
+    const V: u8 = FLAGS6502::V as u8;
+    const N: u8 = FLAGS6502::N as u8;
+
+    // Name, instruction, A, carry in, A out, flags that must be set, flags
+    // that must be clear
+    let cases: [(&str, &[u8], u8, bool, u8, u8, u8); 7] = [
+        ("ADC signed overflow", &[0x69, 0x50], 0x50, false, 0xA0, V | N, C | Z),
+        ("ADC carry out", &[0x69, 0x01], 0xFF, false, 0x00, C | Z, V | N),
+        ("ADC carry in", &[0x69, 0x01], 0x01, true, 0x03, 0, C | Z | V | N),
+        ("SBC borrow", &[0xE9, 0x01], 0x00, true, 0xFF, N, C | Z | V),
+        ("SBC signed overflow", &[0xE9, 0x01], 0x80, true, 0x7F, C | V, Z | N),
+        ("CMP equal", &[0xC9, 0x40], 0x40, false, 0x40, C | Z, N),
+        ("BIT copies bits 7 and 6", &[0x24, 0x20], 0x01, false, 0x01, Z | N | V, 0),
+    ];
+
+    let mut failures = Vec::new();
+    for (name, code, a, carry, result, set, clear) in cases {
+        let (found, status) = one(code, a, carry);
+        if found != result || status & set != set || status & clear != 0 {
+            failures.push(std::format!("{}: A=${:02x} P=${:02x}, expected A=${:02x}", name, found, status, result));
+        }
+    }
+
+    failures
+}
+
+fn save_load() -> Vec<String> {
+    // Stop part way through, inside the loop
+    let mut cpu = program();
+    for _ in 0..40 {
+        cpu.clock();
+    }
+    let state = MachineState::capture(&cpu);
+
+    let mut failures = Vec::new();
+    match MachineState::parse(&state.to_text()) {
+        Ok(parsed) if parsed == state => {}
+        Ok(_) => failures.push("text image reads back different".to_string()),
+        Err(e) => failures.push(std::format!("text image doesn't read back: {}", e)),
+    }
+
+    let path = std::env::temp_dir().join(std::format!("crust6502-selftest-{}.state", std::process::id()));
+    let loaded = state.save(&path).and_then(|_| MachineState::load(&path));
+    let _ = std::fs::remove_file(&path);
+    match loaded {
+        Ok(loaded) if loaded == state => {}
+        Ok(_) => failures.push("state file reads back different".to_string()),
+        Err(e) => failures.push(std::format!("state file: {}", e)),
+    }
+
+    // A machine restored from the image has to finish exactly like the
+    // one it was taken from
+    let mut restored = cpu6502::new();
+    if let Err(e) = state.restore(&mut restored) {
+        failures.push(std::format!("restore failed: {}", e));
+        return failures;
+    }
+
+    if !run_to_done(&mut cpu) || !run_to_done(&mut restored) {
+        failures.push("program never finished after the snapshot".to_string());
+    } else if MachineState::capture(&cpu) != MachineState::capture(&restored) {
+        failures.push("restored machine finished in a different state".to_string());
+    }
+
+    failures
+}
+
+fn demo_programs() -> Vec<String> {
+    demos::DEMOS.iter().flat_map(|demo| demo.verify()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn everything_passes() {
+        let report = run();
+        assert!(report.passed(), "{}", report.describe().join("\n"));
+        assert_eq!(report.describe().last().unwrap(), "10 checks, 0 failed");
+    }
+
+    #[test]
+    fn failures_are_listed() {
+        let report = SelfTestReport {
+            checks: vec![
+                Check { name: "flags", failures: vec!["ADC carry out: A=$00 P=$24, expected A=$00".to_string()], cycles: None },
+                Check { name: "opcode table", failures: Vec::new(), cycles: None },
+                Check { name: "sieve", failures: Vec::new(), cycles: Some(12345) },
+            ],
+        };
+
+        assert!(!report.passed());
+        assert_eq!(report.describe(), vec![
+            "flags                    FAILED",
+            "    ADC carry out: A=$00 P=$24, expected A=$00",
+            "opcode table             ok",
+            "sieve                    ok     12345 cycles",
+            "3 checks, 1 failed",
+        ]);
+    }
+}
@@ -0,0 +1,338 @@
+use std::collections::BTreeSet;
+
+use crate::diagnostics::{Diagnostic, DiagnosticKind, Severity};
+use crate::snapshot::{Snapshot, StateReader, StateWriter};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FrameKind {
+    Subroutine,
+    Interrupt,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Frame {
+    pub kind: FrameKind,
+    // Where execution should continue once this frame returns
+    pub return_to: u16,
+    // Stack pointer after the return address (and status) were pushed
+    pub stkp: u8,
+}
+
+// A host-side copy of every return address the guest pushes. Returns are
+// checked against it to tell genuine stack corruption from RTS tricks.
+//
+// Strict, it also watches SP going round and treats a return to an
+// address that was never pushed as an error rather than a likely trick, so
+// the debugger stops on either.
+pub struct ShadowStack {
+    frames: Vec<Frame>,
+    // Addresses of RTS/RTI instructions that are allowed to return anywhere
+    whitelist: BTreeSet<u16>,
+    strict: bool,
+}
+
+impl ShadowStack {
+    pub fn new() -> Self {
+        ShadowStack {
+            frames: Vec::new(),
+            whitelist: BTreeSet::new(),
+            strict: false,
+        }
+    }
+
+    pub fn strict() -> Self {
+        ShadowStack { strict: true, ..ShadowStack::new() }
+    }
+
+    pub fn is_strict(&self) -> bool {
+        self.strict
+    }
+
+    pub fn allow(&mut self, return_site: u16) {
+        self.whitelist.insert(return_site);
+    }
+
+    pub fn disallow(&mut self, return_site: u16) {
+        self.whitelist.remove(&return_site);
+    }
+
+    pub fn depth(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn clear(&mut self) {
+        self.frames.clear();
+    }
+
+    // Outermost frame first
+    pub fn frames(&self) -> &[Frame] {
+        &self.frames
+    }
+
+    // IRQ, NMI and BRK all push PC and status. The CPU reports these itself
+    // as they happen, with the return address it actually pushed.
+    pub fn push_interrupt(&mut self, return_to: u16, stkp: u8) {
+        self.frames.push(Frame {
+            kind: FrameKind::Interrupt,
+            return_to,
+            stkp,
+        });
+    }
+
+    // Called by the CPU after every instruction. `pc` is the instruction's
+    // own address, `stkp` the stack pointer before it ran and `new_pc` where
+    // it left the program counter.
+    pub fn on_instruction(&mut self, opcode: u8, pc: u16, stkp: u8, new_pc: u16, cycle: u64) -> Vec<Diagnostic> {
+        let mut found = Vec::new();
+
+        match opcode {
+            // JSR returns to the byte after its operand
+            0x20 => self.frames.push(Frame {
+                kind: FrameKind::Subroutine,
+                return_to: pc.wrapping_add(3),
+                stkp: stkp.wrapping_sub(2),
+            }),
+
+            // RTS, RTI
+            0x60 | 0x40 => {
+                if self.whitelist.contains(&pc) {
+                    self.unwind(stkp);
+                    self.pop_matching(stkp);
+                    return found;
+                }
+
+                let abandoned = self.unwind(stkp);
+                if abandoned > 0 {
+                    found.push(Diagnostic {
+                        cycle,
+                        pc,
+                        severity: Severity::Warning,
+                        kind: DiagnosticKind::AbandonedFrames { count: abandoned },
+                    });
+                }
+
+                match self.pop_matching(stkp) {
+                    Some(frame) if frame.return_to != new_pc => found.push(Diagnostic {
+                        cycle,
+                        pc,
+                        severity: Severity::Error,
+                        kind: DiagnosticKind::ReturnMismatch {
+                            expected: frame.return_to,
+                            actual: new_pc,
+                        },
+                    }),
+                    Some(_) => {}
+                    None => found.push(Diagnostic {
+                        cycle,
+                        pc,
+                        severity: if self.strict { Severity::Error } else { Severity::Warning },
+                        kind: DiagnosticKind::UnmatchedReturn { target: new_pc },
+                    }),
+                }
+            }
+
+            _ => {}
+        }
+
+        found
+    }
+
+    // When strict, SP going from `stkp` to `new_stkp` by pushing (or by
+    // pulling) and passing $00 (or $ff) on the way
+    pub fn check_wrap(&self, pc: u16, stkp: u8, new_stkp: u8, push: bool, cycle: u64) -> Option<Diagnostic> {
+        let wrapped = if push { new_stkp > stkp } else { new_stkp < stkp };
+        (self.strict && wrapped).then(|| Diagnostic {
+            cycle,
+            pc,
+            severity: Severity::Error,
+            kind: DiagnosticKind::StackWrapped { overflow: push },
+        })
+    }
+
+    // Drop frames deeper than the current stack pointer, the guest has
+    // discarded their return addresses. Returns how many were dropped.
+    fn unwind(&mut self, stkp: u8) -> usize {
+        let mut count = 0;
+
+        while let Some(frame) = self.frames.last() {
+            if frame.stkp < stkp {
+                self.frames.pop();
+                count += 1;
+            } else {
+                break;
+            }
+        }
+
+        count
+    }
+
+    fn pop_matching(&mut self, stkp: u8) -> Option<Frame> {
+        match self.frames.last() {
+            Some(frame) if frame.stkp == stkp => self.frames.pop(),
+            _ => None,
+        }
+    }
+}
+
+// The frames only, allowed return sites are the user's settings
+impl Snapshot for ShadowStack {
+    fn save_state(&self) -> Vec<u8> {
+        let mut w = StateWriter::new();
+
+        w.u32(self.frames.len() as u32);
+        for frame in &self.frames {
+            w.bool(frame.kind == FrameKind::Interrupt);
+            w.u16(frame.return_to);
+            w.u8(frame.stkp);
+        }
+
+        w.finish()
+    }
+
+    fn load_state(&mut self, data: &[u8], _version: u32) -> Result<(), String> {
+        let mut r = StateReader::new(data);
+
+        let mut frames = Vec::new();
+        for _ in 0..r.u32()? {
+            let kind = if r.bool()? { FrameKind::Interrupt } else { FrameKind::Subroutine };
+            frames.push(Frame { kind, return_to: r.u16()?, stkp: r.u8()? });
+        }
+
+        self.frames = frames;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu6502;
+
+    const JSR: u8 = 0x20;
+    const RTS: u8 = 0x60;
+
+    #[test]
+    fn matched_call_and_return() {
+        let mut shadow = ShadowStack::new();
+
+        assert!(shadow.on_instruction(JSR, 0x8000, 0xFD, 0x9000, 0).is_empty());
+        assert!(shadow.on_instruction(JSR, 0x9000, 0xFB, 0xA000, 6).is_empty());
+        assert_eq!(shadow.depth(), 2);
+
+        assert!(shadow.on_instruction(RTS, 0xA000, 0xF9, 0x9003, 12).is_empty());
+        assert!(shadow.on_instruction(RTS, 0x9003, 0xFB, 0x8003, 18).is_empty());
+        assert_eq!(shadow.depth(), 0);
+    }
+
+    #[test]
+    fn corrupted_return_address() {
+        let mut shadow = ShadowStack::new();
+        shadow.on_instruction(JSR, 0x8000, 0xFD, 0x9000, 0);
+
+        let found = shadow.on_instruction(RTS, 0x9000, 0xFB, 0x1235, 6);
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].severity, Severity::Error);
+        assert_eq!(found[0].kind, DiagnosticKind::ReturnMismatch { expected: 0x8003, actual: 0x1235 });
+    }
+
+    #[test]
+    fn rts_trick_needs_allowing() {
+        // A jump table pushes its target and RTSes to it, no JSR involved
+        let mut shadow = ShadowStack::new();
+        let found = shadow.on_instruction(RTS, 0x8100, 0xFB, 0x8400, 0);
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].kind, DiagnosticKind::UnmatchedReturn { target: 0x8400 });
+
+        shadow.allow(0x8100);
+        assert!(shadow.on_instruction(RTS, 0x8100, 0xFB, 0x8400, 10).is_empty());
+
+        // Allowed sites may also return past real frames without complaint
+        shadow.on_instruction(JSR, 0x8000, 0xFD, 0x8100, 20);
+        assert!(shadow.on_instruction(RTS, 0x8100, 0xFB, 0x8500, 26).is_empty());
+        assert_eq!(shadow.depth(), 0);
+
+        shadow.disallow(0x8100);
+        assert!(!shadow.on_instruction(RTS, 0x8100, 0xFB, 0x8400, 30).is_empty());
+    }
+
+    #[test]
+    fn dropped_frames_are_reported() {
+        let mut shadow = ShadowStack::new();
+        shadow.on_instruction(JSR, 0x8000, 0xFD, 0x9000, 0);
+        shadow.on_instruction(JSR, 0x9000, 0xFB, 0xA000, 6);
+
+        // The inner routine threw away its return address and returned
+        // straight to the outer caller
+        let found = shadow.on_instruction(RTS, 0xA010, 0xFB, 0x8003, 20);
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].kind, DiagnosticKind::AbandonedFrames { count: 1 });
+        assert_eq!(shadow.depth(), 0);
+    }
+
+    #[test]
+    fn interrupts_return_through_rti() {
+        let mut shadow = ShadowStack::new();
+        shadow.push_interrupt(0x8005, 0xFA);
+        assert!(shadow.on_instruction(0x40, 0xC000, 0xFA, 0x8005, 0).is_empty());
+        assert_eq!(shadow.depth(), 0);
+    }
+
+    #[test]
+    fn strict_mode() {
+        let mut shadow = ShadowStack::strict();
+        let found = shadow.on_instruction(RTS, 0x8100, 0xFB, 0x8400, 0);
+        assert_eq!(found[0].severity, Severity::Error);
+
+        // Pushing at $00 wraps, pulling at $fe doesn't
+        assert_eq!(shadow.check_wrap(0x8000, 0x00, 0xFF, true, 0).unwrap().kind, DiagnosticKind::StackWrapped { overflow: true });
+        assert!(shadow.check_wrap(0x8000, 0xFE, 0xFF, false, 0).is_none());
+        assert!(ShadowStack::new().check_wrap(0x8000, 0x00, 0xFF, true, 0).is_none());
+    }
+
+    #[test]
+    fn stack_checks_stop_on_wraps() {
+        // LDX #$01 / TXS / PHA / PHA / PHA, then the same pulling from $fe,
+        // then JMP * rather than running on into a BRK
+        let mut cpu = cpu6502::new();
+        cpu.load_program(&[0xA2, 0x01, 0x9A, 0x48, 0x48, 0x48, 0xA2, 0xFE, 0x9A, 0x68, 0x68, 0x4C, 0x0B, 0x80], 0x8000);
+        cpu.set_reset_vector(0x8000);
+        cpu.enable_stack_checks();
+        cpu.reset();
+
+        for _ in 0..40 {
+            cpu.clock();
+        }
+        let found: Vec<(u16, String)> = cpu.diagnostics.drain().iter().map(|d| (d.pc, d.message())).collect();
+        assert_eq!(found, vec![
+            (0x8004, "stack overflow, SP wrapped from $00 to $ff".to_string()),
+            (0x800A, "stack underflow, SP wrapped from $ff to $00".to_string()),
+        ]);
+    }
+
+    #[test]
+    fn reset_forgets_frames() {
+        // $8000 JSR $8010 / JMP $8000, $8010 JSR $8020 / RTS, $8020 RTS
+        let mut cpu = cpu6502::new();
+        cpu.load_program(&[0x20, 0x10, 0x80, 0x4C, 0x00, 0x80], 0x8000);
+        cpu.load_program(&[0x20, 0x20, 0x80, 0x60], 0x8010);
+        cpu.load_program(&[0x60], 0x8020);
+        cpu.set_reset_vector(0x8000);
+
+        cpu.enable_shadow_stack();
+        cpu.enable_profiler();
+        cpu.reset();
+
+        while cpu.shadow_stack.as_ref().unwrap().depth() < 2 {
+            cpu.clock();
+        }
+
+        cpu.reset();
+        assert_eq!(cpu.shadow_stack.as_ref().unwrap().depth(), 0);
+        assert!(cpu.profile_report(crate::profiler::ProfileSort::Inclusive).is_empty());
+
+        for _ in 0..200 {
+            cpu.clock();
+        }
+        assert!(cpu.diagnostics.drain().is_empty());
+    }
+}
@@ -0,0 +1,770 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::{cpu6502, decode_hex, encode_hex, Bus, Halt, InterruptPoll};
+
+// Bump when the layout of any device's state changes. Loaders get the
+// version an image was written with so they can still read older ones.
+pub const STATE_VERSION: u32 = 2;
+
+const HEADER: &str = "crust-state";
+
+// Implemented by everything on the bus that has state worth keeping: RAM,
+// cartridges and their mappers, and later timers and video chips. The
+// blob format is up to the device, StateWriter and StateReader help.
+pub trait Snapshot {
+    fn save_state(&self) -> Vec<u8>;
+    fn load_state(&mut self, data: &[u8], version: u32) -> Result<(), String>;
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CpuState {
+    pub a: u8,
+    pub x: u8,
+    pub y: u8,
+    pub stkp: u8,
+    pub pc: u16,
+    pub status: u8,
+    pub cycles: u8,
+    pub clock_count: u64,
+}
+
+impl CpuState {
+    pub fn of(cpu: &cpu6502) -> CpuState {
+        CpuState {
+            a: cpu.a,
+            x: cpu.x,
+            y: cpu.y,
+            stkp: cpu.stkp,
+            pc: cpu.pc,
+            status: cpu.status,
+            cycles: cpu.cycles,
+            clock_count: cpu.clock_count,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DeviceState {
+    pub name: String,
+    pub data: Vec<u8>,
+}
+
+// A complete system image: the CPU plus every device on its bus
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MachineState {
+    pub version: u32,
+    pub cpu: CpuState,
+    pub devices: Vec<DeviceState>,
+}
+
+impl MachineState {
+    pub fn capture(cpu: &cpu6502) -> MachineState {
+        let bus = cpu.bus.borrow();
+        let devices = bus.devices().into_iter().chain(cpu.devices())
+            .map(|(name, device)| DeviceState { name: name.to_string(), data: device.save_state() })
+            .collect();
+
+        MachineState {
+            version: STATE_VERSION,
+            cpu: CpuState::of(cpu),
+            devices,
+        }
+    }
+
+    // Devices the image knows nothing about keep their current state and
+    // devices we don't have are skipped, so images survive hardware being
+    // added or removed. Nothing changes unless every device accepts its
+    // state.
+    pub fn restore(&self, cpu: &mut cpu6502) -> Result<(), String> {
+        if self.version > STATE_VERSION {
+            return Err(std::format!("state version {} is newer than this build understands", self.version));
+        }
+
+        let bus = cpu.bus.clone();
+        let mut bus = bus.borrow_mut();
+        let mut devices = bus.devices_mut();
+        devices.extend(cpu.devices_mut());
+        load_devices(&mut devices, &self.devices, self.version)?;
+
+        cpu.a = self.cpu.a;
+        cpu.x = self.cpu.x;
+        cpu.y = self.cpu.y;
+        cpu.stkp = self.cpu.stkp;
+        cpu.pc = self.cpu.pc;
+        cpu.set_status(self.cpu.status);
+        cpu.cycles = self.cpu.cycles;
+        cpu.clock_count = self.cpu.clock_count;
+
+        Ok(())
+    }
+
+    pub fn device(&self, name: &str) -> Option<&[u8]> {
+        self.devices.iter().find(|device| device.name == name).map(|device| device.data.as_slice())
+    }
+
+    // FNV-1a over everything in the image. Unlike std's hashers it's the
+    // same from one build and one machine to the next, so hashes can be
+    // written down and compared later.
+    pub fn hash(&self) -> u64 {
+        let mut hash: u64 = 0xcbf29ce484222325;
+        let mut feed = |bytes: &[u8]| {
+            for b in bytes {
+                hash ^= *b as u64;
+                hash = hash.wrapping_mul(0x100000001b3);
+            }
+        };
+
+        let c = &self.cpu;
+        feed(&self.version.to_le_bytes());
+        feed(&[c.a, c.x, c.y, c.stkp, c.status, c.cycles]);
+        feed(&c.pc.to_le_bytes());
+        feed(&c.clock_count.to_le_bytes());
+
+        for device in &self.devices {
+            feed(device.name.as_bytes());
+            feed(&(device.data.len() as u32).to_le_bytes());
+            feed(&device.data);
+        }
+
+        hash
+    }
+
+    // The runs of bytes that changed going from self to other, device by
+    // device, for finding where a program keeps something: snapshot, do the
+    // thing, snapshot again. Registers and devices that changed size or
+    // are only on one side are left to describe_diff.
+    pub fn diff(&self, other: &MachineState) -> Vec<ChangedRange> {
+        let mut ranges: Vec<ChangedRange> = Vec::new();
+
+        for device in &self.devices {
+            let Some(theirs) = other.device(&device.name).filter(|theirs| theirs.len() == device.data.len()) else {
+                continue;
+            };
+
+            for (offset, (&before, &after)) in device.data.iter().zip(theirs).enumerate() {
+                if before == after {
+                    continue;
+                }
+                match ranges.last_mut() {
+                    Some(range) if range.device == device.name && range.end() == offset => {
+                        range.before.push(before);
+                        range.after.push(after);
+                    }
+                    _ => ranges.push(ChangedRange { device: device.name.clone(), start: offset, before: vec![before], after: vec![after] }),
+                }
+            }
+        }
+
+        ranges
+    }
+
+    // Everything that changed going from self to other as text, a line per
+    // register or run of bytes: "x: 04 -> 05", "ram $0010: 00 -> 01".
+    // Devices only one side has are reported as added or removed.
+    pub fn describe_diff(&self, other: &MachineState) -> Vec<String> {
+        let (a, b) = (&self.cpu, &other.cpu);
+        let mut lines = Vec::new();
+
+        let registers = [("a", a.a, b.a), ("x", a.x, b.x), ("y", a.y, b.y), ("sp", a.stkp, b.stkp), ("p", a.status, b.status)];
+        for (name, before, after) in registers {
+            if before != after {
+                lines.push(std::format!("{}: {:02x} -> {:02x}", name, before, after));
+            }
+        }
+        if a.pc != b.pc {
+            lines.push(std::format!("pc: {:04x} -> {:04x}", a.pc, b.pc));
+        }
+        if (a.clock_count, a.cycles) != (b.clock_count, b.cycles) {
+            lines.push(std::format!("clock: {}+{} -> {}+{}", a.clock_count, a.cycles, b.clock_count, b.cycles));
+        }
+
+        let ranges = self.diff(other);
+
+        for device in &self.devices {
+            match other.device(&device.name) {
+                None => lines.push(std::format!("{}: removed", device.name)),
+                Some(theirs) if theirs.len() != device.data.len() => {
+                    lines.push(std::format!("{}: {} bytes -> {}", device.name, device.data.len(), theirs.len()));
+                }
+                Some(_) => lines.extend(ranges.iter().filter(|range| range.device == device.name).map(ChangedRange::describe)),
+            }
+        }
+
+        for device in &other.devices {
+            if self.device(&device.name).is_none() {
+                lines.push(std::format!("{}: added", device.name));
+            }
+        }
+
+        lines
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        fs::write(path, self.to_text())
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<MachineState> {
+        let text = fs::read_to_string(path)?;
+        MachineState::parse(&text).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    pub fn to_text(&self) -> String {
+        let c = &self.cpu;
+        let mut s = String::new();
+
+        s.push_str(std::format!("{} {}\n", HEADER, self.version).as_str());
+        s.push_str(std::format!("cpu {:02x} {:02x} {:02x} {:02x} {:04x} {:02x} {} {}\n", c.a, c.x, c.y, c.stkp, c.pc, c.status, c.cycles, c.clock_count).as_str());
+
+        for device in &self.devices {
+            s.push_str(std::format!("device {} {}\n", device.name, encode_hex(&device.data)).as_str());
+        }
+
+        s
+    }
+
+    pub fn parse(text: &str) -> Result<MachineState, String> {
+        let mut lines = text.lines();
+
+        let version = match lines.next().and_then(|l| l.strip_prefix(HEADER)) {
+            Some(version) => version.trim().parse::<u32>().map_err(|_| "bad state version".to_string())?,
+            None => return Err("not a state file".to_string()),
+        };
+
+        let hex8 = |s: &str| u8::from_str_radix(s, 16).map_err(|e| e.to_string());
+        let hex16 = |s: &str| u16::from_str_radix(s, 16).map_err(|e| e.to_string());
+
+        let regs: Vec<&str> = lines.next().unwrap_or("").split_whitespace().collect();
+        if regs.len() != 9 || regs[0] != "cpu" {
+            return Err("missing cpu line".to_string());
+        }
+
+        let cpu = CpuState {
+            a: hex8(regs[1])?,
+            x: hex8(regs[2])?,
+            y: hex8(regs[3])?,
+            stkp: hex8(regs[4])?,
+            pc: hex16(regs[5])?,
+            status: hex8(regs[6])?,
+            cycles: regs[7].parse().map_err(|_| "bad cycle count".to_string())?,
+            clock_count: regs[8].parse().map_err(|_| "bad clock count".to_string())?,
+        };
+
+        let mut devices = Vec::new();
+
+        for line in lines {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+
+            match fields.as_slice() {
+                [] => {}
+                ["device", name] => devices.push(DeviceState { name: name.to_string(), data: Vec::new() }),
+                ["device", name, data] => devices.push(DeviceState {
+                    name: name.to_string(),
+                    data: decode_hex(data).map_err(|e| e.to_string())?,
+                }),
+                _ => return Err(std::format!("bad line '{}'", line)),
+            }
+        }
+
+        Ok(MachineState { version, cpu, devices })
+    }
+}
+
+// Consecutive bytes of one device that all changed. For RAM the offset is
+// the address.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChangedRange {
+    pub device: String,
+    pub start: usize,
+    pub before: Vec<u8>,
+    pub after: Vec<u8>,
+}
+
+impl ChangedRange {
+    // One past the last byte
+    pub fn end(&self) -> usize {
+        self.start + self.before.len()
+    }
+
+    // "ram $0010-$0011: 00 01 -> 05 06", the bytes cut short past eight
+    pub fn describe(&self) -> String {
+        let bytes = |data: &[u8]| {
+            let mut text: Vec<String> = data.iter().take(8).map(|byte| std::format!("{:02x}", byte)).collect();
+            if data.len() > 8 {
+                text.push("...".to_string());
+            }
+            text.join(" ")
+        };
+
+        let place = match self.before.len() {
+            1 => std::format!("${:04x}", self.start),
+            _ => std::format!("${:04x}-${:04x}", self.start, self.end() - 1),
+        };
+        std::format!("{} {}: {} -> {}", self.device, place, bytes(&self.before), bytes(&self.after))
+    }
+}
+
+pub type DeviceList<'a> = Vec<(&'static str, &'a dyn Snapshot)>;
+pub type DeviceListMut<'a> = Vec<(&'static str, &'a mut dyn Snapshot)>;
+
+// Hand each device its entry from the image. A device that turns its state
+// down gets the ones loaded before it put back the way they were, so a bad
+// image never leaves the machine half restored.
+pub fn load_devices(devices: &mut DeviceListMut, image: &[DeviceState], version: u32) -> Result<(), String> {
+    let backup: Vec<Vec<u8>> = devices.iter().map(|(_, device)| device.save_state()).collect();
+
+    for i in 0..devices.len() {
+        let (name, device) = &mut devices[i];
+        let state = match image.iter().find(|state| state.name == *name) {
+            Some(state) => state,
+            None => continue,
+        };
+
+        if let Err(e) = device.load_state(&state.data, version) {
+            let e = std::format!("{}: {}", name, e);
+            for (j, data) in backup.iter().enumerate().take(i) {
+                devices[j].1.load_state(data, STATE_VERSION)?;
+            }
+            return Err(e);
+        }
+    }
+
+    Ok(())
+}
+
+impl Snapshot for [u8; 64 * 1024] {
+    fn save_state(&self) -> Vec<u8> {
+        self.to_vec()
+    }
+
+    fn load_state(&mut self, data: &[u8], _version: u32) -> Result<(), String> {
+        if data.len() != self.len() {
+            return Err("ram image has the wrong size".to_string());
+        }
+
+        self.copy_from_slice(data);
+        Ok(())
+    }
+}
+
+// Whether WAI, STP or a JAM left the CPU halted
+impl Snapshot for Halt {
+    fn save_state(&self) -> Vec<u8> {
+        vec![match self {
+            Halt::Running => 0,
+            Halt::Waiting => 1,
+            Halt::Stopped => 2,
+            Halt::Jammed => 3,
+        }]
+    }
+
+    fn load_state(&mut self, data: &[u8], _version: u32) -> Result<(), String> {
+        *self = match data {
+            [0] => Halt::Running,
+            [1] => Halt::Waiting,
+            [2] => Halt::Stopped,
+            [3] => Halt::Jammed,
+            _ => return Err("bad halt state".to_string()),
+        };
+        Ok(())
+    }
+}
+
+// The level an input line was last left at, for the ones that act on an
+// edge like SO
+impl Snapshot for bool {
+    fn save_state(&self) -> Vec<u8> {
+        vec![*self as u8]
+    }
+
+    fn load_state(&mut self, data: &[u8], _version: u32) -> Result<(), String> {
+        *self = match data {
+            [0] => false,
+            [1] => true,
+            _ => return Err("bad line level".to_string()),
+        };
+        Ok(())
+    }
+}
+
+impl Snapshot for InterruptPoll {
+    fn save_state(&self) -> Vec<u8> {
+        let mut w = StateWriter::new();
+
+        w.bool(self.nmi_edge);
+        w.bool(self.nmi_pending);
+        w.bool(self.irq_pending);
+        w.bool(self.i_seen.is_some());
+        w.bool(self.i_seen.unwrap_or(false));
+        w.bool(self.servicing);
+
+        w.finish()
+    }
+
+    fn load_state(&mut self, data: &[u8], _version: u32) -> Result<(), String> {
+        let mut r = StateReader::new(data);
+
+        let (nmi_edge, nmi_pending, irq_pending) = (r.bool()?, r.bool()?, r.bool()?);
+        let i_seen = (r.bool()?, r.bool()?);
+        let servicing = r.bool()?;
+
+        *self = InterruptPoll {
+            nmi_edge,
+            nmi_pending,
+            irq_pending,
+            i_seen: if i_seen.0 { Some(i_seen.1) } else { None },
+            servicing,
+        };
+        Ok(())
+    }
+}
+
+impl Bus {
+    // Everything on the bus with state, by the name it is saved under
+    pub fn devices(&self) -> DeviceList<'_> {
+        let mut devices: DeviceList = vec![("ram", &self.ram)];
+
+        if let Some(cart) = &self.cart {
+            devices.push(("cartridge", cart));
+        }
+
+        devices
+    }
+
+    pub fn devices_mut(&mut self) -> DeviceListMut<'_> {
+        let mut devices: DeviceListMut = vec![("ram", &mut self.ram)];
+
+        if let Some(cart) = &mut self.cart {
+            devices.push(("cartridge", cart));
+        }
+
+        devices
+    }
+}
+
+impl cpu6502 {
+    // State the CPU keeps beside its registers
+    fn devices(&self) -> DeviceList<'_> {
+        let mut devices: DeviceList = vec![("irq", &self.interrupts), ("alarms", &self.scheduler), ("halt", &self.halt), ("poll", &self.poll), ("so", &self.so_low), ("rdy", &self.rdy), ("dma", &self.dma)];
+
+        if let Some(shadow_stack) = &self.shadow_stack {
+            devices.push(("shadow-stack", shadow_stack));
+        }
+
+        devices
+    }
+
+    fn devices_mut(&mut self) -> DeviceListMut<'_> {
+        let mut devices: DeviceListMut = vec![("irq", &mut self.interrupts), ("alarms", &mut self.scheduler), ("halt", &mut self.halt), ("poll", &mut self.poll), ("so", &mut self.so_low), ("rdy", &mut self.rdy), ("dma", &mut self.dma)];
+
+        if let Some(shadow_stack) = &mut self.shadow_stack {
+            devices.push(("shadow-stack", shadow_stack));
+        }
+
+        devices
+    }
+}
+
+// Little endian fields, byte strings carry a 32-bit length
+pub struct StateWriter {
+    data: Vec<u8>,
+}
+
+impl StateWriter {
+    pub fn new() -> Self {
+        StateWriter { data: Vec::new() }
+    }
+
+    pub fn u8(&mut self, value: u8) {
+        self.data.push(value);
+    }
+
+    pub fn u16(&mut self, value: u16) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn u32(&mut self, value: u32) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn u64(&mut self, value: u64) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn bool(&mut self, value: bool) {
+        self.data.push(value as u8);
+    }
+
+    pub fn bytes(&mut self, bytes: &[u8]) {
+        self.u32(bytes.len() as u32);
+        self.data.extend_from_slice(bytes);
+    }
+
+    pub fn finish(self) -> Vec<u8> {
+        self.data
+    }
+}
+
+pub struct StateReader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> StateReader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        StateReader { data }
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], String> {
+        if self.data.len() < len {
+            return Err("state is truncated".to_string());
+        }
+
+        let (head, tail) = self.data.split_at(len);
+        self.data = tail;
+        Ok(head)
+    }
+
+    pub fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub fn u16(&mut self) -> Result<u16, String> {
+        let b = self.take(2)?;
+        Ok(u16::from_le_bytes([b[0], b[1]]))
+    }
+
+    pub fn u32(&mut self) -> Result<u32, String> {
+        let b = self.take(4)?;
+        Ok(u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    pub fn u64(&mut self) -> Result<u64, String> {
+        let mut bytes = [0u8; 8];
+        bytes.copy_from_slice(self.take(8)?);
+        Ok(u64::from_le_bytes(bytes))
+    }
+
+    pub fn bool(&mut self) -> Result<bool, String> {
+        Ok(self.u8()? != 0)
+    }
+
+    pub fn bytes(&mut self) -> Result<&'a [u8], String> {
+        let len = self.u32()? as usize;
+        self.take(len)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::FLAGS6502;
+
+    // $8000 JSR $8010 / JMP $8000, $8010 INX / JMP $8010 (never returns)
+    fn busy_cpu() -> cpu6502 {
+        let mut cpu = cpu6502::new();
+        cpu.load_program(&[0x20, 0x10, 0x80, 0x4C, 0x00, 0x80], 0x8000);
+        cpu.load_program(&[0xE8, 0x4C, 0x10, 0x80], 0x8010);
+        cpu.set_reset_vector(0x8000);
+        cpu.enable_shadow_stack();
+        cpu.reset();
+        cpu
+    }
+
+    fn run(cpu: &mut cpu6502, cycles: u64) {
+        for _ in 0..cycles {
+            cpu.clock();
+        }
+    }
+
+    #[test]
+    fn text_round_trip() {
+        let mut cpu = busy_cpu();
+        cpu.interrupts.register_source("timer");
+        run(&mut cpu, 50);
+
+        let state = MachineState::capture(&cpu);
+        assert!(["ram", "irq", "alarms", "shadow-stack"].iter().all(|name| state.device(name).is_some()));
+
+        let parsed = MachineState::parse(&state.to_text()).unwrap();
+        assert_eq!(parsed, state);
+    }
+
+    #[test]
+    fn restore_brings_everything_back() {
+        let mut cpu = busy_cpu();
+        let timer = cpu.interrupts.register_source("timer");
+        let fired = Arc::new(AtomicU32::new(0));
+        let counter = fired.clone();
+        cpu.add_alarm_every(100, move |_| {
+            counter.fetch_add(1, Ordering::Relaxed);
+        });
+
+        run(&mut cpu, 60);
+        cpu.set_irq(timer, true);
+        cpu.bus.borrow_mut().write(0x0200, 0x42);
+
+        let state = MachineState::capture(&cpu);
+        let (x, pc, clock) = (cpu.x, cpu.pc, cpu.clock_count);
+
+        cpu.set_irq(timer, false);
+        cpu.bus.borrow_mut().write(0x0200, 0x00);
+        run(&mut cpu, 500);
+        cpu.shadow_stack.as_mut().unwrap().clear();
+        let fired_before = fired.load(Ordering::Relaxed);
+
+        state.restore(&mut cpu).unwrap();
+
+        assert_eq!((cpu.x, cpu.pc, cpu.clock_count), (x, pc, clock));
+        assert_eq!(cpu.bus.borrow().read(0x0200, true), 0x42);
+        assert!(cpu.interrupts.irq_line());
+        assert_eq!(cpu.shadow_stack.as_ref().unwrap().depth(), 1);
+        assert_eq!(cpu.scheduler.next_deadline(), Some(100));
+        assert_eq!(MachineState::capture(&cpu), state);
+
+        // The alarm fires on its original schedule again
+        cpu.set_irq(timer, false);
+        run(&mut cpu, 40);
+        assert_eq!(fired.load(Ordering::Relaxed), fired_before + 1);
+    }
+
+    #[test]
+    fn failed_restore_changes_nothing() {
+        let mut cpu = busy_cpu();
+        cpu.interrupts.register_source("timer");
+        run(&mut cpu, 50);
+
+        let mut state = MachineState::capture(&cpu);
+        for device in &mut state.devices {
+            match device.name.as_str() {
+                "ram" => device.data = vec![0x55; 64 * 1024],
+                // Taken on a machine with a different set of IRQ sources
+                "irq" => device.data[0] = 7,
+                _ => {}
+            }
+        }
+        state.cpu.a = 0x99;
+
+        let before = MachineState::capture(&cpu);
+        assert!(state.restore(&mut cpu).is_err());
+        assert_eq!(MachineState::capture(&cpu), before);
+        assert_ne!(cpu.a, 0x99);
+    }
+
+    #[test]
+    fn unknown_and_missing_devices() {
+        let mut cpu = busy_cpu();
+        run(&mut cpu, 50);
+
+        let mut state = MachineState::capture(&cpu);
+        state.devices.retain(|device| device.name == "ram");
+        state.devices.push(DeviceState { name: "vic".to_string(), data: vec![1, 2, 3] });
+
+        let frames = cpu.shadow_stack.as_ref().unwrap().depth();
+        assert!(state.restore(&mut cpu).is_ok());
+        assert_eq!(cpu.shadow_stack.as_ref().unwrap().depth(), frames);
+    }
+
+    #[test]
+    fn so_stays_low_across_a_restore() {
+        let mut cpu = busy_cpu();
+        cpu.set_so(true);
+        let state = MachineState::capture(&cpu);
+
+        cpu.set_so(false);
+        state.restore(&mut cpu).unwrap();
+        assert!(cpu.so_low());
+
+        // Still held, so no new edge to set V
+        cpu.set_flag(FLAGS6502::V, false);
+        cpu.set_so(true);
+        assert_eq!(cpu.get_flag(FLAGS6502::V), 0);
+    }
+
+    #[test]
+    fn describe_diff_lists_what_changed() {
+        let mut cpu = busy_cpu();
+        run(&mut cpu, 50);
+        let before = MachineState::capture(&cpu);
+        assert!(before.describe_diff(&before).is_empty());
+
+        let mut after = before.clone();
+        after.cpu.x = before.cpu.x.wrapping_add(1);
+        after.cpu.pc = 0x1234;
+        after.devices[0].data[0x0010] ^= 0xFF;
+        after.devices.retain(|device| device.name != "shadow-stack");
+
+        assert_eq!(before.describe_diff(&after), vec![
+            std::format!("x: {:02x} -> {:02x}", before.cpu.x, after.cpu.x),
+            std::format!("pc: {:04x} -> 1234", before.cpu.pc),
+            "ram $0010: 00 -> ff".to_string(),
+            "shadow-stack: removed".to_string(),
+        ]);
+        assert_eq!(before.hash(), before.clone().hash());
+        assert_ne!(before.hash(), after.hash());
+    }
+
+    #[test]
+    fn diff_groups_neighbouring_bytes() {
+        let mut cpu = busy_cpu();
+        run(&mut cpu, 50);
+        let before = MachineState::capture(&cpu);
+        assert!(before.diff(&before).is_empty());
+
+        let mut after = before.clone();
+        after.cpu.a ^= 0xFF;
+        let ram = &mut after.devices[0].data;
+        ram[0x0300] ^= 0x01;
+        ram[0x0301] ^= 0x02;
+        ram[0x0303] ^= 0x03;
+        ram[0x1000..0x100A].fill(0xEE);
+
+        let ranges = before.diff(&after);
+        let described: Vec<String> = ranges.iter().map(ChangedRange::describe).collect();
+        assert_eq!(described, [
+            "ram $0300-$0301: 00 00 -> 01 02",
+            "ram $0303: 00 -> 03",
+            "ram $1000-$1009: 00 00 00 00 00 00 00 00 ... -> ee ee ee ee ee ee ee ee ...",
+        ]);
+        assert_eq!((ranges[2].start, ranges[2].end()), (0x1000, 0x100A));
+    }
+
+    #[test]
+    fn newer_versions_are_refused() {
+        let mut cpu = busy_cpu();
+        let mut state = MachineState::capture(&cpu);
+        state.version = STATE_VERSION + 1;
+        assert!(state.restore(&mut cpu).is_err());
+        assert!(MachineState::parse("crust-state 1\ncpu 00\n").is_err());
+    }
+
+    #[test]
+    fn reader_spots_truncation() {
+        let mut w = StateWriter::new();
+        w.u16(0x1234);
+        w.bytes(&[1, 2, 3]);
+        w.u64(u64::MAX);
+        let data = w.finish();
+
+        let mut r = StateReader::new(&data);
+        assert_eq!(r.u16(), Ok(0x1234));
+        assert_eq!(r.bytes(), Ok(&[1u8, 2, 3][..]));
+        assert_eq!(r.u64(), Ok(u64::MAX));
+        assert!(r.is_empty());
+        assert!(r.u8().is_err());
+
+        let mut r = StateReader::new(&data[..4]);
+        r.u16().unwrap();
+        assert!(r.bytes().is_err());
+    }
+}
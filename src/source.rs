@@ -0,0 +1,317 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::{self, Write};
+
+use crate::symbols::SymbolTable;
+use crate::cpu6502;
+
+// Writes a range of memory out as assembler source that builds back into
+// the same bytes. Unlike the display disassembly, operands are written in
+// the assembler's own syntax, branch and jump targets get labels and
+// anything that isn't an instruction becomes .byte.
+
+// Data bytes per .byte line
+const BYTES_PER_LINE: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Syntax {
+    Ca65,
+    // vasm's 6502 module with the oldstyle syntax
+    Vasm,
+}
+
+impl Syntax {
+    fn org(&self) -> &'static str {
+        match self {
+            Syntax::Ca65 => ".org",
+            Syntax::Vasm => "org",
+        }
+    }
+
+    fn byte(&self) -> &'static str {
+        match self {
+            Syntax::Ca65 => ".byte",
+            Syntax::Vasm => "byte",
+        }
+    }
+}
+
+// One line of output, an instruction or a run of data bytes
+enum Item {
+    Instruction { opcode: u8, operand: u16, len: u16 },
+    Data(Vec<u8>),
+}
+
+impl cpu6502 {
+    // $start-$end as source for `syntax`. With `code` (from
+    // CallGraph::code, say) only the addresses in it are decoded and
+    // everything else is data, without it every byte that decodes to a
+    // documented instruction is taken as one.
+    pub fn disassemble_to_writer<W: Write>(&self, out: &mut W, start: u16, end: u16, syntax: Syntax, symbols: &SymbolTable, code: Option<&BTreeSet<u16>>) -> io::Result<()> {
+        let items = self.source_items(start, end, code);
+
+        // Targets become labels where a line (code or data) starts there,
+        // otherwise they are written as numbers or as equates for symbols
+        let mut targets: BTreeSet<u16> = BTreeSet::new();
+        for item in items.values() {
+            if let Item::Instruction { opcode, operand, len } = item {
+                if *len > 1 && !self.is_immediate(*opcode) && !forced_absolute(self, *opcode, *operand) {
+                    targets.insert(*operand);
+                }
+            }
+        }
+
+        let mut labels: BTreeMap<u16, String> = BTreeMap::new();
+        for &addr in items.keys() {
+            match symbols.name_of(addr) {
+                Some(name) => {
+                    labels.insert(addr, name.to_string());
+                }
+                None if targets.contains(&addr) => {
+                    labels.insert(addr, std::format!("L{:04X}", addr));
+                }
+                None => {}
+            }
+        }
+
+        let mut equates: BTreeMap<u16, &str> = BTreeMap::new();
+        for target in &targets {
+            if let (Some(name), false) = (symbols.name_of(*target), labels.contains_key(target)) {
+                equates.insert(*target, name);
+            }
+        }
+
+        writeln!(out, "; ${:04x}-${:04x}", start, end)?;
+        for (addr, name) in &equates {
+            let digits = if *addr < 0x100 { 2 } else { 4 };
+            writeln!(out, "{} = ${:0width$x}", name, addr, width = digits)?;
+        }
+        writeln!(out)?;
+        writeln!(out, "        {} ${:04x}", syntax.org(), start)?;
+
+        let name = |target: u16, digits: usize| match labels.get(&target).map(|s| s.as_str()).or(equates.get(&target).copied()) {
+            Some(name) => name.to_string(),
+            None => std::format!("${:0width$x}", target, width = digits),
+        };
+
+        for (addr, item) in &items {
+            if let Some(label) = labels.get(addr) {
+                writeln!(out, "{}:", label)?;
+            }
+
+            match item {
+                Item::Data(bytes) => writeln!(out, "        {} {}", syntax.byte(), byte_list(bytes))?,
+                Item::Instruction { opcode, operand, len } => {
+                    let bytes: Vec<u8> = (0..*len).map(|i| self.bus.borrow().read(addr.wrapping_add(i), true)).collect();
+                    let text = self.source_line(*opcode, *operand, &name);
+
+                    match text {
+                        Some(text) => writeln!(out, "        {}", text)?,
+                        // No way to say this so every assembler keeps the
+                        // encoding, so spell out the bytes
+                        None => writeln!(out, "        {} {}  ; {}", syntax.byte(), byte_list(&bytes), self.lookup[*opcode as usize].name.to_ascii_lowercase())?,
+                    }
+
+                    // BRK skips the byte after it, the assembler only emits the opcode
+                    if *opcode == 0x00 {
+                        writeln!(out, "        {} ${:02x}", syntax.byte(), bytes[1])?;
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    // Splits the range into instructions and data, keyed by address
+    fn source_items(&self, start: u16, end: u16, code: Option<&BTreeSet<u16>>) -> BTreeMap<u16, Item> {
+        let bus = self.bus.borrow();
+        let mut items = BTreeMap::new();
+        let mut data: Option<(u16, Vec<u8>)> = None;
+        let mut addr = start as u32;
+
+        while addr <= end as u32 {
+            let here = addr as u16;
+            let opcode = bus.read(here, true);
+            let len = self.instruction_len(opcode);
+
+            let decodes = code.map_or(true, |code| code.contains(&here))
+                && self.is_instruction(opcode)
+                && addr + len as u32 - 1 <= end as u32
+                // Operands a code map says are instructions of their own
+                && (1..len).all(|i| code.map_or(true, |code| !code.contains(&here.wrapping_add(i))));
+
+            if decodes {
+                if let Some((at, bytes)) = data.take() {
+                    items.insert(at, Item::Data(bytes));
+                }
+
+                let operand = match len {
+                    1 => 0,
+                    2 => bus.read(here.wrapping_add(1), true) as u16,
+                    _ => bus.read(here.wrapping_add(1), true) as u16 | (bus.read(here.wrapping_add(2), true) as u16) << 8,
+                };
+                let operand = if self.lookup[opcode as usize].addr_mode == cpu6502::REL {
+                    here.wrapping_add(2).wrapping_add(operand as u8 as i8 as u16)
+                } else {
+                    operand
+                };
+
+                items.insert(here, Item::Instruction { opcode, operand, len });
+                addr += len as u32;
+            } else {
+                let (_, bytes) = data.get_or_insert_with(|| (here, Vec::new()));
+                bytes.push(opcode);
+                if bytes.len() == BYTES_PER_LINE {
+                    let (at, bytes) = data.take().unwrap();
+                    items.insert(at, Item::Data(bytes));
+                }
+                addr += 1;
+            }
+        }
+
+        if let Some((at, bytes)) = data {
+            items.insert(at, Item::Data(bytes));
+        }
+
+        items
+    }
+
+    fn is_immediate(&self, opcode: u8) -> bool {
+        self.lookup[opcode as usize].addr_mode == cpu6502::IMM
+    }
+
+    // The instruction in the assembler's syntax, or None when it can't be
+    // written so that it assembles back to the same bytes
+    fn source_line(&self, opcode: u8, operand: u16, name: &dyn Fn(u16, usize) -> String) -> Option<String> {
+        let instruction = &self.lookup[opcode as usize];
+        let mnemonic = instruction.name.to_ascii_lowercase();
+        let mode = instruction.addr_mode;
+
+        // 65C02 only, not every assembler knows them
+        if matches!(mnemonic.as_str(), "wai" | "stp") || forced_absolute(self, opcode, operand) {
+            return None;
+        }
+
+        let operand = if opcode == 0x00 {
+            String::new()
+        } else if mode == cpu6502::IMP {
+            match mnemonic.as_str() {
+                "asl" | "lsr" | "rol" | "ror" => " a".to_string(),
+                _ => String::new(),
+            }
+        } else if mode == cpu6502::IMM {
+            std::format!(" #${:02x}", operand)
+        } else if mode == cpu6502::ZP0 {
+            std::format!(" {}", name(operand, 2))
+        } else if mode == cpu6502::ZPX {
+            std::format!(" {},x", name(operand, 2))
+        } else if mode == cpu6502::ZPY {
+            std::format!(" {},y", name(operand, 2))
+        } else if mode == cpu6502::IZX {
+            std::format!(" ({},x)", name(operand, 2))
+        } else if mode == cpu6502::IZY {
+            std::format!(" ({}),y", name(operand, 2))
+        } else if mode == cpu6502::ABS || mode == cpu6502::REL {
+            std::format!(" {}", name(operand, 4))
+        } else if mode == cpu6502::ABX {
+            std::format!(" {},x", name(operand, 4))
+        } else if mode == cpu6502::ABY {
+            std::format!(" {},y", name(operand, 4))
+        } else {
+            std::format!(" ({})", name(operand, 4))
+        };
+
+        Some(std::format!("{}{}", mnemonic, operand))
+    }
+}
+
+// An absolute mode instruction addressing zero page, which an assembler
+// would shrink to the zero page form
+fn forced_absolute(cpu: &cpu6502, opcode: u8, operand: u16) -> bool {
+    let mode = cpu.lookup[opcode as usize].addr_mode;
+    operand < 0x100 && (mode == cpu6502::ABS || mode == cpu6502::ABX || mode == cpu6502::ABY)
+        // JMP and JSR have no zero page form
+        && opcode != 0x4C && opcode != 0x20
+}
+
+fn byte_list(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| std::format!("${:02x}", b)).collect::<Vec<String>>().join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn source(cpu: &cpu6502, start: u16, end: u16, syntax: Syntax, symbols: &SymbolTable, code: Option<&BTreeSet<u16>>) -> String {
+        let mut out = Vec::new();
+        cpu.disassemble_to_writer(&mut out, start, end, syntax, symbols, code).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn instructions_labels_and_equates() {
+        let mut cpu = cpu6502::new();
+        // LDX #$05 / loop: STA $0200,X / DEX / BNE loop / LDA (ptr),Y / ASL A / JMP done / done: RTS
+        cpu.load_program(&[0xA2, 0x05, 0x9D, 0x00, 0x02, 0xCA, 0xD0, 0xFA, 0xB1, 0x10, 0x0A, 0x4C, 0x0E, 0x80, 0x60], 0x8000);
+        let mut symbols = SymbolTable::new();
+        symbols.insert("ptr", 0x0010);
+        symbols.insert("start", 0x8000);
+
+        assert_eq!(source(&cpu, 0x8000, 0x800E, Syntax::Ca65, &symbols, None), "\
+; $8000-$800e
+ptr = $10
+
+        .org $8000
+start:
+        ldx #$05
+L8002:
+        sta $0200,x
+        dex
+        bne L8002
+        lda (ptr),y
+        asl a
+        jmp L800E
+L800E:
+        rts
+");
+    }
+
+    #[test]
+    fn data_and_encodings_assemblers_would_change() {
+        let mut cpu = cpu6502::new();
+        // BRK $42 / LDA $0010 (absolute) / two undocumented opcodes / a
+        // JSR cut off by the end of the range
+        cpu.load_program(&[0x00, 0x42, 0xAD, 0x10, 0x00, 0x02, 0x03, 0x20, 0x00], 0x9000);
+
+        assert_eq!(source(&cpu, 0x9000, 0x9008, Syntax::Vasm, &SymbolTable::new(), None), "\
+; $9000-$9008
+
+        org $9000
+        brk
+        byte $42
+        byte $ad, $10, $00  ; lda
+        byte $02, $03, $20, $00
+");
+    }
+
+    #[test]
+    fn a_code_map_marks_the_rest_as_data() {
+        let mut cpu = cpu6502::new();
+        // JMP $8005 over a two byte table, then RTS
+        cpu.load_program(&[0x4C, 0x05, 0x80, 0xA9, 0x01, 0x60], 0x8000);
+        cpu.set_reset_vector(0x8000);
+
+        let code = crate::analysis::analyze(&cpu, &[]).code();
+        assert_eq!(code, [0x8000, 0x8005].into_iter().collect());
+
+        assert_eq!(source(&cpu, 0x8000, 0x8005, Syntax::Ca65, &SymbolTable::new(), Some(&code)), "\
+; $8000-$8005
+
+        .org $8000
+        jmp L8005
+        .byte $a9, $01
+L8005:
+        rts
+");
+    }
+}
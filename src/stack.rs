@@ -0,0 +1,189 @@
+use std::collections::HashMap;
+
+use crate::cpu6502;
+use crate::shadow_stack::FrameKind;
+use crate::symbols::SymbolTable;
+
+const JSR: u8 = 0x20;
+
+// A group of bytes between the stack pointer and $01FF and what pushed them
+#[derive(Debug, Clone, PartialEq)]
+pub enum StackEntry {
+    // Return address pushed by the JSR at `call_site`, `addr` is where its
+    // low byte sits and `return_to` where the RTS will land
+    Return { addr: u16, call_site: u16, target: u16, return_to: u16 },
+    // Status and return address pushed by an interrupt or BRK
+    Interrupt { addr: u16, status: u8, return_to: u16 },
+    // Anything else, usually data from PHA/PHP
+    Byte { addr: u16, value: u8 },
+}
+
+impl StackEntry {
+    pub fn addr(&self) -> u16 {
+        match self {
+            StackEntry::Return { addr, .. } | StackEntry::Interrupt { addr, .. } | StackEntry::Byte { addr, .. } => *addr,
+        }
+    }
+
+    // Bytes it takes up on the stack
+    pub fn size(&self) -> u16 {
+        match self {
+            StackEntry::Return { .. } => 2,
+            StackEntry::Interrupt { .. } => 3,
+            StackEntry::Byte { .. } => 1,
+        }
+    }
+
+    // One line for the stack pane, "$01fe: 02 80  ret $8003  JSR sub_8010 at $8000"
+    pub fn describe(&self, symbols: &SymbolTable) -> String {
+        let label = |addr: u16| match symbols.name_of(addr) {
+            Some(name) => name.to_string(),
+            None => std::format!("${:04x}", addr),
+        };
+
+        match self {
+            StackEntry::Return { addr, call_site, target, return_to } => {
+                let pushed = return_to.wrapping_sub(1);
+                std::format!("${:04x}: {:02x} {:02x}     ret ${:04x}  JSR {} at {}", addr, pushed as u8, pushed >> 8, return_to, label(*target), label(*call_site))
+            }
+            StackEntry::Interrupt { addr, status, return_to } => {
+                let source = if status & 0x10 != 0 { "BRK" } else { "IRQ/NMI" };
+                std::format!("${:04x}: {:02x} {:02x} {:02x}  P=${:02x} ret {}  {}", addr, status, *return_to as u8, return_to >> 8, status, label(*return_to), source)
+            }
+            StackEntry::Byte { addr, value } => std::format!("${:04x}: {:02x}", addr, value),
+        }
+    }
+}
+
+// Decodes the live part of the stack, top first. With the shadow stack
+// enabled its frames say exactly where each return address is, otherwise a
+// pair of bytes counts as a return address when it points just past a JSR.
+pub fn decode(cpu: &cpu6502) -> Vec<StackEntry> {
+    let bus = cpu.bus.borrow();
+    let read = |offset: u16| bus.read(0x0100 + offset, true);
+
+    let frames: Option<HashMap<u16, FrameKind>> = cpu.shadow_stack.as_ref().map(|shadow| {
+        shadow.frames().iter().map(|frame| (frame.stkp as u16 + 1, frame.kind)).collect()
+    });
+
+    let mut entries = Vec::new();
+    let mut offset = cpu.stkp as u16 + 1;
+
+    while offset <= 0xFF {
+        let addr = 0x0100 + offset;
+        let word = |at: u16| read(at) as u16 | (read(at + 1) as u16) << 8;
+
+        let kind = match &frames {
+            Some(frames) => frames.get(&offset).copied(),
+            None if offset < 0xFF && bus.read(word(offset).wrapping_sub(2), true) == JSR => Some(FrameKind::Subroutine),
+            None => None,
+        };
+
+        let entry = match kind {
+            Some(FrameKind::Subroutine) if offset < 0xFF => {
+                let return_to = word(offset).wrapping_add(1);
+                let call_site = return_to.wrapping_sub(3);
+                let target = bus.read(call_site.wrapping_add(1), true) as u16 | (bus.read(call_site.wrapping_add(2), true) as u16) << 8;
+                StackEntry::Return { addr, call_site, target, return_to }
+            }
+            Some(FrameKind::Interrupt) if offset < 0xFE => StackEntry::Interrupt {
+                addr,
+                status: read(offset),
+                return_to: word(offset + 1),
+            },
+            _ => StackEntry::Byte { addr, value: read(offset) },
+        };
+
+        offset += entry.size();
+        entries.push(entry);
+    }
+
+    entries
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn load(cpu: &cpu6502, addr: u16, bytes: &[u8]) {
+        for (i, byte) in bytes.iter().enumerate() {
+            cpu.bus.borrow_mut().write(addr + i as u16, *byte);
+        }
+    }
+
+    #[test]
+    fn empty_stack() {
+        let mut cpu = cpu6502::new();
+        cpu.stkp = 0xFF;
+        assert!(decode(&cpu).is_empty());
+    }
+
+    #[test]
+    fn return_addresses_are_found_by_their_jsr() {
+        let mut cpu = cpu6502::new();
+        // $8000 JSR $8010, which pushed $8002, then PHA of $42
+        load(&cpu, 0x8000, &[JSR, 0x10, 0x80]);
+        load(&cpu, 0x01FD, &[0x42, 0x02, 0x80]);
+        cpu.stkp = 0xFC;
+
+        let entries = decode(&cpu);
+        assert_eq!(entries, vec![
+            StackEntry::Byte { addr: 0x01FD, value: 0x42 },
+            StackEntry::Return { addr: 0x01FE, call_site: 0x8000, target: 0x8010, return_to: 0x8003 },
+        ]);
+
+        let mut symbols = SymbolTable::new();
+        symbols.insert("sub_8010", 0x8010);
+        assert_eq!(entries[1].describe(&symbols), "$01fe: 02 80     ret $8003  JSR sub_8010 at $8000");
+        assert_eq!(entries[0].describe(&symbols), "$01fd: 42");
+    }
+
+    #[test]
+    fn data_that_does_not_follow_a_jsr_stays_bytes() {
+        let mut cpu = cpu6502::new();
+        load(&cpu, 0x01FE, &[0x02, 0x80]);
+        cpu.stkp = 0xFD;
+
+        assert_eq!(decode(&cpu), vec![
+            StackEntry::Byte { addr: 0x01FE, value: 0x02 },
+            StackEntry::Byte { addr: 0x01FF, value: 0x80 },
+        ]);
+    }
+
+    #[test]
+    fn shadow_stack_frames_include_interrupts() {
+        let mut cpu = cpu6502::new();
+        cpu.enable_shadow_stack();
+
+        // JSR at $8000 from SP $FF, then an IRQ at $9005 inside the routine
+        load(&cpu, 0x8000, &[JSR, 0x00, 0x90]);
+        load(&cpu, 0x01FB, &[0x20, 0x05, 0x90, 0x02, 0x80]);
+        let shadow = cpu.shadow_stack.as_mut().unwrap();
+        shadow.on_instruction(JSR, 0x8000, 0xFF, 0x9000, 0);
+        shadow.push_interrupt(0x9005, 0xFA);
+        cpu.stkp = 0xFA;
+
+        let entries = decode(&cpu);
+        assert_eq!(entries, vec![
+            StackEntry::Interrupt { addr: 0x01FB, status: 0x20, return_to: 0x9005 },
+            StackEntry::Return { addr: 0x01FE, call_site: 0x8000, target: 0x9000, return_to: 0x8003 },
+        ]);
+        assert_eq!(entries[0].describe(&SymbolTable::new()), "$01fb: 20 05 90  P=$20 ret $9005  IRQ/NMI");
+    }
+
+    #[test]
+    fn shadow_stack_beats_the_guess() {
+        let mut cpu = cpu6502::new();
+        cpu.enable_shadow_stack();
+
+        // Looks like a return address, but no JSR ever pushed it
+        load(&cpu, 0x8000, &[JSR, 0x10, 0x80]);
+        load(&cpu, 0x01FE, &[0x02, 0x80]);
+        cpu.stkp = 0xFD;
+
+        assert_eq!(decode(&cpu), vec![
+            StackEntry::Byte { addr: 0x01FE, value: 0x02 },
+            StackEntry::Byte { addr: 0x01FF, value: 0x80 },
+        ]);
+    }
+}
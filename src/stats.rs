@@ -0,0 +1,172 @@
+use std::collections::BTreeMap;
+
+use crate::cpu6502;
+
+// How often each opcode ran and the cycles it took, counting extra cycles
+// for page crossings and taken branches. Cheap enough to keep on all the
+// time, the CPU bumps two counters per instruction.
+
+#[derive(Clone)]
+pub struct OpcodeCounts {
+    counts: [u64; 256],
+    cycles: [u64; 256],
+}
+
+impl OpcodeCounts {
+    pub fn new() -> Self {
+        OpcodeCounts { counts: [0; 256], cycles: [0; 256] }
+    }
+
+    pub fn record(&mut self, opcode: u8, cycles: u8) {
+        self.counts[opcode as usize] += 1;
+        self.cycles[opcode as usize] += cycles as u64;
+    }
+
+    pub fn clear(&mut self) {
+        *self = OpcodeCounts::new();
+    }
+
+    // Instructions run since the last clear
+    pub fn total(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+}
+
+// One opcode, or everything sharing a mnemonic or addressing mode
+#[derive(Debug, Clone, PartialEq)]
+pub struct StatEntry {
+    pub name: String,
+    pub count: u64,
+    pub cycles: u64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct OpcodeStats {
+    // Opcodes that ran at least once, most executed first. Names are the
+    // mnemonic and addressing mode, "LDA IMM".
+    pub opcodes: Vec<StatEntry>,
+    pub mnemonics: Vec<StatEntry>,
+    pub modes: Vec<StatEntry>,
+    pub instructions: u64,
+    pub cycles: u64,
+}
+
+impl OpcodeStats {
+    // The summary printed at exit and by the console, the top `limit`
+    // mnemonics and all the addressing modes
+    pub fn describe(&self, limit: usize) -> Vec<String> {
+        let mut lines = vec![std::format!("{} instructions, {} cycles", self.instructions, self.cycles)];
+        if self.instructions == 0 {
+            return lines;
+        }
+
+        lines.push("MNEMONIC      COUNT      %      CYCLES".to_string());
+        lines.extend(self.mnemonics.iter().take(limit).map(|entry| self.line(entry)));
+        lines.push("MODE          COUNT      %      CYCLES".to_string());
+        lines.extend(self.modes.iter().map(|entry| self.line(entry)));
+        lines
+    }
+
+    fn line(&self, entry: &StatEntry) -> String {
+        let share = entry.count as f64 * 100.0 / self.instructions as f64;
+        std::format!("{:<8} {:>10} {:>6.2} {:>11}", entry.name, entry.count, share, entry.cycles)
+    }
+}
+
+impl cpu6502 {
+    pub fn stats(&self) -> OpcodeStats {
+        let mut opcodes = Vec::new();
+        let mut mnemonics: BTreeMap<&str, StatEntry> = BTreeMap::new();
+        let mut modes: BTreeMap<&str, StatEntry> = BTreeMap::new();
+
+        for opcode in 0..256 {
+            let (count, cycles) = (self.opcode_counts.counts[opcode], self.opcode_counts.cycles[opcode]);
+            if count == 0 {
+                continue;
+            }
+
+            let mnemonic = self.lookup[opcode].name.as_str();
+            let mode = self.addr_mode_name(opcode as u8);
+            opcodes.push(StatEntry { name: std::format!("{} {}", mnemonic, mode), count, cycles });
+
+            for (key, totals) in [(mnemonic, &mut mnemonics), (mode, &mut modes)] {
+                let entry = totals.entry(key).or_insert(StatEntry { name: key.to_string(), count: 0, cycles: 0 });
+                entry.count += count;
+                entry.cycles += cycles;
+            }
+        }
+
+        let sorted = |mut entries: Vec<StatEntry>| {
+            entries.sort_by(|a, b| b.count.cmp(&a.count).then(a.name.cmp(&b.name)));
+            entries
+        };
+
+        OpcodeStats {
+            instructions: opcodes.iter().map(|entry| entry.count).sum(),
+            cycles: opcodes.iter().map(|entry| entry.cycles).sum(),
+            opcodes: sorted(opcodes),
+            mnemonics: sorted(mnemonics.into_values().collect()),
+            modes: sorted(modes.into_values().collect()),
+        }
+    }
+
+    pub fn reset_stats(&mut self) {
+        self.opcode_counts.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run_until_pc(cpu: &mut cpu6502, pc: u16) {
+        for _ in 0..10_000 {
+            cpu.clock();
+            if cpu.complete() && cpu.pc == pc {
+                return;
+            }
+        }
+        panic!("never reached ${:04x}", pc);
+    }
+
+    #[test]
+    fn counts_and_cycles_by_opcode_mnemonic_and_mode() {
+        let mut cpu = cpu6502::new();
+        // LDX #$03 / loop: LDA $0200,X / DEX / BNE loop / LDA #$00
+        cpu.load_program(&[0xA2, 0x03, 0xBD, 0x00, 0x02, 0xCA, 0xD0, 0xFA, 0xA9, 0x00], 0x8000);
+        cpu.set_reset_vector(0x8000);
+        cpu.reset();
+        run_until_pc(&mut cpu, 0x800A);
+
+        let stats = cpu.stats();
+        assert_eq!(stats.instructions, 1 + 3 * 3 + 1);
+
+        let lda = stats.mnemonics.iter().find(|entry| entry.name == "LDA").unwrap();
+        assert_eq!((lda.count, lda.cycles), (4, 3 * 4 + 2));
+
+        // Taken branches cost a cycle more, the last one falls through
+        let bne = stats.opcodes.iter().find(|entry| entry.name == "BNE REL").unwrap();
+        assert_eq!((bne.count, bne.cycles), (3, 3 + 3 + 2));
+
+        assert_eq!(stats.modes.iter().find(|entry| entry.name == "IMM").unwrap().count, 2);
+        assert_eq!(stats.cycles, stats.opcodes.iter().map(|entry| entry.cycles).sum::<u64>());
+    }
+
+    #[test]
+    fn most_executed_first_and_reset() {
+        let mut cpu = cpu6502::new();
+        // NOP NOP NOP INX
+        cpu.load_program(&[0xEA, 0xEA, 0xEA, 0xE8], 0x8000);
+        cpu.set_reset_vector(0x8000);
+        cpu.reset();
+        run_until_pc(&mut cpu, 0x8004);
+
+        let stats = cpu.stats();
+        assert_eq!(stats.mnemonics[0], StatEntry { name: "NOP".to_string(), count: 3, cycles: 6 });
+        assert_eq!(stats.describe(1).len(), 1 + 2 + 2);
+        assert!(stats.describe(1)[2].starts_with("NOP               3  75.00"));
+
+        cpu.reset_stats();
+        assert_eq!(cpu.stats().describe(10), vec!["0 instructions, 0 cycles"]);
+    }
+}
@@ -0,0 +1,147 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io;
+use std::path::Path;
+
+// Labels for addresses, loaded from the symbol files assemblers emit
+pub struct SymbolTable {
+    by_name: HashMap<String, u16>,
+    by_addr: BTreeMap<u16, String>,
+}
+
+impl SymbolTable {
+    pub fn new() -> Self {
+        SymbolTable {
+            by_name: HashMap::new(),
+            by_addr: BTreeMap::new(),
+        }
+    }
+
+    pub fn insert(&mut self, name: &str, addr: u16) {
+        self.by_name.insert(name.to_string(), addr);
+
+        // Keep the first label we saw for an address as its display name
+        self.by_addr.entry(addr).or_insert_with(|| name.to_string());
+    }
+
+    pub fn lookup(&self, name: &str) -> Option<u16> {
+        self.by_name.get(name).copied()
+    }
+
+    pub fn name_of(&self, addr: u16) -> Option<&str> {
+        self.by_addr.get(&addr).map(|name| name.as_str())
+    }
+
+    pub fn len(&self) -> usize {
+        self.by_name.len()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (u16, &str)> {
+        self.by_addr.iter().map(|(addr, name)| (*addr, name.as_str()))
+    }
+
+    pub fn load_file<P: AsRef<Path>>(&mut self, path: P) -> io::Result<usize> {
+        let text = fs::read_to_string(path)?;
+        Ok(self.load_str(&text))
+    }
+
+    // Understands the common one-symbol-per-line formats:
+    //
+    //   label = $1234        (ca65 / vasm style)
+    //   al C:1234 .label     (VICE label file)
+    //   $1234 label          (address first)
+    //
+    // Returns how many symbols were added, lines that don't parse are skipped.
+    pub fn load_str(&mut self, text: &str) -> usize {
+        let mut count = 0;
+
+        for line in text.lines() {
+            let line = match line.find(';') {
+                Some(comment) => &line[..comment],
+                None => line,
+            };
+            let line = line.trim();
+
+            if line.is_empty() {
+                continue;
+            }
+
+            if let Some((name, addr)) = parse_line(line) {
+                self.insert(name, addr);
+                count += 1;
+            }
+        }
+
+        count
+    }
+}
+
+fn parse_addr(s: &str) -> Option<u16> {
+    let s = s.trim();
+    let s = s.strip_prefix("C:").unwrap_or(s);
+    let s = s.strip_prefix('$').or_else(|| s.strip_prefix("0x")).unwrap_or(s);
+
+    u32::from_str_radix(s, 16).ok().map(|addr| (addr & 0xFFFF) as u16)
+}
+
+fn parse_line(line: &str) -> Option<(&str, u16)> {
+    if let Some((name, addr)) = line.split_once('=') {
+        return parse_addr(addr).map(|addr| (name.trim(), addr));
+    }
+
+    let fields: Vec<&str> = line.split_whitespace().collect();
+
+    match fields.as_slice() {
+        ["al", addr, name] => parse_addr(addr).map(|addr| (name.trim_start_matches('.'), addr)),
+        [addr, name] => parse_addr(addr).map(|addr| (*name, addr)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn assignment_format() {
+        let mut symbols = SymbolTable::new();
+        assert_eq!(symbols.load_str("reset = $8000\nnmi=0xfffa\nvec = 1234\n"), 3);
+        assert_eq!(symbols.lookup("reset"), Some(0x8000));
+        assert_eq!(symbols.lookup("nmi"), Some(0xFFFA));
+        assert_eq!(symbols.lookup("vec"), Some(0x1234));
+    }
+
+    #[test]
+    fn vice_format() {
+        let mut symbols = SymbolTable::new();
+        assert_eq!(symbols.load_str("al C:c000 .main\nal C:00fb .ptr\n"), 2);
+        assert_eq!(symbols.lookup("main"), Some(0xC000));
+        assert_eq!(symbols.name_of(0x00FB), Some("ptr"));
+    }
+
+    #[test]
+    fn address_first_format() {
+        let mut symbols = SymbolTable::new();
+        assert_eq!(symbols.load_str("$0200 buffer\n8000 start\n"), 2);
+        assert_eq!(symbols.lookup("buffer"), Some(0x0200));
+        assert_eq!(symbols.name_of(0x8000), Some("start"));
+    }
+
+    #[test]
+    fn comments_and_junk_are_skipped() {
+        let mut symbols = SymbolTable::new();
+        let text = "; header\n\nloop = $8010 ; inner loop\nnot a symbol line\n$zz bad\n";
+        assert_eq!(symbols.load_str(text), 1);
+        assert_eq!(symbols.lookup("loop"), Some(0x8010));
+        assert_eq!(symbols.len(), 1);
+    }
+
+    #[test]
+    fn first_label_names_the_address() {
+        let mut symbols = SymbolTable::new();
+        symbols.insert("reset", 0x8000);
+        symbols.insert("start", 0x8000);
+        assert_eq!(symbols.name_of(0x8000), Some("reset"));
+        assert_eq!(symbols.lookup("start"), Some(0x8000));
+    }
+}
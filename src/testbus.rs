@@ -0,0 +1,246 @@
+use std::collections::VecDeque;
+use std::ops::RangeInclusive;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+use crate::accesslog::AccessKind;
+use crate::cpu6502;
+use crate::device::BusDevice;
+
+// Memory for instruction tests that keeps every read and write in the order
+// the CPU made them, so a test can check the exact bus traffic and not just
+// where the registers ended up. It can be told what to expect: the accesses
+// are then checked one by one as they happen and expected reads give back
+// the value listed, whatever is in memory. Ranges can be set as the only
+// places the code may touch, anything outside is a failure.
+//
+// Debugger peeks aren't recorded, so looking at memory doesn't disturb a
+// test. Loading a program through the bus is, clear the log afterwards.
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BusAccess {
+    pub addr: u16,
+    pub value: u8,
+    pub kind: AccessKind,
+}
+
+impl BusAccess {
+    pub fn read(addr: u16, value: u8) -> BusAccess {
+        BusAccess { addr, value, kind: AccessKind::Read }
+    }
+
+    pub fn write(addr: u16, value: u8) -> BusAccess {
+        BusAccess { addr, value, kind: AccessKind::Write }
+    }
+
+    // "read $8000 = a9"
+    pub fn describe(&self) -> String {
+        let kind = match self.kind {
+            AccessKind::Read => "read",
+            AccessKind::Write => "write",
+        };
+        std::format!("{} ${:04x} = {:02x}", kind, self.addr, self.value)
+    }
+}
+
+struct State {
+    memory: Vec<u8>,
+    accesses: Vec<BusAccess>,
+    expected: VecDeque<BusAccess>,
+    // Where code may go, anywhere while empty
+    allowed: Vec<RangeInclusive<u16>>,
+    failures: Vec<String>,
+}
+
+impl State {
+    fn access(&mut self, kind: AccessKind, addr: u16, value: u8) -> u8 {
+        let number = self.accesses.len();
+        if !self.allowed.is_empty() && !self.allowed.iter().any(|range| range.contains(&addr)) {
+            self.failures.push(std::format!("access {}: ${:04x} is out of bounds", number, addr));
+        }
+
+        let mut access = BusAccess { addr, value, kind };
+        if let Some(expected) = self.expected.pop_front() {
+            // The expected read supplies the value, so only where matters
+            if kind == AccessKind::Read && expected.kind == kind && expected.addr == addr {
+                access.value = expected.value;
+            } else if expected != access {
+                self.failures.push(std::format!("access {}: expected {}, got {}", number, expected.describe(), access.describe()));
+            }
+        }
+
+        self.accesses.push(access);
+        access.value
+    }
+}
+
+// The test's end of the bus, which can be kept once the TestBus is mapped
+#[derive(Clone)]
+pub struct TestBusLog(Arc<Mutex<State>>);
+
+impl TestBusLog {
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.0.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    // Puts bytes in memory without them showing up as accesses
+    pub fn load(&self, addr: u16, bytes: &[u8]) {
+        let mut state = self.state();
+        for (i, byte) in bytes.iter().enumerate() {
+            state.memory[addr.wrapping_add(i as u16) as usize] = *byte;
+        }
+    }
+
+    pub fn memory(&self, addr: u16) -> u8 {
+        self.state().memory[addr as usize]
+    }
+
+    // Oldest first
+    pub fn accesses(&self) -> Vec<BusAccess> {
+        self.state().accesses.clone()
+    }
+
+    // Forgets the accesses and failures so far, and any expectations left
+    pub fn clear(&self) {
+        let mut state = self.state();
+        state.accesses.clear();
+        state.expected.clear();
+        state.failures.clear();
+    }
+
+    // The accesses the next ones have to be, in order
+    pub fn expect(&self, accesses: &[BusAccess]) {
+        self.state().expected = accesses.iter().copied().collect();
+    }
+
+    // Adds a range code may touch, once there's one nothing outside is
+    pub fn allow(&self, start: u16, end: u16) {
+        self.state().allowed.push(start..=end);
+    }
+
+    // Everything that went against the expectations or the bounds, and any
+    // expected accesses that never came
+    pub fn verify(&self) -> Result<(), Vec<String>> {
+        let state = self.state();
+        let mut failures = state.failures.clone();
+        if let Some(next) = state.expected.front() {
+            failures.push(std::format!("{} expected accesses didn't happen, the first {}", state.expected.len(), next.describe()));
+        }
+
+        if failures.is_empty() {
+            Ok(())
+        } else {
+            Err(failures)
+        }
+    }
+}
+
+pub struct TestBus {
+    log: TestBusLog,
+}
+
+impl TestBus {
+    pub fn new() -> Self {
+        let state = State { memory: vec![0; 0x10000], accesses: Vec::new(), expected: VecDeque::new(), allowed: Vec::new(), failures: Vec::new() };
+        TestBus { log: TestBusLog(Arc::new(Mutex::new(state))) }
+    }
+
+    pub fn log(&self) -> TestBusLog {
+        self.log.clone()
+    }
+}
+
+// Mapped over the whole address space, so offsets are addresses
+impl BusDevice for TestBus {
+    fn read(&mut self, offset: u16) -> u8 {
+        let mut state = self.log.state();
+        let value = state.memory[offset as usize];
+        state.access(AccessKind::Read, offset, value)
+    }
+
+    fn peek(&self, offset: u16) -> u8 {
+        self.log.state().memory[offset as usize]
+    }
+
+    fn write(&mut self, offset: u16, data: u8) {
+        let mut state = self.log.state();
+        state.memory[offset as usize] = data;
+        state.access(AccessKind::Write, offset, data);
+    }
+}
+
+impl cpu6502 {
+    // Puts a TestBus over all of memory and returns the test's end of it
+    pub fn attach_test_bus(&mut self) -> TestBusLog {
+        let bus = TestBus::new();
+        let log = bus.log();
+        self.bus.borrow_mut().map("test", 0x0000, 0xFFFF, Box::new(bus));
+        log
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn step(cpu: &mut cpu6502) {
+        cpu.clock();
+        while !cpu.complete() {
+            cpu.clock();
+        }
+    }
+
+    fn cpu_at(program: &[u8]) -> (cpu6502, TestBusLog) {
+        let mut cpu = cpu6502::new();
+        let log = cpu.attach_test_bus();
+        log.load(0x8000, program);
+        log.load(0xFFFC, &[0x00, 0x80]);
+        cpu.reset();
+        // The reset's own cycles
+        step(&mut cpu);
+        log.clear();
+        (cpu, log)
+    }
+
+    #[test]
+    fn records_traffic_in_order() {
+        // LDA $0200 / STA $0300
+        let (mut cpu, log) = cpu_at(&[0xAD, 0x00, 0x02, 0x8D, 0x00, 0x03]);
+        log.load(0x0200, &[0x42]);
+        step(&mut cpu);
+        step(&mut cpu);
+
+        let traffic: Vec<String> = log.accesses().iter().map(BusAccess::describe).collect();
+        assert_eq!(traffic, [
+            "read $8000 = ad", "read $8001 = 00", "read $8002 = 02", "read $0200 = 42",
+            "read $8003 = 8d", "read $8004 = 00", "read $8005 = 03", "write $0300 = 42",
+        ]);
+        assert!(log.verify().is_ok());
+        // Looking doesn't count
+        assert_eq!(cpu.bus.borrow().read(0x0300, true), 0x42);
+        assert_eq!(log.accesses().len(), 8);
+    }
+
+    #[test]
+    fn expected_reads_supply_values_and_mismatches_fail() {
+        // LDA $0200 / STA $0300
+        let (mut cpu, log) = cpu_at(&[0xAD, 0x00, 0x02, 0x8D, 0x00, 0x03]);
+        log.expect(&[
+            BusAccess::read(0x8000, 0xAD),
+            BusAccess::read(0x8001, 0x00),
+            BusAccess::read(0x8002, 0x02),
+            BusAccess::read(0x0200, 0x99),
+        ]);
+        step(&mut cpu);
+        assert_eq!(cpu.a, 0x99);
+        assert!(log.verify().is_ok());
+
+        log.expect(&[BusAccess::read(0x8003, 0x8D), BusAccess::read(0x8004, 0x00), BusAccess::read(0x8005, 0x03), BusAccess::write(0x0300, 0x98), BusAccess::read(0x8006, 0xEA)]);
+        log.allow(0x8000, 0x80FF);
+        step(&mut cpu);
+        assert_eq!(log.verify(), Err(vec![
+            "access 7: $0300 is out of bounds".to_string(),
+            "access 7: expected write $0300 = 98, got write $0300 = 99".to_string(),
+            "1 expected accesses didn't happen, the first read $8006 = ea".to_string(),
+        ]));
+    }
+}
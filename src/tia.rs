@@ -0,0 +1,530 @@
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+use crate::device::BusDevice;
+use crate::scheduler::EventQueue;
+
+// The 2600's TIA, the video side of it: playfield, two players, two
+// missiles and a ball drawn a colour clock at a time as the beam crosses
+// the screen. Offsets are the chip's own six address lines for writes and
+// the low four for reads.
+//
+// Nothing is clocked. The chip works out where the beam is from the CPU's
+// cycle count, three colour clocks to a cycle, and draws up to there
+// whenever a register is about to change, so what's on each line is what
+// the registers held as the beam went past. Writes are taken to land on
+// the third cycle of the instruction, which is where STA zp puts them,
+// and kernels nearly always use that.
+//
+// Not here: sound, the paddle inputs, the input latch and the finer
+// points of HMOVE timing. Moving objects in the middle of a line takes
+// effect straight away.
+
+pub const FRAME_WIDTH: usize = 160;
+// NTSC, VSYNC and VBLANK included
+pub const FRAME_LINES: usize = 262;
+
+const CLOCKS_PER_LINE: u64 = 228;
+const HBLANK: u64 = 68;
+const CLOCKS_PER_CYCLE: u64 = 3;
+const WRITE_DELAY: u64 = 2;
+
+const VSYNC: usize = 0x00;
+const VBLANK: usize = 0x01;
+const WSYNC: usize = 0x02;
+const NUSIZ0: usize = 0x04;
+const COLUP0: usize = 0x06;
+const COLUPF: usize = 0x08;
+const COLUBK: usize = 0x09;
+const CTRLPF: usize = 0x0A;
+const REFP0: usize = 0x0B;
+const PF0: usize = 0x0D;
+const PF1: usize = 0x0E;
+const PF2: usize = 0x0F;
+const RESP0: usize = 0x10;
+const RESBL: usize = 0x14;
+const GRP0: usize = 0x1B;
+const GRP1: usize = 0x1C;
+const ENAM0: usize = 0x1D;
+const ENABL: usize = 0x1F;
+const HMP0: usize = 0x20;
+const HMBL: usize = 0x24;
+const VDELP0: usize = 0x25;
+const VDELBL: usize = 0x27;
+const RESMP0: usize = 0x28;
+const RESMP1: usize = 0x29;
+const HMOVE: usize = 0x2A;
+const HMCLR: usize = 0x2B;
+const CXCLR: usize = 0x2C;
+
+// The moveable objects, in the order their RESxx and HMxx registers come
+const P0: usize = 0;
+const P1: usize = 1;
+const M0: usize = 2;
+const M1: usize = 3;
+const BL: usize = 4;
+const PF: usize = 5;
+
+// Which pair of objects overlapping sets which bit of which collision
+// register, as (register, bit, object, object)
+const COLLISIONS: [(usize, u8, usize, usize); 15] = [
+    (0, 0x80, M0, P1), (0, 0x40, M0, P0),
+    (1, 0x80, M1, P0), (1, 0x40, M1, P1),
+    (2, 0x80, P0, PF), (2, 0x40, P0, BL),
+    (3, 0x80, P1, PF), (3, 0x40, P1, BL),
+    (4, 0x80, M0, PF), (4, 0x40, M0, BL),
+    (5, 0x80, M1, PF), (5, 0x40, M1, BL),
+    (6, 0x80, BL, PF),
+    (7, 0x80, P0, P1), (7, 0x40, M0, M1),
+];
+
+// Where the copies of a player or missile start, relative to the first,
+// and how many pixels wide a player's pixel is, for each NUSIZ setting
+const COPIES: [&[usize]; 8] = [&[0], &[0, 16], &[0, 32], &[0, 16, 32], &[0, 64], &[0], &[0, 32, 64], &[0]];
+const STRETCH: [usize; 8] = [1, 1, 1, 1, 1, 2, 1, 4];
+
+// An NTSC palette, worked out from each colour's hue and luminance rather
+// than measured off a real set. Hue 0 is grey, the other fifteen go round
+// the colour wheel from gold. 0x00RRGGBB, as minifb wants.
+fn ntsc_palette() -> Vec<u32> {
+    (0..128u32).map(|colour| {
+        let hue = colour >> 3;
+        let y = (colour & 7) as f32 / 7.0 * 0.92;
+        let (i, q) = if hue == 0 {
+            (0.0, 0.0)
+        } else {
+            let angle = ((hue - 1) as f32 * 24.0 - 25.0).to_radians();
+            (0.22 * angle.cos(), 0.22 * angle.sin())
+        };
+
+        let channel = |value: f32| (value.clamp(0.0, 1.0) * 255.0).round() as u32;
+        let r = channel(y + 0.956 * i + 0.621 * q);
+        let g = channel(y - 0.272 * i - 0.647 * q);
+        let b = channel(y - 1.106 * i + 1.703 * q);
+        r << 16 | g << 8 | b
+    }).collect()
+}
+
+struct Chip {
+    // What was last written to each register
+    regs: [u8; 0x40],
+    // Horizontal positions of P0, P1, M0, M1 and the ball, 0-159
+    positions: [usize; 5],
+    // The values the vertical delays show: GRP0 and GRP1 as they were
+    // before the other one was last written, ENABL before GRP1 was
+    old_grp: [u8; 2],
+    old_enabl: u8,
+    collisions: [u8; 8],
+    // The fire buttons, pressed or not
+    fire: [bool; 2],
+    // Colour clock the picture has been drawn up to, and the one the
+    // frame being drawn started on
+    drawn_to: u64,
+    frame_start: u64,
+    // The line HMOVE was last strobed on, which starts with 8 pixels of
+    // black
+    hmove_line: Option<u64>,
+    frame: Vec<u32>,
+    finished: Vec<u32>,
+    frames: u64,
+    palette: Vec<u32>,
+    events: EventQueue,
+}
+
+impl Chip {
+    fn clock(&self) -> u64 {
+        (self.events.now() + WRITE_DELAY) * CLOCKS_PER_CYCLE
+    }
+
+    fn playfield(&self, x: usize) -> bool {
+        let mut bit = x / 4;
+        if bit >= 20 {
+            bit -= 20;
+            if self.regs[CTRLPF] & 0x01 != 0 {
+                bit = 19 - bit;
+            }
+        }
+
+        match bit {
+            0..=3 => self.regs[PF0] >> (4 + bit) & 1 != 0,
+            4..=11 => self.regs[PF1] >> (11 - bit) & 1 != 0,
+            _ => self.regs[PF2] >> (bit - 12) & 1 != 0,
+        }
+    }
+
+    // How far `x` is into the object starting at `position`
+    fn distance(x: usize, position: usize) -> usize {
+        (x + FRAME_WIDTH - position) % FRAME_WIDTH
+    }
+
+    fn player(&self, n: usize, x: usize) -> bool {
+        let graphics = if self.regs[VDELP0 + n] & 0x01 != 0 { self.old_grp[n] } else { self.regs[GRP0 + n] };
+        let size = (self.regs[NUSIZ0 + n] & 0x07) as usize;
+        let stretch = STRETCH[size];
+        let reflected = self.regs[REFP0 + n] & 0x08 != 0;
+
+        COPIES[size].iter().any(|copy| {
+            let d = Chip::distance(x, (self.positions[n] + copy) % FRAME_WIDTH);
+            if d >= 8 * stretch {
+                return false;
+            }
+            let bit = d / stretch;
+            let shift = if reflected { bit } else { 7 - bit };
+            graphics >> shift & 1 != 0
+        })
+    }
+
+    fn missile(&self, n: usize, x: usize) -> bool {
+        if self.regs[ENAM0 + n] & 0x02 == 0 || self.regs[RESMP0 + n] & 0x02 != 0 {
+            return false;
+        }
+        let nusiz = self.regs[NUSIZ0 + n];
+        let width = 1 << (nusiz >> 4 & 0x03);
+        COPIES[(nusiz & 0x07) as usize].iter()
+            .any(|copy| Chip::distance(x, (self.positions[M0 + n] + copy) % FRAME_WIDTH) < width)
+    }
+
+    fn ball(&self, x: usize) -> bool {
+        let enabled = if self.regs[VDELBL] & 0x01 != 0 { self.old_enabl } else { self.regs[ENABL] };
+        let width = 1 << (self.regs[CTRLPF] >> 4 & 0x03);
+        enabled & 0x02 != 0 && Chip::distance(x, self.positions[BL]) < width
+    }
+
+    // The colour at `x` on a line that isn't blanked, noting collisions
+    // on the way
+    fn pixel(&mut self, x: usize) -> u8 {
+        let on = [
+            self.player(0, x),
+            self.player(1, x),
+            self.missile(0, x),
+            self.missile(1, x),
+            self.ball(x),
+            self.playfield(x),
+        ];
+
+        for (register, bit, a, b) in COLLISIONS {
+            if on[a] && on[b] {
+                self.collisions[register] |= bit;
+            }
+        }
+
+        // Score mode colours each half of the playfield like the player
+        // on that side
+        let ctrlpf = self.regs[CTRLPF];
+        let playfield = if ctrlpf & 0x02 != 0 { self.regs[COLUP0 + x / 80] } else { self.regs[COLUPF] };
+        let players = [(on[P0] || on[M0], self.regs[COLUP0]), (on[P1] || on[M1], self.regs[COLUP0 + 1])];
+        let field = [(on[PF], playfield), (on[BL], self.regs[COLUPF])];
+
+        let layers = if ctrlpf & 0x04 != 0 {
+            [field[0], field[1], players[0], players[1]]
+        } else {
+            [players[0], players[1], field[0], field[1]]
+        };
+        layers.iter().find(|(on, _)| *on).map_or(self.regs[COLUBK], |(_, colour)| *colour)
+    }
+
+    // Draws everything the beam has passed before colour clock `to`
+    fn draw_to(&mut self, to: u64) {
+        let blanked = (self.regs[VSYNC] | self.regs[VBLANK]) & 0x02 != 0;
+
+        while self.drawn_to < to {
+            let clock = self.drawn_to;
+            let x = clock % CLOCKS_PER_LINE;
+            if x < HBLANK {
+                self.drawn_to = (clock - x + HBLANK).min(to);
+                continue;
+            }
+            self.drawn_to += 1;
+
+            let line = clock.saturating_sub(self.frame_start) / CLOCKS_PER_LINE;
+            if line >= FRAME_LINES as u64 {
+                continue;
+            }
+
+            let x = (x - HBLANK) as usize;
+            let hmove_bar = x < 8 && self.hmove_line == Some(clock / CLOCKS_PER_LINE);
+            let colour = if blanked || hmove_bar { None } else { Some(self.pixel(x)) };
+            self.frame[line as usize * FRAME_WIDTH + x] = colour.map_or(0, |colour| self.palette[(colour >> 1) as usize]);
+        }
+    }
+
+    // Where an object reset at colour clock `clock` starts. Players show
+    // up five pixels after the strobe, the others four, and any strobed
+    // during horizontal blank start at the left edge, give or take.
+    fn reset_position(clock: u64, object: usize) -> usize {
+        let x = clock % CLOCKS_PER_LINE;
+        let delay = if object < M0 { 5 } else { 4 };
+        if x < HBLANK {
+            delay - 2
+        } else {
+            ((x - HBLANK) as usize + delay) % FRAME_WIDTH
+        }
+    }
+
+    fn write(&mut self, register: usize, data: u8) {
+        let clock = self.clock();
+        self.draw_to(clock);
+
+        match register {
+            // VSYNC going on finishes the frame, and the next starts with
+            // the line it went on in
+            VSYNC => {
+                if data & 0x02 != 0 && self.regs[VSYNC] & 0x02 == 0 {
+                    std::mem::swap(&mut self.frame, &mut self.finished);
+                    self.frame.iter_mut().for_each(|pixel| *pixel = 0);
+                    self.frames += 1;
+                    self.frame_start = clock - clock % CLOCKS_PER_LINE;
+                }
+            }
+            // Holds the CPU until the start of the next line
+            WSYNC => {
+                let until = (clock / CLOCKS_PER_LINE + 1) * CLOCKS_PER_LINE / CLOCKS_PER_CYCLE;
+                self.events.schedule_in(0, move |cpu| cpu.hold_until(until));
+            }
+            RESP0..=RESBL => {
+                let object = register - RESP0;
+                self.positions[object] = Chip::reset_position(clock, object);
+            }
+            GRP0 => self.old_grp[1] = self.regs[GRP1],
+            GRP1 => {
+                self.old_grp[0] = self.regs[GRP0];
+                self.old_enabl = self.regs[ENABL];
+            }
+            // A missile let go of its player starts from the middle of it
+            RESMP0 | RESMP1 => {
+                let n = register - RESMP0;
+                if data & 0x02 != 0 {
+                    let stretch = STRETCH[(self.regs[NUSIZ0 + n] & 0x07) as usize];
+                    self.positions[M0 + n] = (self.positions[n] + 4 * stretch - 1) % FRAME_WIDTH;
+                }
+            }
+            // Each object moves left by its HMxx's top nibble, signed
+            HMOVE => {
+                for object in P0..=BL {
+                    let motion = (self.regs[HMP0 + object] as i8 >> 4) as isize;
+                    let position = self.positions[object] as isize - motion;
+                    self.positions[object] = position.rem_euclid(FRAME_WIDTH as isize) as usize;
+                }
+                self.hmove_line = Some(clock / CLOCKS_PER_LINE);
+            }
+            HMCLR => self.regs[HMP0..=HMBL].iter_mut().for_each(|hm| *hm = 0),
+            CXCLR => self.collisions = [0; 8],
+            _ => {}
+        }
+
+        self.regs[register] = data;
+    }
+
+    fn read(&self, register: usize) -> u8 {
+        match register {
+            0x00..=0x07 => self.collisions[register],
+            // INPT4 and INPT5, bit 7 low while the button's held
+            0x0C | 0x0D => if self.fire[register - 0x0C] { 0x00 } else { 0x80 },
+            _ => 0x00,
+        }
+    }
+}
+
+// The host's side: the picture and the fire buttons
+#[derive(Clone)]
+pub struct TiaPort(Arc<Mutex<Chip>>);
+
+impl TiaPort {
+    fn chip(&self) -> MutexGuard<'_, Chip> {
+        self.0.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    // The last whole frame, FRAME_WIDTH by FRAME_LINES
+    pub fn frame(&self) -> Vec<u32> {
+        self.chip().finished.clone()
+    }
+
+    // How many frames have been finished, to tell when there's a new one
+    pub fn frames(&self) -> u64 {
+        self.chip().frames
+    }
+
+    pub fn set_fire(&self, player: usize, pressed: bool) {
+        self.chip().fire[player] = pressed;
+    }
+}
+
+pub struct Tia {
+    port: TiaPort,
+}
+
+impl Tia {
+    pub fn new(events: EventQueue) -> Self {
+        let chip = Chip {
+            regs: [0; 0x40],
+            positions: [0; 5],
+            old_grp: [0; 2],
+            old_enabl: 0,
+            collisions: [0; 8],
+            fire: [false; 2],
+            drawn_to: events.now() * CLOCKS_PER_CYCLE,
+            frame_start: 0,
+            hmove_line: None,
+            frame: vec![0; FRAME_WIDTH * FRAME_LINES],
+            finished: vec![0; FRAME_WIDTH * FRAME_LINES],
+            frames: 0,
+            palette: ntsc_palette(),
+            events,
+        };
+        Tia { port: TiaPort(Arc::new(Mutex::new(chip))) }
+    }
+
+    pub fn port(&self) -> TiaPort {
+        self.port.clone()
+    }
+}
+
+impl BusDevice for Tia {
+    // Collisions so far have to be drawn before they can be read
+    fn read(&mut self, offset: u16) -> u8 {
+        let mut chip = self.port.chip();
+        let clock = chip.clock();
+        chip.draw_to(clock);
+        chip.read((offset & 0x0F) as usize)
+    }
+
+    fn peek(&self, offset: u16) -> u8 {
+        self.port.chip().read((offset & 0x0F) as usize)
+    }
+
+    fn write(&mut self, offset: u16, data: u8) {
+        self.port.chip().write((offset & 0x3F) as usize, data);
+    }
+
+    fn reset(&mut self) {
+        let mut chip = self.port.chip();
+        chip.regs = [0; 0x40];
+        chip.collisions = [0; 8];
+        chip.hmove_line = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu6502;
+
+    fn run(cpu: &mut cpu6502, cycles: u64) {
+        for _ in 0..cycles {
+            cpu.clock();
+        }
+    }
+
+    fn write(tia: &mut Tia, register: usize, data: u8) {
+        tia.write(register as u16, data);
+    }
+
+    #[test]
+    fn playfield_halves() {
+        let tia = Tia::new(EventQueue::default());
+        let mut chip = tia.port.chip();
+        chip.regs[PF0] = 0x10;
+        chip.regs[PF2] = 0x80;
+
+        // PF0 bit 4 is the leftmost 4 pixels, PF2 bit 7 the 4 before the
+        // middle, then the right half repeats the left
+        let on: Vec<bool> = [0, 3, 4, 76, 79, 80, 84, 156].iter().map(|&x| chip.playfield(x)).collect();
+        assert_eq!(on, vec![true, true, false, true, true, true, false, true]);
+
+        // Or mirrors it
+        chip.regs[CTRLPF] = 0x01;
+        let on: Vec<bool> = [80, 83, 84, 152, 156].iter().map(|&x| chip.playfield(x)).collect();
+        assert_eq!(on, vec![true, true, false, false, true]);
+    }
+
+    #[test]
+    fn players_copies_and_reflection() {
+        let tia = Tia::new(EventQueue::default());
+        let mut chip = tia.port.chip();
+        chip.positions[P0] = 10;
+        chip.regs[GRP0] = 0x80;
+
+        assert!(chip.player(0, 10) && !chip.player(0, 11) && !chip.player(0, 26));
+        chip.regs[REFP0] = 0x08;
+        assert!(!chip.player(0, 10) && chip.player(0, 17));
+
+        // Two copies 16 apart, then one at double size
+        chip.regs[REFP0] = 0;
+        chip.regs[NUSIZ0] = 0x01;
+        assert!(chip.player(0, 10) && chip.player(0, 26));
+        chip.regs[NUSIZ0] = 0x05;
+        assert!(chip.player(0, 11) && !chip.player(0, 12) && !chip.player(0, 26));
+    }
+
+    #[test]
+    fn vertical_delay() {
+        let mut tia = Tia::new(EventQueue::default());
+        write(&mut tia, VDELP0, 0x01);
+
+        // GRP0 shows as it was before GRP1 was last written
+        write(&mut tia, GRP0, 0xFF);
+        assert!(!tia.port.chip().player(0, 0));
+        write(&mut tia, GRP1, 0x00);
+        assert!(tia.port.chip().player(0, 0));
+    }
+
+    #[test]
+    fn lines_are_drawn_as_the_beam_passes() {
+        let mut cpu = cpu6502::new();
+        let mut tia = Tia::new(cpu.events());
+        let port = tia.port();
+
+        // Three lines of VSYNC, one of white, then the next frame
+        write(&mut tia, VSYNC, 0x02);
+        run(&mut cpu, 76 * 3);
+        write(&mut tia, VSYNC, 0x00);
+        write(&mut tia, COLUBK, 0x0E);
+        run(&mut cpu, 76);
+        write(&mut tia, VSYNC, 0x02);
+
+        assert_eq!(port.frames(), 2);
+        let frame = port.frame();
+        let white = ntsc_palette()[0x07];
+        let at = |line: usize, x: usize| frame[line * FRAME_WIDTH + x];
+        assert_eq!((at(0, 10), at(2, 159)), (0, 0));
+        assert_eq!((at(3, 0), at(3, 159)), (white, white));
+        assert_eq!(at(4, 10), 0);
+    }
+
+    #[test]
+    fn collisions_and_fire_buttons() {
+        let mut cpu = cpu6502::new();
+        let mut tia = Tia::new(cpu.events());
+
+        write(&mut tia, GRP0, 0xFF);
+        write(&mut tia, PF0, 0xF0);
+        run(&mut cpu, 76);
+        assert_eq!(tia.read(0x02) & 0xC0, 0x80);
+        write(&mut tia, CXCLR, 0);
+        assert_eq!(tia.peek(0x02), 0);
+
+        assert_eq!(tia.read(0x0C), 0x80);
+        tia.port().set_fire(0, true);
+        assert_eq!(tia.read(0x0C), 0x00);
+    }
+
+    #[test]
+    fn wsync_holds_the_cpu_until_the_next_line() {
+        let mut cpu = cpu6502::new();
+        // STA WSYNC / INX / JMP $8000
+        cpu.load_program(&[0x85, 0x02, 0xE8, 0x4C, 0x00, 0x80], 0x8000);
+        cpu.bus.borrow_mut().map("tia", 0x0000, 0x003F, Box::new(Tia::new(cpu.events())));
+        cpu.set_reset_vector(0x8000);
+        cpu.reset();
+
+        // Every INX starts a line
+        let mut starts = Vec::new();
+        while starts.len() < 3 {
+            cpu.clock();
+            if cpu.complete() && cpu.pc == 0x8003 {
+                starts.push(cpu.clock_count() - 2);
+            }
+        }
+        assert_eq!(starts.iter().map(|start| start % 76).collect::<Vec<_>>(), vec![0, 0, 0]);
+        assert_eq!((starts[1] - starts[0], starts[2] - starts[1]), (76, 76));
+    }
+}
@@ -0,0 +1,211 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use crate::analysis::json_escape;
+use crate::cpu6502;
+use crate::symbols::SymbolTable;
+
+// Subroutine calls and interrupt handlers as spans of time, written out in
+// Chrome's trace event format for Perfetto or chrome://tracing to show as
+// a flame chart. Calls are paired up by stack pointer as the profiler does
+// it; a handler runs from its interrupt being taken to the RTI pulling
+// what it pushed. Timestamps are cycles, where the viewers expect
+// microseconds, so "1 us" on screen is a cycle.
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SpanKind {
+    Subroutine,
+    Irq,
+    Nmi,
+    Brk,
+}
+
+impl SpanKind {
+    fn category(&self) -> &'static str {
+        match self {
+            SpanKind::Subroutine => "subroutine",
+            _ => "interrupt",
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            SpanKind::Subroutine => "JSR",
+            SpanKind::Irq => "IRQ",
+            SpanKind::Nmi => "NMI",
+            SpanKind::Brk => "BRK",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Span {
+    pub kind: SpanKind,
+    // The subroutine or handler's first instruction
+    pub addr: u16,
+    pub start: u64,
+    pub end: u64,
+}
+
+struct Open {
+    kind: SpanKind,
+    addr: u16,
+    // Stack pointer after the return address (and status) went on, what
+    // the matching RTS or RTI runs with
+    stkp: u8,
+    start: u64,
+}
+
+pub struct Timeline {
+    open: Vec<Open>,
+    spans: Vec<Span>,
+}
+
+impl Timeline {
+    pub fn new() -> Self {
+        Timeline { open: Vec::new(), spans: Vec::new() }
+    }
+
+    pub fn spans(&self) -> &[Span] {
+        &self.spans
+    }
+
+    // Called once per instruction, like the profiler. `stkp` is the stack
+    // pointer before the instruction ran, `end` the cycle it completes on.
+    pub fn on_instruction(&mut self, opcode: u8, stkp: u8, new_pc: u16, end: u64) {
+        match opcode {
+            // JSR
+            0x20 => self.open.push(Open { kind: SpanKind::Subroutine, addr: new_pc, stkp: stkp.wrapping_sub(2), start: end }),
+            // RTS and RTI, closing anything abandoned below them too
+            0x60 | 0x40 => {
+                while self.open.last().is_some_and(|open| open.stkp < stkp) {
+                    self.close(end);
+                }
+                let returns = |open: &Open| (open.kind == SpanKind::Subroutine) == (opcode == 0x60);
+                if self.open.last().is_some_and(|open| open.stkp == stkp && returns(open)) {
+                    self.close(end);
+                }
+            }
+            _ => (),
+        }
+    }
+
+    // Called once an interrupt has pushed and loaded its vector
+    pub fn on_interrupt(&mut self, kind: SpanKind, handler: u16, stkp: u8, cycle: u64) {
+        self.open.push(Open { kind, addr: handler, stkp, start: cycle });
+    }
+
+    fn close(&mut self, end: u64) {
+        if let Some(open) = self.open.pop() {
+            self.spans.push(Span { kind: open.kind, addr: open.addr, start: open.start, end });
+        }
+    }
+
+    // Ends everything still running at `now`, e.g. before writing it out
+    pub fn close_all(&mut self, now: u64) {
+        while !self.open.is_empty() {
+            self.close(now);
+        }
+    }
+
+    // Complete ("X") events, one per span, named from the symbol table
+    // where it can be
+    pub fn write_json<W: Write>(&self, out: &mut W, symbols: &SymbolTable) -> io::Result<()> {
+        writeln!(out, "{{\"displayTimeUnit\": \"ns\", \"otherData\": {{\"timestamps\": \"cycles\"}}, \"traceEvents\": [")?;
+        for (i, span) in self.spans.iter().enumerate() {
+            let name = match (span.kind, symbols.name_of(span.addr)) {
+                (SpanKind::Subroutine, Some(name)) => name.to_string(),
+                (SpanKind::Subroutine, None) => std::format!("${:04x}", span.addr),
+                (kind, Some(name)) => std::format!("{} {}", kind.name(), name),
+                (kind, None) => std::format!("{} ${:04x}", kind.name(), span.addr),
+            };
+            let comma = if i + 1 < self.spans.len() { "," } else { "" };
+            writeln!(
+                out,
+                "  {{\"name\": \"{}\", \"cat\": \"{}\", \"ph\": \"X\", \"ts\": {}, \"dur\": {}, \"pid\": 1, \"tid\": 1, \"args\": {{\"addr\": \"${:04x}\"}}}}{}",
+                json_escape(&name),
+                span.kind.category(),
+                span.start,
+                span.end.saturating_sub(span.start),
+                span.addr,
+                comma
+            )?;
+        }
+        writeln!(out, "]}}")
+    }
+}
+
+impl cpu6502 {
+    // Starts recording spans afresh
+    pub fn enable_timeline(&mut self) {
+        self.timeline = Some(Timeline::new());
+    }
+
+    // Stops recording and hands back what was seen, with anything still
+    // running ended now
+    pub fn take_timeline(&mut self) -> Option<Timeline> {
+        let mut timeline = self.timeline.take()?;
+        timeline.close_all(self.clock_count);
+        Some(timeline)
+    }
+
+    // Called right after an interrupt (or BRK) has jumped to its handler
+    pub(crate) fn timeline_interrupt(&mut self, kind: SpanKind) {
+        if let Some(timeline) = &mut self.timeline {
+            timeline.on_interrupt(kind, self.pc, self.stkp, self.clock_count);
+        }
+    }
+}
+
+// Writes a timeline to `path` for Perfetto to open
+pub fn save<P: AsRef<Path>>(timeline: &Timeline, path: P, symbols: &SymbolTable) -> io::Result<()> {
+    let mut out = BufWriter::new(File::create(path)?);
+    timeline.write_json(&mut out, symbols)?;
+    out.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nested_calls_and_handlers() {
+        let mut timeline = Timeline::new();
+        // JSR $9000 at cycle 6, which calls $9100, which returns at 20
+        timeline.on_instruction(0x20, 0xFD, 0x9000, 6);
+        timeline.on_instruction(0x20, 0xFB, 0x9100, 12);
+        timeline.on_instruction(0x60, 0xF9, 0x9003, 20);
+        // An IRQ arrives, its RTI runs with the status pushed
+        timeline.on_interrupt(SpanKind::Irq, 0xA000, 0xF8, 25);
+        timeline.on_instruction(0x60, 0xF8, 0x0000, 30);
+        timeline.on_instruction(0x40, 0xF8, 0x9003, 40);
+        timeline.close_all(50);
+
+        let spans: Vec<(SpanKind, u16, u64, u64)> = timeline.spans().iter().map(|s| (s.kind, s.addr, s.start, s.end)).collect();
+        assert_eq!(spans, [(SpanKind::Subroutine, 0x9100, 12, 20), (SpanKind::Irq, 0xA000, 25, 40), (SpanKind::Subroutine, 0x9000, 6, 50)]);
+    }
+
+    #[test]
+    fn chrome_json_from_a_run() {
+        let mut cpu = cpu6502::new();
+        // JSR sub / loop: JMP loop / sub: NOP / RTS
+        cpu.load_program(&[0x20, 0x06, 0x80, 0x4C, 0x03, 0x80, 0xEA, 0x60], 0x8000);
+        cpu.set_reset_vector(0x8000);
+        cpu.reset();
+        cpu.enable_timeline();
+        for _ in 0..40 {
+            cpu.clock();
+        }
+
+        let mut symbols = SymbolTable::new();
+        symbols.insert("sub", 0x8006);
+        let mut json = Vec::new();
+        cpu.take_timeline().unwrap().write_json(&mut json, &symbols).unwrap();
+        let json = String::from_utf8(json).unwrap();
+
+        assert!(json.starts_with("{\"displayTimeUnit\""), "{}", json);
+        assert!(json.contains("{\"name\": \"sub\", \"cat\": \"subroutine\", \"ph\": \"X\", \"ts\": 14, \"dur\": 8, \"pid\": 1, \"tid\": 1, \"args\": {\"addr\": \"$8006\"}}\n"), "{}", json);
+        assert!(json.ends_with("]}\n"));
+    }
+}
@@ -0,0 +1,301 @@
+use crate::cpu6502;
+use crate::interrupts::HOST_IRQ;
+use crate::replay::{Event, Recording, Stimulus};
+use crate::snapshot::MachineState;
+
+// Finds code that only works when an interrupt lands at the right moment.
+// Every run starts from the same checkpoint, delivers one interrupt a
+// different number of cycles in and carries on until the program reaches
+// `until`. Runs that end with different memory or registers are grouped
+// into outcomes, so a handler that trashes a register or a main loop with
+// a non-atomic update shows up as more than one.
+
+// The devices compared between runs. Timers, alarms and the interrupt
+// bookkeeping are expected to differ with the timing, so they aren't.
+const COMPARED: &[&str] = &["ram", "cartridge"];
+
+// Differences listed per outcome in the report
+const MAX_DIFF_LINES: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Line {
+    Irq,
+    Nmi,
+}
+
+impl Line {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Line::Irq => "IRQ",
+            Line::Nmi => "NMI",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct TimingFuzz {
+    pub line: Line,
+    // Cycles after the checkpoint to deliver the interrupt at, inclusive
+    pub first: u64,
+    pub last: u64,
+    // How long the IRQ line is held low. Anything shorter than the longest
+    // instruction can be missed, which is worth knowing too.
+    pub hold: u64,
+    // Each run stops the first time an instruction finishes here once the
+    // interrupt has been delivered and taken (or missed)
+    pub until: u16,
+    // A run that hasn't reached `until` after this many cycles is given up on
+    pub max_cycles: u64,
+}
+
+// The runs that ended the same way
+#[derive(Debug, Clone)]
+pub struct Outcome {
+    pub offsets: Vec<u64>,
+    // None when the runs never reached `until`
+    pub state: Option<MachineState>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Report {
+    pub line: Line,
+    pub until: u16,
+    // Most common first, the one the others are diffed against
+    pub outcomes: Vec<Outcome>,
+}
+
+impl TimingFuzz {
+    pub fn new(line: Line, until: u16) -> Self {
+        TimingFuzz {
+            line,
+            first: 0,
+            last: 100,
+            hold: 8,
+            until,
+            max_cycles: 100_000,
+        }
+    }
+
+    // The run at `offset` as a replay, to step through in the debugger
+    pub fn recording(&self, checkpoint: &MachineState, offset: u64) -> Recording {
+        let cycle = checkpoint.cpu.clock_count + offset;
+
+        let events = match self.line {
+            Line::Irq => vec![
+                Event { cycle, stimulus: Stimulus::IrqLine { source: HOST_IRQ, asserted: true } },
+                Event { cycle: cycle + self.hold, stimulus: Stimulus::IrqLine { source: HOST_IRQ, asserted: false } },
+            ],
+            Line::Nmi => vec![Event { cycle, stimulus: Stimulus::Nmi }],
+        };
+
+        Recording { initial: checkpoint.clone(), events }
+    }
+
+    // Runs every offset from the checkpoint, which has to be taken between
+    // instructions. The machine is left back at the checkpoint.
+    pub fn run(&self, cpu: &mut cpu6502, checkpoint: &MachineState) -> Result<Report, String> {
+        if checkpoint.cpu.cycles != 0 {
+            return Err("checkpoint has to be taken between instructions".to_string());
+        }
+
+        let mut outcomes: Vec<Outcome> = Vec::new();
+
+        for offset in self.first..=self.last {
+            cpu.start_replay(self.recording(checkpoint, offset))?;
+            let state = self.finish(cpu);
+
+            match outcomes.iter_mut().find(|outcome| outcome.state == state) {
+                Some(outcome) => outcome.offsets.push(offset),
+                None => outcomes.push(Outcome { offsets: vec![offset], state }),
+            }
+        }
+
+        cpu.player = None;
+        checkpoint.restore(cpu)?;
+
+        // Stable, so ties stay in the order they were first seen
+        outcomes.sort_by(|a, b| b.offsets.len().cmp(&a.offsets.len()));
+
+        Ok(Report { line: self.line, until: self.until, outcomes })
+    }
+
+    // Clocks until the run is over and keeps only the part of the machine
+    // that is compared
+    fn finish(&self, cpu: &mut cpu6502) -> Option<MachineState> {
+        let end = cpu.clock_count + self.max_cycles;
+
+        while cpu.clock_count < end {
+            cpu.clock();
+
+            let pending = cpu.poll.nmi_edge || cpu.poll.nmi_pending || cpu.poll.irq_pending;
+            if cpu.complete() && cpu.pc == self.until && cpu.player.is_none() && !pending {
+                let mut state = MachineState::capture(cpu);
+                state.cpu.cycles = 0;
+                state.cpu.clock_count = 0;
+                state.devices.retain(|device| COMPARED.contains(&device.name.as_str()));
+
+                // Below the stack pointer is whatever the interrupt left
+                // there, which is bound to depend on where it landed
+                let free = 0x0100..=0x0100 + cpu.stkp as usize;
+                if let Some(ram) = state.devices.iter_mut().find(|device| device.name == "ram") {
+                    ram.data[free].fill(0);
+                }
+                return Some(state);
+            }
+        }
+
+        None
+    }
+}
+
+impl Report {
+    // Every offset ended the same way
+    pub fn is_consistent(&self) -> bool {
+        self.outcomes.len() <= 1
+    }
+
+    // For the console: a line per outcome and what it did differently
+    //
+    //   IRQ until $8007: 2 outcomes
+    //     9 runs at +0-+2, +7-+12
+    //     4 runs at +3-+6
+    //       ram $0010: 02 -> 01
+    pub fn describe(&self) -> Vec<String> {
+        let mut lines = vec![std::format!("{} until ${:04x}: {} outcome{}", self.line.name(), self.until, self.outcomes.len(), if self.outcomes.len() == 1 { "" } else { "s" })];
+        let expected = self.outcomes.first().and_then(|outcome| outcome.state.as_ref());
+
+        for outcome in &self.outcomes {
+            let runs = if outcome.offsets.len() == 1 { "run" } else { "runs" };
+            let mut line = std::format!("  {} {} at {}", outcome.offsets.len(), runs, offset_ranges(&outcome.offsets));
+
+            match (&outcome.state, expected) {
+                (None, _) => line.push_str(std::format!(", never reached ${:04x}", self.until).as_str()),
+                (Some(state), Some(expected)) => {
+                    lines.push(line);
+
+                    let diff = expected.describe_diff(state);
+                    for change in diff.iter().take(MAX_DIFF_LINES) {
+                        lines.push(std::format!("    {}", change));
+                    }
+                    if diff.len() > MAX_DIFF_LINES {
+                        lines.push(std::format!("    and {} more", diff.len() - MAX_DIFF_LINES));
+                    }
+                    continue;
+                }
+                // The most common outcome was a timeout, nothing to diff against
+                (Some(_), None) => {}
+            }
+
+            lines.push(line);
+        }
+
+        lines
+    }
+}
+
+// "+0-+2, +7" for [0, 1, 2, 7]
+fn offset_ranges(offsets: &[u64]) -> String {
+    let mut ranges: Vec<(u64, u64)> = Vec::new();
+
+    for &offset in offsets {
+        match ranges.last_mut() {
+            Some((_, end)) if *end + 1 == offset => *end = offset,
+            _ => ranges.push((offset, offset)),
+        }
+    }
+
+    ranges.iter()
+        .map(|&(start, end)| if start == end { std::format!("+{}", start) } else { std::format!("+{}-+{}", start, end) })
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // LDA $10 / CLC / ADC #$01 / STA $10 / JMP $8007, with `handler` at $9000
+    // for both interrupts
+    fn checkpoint(handler: &[u8]) -> (cpu6502, MachineState) {
+        let mut cpu = cpu6502::new();
+        cpu.load_program(&[0xA5, 0x10, 0x18, 0x69, 0x01, 0x85, 0x10, 0x4C, 0x07, 0x80], 0x8000);
+        cpu.load_program(handler, 0x9000);
+        cpu.set_reset_vector(0x8000);
+        cpu.set_irq_vector(0x9000);
+        cpu.set_nmi_vector(0x9000);
+        cpu.reset();
+        while !cpu.complete() {
+            cpu.clock();
+        }
+
+        let state = MachineState::capture(&cpu);
+        (cpu, state)
+    }
+
+    fn fuzz(line: Line) -> TimingFuzz {
+        TimingFuzz { last: 12, ..TimingFuzz::new(line, 0x8007) }
+    }
+
+    #[test]
+    fn lost_updates_show_up_as_a_second_outcome() {
+        // INC $10 / RTI, racing the main code's read-modify-write of $10
+        let (mut cpu, checkpoint) = checkpoint(&[0xE6, 0x10, 0x40]);
+        let report = fuzz(Line::Irq).run(&mut cpu, &checkpoint).unwrap();
+
+        assert!(!report.is_consistent());
+        assert_eq!(report.outcomes.len(), 2);
+        let lost = &report.outcomes[1];
+        assert_eq!(report.outcomes[0].state.as_ref().unwrap().device("ram").unwrap()[0x10], 2);
+        assert_eq!(lost.state.as_ref().unwrap().device("ram").unwrap()[0x10], 1);
+
+        let text = report.describe();
+        assert_eq!(text[0], "IRQ until $8007: 2 outcomes");
+        assert!(text.contains(&"    ram $0010: 02 -> 01".to_string()));
+
+        // The machine is back where it started
+        assert_eq!(MachineState::capture(&cpu), checkpoint);
+
+        // and any run can be replayed to watch it go wrong
+        cpu.start_replay(fuzz(Line::Irq).recording(&checkpoint, lost.offsets[0])).unwrap();
+        for _ in 0..100 {
+            cpu.clock();
+        }
+        assert_eq!(cpu.bus.borrow().read(0x0010, true), 1);
+    }
+
+    #[test]
+    fn handlers_that_leave_nothing_behind_are_consistent() {
+        // INC $20 / RTI
+        let (mut cpu, checkpoint) = checkpoint(&[0xE6, 0x20, 0x40]);
+        let report = fuzz(Line::Nmi).run(&mut cpu, &checkpoint).unwrap();
+
+        assert!(report.is_consistent());
+        assert_eq!(report.outcomes[0].offsets, (0..=12).collect::<Vec<u64>>());
+        assert_eq!(report.describe(), vec!["NMI until $8007: 1 outcome", "  13 runs at +0-+12"]);
+    }
+
+    #[test]
+    fn runs_that_never_finish() {
+        // A handler that never returns, so the main code can only finish
+        // when the interrupt arrives after it's done
+        let (mut cpu, checkpoint) = checkpoint(&[0x4C, 0x00, 0x90]);
+        let report = TimingFuzz { max_cycles: 200, ..fuzz(Line::Nmi) }.run(&mut cpu, &checkpoint).unwrap();
+
+        assert!(report.outcomes.iter().any(|outcome| outcome.state.is_none()));
+        assert!(report.describe().iter().any(|line| line.ends_with("never reached $8007")));
+    }
+
+    #[test]
+    fn checkpoints_are_between_instructions() {
+        let (mut cpu, _) = checkpoint(&[0x40]);
+        cpu.clock();
+        let checkpoint = MachineState::capture(&cpu);
+        assert!(fuzz(Line::Irq).run(&mut cpu, &checkpoint).is_err());
+    }
+
+    #[test]
+    fn offsets_are_shown_as_ranges() {
+        assert_eq!(offset_ranges(&[0, 1, 2, 7, 9, 10]), "+0-+2, +7, +9-+10");
+        assert_eq!(offset_ranges(&[]), "");
+    }
+}
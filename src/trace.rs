@@ -0,0 +1,316 @@
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::net::TcpStream;
+
+// Everything that traces execution goes through a TraceSink. The CPU hands
+// each event to whichever sink is installed, and sinks that produce text
+// share the formats below, so a new kind of trace only needs a new event
+// and a new output only needs a new sink.
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TraceEvent<'a> {
+    // An instruction about to run, with the registers before it ran
+    Instruction {
+        cycle: u64,
+        pc: u16,
+        // Opcode and operand, `len` of them are used
+        bytes: [u8; 3],
+        len: u16,
+        mnemonic: &'a str,
+        a: u8,
+        x: u8,
+        y: u8,
+        p: u8,
+        s: u8,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TraceFormat {
+    // One line per instruction laid out like nestest.log, so traces can be
+    // diffed against known good ones
+    Nestest,
+    // One JSON object per line
+    JsonLines,
+}
+
+impl TraceFormat {
+    pub fn parse(name: &str) -> Result<TraceFormat, String> {
+        match name {
+            "nestest" => Ok(TraceFormat::Nestest),
+            "json" | "jsonl" => Ok(TraceFormat::JsonLines),
+            _ => Err(std::format!("unknown trace format '{}', try nestest or json", name)),
+        }
+    }
+
+    pub fn line(&self, event: &TraceEvent) -> String {
+        match (self, event) {
+            (TraceFormat::Nestest, TraceEvent::Instruction { cycle, pc, bytes, len, mnemonic, a, x, y, p, s }) => {
+                let bytes: Vec<String> = bytes[..*len as usize].iter().map(|b| std::format!("{:02X}", b)).collect();
+                std::format!("{:04X}  {:<8}  {:<32}A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X} CYC:{}", pc, bytes.join(" "), mnemonic, a, x, y, p, s, cycle)
+            }
+            (TraceFormat::JsonLines, TraceEvent::Instruction { cycle, pc, bytes, len, mnemonic, a, x, y, p, s }) => {
+                let bytes: String = bytes[..*len as usize].iter().map(|b| std::format!("{:02x}", b)).collect();
+                std::format!(
+                    "{{\"cycle\":{},\"pc\":{},\"bytes\":\"{}\",\"op\":\"{}\",\"a\":{},\"x\":{},\"y\":{},\"p\":{},\"s\":{}}}",
+                    cycle, pc, bytes, mnemonic, a, x, y, p, s
+                )
+            }
+        }
+    }
+}
+
+pub trait TraceSink: Send {
+    fn event(&mut self, event: &TraceEvent) -> io::Result<()>;
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// Formatted lines to anything that can be written to. Writes are buffered,
+// a trace line per instruction is far too many small writes otherwise.
+pub struct WriterSink<W: Write> {
+    out: BufWriter<W>,
+    format: TraceFormat,
+}
+
+impl<W: Write> WriterSink<W> {
+    pub fn new(out: W, format: TraceFormat) -> Self {
+        WriterSink { out: BufWriter::with_capacity(64 * 1024, out), format }
+    }
+
+    pub fn into_inner(self) -> io::Result<W> {
+        self.out.into_inner().map_err(|e| e.into_error())
+    }
+}
+
+impl WriterSink<File> {
+    pub fn create(path: &str, format: TraceFormat) -> io::Result<Self> {
+        Ok(WriterSink::new(File::create(path)?, format))
+    }
+}
+
+impl WriterSink<TcpStream> {
+    // Streams to a listener elsewhere, "nc -l 6502 > trace.log" say
+    pub fn connect(addr: &str, format: TraceFormat) -> io::Result<Self> {
+        let stream = TcpStream::connect(addr)?;
+        stream.set_nodelay(true)?;
+        Ok(WriterSink::new(stream, format))
+    }
+}
+
+impl<W: Write + Send> TraceSink for WriterSink<W> {
+    fn event(&mut self, event: &TraceEvent) -> io::Result<()> {
+        writeln!(self.out, "{}", self.format.line(event))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+
+// Hands events to a closure, for tools that want them as they happen
+pub struct CallbackSink<F: FnMut(&TraceEvent) + Send> {
+    callback: F,
+}
+
+impl<F: FnMut(&TraceEvent) + Send> CallbackSink<F> {
+    pub fn new(callback: F) -> Self {
+        CallbackSink { callback }
+    }
+}
+
+impl<F: FnMut(&TraceEvent) + Send> TraceSink for CallbackSink<F> {
+    fn event(&mut self, event: &TraceEvent) -> io::Result<()> {
+        (self.callback)(event);
+        Ok(())
+    }
+}
+
+// One instruction as the trace ring keeps it. The mnemonic is looked up
+// again when the ring is written out, so records stay small and owned.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TraceRecord {
+    pub cycle: u64,
+    pub pc: u16,
+    pub bytes: [u8; 3],
+    pub len: u16,
+    pub a: u8,
+    pub x: u8,
+    pub y: u8,
+    pub p: u8,
+    pub s: u8,
+}
+
+impl TraceRecord {
+    pub fn event<'a>(&self, mnemonic: &'a str) -> TraceEvent<'a> {
+        TraceEvent::Instruction {
+            cycle: self.cycle,
+            pc: self.pc,
+            bytes: self.bytes,
+            len: self.len,
+            mnemonic,
+            a: self.a,
+            x: self.x,
+            y: self.y,
+            p: self.p,
+            s: self.s,
+        }
+    }
+}
+
+// The last `capacity` instructions, oldest first. Cheap enough to leave
+// running so that when something goes wrong long after its cause the run
+// up to it can still be written out through any TraceSink.
+pub struct TraceRing {
+    records: VecDeque<TraceRecord>,
+    capacity: usize,
+}
+
+impl TraceRing {
+    pub fn new(capacity: usize) -> Self {
+        TraceRing { records: VecDeque::with_capacity(capacity), capacity }
+    }
+
+    pub fn push(&mut self, record: TraceRecord) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.records.len() == self.capacity {
+            self.records.pop_front();
+        }
+        self.records.push_back(record);
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn len(&self) -> usize {
+        self.records.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.records.clear();
+    }
+
+    // The newest `count` records, oldest first
+    pub fn last(&self, count: usize) -> impl Iterator<Item = &TraceRecord> {
+        self.records.iter().skip(self.records.len().saturating_sub(count))
+    }
+}
+
+// Picks a sink from a target given at runtime: "tcp:<host>:<port>" for a
+// socket, anything else is a file name
+pub fn open(target: &str, format: TraceFormat) -> io::Result<Box<dyn TraceSink>> {
+    match target.strip_prefix("tcp:") {
+        Some(addr) => Ok(Box::new(WriterSink::connect(addr, format)?)),
+        None => Ok(Box::new(WriterSink::create(target, format)?)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+    use std::net::TcpListener;
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+    use crate::cpu6502;
+
+    fn event() -> TraceEvent<'static> {
+        TraceEvent::Instruction { cycle: 7, pc: 0xC000, bytes: [0x4C, 0xF5, 0xC5], len: 3, mnemonic: "JMP", a: 0, x: 0, y: 0, p: 0x24, s: 0xFD }
+    }
+
+    #[test]
+    fn formats() {
+        assert_eq!(TraceFormat::Nestest.line(&event()), "C000  4C F5 C5  JMP                             A:00 X:00 Y:00 P:24 SP:FD CYC:7");
+        assert_eq!(
+            TraceFormat::JsonLines.line(&event()),
+            "{\"cycle\":7,\"pc\":49152,\"bytes\":\"4cf5c5\",\"op\":\"JMP\",\"a\":0,\"x\":0,\"y\":0,\"p\":36,\"s\":253}"
+        );
+        assert!(TraceFormat::parse("csv").is_err());
+    }
+
+    #[test]
+    fn cpu_traces_every_instruction() {
+        let mut cpu = cpu6502::new();
+        // LDX #$02 / DEX / BNE -3 / NOP
+        cpu.load_program(&[0xA2, 0x02, 0xCA, 0xD0, 0xFD, 0xEA], 0x8000);
+        cpu.set_reset_vector(0x8000);
+        cpu.reset();
+
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let log = seen.clone();
+        cpu.set_trace(Some(Box::new(CallbackSink::new(move |event: &TraceEvent| {
+            match *event {
+                TraceEvent::Instruction { pc, x, .. } => log.lock().unwrap().push((pc, x)),
+            }
+        }))));
+
+        while cpu.pc != 0x8006 {
+            cpu.clock();
+        }
+
+        assert_eq!(*seen.lock().unwrap(), vec![(0x8000, 0), (0x8002, 2), (0x8003, 1), (0x8002, 1), (0x8003, 0), (0x8005, 0)]);
+
+        cpu.set_trace(None);
+        assert!(!cpu.is_tracing());
+    }
+
+    #[test]
+    fn ring_keeps_the_newest_instructions() {
+        let mut cpu = cpu6502::new();
+        // LDX #$05 / DEX / BNE -3 / NOP
+        cpu.load_program(&[0xA2, 0x05, 0xCA, 0xD0, 0xFD, 0xEA], 0x8000);
+        cpu.set_reset_vector(0x8000);
+        cpu.reset();
+        cpu.set_trace_ring(Some(4));
+
+        while cpu.pc != 0x8006 {
+            cpu.clock();
+        }
+
+        let ring = cpu.trace_ring().unwrap();
+        assert_eq!(ring.len(), 4);
+        let kept: Vec<(u16, u8)> = ring.last(usize::MAX).map(|record| (record.pc, record.x)).collect();
+        assert_eq!(kept, vec![(0x8003, 1), (0x8002, 1), (0x8003, 0), (0x8005, 0)]);
+
+        let mut sink = WriterSink::new(Vec::new(), TraceFormat::Nestest);
+        assert_eq!(cpu.dump_trace_ring(2, &mut sink).unwrap(), 2);
+        let text = String::from_utf8(sink.into_inner().unwrap()).unwrap();
+        let ops: Vec<&str> = text.lines().map(|line| &line[16..19]).collect();
+        assert_eq!(ops, vec!["BNE", "NOP"]);
+
+        cpu.set_trace_ring(None);
+        assert!(cpu.trace_ring().is_none());
+    }
+
+    #[test]
+    fn writer_and_socket_sinks() {
+        let mut sink = WriterSink::new(Vec::new(), TraceFormat::Nestest);
+        sink.event(&event()).unwrap();
+        sink.event(&event()).unwrap();
+        let text = String::from_utf8(sink.into_inner().unwrap()).unwrap();
+        assert_eq!(text.lines().count(), 2);
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let mut sink = open(&std::format!("tcp:{}", addr), TraceFormat::JsonLines).unwrap();
+        let (mut stream, _) = listener.accept().unwrap();
+
+        sink.event(&event()).unwrap();
+        sink.flush().unwrap();
+        drop(sink);
+
+        let mut received = String::new();
+        stream.read_to_string(&mut received).unwrap();
+        assert_eq!(received, std::format!("{}\n", TraceFormat::JsonLines.line(&event())));
+    }
+}
@@ -0,0 +1,155 @@
+use std::io::Write;
+
+use crate::{cpu6502, Halt};
+
+// The bare minimum of I/O most test and benchmark programs expect from
+// their host: somewhere to print a character and a way to stop with a
+// result. Writing to a trap address or calling it with JSR triggers it.
+// A call returns straight away as if the address held an RTS.
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Traps {
+    // Prints the byte written, or A for a JSR
+    pub char_out: Option<u16>,
+    // Stops the CPU with the byte written, or A, as the exit code
+    pub exit: Option<u16>,
+}
+
+impl cpu6502 {
+    pub fn set_traps(&mut self, traps: Traps) {
+        self.traps = traps;
+    }
+
+    pub fn traps(&self) -> Traps {
+        self.traps
+    }
+
+    // Where character output goes, stdout unless changed
+    pub fn set_trap_output(&mut self, output: Box<dyn Write + Send>) {
+        self.trap_output = output;
+    }
+
+    // Set once the exit trap fires. The CPU stays stopped until a reset.
+    pub fn exit_code(&self) -> Option<u8> {
+        self.exit_code
+    }
+
+    // Called for every write the CPU makes
+    pub(crate) fn trap_write(&mut self, addr: u16, data: u8) {
+        if self.traps.char_out == Some(addr) {
+            self.trap_char(data);
+        }
+        if self.traps.exit == Some(addr) {
+            self.trap_exit(data);
+        }
+    }
+
+    // Called after a JSR. Returns true if it went to a trap or an OS or
+    // KERNAL entry point, which has then already returned.
+    pub(crate) fn trap_call(&mut self) -> bool {
+        let target = Some(self.pc);
+        if target == self.traps.char_out {
+            self.trap_char(self.a);
+        } else if target == self.traps.exit {
+            self.trap_exit(self.a);
+        } else if !self.os_call() && !self.kernal_call() {
+            return false;
+        }
+
+        // Same as the RTS that would have been there
+        let lo = self.read(0x0100 + self.stkp.wrapping_add(1) as u16) as u16;
+        let hi = self.read(0x0100 + self.stkp.wrapping_add(2) as u16) as u16;
+        self.stkp = self.stkp.wrapping_add(2);
+        self.pc = ((hi << 8) | lo).wrapping_add(1);
+        self.cycles += 6;
+        true
+    }
+
+    pub(crate) fn trap_char(&mut self, c: u8) {
+        // Best effort, a closed stdout shouldn't stop the program
+        let _ = self.trap_output.write_all(&[c]);
+        if c == b'\n' {
+            let _ = self.trap_output.flush();
+        }
+    }
+
+    fn trap_exit(&mut self, code: u8) {
+        let _ = self.trap_output.flush();
+        self.exit_code = Some(code);
+        self.halt = Halt::Stopped;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+
+    struct Captured(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Captured {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn run(code: &[u8]) -> (cpu6502, Arc<Mutex<Vec<u8>>>) {
+        let mut cpu = cpu6502::new();
+        cpu.load_program(code, 0x8000);
+        cpu.set_reset_vector(0x8000);
+        cpu.set_traps(Traps { char_out: Some(0xF001), exit: Some(0xF002) });
+
+        let output = Arc::new(Mutex::new(Vec::new()));
+        cpu.set_trap_output(Box::new(Captured(output.clone())));
+        cpu.reset();
+
+        for _ in 0..1000 {
+            cpu.clock();
+            if cpu.exit_code().is_some() && cpu.complete() {
+                break;
+            }
+        }
+
+        (cpu, output)
+    }
+
+    #[test]
+    fn writes_print_and_exit() {
+        // LDA #'H' / STA $F001 / LDX #'i' / STX $F001 / LDA #$03 / STA $F002 / LDA #$FF
+        let (cpu, output) = run(&[0xA9, b'H', 0x8D, 0x01, 0xF0, 0xA2, b'i', 0x8E, 0x01, 0xF0, 0xA9, 0x03, 0x8D, 0x02, 0xF0, 0xA9, 0xFF]);
+
+        assert_eq!(*output.lock().unwrap(), b"Hi");
+        assert_eq!(cpu.exit_code(), Some(3));
+        assert_eq!(cpu.halt_state(), Halt::Stopped);
+        // Nothing after the exit ran
+        assert_eq!(cpu.a, 0x03);
+    }
+
+    #[test]
+    fn calls_print_a_and_return() {
+        // LDA #'o' / JSR $F001 / LDA #'k' / JSR $F001 / LDA #$00 / JSR $F002
+        let (cpu, output) = run(&[0xA9, b'o', 0x20, 0x01, 0xF0, 0xA9, b'k', 0x20, 0x01, 0xF0, 0xA9, 0x00, 0x20, 0x02, 0xF0]);
+
+        assert_eq!(*output.lock().unwrap(), b"ok");
+        assert_eq!(cpu.exit_code(), Some(0));
+        // Each call came back, including the last
+        assert_eq!(cpu.stkp, 0xFD);
+        assert_eq!(cpu.pc, 0x800F);
+    }
+
+    #[test]
+    fn reset_clears_the_exit_code() {
+        let (mut cpu, _) = run(&[0xA9, 0x01, 0x8D, 0x02, 0xF0]);
+        assert_eq!(cpu.exit_code(), Some(1));
+
+        cpu.reset();
+        assert_eq!((cpu.exit_code(), cpu.halt_state()), (None, Halt::Running));
+    }
+}
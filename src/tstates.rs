@@ -0,0 +1,140 @@
+use crate::cpu6502;
+
+// Whole instruction sequences timed against totals worked out by hand from
+// the NMOS 6502 datasheet. The per-opcode tests check each instruction on
+// its own, this checks them run together the way programs run them, loops
+// and calls and interrupts included, so a cycle lost or gained anywhere
+// between instructions shows up as a total that's off.
+
+// Far more than any sequence needs
+const MAX_CYCLES: u64 = 1_000;
+
+pub struct Sequence {
+    pub name: &'static str,
+    pub origin: u16,
+    pub code: &'static [u8],
+    // Bytes put in memory first, pointers and vectors
+    pub pokes: &'static [(u16, u8)],
+    // Where the sequence ends, it's done when an instruction finishes
+    // with PC here
+    pub end: u16,
+    pub cycles: u64,
+}
+
+pub const SEQUENCES: &[Sequence] = &[
+    // 2 + 5 x DEX + 4 taken BNEs + the last one falling through
+    Sequence {
+        name: "countdown loop",
+        origin: 0x0200,
+        code: &[0xA2, 0x05, 0xCA, 0xD0, 0xFD],
+        pokes: &[],
+        end: 0x0205,
+        cycles: 2 + 5 * 2 + 4 * 3 + 2,
+    },
+    // The same with the BNE ending a page, so every taken one crosses back
+    Sequence {
+        name: "loop across a page",
+        origin: 0x02FB,
+        code: &[0xA2, 0x03, 0xCA, 0xD0, 0xFD],
+        pokes: &[],
+        end: 0x0300,
+        cycles: 2 + 3 * 2 + 2 * 4 + 2,
+    },
+    // JSR $0205 / $0205: RTS
+    Sequence {
+        name: "subroutine call",
+        origin: 0x0200,
+        code: &[0x20, 0x05, 0x02, 0xEA, 0xEA, 0x60],
+        pokes: &[],
+        end: 0x0203,
+        cycles: 6 + 6,
+    },
+    // JMP ($0010) to $0206, JMP $0209
+    Sequence {
+        name: "jumps",
+        origin: 0x0200,
+        code: &[0x6C, 0x10, 0x00, 0xEA, 0xEA, 0xEA, 0x4C, 0x09, 0x02],
+        pokes: &[(0x0010, 0x06), (0x0011, 0x02)],
+        end: 0x0209,
+        cycles: 5 + 3,
+    },
+    // LDX #$FF / LDA $10FF,X crossing / STA $1000,X always 5 / INC $10,X
+    Sequence {
+        name: "indexed",
+        origin: 0x0200,
+        code: &[0xA2, 0xFF, 0xBD, 0xFF, 0x10, 0x9D, 0x00, 0x10, 0xF6, 0x10],
+        pokes: &[],
+        end: 0x020A,
+        cycles: 2 + 5 + 5 + 6,
+    },
+    // LDY #$01 / LDA ($80),Y crossing from $10FF / STA ($80),Y always 6
+    Sequence {
+        name: "indirect indexed",
+        origin: 0x0200,
+        code: &[0xA0, 0x01, 0xB1, 0x80, 0x91, 0x80],
+        pokes: &[(0x0080, 0xFF), (0x0081, 0x10)],
+        end: 0x0206,
+        cycles: 2 + 6 + 6,
+    },
+    // PHA / PLA / PHP / PLP
+    Sequence {
+        name: "stack",
+        origin: 0x0200,
+        code: &[0x48, 0x68, 0x08, 0x28],
+        pokes: &[],
+        end: 0x0204,
+        cycles: 3 + 4 + 3 + 4,
+    },
+    // ASL $1000 / ASL $1000,X, read-modify-write never saves the cycle
+    Sequence {
+        name: "read-modify-write",
+        origin: 0x0200,
+        code: &[0x0E, 0x00, 0x10, 0x1E, 0x00, 0x10],
+        pokes: &[],
+        end: 0x0206,
+        cycles: 6 + 7,
+    },
+    // BRK to a handler at $0300 that's just RTI, back to $0202
+    Sequence {
+        name: "BRK and RTI",
+        origin: 0x0200,
+        code: &[0x00, 0xEA],
+        pokes: &[(0xFFFE, 0x00), (0xFFFF, 0x03), (0x0300, 0x40)],
+        end: 0x0202,
+        cycles: 7 + 6,
+    },
+];
+
+impl Sequence {
+    // The cycles it took here, or None if it never got to `end`
+    pub fn run(&self) -> Option<u64> {
+        let mut cpu = cpu6502::new();
+        for &(addr, data) in self.pokes {
+            cpu.bus.borrow_mut().write(addr, data);
+        }
+        cpu.load_program(self.code, self.origin);
+        cpu.pc = self.origin;
+
+        for cycles in 1..=MAX_CYCLES {
+            cpu.clock();
+            if cpu.complete() && cpu.pc == self.end {
+                return Some(cycles);
+            }
+        }
+        None
+    }
+}
+
+// One line for each sequence whose total isn't the datasheet's, nothing
+// when they all agree
+pub fn audit() -> Vec<String> {
+    let mut divergences = Vec::new();
+    for sequence in SEQUENCES {
+        match sequence.run() {
+            Some(cycles) if cycles == sequence.cycles => {}
+            Some(cycles) => divergences.push(std::format!("{}: {} cycles, the datasheet says {}", sequence.name, cycles, sequence.cycles)),
+            None => divergences.push(std::format!("{}: never reached ${:04x}", sequence.name, sequence.end)),
+        }
+    }
+    divergences
+}
@@ -0,0 +1,192 @@
+use crate::cpu6502;
+use crate::symbols::SymbolTable;
+
+// The three vectors at the top of memory, what's in them now and which one
+// the CPU last went through. Each keeps the last time it was taken, so a
+// handler that never runs, or a reset nobody expected, is easy to spot.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Vector {
+    Nmi,
+    Reset,
+    Irq,
+}
+
+impl Vector {
+    // In address order, the way they sit in memory
+    pub const ALL: [Vector; 3] = [Vector::Nmi, Vector::Reset, Vector::Irq];
+
+    pub fn from_name(name: &str) -> Option<Vector> {
+        match name.trim().to_ascii_lowercase().as_str() {
+            "nmi" => Some(Vector::Nmi),
+            "reset" | "res" => Some(Vector::Reset),
+            "irq" | "brk" => Some(Vector::Irq),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Vector::Nmi => "NMI",
+            Vector::Reset => "RESET",
+            Vector::Irq => "IRQ",
+        }
+    }
+
+    // Where the low byte is, the high byte follows
+    pub fn addr(&self) -> u16 {
+        match self {
+            Vector::Nmi => 0xFFFA,
+            Vector::Reset => 0xFFFC,
+            Vector::Irq => 0xFFFE,
+        }
+    }
+
+    fn index(&self) -> usize {
+        match self {
+            Vector::Nmi => 0,
+            Vector::Reset => 1,
+            Vector::Irq => 2,
+        }
+    }
+}
+
+// What sent the CPU through a vector. BRK shares the IRQ vector, so this
+// says which it was.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Cause {
+    Irq,
+    Brk,
+    Nmi,
+    Reset,
+}
+
+impl Cause {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Cause::Irq => "IRQ",
+            Cause::Brk => "BRK",
+            Cause::Nmi => "NMI",
+            Cause::Reset => "RESET",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct VectorTaken {
+    pub vector: Vector,
+    pub cause: Cause,
+    pub cycle: u64,
+    // Where the vector pointed at the time
+    pub handler: u16,
+}
+
+impl VectorTaken {
+    // "IRQ at cycle 1234 to $8040"
+    pub fn describe(&self) -> String {
+        std::format!("{} at cycle {} to ${:04x}", self.cause.name(), self.cycle, self.handler)
+    }
+}
+
+// Kept on the CPU, one slot per vector
+#[derive(Debug, Clone, Default)]
+pub struct VectorLog {
+    taken: [Option<VectorTaken>; 3],
+}
+
+impl VectorLog {
+    pub fn new() -> Self {
+        VectorLog::default()
+    }
+}
+
+impl cpu6502 {
+    // What's in a vector now, read without side effects
+    pub fn vector(&self, vector: Vector) -> u16 {
+        let bus = self.bus.borrow();
+        bus.read(vector.addr(), true) as u16 | (bus.read(vector.addr().wrapping_add(1), true) as u16) << 8
+    }
+
+    // "NMI   $a000 nmi_handler  NMI at cycle 1234 to $a000", for the pane
+    // and the console
+    pub fn describe_vector(&self, vector: Vector, symbols: &SymbolTable) -> String {
+        let addr = self.vector(vector);
+        let taken = match self.vector_taken(vector) {
+            Some(taken) => taken.describe(),
+            None => "never taken".to_string(),
+        };
+        std::format!("{:<5} ${:04x} {:<12} {}", vector.name(), addr, symbols.name_of(addr).unwrap_or(""), taken)
+    }
+
+    // The last time each vector was taken
+    pub fn vector_taken(&self, vector: Vector) -> Option<VectorTaken> {
+        self.vectors.taken[vector.index()]
+    }
+
+    // Whichever vector the CPU went through most recently
+    pub fn last_vector(&self) -> Option<VectorTaken> {
+        self.vectors.taken.iter().flatten().max_by_key(|taken| taken.cycle).copied()
+    }
+
+    // Called once the PC has been loaded from the vector
+    pub(crate) fn took_vector(&mut self, vector: Vector, cause: Cause) {
+        self.vectors.taken[vector.index()] = Some(VectorTaken { vector, cause, cycle: self.clock_count, handler: self.pc });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::debugger::Debugger;
+
+    fn step(cpu: &mut cpu6502) {
+        cpu.clock();
+        while !cpu.complete() {
+            cpu.clock();
+        }
+    }
+
+    #[test]
+    fn remembers_the_vectors_taken() {
+        // NOP / BRK
+        let mut cpu = cpu6502::new();
+        cpu.load_program(&[0xEA, 0x00], 0x8000);
+        cpu.load_program(&[0xEA; 4], 0x9000);
+        cpu.set_reset_vector(0x8000);
+        cpu.set_irq_vector(0x9000);
+        cpu.set_nmi_vector(0xA000);
+        cpu.reset();
+        assert_eq!(cpu.vector(Vector::Irq), 0x9000);
+        assert_eq!(cpu.last_vector().map(|taken| (taken.vector, taken.handler)), Some((Vector::Reset, 0x8000)));
+        assert_eq!(cpu.vector_taken(Vector::Irq), None);
+
+        step(&mut cpu);
+        step(&mut cpu);
+        step(&mut cpu);
+        let brk = cpu.vector_taken(Vector::Irq).unwrap();
+        assert_eq!((brk.cause, brk.handler), (Cause::Brk, 0x9000));
+        assert_eq!(cpu.last_vector(), Some(brk));
+
+        cpu.trigger_nmi();
+        while cpu.vector_taken(Vector::Nmi).is_none() {
+            step(&mut cpu);
+        }
+        let nmi = cpu.last_vector().unwrap();
+        assert_eq!((nmi.vector, nmi.cause, nmi.handler), (Vector::Nmi, Cause::Nmi, 0xA000));
+        assert!(nmi.cycle > brk.cycle);
+        assert_eq!(nmi.describe(), std::format!("NMI at cycle {} to $a000", nmi.cycle));
+    }
+
+    #[test]
+    fn edits_undo_as_one() {
+        let mut cpu = cpu6502::new();
+        let mut debugger = Debugger::new();
+        cpu.set_nmi_vector(0x1234);
+
+        debugger.set_vector(&mut cpu, Vector::from_name("nmi").unwrap(), 0xC0DE);
+        assert_eq!(cpu.vector(Vector::Nmi), 0xC0DE);
+        assert!(debugger.undo(&mut cpu));
+        assert_eq!(cpu.vector(Vector::Nmi), 0x1234);
+        assert_eq!(debugger.undo_depth(), 0);
+    }
+}
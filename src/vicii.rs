@@ -0,0 +1,431 @@
+use std::any::Any;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+use crate::cia::CiaPort;
+use crate::cpu6502;
+use crate::device::BusDevice;
+use crate::interrupts::IrqSource;
+use crate::scheduler::{AlarmId, EventQueue};
+use crate::Bus;
+
+// A PAL 6569 VIC-II, as far as standard text mode: 40x25 characters from
+// screen memory, each in its own colour from colour RAM on the background
+// colour, inside the border. Registers the text mode doesn't use are kept
+// and read back.
+//
+//   +11  control 1: bits 0-2 vertical scroll, bit 3 25 rows rather than
+//        24, bit 4 display on, bits 5-6 bitmap and extended colour modes,
+//        bit 7 bit 8 of the raster line (read) or the compare (write)
+//   +12  raster line, or the line to interrupt on when written
+//   +16  control 2: bits 0-2 horizontal scroll, bit 3 40 columns rather
+//        than 38, bit 4 multicolour mode
+//   +18  bits 4-7 screen memory in 1K steps, bits 1-3 the character set
+//        in 2K steps, both within the VIC's 16K bank
+//   +19  interrupt flags, bit 0 the raster, bit 7 set if any enabled one
+//        is. Writing 1s clears them.
+//   +1A  interrupt enables
+//   +20  border colour       +21  background colour
+//
+// The registers repeat every 64 bytes. The 16K bank is picked by the
+// second CIA's PA0-PA1, inverted, and in banks 0 and 2 the character ROM
+// shows through at $1000-$1FFF. The VIC reads RAM past whatever the CPU
+// sees there.
+//
+// Each line is drawn as the beam finishes it, with what the registers hold
+// then, so raster splits land on the line. Not here: sprites, the modes
+// other than standard text (they show the background colour), the light
+// pen and the cycles badlines steal from the CPU.
+
+pub const FRAME_WIDTH: usize = 384;
+pub const FRAME_LINES: usize = 272;
+
+pub const CYCLES_PER_LINE: u64 = 63;
+pub const LINES_PER_FRAME: u16 = 312;
+
+// The raster line the picture starts on, and where the text area starts
+// within it across and down with the scroll registers at 0
+const FIRST_LINE: u16 = 15;
+const BORDER_LEFT: usize = 32;
+const TEXT_TOP: u16 = 48;
+
+const CR1: usize = 0x11;
+const RASTER: usize = 0x12;
+const CR2: usize = 0x16;
+const MEMORY: usize = 0x18;
+const IRQ_FLAGS: usize = 0x19;
+const IRQ_ENABLE: usize = 0x1A;
+const BORDER: usize = 0x20;
+const BACKGROUND: usize = 0x21;
+
+const IRQ_RASTER: u8 = 0x01;
+
+// The 16 colours, as Pepto measured them
+pub const PALETTE: [u32; 16] = [
+    0x000000, 0xFFFFFF, 0x68372B, 0x70A4B2, 0x6F3D86, 0x588D43, 0x352879, 0xB8C76F,
+    0x6F4F25, 0x433900, 0x9A6759, 0x444444, 0x6C6C6C, 0x9AD284, 0x6C5EB5, 0x959595,
+];
+
+struct Chip {
+    regs: [u8; 0x40],
+    colour_ram: [u8; 1024],
+    // Where the beam is, and the line the raster interrupt is for
+    line: u16,
+    compare: u16,
+    characters: Option<Vec<u8>>,
+    // The CIA whose port A picks the bank
+    bank_select: Option<CiaPort>,
+    frame: Vec<u32>,
+    finished: Vec<u32>,
+    frames: u64,
+    events: EventQueue,
+    // Where the IRQ output goes once attached, what it was last set to and
+    // the event that moves the beam on a line at a time
+    irq: Option<IrqSource>,
+    irq_raised: bool,
+    line_event: Option<AlarmId>,
+}
+
+impl Chip {
+    fn bank(&self) -> u16 {
+        self.bank_select.as_ref().map_or(0, |cia| 3 - (cia.port_a() & 3) as u16)
+    }
+
+    // A byte of the VIC's 16K
+    fn fetch(&self, bus: &Bus, addr: u16) -> u8 {
+        let (bank, addr) = (self.bank(), addr & 0x3FFF);
+        if let Some(characters) = self.characters.as_ref().filter(|rom| !rom.is_empty()) {
+            if bank & 1 == 0 && addr & 0x3000 == 0x1000 {
+                return characters[(addr & 0x0FFF) as usize % characters.len()];
+            }
+        }
+        bus.peek_ram(bank * 0x4000 + addr)
+    }
+
+    fn draw_line(&mut self, bus: &Bus) {
+        if !(FIRST_LINE..FIRST_LINE + FRAME_LINES as u16).contains(&self.line) {
+            return;
+        }
+        let y = self.line;
+        let (cr1, cr2) = (self.regs[CR1], self.regs[CR2]);
+        let border = PALETTE[(self.regs[BORDER] & 0x0F) as usize];
+        let background = PALETTE[(self.regs[BACKGROUND] & 0x0F) as usize];
+
+        // The border closes in by 4 lines and 8 pixels for 24 rows and 38
+        // columns, with a little more off the left than the right
+        let (top, bottom) = if cr1 & 0x08 != 0 { (51, 251) } else { (55, 247) };
+        let (left, right) = if cr2 & 0x08 != 0 { (BORDER_LEFT, BORDER_LEFT + 320) } else { (BORDER_LEFT + 7, BORDER_LEFT + 311) };
+        let display = cr1 & 0x10 != 0 && (top..bottom).contains(&y);
+        let standard_text = cr1 & 0x60 == 0 && cr2 & 0x10 == 0;
+
+        let screen = (self.regs[MEMORY] >> 4) as u16 * 0x400;
+        let charset = ((self.regs[MEMORY] >> 1) & 0x07) as u16 * 0x800;
+        let text_y = y as isize - (TEXT_TOP + (cr1 & 0x07) as u16) as isize;
+
+        let start = (y - FIRST_LINE) as usize * FRAME_WIDTH;
+        for x in 0..FRAME_WIDTH {
+            let colour = if !display || x < left || x >= right {
+                border
+            } else {
+                let text_x = x as isize - (BORDER_LEFT + (cr2 & 0x07) as usize) as isize;
+                if !standard_text || text_x < 0 || !(0..200).contains(&text_y) {
+                    background
+                } else {
+                    let (column, row) = (text_x as u16 / 8, text_y as u16 / 8);
+                    let cell = row * 40 + column;
+                    let code = self.fetch(bus, screen + cell) as u16;
+                    let glyph = self.fetch(bus, charset + code * 8 + text_y as u16 % 8);
+                    if glyph & (0x80 >> (text_x % 8)) != 0 {
+                        PALETTE[(self.colour_ram[cell as usize] & 0x0F) as usize]
+                    } else {
+                        background
+                    }
+                }
+            };
+            self.frame[start + x] = colour;
+        }
+    }
+
+    // The beam reaching the end of the line
+    fn next_line(&mut self, bus: &Bus) {
+        self.draw_line(bus);
+
+        self.line = (self.line + 1) % LINES_PER_FRAME;
+        if self.line == 0 {
+            std::mem::swap(&mut self.frame, &mut self.finished);
+            self.frames += 1;
+        }
+        if self.line == self.compare {
+            self.regs[IRQ_FLAGS] |= IRQ_RASTER;
+        }
+        self.update_irq();
+    }
+
+    fn update_irq(&mut self) {
+        let asserted = self.regs[IRQ_FLAGS] & self.regs[IRQ_ENABLE] & 0x0F != 0;
+        if asserted == self.irq_raised {
+            return;
+        }
+        if let Some(source) = self.irq {
+            self.events.schedule_in(0, move |cpu| cpu.set_irq(source, asserted));
+            self.irq_raised = asserted;
+        }
+    }
+
+    fn read(&self, register: usize) -> u8 {
+        match register {
+            CR1 => (self.regs[CR1] & 0x7F) | ((self.line >> 1) as u8 & 0x80),
+            RASTER => self.line as u8,
+            CR2 => self.regs[CR2] | 0xC0,
+            MEMORY => self.regs[MEMORY] | 0x01,
+            IRQ_FLAGS => {
+                let any = if self.regs[IRQ_FLAGS] & self.regs[IRQ_ENABLE] & 0x0F != 0 { 0x80 } else { 0 };
+                self.regs[IRQ_FLAGS] | 0x70 | any
+            }
+            IRQ_ENABLE => self.regs[IRQ_ENABLE] | 0xF0,
+            // Sprite collisions, and there are no sprites
+            0x1E | 0x1F => 0x00,
+            0x20..=0x2E => self.regs[register] | 0xF0,
+            0x2F..=0x3F => 0xFF,
+            _ => self.regs[register],
+        }
+    }
+
+    fn write(&mut self, register: usize, data: u8) {
+        match register {
+            CR1 => {
+                self.regs[CR1] = data;
+                self.compare = (self.compare & 0xFF) | ((data as u16 & 0x80) << 1);
+            }
+            RASTER => self.compare = (self.compare & 0x100) | data as u16,
+            IRQ_FLAGS => self.regs[IRQ_FLAGS] &= !data,
+            IRQ_ENABLE => self.regs[IRQ_ENABLE] = data & 0x0F,
+            0x2F..=0x3F => {}
+            _ => self.regs[register] = data,
+        }
+        self.update_irq();
+    }
+}
+
+// The host's side: the picture, and the character ROM to give it
+#[derive(Clone)]
+pub struct VicPort(Arc<Mutex<Chip>>);
+
+impl VicPort {
+    fn chip(&self) -> MutexGuard<'_, Chip> {
+        self.0.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    // The last whole frame, FRAME_WIDTH by FRAME_LINES
+    pub fn frame(&self) -> Vec<u32> {
+        self.chip().finished.clone()
+    }
+
+    // How many frames have been finished, to tell when there's a new one
+    pub fn frames(&self) -> u64 {
+        self.chip().frames
+    }
+
+    pub fn raster_line(&self) -> u16 {
+        self.chip().line
+    }
+
+    // The 4K character ROM, which is Commodore's. Without one the VIC sees
+    // RAM where it would be.
+    pub fn set_character_rom(&self, rom: &[u8]) {
+        self.chip().characters = Some(rom.to_vec());
+    }
+
+    // Takes the bank from `cia`'s port A, the C64's second CIA
+    pub fn connect_bank_select(&self, cia: CiaPort) {
+        self.chip().bank_select = Some(cia);
+    }
+
+    pub fn colour_ram(&self) -> ColourRam {
+        ColourRam { port: self.clone() }
+    }
+}
+
+pub struct Vic {
+    port: VicPort,
+}
+
+impl Vic {
+    pub fn new(events: EventQueue) -> Self {
+        let chip = Chip {
+            regs: [0; 0x40],
+            colour_ram: [0; 1024],
+            line: 0,
+            compare: 0,
+            characters: None,
+            bank_select: None,
+            frame: vec![0; FRAME_WIDTH * FRAME_LINES],
+            finished: vec![0; FRAME_WIDTH * FRAME_LINES],
+            frames: 0,
+            events,
+            irq: None,
+            irq_raised: false,
+            line_event: None,
+        };
+        Vic { port: VicPort(Arc::new(Mutex::new(chip))) }
+    }
+
+    pub fn port(&self) -> VicPort {
+        self.port.clone()
+    }
+
+    // Wires the IRQ output to `source` and sets the beam moving, which
+    // takes an event every line
+    pub fn connect(&mut self, source: IrqSource) {
+        let mut chip = self.port.chip();
+        chip.irq = Some(source);
+        if let Some(id) = chip.line_event.take() {
+            chip.events.cancel(id);
+        }
+        let port = self.port.clone();
+        chip.line_event = Some(chip.events.schedule_every(CYCLES_PER_LINE, move |cpu| {
+            // The bus first, as when the CPU reaches the chip through it
+            let bus = cpu.bus.borrow();
+            port.chip().next_line(&bus);
+        }));
+        chip.update_irq();
+    }
+}
+
+impl BusDevice for Vic {
+    // Reading the flags doesn't clear them, writing them back does
+    fn read(&mut self, offset: u16) -> u8 {
+        self.peek(offset)
+    }
+
+    fn peek(&self, offset: u16) -> u8 {
+        self.port.chip().read((offset & 0x3F) as usize)
+    }
+
+    fn write(&mut self, offset: u16, data: u8) {
+        self.port.chip().write((offset & 0x3F) as usize, data);
+    }
+
+    fn reset(&mut self) {
+        let mut chip = self.port.chip();
+        chip.regs = [0; 0x40];
+        chip.compare = 0;
+        chip.update_irq();
+    }
+
+    fn as_any(&self) -> Option<&dyn Any> {
+        Some(self)
+    }
+}
+
+// 1K of 4 bit colour RAM, one nybble per character cell. The top four
+// bits aren't there and read as 0.
+pub struct ColourRam {
+    port: VicPort,
+}
+
+impl BusDevice for ColourRam {
+    fn read(&mut self, offset: u16) -> u8 {
+        self.peek(offset)
+    }
+
+    fn peek(&self, offset: u16) -> u8 {
+        self.port.chip().colour_ram[(offset & 0x3FF) as usize]
+    }
+
+    fn write(&mut self, offset: u16, data: u8) {
+        self.port.chip().colour_ram[(offset & 0x3FF) as usize] = data & 0x0F;
+    }
+}
+
+impl cpu6502 {
+    // Maps a VIC-II at $base-$base+3FF with its IRQ wired to the CPU, and
+    // colour RAM at $colour-$colour+3FF. Returns the host's end of it.
+    pub fn attach_vic(&mut self, base: u16, colour: u16) -> VicPort {
+        let mut vic = Vic::new(self.events());
+        vic.connect(self.interrupts.register_source("vic"));
+        let port = vic.port();
+        let mut bus = self.bus.borrow_mut();
+        bus.map_mirrored("vic", base, base.wrapping_add(0x3FF), 0x40, Box::new(vic));
+        bus.map("colour ram", colour, colour.wrapping_add(0x3FF), Box::new(port.colour_ram()));
+        port
+    }
+
+    // The VIC-II attached last, if one still is
+    pub fn vic(&self) -> Option<VicPort> {
+        let bus = self.bus.borrow();
+        let mapping = bus.mappings().find(|mapping| mapping.name == "vic")?;
+        let device = mapping.device();
+        device.as_any()?.downcast_ref::<Vic>().map(Vic::port)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(cpu: &mut cpu6502, cycles: u64) {
+        for _ in 0..cycles {
+            cpu.clock();
+        }
+    }
+
+    #[test]
+    fn draws_text_and_border() {
+        let mut cpu = cpu6502::new();
+        let vic = cpu.attach_vic(0xD000, 0xD800);
+
+        // Screen at $0400, a character set at $2000 whose character 1 is
+        // a bar across its top row
+        let mut bus = cpu.bus.borrow_mut();
+        bus.write(0x2008, 0xFF);
+        bus.write(0x0400, 0x01);
+        bus.write(0xD800, 0x07);
+        bus.write(0xD011, 0x1B);
+        bus.write(0xD016, 0x08);
+        bus.write(0xD018, 0x18);
+        bus.write(0xD020, 0x0E);
+        bus.write(0xD021, 0x06);
+        assert_eq!((bus.read(0xD800, true), bus.read(0xD020, true), bus.read(0xD318, true)), (0x07, 0xFE, 0x19));
+        drop(bus);
+
+        run(&mut cpu, CYCLES_PER_LINE * LINES_PER_FRAME as u64 * 2);
+        assert!(vic.frames() >= 1);
+        let frame = vic.frame();
+        let at = |x: usize, y: u16| frame[(y - FIRST_LINE) as usize * FRAME_WIDTH + x];
+
+        // The first cell's top row in yellow, the row under it background
+        assert_eq!((at(BORDER_LEFT, 51), at(BORDER_LEFT + 7, 51)), (PALETTE[7], PALETTE[7]));
+        assert_eq!((at(BORDER_LEFT + 8, 51), at(BORDER_LEFT, 52)), (PALETTE[6], PALETTE[6]));
+        assert_eq!((at(0, 51), at(BORDER_LEFT, 50)), (PALETTE[14], PALETTE[14]));
+
+        // 38 columns pull the border over the first cell's left edge
+        cpu.bus.borrow_mut().write(0xD016, 0x00);
+        run(&mut cpu, CYCLES_PER_LINE * LINES_PER_FRAME as u64 * 2);
+        let frame = vic.frame();
+        assert_eq!(frame[(51 - FIRST_LINE) as usize * FRAME_WIDTH + BORDER_LEFT], PALETTE[14]);
+        assert_eq!(frame[(51 - FIRST_LINE) as usize * FRAME_WIDTH + BORDER_LEFT + 7], PALETTE[7]);
+    }
+
+    #[test]
+    fn raster_interrupts() {
+        let mut cpu = cpu6502::new();
+        // LDA #$01 / STA $D01A / LDA #$64 / STA $D012 / LDA #$1B / STA $D011
+        // CLI / loop: JMP loop
+        cpu.load_program(&[0xA9, 0x01, 0x8D, 0x1A, 0xD0, 0xA9, 0x64, 0x8D, 0x12, 0xD0, 0xA9, 0x1B, 0x8D, 0x11, 0xD0, 0x58, 0x4C, 0x10, 0x80], 0x8000);
+        // irq: LDA $D012 / STA $10 / INC $11 / LDA #$01 / STA $D019 / RTI
+        cpu.load_program(&[0xAD, 0x12, 0xD0, 0x85, 0x10, 0xE6, 0x11, 0xA9, 0x01, 0x8D, 0x19, 0xD0, 0x40], 0x9000);
+        cpu.set_reset_vector(0x8000);
+        cpu.set_irq_vector(0x9000);
+        let vic = cpu.attach_vic(0xD000, 0xD800);
+        cpu.reset();
+
+        run(&mut cpu, CYCLES_PER_LINE * LINES_PER_FRAME as u64 * 3 + 10);
+
+        // Once a frame, on line 100
+        let bus = cpu.bus.borrow();
+        assert_eq!(bus.read(0x10, true), 100);
+        assert_eq!(bus.read(0x11, true), 3);
+        assert_eq!(bus.read(0xD019, true) & 0x81, 0);
+        assert!(vic.raster_line() < LINES_PER_FRAME);
+    }
+}
@@ -0,0 +1,189 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+#[cfg(feature = "gui")]
+use std::sync::mpsc::{channel, Receiver};
+
+#[cfg(feature = "gui")]
+use notify::event::ModifyKind;
+#[cfg(feature = "gui")]
+use notify::{EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+
+// Notices when a file is rewritten. With the gui feature the platform says
+// when (inotify, FSEvents and so on, through notify), which catches a
+// rewrite even when it leaves the size and timestamp as they were.
+// Without it, or where the platform won't watch the file, it falls back to
+// comparing the modification time and length with what they were last
+// time. Polling once in a while is plenty for an edit-assemble-run loop
+// and needs nothing from the platform.
+pub struct FileWatcher {
+    path: PathBuf,
+    stamp: Option<(SystemTime, u64)>,
+    #[cfg(feature = "gui")]
+    events: Option<Events>,
+}
+
+impl FileWatcher {
+    pub fn new<P: AsRef<Path>>(path: P) -> Self {
+        let watcher = FileWatcher::polling(path);
+        #[cfg(feature = "gui")]
+        let watcher = FileWatcher { events: Events::watch(&watcher.path), ..watcher };
+        watcher
+    }
+
+    // One that only ever polls, whatever the platform could do
+    pub fn polling<P: AsRef<Path>>(path: P) -> Self {
+        let path = path.as_ref().to_path_buf();
+        let stamp = stamp(&path);
+        FileWatcher {
+            path,
+            stamp,
+            #[cfg(feature = "gui")]
+            events: None,
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    // True once for each change since the last call. A file that's missing
+    // for the moment, as it is halfway through an editor's save, isn't a
+    // change until it comes back.
+    pub fn changed(&mut self) -> bool {
+        #[cfg(feature = "gui")]
+        if let Some(events) = &self.events {
+            if !events.touched(&self.path) {
+                return false;
+            }
+            self.stamp = stamp(&self.path);
+            return self.stamp.is_some();
+        }
+
+        match stamp(&self.path) {
+            Some(stamp) if Some(stamp) != self.stamp => {
+                self.stamp = Some(stamp);
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+fn stamp(path: &Path) -> Option<(SystemTime, u64)> {
+    let metadata = fs::metadata(path).ok()?;
+    Some((metadata.modified().ok()?, metadata.len()))
+}
+
+#[cfg(feature = "gui")]
+struct Events {
+    // Dropping it ends the watch
+    _watcher: RecommendedWatcher,
+    receiver: Receiver<notify::Result<notify::Event>>,
+}
+
+#[cfg(feature = "gui")]
+impl Events {
+    // Watches the directory rather than the file, editors often save by
+    // writing a new file and renaming it over the old one
+    fn watch(path: &Path) -> Option<Events> {
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+
+        let (sender, receiver) = channel();
+        let mut watcher = notify::recommended_watcher(sender).ok()?;
+        watcher.watch(dir, RecursiveMode::NonRecursive).ok()?;
+        Some(Events { _watcher: watcher, receiver })
+    }
+
+    // Whether anything wrote, replaced or removed the file since the last
+    // call. Reads don't count, or loading it would look like a change.
+    fn touched(&self, path: &Path) -> bool {
+        let mut touched = false;
+        while let Ok(event) = self.receiver.try_recv() {
+            let Ok(event) = event else {
+                continue;
+            };
+            let writes = match event.kind {
+                EventKind::Create(_) | EventKind::Remove(_) => true,
+                EventKind::Modify(kind) => !matches!(kind, ModifyKind::Metadata(_)),
+                _ => false,
+            };
+            if writes && event.paths.iter().any(|changed| changed.file_name() == path.file_name()) {
+                touched = true;
+            }
+        }
+        touched
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_file(name: &str) -> PathBuf {
+        std::env::temp_dir().join(std::format!("crust-{}-{}.bin", name, std::process::id()))
+    }
+
+    #[test]
+    fn sees_each_rewrite_once() {
+        let path = temp_file("watch");
+        fs::write(&path, [0xEA]).unwrap();
+
+        let mut watcher = FileWatcher::polling(&path);
+        assert!(!watcher.changed());
+
+        // A different length shows even where timestamps are coarse
+        fs::write(&path, [0xEA, 0xEA]).unwrap();
+        assert!(watcher.changed());
+        assert!(!watcher.changed());
+
+        fs::remove_file(&path).unwrap();
+        assert!(!watcher.changed());
+        fs::write(&path, [0xEA]).unwrap();
+        assert!(watcher.changed());
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[cfg(feature = "gui")]
+    #[test]
+    fn notifications_catch_same_size_rewrites() {
+        use std::time::{Duration, Instant};
+
+        // Events come in on another thread, give them a moment
+        fn settle(watcher: &mut FileWatcher) -> bool {
+            let start = Instant::now();
+            let mut changed = false;
+            while start.elapsed() < Duration::from_millis(500) {
+                changed |= watcher.changed();
+                std::thread::sleep(Duration::from_millis(20));
+            }
+            changed
+        }
+
+        let path = temp_file("notify");
+        fs::write(&path, [0xEA]).unwrap();
+
+        let mut watcher = FileWatcher::new(&path);
+        if watcher.events.is_none() {
+            // Nothing to test where the platform won't watch
+            fs::remove_file(&path).unwrap();
+            return;
+        }
+        assert!(!settle(&mut watcher));
+
+        fs::write(&path, [0x00]).unwrap();
+        assert!(settle(&mut watcher));
+        assert!(!watcher.changed());
+
+        // Reloading it isn't a change
+        fs::read(&path).unwrap();
+        assert!(!settle(&mut watcher));
+
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,128 @@
+use crate::{cpu6502, Variant, FLAGS6502};
+
+// Spotting a guest that has stopped for good, so a headless run can end
+// there instead of burning its whole cycle cap. Test programs usually
+// finish, pass or fail, on a JMP to itself or a branch to itself that's
+// always taken, and those are recognised by looking at the next
+// instruction. Anything else that spins on one address is caught by
+// counting how many times in a row the same PC comes up.
+//
+// A loop like that waiting for an interrupt looks just the same, so it's
+// only taken as halted while no interrupt is on its way. One that arrives
+// later, from a timer say, can't be foreseen: leave the watchdog off for
+// programs that idle that way.
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Watchdog {
+    // JMP to itself, JMP (ind) through a pointer to itself, or a branch to
+    // itself that the flags say will be taken
+    pub self_loops: bool,
+    // This many instructions in a row at one address is a halt too
+    pub max_repeats: Option<u64>,
+}
+
+impl Default for Watchdog {
+    fn default() -> Self {
+        Watchdog { self_loops: true, max_repeats: None }
+    }
+}
+
+impl cpu6502 {
+    // Checked by run_until between instructions, off unless set
+    pub fn set_watchdog(&mut self, watchdog: Option<Watchdog>) {
+        self.watchdog = watchdog;
+    }
+
+    pub fn watchdog(&self) -> Option<Watchdog> {
+        self.watchdog
+    }
+
+    // True if the instruction at the PC is one of the idioms that never
+    // gets anywhere, and nothing is about to interrupt it
+    pub fn at_self_loop(&self) -> bool {
+        let bus = self.bus.borrow();
+        let operand = |offset: u16| bus.read(self.pc.wrapping_add(offset), true);
+        let word = |addr: u16| bus.read(addr, true) as u16 | (bus.read(addr.wrapping_add(1), true) as u16) << 8;
+        let flag = |f: FLAGS6502| self.status & f as u8 != 0;
+
+        let stuck = match operand(0) {
+            0x4C => word(self.pc.wrapping_add(1)) == self.pc,
+            // The NMOS part doesn't carry into the high byte of the pointer
+            0x6C => {
+                let pointer = word(self.pc.wrapping_add(1));
+                let high = match self.variant {
+                    Variant::Nmos6502 => (pointer & 0xFF00) | (pointer.wrapping_add(1) & 0x00FF),
+                    Variant::Cmos65C02 => pointer.wrapping_add(1),
+                };
+                (bus.read(pointer, true) as u16 | (bus.read(high, true) as u16) << 8) == self.pc
+            }
+            opcode @ (0x10 | 0x30 | 0x50 | 0x70 | 0x90 | 0xB0 | 0xD0 | 0xF0) if operand(1) == 0xFE => {
+                let set = match opcode >> 6 {
+                    0 => flag(FLAGS6502::N),
+                    1 => flag(FLAGS6502::V),
+                    2 => flag(FLAGS6502::C),
+                    _ => flag(FLAGS6502::Z),
+                };
+                set == (opcode & 0x20 != 0)
+            }
+            // BRA
+            0x80 => self.variant == Variant::Cmos65C02 && operand(1) == 0xFE,
+            _ => false,
+        };
+
+        let interrupt_coming = self.poll.nmi_pending || self.poll.nmi_edge || self.poll.irq_pending || (self.interrupts.irq_line() && !flag(FLAGS6502::I));
+        stuck && !interrupt_coming
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::run::StopReason;
+
+    fn start(program: &[u8]) -> cpu6502 {
+        let mut cpu = cpu6502::new();
+        cpu.load_program(program, 0x8000);
+        cpu.set_reset_vector(0x8000);
+        cpu.reset();
+        cpu.set_watchdog(Some(Watchdog::default()));
+        cpu
+    }
+
+    #[test]
+    fn stops_on_loops_to_self() {
+        // LDX #$03 / done: JMP done
+        let mut cpu = start(&[0xA2, 0x03, 0x4C, 0x02, 0x80]);
+        assert_eq!(cpu.run_until(|_| false, 10_000), StopReason::Halted(0x8002));
+        assert_eq!(cpu.x, 3);
+
+        // SEC / BCS * is always taken, CLC / BCS * falls through to the BRK
+        let mut cpu = start(&[0x38, 0xB0, 0xFE]);
+        assert_eq!(cpu.run_until(|_| false, 10_000), StopReason::Halted(0x8001));
+        let mut cpu = start(&[0x18, 0xB0, 0xFE]);
+        assert_eq!(cpu.run_until(|_| false, 10_000), StopReason::Break);
+
+        // Off, the cap is all that stops it
+        let mut cpu = start(&[0x4C, 0x00, 0x80]);
+        cpu.set_watchdog(None);
+        assert_eq!(cpu.run_until(|_| false, 1000), StopReason::CycleLimit);
+    }
+
+    #[test]
+    fn pending_interrupts_and_repeats() {
+        // An NMI on its way gets out of the loop
+        let mut cpu = start(&[0x4C, 0x00, 0x80]);
+        assert_eq!(cpu.run_until(|_| false, 10_000), StopReason::Halted(0x8000));
+        assert!(cpu.at_self_loop());
+        cpu.trigger_nmi();
+        assert!(!cpu.at_self_loop());
+
+        // loop: JMP (ptr) where ptr holds loop, only caught by counting
+        // with self_loops off
+        let mut cpu = start(&[0x6C, 0x00, 0x02]);
+        cpu.load_program(&[0x00, 0x80], 0x0200);
+        cpu.set_watchdog(Some(Watchdog { self_loops: false, max_repeats: Some(10) }));
+        assert_eq!(cpu.run_until(|_| false, 10_000), StopReason::Halted(0x8000));
+        assert_eq!(cpu.clock_count(), 8 + 10 * 5);
+    }
+}
@@ -0,0 +1,146 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::frame::{InputState, AUDIO_SAMPLE_RATE};
+use crate::machine::Machine;
+
+// Sound from a headless run saved as a .wav, to listen to or to keep as a
+// golden file and compare later runs against. Files are 16-bit mono PCM,
+// which is all the mix ever is and what every player takes. Reading only
+// understands that same layout.
+
+// The canonical 44 byte header, then the samples
+const HEADER_LEN: usize = 44;
+
+pub fn encode_wav(samples: &[f32], sample_rate: u32) -> Vec<u8> {
+    let data_len = samples.len() as u32 * 2;
+    let mut bytes = Vec::with_capacity(HEADER_LEN + data_len as usize);
+    bytes.extend_from_slice(b"RIFF");
+    bytes.extend_from_slice(&(36 + data_len).to_le_bytes());
+    bytes.extend_from_slice(b"WAVEfmt ");
+    bytes.extend_from_slice(&16u32.to_le_bytes());
+    // PCM, one channel
+    bytes.extend_from_slice(&1u16.to_le_bytes());
+    bytes.extend_from_slice(&1u16.to_le_bytes());
+    bytes.extend_from_slice(&sample_rate.to_le_bytes());
+    bytes.extend_from_slice(&(sample_rate * 2).to_le_bytes());
+    // Bytes per frame and bits per sample
+    bytes.extend_from_slice(&2u16.to_le_bytes());
+    bytes.extend_from_slice(&16u16.to_le_bytes());
+    bytes.extend_from_slice(b"data");
+    bytes.extend_from_slice(&data_len.to_le_bytes());
+    for sample in samples {
+        bytes.extend_from_slice(&((sample.clamp(-1.0, 1.0) * i16::MAX as f32).round() as i16).to_le_bytes());
+    }
+    bytes
+}
+
+// The sample rate and the samples
+pub fn decode_wav(bytes: &[u8]) -> Result<(u32, Vec<f32>), String> {
+    let u16_at = |at: usize| u16::from_le_bytes([bytes[at], bytes[at + 1]]);
+    let u32_at = |at: usize| u32::from_le_bytes([bytes[at], bytes[at + 1], bytes[at + 2], bytes[at + 3]]);
+
+    if bytes.len() < HEADER_LEN || &bytes[0..4] != b"RIFF" || &bytes[8..16] != b"WAVEfmt " || &bytes[36..40] != b"data" {
+        return Err("not a canonical .wav file".to_string());
+    }
+    if (u16_at(20), u16_at(22), u16_at(34)) != (1, 1, 16) {
+        return Err("only 16-bit mono PCM is read".to_string());
+    }
+
+    let data = &bytes[HEADER_LEN..];
+    let len = (u32_at(40) as usize).min(data.len());
+    let samples = data[..len].chunks_exact(2).map(|pair| i16::from_le_bytes([pair[0], pair[1]]) as f32 / i16::MAX as f32).collect();
+    Ok((u32_at(24), samples))
+}
+
+pub fn save_wav<P: AsRef<Path>>(path: P, samples: &[f32], sample_rate: u32) -> io::Result<()> {
+    fs::write(path, encode_wav(samples, sample_rate))
+}
+
+pub fn load_wav<P: AsRef<Path>>(path: P) -> Result<(u32, Vec<f32>), String> {
+    let path = path.as_ref();
+    let bytes = fs::read(path).map_err(|e| std::format!("{}: {}", path.display(), e))?;
+    decode_wav(&bytes).map_err(|e| std::format!("{}: {}", path.display(), e))
+}
+
+// What's wrong with `actual` as a match for `expected`, allowing each
+// sample to be off by `tolerance`. Saving rounds to 16 bits, so anything
+// from a file wants a tolerance of at least 1.0 / 32767.
+pub fn compare_audio(expected: &[f32], actual: &[f32], tolerance: f32) -> Vec<String> {
+    let mut problems = Vec::new();
+    if let Some(i) = expected.iter().zip(actual).position(|(a, b)| (a - b).abs() > tolerance) {
+        problems.push(std::format!("sample {}: {:.4}, expected {:.4}", i, actual[i], expected[i]));
+    }
+    if expected.len() != actual.len() {
+        problems.push(std::format!("{} samples, expected {}", actual.len(), expected.len()));
+    }
+    problems
+}
+
+impl Machine {
+    // Runs `frames` frames with no input and writes what they sounded
+    // like, returning how many samples that was
+    pub fn record_wav<P: AsRef<Path>>(&mut self, path: P, frames: u32) -> io::Result<usize> {
+        let mut samples = Vec::new();
+        for _ in 0..frames {
+            samples.extend(self.run_frame(&InputState::default()).audio_samples);
+        }
+        save_wav(path, &samples, AUDIO_SAMPLE_RATE)?;
+        Ok(samples.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu6502;
+    use crate::device::BusDevice;
+
+    // A square wave at whatever level was last written, 100 samples each
+    // time it's asked
+    struct Beeper {
+        level: f32,
+    }
+
+    impl BusDevice for Beeper {
+        fn read(&mut self, _offset: u16) -> u8 {
+            0
+        }
+
+        fn peek(&self, _offset: u16) -> u8 {
+            0
+        }
+
+        fn write(&mut self, _offset: u16, data: u8) {
+            self.level = data as f32 / 255.0;
+        }
+
+        fn take_samples(&mut self) -> Vec<f32> {
+            (0..100).map(|i| if i % 10 < 5 { self.level } else { -self.level }).collect()
+        }
+    }
+
+    #[test]
+    fn records_and_compares_the_mix() {
+        let cpu = cpu6502::new();
+        cpu.bus.borrow_mut().map("beeper", 0xD000, 0xD000, Box::new(Beeper { level: 0.5 }));
+        cpu.bus.borrow_mut().map("loud", 0xD001, 0xD001, Box::new(Beeper { level: 0.75 }));
+        let mut machine = Machine::from_cpu(cpu);
+
+        // The two add up past full scale and get clipped
+        let mix = machine.run_frame(&InputState::default()).audio_samples;
+        assert_eq!((mix.len(), mix[0], mix[5]), (100, 1.0, -1.0));
+
+        machine.bus.borrow_mut().write(0xD001, 0);
+        let path = std::env::temp_dir().join(std::format!("crust-wav-{}.wav", std::process::id()));
+        assert_eq!(machine.record_wav(&path, 3).unwrap(), 300);
+        let (rate, samples) = load_wav(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(rate, AUDIO_SAMPLE_RATE);
+        let expected: Vec<f32> = (0..300).map(|i| if i % 10 < 5 { 0.5 } else { -0.5 }).collect();
+        assert!(compare_audio(&expected, &samples, 1.0 / 32767.0).is_empty());
+        assert_eq!(compare_audio(&expected[..299], &samples, 0.0).len(), 2);
+    }
+}